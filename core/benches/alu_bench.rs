@@ -0,0 +1,90 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    benches::alu_bench.rs
+
+    Benchmarks for the 808x ALU and flag evaluation.
+
+*/
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use marty_core::{
+    cpu_808x::{mnemonic::Mnemonic, Cpu, Flag},
+    cpu_common::{CpuType, TraceMode},
+    tracelogger::TraceLogger,
+};
+
+fn operands() -> Vec<(u16, u16)> {
+    (0..256u32)
+        .map(|i| {
+            (
+                (i.wrapping_mul(0x9E37) >> 3) as u16,
+                (i.wrapping_mul(0x79B9) >> 5) as u16,
+            )
+        })
+        .collect()
+}
+
+pub fn alu_flags_bench(c: &mut Criterion) {
+    let mut cpu = Cpu::new(CpuType::Intel8088, TraceMode::None, TraceLogger::None);
+    let operands = operands();
+
+    // Flags that are overwritten before they are read.
+    c.bench_function("alu_flags_unread", |b| {
+        b.iter(|| {
+            for &(op1, op2) in &operands {
+                let result = cpu.math_op16(Mnemonic::ADD, op1, op2);
+                let result = cpu.math_op16(Mnemonic::SUB, result, op2);
+                black_box(cpu.math_op16(Mnemonic::AND, result, op1));
+            }
+        });
+    });
+
+    // A compare followed by conditional jumps on ZF and OF.
+    c.bench_function("alu_flags_branch", |b| {
+        b.iter(|| {
+            for &(op1, op2) in &operands {
+                cpu.math_op16(Mnemonic::CMP, op1, op2);
+                black_box(cpu.get_flag(Flag::Zero));
+                black_box(cpu.get_flag(Flag::Overflow));
+            }
+        });
+    });
+
+    // Every result's flags read in full, as by PUSHF.
+    c.bench_function("alu_flags_pushf", |b| {
+        b.iter(|| {
+            for &(op1, op2) in &operands {
+                cpu.math_op16(Mnemonic::ADC, op1, op2);
+                black_box(cpu.get_flags());
+            }
+        });
+    });
+}
+
+criterion_group!(alu_benches, alu_flags_bench);
+criterion_main!(alu_benches);
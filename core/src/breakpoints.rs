@@ -26,16 +26,289 @@
 
     breakpoints.rs
 
-    Implement enum for breakpoint definitions.
+    Implement enum for breakpoint definitions, video memory write breakpoints,
+    memory watch regions and interrupt vector watch records.
+
+    BreakpointSet manages breakpoints by id and optional group name, so they
+    can be enabled and disabled without being deleted, and counts how many
+    times each has been hit.
 
 */
 
+use std::fmt;
+
 #[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum BreakPointType {
-    Execute(u16, u16),   // Breakpoint on CS:IP
-    ExecuteOffset(u16),  // Breakpoint on *::IP
-    ExecuteFlat(u32),    // Breakpoint on CS<<4+IP
-    MemAccess(u16, u16), // Breakpoint on memory access, seg::offset
-    MemAccessFlat(u32),  // Breakpoint on memory access, seg<<4+offset
-    Interrupt(u8),       // Breakpoint on interrupt #
+    Execute(u16, u16),                // Breakpoint on CS:IP
+    ExecuteOffset(u16),               // Breakpoint on *::IP
+    ExecuteFlat(u32),                 // Breakpoint on CS<<4+IP
+    MemAccess(u16, u16),              // Breakpoint on memory access, seg::offset
+    MemAccessFlat(u32),               // Breakpoint on memory access, seg<<4+offset
+    Interrupt(u8),                    // Breakpoint on interrupt #
+    VideoWrite(VideoWriteBreakpoint), // Breakpoint on write to video memory
+}
+
+impl BreakPointType {
+    /// Return true if this breakpoint would have been hit by the specified trigger.
+    pub fn matches(&self, trigger: &BreakpointTrigger) -> bool {
+        let flat = |seg: u16, offset: u16| (((seg as u32) << 4) + offset as u32) & 0xFFFFF;
+        match (self, trigger) {
+            (BreakPointType::Execute(cs, ip), BreakpointTrigger::Execute(addr)) => flat(*cs, *ip) == *addr,
+            (BreakPointType::ExecuteFlat(bp_addr), BreakpointTrigger::Execute(addr)) => bp_addr == addr,
+            (BreakPointType::MemAccess(seg, offset), BreakpointTrigger::MemAccess(addr)) => {
+                flat(*seg, *offset) == *addr
+            }
+            (BreakPointType::MemAccessFlat(bp_addr), BreakpointTrigger::MemAccess(addr)) => bp_addr == addr,
+            (BreakPointType::Interrupt(bp_vector), BreakpointTrigger::Interrupt(vector)) => bp_vector == vector,
+            (BreakPointType::VideoWrite(bp), BreakpointTrigger::VideoWrite(addr, data)) => {
+                bp.matches(*addr as usize, *data)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BreakPointType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakPointType::Execute(cs, ip) => write!(f, "Execute {:04X}:{:04X}", cs, ip),
+            BreakPointType::ExecuteOffset(ip) => write!(f, "Execute *:{:04X}", ip),
+            BreakPointType::ExecuteFlat(addr) => write!(f, "Execute {:05X}", addr),
+            BreakPointType::MemAccess(seg, offset) => write!(f, "Access {:04X}:{:04X}", seg, offset),
+            BreakPointType::MemAccessFlat(addr) => write!(f, "Access {:05X}", addr),
+            BreakPointType::Interrupt(vector) => write!(f, "Interrupt {:02X}", vector),
+            BreakPointType::VideoWrite(bp) => match bp.value {
+                Some(value) => write!(f, "Video write {:05X}+{:X}={:02X}", bp.start, bp.size, value),
+                None => write!(f, "Video write {:05X}+{:X}", bp.start, bp.size),
+            },
+        }
+    }
+}
+
+/// The event that caused the CPU to enter the BreakpointHit state.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BreakpointTrigger {
+    Execute(u32),        // Flat address of the instruction
+    MemAccess(u32),      // Flat address of the bus access
+    Interrupt(u8),       // Interrupt vector
+    VideoWrite(u32, u8), // Flat address and value written
+}
+
+pub type BreakpointId = usize;
+
+/// A managed breakpoint. Disabled breakpoints are kept but not installed in the CPU.
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub kind: BreakPointType,
+    pub group: Option<String>,
+    pub enabled: bool,
+    pub hits: u64,
+}
+
+#[derive(Default)]
+pub struct BreakpointSet {
+    breakpoints: Vec<Breakpoint>,
+    next_id: BreakpointId,
+}
+
+impl BreakpointSet {
+    /// Add a breakpoint, optionally as a member of a named group, and return its id.
+    pub fn add(&mut self, kind: BreakPointType, group: Option<&str>) -> BreakpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            kind,
+            group: group.map(String::from),
+            enabled: true,
+            hits: 0,
+        });
+        id
+    }
+
+    /// Remove the breakpoint with the specified id. Returns false if there is no such breakpoint.
+    pub fn remove(&mut self, id: BreakpointId) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.id != id);
+        self.breakpoints.len() != len
+    }
+
+    /// Remove all breakpoints in the specified group, or all ungrouped breakpoints if None.
+    /// Returns the number of breakpoints removed.
+    pub fn remove_group(&mut self, group: Option<&str>) -> usize {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.group.as_deref() != group);
+        len - self.breakpoints.len()
+    }
+
+    /// Enable or disable the breakpoint with the specified id. Returns false if there is no such
+    /// breakpoint.
+    pub fn set_enabled(&mut self, id: BreakpointId, state: bool) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            Some(bp) => {
+                bp.enabled = state;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enable or disable all breakpoints in the specified group. Returns the number of
+    /// breakpoints in the group.
+    pub fn set_group_enabled(&mut self, group: &str, state: bool) -> usize {
+        let mut count = 0;
+        for bp in self
+            .breakpoints
+            .iter_mut()
+            .filter(|bp| bp.group.as_deref() == Some(group))
+        {
+            bp.enabled = state;
+            count += 1;
+        }
+        count
+    }
+
+    /// Return all breakpoints, in the order they were added.
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Return the names of all groups, in the order they were first used.
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups: Vec<String> = Vec::new();
+        for group in self.breakpoints.iter().filter_map(|bp| bp.group.as_ref()) {
+            if !groups.contains(group) {
+                groups.push(group.clone());
+            }
+        }
+        groups
+    }
+
+    /// Return the enabled breakpoints, to be installed in the CPU.
+    pub fn active(&self) -> Vec<BreakPointType> {
+        self.breakpoints
+            .iter()
+            .filter(|bp| bp.enabled)
+            .map(|bp| bp.kind)
+            .collect()
+    }
+
+    /// Count a hit against every enabled breakpoint matching the trigger. Returns the id of the
+    /// first matching breakpoint.
+    pub fn record_hit(&mut self, trigger: &BreakpointTrigger) -> Option<BreakpointId> {
+        let mut first = None;
+        for bp in self
+            .breakpoints
+            .iter_mut()
+            .filter(|bp| bp.enabled && bp.kind.matches(trigger))
+        {
+            bp.hits += 1;
+            first.get_or_insert(bp.id);
+        }
+        first
+    }
+
+    pub fn reset_hits(&mut self) {
+        self.breakpoints.iter_mut().for_each(|bp| bp.hits = 0);
+    }
+}
+
+/// A breakpoint on writes to video memory. Checked when a write is dispatched to a video card, so
+/// it does not require any memory flags and costs nothing for writes to ordinary RAM.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VideoWriteBreakpoint {
+    pub start: u32,
+    pub size:  u32,
+    /// Only break when this byte is written, if specified.
+    pub value: Option<u8>,
+}
+
+impl VideoWriteBreakpoint {
+    pub fn matches(&self, address: usize, data: u8) -> bool {
+        address >= self.start as usize
+            && address < (self.start as usize + self.size as usize)
+            && self.value.is_none_or(|value| value == data)
+    }
+}
+
+/// The action to take when a write occurs within a watch region.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchAction {
+    Ignore, // Take no action. Combined with write protection, writes are silently dropped.
+    Log,    // Log the write.
+    Break,  // Log the write and enter the BreakpointHit state after the current instruction.
+}
+
+/// A range of memory to watch for writes at runtime. If protected, writes to the region are discarded.
+#[derive(Clone, Debug)]
+pub struct WatchRegion {
+    pub start:   u32,
+    pub size:    u32,
+    pub protect: bool,
+    pub action:  WatchAction,
+}
+
+impl WatchRegion {
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.start as usize && address < (self.start as usize + self.size as usize)
+    }
+}
+
+/// Details of the most recent write to a watch region.
+#[derive(Copy, Clone, Debug)]
+pub struct WatchHit {
+    pub region:  usize,
+    pub address: u32,
+    pub data:    u8,
+    pub blocked: bool,
+}
+
+/// A change to an entry in the interrupt vector table, and the instruction that made it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VectorChange {
+    pub vector: u8,
+    pub old_cs: u16,
+    pub old_ip: u16,
+    pub new_cs: u16,
+    pub new_ip: u16,
+    pub cs: u16, // CS:IP of the instruction that wrote the vector
+    pub ip: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakpoint_set() {
+        let mut set = BreakpointSet::default();
+        let ungrouped = set.add(BreakPointType::ExecuteFlat(0xFE05B), None);
+        let int13 = set.add(BreakPointType::Interrupt(0x13), Some("disk"));
+        let vram = set.add(BreakPointType::MemAccess(0xB800, 0x0000), Some("disk"));
+        assert_eq!(set.groups(), vec!["disk".to_string()]);
+
+        // Disabled breakpoints are kept, but not installed and not hit.
+        assert_eq!(set.set_group_enabled("disk", false), 2);
+        assert_eq!(set.active(), vec![BreakPointType::ExecuteFlat(0xFE05B)]);
+        assert_eq!(set.record_hit(&BreakpointTrigger::Interrupt(0x13)), None);
+
+        assert!(set.set_enabled(int13, true));
+        assert_eq!(set.record_hit(&BreakpointTrigger::Interrupt(0x13)), Some(int13));
+        assert_eq!(set.record_hit(&BreakpointTrigger::Execute(0xFE05B)), Some(ungrouped));
+        assert_eq!(set.record_hit(&BreakpointTrigger::Execute(0xFE05B)), Some(ungrouped));
+        set.set_enabled(vram, true);
+        assert_eq!(set.record_hit(&BreakpointTrigger::MemAccess(0xB8000)), Some(vram));
+        let hits: Vec<u64> = set.list().iter().map(|bp| bp.hits).collect();
+        assert_eq!(hits, vec![2, 1, 1]);
+
+        // Removing ungrouped breakpoints leaves groups intact.
+        assert_eq!(set.remove_group(None), 1);
+        assert_eq!(set.list().len(), 2);
+        set.reset_hits();
+        assert!(set.list().iter().all(|bp| bp.hits == 0));
+        assert!(set.remove(int13));
+        assert!(!set.remove(int13));
+    }
 }
@@ -38,9 +38,8 @@
 use anyhow::Error;
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     fmt,
-    path::Path,
 };
 
 use ringbuf::Producer;
@@ -48,22 +47,33 @@ use ringbuf::Producer;
 use crate::{bytequeue::*, cpu_808x::*};
 
 use crate::{
-    device_traits::videocard::{ClockingMode, VideoCardId, VideoCardInterface, VideoType},
+    breakpoints::{VectorChange, VideoWriteBreakpoint, WatchAction, WatchHit, WatchRegion},
+    device_scheduler::{earliest_deadline, DeviceSchedule},
+    device_traits::{
+        devicestate::DeviceState,
+        nonvolatile::NonVolatile,
+        videocard::{ClockingMode, VideoCardId, VideoCardInterface, VideoType},
+    },
     devices::keyboard::KeyboardType,
+    log_event,
     machine::KeybufferEntry,
-    machine_config::MachineDescriptor,
+    machine_config::{DmaType, MachineDescriptor, PicType},
     syntax_token::SyntaxToken,
+    tracelogger::{LogCategory, LogLevel},
 };
 
 use crate::devices::{
     dma::*,
-    fdc::FloppyController,
+    fdc::{FloppyController, FDC_DMA, FDC_IRQ},
+    game_port::GamePort,
     hdc::*,
     keyboard::*,
+    lpt_port::LPT_DEFAULT_IRQ,
     mouse::*,
     pic::*,
     pit::Pit,
     ppi::*,
+    rtc::Rtc,
     serial::*,
 };
 
@@ -77,20 +87,46 @@ use crate::{
     device_traits::videocard::{VideoCard, VideoCardDispatch},
     devices::{
         cga::{self, CGACard},
+        compaq_video::CompaqVideoCard,
         mda::{self, MDACard},
     },
     machine::MachineCheckpoint,
-    machine_config::{normalize_conventional_memory, MachineConfiguration},
-    machine_types::{HardDiskControllerType, SerialControllerType, SerialMouseType},
+    machine_config::{
+        normalize_conventional_memory,
+        MachineConfiguration,
+        SerialMouseConfig,
+        VideoCardConfig,
+        IBM_PC_SYSTEM_CLOCK,
+    },
+    machine_types::{HardDiskControllerType, OpenBusType, SerialControllerType, SerialMouseType},
     memerror::MemError,
+    rewind::WriteJournal,
+    video_trace::{VideoRegisterTrace, VideoTraceFilter},
 };
 
 pub const NO_IO_BYTE: u8 = 0xFF; // This is the byte read from a unconnected IO address.
 pub const OPEN_BUS_BYTE: u8 = 0xFF; // This is the byte read from an unmapped memory address.
 
+// The NMI mask register of the 5150 and 5160. Writing a byte with bit 7 set enables NMI.
+pub const NMI_MASK_PORT: u16 = 0xA0;
+pub const NMI_MASK_ENABLE: u8 = 0b1000_0000;
+
 const ADDRESS_SPACE: usize = 0x10_0000;
+// The high memory area just above 1MB, reachable by real mode addresses when they do not wrap.
+const HMA_LEN: usize = 0x1_0000;
+// Address masks for CPU linear addresses, with address wrap at 1MB enabled and disabled. With wrap
+// disabled, the full 24-bit address bus of the 80286 is available.
+const ADDRESS_WRAP_MASK: u32 = 0xF_FFFF;
+const ADDRESS_FULL_MASK: u32 = 0xFF_FFFF;
+const ADDRESS_A20_BIT: u32 = 0x10_0000;
 const DEFAULT_WAIT_STATES: u32 = 0;
 
+/// Memory is tracked for changes in pages of this size, so that snapshots only need to copy the
+/// pages that were written since the last snapshot.
+pub const MEMORY_PAGE_SHIFT: usize = 12;
+pub const MEMORY_PAGE_SIZE: usize = 1 << MEMORY_PAGE_SHIFT;
+const MEMORY_PAGES: usize = ADDRESS_SPACE >> MEMORY_PAGE_SHIFT;
+
 const MMIO_MAP_SIZE: usize = 0x2000;
 const MMIO_MAP_SHIFT: usize = 13;
 const MMIO_MAP_LEN: usize = ADDRESS_SPACE >> MMIO_MAP_SHIFT;
@@ -101,6 +137,17 @@ pub const MEM_BPE_BIT: u8 = 0b0010_0000; // Bit to signify that this address is
 pub const MEM_BPA_BIT: u8 = 0b0001_0000; // Bit to signify that this address is associated with a breakpoint on access
 pub const MEM_CP_BIT: u8 = 0b0000_1000; // Bit to signify that this address is a ROM checkpoint
 pub const MEM_MMIO_BIT: u8 = 0b0000_0100; // Bit to signify that this address is MMIO mapped
+pub const MEM_WAIT_BIT: u8 = 0b0000_0010; // Bit to signify that this address has a wait state cost
+pub const MEM_WATCH_BIT: u8 = 0b0000_0001; // Bit to signify that this address is within a write watch region
+
+pub const IVT_SIZE: usize = 0x400; // Size of the interrupt vector table at address 0
+
+// Flags that send a memory access down the slow path. Addresses with none of these set are plain RAM.
+const MEM_WRITE_SLOW_MASK: u8 = MEM_WATCH_BIT | MEM_MMIO_BIT | MEM_ROM_BIT;
+const MEM_WAIT_SLOW_MASK: u8 = MEM_WAIT_BIT | MEM_MMIO_BIT;
+// The same flags, repeated for both bytes of a word as returned by word_flags().
+const MEM_READ_SLOW_MASK16: u16 = u16::from_le_bytes([MEM_MMIO_BIT, MEM_MMIO_BIT]);
+const MEM_WRITE_SLOW_MASK16: u16 = u16::from_le_bytes([MEM_WRITE_SLOW_MASK, MEM_WRITE_SLOW_MASK]);
 
 pub const KB_UPDATE_RATE: f64 = 5000.0; // Keyboard device update rate in microseconds
 
@@ -112,10 +159,55 @@ pub struct TimingTableEntry {
     pub us: f64,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Specifies the CPU clock relative to the system crystal. Devices are always clocked in system
+/// ticks, so CPU cycles must be converted using the active ClockFactor.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ClockFactor {
     Divisor(u8),
     Multiplier(u8),
+    /// The CPU runs from its own oscillator at the specified frequency in MHz. Turbo boards
+    /// running at 8 or 10MHz use a separate CPU crystal that is not a multiple of the system clock.
+    Fixed(f64),
+}
+
+impl ClockFactor {
+    /// Return the CPU frequency in MHz for the given system crystal frequency.
+    pub fn cpu_mhz(&self, system_crystal: f64) -> f64 {
+        match *self {
+            ClockFactor::Divisor(n) => system_crystal / (n as f64),
+            ClockFactor::Multiplier(n) => system_crystal * (n as f64),
+            ClockFactor::Fixed(mhz) => mhz,
+        }
+    }
+
+    /// Return the number of system ticks elapsed per CPU cycle.
+    pub fn ticks_per_cycle(&self, system_crystal: f64) -> f64 {
+        match *self {
+            ClockFactor::Divisor(n) => n as f64,
+            ClockFactor::Multiplier(n) => 1.0 / (n as f64),
+            ClockFactor::Fixed(mhz) => system_crystal / mhz,
+        }
+    }
+
+    /// Convert a count of CPU cycles to system ticks. Fractional ticks are truncated.
+    #[inline]
+    pub fn cycles_to_ticks(&self, cycles: u32, system_crystal: f64) -> u32 {
+        match *self {
+            ClockFactor::Divisor(n) => cycles * (n as u32),
+            ClockFactor::Multiplier(n) => cycles / (n as u32),
+            ClockFactor::Fixed(_) => (cycles as f64 * self.ticks_per_cycle(system_crystal)) as u32,
+        }
+    }
+
+    /// Convert a count of system ticks to CPU cycles, rounding upwards.
+    #[inline]
+    pub fn ticks_to_cycles(&self, ticks: u32, system_crystal: f64) -> u32 {
+        match *self {
+            ClockFactor::Divisor(n) => ticks.div_ceil(n as u32),
+            ClockFactor::Multiplier(n) => ticks * (n as u32),
+            ClockFactor::Fixed(_) => (ticks as f64 / self.ticks_per_cycle(system_crystal)).ceil() as u32,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -139,15 +231,8 @@ impl Default for DeviceRunContext {
 
 impl DeviceRunContext {
     pub fn new(cpu_ticks: u32, factor: ClockFactor, sysclock: f64) -> Self {
-        let delta_ticks = match factor {
-            ClockFactor::Divisor(n) => (cpu_ticks + (n as u32) - 1) / (n as u32),
-            ClockFactor::Multiplier(n) => cpu_ticks * (n as u32),
-        };
-        let mhz = match factor {
-            ClockFactor::Divisor(n) => sysclock / (n as f64),
-            ClockFactor::Multiplier(n) => sysclock * (n as f64),
-        };
-        let delta_us = 1.0 / mhz * cpu_ticks as f64;
+        let delta_ticks = factor.cycles_to_ticks(cpu_ticks, sysclock);
+        let delta_us = 1.0 / factor.cpu_mhz(sysclock) * cpu_ticks as f64;
 
         Self {
             delta_ticks,
@@ -194,7 +279,11 @@ pub enum DeviceId {
 pub enum DeviceEvent {
     DramRefreshUpdate(u16, u16, u32),
     DramRefreshEnable(bool),
+    /// Returned by Machine::frame_update() when the guest toggles the PPI turbo bit. The new CPU
+    /// clock factor has already been applied; see Machine::get_cpu_mhz().
+    /// The turbo button (Machine::set_turbo_mode) takes priority and does not generate this event.
     TurboToggled(bool),
+    KeyboardStateChanged(KeyboardState),
 }
 
 pub trait MemoryMappedDevice {
@@ -243,8 +332,20 @@ impl MemRangeDescriptor {
             read_only,
         }
     }
+
+    /// Set the number of wait states added to each access within this range.
+    pub fn with_cycle_cost(mut self, cycle_cost: u32) -> Self {
+        self.cycle_cost = cycle_cost;
+        self
+    }
+
+    #[inline]
+    fn contains(&self, address: usize) -> bool {
+        address >= self.address && address < self.address + self.size
+    }
 }
 
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum IoDeviceType {
     Ppi,
     Pit,
@@ -256,7 +357,143 @@ pub enum IoDeviceType {
     FloppyController,
     HardDiskController,
     Mouse,
+    GamePort,
+    Rtc,
+    NmiMask,
     Video(VideoCardId),
+    /// The keyboard is read through the PPI or keyboard controller and has no ports of its own.
+    /// This identifies it for device state capture.
+    Keyboard,
+}
+
+/// Describes which device or configuration entry could not be installed, and why.
+#[derive(Debug)]
+pub enum DeviceInstallError {
+    Memory(String),
+    WaitStateRange { address: usize, size: usize },
+    IoConflict { device: IoDeviceType, port: u16, other: IoDeviceType },
+    IrqUnavailable { device: IoDeviceType, irq: u8 },
+    DmaUnavailable { device: IoDeviceType, dma: usize },
+    TooManySerialPorts { specified: usize, supported: usize },
+    TooManySerialControllers(usize),
+    SerialMouseNoController,
+    SerialMouseInvalidPort(usize),
+    VideoUnsupported { index: usize, video_type: VideoType },
+    IrqShared { irq: u8, device: IoDeviceType, other: IoDeviceType },
+    DmaConflict { dma: usize, device: IoDeviceType, other: IoDeviceType },
+    MemoryConflict { address: usize, device: IoDeviceType, other: IoDeviceType },
+    MissingDevice(&'static str),
+}
+impl std::error::Error for DeviceInstallError {}
+impl fmt::Display for DeviceInstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceInstallError::Memory(msg) => write!(f, "Invalid memory configuration: {}", msg),
+            DeviceInstallError::WaitStateRange { address, size } => write!(
+                f,
+                "Wait state range {:05X}-{:05X} is outside the address space.",
+                address,
+                address + size
+            ),
+            DeviceInstallError::IoConflict { device, port, other } => {
+                write!(f, "IO port {:04X} of {:?} conflicts with {:?}.", port, device, other)
+            }
+            DeviceInstallError::IrqUnavailable { device, irq } => {
+                write!(
+                    f,
+                    "IRQ {} of {:?} is not available on a single PIC system.",
+                    irq, device
+                )
+            }
+            DeviceInstallError::DmaUnavailable { device, dma } => write!(
+                f,
+                "DMA channel {} of {:?} is not available on a single DMA system.",
+                dma, device
+            ),
+            DeviceInstallError::TooManySerialPorts { specified, supported } => write!(
+                f,
+                "Serial controller supports {} ports, but {} were specified.",
+                supported, specified
+            ),
+            DeviceInstallError::TooManySerialControllers(count) => {
+                write!(
+                    f,
+                    "Only one serial controller is supported, but {} were specified.",
+                    count
+                )
+            }
+            DeviceInstallError::SerialMouseNoController => {
+                write!(
+                    f,
+                    "A serial mouse was specified, but there is no serial controller to attach it to."
+                )
+            }
+            DeviceInstallError::SerialMouseInvalidPort(port) => {
+                write!(f, "Serial mouse port {} is not a valid serial port.", port)
+            }
+            DeviceInstallError::VideoUnsupported { index, video_type } => write!(
+                f,
+                "Video card #{} of type {:?} is not implemented or its feature was not compiled.",
+                index, video_type
+            ),
+            DeviceInstallError::IrqShared { irq, device, other } => {
+                write!(f, "IRQ {} of {:?} is shared with {:?}.", irq, device, other)
+            }
+            DeviceInstallError::DmaConflict { dma, device, other } => {
+                write!(f, "DMA channel {} of {:?} conflicts with {:?}.", dma, device, other)
+            }
+            DeviceInstallError::MemoryConflict { address, device, other } => write!(
+                f,
+                "Memory at {:05X} of {:?} conflicts with {:?}.",
+                address, device, other
+            ),
+            DeviceInstallError::MissingDevice(device) => write!(f, "No {} is configured.", device),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConfigSeverity {
+    /// The configuration is likely a mistake, but can be installed.
+    Warning,
+    /// The configuration can't be installed, or can't work as real hardware would.
+    Error,
+}
+
+/// A problem found when validating a machine configuration.
+#[derive(Debug)]
+pub struct ConfigDiagnostic {
+    pub severity: ConfigSeverity,
+    pub problem:  DeviceInstallError,
+}
+
+impl ConfigDiagnostic {
+    pub fn warning(problem: DeviceInstallError) -> Self {
+        Self {
+            severity: ConfigSeverity::Warning,
+            problem,
+        }
+    }
+
+    pub fn error(problem: DeviceInstallError) -> Self {
+        Self {
+            severity: ConfigSeverity::Error,
+            problem,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == ConfigSeverity::Error
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            ConfigSeverity::Warning => write!(f, "Warning: {}", self.problem),
+            ConfigSeverity::Error => write!(f, "Error: {}", self.problem),
+        }
+    }
 }
 
 pub enum IoDeviceDispatch {
@@ -305,6 +542,7 @@ pub enum MmioDeviceType {
 // us to call them with bus as an argument.
 pub struct BusInterface {
     cpu_factor: ClockFactor,
+    system_crystal: f64,
     timing_table: Box<[TimingTableEntry; TIMING_TABLE_LEN]>,
     machine_desc: Option<MachineDescriptor>,
     keyboard_type: KeyboardType,
@@ -312,13 +550,38 @@ pub struct BusInterface {
     conventional_size: usize,
     memory: Vec<u8>,
     memory_mask: Vec<u8>,
+    address_mask: u32,
+    hma: Vec<u8>,
+    page_epoch: Vec<u32>,
+    memory_epoch: u32,
+    code_map: Vec<u64>,
+    code_writes: Vec<u32>,
     desc_vec: Vec<MemRangeDescriptor>,
+    watch_regions: Vec<WatchRegion>,
+    watch_hit: Option<WatchHit>,
+    watch_break: bool,
+    video_break: Option<(u32, u8)>,
+    ivt_watch: bool,
+    ivt_pending: Vec<(u8, u32)>,
+    vector_changes: Vec<VectorChange>,
+    write_journal: Option<WriteJournal>,
+    parity_errors: Vec<usize>,
+    nmi_mask: bool,
+    video_breakpoints: Vec<VideoWriteBreakpoint>,
     mmio_map: Vec<(MemRangeDescriptor, MmioDeviceType)>,
     mmio_map_fast: [MmioDeviceType; MMIO_MAP_LEN],
     mmio_data: MmioData,
     cursor: usize,
 
     io_map: HashMap<u16, IoDeviceType>,
+    io_wait_map: HashMap<u16, u32>,
+    rom_wait_states: u32,
+    open_bus_type: OpenBusType,
+    open_bus_value: u8,
+    open_bus_pattern: Vec<u8>,
+    open_bus_index: usize,
+    open_bus_memory: bool,
+    open_bus_latch: u8,
     ppi: Option<Ppi>,
     pit: Option<Pit>,
     dma_counter: u16,
@@ -330,19 +593,33 @@ pub struct BusInterface {
     fdc: Option<FloppyController>,
     hdc: Option<HardDiskController>,
     mouse: Option<Mouse>,
+    game_port: Option<GamePort>,
+    rtc: Option<Rtc>,
 
     videocards:    HashMap<VideoCardId, VideoCardDispatch>,
     videocard_ids: Vec<VideoCardId>,
+    video_trace:   Option<VideoRegisterTrace>,
+
+    // Video card settings chosen at install time, also used for cards added later.
+    video_clock_mode: ClockingMode,
+    video_frame_debug: bool,
+    video_have_expansion: bool,
 
     cycles_to_ticks:   [u32; 256], // TODO: Benchmarks don't show any faster than raw multiplication. It's not slower either though.
     pit_ticks_advance: u32, // We can schedule extra PIT ticks to add when run() occurs. This is generally used for PIT phase offset adjustment.
+    tick_accum:        f64, // Fractional system tick carried between device updates with a fixed CPU clock.
 
     timer_trigger1_armed: bool,
     timer_trigger2_armed: bool,
 
     cga_tick_accum: u32,
-    kb_us_accum:    f64,
+    kb_us_accum: f64,
     refresh_active: bool,
+    elapsed_us: f64,
+
+    fdc_schedule:    DeviceSchedule,
+    hdc_schedule:    DeviceSchedule,
+    serial_schedule: DeviceSchedule,
 }
 
 impl ByteQueue for BusInterface {
@@ -357,6 +634,9 @@ impl ByteQueue for BusInterface {
     fn wait(&mut self, _cycles: u32) {}
     fn wait_i(&mut self, _cycles: u32, _instr: &[u16]) {}
     fn wait_comment(&mut self, _comment: &str) {}
+    fn elapsed(&self) -> u32 {
+        0
+    }
     fn set_pc(&mut self, _pc: u16) {}
 
     fn q_read_u8(&mut self, _dtype: QueueType, _reader: QueueReader) -> u8 {
@@ -441,6 +721,7 @@ impl Default for BusInterface {
     fn default() -> Self {
         BusInterface {
             cpu_factor: ClockFactor::Divisor(3),
+            system_crystal: IBM_PC_SYSTEM_CLOCK,
             timing_table: Box::new([TimingTableEntry { sys_ticks: 0, us: 0.0 }; TIMING_TABLE_LEN]),
             machine_desc: None,
             keyboard_type: KeyboardType::ModelF,
@@ -448,13 +729,38 @@ impl Default for BusInterface {
             conventional_size: ADDRESS_SPACE,
             memory: vec![OPEN_BUS_BYTE; ADDRESS_SPACE],
             memory_mask: vec![0; ADDRESS_SPACE],
+            address_mask: ADDRESS_WRAP_MASK,
+            hma: vec![0; HMA_LEN],
+            page_epoch: vec![1; MEMORY_PAGES],
+            memory_epoch: 1,
+            code_map: Vec::new(),
+            code_writes: Vec::new(),
             desc_vec: Vec::new(),
+            watch_regions: Vec::new(),
+            watch_hit: None,
+            watch_break: false,
+            video_break: None,
+            ivt_watch: false,
+            ivt_pending: Vec::new(),
+            vector_changes: Vec::new(),
+            write_journal: None,
+            parity_errors: Vec::new(),
+            nmi_mask: false,
+            video_breakpoints: Vec::new(),
             mmio_map: Vec::new(),
             mmio_map_fast: [MmioDeviceType::Memory; MMIO_MAP_LEN],
             mmio_data: MmioData::new(),
             cursor: 0,
 
             io_map: HashMap::new(),
+            io_wait_map: HashMap::new(),
+            rom_wait_states: 0,
+            open_bus_type: OpenBusType::Fixed,
+            open_bus_value: NO_IO_BYTE,
+            open_bus_pattern: Vec::new(),
+            open_bus_index: 0,
+            open_bus_memory: false,
+            open_bus_latch: OPEN_BUS_BYTE,
             ppi: None,
             pit: None,
             dma_counter: 0,
@@ -466,18 +772,30 @@ impl Default for BusInterface {
             fdc: None,
             hdc: None,
             mouse: None,
+            game_port: None,
+            rtc: None,
             videocards: HashMap::new(),
+            video_trace: None,
             videocard_ids: Vec::new(),
+            video_clock_mode: ClockingMode::Default,
+            video_frame_debug: false,
+            video_have_expansion: false,
 
             cycles_to_ticks:   [0; 256],
             pit_ticks_advance: 0,
+            tick_accum:        0.0,
 
             timer_trigger1_armed: false,
             timer_trigger2_armed: false,
 
             cga_tick_accum: 0,
-            kb_us_accum:    0.0,
+            kb_us_accum: 0.0,
             refresh_active: false,
+            elapsed_us: 0.0,
+
+            fdc_schedule:    DeviceSchedule::new(),
+            hdc_schedule:    DeviceSchedule::new(),
+            serial_schedule: DeviceSchedule::new(),
         }
     }
 }
@@ -487,13 +805,16 @@ impl BusInterface {
         let mut timing_table = Box::new([TimingTableEntry { sys_ticks: 0, us: 0.0 }; TIMING_TABLE_LEN]);
         Self::update_timing_table(&mut timing_table, cpu_factor, machine_desc.system_crystal);
 
-        BusInterface {
+        let mut bus = BusInterface {
             cpu_factor,
+            system_crystal: machine_desc.system_crystal,
             timing_table,
             machine_desc: Some(machine_desc),
             keyboard_type,
             ..BusInterface::default()
-        }
+        };
+        bus.recalculate_cycle_lut();
+        bus
     }
 
     /// Update the bus timing table.
@@ -508,15 +829,8 @@ impl BusInterface {
         for cycles in 0..TIMING_TABLE_LEN {
             let entry = &mut timing_table[cycles];
 
-            entry.sys_ticks = match clock_factor {
-                ClockFactor::Divisor(n) => ((cycles as u32) + (n as u32) - 1) / (n as u32),
-                ClockFactor::Multiplier(n) => (cycles as u32) * (n as u32),
-            };
-            let mhz = match clock_factor {
-                ClockFactor::Divisor(n) => cpu_crystal / (n as f64),
-                ClockFactor::Multiplier(n) => cpu_crystal * (n as f64),
-            };
-            entry.us = 1.0 / mhz * cycles as f64;
+            entry.sys_ticks = clock_factor.cycles_to_ticks(cycles as u32, cpu_crystal);
+            entry.us = 1.0 / clock_factor.cpu_mhz(cpu_crystal) * cycles as f64;
         }
     }
 
@@ -556,6 +870,49 @@ impl BusInterface {
         self.memory.len()
     }
 
+    /// Enable or disable address wrap at 1MB. With wrap disabled, the high memory area above 1MB
+    /// is reachable, as on an AT with the A20 gate open.
+    pub fn set_address_wrap(&mut self, state: bool) {
+        self.address_mask = if state { ADDRESS_WRAP_MASK } else { ADDRESS_FULL_MASK };
+    }
+
+    pub fn address_wrap(&self) -> bool {
+        self.address_mask == ADDRESS_WRAP_MASK
+    }
+
+    /// Apply the current address wrap to a linear address produced by the CPU.
+    #[inline]
+    pub fn wrap_address(&self, address: u32) -> u32 {
+        address & self.address_mask
+    }
+
+    /// Apply address wrap to a linear address produced by a CPU with a 24-bit address bus.
+    /// Wrap only forces A20 low, as the A20 gate of an AT does, so addresses above 2MB that are
+    /// reachable in protected mode are not truncated to 1MB.
+    #[inline]
+    pub fn gate_a20(&self, address: u32) -> u32 {
+        if self.address_wrap() {
+            address & ADDRESS_FULL_MASK & !ADDRESS_A20_BIT
+        }
+        else {
+            address & ADDRESS_FULL_MASK
+        }
+    }
+
+    /// Read a byte above 1MB. Only the high memory area is backed by memory.
+    #[inline]
+    fn hma_read_u8(&self, address: usize) -> u8 {
+        self.hma.get(address - ADDRESS_SPACE).copied().unwrap_or(OPEN_BUS_BYTE)
+    }
+
+    /// Write a byte above 1MB. Writes beyond the high memory area are ignored.
+    #[inline]
+    fn hma_write_u8(&mut self, address: usize, data: u8) {
+        if let Some(byte) = self.hma.get_mut(address - ADDRESS_SPACE) {
+            *byte = data;
+        }
+    }
+
     /// Register a memory-mapped device.
     ///
     /// The MemoryMappedDevice trait's read & write methods will be called instead for memory in the range
@@ -568,9 +925,15 @@ impl BusInterface {
             self.mmio_data.last_map = mem_descriptor.address + mem_descriptor.size;
         }
 
-        // Mark memory flag bit as MMIO for this range.
+        // Mark memory flag bit as MMIO for this range, and flag it for wait states if it has a cost.
+        let flags = if mem_descriptor.cycle_cost > 0 {
+            MEM_MMIO_BIT | MEM_WAIT_BIT
+        }
+        else {
+            MEM_MMIO_BIT
+        };
         for i in mem_descriptor.address..(mem_descriptor.address + mem_descriptor.size) {
-            self.memory_mask[i] |= MEM_MMIO_BIT;
+            self.memory_mask[i] |= flags;
         }
 
         // Add entry to mmio_map_fast
@@ -584,6 +947,39 @@ impl BusInterface {
         self.mmio_map.push((mem_descriptor, device));
     }
 
+    /// Unregister all memory-mapped ranges belonging to devices matching the provided predicate.
+    pub fn unregister_map<F>(&mut self, predicate: F)
+    where
+        F: Fn(&MmioDeviceType) -> bool,
+    {
+        let (removed, kept): (Vec<_>, Vec<_>) = self.mmio_map.drain(..).partition(|(_, device)| predicate(device));
+
+        for (mem_descriptor, _) in removed {
+            for i in mem_descriptor.address..(mem_descriptor.address + mem_descriptor.size) {
+                self.memory_mask[i] &= !(MEM_MMIO_BIT | MEM_WAIT_BIT);
+            }
+            let map_segs = mem_descriptor.size / MMIO_MAP_SIZE;
+            for i in 0..map_segs {
+                self.mmio_map_fast[(mem_descriptor.address >> MMIO_MAP_SHIFT) + i] = MmioDeviceType::Memory;
+            }
+        }
+
+        // Re-register the remaining ranges, as they may have overlapped the removed ones.
+        self.mmio_data.first_map = 0xFFFFF;
+        self.mmio_data.last_map = 0x00000;
+        for (mem_descriptor, device) in kept {
+            self.register_map(device, mem_descriptor);
+        }
+
+        // Restore the wait flags of memory range descriptors the removed ranges overlapped.
+        for desc in self.desc_vec.iter().filter(|desc| desc.cycle_cost > 0) {
+            let end = std::cmp::min(desc.address + desc.size, self.memory_mask.len());
+            for byte_ref in &mut self.memory_mask[desc.address..end] {
+                *byte_ref |= MEM_WAIT_BIT;
+            }
+        }
+    }
+
     pub fn copy_from(&mut self, src: &[u8], location: usize, cycle_cost: u32, read_only: bool) -> Result<(), bool> {
         let src_size = src.len();
         if location + src_size > self.memory.len() {
@@ -592,6 +988,7 @@ impl BusInterface {
             return Err(false);
         }
 
+        self.mark_pages(location, src_size);
         let mem_slice: &mut [u8] = &mut self.memory[location..location + src_size];
         let mask_slice: &mut [u8] = &mut self.memory_mask[location..location + src_size];
 
@@ -607,6 +1004,11 @@ impl BusInterface {
         for dst in mask_slice.iter_mut() {
             *dst |= access_bit;
         }
+        if cycle_cost > 0 {
+            for dst in mask_slice.iter_mut() {
+                *dst |= MEM_WAIT_BIT;
+            }
+        }
 
         self.desc_vec.push({
             MemRangeDescriptor {
@@ -623,21 +1025,114 @@ impl BusInterface {
     /// Write the specified bytes from src_vec into memory at location 'location'
     ///
     /// Does not obey memory mapping
-    pub fn patch_from(&mut self, src_vec: &Vec<u8>, location: usize) -> Result<(), bool> {
+    pub fn patch_from(&mut self, src_vec: &[u8], location: usize) -> Result<(), bool> {
         let src_size = src_vec.len();
         if location + src_size > self.memory.len() {
             // copy request goes out of bounds
             return Err(false);
         }
 
+        self.mark_pages(location, src_size);
         let mem_slice: &mut [u8] = &mut self.memory[location..location + src_size];
 
-        for (dst, src) in mem_slice.iter_mut().zip(src_vec.as_slice()) {
+        for (dst, src) in mem_slice.iter_mut().zip(src_vec) {
             *dst = *src;
         }
         Ok(())
     }
 
+    /// Mark the pages spanning the specified range as written in the current memory epoch.
+    fn mark_pages(&mut self, start: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = start >> MEMORY_PAGE_SHIFT;
+        let last = (start + len - 1) >> MEMORY_PAGE_SHIFT;
+        for epoch in &mut self.page_epoch[first..=last] {
+            *epoch = self.memory_epoch;
+        }
+        self.note_code_write(start, len);
+    }
+
+    /// Begin a new memory epoch, returning the epoch that just ended. Pages written after this
+    /// call will report as changed since the returned epoch.
+    pub fn advance_memory_epoch(&mut self) -> u32 {
+        let epoch = self.memory_epoch;
+        self.memory_epoch += 1;
+        epoch
+    }
+
+    /// Returns true if the specified page of memory has been written since the end of the
+    /// specified epoch.
+    #[inline]
+    pub fn page_changed_since(&self, page: usize, epoch: u32) -> bool {
+        self.page_epoch[page] > epoch
+    }
+
+    /// Enable or disable tracking of writes to the bytes marked with mark_code(). The code map is
+    /// only allocated while tracking is enabled, so that other writes only pay for the check.
+    pub fn set_code_tracking(&mut self, state: bool) {
+        self.code_map = if state { vec![0; ADDRESS_SPACE / 64] } else { Vec::new() };
+        self.code_writes.clear();
+    }
+
+    /// Mark the specified range as holding a cached instruction, so that writes to it are
+    /// recorded. Returns false if the range can't be tracked: if tracking is disabled, the range
+    /// extends past the 1MB address space, or it contains memory-mapped devices.
+    pub fn mark_code(&mut self, start: usize, len: usize) -> bool {
+        if self.code_map.is_empty() || start + len > ADDRESS_SPACE {
+            return false;
+        }
+        if self.memory_mask[start..start + len]
+            .iter()
+            .any(|flags| flags & MEM_MMIO_BIT != 0)
+        {
+            return false;
+        }
+        for address in start..start + len {
+            self.code_map[address >> 6] |= 1 << (address & 0x3F);
+        }
+        true
+    }
+
+    /// Remove all marks from the code map. Writes already recorded are kept.
+    pub fn clear_code_map(&mut self) {
+        self.code_map.fill(0);
+    }
+
+    /// Record the addresses of any marked bytes within the specified range of written memory.
+    /// Marks are removed as they are hit, so each marked byte is recorded at most once.
+    #[inline]
+    fn note_code_write(&mut self, start: usize, len: usize) {
+        if self.code_map.is_empty() {
+            return;
+        }
+        for address in start..(start + len).min(ADDRESS_SPACE) {
+            let bit = 1 << (address & 0x3F);
+            if self.code_map[address >> 6] & bit != 0 {
+                self.code_map[address >> 6] &= !bit;
+                self.code_writes.push(address as u32);
+            }
+        }
+    }
+
+    /// Pass the address of each marked byte written since the last call to the specified function.
+    pub fn drain_code_writes(&mut self, mut f: impl FnMut(u32)) {
+        for address in self.code_writes.drain(..) {
+            f(address);
+        }
+    }
+
+    /// Returns true if the contents of the specified page can't be tracked by epoch, because it
+    /// contains memory-mapped devices or reads as an open bus.
+    pub fn page_is_volatile(&self, page: usize) -> bool {
+        let address = page << MEMORY_PAGE_SHIFT;
+        !matches!(
+            self.mmio_map_fast[address >> MMIO_MAP_SHIFT],
+            MmioDeviceType::Memory | MmioDeviceType::None
+        ) || (self.open_bus_memory && address + MEMORY_PAGE_SIZE > self.conventional_size)
+    }
+
     pub fn get_slice_at(&self, start: usize, len: usize) -> &[u8] {
         &self.memory[start..start + len]
     }
@@ -648,6 +1143,12 @@ impl BusInterface {
 
     pub fn set_descriptor(&mut self, start: usize, size: usize, cycle_cost: u32, read_only: bool) {
         // TODO: prevent overlapping descriptors
+        if cycle_cost > 0 {
+            let end = std::cmp::min(start + size, self.memory_mask.len());
+            for byte_ref in &mut self.memory_mask[start..end] {
+                *byte_ref |= MEM_WAIT_BIT;
+            }
+        }
         self.desc_vec.push({
             MemRangeDescriptor {
                 address: start,
@@ -668,52 +1169,186 @@ impl BusInterface {
         for byte_ref in &mut self.memory {
             *byte_ref = 0;
         }
+        self.hma.fill(0);
+        self.mark_pages(0, self.memory.len());
     }
 
     pub fn reset(&mut self) {
         // Clear mem range descriptors
         self.desc_vec.clear();
+        for byte_ref in &mut self.memory_mask {
+            *byte_ref &= !MEM_WAIT_BIT;
+        }
+        // Memory-mapped devices keep their wait states.
+        for (desc, _) in self.mmio_map.iter().filter(|(desc, _)| desc.cycle_cost > 0) {
+            for byte_ref in &mut self.memory_mask[desc.address..desc.address + desc.size] {
+                *byte_ref |= MEM_WAIT_BIT;
+            }
+        }
 
         self.clear();
     }
 
+    /// Set the CPU clock factor, updating the timing table and cycle conversion table together.
     pub fn set_cpu_factor(&mut self, cpu_factor: ClockFactor) {
         self.cpu_factor = cpu_factor;
+        self.tick_accum = 0.0;
 
+        Self::update_timing_table(&mut self.timing_table, cpu_factor, self.system_crystal);
         self.recalculate_cycle_lut();
     }
 
+    pub fn get_cpu_factor(&self) -> ClockFactor {
+        self.cpu_factor
+    }
+
     pub fn recalculate_cycle_lut(&mut self) {
         for c in 0..256 {
-            self.cycles_to_ticks[c as usize] = self.cpu_cycles_to_system_ticks(c);
+            self.cycles_to_ticks[c as usize] = self.cpu_factor.cycles_to_ticks(c, self.system_crystal);
         }
     }
 
     #[inline]
     /// Convert a count of CPU cycles to system clock ticks based on the current CPU
-    /// clock divisor.
+    /// clock divisor. With a fixed CPU clock, the fractional tick carried over from the last
+    /// device update is included, so that device accesses line up with device updates.
     fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
         match self.cpu_factor {
-            ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32),
+            ClockFactor::Fixed(_) => {
+                (self.tick_accum + cycles as f64 * self.cpu_factor.ticks_per_cycle(self.system_crystal)) as u32
+            }
+            _ => self.cpu_factor.cycles_to_ticks(cycles, self.system_crystal),
+        }
+    }
+
+    #[inline]
+    /// Convert a count of CPU cycles to system clock ticks for a memory-mapped device access.
+    /// The lookup table can't hold the carried fractional tick, so it is only used when the CPU
+    /// clock is a multiple of the system clock.
+    fn mmio_system_ticks(&self, cycles: u32) -> u32 {
+        match self.cpu_factor {
+            ClockFactor::Fixed(_) => self.cpu_cycles_to_system_ticks(cycles),
+            _ => self.cycles_to_ticks[cycles as usize],
+        }
+    }
+
+    /// Convert the CPU cycles elapsed since the last device update to the number of system ticks
+    /// to run devices for. With a fixed CPU clock, the fractional tick is carried over to the next
+    /// call, so that no ticks are lost over a long run.
+    pub fn advance_system_ticks(&mut self, cycles: u32) -> u32 {
+        let ticks = self.cpu_cycles_to_system_ticks(cycles);
+        if let ClockFactor::Fixed(_) = self.cpu_factor {
+            self.tick_accum += cycles as f64 * self.cpu_factor.ticks_per_cycle(self.system_crystal) - ticks as f64;
         }
+        ticks
     }
 
     #[inline]
     /// Convert a count of system clock ticks to CPU cycles based on the current CPU
     /// clock divisor. If a clock Divisor is set, the dividend will be rounded upwards.
     fn system_ticks_to_cpu_cycles(&self, ticks: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => (ticks + (n as u32) - 1) / (n as u32),
-            ClockFactor::Multiplier(n) => ticks * (n as u32),
+        self.cpu_factor.ticks_to_cycles(ticks, self.system_crystal)
+    }
+
+    /// Return the wait states assigned to the address by the most recently added memory range
+    /// descriptor that contains it. Ranges added by copy_from() and set_descriptor() are
+    /// used to model slow ROM, adapter RAM and other devices on the 8-bit expansion bus.
+    #[inline]
+    fn get_range_wait(&self, address: usize) -> u32 {
+        if self.memory_mask[address] & MEM_WAIT_BIT == 0 {
+            return 0;
+        }
+        // Memory range descriptors take precedence over the ranges of memory-mapped devices.
+        self.desc_vec
+            .iter()
+            .rev()
+            .find(|desc| desc.cycle_cost > 0 && desc.contains(address))
+            .or_else(|| {
+                self.mmio_map
+                    .iter()
+                    .rev()
+                    .map(|(desc, _)| desc)
+                    .find(|desc| desc.cycle_cost > 0 && desc.contains(address))
+            })
+            .map(|desc| desc.cycle_cost)
+            .unwrap_or(0)
+    }
+
+    /// Set the number of additional wait states incurred by an IO access to the specified port.
+    pub fn set_io_wait(&mut self, port: u16, wait_states: u32) {
+        if wait_states > 0 {
+            self.io_wait_map.insert(port, wait_states);
+        }
+        else {
+            self.io_wait_map.remove(&port);
+        }
+    }
+
+    /// Return the number of additional wait states incurred by an IO access to the specified port.
+    #[inline]
+    pub fn get_io_wait(&self, port: u16) -> u32 {
+        if self.io_wait_map.is_empty() {
+            return 0;
         }
+        self.io_wait_map.get(&port).copied().unwrap_or(0)
+    }
+
+    /// Record the last value transferred on the data bus. This is returned by reads of an open
+    /// bus when the open bus type is LastValue.
+    #[inline]
+    pub fn set_open_bus_latch(&mut self, byte: u8) {
+        self.open_bus_latch = byte;
+    }
+
+    /// Return the value read from an unconnected IO port or unpopulated memory address.
+    fn open_bus_byte(&mut self) -> u8 {
+        match self.open_bus_type {
+            OpenBusType::Pattern if !self.open_bus_pattern.is_empty() => {
+                let byte = self.open_bus_pattern[self.open_bus_index];
+                self.open_bus_index = (self.open_bus_index + 1) % self.open_bus_pattern.len();
+                byte
+            }
+            _ => self.peek_open_bus_byte(),
+        }
+    }
+
+    /// Return the value read from an open bus without advancing any pattern.
+    fn peek_open_bus_byte(&self) -> u8 {
+        match self.open_bus_type {
+            OpenBusType::Fixed => self.open_bus_value,
+            OpenBusType::LastValue => self.open_bus_latch,
+            OpenBusType::Pattern => self
+                .open_bus_pattern
+                .get(self.open_bus_index)
+                .copied()
+                .unwrap_or(self.open_bus_value),
+        }
+    }
+
+    /// Returns true if the address is unpopulated memory that should read as an open bus.
+    #[inline]
+    fn is_open_memory(&self, address: usize) -> bool {
+        self.open_bus_memory && address >= self.conventional_size && self.memory_mask[address] & MEM_ROM_BIT == 0
+    }
+
+    /// Return the number of wait states to apply to ROMs installed on this bus.
+    pub fn rom_wait_states(&self) -> u32 {
+        self.rom_wait_states
     }
 
     pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
+        if address >= ADDRESS_SPACE {
+            return Ok(DEFAULT_WAIT_STATES);
+        }
         if address < self.memory.len() {
+            if self.memory_mask[address] & MEM_WAIT_SLOW_MASK == 0 {
+                // Plain RAM.
+                return Ok(DEFAULT_WAIT_STATES);
+            }
+            let range_wait = self.get_range_wait(address);
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES);
+                return Ok(DEFAULT_WAIT_STATES + range_wait);
             }
             else {
                 // Handle memory-mapped devices
@@ -725,21 +1360,25 @@ impl BusInterface {
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
                                     let syswait = mda.get_read_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 VideoCardDispatch::Cga(cga) => {
                                     let syswait = cga.get_read_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
+                                }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let syswait = cpq.get_read_wait(address, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
                                     let syswait = ega.get_read_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
                                     let syswait = vga.get_read_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 _ => {}
                             }
@@ -748,17 +1387,25 @@ impl BusInterface {
                     _ => {}
                 }
                 // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES);
+                return Ok(DEFAULT_WAIT_STATES + range_wait);
             }
         }
         Err(MemError::ReadOutOfBoundsError)
     }
 
     pub fn get_write_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
+        if address >= ADDRESS_SPACE {
+            return Ok(DEFAULT_WAIT_STATES);
+        }
         if address < self.memory.len() {
+            if self.memory_mask[address] & MEM_WAIT_SLOW_MASK == 0 {
+                // Plain RAM.
+                return Ok(DEFAULT_WAIT_STATES);
+            }
+            let range_wait = self.get_range_wait(address);
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES);
+                return Ok(DEFAULT_WAIT_STATES + range_wait);
             }
             else {
                 // Handle memory-mapped devices
@@ -771,21 +1418,25 @@ impl BusInterface {
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
                                     let syswait = mda.get_write_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 VideoCardDispatch::Cga(cga) => {
                                     let syswait = cga.get_write_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
+                                }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let syswait = cpq.get_write_wait(address, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
                                     let syswait = ega.get_write_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
                                     let syswait = vga.get_write_wait(address, system_ticks);
-                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait) + range_wait);
                                 }
                                 _ => {}
                             }
@@ -794,44 +1445,57 @@ impl BusInterface {
                     _ => {}
                 }
                 // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES);
+                return Ok(DEFAULT_WAIT_STATES + range_wait);
             }
         }
         Err(MemError::ReadOutOfBoundsError)
     }
 
     pub fn read_u8(&mut self, address: usize, cycles: u32) -> Result<(u8, u32), MemError> {
+        if address >= ADDRESS_SPACE {
+            return Ok((self.hma_read_u8(address), 0));
+        }
+        if !self.parity_errors.is_empty() {
+            self.check_parity(address, 1);
+        }
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
+                if self.is_open_memory(address) {
+                    return Ok((self.open_bus_byte(), 0));
+                }
                 let data: u8 = self.memory[address];
                 return Ok((data, 0));
             }
             else {
                 // Handle memory-mapped devices
-                let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+                let system_ticks = self.mmio_system_ticks(cycles);
 
                 match self.mmio_map_fast[address >> MMIO_MAP_SHIFT] {
                     MmioDeviceType::Video(vid) => {
                         if let Some(card_dispatch) = self.videocards.get_mut(&vid) {
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
-                                    let (data, _waits) = MemoryMappedDevice::mmio_read_u8(mda, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u8(mda, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 VideoCardDispatch::Cga(cga) => {
-                                    let (data, _waits) = MemoryMappedDevice::mmio_read_u8(cga, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u8(cga, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
+                                }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u8(cpq, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
-                                    let (data, _waits) = MemoryMappedDevice::mmio_read_u8(ega, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u8(ega, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
-                                    let (data, _waits) = MemoryMappedDevice::mmio_read_u8(vga, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u8(vga, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 _ => {}
                             }
@@ -846,9 +1510,15 @@ impl BusInterface {
     }
 
     pub fn peek_u8(&self, address: usize) -> Result<u8, MemError> {
+        if address >= ADDRESS_SPACE {
+            return Ok(self.hma_read_u8(address));
+        }
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
+                if self.is_open_memory(address) {
+                    return Ok(self.peek_open_bus_byte());
+                }
                 let b: u8 = self.memory[address];
                 return Ok(b);
             }
@@ -866,6 +1536,10 @@ impl BusInterface {
                                     let data = MemoryMappedDevice::mmio_peek_u8(cga, address);
                                     return Ok(data);
                                 }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let data = MemoryMappedDevice::mmio_peek_u8(cpq, address);
+                                    return Ok(data);
+                                }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
                                     let data = MemoryMappedDevice::mmio_peek_u8(ega, address);
@@ -889,18 +1563,38 @@ impl BusInterface {
     }
 
     pub fn read_u16(&mut self, address: usize, cycles: u32) -> Result<(u16, u32), MemError> {
+        if address >= ADDRESS_SPACE {
+            let w = u16::from_le_bytes([self.hma_read_u8(address), self.hma_read_u8(address + 1)]);
+            return Ok((w, DEFAULT_WAIT_STATES));
+        }
+        if !self.parity_errors.is_empty() {
+            self.check_parity(address, 2);
+        }
         if address < self.memory.len() - 1 {
+            if self.word_flags(address) & MEM_READ_SLOW_MASK16 == 0 && !self.open_bus_memory {
+                // Both bytes are plain RAM.
+                let w = u16::from_le_bytes([self.memory[address], self.memory[address + 1]]);
+                return Ok((w, DEFAULT_WAIT_STATES));
+            }
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
-                let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
+                let lo = match self.is_open_memory(address) {
+                    true => self.open_bus_byte(),
+                    false => self.memory[address],
+                };
+                let hi = match self.is_open_memory(address + 1) {
+                    true => self.open_bus_byte(),
+                    false => self.memory[address + 1],
+                };
+                let w: u16 = lo as u16 | (hi as u16) << 8;
                 return Ok((w, DEFAULT_WAIT_STATES));
             }
             else {
                 // Handle memory-mapped devices
                 match self.mmio_map_fast[address >> MMIO_MAP_SHIFT] {
                     MmioDeviceType::Video(vid) => {
+                        let system_ticks = self.mmio_system_ticks(cycles);
                         if let Some(card_dispatch) = self.videocards.get_mut(&vid) {
-                            let system_ticks = self.cycles_to_ticks[cycles as usize];
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
                                     //let (data, syswait) = MemoryMappedDevice::read_u16(cga, address, system_ticks);
@@ -912,17 +1606,20 @@ impl BusInterface {
                                     let (data, syswait) = cga.mmio_read_u16(address, system_ticks);
                                     return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    //let (data, syswait) = MemoryMappedDevice::read_u16(cpq, address, system_ticks);
+                                    let (data, syswait) = cpq.mmio_read_u16(address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
+                                }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
-                                    let (data, _syswait) =
-                                        MemoryMappedDevice::mmio_read_u16(ega, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u16(ega, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
-                                    let (data, _syswait) =
-                                        MemoryMappedDevice::mmio_read_u16(vga, address, system_ticks);
-                                    return Ok((data, 0));
+                                    let (data, syswait) = MemoryMappedDevice::mmio_read_u16(vga, address, system_ticks);
+                                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                                 }
                                 _ => {}
                             }
@@ -937,11 +1634,25 @@ impl BusInterface {
     }
 
     pub fn write_u8(&mut self, address: usize, data: u8, cycles: u32) -> Result<u32, MemError> {
+        if address >= ADDRESS_SPACE {
+            self.hma_write_u8(address, data);
+            return Ok(DEFAULT_WAIT_STATES);
+        }
+        if self.write_journal.is_some() {
+            self.journal_write(address, 1);
+        }
         if address < self.memory.len() {
-            if self.memory_mask[address] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
+            let flags = self.memory_mask[address];
+            if flags & MEM_WATCH_BIT != 0 && !self.check_write_watch(address, data) {
+                // Write was blocked by a write-protected watch region.
+                return Ok(DEFAULT_WAIT_STATES);
+            }
+            if flags & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
                 // Address is not mapped and not ROM, write to it if it is within conventional memory.
                 if address < self.conventional_size {
                     self.memory[address] = data;
+                    self.page_epoch[address >> MEMORY_PAGE_SHIFT] = self.memory_epoch;
+                    self.note_code_write(address, 1);
                 }
                 return Ok(DEFAULT_WAIT_STATES);
             }
@@ -949,26 +1660,33 @@ impl BusInterface {
                 // Handle memory-mapped devices.
                 match self.mmio_map_fast[address >> MMIO_MAP_SHIFT] {
                     MmioDeviceType::Video(vid) => {
+                        if !self.video_breakpoints.is_empty() {
+                            self.check_video_breakpoints(address, data);
+                        }
+                        let system_ticks = self.mmio_system_ticks(cycles);
                         if let Some(card_dispatch) = self.videocards.get_mut(&vid) {
-                            let system_ticks = self.cycles_to_ticks[cycles as usize];
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
-                                    let _syswait = mda.mmio_write_u8(address, data, system_ticks);
-                                    //return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value.
-                                    return Ok(0);
+                                    let syswait = mda.mmio_write_u8(address, data, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 VideoCardDispatch::Cga(cga) => {
-                                    let _syswait = cga.mmio_write_u8(address, data, system_ticks);
-                                    //return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value.
-                                    return Ok(0);
+                                    let syswait = cga.mmio_write_u8(address, data, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                }
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let syswait = cpq.mmio_write_u8(address, data, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 #[cfg(feature = "ega")]
                                 VideoCardDispatch::Ega(ega) => {
-                                    MemoryMappedDevice::mmio_write_u8(ega, address, data, system_ticks);
+                                    let syswait = MemoryMappedDevice::mmio_write_u8(ega, address, data, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
-                                    MemoryMappedDevice::mmio_write_u8(vga, address, data, system_ticks);
+                                    let syswait = MemoryMappedDevice::mmio_write_u8(vga, address, data, system_ticks);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 _ => {}
                             }
@@ -983,15 +1701,37 @@ impl BusInterface {
     }
 
     pub fn write_u16(&mut self, address: usize, data: u16, cycles: u32) -> Result<u32, MemError> {
+        if address >= ADDRESS_SPACE {
+            let [lo, hi] = data.to_le_bytes();
+            self.hma_write_u8(address, lo);
+            self.hma_write_u8(address + 1, hi);
+            return Ok(DEFAULT_WAIT_STATES);
+        }
+        if self.write_journal.is_some() {
+            self.journal_write(address, 2);
+        }
         if address < self.memory.len() - 1 {
+            if self.word_flags(address) & MEM_WRITE_SLOW_MASK16 == 0 && address < self.conventional_size - 1 {
+                // Both bytes are plain conventional RAM.
+                self.memory[address..address + 2].copy_from_slice(&data.to_le_bytes());
+                self.mark_pages(address, 2);
+                return Ok(DEFAULT_WAIT_STATES);
+            }
+            if (self.memory_mask[address] | self.memory_mask[address + 1]) & MEM_WATCH_BIT != 0 {
+                // Split watched word writes so that each byte is checked individually.
+                let waits = self.write_u8(address, (data & 0xFF) as u8, cycles)?;
+                return Ok(waits + self.write_u8(address + 1, (data >> 8) as u8, cycles)?);
+            }
             if self.memory_mask[address] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
                 // Address is not mapped. Write to memory if within conventional memory size.
                 if address < self.conventional_size - 1 {
                     self.memory[address] = (data & 0xFF) as u8;
                     self.memory[address + 1] = (data >> 8) as u8;
+                    self.mark_pages(address, 2);
                 }
                 else if address < self.conventional_size {
                     self.memory[address] = (data & 0xFF) as u8;
+                    self.mark_pages(address, 1);
                 }
                 return Ok(DEFAULT_WAIT_STATES);
             }
@@ -999,9 +1739,12 @@ impl BusInterface {
                 // Handle memory-mapped devices
                 match self.mmio_map_fast[address >> MMIO_MAP_SHIFT] {
                     MmioDeviceType::Video(vid) => {
+                        if !self.video_breakpoints.is_empty() {
+                            self.check_video_breakpoints(address, (data & 0xFF) as u8);
+                            self.check_video_breakpoints(address + 1, (data >> 8) as u8);
+                        }
+                        let system_ticks = self.mmio_system_ticks(cycles);
                         if let Some(card_dispatch) = self.videocards.get_mut(&vid) {
-                            let system_ticks = self.cycles_to_ticks[cycles as usize];
-
                             match card_dispatch {
                                 VideoCardDispatch::Mda(mda) => {
                                     let mut syswait;
@@ -1029,15 +1772,44 @@ impl BusInterface {
                                     return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                     // temporary wait state value.
                                 }
-                                #[cfg(feature = "ega")]
-                                VideoCardDispatch::Ega(ega) => {
-                                    MemoryMappedDevice::mmio_write_u8(ega, address, (data & 0xFF) as u8, system_ticks);
-                                    MemoryMappedDevice::mmio_write_u8(ega, address + 1, (data >> 8) as u8, 0);
+                                VideoCardDispatch::Compaq(cpq) => {
+                                    let mut syswait;
+                                    syswait = MemoryMappedDevice::mmio_write_u8(
+                                        cpq,
+                                        address,
+                                        (data & 0xFF) as u8,
+                                        system_ticks,
+                                    );
+                                    syswait +=
+                                        MemoryMappedDevice::mmio_write_u8(cpq, address + 1, (data >> 8) as u8, 0);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
+                                    // temporary wait state value.
+                                }
+                                #[cfg(feature = "ega")]
+                                VideoCardDispatch::Ega(ega) => {
+                                    let mut syswait;
+                                    syswait = MemoryMappedDevice::mmio_write_u8(
+                                        ega,
+                                        address,
+                                        (data & 0xFF) as u8,
+                                        system_ticks,
+                                    );
+                                    syswait +=
+                                        MemoryMappedDevice::mmio_write_u8(ega, address + 1, (data >> 8) as u8, 0);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 #[cfg(feature = "vga")]
                                 VideoCardDispatch::Vga(vga) => {
-                                    MemoryMappedDevice::mmio_write_u8(vga, address, (data & 0xFF) as u8, system_ticks);
-                                    MemoryMappedDevice::mmio_write_u8(vga, address + 1, (data >> 8) as u8, 0);
+                                    let mut syswait;
+                                    syswait = MemoryMappedDevice::mmio_write_u8(
+                                        vga,
+                                        address,
+                                        (data & 0xFF) as u8,
+                                        system_ticks,
+                                    );
+                                    syswait +=
+                                        MemoryMappedDevice::mmio_write_u8(vga, address + 1, (data >> 8) as u8, 0);
+                                    return Ok(self.system_ticks_to_cpu_cycles(syswait));
                                 }
                                 _ => {}
                             }
@@ -1051,6 +1823,15 @@ impl BusInterface {
         Err(MemError::ReadOutOfBoundsError)
     }
 
+    /// Return the bit flags for both bytes of the word at the specified address, packed into a u16
+    /// with the flags for the low byte in the low 8 bits. This allows the word access paths to test
+    /// both bytes with a single 16-bit load and branch. The address must be in bounds.
+    #[inline]
+    fn word_flags(&self, address: usize) -> u16 {
+        let pair: [u8; 2] = self.memory_mask[address..address + 2].try_into().unwrap();
+        u16::from_le_bytes(pair)
+    }
+
     /// Get bit flags for the specified byte at address
     #[inline]
     pub fn get_flags(&self, address: usize) -> u8 {
@@ -1078,6 +1859,270 @@ impl BusInterface {
         }
     }
 
+    /// Add a write watch region, returning its index.
+    pub fn add_watch_region(&mut self, region: WatchRegion) -> usize {
+        log::debug!(
+            "Adding watch region at {:05X} size {:X} protect: {} action: {:?}",
+            region.start,
+            region.size,
+            region.protect,
+            region.action
+        );
+        self.watch_regions.push(region);
+        self.update_watch_flags();
+        self.watch_regions.len() - 1
+    }
+
+    /// Remove the write watch region at the specified index.
+    pub fn remove_watch_region(&mut self, idx: usize) {
+        if idx < self.watch_regions.len() {
+            self.watch_regions.remove(idx);
+            self.update_watch_flags();
+        }
+    }
+
+    pub fn clear_watch_regions(&mut self) {
+        self.watch_regions.clear();
+        self.update_watch_flags();
+    }
+
+    pub fn watch_regions(&self) -> &[WatchRegion] {
+        &self.watch_regions
+    }
+
+    /// Return the most recent write to a watch region, if any.
+    pub fn last_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit
+    }
+
+    /// Return whether a write to a watch region with the Break action has occurred, and reset it.
+    pub fn take_watch_break(&mut self) -> bool {
+        std::mem::replace(&mut self.watch_break, false)
+    }
+
+    /// Return the address and value of the write that hit a video write breakpoint, if any, and
+    /// reset it.
+    pub fn take_video_break(&mut self) -> Option<(u32, u8)> {
+        self.video_break.take()
+    }
+
+    /// Enable or disable watching the interrupt vector table for changes. Changes are collected
+    /// per instruction by commit_vector_changes().
+    pub fn set_ivt_watch(&mut self, state: bool) {
+        self.ivt_watch = state;
+        self.ivt_pending.clear();
+        self.update_watch_flags();
+    }
+
+    /// Return whether the interrupt vector table has been written since the last call to
+    /// commit_vector_changes().
+    #[inline]
+    pub fn ivt_written(&self) -> bool {
+        !self.ivt_pending.is_empty()
+    }
+
+    /// Record the vectors changed by the instruction at the specified CS:IP. Vectors rewritten
+    /// with their existing value are not recorded.
+    pub fn commit_vector_changes(&mut self, cs: u16, ip: u16) {
+        for (vector, old) in std::mem::take(&mut self.ivt_pending) {
+            let base = vector as usize * 4;
+            let new = u32::from_le_bytes([
+                self.memory[base],
+                self.memory[base + 1],
+                self.memory[base + 2],
+                self.memory[base + 3],
+            ]);
+            if new == old {
+                continue;
+            }
+            let change = VectorChange {
+                vector,
+                old_cs: (old >> 16) as u16,
+                old_ip: old as u16,
+                new_cs: (new >> 16) as u16,
+                new_ip: new as u16,
+                cs,
+                ip,
+            };
+            log::info!(
+                "INT {:02X} vector changed from {:04X}:{:04X} to {:04X}:{:04X} by instruction at {:04X}:{:04X}",
+                vector,
+                change.old_cs,
+                change.old_ip,
+                change.new_cs,
+                change.new_ip,
+                cs,
+                ip
+            );
+            self.vector_changes.push(change);
+        }
+    }
+
+    /// Return the interrupt vector changes recorded since the last call.
+    pub fn take_vector_changes(&mut self) -> Vec<VectorChange> {
+        std::mem::take(&mut self.vector_changes)
+    }
+
+    /// Enable or disable journaling of memory and IO writes. Enabling journaling starts an empty
+    /// journal.
+    pub fn set_write_journal(&mut self, state: bool) {
+        self.write_journal = state.then(WriteJournal::default);
+    }
+
+    /// Return the writes journaled since the last call, leaving an empty journal in place.
+    pub fn take_write_journal(&mut self) -> WriteJournal {
+        self.write_journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Record the current value of each byte of conventional memory about to be written.
+    /// Writes to ROM and memory-mapped devices do not change the memory array, so are not
+    /// recorded.
+    fn journal_write(&mut self, address: usize, len: usize) {
+        if let Some(journal) = &mut self.write_journal {
+            for addr in address..(address + len).min(self.conventional_size) {
+                if self.memory_mask[addr] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
+                    journal.memory.push((addr, self.memory[addr]));
+                }
+            }
+        }
+    }
+
+    /// Undo the memory writes recorded in a journal by restoring the values they replaced, most
+    /// recent first.
+    pub fn undo_writes(&mut self, journal: &WriteJournal) {
+        for &(address, value) in journal.memory.iter().rev() {
+            self.memory[address] = value;
+            self.mark_pages(address, 1);
+        }
+    }
+
+    /// Add or remove a simulated RAM parity error at the specified address. Reading the address
+    /// latches a parity check in the PPI, raising NMI if it is enabled.
+    pub fn set_parity_error(&mut self, address: usize, state: bool) {
+        self.parity_errors.retain(|&a| a != address);
+        if state {
+            self.parity_errors.push(address);
+        }
+    }
+
+    /// Remove all simulated RAM parity errors.
+    pub fn clear_parity_errors(&mut self) {
+        self.parity_errors.clear();
+    }
+
+    /// Return whether a RAM parity error has been latched, which drives the NMI line on the
+    /// 5150 and 5160.
+    pub fn parity_nmi(&self) -> bool {
+        self.ppi.as_ref().is_some_and(|ppi| ppi.parity_check())
+    }
+
+    fn check_parity(&mut self, address: usize, len: usize) {
+        if self
+            .parity_errors
+            .iter()
+            .any(|&a| (address..address + len).contains(&a))
+        {
+            if let Some(ppi) = &mut self.ppi {
+                log::debug!("Parity error reading [{:05X}]", address);
+                ppi.set_parity_error();
+            }
+        }
+    }
+
+    fn update_watch_flags(&mut self) {
+        for byte_ref in &mut self.memory_mask {
+            *byte_ref &= !MEM_WATCH_BIT;
+        }
+        if self.ivt_watch {
+            for byte_ref in &mut self.memory_mask[..IVT_SIZE] {
+                *byte_ref |= MEM_WATCH_BIT;
+            }
+        }
+        for region in &self.watch_regions {
+            let end = std::cmp::min(region.start as usize + region.size as usize, self.memory_mask.len());
+            for byte_ref in &mut self.memory_mask[region.start as usize..end] {
+                *byte_ref |= MEM_WATCH_BIT;
+            }
+        }
+    }
+
+    /// Handle a write to a watched address. Returns false if the write should be blocked.
+    fn check_write_watch(&mut self, address: usize, data: u8) -> bool {
+        // Save the original value of a vector the first time it is written by an instruction.
+        if self.ivt_watch && address < IVT_SIZE {
+            let vector = (address / 4) as u8;
+            if !self.ivt_pending.iter().any(|(v, _)| *v == vector) {
+                let base = vector as usize * 4;
+                let old = u32::from_le_bytes([
+                    self.memory[base],
+                    self.memory[base + 1],
+                    self.memory[base + 2],
+                    self.memory[base + 3],
+                ]);
+                self.ivt_pending.push((vector, old));
+            }
+        }
+
+        // The most recently added region takes priority.
+        let (idx, region) = match self
+            .watch_regions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, region)| region.contains(address))
+        {
+            Some((idx, region)) => (idx, region),
+            None => return true,
+        };
+
+        match region.action {
+            WatchAction::Ignore => {}
+            WatchAction::Log | WatchAction::Break => {
+                log::warn!(
+                    "Write to watched address {:05X}: {:02X}{}",
+                    address,
+                    data,
+                    if region.protect { " (blocked)" } else { "" }
+                );
+            }
+        }
+        if region.action == WatchAction::Break {
+            self.watch_break = true;
+        }
+        let blocked = region.protect;
+        self.watch_hit = Some(WatchHit {
+            region: idx,
+            address: address as u32,
+            data,
+            blocked,
+        });
+        !blocked
+    }
+
+    pub fn add_video_breakpoint(&mut self, bp: VideoWriteBreakpoint) {
+        log::debug!(
+            "Setting breakpoint on video write at {:05X} size {:X} value: {:?}",
+            bp.start,
+            bp.size,
+            bp.value
+        );
+        self.video_breakpoints.push(bp);
+    }
+
+    pub fn clear_video_breakpoints(&mut self) {
+        self.video_breakpoints.clear();
+    }
+
+    /// Check a write dispatched to a video card against video write breakpoints. A hit enters the
+    /// BreakpointHit state after the current instruction, as with watch regions.
+    fn check_video_breakpoints(&mut self, address: usize, data: u8) {
+        if self.video_breakpoints.iter().any(|bp| bp.matches(address, data)) {
+            log::warn!("Video write breakpoint hit: {:05X}: {:02X}", address, data);
+            self.watch_break = true;
+            self.video_break = Some((address as u32, data));
+        }
+    }
+
     /// Dump memory to a string representation.
     ///
     /// Does not honor memory mappings.
@@ -1314,23 +2359,6 @@ impl BusInterface {
         vec
     }
 
-    pub fn dump_mem(&self, path: &Path) {
-        let filename = path.to_path_buf();
-
-        let len = 0x100000;
-        let address = 0;
-        log::debug!("Dumping {} bytes at address {:05X}", len, address);
-
-        match std::fs::write(filename.clone(), &self.memory) {
-            Ok(_) => {
-                log::debug!("Wrote memory dump: {}", filename.display())
-            }
-            Err(e) => {
-                log::error!("Failed to write memory dump '{}': {}", filename.display(), e)
-            }
-        }
-    }
-
     pub fn dump_ivr_tokens(&mut self) -> Vec<Vec<SyntaxToken>> {
         let mut vec: Vec<Vec<SyntaxToken>> = Vec::new();
 
@@ -1415,9 +2443,11 @@ impl BusInterface {
         &mut self,
         machine_desc: &MachineDescriptor,
         machine_config: &MachineConfiguration,
-    ) -> Result<(), Error> {
+    ) -> Result<(), DeviceInstallError> {
         let video_frame_debug = false;
         let clock_mode = ClockingMode::Default;
+        self.video_frame_debug = video_frame_debug;
+        self.video_clock_mode = clock_mode;
 
         // First we need to initialize the PPI. The PPI is used to read the system's DIP switches, so the PPI must be
         // given several parameters from the machine configuration.
@@ -1437,27 +2467,78 @@ impl BusInterface {
             .unwrap_or(0);
 
         // Get normalized conventional memory and set it.
-        let conventional_memory = normalize_conventional_memory(machine_config)?;
+        let conventional_memory =
+            normalize_conventional_memory(machine_config).map_err(|e| DeviceInstallError::Memory(e.to_string()))?;
         self.set_conventional_size(conventional_memory as usize);
+        self.set_address_wrap(machine_config.memory.address_wrap);
+
+        // Apply conventional memory wait states, and any additional expansion bus wait states.
+        if machine_config.memory.conventional.wait_states > 0 {
+            self.set_descriptor(
+                0,
+                conventional_memory as usize,
+                machine_config.memory.conventional.wait_states,
+                false,
+            );
+        }
+        if let Some(open_bus) = &machine_config.open_bus {
+            self.open_bus_type = open_bus.ob_type;
+            self.open_bus_value = open_bus.value.unwrap_or(NO_IO_BYTE);
+            self.open_bus_pattern = open_bus.pattern.clone().unwrap_or_default();
+            self.open_bus_index = 0;
+            self.open_bus_memory = open_bus.memory;
+            if self.open_bus_type == OpenBusType::Pattern && self.open_bus_pattern.is_empty() {
+                log::warn!("Open bus type is Pattern, but no pattern was specified.");
+            }
+        }
+        if let Some(wait_config) = &machine_config.wait_states {
+            self.rom_wait_states = wait_config.rom;
+            for mem_wait in wait_config.memory.iter() {
+                let address = mem_wait.address as usize;
+                if address + mem_wait.size as usize > ADDRESS_SPACE {
+                    return Err(DeviceInstallError::WaitStateRange {
+                        address,
+                        size: mem_wait.size as usize,
+                    });
+                }
+                self.set_descriptor(address, mem_wait.size as usize, mem_wait.wait_states, false);
+            }
+            for io_wait in wait_config.io.iter() {
+                for offset in 0..io_wait.size.unwrap_or(1) {
+                    self.set_io_wait(io_wait.port.wrapping_add(offset), io_wait.wait_states);
+                }
+            }
+        }
 
         // Set the expansion rom flag for DIP if there is anything besides a video card
         // that needs an expansion ROM.
         //let mut have_expansion = { machine_config.hdc.is_some() };
         //have_expansion = false;
+        self.video_have_expansion = false;
 
         // Create PPI if PPI is defined for this machine type
         if machine_desc.have_ppi {
-            self.ppi = Some(Ppi::new(
+            // Set the DIP switches to match the installed hardware, unless the configuration
+            // specifies them.
+            let mut dip_switches = Ppi::default_dip_switches(
                 machine_desc.machine_type,
                 conventional_memory,
-                false,
-                video_types,
+                self.video_have_expansion,
+                &video_types,
                 num_floppies,
-            ));
+            );
+            if let Some(dip_config) = &machine_config.dip_switches {
+                dip_switches = dip_config.apply(dip_switches);
+            }
+            self.ppi = Some(Ppi::new(machine_desc.machine_type, dip_switches));
             // Add PPI ports to io_map
             let port_list = self.ppi.as_mut().unwrap().port_list();
             self.io_map
                 .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Ppi)));
+            // Machines with a PPI mask NMI with a register that an AT uses for its secondary PIC.
+            if machine_desc.pic_type != PicType::Chained {
+                self.io_map.insert(NMI_MASK_PORT, IoDeviceType::NmiMask);
+            }
         }
 
         // Create the PIT. One PIT will always exist, but it may be an 8253 or 8254.
@@ -1494,6 +2575,15 @@ impl BusInterface {
             .extend(port_list.into_iter().map(|p| (p, IoDeviceType::DmaPrimary)));
         self.dma1 = Some(dma1);
 
+        // Create the secondary DMA controller if the machine has one (AT)
+        if machine_desc.dma_type == DmaType::Chained {
+            let dma2 = DMAController::new_secondary();
+            let port_list = dma2.port_list();
+            self.io_map
+                .extend(port_list.into_iter().map(|p| (p, IoDeviceType::DmaSecondary)));
+            self.dma2 = Some(dma2);
+        }
+
         // Create PIC. One PIC will always exist.
         let pic1 = Pic::new();
         // Add PIC ports to io_map
@@ -1502,6 +2592,15 @@ impl BusInterface {
             .extend(port_list.into_iter().map(|p| (p, IoDeviceType::PicPrimary)));
         self.pic1 = Some(pic1);
 
+        // Create the secondary PIC if the machine has one (AT). It is cascaded on IR2 of the primary.
+        if machine_desc.pic_type == PicType::Chained {
+            let pic2 = Pic::with_port_base(PIC2_COMMAND_PORT);
+            let port_list = pic2.port_list();
+            self.io_map
+                .extend(port_list.into_iter().map(|p| (p, IoDeviceType::PicSecondary)));
+            self.pic2 = Some(pic2);
+        }
+
         // Create keyboard if specified.
         if let Some(kb_config) = &machine_config.keyboard {
             let mut keyboard = Keyboard::new(kb_config.kb_type, false);
@@ -1517,40 +2616,57 @@ impl BusInterface {
 
         // Create FDC if specified.
         if let Some(fdc_config) = &machine_config.fdc {
-            let floppy_ct = fdc_config.drive.len();
+            let drive_types: Vec<_> = fdc_config.drive.iter().map(|d| d.fd_type).collect();
 
-            let fdc = FloppyController::new(floppy_ct);
+            let fdc = FloppyController::new(&drive_types);
             // Add FDC ports to io_map
-            let port_list = fdc.port_list();
-            self.io_map
-                .extend(port_list.into_iter().map(|p| (p, IoDeviceType::FloppyController)));
+            self.map_io_ports(fdc.port_list(), IoDeviceType::FloppyController)?;
             self.fdc = Some(fdc);
         }
 
         // Create a HardDiskController if specified
         if let Some(hdc_config) = &machine_config.hdc {
             match hdc_config.hdc_type {
-                HardDiskControllerType::IbmXebec => {
+                HardDiskControllerType::IbmXebec | HardDiskControllerType::Wd1002 => {
                     // TODO: Get the correct drive type from the specified VHD...?
-                    let hdc = HardDiskController::new(2, DRIVE_TYPE2_DIP);
+                    let mut hdc = HardDiskController::new(hdc_config.hdc_type, 2, DRIVE_TYPE2_DIP);
+                    hdc.set_resources(
+                        hdc_config.io_base.unwrap_or(HDC_DATA_REGISTER),
+                        Self::validate_irq(IoDeviceType::HardDiskController, hdc_config.irq.unwrap_or(HDC_IRQ))?,
+                        Self::validate_dma(IoDeviceType::HardDiskController, hdc_config.dma.unwrap_or(HDC_DMA))?,
+                    );
                     // Add HDC ports to io_map
-                    let port_list = hdc.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::HardDiskController)));
+                    self.map_io_ports(hdc.port_list(), IoDeviceType::HardDiskController)?;
                     self.hdc = Some(hdc);
                 }
             }
         }
 
         // Create a Serial card if specified
-        if let Some(serial_config) = machine_config.serial.get(0) {
+        if machine_config.serial.len() > 1 {
+            return Err(DeviceInstallError::TooManySerialControllers(
+                machine_config.serial.len(),
+            ));
+        }
+        if let Some(serial_config) = machine_config.serial.first() {
             match serial_config.sc_type {
                 SerialControllerType::IbmAsync => {
-                    let serial = SerialPortController::new();
+                    let mut serial = SerialPortController::new();
+                    if serial_config.port.len() > SERIAL_PORT_COUNT {
+                        return Err(DeviceInstallError::TooManySerialPorts {
+                            specified: serial_config.port.len(),
+                            supported: SERIAL_PORT_COUNT,
+                        });
+                    }
+                    for (i, port_config) in serial_config.port.iter().enumerate() {
+                        serial.set_port_resources(
+                            i,
+                            port_config.io_base as u16,
+                            Self::validate_irq(IoDeviceType::Serial, port_config.irq as u8)?,
+                        );
+                    }
                     // Add Serial Controller ports to io_map
-                    let port_list = serial.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Serial)));
+                    self.map_io_ports(serial.port_list(), IoDeviceType::Serial)?;
                     self.serial = Some(serial);
                 }
             }
@@ -1559,102 +2675,484 @@ impl BusInterface {
         // Create a Serial mouse if specified
         if let Some(serial_mouse_config) = &machine_config.serial_mouse {
             // Only create mouse if we have as serial card to plug it into!
-            if self.serial.is_some() {
-                match serial_mouse_config.mouse_type {
-                    SerialMouseType::Microsoft => {
-                        let mouse = Mouse::new(serial_mouse_config.port as usize);
-                        self.mouse = Some(mouse);
-                    }
-                }
+            if self.serial.is_none() {
+                return Err(DeviceInstallError::SerialMouseNoController);
+            }
+            if serial_mouse_config.port as usize >= SERIAL_PORT_COUNT {
+                return Err(DeviceInstallError::SerialMouseInvalidPort(
+                    serial_mouse_config.port as usize,
+                ));
             }
+            self.create_serial_mouse(serial_mouse_config);
+        }
+
+        // Create a game port if specified
+        if let Some(game_port_config) = &machine_config.game_port {
+            let game_port = GamePort::new(game_port_config);
+            self.map_io_ports(game_port.port_list(), IoDeviceType::GamePort)?;
+            self.game_port = Some(game_port);
+        }
+
+        // Create a real time clock if specified
+        if let Some(rtc_config) = &machine_config.rtc {
+            let rtc = Rtc::new(rtc_config.io_base);
+            self.map_io_ports(rtc.port_list(), IoDeviceType::Rtc)?;
+            self.rtc = Some(rtc);
         }
 
         // Create video cards
         for (i, card) in machine_config.video.iter().enumerate() {
-            let video_dispatch;
             let video_id = VideoCardId {
                 idx:   i,
                 vtype: card.video_type,
             };
+            self.install_videocard(video_id, card, clock_mode, video_frame_debug)?;
+        }
 
-            log::debug!("Creating video card of type: {:?}", card.video_type);
-            match card.video_type {
-                VideoType::MDA => {
-                    let mda = MDACard::new(TraceLogger::None, clock_mode, true, video_frame_debug);
-                    let port_list = mda.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Video(video_id))));
+        self.machine_desc = Some(*machine_desc);
+        self.system_crystal = machine_desc.system_crystal;
+        Ok(())
+    }
 
-                    let mem_descriptor = MemRangeDescriptor::new(mda::MDA_MEM_ADDRESS, mda::MDA_MEM_APERTURE, false);
-                    self.register_map(MmioDeviceType::Video(video_id), mem_descriptor);
+    /// Check a machine configuration for resource conflicts and missing devices, without installing
+    /// anything. Every problem found is returned, rather than just the first.
+    ///
+    /// Errors describe configurations that install_devices() would reject, or whose devices could
+    /// not work together on real hardware. Warnings describe configurations that can be installed,
+    /// but are likely to be mistakes.
+    pub fn validate_config(
+        machine_desc: &MachineDescriptor,
+        machine_config: &MachineConfiguration,
+    ) -> Vec<ConfigDiagnostic> {
+        let mut diagnostics = Vec::new();
 
-                    video_dispatch = VideoCardDispatch::Mda(mda)
+        if let Err(e) = normalize_conventional_memory(machine_config) {
+            diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::Memory(e.to_string())));
+        }
+        if let Some(wait_config) = &machine_config.wait_states {
+            for mem_wait in wait_config.memory.iter() {
+                let address = mem_wait.address as usize;
+                if address + mem_wait.size as usize > ADDRESS_SPACE {
+                    diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::WaitStateRange {
+                        address,
+                        size: mem_wait.size as usize,
+                    }));
                 }
-                VideoType::CGA => {
-                    let cga = CGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
-                    let port_list = cga.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Video(video_id))));
+            }
+        }
+
+        // Collect the IO ports, IRQs and DMA channels claimed by each device.
+        let mut ports: Vec<(IoDeviceType, Vec<u16>)> = Vec::new();
+        let mut irqs: Vec<(IoDeviceType, u8)> = Vec::new();
+        let mut dmas: Vec<(IoDeviceType, usize)> = Vec::new();
+
+        if machine_desc.have_ppi {
+            let ppi = Ppi::new(machine_desc.machine_type, DipSwitches::default());
+            ports.push((IoDeviceType::Ppi, ppi.port_list()));
+            irqs.push((IoDeviceType::Ppi, 1));
+            if machine_desc.pic_type != PicType::Chained {
+                ports.push((IoDeviceType::NmiMask, vec![NMI_MASK_PORT]));
+            }
+        }
+        let pit = Pit::new(
+            machine_desc.pit_type,
+            machine_desc.timer_crystal.unwrap_or(machine_desc.system_crystal),
+            machine_desc.timer_divisor,
+        );
+        ports.push((IoDeviceType::Pit, pit.port_list()));
+        irqs.push((IoDeviceType::Pit, 0));
+        ports.push((IoDeviceType::DmaPrimary, DMAController::new().port_list()));
+        ports.push((IoDeviceType::PicPrimary, Pic::new().port_list()));
 
-                    let mem_descriptor = MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false);
-                    self.register_map(MmioDeviceType::Video(video_id), mem_descriptor);
+        if machine_config.keyboard.is_none() {
+            diagnostics.push(ConfigDiagnostic::warning(DeviceInstallError::MissingDevice("keyboard")));
+        }
+
+        if let Some(fdc_config) = &machine_config.fdc {
+            let drive_types: Vec<_> = fdc_config.drive.iter().map(|d| d.fd_type).collect();
+            let fdc = FloppyController::new(&drive_types);
+            ports.push((IoDeviceType::FloppyController, fdc.port_list()));
+            irqs.push((IoDeviceType::FloppyController, FDC_IRQ));
+            dmas.push((IoDeviceType::FloppyController, FDC_DMA));
+        }
 
-                    video_dispatch = VideoCardDispatch::Cga(cga)
+        if let Some(hdc_config) = &machine_config.hdc {
+            match hdc_config.hdc_type {
+                HardDiskControllerType::IbmXebec | HardDiskControllerType::Wd1002 => {
+                    let device = IoDeviceType::HardDiskController;
+                    let irq = hdc_config.irq.unwrap_or(HDC_IRQ);
+                    let dma = hdc_config.dma.unwrap_or(HDC_DMA);
+                    if let Err(e) = Self::validate_irq(device, irq) {
+                        diagnostics.push(ConfigDiagnostic::error(e));
+                    }
+                    if let Err(e) = Self::validate_dma(device, dma) {
+                        diagnostics.push(ConfigDiagnostic::error(e));
+                    }
+                    let mut hdc = HardDiskController::new(hdc_config.hdc_type, 2, DRIVE_TYPE2_DIP);
+                    hdc.set_resources(hdc_config.io_base.unwrap_or(HDC_DATA_REGISTER), irq, dma);
+                    ports.push((device, hdc.port_list()));
+                    irqs.push((device, irq));
+                    dmas.push((device, dma));
                 }
-                #[cfg(feature = "ega")]
-                VideoType::EGA => {
-                    let ega = EGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
-                    let port_list = ega.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Video(video_id))));
+            }
+        }
+
+        if machine_config.serial.len() > 1 {
+            diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::TooManySerialControllers(
+                machine_config.serial.len(),
+            )));
+        }
+        if let Some(serial_config) = machine_config.serial.first() {
+            match serial_config.sc_type {
+                SerialControllerType::IbmAsync => {
+                    if serial_config.port.len() > SERIAL_PORT_COUNT {
+                        diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::TooManySerialPorts {
+                            specified: serial_config.port.len(),
+                            supported: SERIAL_PORT_COUNT,
+                        }));
+                    }
+                    let mut serial = SerialPortController::new();
+                    for (i, port_config) in serial_config.port.iter().take(SERIAL_PORT_COUNT).enumerate() {
+                        let irq = port_config.irq as u8;
+                        if let Err(e) = Self::validate_irq(IoDeviceType::Serial, irq) {
+                            diagnostics.push(ConfigDiagnostic::error(e));
+                        }
+                        serial.set_port_resources(i, port_config.io_base as u16, irq);
+                        irqs.push((IoDeviceType::Serial, irq));
+                    }
+                    ports.push((IoDeviceType::Serial, serial.port_list()));
+                }
+            }
+        }
+
+        if let Some(serial_mouse_config) = &machine_config.serial_mouse {
+            if machine_config.serial.is_empty() {
+                diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::SerialMouseNoController));
+            }
+            else if serial_mouse_config.port as usize >= SERIAL_PORT_COUNT {
+                diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::SerialMouseInvalidPort(
+                    serial_mouse_config.port as usize,
+                )));
+            }
+        }
+
+        if let Some(game_port_config) = &machine_config.game_port {
+            ports.push((IoDeviceType::GamePort, GamePort::new(game_port_config).port_list()));
+        }
+        if let Some(rtc_config) = &machine_config.rtc {
+            ports.push((IoDeviceType::Rtc, Rtc::new(rtc_config.io_base).port_list()));
+        }
 
-                    let cga_mem_descriptor =
-                        MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false);
-                    let ega_mem_descriptor =
-                        MemRangeDescriptor::new(ega::EGA_MEM_ADDRESS, ega::EGA_GFX_PLANE_SIZE, false);
-                    self.register_map(MmioDeviceType::Video(video_id), cga_mem_descriptor);
-                    self.register_map(MmioDeviceType::Video(video_id), ega_mem_descriptor);
+        // Video cards claim memory apertures as well as ports. Cards with a parallel port use its
+        // fixed IRQ.
+        let mut apertures: Vec<(IoDeviceType, MemRangeDescriptor)> = Vec::new();
+        if machine_config.video.is_empty() {
+            diagnostics.push(ConfigDiagnostic::warning(DeviceInstallError::MissingDevice(
+                "video card",
+            )));
+        }
+        for (i, card) in machine_config.video.iter().enumerate() {
+            let video_id = VideoCardId {
+                idx:   i,
+                vtype: card.video_type,
+            };
+            let device = IoDeviceType::Video(video_id);
+            match Self::create_videocard(video_id, card, ClockingMode::Default, false) {
+                Ok((_, port_list, mem_descriptors)) => {
+                    ports.push((device, port_list));
+                    apertures.extend(mem_descriptors.into_iter().map(|desc| (device, desc)));
+                    if matches!(card.video_type, VideoType::MDA | VideoType::CompaqDual) {
+                        irqs.push((device, LPT_DEFAULT_IRQ as u8));
+                    }
+                }
+                Err(e) => diagnostics.push(ConfigDiagnostic::error(e)),
+            }
+        }
 
-                    video_dispatch = VideoCardDispatch::Ega(ega)
+        // Report each conflicting pair of devices once, at the first resource they share.
+        let mut io_map: HashMap<u16, IoDeviceType> = HashMap::new();
+        let mut reported: Vec<(IoDeviceType, IoDeviceType)> = Vec::new();
+        for (device, port_list) in &ports {
+            for port in port_list {
+                match io_map.get(port) {
+                    Some(other) if other != device => {
+                        if !reported.contains(&(*device, *other)) {
+                            reported.push((*device, *other));
+                            diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::IoConflict {
+                                device: *device,
+                                port:   *port,
+                                other:  *other,
+                            }));
+                        }
+                    }
+                    _ => {
+                        io_map.insert(*port, *device);
+                    }
                 }
-                #[cfg(feature = "vga")]
-                VideoType::VGA => {
-                    let vga = VGACard::new(TraceLogger::None);
-                    let port_list = vga.port_list();
-                    self.io_map
-                        .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Video(video_id))));
-
-                    let cga_mem_descriptor =
-                        MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false);
-                    let mem_descriptor = MemRangeDescriptor::new(vga::VGA_GFX_ADDRESS, vga::VGA_GFX_PLANE_SIZE, false);
-                    self.register_map(MmioDeviceType::Video(video_id), cga_mem_descriptor);
-                    self.register_map(MmioDeviceType::Video(video_id), mem_descriptor);
-
-                    video_dispatch = VideoCardDispatch::Vga(vga)
-                }
-                #[allow(unreachable_patterns)]
-                _ => {
-                    panic!(
-                        "card type {:?} not implemented or feature not compiled",
-                        card.video_type
-                    );
+            }
+        }
+
+        for (i, (device, irq)) in irqs.iter().enumerate() {
+            if let Some((other, _)) = irqs[..i].iter().find(|(_, other_irq)| other_irq == irq) {
+                diagnostics.push(ConfigDiagnostic::warning(DeviceInstallError::IrqShared {
+                    irq:    *irq,
+                    device: *device,
+                    other:  *other,
+                }));
+            }
+        }
+
+        for (i, (device, dma)) in dmas.iter().enumerate() {
+            if let Some((other, _)) = dmas[..i].iter().find(|(_, other_dma)| other_dma == dma) {
+                diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::DmaConflict {
+                    dma:    *dma,
+                    device: *device,
+                    other:  *other,
+                }));
+            }
+        }
+
+        for (i, (device, range)) in apertures.iter().enumerate() {
+            let overlap = apertures[..i].iter().find(|(other, other_range)| {
+                other != device
+                    && range.address < other_range.address + other_range.size
+                    && other_range.address < range.address + range.size
+            });
+            if let Some((other, other_range)) = overlap {
+                diagnostics.push(ConfigDiagnostic::error(DeviceInstallError::MemoryConflict {
+                    address: range.address.max(other_range.address),
+                    device:  *device,
+                    other:   *other,
+                }));
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Add a device's ports to the IO map. Fails if any of the ports are already claimed by another device.
+    fn map_io_ports(&mut self, port_list: Vec<u16>, device: IoDeviceType) -> Result<(), DeviceInstallError> {
+        for port in &port_list {
+            if let Some(other_device) = self.io_map.get(port) {
+                return Err(DeviceInstallError::IoConflict {
+                    device,
+                    port: *port,
+                    other: *other_device,
+                });
+            }
+        }
+        self.io_map.extend(port_list.into_iter().map(|p| (p, device)));
+        Ok(())
+    }
+
+    /// Check that an IRQ can be serviced by the installed interrupt controller.
+    fn validate_irq(device: IoDeviceType, irq: u8) -> Result<u8, DeviceInstallError> {
+        if irq > 7 {
+            return Err(DeviceInstallError::IrqUnavailable { device, irq });
+        }
+        Ok(irq)
+    }
+
+    /// Check that a DMA channel can be serviced by the installed DMA controller.
+    fn validate_dma(device: IoDeviceType, dma: usize) -> Result<usize, DeviceInstallError> {
+        if dma > 3 {
+            return Err(DeviceInstallError::DmaUnavailable { device, dma });
+        }
+        Ok(dma)
+    }
+
+    /// Create a video card and register its IO ports and memory ranges. Fails if the card's
+    /// resources conflict with a video card that is already installed.
+    fn install_videocard(
+        &mut self,
+        video_id: VideoCardId,
+        card: &VideoCardConfig,
+        clock_mode: ClockingMode,
+        video_frame_debug: bool,
+    ) -> Result<(), DeviceInstallError> {
+        let (video_dispatch, port_list, mem_descriptors) =
+            Self::create_videocard(video_id, card, clock_mode, video_frame_debug)?;
+
+        // Check for conflicts with any installed video card.
+        for port in &port_list {
+            if let Some(other @ IoDeviceType::Video(_)) = self.io_map.get(port) {
+                return Err(DeviceInstallError::IoConflict {
+                    device: IoDeviceType::Video(video_id),
+                    port:   *port,
+                    other:  *other,
+                });
+            }
+        }
+        for range in &mem_descriptors {
+            for (other_range, other) in &self.mmio_map {
+                if let MmioDeviceType::Video(other_id) = other {
+                    if range.address < other_range.address + other_range.size
+                        && other_range.address < range.address + range.size
+                    {
+                        return Err(DeviceInstallError::MemoryConflict {
+                            address: range.address.max(other_range.address),
+                            device:  IoDeviceType::Video(video_id),
+                            other:   IoDeviceType::Video(*other_id),
+                        });
+                    }
                 }
             }
+        }
 
-            self.videocards.insert(video_id, video_dispatch);
-            self.videocard_ids.push(video_id);
+        self.io_map
+            .extend(port_list.into_iter().map(|p| (p, IoDeviceType::Video(video_id))));
+        for mem_descriptor in mem_descriptors {
+            self.register_map(MmioDeviceType::Video(video_id), mem_descriptor);
         }
 
-        self.machine_desc = Some(machine_desc.clone());
+        self.videocards.insert(video_id, video_dispatch);
+        self.videocard_ids.push(video_id);
         Ok(())
     }
 
+    /// Create a video card, returning it along with the IO ports and memory ranges it occupies.
+    fn create_videocard(
+        video_id: VideoCardId,
+        card: &VideoCardConfig,
+        clock_mode: ClockingMode,
+        video_frame_debug: bool,
+    ) -> Result<(VideoCardDispatch, Vec<u16>, Vec<MemRangeDescriptor>), DeviceInstallError> {
+        let port_list: Vec<u16>;
+        let mem_descriptors: Vec<MemRangeDescriptor>;
+
+        log::debug!("Creating video card of type: {:?}", card.video_type);
+        let video_dispatch = match card.video_type {
+            VideoType::MDA => {
+                let mda = MDACard::new(
+                    TraceLogger::None,
+                    clock_mode,
+                    Some(card.lpt_mode.unwrap_or_default()),
+                    video_frame_debug,
+                );
+                port_list = mda.port_list();
+                mem_descriptors = vec![MemRangeDescriptor::new(
+                    mda::MDA_MEM_ADDRESS,
+                    mda::MDA_MEM_APERTURE,
+                    false,
+                )];
+                VideoCardDispatch::Mda(mda)
+            }
+            VideoType::CGA => {
+                let cga = CGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
+                port_list = cga.port_list();
+                mem_descriptors = vec![MemRangeDescriptor::new(
+                    cga::CGA_MEM_ADDRESS,
+                    cga::CGA_MEM_APERTURE,
+                    false,
+                )];
+                VideoCardDispatch::Cga(cga)
+            }
+            VideoType::CompaqDual => {
+                let cpq = CompaqVideoCard::new(
+                    TraceLogger::None,
+                    clock_mode,
+                    card.display.unwrap_or_default(),
+                    card.lpt_mode.unwrap_or_default(),
+                    video_frame_debug,
+                );
+                port_list = cpq.port_list();
+                mem_descriptors = vec![
+                    MemRangeDescriptor::new(mda::MDA_MEM_ADDRESS, mda::MDA_MEM_APERTURE, false),
+                    MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false),
+                ];
+                VideoCardDispatch::Compaq(cpq)
+            }
+            #[cfg(feature = "ega")]
+            VideoType::EGA => {
+                let ega = EGACard::new(
+                    TraceLogger::None,
+                    clock_mode,
+                    card.monitor.unwrap_or_default(),
+                    card.memory.unwrap_or_default(),
+                    video_frame_debug,
+                );
+                port_list = ega.port_list();
+                mem_descriptors = vec![
+                    MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false),
+                    MemRangeDescriptor::new(ega::EGA_MEM_ADDRESS, ega::EGA_GFX_PLANE_SIZE, false),
+                ];
+                VideoCardDispatch::Ega(ega)
+            }
+            #[cfg(feature = "vga")]
+            VideoType::VGA => {
+                let vga = VGACard::new(TraceLogger::None);
+                port_list = vga.port_list();
+                mem_descriptors = vec![
+                    MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false),
+                    MemRangeDescriptor::new(vga::VGA_GFX_ADDRESS, vga::VGA_GFX_PLANE_SIZE, false),
+                ];
+                VideoCardDispatch::Vga(vga)
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(DeviceInstallError::VideoUnsupported {
+                    index: video_id.idx,
+                    video_type: card.video_type,
+                });
+            }
+        };
+
+        let cycle_cost = card.wait_states.unwrap_or(0);
+        let mem_descriptors = mem_descriptors
+            .into_iter()
+            .map(|desc| desc.with_cycle_cost(cycle_cost))
+            .collect();
+
+        Ok((video_dispatch, port_list, mem_descriptors))
+    }
+
+    /// Add a video card to a running machine, with the clocking mode and debug settings the
+    /// installed cards were created with. The PPI video DIP switches are updated to reflect
+    /// the new set of cards, but the BIOS will not notice the change until the next reboot.
+    pub fn add_videocard(&mut self, card: &VideoCardConfig) -> Result<VideoCardId, Error> {
+        let idx = self.videocard_ids.iter().map(|vid| vid.idx + 1).max().unwrap_or(0);
+        let video_id = VideoCardId {
+            idx,
+            vtype: card.video_type,
+        };
+        self.install_videocard(video_id, card, self.video_clock_mode, self.video_frame_debug)?;
+        self.update_ppi_video_types();
+        log::debug!("Added video card: {:?}", video_id);
+        Ok(video_id)
+    }
+
+    /// Remove a video card from a running machine, unregistering its IO ports and memory ranges.
+    pub fn remove_videocard(&mut self, vid: VideoCardId) -> Result<(), Error> {
+        if self.videocards.remove(&vid).is_none() {
+            return Err(anyhow::anyhow!("No such video card: {:?}", vid));
+        }
+        self.videocard_ids.retain(|id| *id != vid);
+        self.io_map
+            .retain(|_, device| !matches!(device, IoDeviceType::Video(id) if *id == vid));
+        self.unregister_map(|device| matches!(device, MmioDeviceType::Video(id) if *id == vid));
+        self.update_ppi_video_types();
+        log::debug!("Removed video card: {:?}", vid);
+        Ok(())
+    }
+
+    fn update_ppi_video_types(&mut self) {
+        let video_types = self
+            .videocard_ids
+            .iter()
+            .map(|vid| vid.vtype)
+            .collect::<Vec<VideoType>>();
+        if let Some(ppi) = &mut self.ppi {
+            ppi.set_video_types(self.video_have_expansion, &video_types);
+        }
+    }
+
     /// Return whether NMI is enabled.
-    /// On the 5150 & 5160, NMI generation can be disabled via the PPI.
+    /// On the 5150 & 5160, NMI is masked by the NMI mask register, and NMI generation can be
+    /// disabled via the PPI.
     pub fn nmi_enabled(&self) -> bool {
         if self.machine_desc.unwrap().have_ppi {
             if let Some(ppi) = &self.ppi {
-                ppi.nmi_enabled()
+                self.nmi_mask && ppi.nmi_enabled()
             }
             else {
                 true
@@ -1672,6 +3170,27 @@ impl BusInterface {
         self.pit_ticks_advance += ticks;
     }
 
+    /// Return the time in microseconds until the next device deadline, or None if all devices are
+    /// idle. This is the earlier of the next change of the timer interrupt output and the next
+    /// scheduled device becoming due to run.
+    pub fn next_device_deadline(&self) -> Option<f64> {
+        let mut deadline = self.pit.as_ref().and_then(|pit| pit.next_deadline());
+        if let Some(fdc) = &self.fdc {
+            deadline = earliest_deadline(deadline, self.fdc_schedule.remaining(fdc.next_deadline()));
+        }
+        if let Some(hdc) = &self.hdc {
+            deadline = earliest_deadline(deadline, self.hdc_schedule.remaining(hdc.next_deadline()));
+        }
+        if let Some(serial) = &self.serial {
+            let mut serial_deadline = serial.next_deadline();
+            if let Some(mouse) = &self.mouse {
+                serial_deadline = earliest_deadline(serial_deadline, mouse.next_deadline());
+            }
+            deadline = earliest_deadline(deadline, self.serial_schedule.remaining(serial_deadline));
+        }
+        deadline
+    }
+
     pub fn run_devices(
         &mut self,
         us: f64,
@@ -1681,6 +3200,7 @@ impl BusInterface {
         speaker_buf_producer: &mut Producer<u8>,
     ) -> Option<DeviceEvent> {
         let mut event = None;
+        self.elapsed_us += us;
 
         if let Some(keyboard) = &mut self.keyboard {
             // Send keyboard events to devices.
@@ -1736,6 +3256,20 @@ impl BusInterface {
         // There will always be a PIC, so safe to unwrap.
         let pic = self.pic1.as_mut().unwrap();
 
+        // Run the secondary PIC if present, and forward its INTR line to the cascade input of the primary.
+        if let Some(pic2) = &mut self.pic2 {
+            pic2.run(sys_ticks);
+            let cascade_bit = 0x01 << PIC_CASCADE_IRQ;
+            if pic2.query_interrupt_line() {
+                if pic.ir() & cascade_bit == 0 {
+                    pic.request_interrupt(PIC_CASCADE_IRQ);
+                }
+            }
+            else if pic.ir() & cascade_bit != 0 {
+                pic.clear_interrupt(PIC_CASCADE_IRQ);
+            }
+        }
+
         pic.run(sys_ticks);
 
         // There will always be a PIT, so safe to unwrap.
@@ -1747,6 +3281,7 @@ impl BusInterface {
         }
 
         // Run the PIT. The PIT communicates with lots of things, so we send it the entire bus.
+        // It is run every slice regardless of its deadline, as it produces a speaker sample per tick.
         // The PIT may have a separate clock crystal, such as in the IBM AT. In this case, there may not
         // be an integer number of PIT ticks per system ticks. Therefore the PIT can take either
         // system ticks (PC/XT) or microseconds as an update parameter.
@@ -1851,15 +3386,19 @@ impl BusInterface {
 
         let mut dma1 = self.dma1.take().unwrap();
 
-        // Run the FDC, passing it DMA controller while DMA is still unattached.
+        // Run the FDC if it is due, passing it DMA controller while DMA is still unattached.
         if let Some(mut fdc) = self.fdc.take() {
-            fdc.run(&mut dma1, self, us);
+            if let Some(fdc_us) = self.fdc_schedule.tick(us, fdc.next_deadline()) {
+                fdc.run(&mut dma1, self, fdc_us);
+            }
             self.fdc = Some(fdc);
         }
 
-        // Run the HDC, passing it DMA controller while DMA is still unattached.
+        // Run the HDC if it is due, passing it DMA controller while DMA is still unattached.
         if let Some(mut hdc) = self.hdc.take() {
-            hdc.run(&mut dma1, self, us);
+            if let Some(hdc_us) = self.hdc_schedule.tick(us, hdc.next_deadline()) {
+                hdc.run(&mut dma1, self, hdc_us);
+            }
             self.hdc = Some(hdc);
         }
 
@@ -1869,15 +3408,34 @@ impl BusInterface {
         // Replace the DMA controller.
         self.dma1 = Some(dma1);
 
-        // Run the serial port and mouse.
+        // Run the serial port and mouse if either is due. The mouse depends on the serial port's
+        // control lines, so they are scheduled together.
         if let Some(serial) = &mut self.serial {
-            serial.run(&mut self.pic1.as_mut().unwrap(), us);
+            let mut deadline = serial.next_deadline();
+            if let Some(mouse) = &self.mouse {
+                deadline = earliest_deadline(deadline, mouse.next_deadline());
+            }
+
+            if let Some(serial_us) = self.serial_schedule.tick(us, deadline) {
+                serial.run(self.pic1.as_mut().unwrap(), serial_us);
+
+                if let Some(mouse) = &mut self.mouse {
+                    mouse.run(serial, serial_us);
+                }
+            }
+        }
 
-            if let Some(mouse) = &mut self.mouse {
-                mouse.run(serial, us);
+        // Run the game port while any of its one-shots are timing.
+        if let Some(game_port) = &mut self.game_port {
+            if game_port.active() {
+                game_port.run(us);
             }
         }
 
+        if let Some(rtc) = &mut self.rtc {
+            rtc.run(us);
+        }
+
         // Run all video cards
         for (_vid, video_dispatch) in self.videocards.iter_mut() {
             match video_dispatch {
@@ -1946,6 +3504,9 @@ impl BusInterface {
                         }
                     }
                 }
+                VideoCardDispatch::Compaq(cpq) => {
+                    cpq.run(us, sys_ticks, &mut self.pic1);
+                }
                 #[cfg(feature = "ega")]
                 VideoCardDispatch::Ega(ega) => {
                     ega.run(DeviceRunTimeUnit::Microseconds(us), &mut self.pic1);
@@ -1963,6 +3524,9 @@ impl BusInterface {
 
     /// Call the reset methods for all devices on the bus
     pub fn reset_devices(&mut self) {
+        // NMI is masked until software enables it.
+        self.nmi_mask = false;
+
         // Reset PIT
         if let Some(pit) = self.pit.as_mut() {
             pit.reset();
@@ -1972,15 +3536,26 @@ impl BusInterface {
         if let Some(pic1) = self.pic1.as_mut() {
             pic1.reset();
         }
+        if let Some(pic2) = self.pic2.as_mut() {
+            pic2.reset();
+        }
 
         // Reset DMA
         if let Some(dma1) = self.dma1.as_mut() {
             dma1.reset();
         }
+        if let Some(dma2) = self.dma2.as_mut() {
+            dma2.reset();
+        }
 
         // Reset video cards
         let vids: Vec<_> = self.videocards.keys().cloned().collect();
         for vid in vids {
+            if let Some(VideoCardDispatch::Compaq(cpq)) = self.videocards.get_mut(&vid) {
+                // Reset both personalities, not just the one being displayed.
+                cpq.reset();
+                continue;
+            }
             self.video_mut(&vid).map(|video| video.reset());
         }
     }
@@ -2011,10 +3586,7 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(d) => d as u32 * cycles,
-            ClockFactor::Multiplier(m) => cycles / m as u32,
-        };
+        let sys_ticks = self.cpu_cycles_to_system_ticks(cycles);
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
         if let Some(device_id) = self.io_map.get(&port) {
@@ -2063,6 +3635,7 @@ impl BusInterface {
                 }
                 IoDeviceType::FloppyController => {
                     if let Some(fdc) = &mut self.fdc {
+                        self.fdc_schedule.wake();
                         fdc.read_u8(port, nul_delta)
                     }
                     else {
@@ -2071,6 +3644,7 @@ impl BusInterface {
                 }
                 IoDeviceType::HardDiskController => {
                     if let Some(hdc) = &mut self.hdc {
+                        self.hdc_schedule.wake();
                         hdc.read_u8(port, nul_delta)
                     }
                     else {
@@ -2080,12 +3654,29 @@ impl BusInterface {
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
+                        self.serial_schedule.wake();
                         serial.read_u8(port, nul_delta)
                     }
                     else {
                         NO_IO_BYTE
                     }
                 }
+                IoDeviceType::GamePort => {
+                    if let Some(game_port) = &mut self.game_port {
+                        game_port.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::Rtc => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
 
                 IoDeviceType::Video(vid) => {
                     if let Some(video_dispatch) = self.videocards.get_mut(&vid) {
@@ -2096,6 +3687,9 @@ impl BusInterface {
                             VideoCardDispatch::Cga(cga) => {
                                 IoDevice::read_u8(cga, port, DeviceRunTimeUnit::SystemTicks(sys_ticks))
                             }
+                            VideoCardDispatch::Compaq(cpq) => {
+                                IoDevice::read_u8(cpq, port, DeviceRunTimeUnit::SystemTicks(sys_ticks))
+                            }
                             #[cfg(feature = "ega")]
                             VideoCardDispatch::Ega(ega) => IoDevice::read_u8(ega, port, nul_delta),
                             #[cfg(feature = "vga")]
@@ -2112,7 +3706,13 @@ impl BusInterface {
         }
         else {
             // Unhandled IO address read
-            NO_IO_BYTE
+            log_event!(
+                LogCategory::Io,
+                LogLevel::Trace,
+                "Unhandled IO read from port {:04X}",
+                port
+            );
+            self.open_bus_byte()
         }
     }
 
@@ -2121,6 +3721,9 @@ impl BusInterface {
     /// We provide the elapsed cycle count for the current instruction. This allows a device
     /// to optionally tick itself to bring itself in sync with CPU state.
     pub fn io_write_u8(&mut self, port: u16, data: u8, cycles: u32) {
+        if let Some(journal) = &mut self.write_journal {
+            journal.io.push((port, data));
+        }
         /*
         let handler_opt = self.handlers.get_mut(&port);
         if let Some(handler) = handler_opt {
@@ -2132,20 +3735,33 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32),
-        };
+        let sys_ticks = self.cpu_cycles_to_system_ticks(cycles);
 
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
         if let Some(device_id) = self.io_map.get(&port) {
             match device_id {
                 IoDeviceType::Ppi => {
+                    let timer_gate_port = port == PPI_PORT_B;
+                    if timer_gate_port {
+                        // Port B controls the PIT channel 2 gate. Catch the PIT up to the CPU
+                        // first, so it sees the old gate state up until this write.
+                        if let Some(mut pit) = self.pit.take() {
+                            pit.catch_up(self, DeviceRunTimeUnit::SystemTicks(sys_ticks));
+                            self.pit = Some(pit);
+                        }
+                    }
                     if let Some(mut ppi) = self.ppi.take() {
                         ppi.write_u8(port, data, Some(self), nul_delta);
                         self.ppi = Some(ppi);
                     }
+                    if timer_gate_port {
+                        let gate = self.ppi.as_mut().map(|ppi| ppi.get_pit_channel2_gate());
+                        if let (Some(gate), Some(mut pit)) = (gate, self.pit.take()) {
+                            pit.set_channel_gate(2, gate, self);
+                            self.pit = Some(pit);
+                        }
+                    }
                 }
                 IoDeviceType::Pit => {
                     if let Some(mut pit) = self.pit.take() {
@@ -2180,12 +3796,14 @@ impl BusInterface {
                 }
                 IoDeviceType::FloppyController => {
                     if let Some(mut fdc) = self.fdc.take() {
+                        self.fdc_schedule.wake();
                         fdc.write_u8(port, data, Some(self), nul_delta);
                         self.fdc = Some(fdc);
                     }
                 }
                 IoDeviceType::HardDiskController => {
                     if let Some(mut hdc) = self.hdc.take() {
+                        self.hdc_schedule.wake();
                         hdc.write_u8(port, data, Some(self), nul_delta);
                         self.hdc = Some(hdc);
                     }
@@ -2193,10 +3811,28 @@ impl BusInterface {
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
+                        self.serial_schedule.wake();
                         serial.write_u8(port, data, None, nul_delta);
                     }
                 }
+                IoDeviceType::GamePort => {
+                    if let Some(game_port) = &mut self.game_port {
+                        game_port.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::Rtc => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::NmiMask => {
+                    self.nmi_mask = data & NMI_MASK_ENABLE != 0;
+                }
                 IoDeviceType::Video(vid) => {
+                    let vid = *vid;
+                    if self.video_trace.is_some() {
+                        self.trace_video_write(vid, port, data);
+                    }
                     if let Some(video_dispatch) = self.videocards.get_mut(&vid) {
                         match video_dispatch {
                             VideoCardDispatch::Mda(mda) => {
@@ -2205,6 +3841,9 @@ impl BusInterface {
                             VideoCardDispatch::Cga(cga) => {
                                 IoDevice::write_u8(cga, port, data, None, DeviceRunTimeUnit::SystemTicks(sys_ticks))
                             }
+                            VideoCardDispatch::Compaq(cpq) => {
+                                IoDevice::write_u8(cpq, port, data, None, DeviceRunTimeUnit::SystemTicks(sys_ticks))
+                            }
                             #[cfg(feature = "ega")]
                             VideoCardDispatch::Ega(ega) => IoDevice::write_u8(ega, port, data, None, nul_delta),
                             #[cfg(feature = "vga")]
@@ -2216,6 +3855,73 @@ impl BusInterface {
                 _ => {}
             }
         }
+        else {
+            log_event!(
+                LogCategory::Io,
+                LogLevel::Trace,
+                "Unhandled IO write to port {:04X}: {:02X}",
+                port,
+                data
+            );
+        }
+    }
+
+    /// Read a 16-bit value from an IO port. ISA devices are 8 bits wide, so the word is read as
+    /// two bytes from `port` and `port + 1`, as the bus conversion logic on a 16-bit bus does.
+    pub fn io_read_u16(&mut self, port: u16, cycles: u32) -> u16 {
+        let lo = self.io_read_u8(port, cycles);
+        let hi = self.io_read_u8(port.wrapping_add(1), 0);
+        (hi as u16) << 8 | lo as u16
+    }
+
+    /// Write a 16-bit value to an IO port, as two bytes to `port` and `port + 1`.
+    pub fn io_write_u16(&mut self, port: u16, data: u16, cycles: u32) {
+        self.io_write_u8(port, (data & 0xFF) as u8, cycles);
+        self.io_write_u8(port.wrapping_add(1), (data >> 8) as u8, 0);
+    }
+
+    /// Start tracing video register writes to the specified logger, replacing any existing trace.
+    pub fn start_video_trace(&mut self, logger: TraceLogger, filter: VideoTraceFilter) {
+        self.video_trace = Some(VideoRegisterTrace::new(logger, filter));
+    }
+
+    /// Stop tracing video register writes, flushing the trace log.
+    pub fn stop_video_trace(&mut self) {
+        if let Some(mut trace) = self.video_trace.take() {
+            trace.flush();
+        }
+    }
+
+    pub fn video_trace_mut(&mut self) -> Option<&mut VideoRegisterTrace> {
+        self.video_trace.as_mut()
+    }
+
+    /// Returns true if video register writes are being traced.
+    #[inline]
+    pub fn video_trace_enabled(&self) -> bool {
+        self.video_trace.is_some()
+    }
+
+    /// Set the address of the instruction performing the next IO write, for video register tracing.
+    #[inline]
+    pub fn set_video_trace_source(&mut self, cs: u16, ip: u16) {
+        if let Some(trace) = &mut self.video_trace {
+            trace.set_source(cs, ip);
+        }
+    }
+
+    /// Log a write to a video card register, before the write is performed.
+    fn trace_video_write(&mut self, vid: VideoCardId, port: u16, data: u8) {
+        let (reg, beam, scanline) = match self.video(&vid) {
+            Some(card) => match card.get_io_register(port) {
+                Some(reg) => (reg, card.get_beam_pos(), card.get_scanline()),
+                None => return,
+            },
+            None => return,
+        };
+        if let Some(trace) = &mut self.video_trace {
+            trace.log_write(vid, reg, data, beam, scanline);
+        }
     }
 
     // Device accessors
@@ -2231,6 +3937,41 @@ impl BusInterface {
         &mut self.pic1
     }
 
+    /// Enable or disable the interrupt trace on all PICs.
+    pub fn set_irq_trace(&mut self, enabled: bool) {
+        for pic in [&mut self.pic1, &mut self.pic2].into_iter().flatten() {
+            pic.set_irq_trace(enabled);
+        }
+    }
+
+    /// Remove and return the interrupt trace entries recorded since the last call. Interrupts
+    /// delivered by the secondary PIC are reported as IRQ 8-15.
+    pub fn take_irq_trace(&mut self) -> Vec<IrqTraceEntry> {
+        let mut entries = match &mut self.pic1 {
+            Some(pic1) => pic1.take_irq_trace(),
+            None => Vec::new(),
+        };
+        if let Some(pic2) = &mut self.pic2 {
+            entries.extend(pic2.take_irq_trace().into_iter().map(|mut entry| {
+                entry.irq += 8;
+                entry
+            }));
+            entries.sort_by_key(|entry| entry.inta_ticks);
+        }
+        entries
+    }
+
+    /// Run the interrupt acknowledge cycle. The primary PIC provides the vector unless the
+    /// acknowledged interrupt came from the cascaded secondary PIC, which then provides it instead.
+    pub fn get_interrupt_vector(&mut self) -> Option<u8> {
+        let pic1 = self.pic1.as_mut()?;
+        let vector = pic1.get_interrupt_vector()?;
+        match &mut self.pic2 {
+            Some(pic2) if pic1.cascade_acknowledged() => pic2.get_interrupt_vector().or(Some(vector)),
+            _ => Some(vector),
+        }
+    }
+
     pub fn ppi_mut(&mut self) -> &mut Option<Ppi> {
         &mut self.ppi
     }
@@ -2239,6 +3980,69 @@ impl BusInterface {
         &mut self.dma1
     }
 
+    /// Return the state of the DMA controller's HRQ (Hold Request) line.
+    #[inline]
+    pub fn dma_hold_request(&self) -> bool {
+        self.dma1.as_ref().is_some_and(|dma| dma.hold_request())
+    }
+
+    fn create_serial_mouse(&mut self, serial_mouse_config: &SerialMouseConfig) {
+        match serial_mouse_config.mouse_type {
+            SerialMouseType::Microsoft => {
+                let mut mouse = Mouse::new(serial_mouse_config.port as usize);
+                if let Some(scale) = serial_mouse_config.scale {
+                    mouse.set_scale(scale);
+                }
+                mouse.set_invert(serial_mouse_config.invert_x, serial_mouse_config.invert_y);
+                if let Some(interval) = serial_mouse_config.report_interval {
+                    mouse.set_report_interval(interval);
+                }
+                self.mouse = Some(mouse);
+            }
+        }
+    }
+
+    /// Plug a serial mouse into a running machine. The modem status lines of the serial port
+    /// are raised so that a guest driver can detect the new device.
+    pub fn attach_serial_mouse(&mut self, serial_mouse_config: &SerialMouseConfig) -> Result<(), Error> {
+        let port = serial_mouse_config.port as usize;
+        let serial = match &mut self.serial {
+            Some(serial) => serial,
+            None => return Err(anyhow::anyhow!("No serial port controller present")),
+        };
+        if port >= SERIAL_PORT_COUNT {
+            return Err(anyhow::anyhow!("Invalid serial port: {}", port));
+        }
+        if self.mouse.is_some() {
+            return Err(anyhow::anyhow!("A serial mouse is already attached"));
+        }
+        if serial.is_bridged(port) {
+            return Err(anyhow::anyhow!("Serial port {} is bridged to a host port", port));
+        }
+
+        serial.set_device_connected(port, true);
+        self.create_serial_mouse(serial_mouse_config);
+        if let (Some(mouse), Some(serial)) = (&mut self.mouse, &mut self.serial) {
+            mouse.attach(serial);
+        }
+        log::debug!("Attached serial mouse to port {}", port);
+        Ok(())
+    }
+
+    /// Unplug the serial mouse, if present. Returns true if a mouse was removed.
+    pub fn detach_serial_mouse(&mut self) -> bool {
+        if let Some(mouse) = self.mouse.take() {
+            if let Some(serial) = &mut self.serial {
+                serial.set_device_connected(mouse.port(), false);
+            }
+            log::debug!("Detached serial mouse from port {}", mouse.port());
+            true
+        }
+        else {
+            false
+        }
+    }
+
     pub fn serial_mut(&mut self) -> &mut Option<SerialPortController> {
         &mut self.serial
     }
@@ -2251,10 +4055,147 @@ impl BusInterface {
         &mut self.hdc
     }
 
+    /// Return the emulated time in microseconds that devices have been run for. Used to timestamp
+    /// device events; only as precise as a device slice.
+    pub fn elapsed_us(&self) -> f64 {
+        self.elapsed_us
+    }
+
     pub fn mouse_mut(&mut self) -> &mut Option<Mouse> {
         &mut self.mouse
     }
 
+    pub fn game_port_mut(&mut self) -> &mut Option<GamePort> {
+        &mut self.game_port
+    }
+
+    pub fn rtc_mut(&mut self) -> &mut Option<Rtc> {
+        &mut self.rtc
+    }
+
+    /// Return the state of the specified device as structured JSON, or None if the device is not
+    /// installed or does not support state capture.
+    pub fn device_state(&self, device: IoDeviceType) -> Option<serde_json::Value> {
+        match device {
+            IoDeviceType::Ppi => self.ppi.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Pit => self.pit.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::DmaPrimary => self.dma1.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::DmaSecondary => self.dma2.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::PicPrimary => self.pic1.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::PicSecondary => self.pic2.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Serial => self.serial.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::FloppyController => self.fdc.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::HardDiskController => self.hdc.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Mouse => self.mouse.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Rtc => self.rtc.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Keyboard => self.keyboard.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::GamePort => self.game_port.as_ref().map(|d| d.dump_state()),
+            IoDeviceType::Video(vid) => match self.videocards.get(&vid)? {
+                VideoCardDispatch::Mda(mda) => Some(mda.dump_state()),
+                VideoCardDispatch::Cga(cga) => Some(cga.dump_state()),
+                VideoCardDispatch::Compaq(cpq) => Some(cpq.dump_state()),
+                #[cfg(feature = "ega")]
+                VideoCardDispatch::Ega(ega) => Some(ega.dump_state()),
+                #[cfg(feature = "vga")]
+                VideoCardDispatch::Vga(vga) => Some(vga.dump_state()),
+                VideoCardDispatch::None => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Return the state of every installed device that supports state capture, keyed by device
+    /// type.
+    pub fn device_states(&self) -> BTreeMap<String, serde_json::Value> {
+        self.capture_device_states()
+            .into_iter()
+            .map(|(device, state)| (format!("{:?}", device), state))
+            .collect()
+    }
+
+    /// Return the state of every installed device that supports state capture, in the order they
+    /// should be restored.
+    pub fn capture_device_states(&self) -> Vec<(IoDeviceType, serde_json::Value)> {
+        [
+            IoDeviceType::Ppi,
+            IoDeviceType::Pit,
+            IoDeviceType::DmaPrimary,
+            IoDeviceType::DmaSecondary,
+            IoDeviceType::PicPrimary,
+            IoDeviceType::PicSecondary,
+            IoDeviceType::Serial,
+            IoDeviceType::FloppyController,
+            IoDeviceType::HardDiskController,
+            IoDeviceType::Mouse,
+            IoDeviceType::Rtc,
+            IoDeviceType::Keyboard,
+            IoDeviceType::GamePort,
+        ]
+        .into_iter()
+        .chain(self.videocard_ids.iter().map(|vid| IoDeviceType::Video(*vid)))
+        .filter_map(|device| self.device_state(device).map(|state| (device, state)))
+        .collect()
+    }
+
+    /// Restore the state of the specified device from structured JSON produced by device_state().
+    pub fn load_device_state(&mut self, device: IoDeviceType, state: serde_json::Value) -> Result<(), Error> {
+        fn load<D: DeviceState>(
+            device: &mut Option<D>,
+            device_type: IoDeviceType,
+            state: serde_json::Value,
+        ) -> Result<(), Error> {
+            match device {
+                Some(device) => device.load_state(serde_json::from_value(state)?),
+                None => Err(anyhow::anyhow!("Device not installed: {:?}", device_type)),
+            }
+        }
+
+        match device {
+            IoDeviceType::Ppi => load(&mut self.ppi, device, state),
+            IoDeviceType::Pit => load(&mut self.pit, device, state),
+            IoDeviceType::DmaPrimary => load(&mut self.dma1, device, state),
+            IoDeviceType::DmaSecondary => load(&mut self.dma2, device, state),
+            IoDeviceType::PicPrimary => load(&mut self.pic1, device, state),
+            IoDeviceType::PicSecondary => load(&mut self.pic2, device, state),
+            IoDeviceType::Serial => load(&mut self.serial, device, state),
+            IoDeviceType::FloppyController => load(&mut self.fdc, device, state),
+            IoDeviceType::HardDiskController => load(&mut self.hdc, device, state),
+            IoDeviceType::Mouse => load(&mut self.mouse, device, state),
+            IoDeviceType::Rtc => load(&mut self.rtc, device, state),
+            IoDeviceType::Keyboard => load(&mut self.keyboard, device, state),
+            IoDeviceType::GamePort => load(&mut self.game_port, device, state),
+            IoDeviceType::Video(vid) => match self.videocards.get_mut(&vid) {
+                Some(VideoCardDispatch::Mda(mda)) => mda.load_state(serde_json::from_value(state)?),
+                Some(VideoCardDispatch::Cga(cga)) => cga.load_state(serde_json::from_value(state)?),
+                Some(VideoCardDispatch::Compaq(cpq)) => cpq.load_state(serde_json::from_value(state)?),
+                #[cfg(feature = "ega")]
+                Some(VideoCardDispatch::Ega(ega)) => ega.load_state(serde_json::from_value(state)?),
+                #[cfg(feature = "vga")]
+                Some(VideoCardDispatch::Vga(vga)) => vga.load_state(serde_json::from_value(state)?),
+                _ => Err(anyhow::anyhow!("Device not installed: {:?}", device)),
+            },
+            _ => Err(anyhow::anyhow!("Device does not support state capture: {:?}", device)),
+        }
+    }
+
+    /// Return every installed device with non-volatile memory.
+    pub fn nvram_devices(&self) -> Vec<&dyn NonVolatile> {
+        let mut devices: Vec<&dyn NonVolatile> = Vec::new();
+        if let Some(rtc) = &self.rtc {
+            devices.push(rtc);
+        }
+        devices
+    }
+
+    /// Return every installed device with non-volatile memory, mutably.
+    pub fn nvram_devices_mut(&mut self) -> Vec<&mut dyn NonVolatile> {
+        let mut devices: Vec<&mut dyn NonVolatile> = Vec::new();
+        if let Some(rtc) = &mut self.rtc {
+            devices.push(rtc);
+        }
+        devices
+    }
+
     pub fn primary_video(&self) -> Option<Box<&dyn VideoCard>> {
         if self.videocard_ids.len() > 0 {
             self.video(&self.videocard_ids[0])
@@ -2279,6 +4220,7 @@ impl BusInterface {
             match video_dispatch {
                 VideoCardDispatch::Mda(mda) => Some(Box::new(mda as &dyn VideoCard)),
                 VideoCardDispatch::Cga(cga) => Some(Box::new(cga as &dyn VideoCard)),
+                VideoCardDispatch::Compaq(cpq) => Some(Box::new(cpq.active())),
                 #[cfg(feature = "ega")]
                 VideoCardDispatch::Ega(ega) => Some(Box::new(ega as &dyn VideoCard)),
                 #[cfg(feature = "vga")]
@@ -2296,6 +4238,7 @@ impl BusInterface {
             match video_dispatch {
                 VideoCardDispatch::Mda(mda) => Some(Box::new(mda as &mut dyn VideoCard)),
                 VideoCardDispatch::Cga(cga) => Some(Box::new(cga as &mut dyn VideoCard)),
+                VideoCardDispatch::Compaq(cpq) => Some(Box::new(cpq.active_mut())),
                 #[cfg(feature = "ega")]
                 VideoCardDispatch::Ega(ega) => Some(Box::new(ega as &mut dyn VideoCard)),
                 #[cfg(feature = "vga")]
@@ -2324,6 +4267,10 @@ impl BusInterface {
                     card: Box::new(cga as &mut dyn VideoCard),
                     id:   *vid,
                 }),
+                VideoCardDispatch::Compaq(cpq) => f(VideoCardInterface {
+                    card: Box::new(cpq.active_mut()),
+                    id:   *vid,
+                }),
                 #[cfg(feature = "ega")]
                 VideoCardDispatch::Ega(ega) => f(VideoCardInterface {
                     card: Box::new(ega as &mut dyn VideoCard),
@@ -2365,3 +4312,317 @@ impl BusInterface {
         self.keyboard.as_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        machine_config::{
+            get_machine_descriptor,
+            ConventionalMemoryConfig,
+            FloppyControllerConfig,
+            FloppyDriveConfig,
+            HardDriveControllerConfig,
+            MemoryConfig,
+        },
+        machine_types::{FdcType, FloppyDriveType, MachineType},
+    };
+
+    fn test_config() -> MachineConfiguration {
+        MachineConfiguration {
+            speaker: false,
+            speaker_profile: None,
+            ppi_turbo: None,
+            turbo_clock: None,
+            reset_vector: None,
+            dram_refresh: None,
+            wait_states: None,
+            open_bus: None,
+            descriptor: None,
+            dip_switches: None,
+            machine_type: MachineType::Ibm5160,
+            memory: MemoryConfig {
+                conventional: ConventionalMemoryConfig {
+                    size: 0x10000,
+                    wait_states: 0,
+                },
+                address_wrap: true,
+            },
+            keyboard: None,
+            serial_mouse: None,
+            game_port: None,
+            rtc: None,
+            video: vec![VideoCardConfig {
+                video_type: VideoType::CGA,
+                monitor: None,
+                memory: None,
+                display: None,
+                scaler_preset: None,
+                lpt_mode: None,
+                wait_states: None,
+            }],
+            serial: Vec::new(),
+            fdc: Some(FloppyControllerConfig {
+                fdc_type: FdcType::IbmNec,
+                drive:    vec![FloppyDriveConfig {
+                    fd_type: FloppyDriveType::Floppy360K,
+                    image:   None,
+                }],
+            }),
+            hdc: None,
+            media: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let machine_desc = *get_machine_descriptor(MachineType::Ibm5160).unwrap();
+        let mut config = test_config();
+
+        // Only the missing keyboard should be reported.
+        let diagnostics = BusInterface::validate_config(&machine_desc, &config);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(
+            diagnostics[0].problem,
+            DeviceInstallError::MissingDevice("keyboard")
+        ));
+        assert!(!diagnostics[0].is_error());
+
+        // An HDC moved onto the FDC's DMA channel and the PIT's ports, and a second CGA, should all
+        // be reported together.
+        config.hdc = Some(HardDriveControllerConfig {
+            hdc_type: HardDiskControllerType::IbmXebec,
+            io_base: Some(0x40),
+            irq: None,
+            dma: Some(FDC_DMA),
+            drive: None,
+        });
+        config.video.push(config.video[0].clone());
+        let diagnostics = BusInterface::validate_config(&machine_desc, &config);
+        let errors: Vec<_> = diagnostics.iter().filter(|d| d.is_error()).collect();
+        assert!(errors
+            .iter()
+            .any(|d| matches!(d.problem, DeviceInstallError::DmaConflict { dma: FDC_DMA, .. })));
+        assert!(errors.iter().any(|d| matches!(
+            d.problem,
+            DeviceInstallError::IoConflict {
+                device: IoDeviceType::HardDiskController,
+                other: IoDeviceType::Pit,
+                ..
+            }
+        )));
+        assert!(errors
+            .iter()
+            .any(|d| matches!(d.problem, DeviceInstallError::MemoryConflict { address: 0xB8000, .. })));
+    }
+
+    #[test]
+    fn test_mmio_wait_states() {
+        let mut bus = BusInterface::default();
+        bus.register_map(
+            MmioDeviceType::Memory,
+            MemRangeDescriptor::new(0xD0000, 0x4000, false).with_cycle_cost(3),
+        );
+        assert_eq!(bus.get_read_wait(0xD0000, 0).unwrap(), DEFAULT_WAIT_STATES + 3);
+        assert_eq!(bus.get_write_wait(0xD3FFF, 0).unwrap(), DEFAULT_WAIT_STATES + 3);
+        assert_eq!(bus.get_read_wait(0xD4000, 0).unwrap(), DEFAULT_WAIT_STATES);
+
+        // Memory range descriptors take precedence, and survive removal of the device.
+        bus.set_descriptor(0xD0000, 0x1000, 1, false);
+        assert_eq!(bus.get_read_wait(0xD0000, 0).unwrap(), DEFAULT_WAIT_STATES + 1);
+        bus.unregister_map(|device| matches!(device, MmioDeviceType::Memory));
+        assert_eq!(bus.get_read_wait(0xD0000, 0).unwrap(), DEFAULT_WAIT_STATES + 1);
+        assert_eq!(bus.get_read_wait(0xD2000, 0).unwrap(), DEFAULT_WAIT_STATES);
+    }
+
+    fn video_wait_bus(video_type: VideoType) -> BusInterface {
+        let machine_desc = *get_machine_descriptor(MachineType::Ibm5160).unwrap();
+        let mut config = test_config();
+        config.fdc = None;
+        config.video[0].video_type = video_type;
+        let mut bus = BusInterface::new(ClockFactor::Divisor(3), machine_desc, KeyboardType::ModelF);
+        bus.install_devices(&machine_desc, &config).unwrap();
+        bus
+    }
+
+    #[test]
+    fn test_video_mmio_u8_wait_states() {
+        let mut bus = video_wait_bus(VideoType::CGA);
+
+        // 8-bit VRAM accesses report the card's wait states in CPU cycles, matching the wait
+        // reported for the same clock phase by get_read_wait() and get_write_wait().
+        for cycles in 0..16 {
+            let read_wait = bus.get_read_wait(cga::CGA_MEM_ADDRESS, cycles).unwrap();
+            let write_wait = bus.get_write_wait(cga::CGA_MEM_ADDRESS, cycles).unwrap();
+            assert!(read_wait > 0);
+            assert_eq!(bus.write_u8(cga::CGA_MEM_ADDRESS, 0x5A, cycles).unwrap(), write_wait);
+            assert_eq!(bus.read_u8(cga::CGA_MEM_ADDRESS, cycles).unwrap(), (0x5A, read_wait));
+        }
+    }
+
+    #[cfg(feature = "ega")]
+    #[test]
+    fn test_ega_mmio_u8_wait_states() {
+        let mut bus = video_wait_bus(VideoType::EGA);
+
+        // The EGA has no wait states of its own, so 8-bit VRAM accesses no longer fall through to
+        // the default wait but still agree with get_read_wait() and get_write_wait().
+        for cycles in 0..16 {
+            let read_wait = bus.get_read_wait(ega::EGA_MEM_ADDRESS, cycles).unwrap();
+            let write_wait = bus.get_write_wait(ega::EGA_MEM_ADDRESS, cycles).unwrap();
+            assert_eq!(bus.write_u8(ega::EGA_MEM_ADDRESS, 0x5A, cycles).unwrap(), write_wait);
+            assert_eq!(bus.read_u8(ega::EGA_MEM_ADDRESS, cycles).unwrap().1, read_wait);
+        }
+    }
+
+    #[test]
+    fn test_memory_access_paths() {
+        let mut bus = BusInterface::default();
+        bus.set_conventional_size(0x10000);
+        bus.copy_from(&[0xAA, 0xBB], 0x20000, 0, true).unwrap();
+        bus.set_code_tracking(true);
+        assert!(bus.mark_code(0x1000, 2));
+        let epoch = bus.advance_memory_epoch();
+
+        // Plain conventional RAM is written, and writes are reported to the page and code maps.
+        assert_eq!(bus.write_u8(0x1000, 0x12, 0).unwrap(), DEFAULT_WAIT_STATES);
+        assert_eq!(bus.write_u16(0x1001, 0x5634, 0).unwrap(), DEFAULT_WAIT_STATES);
+        assert_eq!(bus.read_u8(0x1000, 0).unwrap().0, 0x12);
+        assert_eq!(bus.read_u16(0x1001, 0).unwrap().0, 0x5634);
+        assert!(bus.page_changed_since(0x1000 >> MEMORY_PAGE_SHIFT, epoch));
+        let mut writes = Vec::new();
+        bus.drain_code_writes(|address| writes.push(address));
+        assert_eq!(writes, vec![0x1000, 0x1001]);
+
+        // Memory past the end of conventional memory isn't written.
+        bus.write_u8(0x18000, 0x12, 0).unwrap();
+        bus.write_u16(0xFFFF, 0x5634, 0).unwrap();
+        assert_eq!(bus.peek_u8(0x18000).unwrap(), OPEN_BUS_BYTE);
+        assert_eq!(bus.peek_u8(0xFFFF).unwrap(), 0x34);
+        assert_eq!(bus.peek_u8(0x10000).unwrap(), OPEN_BUS_BYTE);
+
+        // ROM is readable but not writable.
+        bus.set_conventional_size(ADDRESS_SPACE);
+        bus.write_u8(0x20001, 0x12, 0).unwrap();
+        bus.write_u16(0x20000, 0x5634, 0).unwrap();
+        assert_eq!(bus.read_u16(0x20000, 0).unwrap().0, 0xBBAA);
+
+        // Protected watch regions block byte and word writes, one byte at a time.
+        bus.add_watch_region(WatchRegion {
+            start:   0x3001,
+            size:    1,
+            protect: true,
+            action:  WatchAction::Ignore,
+        });
+        bus.write_u8(0x3001, 0x12, 0).unwrap();
+        bus.write_u16(0x3000, 0x5634, 0).unwrap();
+        assert_eq!(bus.read_u16(0x3000, 0).unwrap().0, 0xFF34);
+    }
+
+    #[test]
+    fn test_fixed_clock_ticks() {
+        let mut bus = BusInterface::default();
+        bus.set_cpu_factor(ClockFactor::Fixed(8.0));
+        let ticks_per_cycle = IBM_PC_SYSTEM_CLOCK / 8.0;
+
+        // Run devices in short, uneven steps, as instructions would. Each step is a fractional
+        // number of ticks, which must not be lost.
+        let mut cycles = 0u64;
+        let mut ticks = 0u64;
+        for i in 0..1_000_000 {
+            let step = 3 + i % 7;
+            cycles += step as u64;
+            ticks += bus.advance_system_ticks(step) as u64;
+
+            // Device accesses within the next instruction are timed from the same fractional tick.
+            let expected = ((cycles as f64 + 4.0) * ticks_per_cycle) as u64 - ticks;
+            assert!(bus.mmio_system_ticks(4) as u64 + 1 >= expected);
+            assert!(bus.mmio_system_ticks(4) as u64 <= expected);
+        }
+        let expected = (cycles as f64 * ticks_per_cycle) as u64;
+        assert!(ticks.abs_diff(expected) <= 1, "{} ticks, expected {}", ticks, expected);
+
+        // Integer clock factors have no fractional ticks to carry.
+        bus.set_cpu_factor(ClockFactor::Divisor(3));
+        assert_eq!(bus.advance_system_ticks(5), 15);
+        assert_eq!(bus.mmio_system_ticks(5), 15);
+    }
+
+    #[test]
+    fn test_address_wrap() {
+        let mut bus = BusInterface::default();
+        bus.write_u8(0x10, 0x12, 0).unwrap();
+
+        // FFFF:0020 wraps to 0000:0010 by default.
+        let address = bus.wrap_address((0xFFFF << 4) + 0x20);
+        assert_eq!(address, 0x10);
+        assert_eq!(bus.peek_u8(address as usize).unwrap(), 0x12);
+
+        // With wrap disabled, the same address reaches the high memory area.
+        bus.set_address_wrap(false);
+        assert!(!bus.address_wrap());
+        let address = bus.wrap_address((0xFFFF << 4) + 0x20) as usize;
+        assert_eq!(address, 0x10_0010);
+        bus.write_u16(address, 0x5634, 0).unwrap();
+        assert_eq!(bus.read_u16(address, 0).unwrap().0, 0x5634);
+        assert_eq!(bus.peek_u8(0x10).unwrap(), 0x12);
+
+        // Memory beyond the high memory area is not populated.
+        bus.write_u8(ADDRESS_SPACE + HMA_LEN, 0x00, 0).unwrap();
+        assert_eq!(bus.peek_u8(ADDRESS_SPACE + HMA_LEN).unwrap(), OPEN_BUS_BYTE);
+
+        // A 24-bit address only loses A20 when wrap is enabled.
+        assert_eq!(bus.gate_a20(0x12_3456), 0x12_3456);
+        assert_eq!(bus.gate_a20(0x0112_3456), 0x12_3456);
+        bus.set_address_wrap(true);
+        assert_eq!(bus.gate_a20(0x10_0010), 0x10);
+        assert_eq!(bus.gate_a20(0x12_3456), 0x02_3456);
+    }
+
+    #[test]
+    fn test_add_remove_videocard() {
+        let machine_desc = *get_machine_descriptor(MachineType::Ibm5160).unwrap();
+        let mut config = test_config();
+        config.fdc = None;
+        let mut bus = BusInterface::new(ClockFactor::Divisor(3), machine_desc, KeyboardType::ModelF);
+        bus.install_devices(&machine_desc, &config).unwrap();
+        let video_dip = |bus: &mut BusInterface| bus.ppi_mut().as_ref().unwrap().dip_switches().sw1 & SW1_VIDEO_MASK;
+        assert_eq!(video_dip(&mut bus), SW1_HAVE_CGA_HIRES);
+
+        // A second CGA conflicts with the first.
+        assert!(bus.add_videocard(&config.video[0]).is_err());
+
+        let mut mda_config = config.video[0].clone();
+        mda_config.video_type = VideoType::MDA;
+        let mda = bus.add_videocard(&mda_config).unwrap();
+        assert_eq!(bus.enumerate_videocards().len(), 2);
+        assert_eq!(bus.io_map.get(&0x3B4), Some(&IoDeviceType::Video(mda)));
+
+        // Once the CGA is gone, the DIP switches report the MDA.
+        let cga = bus.enumerate_videocards()[0];
+        bus.remove_videocard(cga).unwrap();
+        assert_eq!(bus.enumerate_videocards(), vec![mda]);
+        assert_eq!(bus.io_map.get(&0x3D4), None);
+        assert_eq!(video_dip(&mut bus), SW1_HAVE_MDA);
+        assert!(bus.remove_videocard(cga).is_err());
+
+        // A card whose memory overlaps an installed card's is rejected, even without a port
+        // conflict.
+        let other = VideoCardId {
+            idx:   9,
+            vtype: VideoType::CGA,
+        };
+        bus.register_map(
+            MmioDeviceType::Video(other),
+            MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, 0x4000, false),
+        );
+        assert!(matches!(
+            bus.add_videocard(&config.video[0]),
+            Err(e) if matches!(
+                e.downcast_ref::<DeviceInstallError>(),
+                Some(DeviceInstallError::MemoryConflict { address: cga::CGA_MEM_ADDRESS, .. })
+            )
+        ));
+        assert_eq!(bus.io_map.get(&0x3D4), None);
+    }
+}
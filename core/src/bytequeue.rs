@@ -57,6 +57,8 @@ pub trait ByteQueue {
     fn wait_i(&mut self, cycles: u32, instr: &[u16]);
     fn wait_comment(&mut self, comment: &'static str);
     fn set_pc(&mut self, pc: u16);
+    /// Return the number of cycles elapsed in the current instruction, or 0 if reads are not timed.
+    fn elapsed(&self) -> u32;
 
     fn q_read_u8(&mut self, qtype: QueueType, reader: QueueReader) -> u8;
     fn q_read_i8(&mut self, qtype: QueueType, reader: QueueReader) -> i8;
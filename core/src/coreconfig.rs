@@ -69,6 +69,7 @@ pub trait CoreConfig {
     fn get_validator_type(&self) -> Option<ValidatorType>;
     fn get_validator_trace_file(&self) -> Option<PathBuf>;
     fn get_validator_baud(&self) -> Option<u32>;
+    fn get_validator_address(&self) -> Option<String>;
     fn get_cpu_trace_mode(&self) -> Option<TraceMode>;
     fn get_cpu_trace_on(&self) -> bool;
     fn get_cpu_trace_file(&self) -> Option<PathBuf>;
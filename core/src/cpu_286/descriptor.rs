@@ -0,0 +1,316 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    ---------------------------------------------------------------------------
+
+    cpu_286::descriptor.rs
+
+    Implements the 80286 descriptor formats: segment and gate descriptors,
+    the descriptor table registers, and the descriptor cache that shadows
+    each segment register.
+
+*/
+
+/// The descriptor is present in memory.
+pub const ACCESS_PRESENT: u8 = 0b1000_0000;
+/// Descriptor privilege level, bits 5-6.
+pub const ACCESS_DPL_MASK: u8 = 0b0110_0000;
+/// Set for code and data segments, clear for system segments and gates.
+pub const ACCESS_SEGMENT: u8 = 0b0001_0000;
+/// Set for code segments, clear for data segments.
+pub const ACCESS_EXECUTABLE: u8 = 0b0000_1000;
+/// Code segments only: the segment may be entered from a less privileged level.
+pub const ACCESS_CONFORMING: u8 = 0b0000_0100;
+/// Data segments only: the segment grows downwards from its limit.
+pub const ACCESS_EXPAND_DOWN: u8 = 0b0000_0100;
+/// Code segments only: the segment may be read as data.
+pub const ACCESS_READABLE: u8 = 0b0000_0010;
+/// Data segments only: the segment may be written.
+pub const ACCESS_WRITABLE: u8 = 0b0000_0010;
+/// Set by the CPU whenever the descriptor is loaded into a segment register.
+pub const ACCESS_ACCESSED: u8 = 0b0000_0001;
+/// The type field of a system descriptor.
+pub const ACCESS_SYSTEM_TYPE_MASK: u8 = 0b0000_1111;
+
+/// The types of system segment and gate descriptors defined by the 80286.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SystemDescriptorType {
+    AvailableTss,
+    Ldt,
+    BusyTss,
+    CallGate,
+    TaskGate,
+    InterruptGate,
+    TrapGate,
+    Invalid,
+}
+
+impl From<u8> for SystemDescriptorType {
+    fn from(access: u8) -> Self {
+        match access & ACCESS_SYSTEM_TYPE_MASK {
+            1 => SystemDescriptorType::AvailableTss,
+            2 => SystemDescriptorType::Ldt,
+            3 => SystemDescriptorType::BusyTss,
+            4 => SystemDescriptorType::CallGate,
+            5 => SystemDescriptorType::TaskGate,
+            6 => SystemDescriptorType::InterruptGate,
+            7 => SystemDescriptorType::TrapGate,
+            _ => SystemDescriptorType::Invalid,
+        }
+    }
+}
+
+/// A code, data or system segment descriptor.
+///
+/// In memory a descriptor is 8 bytes: a 16-bit limit, a 24-bit base, the access byte, and a
+/// final word that is reserved on the 80286.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SegmentDescriptor {
+    pub base:   u32,
+    pub limit:  u16,
+    pub access: u8,
+}
+
+impl SegmentDescriptor {
+    pub const SIZE: usize = 8;
+
+    pub fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Self {
+            base:   u32::from_le_bytes([bytes[2], bytes[3], bytes[4], 0]),
+            limit:  u16::from_le_bytes([bytes[0], bytes[1]]),
+            access: bytes[5],
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let limit = self.limit.to_le_bytes();
+        let base = self.base.to_le_bytes();
+        [limit[0], limit[1], base[0], base[1], base[2], self.access, 0, 0]
+    }
+
+    /// The descriptor cache contents produced by a real mode segment load. Real mode segments
+    /// are present, writable data segments with a 64K limit.
+    pub fn real_mode(segment: u16) -> Self {
+        Self {
+            base:   (segment as u32) << 4,
+            limit:  0xFFFF,
+            access: ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_WRITABLE | ACCESS_ACCESSED,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access & ACCESS_DPL_MASK) >> 5
+    }
+
+    pub fn is_segment(&self) -> bool {
+        self.access & ACCESS_SEGMENT != 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_EXECUTABLE != 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_EXECUTABLE == 0
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & ACCESS_CONFORMING != 0
+    }
+
+    pub fn is_expand_down(&self) -> bool {
+        self.is_data() && self.access & ACCESS_EXPAND_DOWN != 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.is_data() || self.access & ACCESS_READABLE != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.is_data() && self.access & ACCESS_WRITABLE != 0
+    }
+
+    pub fn system_type(&self) -> Option<SystemDescriptorType> {
+        match self.is_segment() {
+            true => None,
+            false => Some(SystemDescriptorType::from(self.access)),
+        }
+    }
+
+    /// Returns true if an access of `size` bytes at `offset` lies within the segment limit.
+    pub fn offset_valid(&self, offset: u16, size: u16) -> bool {
+        let last = offset as u32 + size.saturating_sub(1) as u32;
+        if self.is_expand_down() {
+            // Expand-down segments hold the offsets above the limit.
+            offset as u32 > self.limit as u32 && last <= 0xFFFF
+        }
+        else {
+            last <= self.limit as u32
+        }
+    }
+}
+
+/// A call, interrupt, trap or task gate.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GateDescriptor {
+    pub offset: u16,
+    pub selector: u16,
+    pub word_count: u8,
+    pub access: u8,
+}
+
+impl GateDescriptor {
+    pub fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Self {
+            offset: u16::from_le_bytes([bytes[0], bytes[1]]),
+            selector: u16::from_le_bytes([bytes[2], bytes[3]]),
+            word_count: bytes[4] & 0x1F,
+            access: bytes[5],
+        }
+    }
+
+    pub fn gate_type(&self) -> SystemDescriptorType {
+        match self.access & ACCESS_SEGMENT {
+            0 => SystemDescriptorType::from(self.access),
+            _ => SystemDescriptorType::Invalid,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access & ACCESS_DPL_MASK) >> 5
+    }
+}
+
+/// The GDTR and IDTR registers. LGDT and LIDT load 6 bytes: a 16-bit limit followed by a 24-bit
+/// base. SGDT and SIDT store the undefined final byte as 0xFF.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DescriptorTableRegister {
+    pub base:  u32,
+    pub limit: u16,
+}
+
+impl Default for DescriptorTableRegister {
+    /// The IDTR resets to the real mode interrupt vector table; the GDTR is unused until loaded.
+    fn default() -> Self {
+        Self {
+            base:  0,
+            limit: 0x03FF,
+        }
+    }
+}
+
+impl DescriptorTableRegister {
+    pub fn from_bytes(bytes: &[u8; 6]) -> Self {
+        Self {
+            base:  u32::from_le_bytes([bytes[2], bytes[3], bytes[4], 0]),
+            limit: u16::from_le_bytes([bytes[0], bytes[1]]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let limit = self.limit.to_le_bytes();
+        let base = self.base.to_le_bytes();
+        [limit[0], limit[1], base[0], base[1], base[2], 0xFF]
+    }
+
+    /// Returns true if the table holds the whole 8-byte descriptor at `offset`.
+    pub fn contains(&self, offset: u16) -> bool {
+        offset as u32 + 7 <= self.limit as u32
+    }
+}
+
+/// A segment register's selector together with the descriptor that was loaded for it. The CPU
+/// uses the cached descriptor for every access until the register is loaded again.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SegmentCache {
+    pub selector:   u16,
+    pub descriptor: SegmentDescriptor,
+}
+
+impl SegmentCache {
+    pub fn real_mode(segment: u16) -> Self {
+        Self {
+            selector:   segment,
+            descriptor: SegmentDescriptor::real_mode(segment),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descriptor_roundtrip() {
+        let bytes = [0xFF, 0x7F, 0x00, 0x34, 0x12, 0x93, 0x00, 0x00];
+        let desc = SegmentDescriptor::from_bytes(&bytes);
+        assert_eq!(desc.base, 0x123400);
+        assert_eq!(desc.limit, 0x7FFF);
+        assert!(desc.is_present());
+        assert!(desc.is_data());
+        assert!(desc.is_writable());
+        assert_eq!(desc.dpl(), 0);
+        assert_eq!(desc.to_bytes(), bytes);
+
+        let gate = GateDescriptor::from_bytes(&[0x78, 0x56, 0x08, 0x00, 0x00, 0xE6, 0x00, 0x00]);
+        assert_eq!(gate.offset, 0x5678);
+        assert_eq!(gate.selector, 0x0008);
+        assert_eq!(gate.dpl(), 3);
+        assert_eq!(gate.gate_type(), SystemDescriptorType::InterruptGate);
+
+        let dtr = DescriptorTableRegister::from_bytes(&[0x17, 0x00, 0x00, 0x00, 0x0F, 0x00]);
+        assert_eq!(dtr.base, 0x0F0000);
+        assert_eq!(dtr.limit, 0x0017);
+        assert!(dtr.contains(0x0010));
+        assert!(!dtr.contains(0x0018));
+        assert_eq!(dtr.to_bytes(), [0x17, 0x00, 0x00, 0x00, 0x0F, 0xFF]);
+    }
+
+    #[test]
+    fn test_offset_limits() {
+        let mut desc = SegmentDescriptor::real_mode(0x1000);
+        assert_eq!(desc.base, 0x10000);
+        assert!(desc.offset_valid(0xFFFE, 2));
+        assert!(!desc.offset_valid(0xFFFF, 2));
+
+        desc.limit = 0x00FF;
+        assert!(desc.offset_valid(0x00FE, 2));
+        assert!(!desc.offset_valid(0x00FF, 2));
+
+        // An expand-down segment with limit 0x00FF holds offsets 0x0100-0xFFFF.
+        desc.access |= ACCESS_EXPAND_DOWN;
+        assert!(!desc.offset_valid(0x00FF, 1));
+        assert!(desc.offset_valid(0x0100, 2));
+        assert!(!desc.offset_valid(0xFFFF, 2));
+    }
+}
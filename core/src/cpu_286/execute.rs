@@ -0,0 +1,1153 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    ---------------------------------------------------------------------------
+
+    cpu_286::execute.rs
+
+    Implements the 80286 extensions to the execution core: the descriptor
+    caches behind the segment registers, protected mode interrupt and return
+    handling, and the instructions the 80286 added to the 8086 set.
+
+    Segment registers are still written directly throughout the core. In
+    protected mode, a segment register whose value no longer matches its
+    cached selector is reloaded from the descriptor tables at the next
+    instruction boundary (or immediately for CS, on the queue flush that
+    follows every change of CS). A failed load restores the old selector and
+    raises the fault before the next instruction executes.
+
+*/
+
+use crate::{
+    cpu_286::*,
+    cpu_808x::{biu::*, mnemonic::Mnemonic, *},
+};
+
+const SEG_CACHE_ES: usize = 0;
+const SEG_CACHE_CS: usize = 1;
+const SEG_CACHE_SS: usize = 2;
+const SEG_CACHE_DS: usize = 3;
+
+impl Cpu {
+    pub(crate) fn reset_286(&mut self) {
+        self.msw = 0;
+        self.gdtr = DescriptorTableRegister {
+            base:  0,
+            limit: 0xFFFF,
+        };
+        self.idtr = DescriptorTableRegister::default();
+        self.ldtr = SegmentCache::default();
+        self.tr = SegmentCache::default();
+        self.seg_cache = [SegmentCache::default(); 4];
+        self.iopl_nt = 0;
+        self.pending_fault = None;
+    }
+
+    #[inline]
+    pub fn is_protected_mode(&self) -> bool {
+        self.msw & MSW_PE != 0
+    }
+
+    /// The current privilege level. This is the RPL of the CS selector in protected mode.
+    #[inline]
+    pub fn cpl(&self) -> u8 {
+        match self.is_protected_mode() {
+            true => (self.seg_cache[SEG_CACHE_CS].selector & 0x03) as u8,
+            false => 0,
+        }
+    }
+
+    pub fn msw(&self) -> u16 {
+        self.msw | MSW_RESERVED
+    }
+
+    fn seg_cache_index(segment: Segment) -> Option<usize> {
+        match segment {
+            Segment::None => None,
+            Segment::ES => Some(SEG_CACHE_ES),
+            Segment::CS => Some(SEG_CACHE_CS),
+            Segment::SS => Some(SEG_CACHE_SS),
+            Segment::DS => Some(SEG_CACHE_DS),
+        }
+    }
+
+    fn segment_register(&self, segment: Segment) -> u16 {
+        match segment {
+            Segment::None => 0,
+            Segment::ES => self.es,
+            Segment::CS => self.cs,
+            Segment::SS => self.ss,
+            Segment::DS => self.ds,
+        }
+    }
+
+    fn set_segment_register(&mut self, segment: Segment, value: u16) {
+        match segment {
+            Segment::None => {}
+            Segment::ES => self.es = value,
+            Segment::CS => self.cs = value,
+            Segment::SS => self.ss = value,
+            Segment::DS => self.ds = value,
+        }
+    }
+
+    /// Return the base address of a segment. In protected mode, the base comes from the segment's
+    /// descriptor cache instead of the segment register.
+    #[inline]
+    pub fn segment_base(&self, segment: Segment) -> u32 {
+        if self.is_protected_mode() {
+            match Cpu::seg_cache_index(segment) {
+                Some(idx) => self.seg_cache[idx].descriptor.base,
+                None => 0,
+            }
+        }
+        else {
+            (self.segment_register(segment) as u32) << 4
+        }
+    }
+
+    // System accesses to descriptor tables and the TSS are made directly on the bus, without
+    // running bus cycles.
+    fn read_linear_u8(&mut self, address: u32) -> u8 {
+        self.bus
+            .peek_u8(self.wrap_linear_address(address) as usize)
+            .unwrap_or(0xFF)
+    }
+
+    fn read_linear_u16(&mut self, address: u32) -> u16 {
+        self.read_linear_u8(address) as u16 | (self.read_linear_u8(address.wrapping_add(1)) as u16) << 8
+    }
+
+    fn read_linear_bytes<const N: usize>(&mut self, address: u32) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_linear_u8(address.wrapping_add(i as u32));
+        }
+        bytes
+    }
+
+    fn write_linear_u8(&mut self, address: u32, data: u8) {
+        _ = self.bus.write_u8(self.wrap_linear_address(address) as usize, data, 0);
+    }
+
+    /// Read the descriptor for a selector from the GDT or LDT, returning its address and raw bytes.
+    fn read_descriptor(&mut self, selector: Selector) -> Result<(u32, [u8; 8]), ProtectionFault> {
+        let table = match selector.is_local() {
+            true => {
+                if Selector(self.ldtr.selector).is_null() {
+                    return Err(ProtectionFault::GeneralProtection(selector.error_code()));
+                }
+                DescriptorTableRegister {
+                    base:  self.ldtr.descriptor.base,
+                    limit: self.ldtr.descriptor.limit,
+                }
+            }
+            false => self.gdtr,
+        };
+
+        if !table.contains(selector.table_offset()) {
+            return Err(ProtectionFault::GeneralProtection(selector.error_code()));
+        }
+        let address = table.base.wrapping_add(selector.table_offset() as u32);
+        Ok((address, self.read_linear_bytes(address)))
+    }
+
+    fn read_segment_descriptor(&mut self, selector: Selector) -> Result<(u32, SegmentDescriptor), ProtectionFault> {
+        let (address, bytes) = self.read_descriptor(selector)?;
+        Ok((address, SegmentDescriptor::from_bytes(&bytes)))
+    }
+
+    /// Set the accessed bit of a segment descriptor, as the CPU does whenever it loads one.
+    fn mark_descriptor_accessed(&mut self, address: u32, descriptor: &mut SegmentDescriptor) {
+        if descriptor.is_segment() && descriptor.access & ACCESS_ACCESSED == 0 {
+            descriptor.access |= ACCESS_ACCESSED;
+            self.write_linear_u8(address.wrapping_add(5), descriptor.access);
+        }
+    }
+
+    /// Load a segment register and its descriptor cache together. The descriptor must already have
+    /// been validated.
+    fn load_segment_cache(&mut self, segment: Segment, selector: u16, descriptor: SegmentDescriptor) {
+        if let Some(idx) = Cpu::seg_cache_index(segment) {
+            self.set_segment_register(segment, selector);
+            self.seg_cache[idx] = SegmentCache { selector, descriptor };
+        }
+    }
+
+    /// Bring the descriptor cache of a segment register up to date with the register's value.
+    pub fn sync_segment_cache(&mut self, segment: Segment) {
+        let idx = match Cpu::seg_cache_index(segment) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let value = self.segment_register(segment);
+        if self.seg_cache[idx].selector == value {
+            return;
+        }
+
+        let load = match segment {
+            Segment::CS => SegmentLoad::Code,
+            Segment::SS => SegmentLoad::Stack,
+            _ => SegmentLoad::Data,
+        };
+        let selector = Selector(value);
+        let cpl = self.cpl();
+
+        let result = if load == SegmentLoad::Data && selector.is_null() {
+            Ok(SegmentDescriptor::default())
+        }
+        else {
+            match self.read_segment_descriptor(selector) {
+                Ok((address, mut descriptor)) => match check_segment_load(load, selector, &descriptor, cpl) {
+                    Ok(()) => {
+                        self.mark_descriptor_accessed(address, &mut descriptor);
+                        Ok(descriptor)
+                    }
+                    Err(fault) => Err(fault),
+                },
+                Err(fault) => Err(fault),
+            }
+        };
+
+        match result {
+            Ok(descriptor) => {
+                // CS always carries the current privilege level in its RPL.
+                let selector = match load {
+                    SegmentLoad::Code => (value & !0x03) | cpl as u16,
+                    _ => value,
+                };
+                self.load_segment_cache(segment, selector, descriptor);
+            }
+            Err(fault) => {
+                log::debug!("Segment load of {:04X} into {:?} failed with {}", value, segment, fault);
+                let old_selector = self.seg_cache[idx].selector;
+                self.set_segment_register(segment, old_selector);
+                self.raise_protection_fault(fault);
+            }
+        }
+    }
+
+    /// Reload any descriptor caches whose segment registers have changed.
+    pub fn sync_descriptor_caches(&mut self) {
+        if self.is_protected_mode() {
+            for segment in [Segment::CS, Segment::SS, Segment::DS, Segment::ES] {
+                self.sync_segment_cache(segment);
+            }
+        }
+    }
+
+    /// Record a protection fault to be delivered at the next instruction boundary. Only the
+    /// first fault of an instruction is kept.
+    pub fn raise_protection_fault(&mut self, fault: ProtectionFault) {
+        if self.pending_fault.is_none() {
+            self.pending_fault = Some(fault);
+        }
+    }
+
+    /// Deliver a pending protection fault, if any. Returns true if a fault was delivered.
+    pub fn deliver_pending_fault(&mut self) -> bool {
+        match self.pending_fault.take() {
+            Some(fault) => {
+                log::debug!("Delivering {} at [{:04X}:{:04X}]", fault, self.cs, self.ip());
+                self.biu_suspend_fetch();
+                self.protected_mode_interrupt(fault.vector(), InterruptType::Exception, Some(fault.error_code()));
+                self.int_count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Raise an exception whose return address is the faulting instruction, such as the invalid
+    /// opcode exception.
+    pub fn raise_exception_286(&mut self, vector: u8) {
+        self.biu_suspend_fetch();
+        self.pc = self.instruction_ip;
+        self.queue.flush();
+        self.intr_routine(vector, InterruptType::Exception, false);
+        self.int_count += 1;
+    }
+
+    /// Read the stack pointer for a privilege level from the current TSS.
+    fn tss_stack(&mut self, level: u8) -> Result<(u16, u16), ProtectionFault> {
+        let offset = 2 + level as u32 * 4;
+        if offset + 3 > self.tr.descriptor.limit as u32 {
+            return Err(ProtectionFault::InvalidTss(self.tr.selector & !0x03));
+        }
+        let base = self.tr.descriptor.base.wrapping_add(offset);
+        let sp = self.read_linear_u16(base);
+        let ss = self.read_linear_u16(base.wrapping_add(2));
+        Ok((ss, sp))
+    }
+
+    /// Validate and load SS for a privilege level change. Faults are reported against the TSS.
+    fn load_inner_stack(&mut self, level: u8) -> Result<(u16, u16, SegmentDescriptor), ProtectionFault> {
+        let (ss, sp) = self.tss_stack(level)?;
+        let selector = Selector(ss);
+        let (address, mut descriptor) = self
+            .read_segment_descriptor(selector)
+            .map_err(|_| ProtectionFault::InvalidTss(selector.error_code()))?;
+
+        match check_segment_load(SegmentLoad::Stack, selector, &descriptor, level) {
+            Ok(()) => {}
+            Err(ProtectionFault::Stack(code)) => return Err(ProtectionFault::Stack(code)),
+            Err(_) => return Err(ProtectionFault::InvalidTss(selector.error_code())),
+        }
+        self.mark_descriptor_accessed(address, &mut descriptor);
+        Ok((ss, sp, descriptor))
+    }
+
+    /// Deliver an interrupt or exception through the IDT. A fault while delivering an exception
+    /// escalates to a double fault, and a fault while delivering a double fault shuts the
+    /// processor down.
+    pub fn protected_mode_interrupt(&mut self, vector: u8, itype: InterruptType, error_code: Option<u16>) {
+        let mut vector = vector;
+        let mut itype = itype;
+        let mut error_code = error_code;
+
+        // Callers suspend fetching first. Let a fetch that was in progress reach the queue before
+        // CORR rewinds PC, or the return address will be off by one.
+        self.cycles(2);
+        self.corr();
+        loop {
+            match self.enter_interrupt_gate(vector, itype, error_code) {
+                Ok(()) => return,
+                Err(fault) => {
+                    log::debug!("Fault {} while delivering interrupt {:02X}", fault, vector);
+                    if vector == EXCEPTION_DOUBLE_FAULT {
+                        self.shutdown();
+                        return;
+                    }
+                    if (EXCEPTION_INVALID_TSS..=EXCEPTION_GENERAL_PROTECTION).contains(&vector) {
+                        vector = EXCEPTION_DOUBLE_FAULT;
+                        error_code = Some(0);
+                    }
+                    else {
+                        vector = fault.vector();
+                        error_code = Some(fault.error_code());
+                    }
+                    itype = InterruptType::Exception;
+                }
+            }
+        }
+    }
+
+    fn enter_interrupt_gate(
+        &mut self,
+        vector: u8,
+        itype: InterruptType,
+        error_code: Option<u16>,
+    ) -> Result<(), ProtectionFault> {
+        // Faults on the IDT entry itself report the vector with the IDT bit set.
+        let gate_offset = vector as u16 * 8;
+        let idt_code = gate_offset | 0x02;
+        if !self.idtr.contains(gate_offset) {
+            return Err(ProtectionFault::GeneralProtection(idt_code));
+        }
+
+        let gate_bytes = self.read_linear_bytes(self.idtr.base.wrapping_add(gate_offset as u32));
+        let gate = GateDescriptor::from_bytes(&gate_bytes);
+        let cpl = self.cpl();
+
+        match gate.gate_type() {
+            SystemDescriptorType::InterruptGate | SystemDescriptorType::TrapGate => {}
+            SystemDescriptorType::TaskGate => {
+                log::error!(
+                    "Task gate for interrupt {:02X}: task switching is not supported",
+                    vector
+                );
+                return Err(ProtectionFault::GeneralProtection(idt_code));
+            }
+            _ => return Err(ProtectionFault::GeneralProtection(idt_code)),
+        }
+        if itype == InterruptType::Software && gate.dpl() < cpl {
+            return Err(ProtectionFault::GeneralProtection(idt_code));
+        }
+        if !gate.is_present() {
+            return Err(ProtectionFault::NotPresent(idt_code));
+        }
+
+        let selector = Selector(gate.selector);
+        if selector.is_null() {
+            return Err(ProtectionFault::GeneralProtection(0));
+        }
+        let (address, mut descriptor) = self.read_segment_descriptor(selector)?;
+        if !descriptor.is_code() || descriptor.dpl() > cpl {
+            return Err(ProtectionFault::GeneralProtection(selector.error_code()));
+        }
+        if !descriptor.is_present() {
+            return Err(ProtectionFault::NotPresent(selector.error_code()));
+        }
+        self.mark_descriptor_accessed(address, &mut descriptor);
+
+        let new_cpl = match descriptor.is_conforming() {
+            true => cpl,
+            false => descriptor.dpl(),
+        };
+        let inner_stack = match new_cpl < cpl {
+            true => Some(self.load_inner_stack(new_cpl)?),
+            false => None,
+        };
+
+        // All checks have passed. Build the interrupt frame.
+        let flags = self.pushed_flags_286();
+        // PC was corrected on entry and now holds the return address.
+        let return_cs = self.cs;
+        let return_ip = self.pc;
+
+        self.push_call_stack(
+            CallStackEntry::Interrupt {
+                ret_cs: return_cs,
+                ret_ip: return_ip,
+                call_cs: gate.selector,
+                call_ip: gate.offset,
+                itype,
+                number: vector,
+                ah: self.ah,
+            },
+            return_cs,
+            return_ip,
+        );
+
+        if let Some((ss, sp, stack_descriptor)) = inner_stack {
+            let (old_ss, old_sp) = (self.ss, self.sp);
+            self.load_segment_cache(Segment::SS, ss, stack_descriptor);
+            self.sp = sp;
+            self.push_u16(old_ss, ReadWriteFlag::Normal);
+            self.push_u16(old_sp, ReadWriteFlag::Normal);
+        }
+        self.push_u16(flags, ReadWriteFlag::Normal);
+        self.push_u16(return_cs, ReadWriteFlag::Normal);
+        self.push_u16(return_ip, ReadWriteFlag::Normal);
+        if let Some(code) = error_code {
+            self.push_u16(code, ReadWriteFlag::Normal);
+        }
+
+        if gate.gate_type() == SystemDescriptorType::InterruptGate {
+            self.clear_flag(Flag::Interrupt);
+        }
+        self.clear_flag(Flag::Trap);
+        self.iopl_nt &= !FLAGS_NT;
+
+        self.load_segment_cache(Segment::CS, (gate.selector & !0x03) | new_cpl as u16, descriptor);
+        self.pc = gate.offset;
+        self.biu_queue_flush();
+        self.cycles(4);
+        Ok(())
+    }
+
+    /// Perform a protected mode far return or IRET, including a return to an outer privilege level.
+    pub fn protected_mode_return(&mut self, release: u16, iret: bool) {
+        let cpl = self.cpl();
+        let sp = self.sp;
+        let new_ip = self.biu_read_u16(Segment::SS, sp, ReadWriteFlag::Normal);
+        let new_cs = self.biu_read_u16(Segment::SS, sp.wrapping_add(2), ReadWriteFlag::Normal);
+        let new_flags = match iret {
+            true => Some(self.biu_read_u16(Segment::SS, sp.wrapping_add(4), ReadWriteFlag::Normal)),
+            false => None,
+        };
+        let frame_size = match iret {
+            true => 6,
+            false => 4u16.wrapping_add(release),
+        };
+
+        let selector = Selector(new_cs);
+        if selector.is_null() {
+            self.raise_protection_fault(ProtectionFault::GeneralProtection(0));
+            return;
+        }
+        if selector.rpl() < cpl {
+            self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code()));
+            return;
+        }
+        let (address, mut descriptor) = match self.read_segment_descriptor(selector) {
+            Ok(result) => result,
+            Err(fault) => {
+                self.raise_protection_fault(fault);
+                return;
+            }
+        };
+        let new_cpl = selector.rpl();
+        let code_ok = descriptor.is_code()
+            && match descriptor.is_conforming() {
+                true => descriptor.dpl() <= new_cpl,
+                false => descriptor.dpl() == new_cpl,
+            };
+        if !code_ok {
+            self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code()));
+            return;
+        }
+        if !descriptor.is_present() {
+            self.raise_protection_fault(ProtectionFault::NotPresent(selector.error_code()));
+            return;
+        }
+
+        // A return to an outer level also pops the outer stack pointer.
+        let outer_stack = match new_cpl > cpl {
+            true => {
+                let outer_sp = self.biu_read_u16(Segment::SS, sp.wrapping_add(frame_size), ReadWriteFlag::Normal);
+                let outer_ss = self.biu_read_u16(Segment::SS, sp.wrapping_add(frame_size + 2), ReadWriteFlag::Normal);
+                let ss_selector = Selector(outer_ss);
+                match self.read_segment_descriptor(ss_selector) {
+                    Ok((ss_address, mut ss_descriptor)) => {
+                        match check_segment_load(SegmentLoad::Stack, ss_selector, &ss_descriptor, new_cpl) {
+                            Ok(()) => {
+                                self.mark_descriptor_accessed(ss_address, &mut ss_descriptor);
+                                Some((outer_ss, outer_sp, ss_descriptor))
+                            }
+                            Err(fault) => {
+                                self.raise_protection_fault(fault);
+                                return;
+                            }
+                        }
+                    }
+                    Err(fault) => {
+                        self.raise_protection_fault(fault);
+                        return;
+                    }
+                }
+            }
+            false => None,
+        };
+
+        self.mark_descriptor_accessed(address, &mut descriptor);
+        if let Some(flags) = new_flags {
+            self.set_popped_flags(flags);
+        }
+        self.load_segment_cache(Segment::CS, new_cs, descriptor);
+        self.pc = new_ip;
+
+        match outer_stack {
+            Some((ss, outer_sp, ss_descriptor)) => {
+                self.load_segment_cache(Segment::SS, ss, ss_descriptor);
+                self.sp = outer_sp.wrapping_add(if iret { 0 } else { release });
+            }
+            None => {
+                self.sp = sp.wrapping_add(frame_size);
+            }
+        }
+
+        self.biu_suspend_fetch();
+        self.biu_queue_flush();
+        self.cycles(4);
+    }
+
+    fn shutdown(&mut self) {
+        // The IBM AT resets the CPU when it detects a shutdown cycle.
+        log::warn!("CPU shutdown at [{:04X}:{:04X}], resetting", self.cs, self.ip());
+        self.reset();
+    }
+
+    /// The flags image pushed by the 80286. Bits 12-15 are always clear in real mode; in protected
+    /// mode they hold IOPL and NT.
+    pub fn pushed_flags_286(&self) -> u16 {
+        let flags = self.resolved_flags() & 0x0FFF;
+        match self.is_protected_mode() {
+            true => flags | self.iopl_nt,
+            false => flags,
+        }
+    }
+
+    /// Apply a flags image popped by IRET.
+    fn set_popped_flags(&mut self, flags: u16) {
+        let trap_was_set = self.get_flag(Flag::Trap);
+        self.flags = (flags & FLAGS_POP_MASK) | CPU_FLAGS_RESERVED_ON;
+        self.lazy_flags = LazyFlags::None;
+        self.pop_flags_286(flags);
+        if !trap_was_set && self.get_flag(Flag::Trap) {
+            self.trap_enable_delay = self.trap_enable_delay_len;
+        }
+    }
+
+    /// Update IOPL and NT from a popped flags image. IOPL can only be changed at privilege level 0.
+    pub(crate) fn pop_flags_286(&mut self, flags: u16) {
+        if self.is_protected_mode() {
+            self.iopl_nt = match self.cpl() {
+                0 => flags & FLAGS_IOPL_NT_MASK,
+                _ => (self.iopl_nt & FLAGS_IOPL) | (flags & FLAGS_NT),
+            };
+        }
+    }
+
+    /// Load the MSW. Once set, PE can only be cleared by a reset.
+    fn lmsw(&mut self, value: u16) {
+        let entering = !self.is_protected_mode() && value & MSW_PE != 0;
+        self.msw = (self.msw & MSW_PE) | (value & MSW_MASK);
+
+        if entering {
+            log::debug!("Entering protected mode at [{:04X}:{:04X}]", self.cs, self.ip());
+            // The descriptor caches keep their real mode contents until each register is reloaded.
+            self.seg_cache[SEG_CACHE_ES] = SegmentCache::real_mode(self.es);
+            self.seg_cache[SEG_CACHE_CS] = SegmentCache::real_mode(self.cs);
+            self.seg_cache[SEG_CACHE_SS] = SegmentCache::real_mode(self.ss);
+            self.seg_cache[SEG_CACHE_DS] = SegmentCache::real_mode(self.ds);
+        }
+    }
+
+    /// Check that a selector names a segment visible at the current privilege level, for LAR,
+    /// LSL, VERR and VERW.
+    fn visible_descriptor(&mut self, selector: Selector) -> Option<SegmentDescriptor> {
+        if selector.is_null() {
+            return None;
+        }
+        let (_, descriptor) = self.read_segment_descriptor(selector).ok()?;
+        let privileged = descriptor.dpl() < self.cpl().max(selector.rpl());
+        if descriptor.is_conforming() || !privileged {
+            Some(descriptor)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Return the segment and offset of the current instruction's memory operand, or None if it
+    /// names a register.
+    fn memory_operand(&mut self, operand: OperandType) -> Option<(Segment, u16)> {
+        match operand {
+            OperandType::AddressingMode(mode) => {
+                let (_segment_val, segment, offset) = self.calc_effective_address(mode, self.i.segment_override);
+                Some((segment, offset))
+            }
+            _ => None,
+        }
+    }
+
+    /// Execute an instruction that is new or different on the 80286. Returns false if the current
+    /// instruction executes the same way as on the 8088.
+    #[rustfmt::skip]
+    pub fn execute_286_instruction(&mut self, jump: &mut bool) -> bool {
+        match self.i.opcode {
+            0x0F => self.execute_286_system(jump),
+            0x60 => {
+                // PUSHA
+                let sp = self.sp;
+                for value in [self.ax, self.cx, self.dx, self.bx, sp, self.bp, self.si, self.di] {
+                    self.push_u16(value, ReadWriteFlag::Normal);
+                }
+                true
+            }
+            0x61 => {
+                // POPA. The saved SP is discarded.
+                self.di = self.pop_u16();
+                self.si = self.pop_u16();
+                self.bp = self.pop_u16();
+                _ = self.pop_u16();
+                let bx = self.pop_u16();
+                let dx = self.pop_u16();
+                let cx = self.pop_u16();
+                let ax = self.pop_u16();
+                self.set_register16(Register16::BX, bx);
+                self.set_register16(Register16::DX, dx);
+                self.set_register16(Register16::CX, cx);
+                self.set_register16(Register16::AX, ax);
+                true
+            }
+            0x62 => {
+                // BOUND r16, m16&16. The register form is invalid.
+                if !matches!(self.i.operand2_type, OperandType::AddressingMode(_)) {
+                    self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+                    *jump = true;
+                    return true;
+                }
+                let index = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap() as i16;
+                let (upper, lower) = self
+                    .read_operand_farptr(self.i.operand2_type, self.i.segment_override, ReadWriteFlag::Normal)
+                    .unwrap();
+                if index < lower as i16 || index > upper as i16 {
+                    self.raise_exception_286(EXCEPTION_BOUND);
+                    *jump = true;
+                }
+                true
+            }
+            0x63 => {
+                // ARPL r/m16, r16
+                if !self.is_protected_mode() {
+                    self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+                    *jump = true;
+                    return true;
+                }
+                let dest = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let src = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                if dest & 0x03 < src & 0x03 {
+                    self.set_flag(Flag::Zero);
+                    self.write_operand16(self.i.operand1_type, self.i.segment_override, (dest & !0x03) | (src & 0x03), ReadWriteFlag::Normal);
+                }
+                else {
+                    self.clear_flag(Flag::Zero);
+                }
+                true
+            }
+            0x64..=0x67 => {
+                self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+                *jump = true;
+                true
+            }
+            0x68 => {
+                // PUSH imm16
+                let value = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                self.push_u16(value, ReadWriteFlag::RNI);
+                true
+            }
+            0x6A => {
+                // PUSH imm8, sign-extended
+                let value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap() as i8 as i16 as u16;
+                self.push_u16(value, ReadWriteFlag::RNI);
+                true
+            }
+            0x69 | 0x6B => {
+                // IMUL r16, r/m16, imm
+                let multiplicand = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let multiplier = match self.i.operand3_type {
+                    OperandType::Immediate16(_) => self.read_operand16(self.i.operand3_type, SegmentOverride::None).unwrap(),
+                    _ => self.read_operand8(self.i.operand3_type, SegmentOverride::None).unwrap() as i8 as i16 as u16,
+                };
+                let product = multiplicand as i16 as i32 * multiplier as i16 as i32;
+                let overflow = product != product as i16 as i32;
+                self.cycles(21);
+                self.set_flag_state(Flag::Carry, overflow);
+                self.set_flag_state(Flag::Overflow, overflow);
+                self.write_operand16(self.i.operand1_type, self.i.segment_override, product as u16, ReadWriteFlag::RNI);
+                true
+            }
+            0x6C..=0x6F => {
+                // INSB, INSW, OUTSB, OUTSW
+                if self.rep_start() {
+                    self.string_op(self.i.mnemonic, self.i.segment_override);
+                    if self.in_rep {
+                        self.decrement_register16(Register16::CX);
+                        if self.intr_pending {
+                            self.rep_interrupt();
+                        }
+                        else if self.cx == 0 {
+                            self.rep_end();
+                        }
+                    }
+                }
+                true
+            }
+            0xC0 => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR: r/m8, imm8
+                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let count = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap();
+                self.cycles(5 + (count & 0x1F) as u32);
+                let result = self.bitshift_op8(self.i.mnemonic, op1_value, count);
+                self.write_operand8(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+                true
+            }
+            0xC1 => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR: r/m16, imm8
+                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let count = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap();
+                self.cycles(5 + (count & 0x1F) as u32);
+                let result = self.bitshift_op16(self.i.mnemonic, op1_value, count);
+                self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+                true
+            }
+            0xC8 => {
+                // ENTER imm16, imm8
+                let frame_size = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let level = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap() & 0x1F;
+                self.push_u16(self.bp, ReadWriteFlag::Normal);
+                let frame_ptr = self.sp;
+                if level > 0 {
+                    for _ in 1..level {
+                        self.bp = self.bp.wrapping_sub(2);
+                        let display = self.biu_read_u16(Segment::SS, self.bp, ReadWriteFlag::Normal);
+                        self.push_u16(display, ReadWriteFlag::Normal);
+                    }
+                    self.push_u16(frame_ptr, ReadWriteFlag::Normal);
+                }
+                self.bp = frame_ptr;
+                self.sp = self.sp.wrapping_sub(frame_size);
+                self.cycles(11);
+                true
+            }
+            0xC9 => {
+                // LEAVE
+                self.sp = self.bp;
+                self.bp = self.pop_u16();
+                self.cycles(3);
+                true
+            }
+            0xCA | 0xCB if self.is_protected_mode() => {
+                // RETF in protected mode may return to an outer privilege level.
+                let release = match self.i.opcode {
+                    0xCA => self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap(),
+                    _ => 0,
+                };
+                self.protected_mode_return(release, false);
+                *jump = true;
+                true
+            }
+            0xFE | 0xFF if self.i.mnemonic == Mnemonic::Undefined => {
+                self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+                *jump = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Execute the two-byte 0x0F system instructions.
+    fn execute_286_system(&mut self, jump: &mut bool) -> bool {
+        let protected_only = matches!(
+            self.i.mnemonic,
+            Mnemonic::SLDT
+                | Mnemonic::STR
+                | Mnemonic::LLDT
+                | Mnemonic::LTR
+                | Mnemonic::VERR
+                | Mnemonic::VERW
+                | Mnemonic::LAR
+                | Mnemonic::LSL
+        );
+        let privileged = matches!(
+            self.i.mnemonic,
+            Mnemonic::LLDT | Mnemonic::LTR | Mnemonic::LGDT | Mnemonic::LIDT | Mnemonic::LMSW | Mnemonic::CLTS
+        );
+
+        if self.i.mnemonic == Mnemonic::Undefined || (protected_only && !self.is_protected_mode()) {
+            self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+            *jump = true;
+            return true;
+        }
+        if privileged && self.cpl() != 0 {
+            self.raise_protection_fault(ProtectionFault::GeneralProtection(0));
+            return true;
+        }
+
+        self.cycles(2);
+        match self.i.mnemonic {
+            Mnemonic::SLDT => {
+                self.write_operand16(
+                    self.i.operand1_type,
+                    self.i.segment_override,
+                    self.ldtr.selector,
+                    ReadWriteFlag::RNI,
+                );
+            }
+            Mnemonic::STR => {
+                self.write_operand16(
+                    self.i.operand1_type,
+                    self.i.segment_override,
+                    self.tr.selector,
+                    ReadWriteFlag::RNI,
+                );
+            }
+            Mnemonic::LLDT => {
+                let selector = Selector(
+                    self.read_operand16(self.i.operand1_type, self.i.segment_override)
+                        .unwrap(),
+                );
+                if selector.is_null() {
+                    self.ldtr = SegmentCache::default();
+                }
+                else if selector.is_local() {
+                    self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code()));
+                }
+                else {
+                    match self.read_segment_descriptor(selector) {
+                        Ok((_, descriptor)) if descriptor.system_type() == Some(SystemDescriptorType::Ldt) => {
+                            if descriptor.is_present() {
+                                self.ldtr = SegmentCache {
+                                    selector: selector.0,
+                                    descriptor,
+                                };
+                            }
+                            else {
+                                self.raise_protection_fault(ProtectionFault::NotPresent(selector.error_code()));
+                            }
+                        }
+                        Ok(_) => self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code())),
+                        Err(fault) => self.raise_protection_fault(fault),
+                    }
+                }
+            }
+            Mnemonic::LTR => {
+                let selector = Selector(
+                    self.read_operand16(self.i.operand1_type, self.i.segment_override)
+                        .unwrap(),
+                );
+                if selector.is_null() || selector.is_local() {
+                    self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code()));
+                }
+                else {
+                    match self.read_segment_descriptor(selector) {
+                        Ok((address, mut descriptor))
+                            if descriptor.system_type() == Some(SystemDescriptorType::AvailableTss) =>
+                        {
+                            if descriptor.is_present() {
+                                // Loading TR marks the TSS busy.
+                                descriptor.access |= 0x02;
+                                self.write_linear_u8(address.wrapping_add(5), descriptor.access);
+                                self.tr = SegmentCache {
+                                    selector: selector.0,
+                                    descriptor,
+                                };
+                            }
+                            else {
+                                self.raise_protection_fault(ProtectionFault::NotPresent(selector.error_code()));
+                            }
+                        }
+                        Ok(_) => self.raise_protection_fault(ProtectionFault::GeneralProtection(selector.error_code())),
+                        Err(fault) => self.raise_protection_fault(fault),
+                    }
+                }
+            }
+            Mnemonic::VERR | Mnemonic::VERW => {
+                let selector = Selector(
+                    self.read_operand16(self.i.operand1_type, self.i.segment_override)
+                        .unwrap(),
+                );
+                let accessible = match self.visible_descriptor(selector) {
+                    Some(descriptor) => match self.i.mnemonic {
+                        Mnemonic::VERR => descriptor.is_segment() && descriptor.is_readable(),
+                        _ => descriptor.is_writable(),
+                    },
+                    None => false,
+                };
+                self.set_flag_state(Flag::Zero, accessible);
+            }
+            Mnemonic::LAR | Mnemonic::LSL => {
+                let selector = Selector(
+                    self.read_operand16(self.i.operand2_type, self.i.segment_override)
+                        .unwrap(),
+                );
+                // LAR accepts any segment or gate, LSL only segments and LDT/TSS descriptors.
+                let value = match self.visible_descriptor(selector) {
+                    Some(descriptor) => match (self.i.mnemonic, descriptor.system_type()) {
+                        (_, Some(SystemDescriptorType::Invalid)) => None,
+                        (Mnemonic::LAR, _) => Some((descriptor.access as u16) << 8),
+                        (
+                            _,
+                            None
+                            | Some(
+                                SystemDescriptorType::AvailableTss
+                                | SystemDescriptorType::Ldt
+                                | SystemDescriptorType::BusyTss,
+                            ),
+                        ) => Some(descriptor.limit),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                match value {
+                    Some(value) => {
+                        self.set_flag(Flag::Zero);
+                        self.write_operand16(self.i.operand1_type, SegmentOverride::None, value, ReadWriteFlag::RNI);
+                    }
+                    None => self.clear_flag(Flag::Zero),
+                }
+            }
+            Mnemonic::SGDT | Mnemonic::SIDT | Mnemonic::LGDT | Mnemonic::LIDT => {
+                let (segment, offset) = match self.memory_operand(self.i.operand1_type) {
+                    Some(operand) => operand,
+                    None => {
+                        self.raise_exception_286(EXCEPTION_INVALID_OPCODE);
+                        *jump = true;
+                        return true;
+                    }
+                };
+                match self.i.mnemonic {
+                    Mnemonic::SGDT | Mnemonic::SIDT => {
+                        let bytes = match self.i.mnemonic {
+                            Mnemonic::SGDT => self.gdtr.to_bytes(),
+                            _ => self.idtr.to_bytes(),
+                        };
+                        for (i, word) in bytes.chunks_exact(2).enumerate() {
+                            let word = u16::from_le_bytes([word[0], word[1]]);
+                            self.biu_write_u16(segment, offset.wrapping_add(i as u16 * 2), word, ReadWriteFlag::Normal);
+                        }
+                    }
+                    _ => {
+                        let mut bytes = [0u8; 6];
+                        for i in 0..3 {
+                            let word =
+                                self.biu_read_u16(segment, offset.wrapping_add(i as u16 * 2), ReadWriteFlag::Normal);
+                            bytes[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+                        }
+                        let table = DescriptorTableRegister::from_bytes(&bytes);
+                        match self.i.mnemonic {
+                            Mnemonic::LGDT => self.gdtr = table,
+                            _ => self.idtr = table,
+                        }
+                    }
+                }
+            }
+            Mnemonic::SMSW => {
+                self.write_operand16(
+                    self.i.operand1_type,
+                    self.i.segment_override,
+                    self.msw(),
+                    ReadWriteFlag::RNI,
+                );
+            }
+            Mnemonic::LMSW => {
+                let value = self
+                    .read_operand16(self.i.operand1_type, self.i.segment_override)
+                    .unwrap();
+                self.lmsw(value);
+            }
+            Mnemonic::CLTS => {
+                self.msw &= !MSW_TS;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu_808x::*, cpu_common::CpuType};
+
+    /// Load a program at 0000:0100 and run it until execution reaches `end`.
+    fn run_program(cpu: &mut Cpu, program: &[u8], end: usize) {
+        cpu.bus_mut().copy_from(program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(end);
+
+        for _ in 0..1000 {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                return;
+            }
+            cpu.step_finish().unwrap();
+        }
+        panic!("program did not reach {:05X}", end);
+    }
+
+    #[test]
+    fn test_real_mode_instructions() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x04,       // MOV SP, 0400h
+            0xB8, 0x34, 0x12,       // MOV AX, 1234h
+            0xBB, 0x78, 0x56,       // MOV BX, 5678h
+            0x60,                   // PUSHA
+            0x31, 0xC0,             // XOR AX, AX
+            0x31, 0xDB,             // XOR BX, BX
+            0x61,                   // POPA
+            0x6A, 0xFE,             // PUSH -2
+            0x59,                   // POP CX
+            0x6B, 0xD0, 0x03,       // IMUL DX, AX, 3
+            0xC1, 0xE3, 0x04,       // SHL BX, 4
+            0xC8, 0x04, 0x00, 0x00, // ENTER 4, 0
+            0x89, 0xE6,             // MOV SI, SP
+            0xC9,                   // LEAVE
+            0x0F, 0x01, 0xE7,       // SMSW DI
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel80286);
+        run_program(&mut cpu, &program, 0x100 + program.len());
+
+        assert_eq!(cpu.get_register16(Register16::AX), 0x1234);
+        assert_eq!(cpu.get_register16(Register16::CX), 0xFFFE);
+        assert_eq!(cpu.get_register16(Register16::DX), 0x369C);
+        assert_eq!(cpu.get_register16(Register16::BX), 0x6780);
+        // ENTER pushed BP and reserved 4 bytes; LEAVE released both.
+        assert_eq!(cpu.get_register16(Register16::SI), 0x03FA);
+        assert_eq!(cpu.get_register16(Register16::SP), 0x0400);
+        assert_eq!(cpu.get_register16(Register16::BP), 0x0000);
+        // The reserved MSW bits read as set, and PE is clear after reset.
+        assert_eq!(cpu.get_register16(Register16::DI), 0xFFF0);
+        assert!(!cpu.is_protected_mode());
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x04, // MOV SP, 0400h
+            0x64,             // Undefined on the 80286
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel80286);
+        // Point the invalid opcode vector at 0000:0200.
+        cpu.bus_mut().copy_from(&[0x00, 0x02, 0x00, 0x00], 0x18, 0, false).unwrap();
+        run_program(&mut cpu, &program, 0x200);
+
+        // Unlike a software interrupt, the return address is that of the faulting instruction.
+        assert_eq!(cpu.get_register16(Register16::SP), 0x03FA);
+        let (ret_ip, _) = cpu.bus_mut().read_u16(0x3FA, 0).unwrap();
+        assert_eq!(ret_ip, 0x0103);
+    }
+
+    #[test]
+    fn test_protected_mode() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x07,                         // MOV SP, 0700h
+            0x0F, 0x01, 0x16, 0x00, 0x08,             // LGDT [0800h]
+            0x0F, 0x01, 0x1E, 0x06, 0x08,             // LIDT [0806h]
+            0xB8, 0x01, 0x00,                         // MOV AX, 1
+            0x0F, 0x01, 0xF0,                         // LMSW AX
+            0xEA, 0x18, 0x01, 0x08, 0x00,             // JMP 0008:0118
+            0xB8, 0x10, 0x00,                         // MOV AX, 0010h
+            0x8E, 0xD8,                               // MOV DS, AX
+            0xC7, 0x06, 0x10, 0x00, 0x55, 0xAA,       // MOV WORD [0010h], 0AA55h
+            0xB8, 0x18, 0x00,                         // MOV AX, 0018h
+            0x8E, 0xC0,                               // MOV ES, AX
+            0x26, 0xC7, 0x06, 0x20, 0x00, 0x34, 0x12, // MOV WORD ES:[0020h], 1234h
+            0xCD, 0x21,                               // INT 21h
+            0x0F, 0x01, 0xE3,                         // SMSW BX
+            0x8C, 0xC9,                               // MOV CX, CS
+        ];
+        #[rustfmt::skip]
+        let tables = [
+            // GDTR and IDTR images
+            0x1F, 0x00, 0x10, 0x08, 0x00, 0x00,
+            0x0F, 0x01, 0x00, 0x09, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            // GDT: null, code at 0, data at 3MB and data at 1MB
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0xFF, 0x00, 0x00, 0x00, 0x9A, 0x00, 0x00,
+            0xFF, 0xFF, 0x00, 0x00, 0x30, 0x92, 0x00, 0x00,
+            0xFF, 0xFF, 0x00, 0x00, 0x10, 0x92, 0x00, 0x00,
+        ];
+        // Interrupt gate for INT 21h to 0008:0200, and its handler.
+        let gate = [0x00, 0x02, 0x08, 0x00, 0x00, 0x86, 0x00, 0x00];
+        let handler = [0xBA, 0xEF, 0xBE, 0xCF]; // MOV DX, 0BEEFh; IRET
+
+        let mut cpu = test_cpu(CpuType::Intel80286);
+        cpu.bus_mut().copy_from(&tables, 0x800, 0, false).unwrap();
+        cpu.bus_mut().copy_from(&gate, 0x900 + 0x21 * 8, 0, false).unwrap();
+        cpu.bus_mut().copy_from(&handler, 0x200, 0, false).unwrap();
+        run_program(&mut cpu, &program, 0x100 + program.len());
+
+        assert!(cpu.is_protected_mode());
+        assert_eq!(cpu.get_register16(Register16::BX) & 0x0001, 0x0001);
+        assert_eq!(cpu.get_register16(Register16::CX), 0x0008);
+        assert_eq!(cpu.get_register16(Register16::DS), 0x0010);
+        assert_eq!(cpu.get_register16(Register16::DX), 0xBEEF);
+        assert_eq!(cpu.get_register16(Register16::SP), 0x0700);
+        // Loading DS marked its descriptor accessed.
+        assert_eq!(cpu.bus_mut().peek_u8(0x825).unwrap(), 0x93);
+        // The 3MB segment is unpopulated, and must not alias low memory. With the A20 gate
+        // closed, the 1MB segment wraps to 0.
+        assert_ne!(cpu.bus_mut().read_u16(0x10, 0).unwrap().0, 0xAA55);
+        assert_eq!(cpu.bus_mut().read_u16(0x20, 0).unwrap().0, 0x1234);
+    }
+}
@@ -0,0 +1,307 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_286::mod.rs
+
+    Implements the architectural state that is specific to the Intel 80286:
+    the Machine Status Word, segment selectors, and the descriptor formats
+    and protection rules used in protected mode.
+
+    The 80286 executes the 8086 instruction set in real mode, so the 808x
+    execution core runs both CPU types. This module holds the parts of the
+    80286 that have no 8088 equivalent; execute.rs extends the 808x core
+    with the 80286's instructions and protected mode transfers.
+
+*/
+
+pub mod descriptor;
+mod execute;
+
+use std::fmt::Display;
+
+pub use descriptor::*;
+
+/// Protection Enable. Once set by LMSW, only a reset returns the 80286 to real mode.
+pub const MSW_PE: u16 = 0b0000_0000_0000_0001;
+/// Monitor Processor Extension.
+pub const MSW_MP: u16 = 0b0000_0000_0000_0010;
+/// Emulate Processor Extension.
+pub const MSW_EM: u16 = 0b0000_0000_0000_0100;
+/// Task Switched.
+pub const MSW_TS: u16 = 0b0000_0000_0000_1000;
+/// The bits of the MSW that LMSW can modify.
+pub const MSW_MASK: u16 = MSW_PE | MSW_MP | MSW_EM | MSW_TS;
+/// The undefined upper bits of the MSW read back as set.
+pub const MSW_RESERVED: u16 = !MSW_MASK;
+
+/// The IOPL and NT flag bits. These only exist in protected mode; in real mode the 80286 keeps
+/// bits 12-15 of the flags register clear.
+pub const FLAGS_IOPL_NT_MASK: u16 = FLAGS_IOPL | FLAGS_NT;
+pub const FLAGS_IOPL: u16 = 0b0011_0000_0000_0000;
+pub const FLAGS_NT: u16 = 0b0100_0000_0000_0000;
+
+pub const EXCEPTION_BOUND: u8 = 5;
+pub const EXCEPTION_INVALID_OPCODE: u8 = 6;
+pub const EXCEPTION_NO_COPROCESSOR: u8 = 7;
+pub const EXCEPTION_DOUBLE_FAULT: u8 = 8;
+pub const EXCEPTION_INVALID_TSS: u8 = 10;
+pub const EXCEPTION_NOT_PRESENT: u8 = 11;
+pub const EXCEPTION_STACK: u8 = 12;
+pub const EXCEPTION_GENERAL_PROTECTION: u8 = 13;
+
+/// A protection fault raised while validating a selector or descriptor. Each variant carries the
+/// error code pushed onto the stack when the fault is delivered.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProtectionFault {
+    InvalidTss(u16),
+    NotPresent(u16),
+    Stack(u16),
+    GeneralProtection(u16),
+}
+
+impl ProtectionFault {
+    pub fn vector(&self) -> u8 {
+        match self {
+            ProtectionFault::InvalidTss(_) => EXCEPTION_INVALID_TSS,
+            ProtectionFault::NotPresent(_) => EXCEPTION_NOT_PRESENT,
+            ProtectionFault::Stack(_) => EXCEPTION_STACK,
+            ProtectionFault::GeneralProtection(_) => EXCEPTION_GENERAL_PROTECTION,
+        }
+    }
+
+    pub fn error_code(&self) -> u16 {
+        match *self {
+            ProtectionFault::InvalidTss(code)
+            | ProtectionFault::NotPresent(code)
+            | ProtectionFault::Stack(code)
+            | ProtectionFault::GeneralProtection(code) => code,
+        }
+    }
+}
+
+impl Display for ProtectionFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProtectionFault::InvalidTss(_) => "#TS",
+            ProtectionFault::NotPresent(_) => "#NP",
+            ProtectionFault::Stack(_) => "#SS",
+            ProtectionFault::GeneralProtection(_) => "#GP",
+        };
+        write!(f, "{}({:04X})", name, self.error_code())
+    }
+}
+
+/// A segment selector, as loaded into a segment register in protected mode.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Selector(pub u16);
+
+impl Selector {
+    /// Index of the descriptor within its table.
+    pub fn index(&self) -> u16 {
+        self.0 >> 3
+    }
+
+    /// Byte offset of the descriptor within its table.
+    pub fn table_offset(&self) -> u16 {
+        self.0 & !0x07
+    }
+
+    /// Returns true if the selector refers to the LDT instead of the GDT.
+    pub fn is_local(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    /// Requested privilege level.
+    pub fn rpl(&self) -> u8 {
+        (self.0 & 0x03) as u8
+    }
+
+    /// A null selector refers to the first GDT entry, which can never be used.
+    pub fn is_null(&self) -> bool {
+        self.0 & !0x03 == 0
+    }
+
+    /// The error code pushed by a fault on this selector.
+    pub fn error_code(&self) -> u16 {
+        self.0 & !0x03
+    }
+}
+
+/// The kind of segment register a selector is being loaded into. The 80286 applies different
+/// rules to each.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SegmentLoad {
+    Code,
+    Stack,
+    Data,
+}
+
+/// Check whether a descriptor may be loaded into a segment register at the current privilege
+/// level. Control transfers that change privilege level go through gates, which are not
+/// accepted here.
+pub fn check_segment_load(
+    load: SegmentLoad,
+    selector: Selector,
+    descriptor: &SegmentDescriptor,
+    cpl: u8,
+) -> Result<(), ProtectionFault> {
+    let code = selector.error_code();
+    let rpl = selector.rpl();
+    let dpl = descriptor.dpl();
+
+    match load {
+        SegmentLoad::Data => {
+            // A null selector may be loaded into DS or ES. Using it later is what faults.
+            if selector.is_null() {
+                return Ok(());
+            }
+            if !descriptor.is_segment() || (descriptor.is_code() && !descriptor.is_readable()) {
+                return Err(ProtectionFault::GeneralProtection(code));
+            }
+            if (descriptor.is_data() || !descriptor.is_conforming()) && cpl.max(rpl) > dpl {
+                return Err(ProtectionFault::GeneralProtection(code));
+            }
+            if !descriptor.is_present() {
+                return Err(ProtectionFault::NotPresent(code));
+            }
+        }
+        SegmentLoad::Stack => {
+            if selector.is_null() {
+                return Err(ProtectionFault::GeneralProtection(0));
+            }
+            if rpl != cpl || dpl != cpl || !descriptor.is_data() || !descriptor.is_writable() {
+                return Err(ProtectionFault::GeneralProtection(code));
+            }
+            if !descriptor.is_present() {
+                return Err(ProtectionFault::Stack(code));
+            }
+        }
+        SegmentLoad::Code => {
+            if selector.is_null() {
+                return Err(ProtectionFault::GeneralProtection(0));
+            }
+            if !descriptor.is_code() {
+                return Err(ProtectionFault::GeneralProtection(code));
+            }
+            let privilege_ok = match descriptor.is_conforming() {
+                true => dpl <= cpl,
+                false => rpl <= cpl && dpl == cpl,
+            };
+            if !privilege_ok {
+                return Err(ProtectionFault::GeneralProtection(code));
+            }
+            if !descriptor.is_present() {
+                return Err(ProtectionFault::NotPresent(code));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_segment(dpl: u8) -> SegmentDescriptor {
+        SegmentDescriptor {
+            base:   0x1000,
+            limit:  0xFFFF,
+            access: ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_WRITABLE | (dpl << 5),
+        }
+    }
+
+    fn code_segment(dpl: u8, conforming: bool) -> SegmentDescriptor {
+        let mut access = ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_EXECUTABLE | ACCESS_READABLE | (dpl << 5);
+        if conforming {
+            access |= ACCESS_CONFORMING;
+        }
+        SegmentDescriptor {
+            base: 0x2000,
+            limit: 0xFFFF,
+            access,
+        }
+    }
+
+    #[test]
+    fn test_selector_fields() {
+        let selector = Selector(0x002F);
+        assert_eq!(selector.index(), 5);
+        assert_eq!(selector.table_offset(), 0x0028);
+        assert!(selector.is_local());
+        assert_eq!(selector.rpl(), 3);
+        assert_eq!(selector.error_code(), 0x002C);
+        assert!(Selector(0x0003).is_null());
+        assert!(!Selector(0x0004).is_null());
+    }
+
+    #[test]
+    fn test_segment_load_rules() {
+        let cpl = 0;
+        assert!(check_segment_load(SegmentLoad::Data, Selector(0x0000), &SegmentDescriptor::default(), cpl).is_ok());
+        assert_eq!(
+            check_segment_load(SegmentLoad::Stack, Selector(0x0000), &SegmentDescriptor::default(), cpl),
+            Err(ProtectionFault::GeneralProtection(0))
+        );
+        assert!(check_segment_load(SegmentLoad::Data, Selector(0x0008), &data_segment(0), cpl).is_ok());
+        assert!(check_segment_load(SegmentLoad::Stack, Selector(0x0008), &data_segment(0), cpl).is_ok());
+
+        // A code segment can't be used as a stack, and an execute-only one can't be used for data.
+        let code = code_segment(0, false);
+        assert_eq!(
+            check_segment_load(SegmentLoad::Stack, Selector(0x0010), &code, cpl),
+            Err(ProtectionFault::GeneralProtection(0x0010))
+        );
+        let execute_only = SegmentDescriptor {
+            access: code.access & !ACCESS_READABLE,
+            ..code
+        };
+        assert!(check_segment_load(SegmentLoad::Data, Selector(0x0010), &execute_only, cpl).is_err());
+        assert!(check_segment_load(SegmentLoad::Code, Selector(0x0010), &code, cpl).is_ok());
+
+        // Data at a more privileged level can't be loaded from ring 3.
+        assert_eq!(
+            check_segment_load(SegmentLoad::Data, Selector(0x000B), &data_segment(0), 3),
+            Err(ProtectionFault::GeneralProtection(0x0008))
+        );
+        // Conforming code may be entered from a less privileged level.
+        assert!(check_segment_load(SegmentLoad::Code, Selector(0x0013), &code_segment(0, true), 3).is_ok());
+        assert!(check_segment_load(SegmentLoad::Code, Selector(0x0013), &code_segment(0, false), 3).is_err());
+
+        // Not-present segments raise #NP, or #SS for the stack segment.
+        let absent = SegmentDescriptor {
+            access: data_segment(0).access & !ACCESS_PRESENT,
+            ..data_segment(0)
+        };
+        assert_eq!(
+            check_segment_load(SegmentLoad::Data, Selector(0x0018), &absent, cpl),
+            Err(ProtectionFault::NotPresent(0x0018))
+        );
+        assert_eq!(
+            check_segment_load(SegmentLoad::Stack, Selector(0x0018), &absent, cpl),
+            Err(ProtectionFault::Stack(0x0018))
+        );
+    }
+}
@@ -87,14 +87,17 @@ impl Cpu {
 
     #[inline]
     pub fn calc_linear_address_seg(&self, segment: Segment, offset: u16) -> u32 {
-        let segment_val: u16 = match segment {
-            Segment::None => 0,
-            Segment::ES => self.es,
-            Segment::CS => self.cs,
-            Segment::DS => self.ds,
-            Segment::SS => self.ss,
-        };
-        (((segment_val as u32) << 4) + offset as u32) & 0xFFFFFu32
+        self.wrap_linear_address(self.segment_base(segment) + offset as u32)
+    }
+
+    /// Apply the address wrap of the CPU's address bus: 20 bits for the 8088 and 8086, or 24 bits
+    /// behind the A20 gate for the 80286.
+    #[inline]
+    pub fn wrap_linear_address(&self, address: u32) -> u32 {
+        match self.cpu_type {
+            CpuType::Intel80286 => self.bus.gate_a20(address),
+            _ => self.bus.wrap_address(address),
+        }
     }
 
     pub fn segment_override(seg_override: SegmentOverride, seg_default: Segment) -> Segment {
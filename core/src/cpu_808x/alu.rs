@@ -70,26 +70,137 @@ impl Cpu {
             self.set_parity_flag(result);
         }
     */
+    /// Set the Sign, Zero and Parity flags from an 8-bit result. Evaluation of the flags is deferred
+    /// until they are read; see resolved_flags().
+    #[inline(always)]
     pub fn set_szp_flags_from_result_u8(&mut self, result: u8) {
-        // Set Sign flag to state of Sign (HO) bit
-        self.set_flag_state(Flag::Sign, result & 0x80 != 0);
+        self.defer_flags(LazyFlags::Szp, false, 0, 0, result as u16);
+    }
 
-        // Set Zero flag if result is 0, clear it if not
-        self.set_flag_state(Flag::Zero, result == 0);
+    /// Set the Sign, Zero and Parity flags from a 16-bit result. Evaluation of the flags is deferred
+    /// until they are read; see resolved_flags().
+    #[inline(always)]
+    pub fn set_szp_flags_from_result_u16(&mut self, result: u16) {
+        self.defer_flags(LazyFlags::Szp, true, 0, 0, result);
+    }
 
-        // Set Parity Flag
-        self.set_parity_flag_from_u8(result);
+    /// Set the Sign, Zero, Parity, Aux Carry and Overflow flags from the operands and result of an
+    /// 8-bit addition. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_add_flags_u8(&mut self, operand1: u8, operand2: u8, result: u8) {
+        self.defer_flags(LazyFlags::Add, false, operand1 as u16, operand2 as u16, result as u16);
     }
 
-    pub fn set_szp_flags_from_result_u16(&mut self, result: u16) {
-        // Set Sign flag to state of Sign (HO) bit
-        self.set_flag_state(Flag::Sign, result & 0x8000 != 0);
+    /// Set the Sign, Zero, Parity, Aux Carry and Overflow flags from the operands and result of a
+    /// 16-bit addition. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_add_flags_u16(&mut self, operand1: u16, operand2: u16, result: u16) {
+        self.defer_flags(LazyFlags::Add, true, operand1, operand2, result);
+    }
+
+    /// Set the Sign, Zero, Parity, Aux Carry and Overflow flags from the operands and result of an
+    /// 8-bit subtraction. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_sub_flags_u8(&mut self, operand1: u8, operand2: u8, result: u8) {
+        self.defer_flags(LazyFlags::Sub, false, operand1 as u16, operand2 as u16, result as u16);
+    }
+
+    /// Set the Sign, Zero, Parity, Aux Carry and Overflow flags from the operands and result of a
+    /// 16-bit subtraction. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_sub_flags_u16(&mut self, operand1: u16, operand2: u16, result: u16) {
+        self.defer_flags(LazyFlags::Sub, true, operand1, operand2, result);
+    }
+
+    /// Set the Sign, Zero and Parity flags from the 8-bit result of a logical operation, and clear
+    /// the Overflow flag. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_logic_flags_u8(&mut self, result: u8) {
+        self.defer_flags(LazyFlags::Logic, false, 0, 0, result as u16);
+    }
+
+    /// Set the Sign, Zero and Parity flags from the 16-bit result of a logical operation, and clear
+    /// the Overflow flag. Evaluation of the flags is deferred until they are read.
+    #[inline(always)]
+    pub fn set_logic_flags_u16(&mut self, result: u16) {
+        self.defer_flags(LazyFlags::Logic, true, 0, 0, result);
+    }
 
-        // Set Zero flag if result is 0, clear it if not
-        self.set_flag_state(Flag::Zero, result == 0);
+    /// Record the operands and result of an ALU operation so that the flags it sets can be
+    /// evaluated when they are read. Any pending flags the new operation does not set are
+    /// committed first.
+    #[inline(always)]
+    fn defer_flags(&mut self, kind: LazyFlags, word: bool, operand1: u16, operand2: u16, result: u16) {
+        match (self.lazy_flags, kind) {
+            (LazyFlags::Add | LazyFlags::Sub, LazyFlags::Szp | LazyFlags::Logic)
+            | (LazyFlags::Logic, LazyFlags::Szp) => self.resolve_flags(),
+            _ => {}
+        }
+        self.lazy_flags = kind;
+        self.lazy_word = word;
+        self.lazy_operand1 = operand1;
+        self.lazy_operand2 = operand2;
+        self.lazy_result = result;
+    }
+
+    /// Return the flags register with any pending flags evaluated.
+    #[inline]
+    pub fn resolved_flags(&self) -> u16 {
+        if self.lazy_flags == LazyFlags::None {
+            return self.flags;
+        }
+
+        let sign_mask = if self.lazy_word { 0x8000 } else { 0x0080 };
+        let (op1, op2, result) = (self.lazy_operand1, self.lazy_operand2, self.lazy_result);
+        let mut flags = self.flags & !(CPU_FLAG_SIGN | CPU_FLAG_ZERO | CPU_FLAG_PARITY);
+        flags |= CPU_FLAG_SIGN * (result & sign_mask != 0) as u16;
+        flags |= CPU_FLAG_ZERO * (result == 0) as u16;
+        flags |= CPU_FLAG_PARITY * PARITY_TABLE[(result & 0xFF) as usize] as u16;
+
+        match self.lazy_flags {
+            LazyFlags::Add | LazyFlags::Sub => {
+                let overflow_bits = match self.lazy_flags {
+                    LazyFlags::Add => (op1 ^ result) & (op2 ^ result),
+                    _ => (op1 ^ op2) & (op1 ^ result),
+                };
+                flags &= !(CPU_FLAG_OVERFLOW | CPU_FLAG_AUX_CARRY);
+                flags |= CPU_FLAG_OVERFLOW * (overflow_bits & sign_mask != 0) as u16;
+                // AF is the carry out of bit 3, which lands in bit 4 - the position of AF.
+                flags |= (op1 ^ op2 ^ result) & CPU_FLAG_AUX_CARRY;
+            }
+            LazyFlags::Logic => flags &= !CPU_FLAG_OVERFLOW,
+            _ => {}
+        }
+        flags
+    }
 
-        // Set Parity Flag
-        self.set_parity_flag_from_u16(result);
+    /// Evaluate a single flag if it is pending, without evaluating the others. Returns None if
+    /// the flag register holds the flag's state.
+    #[inline(always)]
+    pub(crate) fn pending_flag(&self, flag: &Flag) -> Option<bool> {
+        let sign_mask = if self.lazy_word { 0x8000 } else { 0x0080 };
+        let (op1, op2, result) = (self.lazy_operand1, self.lazy_operand2, self.lazy_result);
+        match (flag, self.lazy_flags) {
+            (_, LazyFlags::None) => None,
+            (Flag::Sign, _) => Some(result & sign_mask != 0),
+            (Flag::Zero, _) => Some(result == 0),
+            (Flag::Parity, _) => Some(PARITY_TABLE[(result & 0xFF) as usize]),
+            (Flag::AuxCarry, LazyFlags::Add | LazyFlags::Sub) => Some((op1 ^ op2 ^ result) & 0x10 != 0),
+            (Flag::Overflow, LazyFlags::Add) => Some((op1 ^ result) & (op2 ^ result) & sign_mask != 0),
+            (Flag::Overflow, LazyFlags::Sub) => Some((op1 ^ op2) & (op1 ^ result) & sign_mask != 0),
+            (Flag::Overflow, LazyFlags::Logic) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Commit any pending flags to the flags register. This must be done before any of the
+    /// deferred flags are modified individually.
+    #[inline]
+    pub fn resolve_flags(&mut self) {
+        if self.lazy_flags != LazyFlags::None {
+            self.flags = self.resolved_flags();
+            self.lazy_flags = LazyFlags::None;
+        }
     }
 
     pub fn add_u8(byte1: u8, byte2: u8, carry_in: bool) -> (u8, bool, bool, bool) {
@@ -436,29 +547,23 @@ impl Cpu {
     pub fn math_op8(&mut self, opcode: Mnemonic, operand1: u8, operand2: u8) -> u8 {
         match opcode {
             Mnemonic::ADD => {
-                let (result, carry, overflow, aux_carry) = operand1.alu_add(operand2);
+                let (result, carry, _, _) = operand1.alu_add(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_add_flags_u8(operand1, operand2, result);
                 result
             }
             Mnemonic::ADC => {
-                let (result, carry, overflow, aux_carry) = operand1.alu_adc(operand2, self.get_flag(Flag::Carry));
+                let (result, carry, _, _) = operand1.alu_adc(operand2, self.get_flag(Flag::Carry));
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_add_flags_u8(operand1, operand2, result);
                 result
             }
             Mnemonic::SUB => {
                 //let (result, carry, overflow, aux_carry) = Cpu::sub_u8(operand1, operand2, false );
 
-                let (result, carry, overflow, aux_carry) = operand1.alu_sub(operand2);
+                let (result, carry, _, _) = operand1.alu_sub(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(operand1, operand2, result);
                 result
             }
             Mnemonic::SBB => {
@@ -467,66 +572,55 @@ impl Cpu {
                 // And pass it to SBB
                 //let (result, carry, overflow, aux_carry) = Cpu::sub_u8(operand1, operand2, carry_in );
 
-                let (result, carry, overflow, aux_carry) = operand1.alu_sbb(operand2, carry_in);
+                let (result, carry, _, _) = operand1.alu_sbb(operand2, carry_in);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(operand1, operand2, result);
                 result
             }
             Mnemonic::NEG => {
                 // Compute (0-operand)
                 // Flags: The CF flag set to 0 if the source operand is 0; otherwise it is set to 1.
                 // The OF, SF, ZF, AF, and PF flags are set according to the result.
-                let (result, _carry, overflow, aux_carry) = 0u8.alu_sub(operand1);
+                let (result, _, _, _) = 0u8.alu_sub(operand1);
 
                 self.set_flag_state(Flag::Carry, operand1 != 0);
                 // NEG Updates AF, SF, PF, ZF
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(0, operand1, result);
                 result
             }
             Mnemonic::INC => {
                 // INC acts like add xx, 1, however does not set carry flag
-                let (result, _carry, overflow, aux_carry) = operand1.alu_add(1);
+                let (result, _, _, _) = operand1.alu_add(1);
                 // DO NOT set carry Flag
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_add_flags_u8(operand1, 1, result);
                 result
             }
             Mnemonic::DEC => {
                 // DEC acts like sub xx, 1, however does not set carry flag
-                let (result, _carry, overflow, aux_carry) = operand1.alu_sub(1);
+                let (result, _, _, _) = operand1.alu_sub(1);
                 // DEC does NOT set carry Flag
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(operand1, 1, result);
                 result
             }
             Mnemonic::OR => {
                 let result = operand1 | operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_logic_flags_u8(result);
                 result
             }
             Mnemonic::AND => {
                 let result = operand1 & operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_logic_flags_u8(result);
                 result
             }
             Mnemonic::TEST => {
                 let result = operand1 & operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_logic_flags_u8(result);
                 // TEST does not modify operand1
                 operand1
             }
@@ -534,8 +628,7 @@ impl Cpu {
                 let result = operand1 ^ operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_logic_flags_u8(result);
                 result
             }
             Mnemonic::NOT => {
@@ -545,11 +638,9 @@ impl Cpu {
             }
             Mnemonic::CMP => {
                 // CMP behaves like SUB except we do not store the result
-                let (result, carry, overflow, aux_carry) = operand1.alu_sub(operand2);
+                let (result, carry, _, _) = operand1.alu_sub(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(operand1, operand2, result);
                 // Return the operand1 unchanged
                 operand1
             }
@@ -561,28 +652,22 @@ impl Cpu {
     pub fn math_op16(&mut self, opcode: Mnemonic, operand1: u16, operand2: u16) -> u16 {
         match opcode {
             Mnemonic::ADD => {
-                let (result, carry, overflow, aux_carry) = operand1.alu_add(operand2);
+                let (result, carry, _, _) = operand1.alu_add(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_add_flags_u16(operand1, operand2, result);
                 result
             }
             Mnemonic::ADC => {
-                let (result, carry, overflow, aux_carry) = operand1.alu_adc(operand2, self.get_flag(Flag::Carry));
+                let (result, carry, _, _) = operand1.alu_adc(operand2, self.get_flag(Flag::Carry));
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_add_flags_u16(operand1, operand2, result);
                 result
             }
             Mnemonic::SUB => {
                 //let (result, carry, overflow, aux_carry) = Cpu::sub_u16(operand1, operand2, false );
-                let (result, carry, overflow, aux_carry) = operand1.alu_sub(operand2);
+                let (result, carry, _, _) = operand1.alu_sub(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(operand1, operand2, result);
                 result
             }
             Mnemonic::SBB => {
@@ -590,63 +675,52 @@ impl Cpu {
                 let carry_in = self.get_flag(Flag::Carry);
                 // And pass it to SBB
                 //let (result, carry, overflow, aux_carry) = Cpu::sub_u16(operand1, operand2, carry_in );
-                let (result, carry, overflow, aux_carry) = operand1.alu_sbb(operand2, carry_in);
+                let (result, carry, _, _) = operand1.alu_sbb(operand2, carry_in);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(operand1, operand2, result);
                 result
             }
             Mnemonic::NEG => {
                 // Compute (0-operand)
                 // Flags: The CF flag set to 0 if the source operand is 0; otherwise it is set to 1.
                 // The OF, SF, ZF, AF, and PF flags are set according to the result.
-                let (result, _carry, overflow, aux_carry) = 0u16.alu_sub(operand1);
+                let (result, _, _, _) = 0u16.alu_sub(operand1);
 
                 self.set_flag_state(Flag::Carry, operand1 != 0);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(0, operand1, result);
                 result
             }
             Mnemonic::INC => {
                 // INC acts like add xx, 1, however does not set carry flag
-                let (result, _carry, overflow, aux_carry) = operand1.alu_add(1);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                let (result, _, _, _) = operand1.alu_add(1);
+                self.set_add_flags_u16(operand1, 1, result);
                 result
             }
             Mnemonic::DEC => {
                 // DEC acts like sub xx, 1, however does not set carry flag
-                let (result, _carry, overflow, aux_carry) = operand1.alu_sub(1);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                let (result, _, _, _) = operand1.alu_sub(1);
+                self.set_sub_flags_u16(operand1, 1, result);
                 result
             }
             Mnemonic::OR => {
                 let result = operand1 | operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_logic_flags_u16(result);
                 result
             }
             Mnemonic::AND => {
                 let result = operand1 & operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_logic_flags_u16(result);
                 result
             }
             Mnemonic::TEST => {
                 let result = operand1 & operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_logic_flags_u16(result);
                 // Do not modify operand
                 operand1
             }
@@ -654,8 +728,7 @@ impl Cpu {
                 let result = operand1 ^ operand2;
                 // Clear carry, overflow
                 self.clear_flag(Flag::Carry);
-                self.clear_flag(Flag::Overflow);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_logic_flags_u16(result);
                 result
             }
             Mnemonic::NOT => {
@@ -665,11 +738,9 @@ impl Cpu {
             }
             Mnemonic::CMP => {
                 // CMP behaves like SUB except we do not store the result
-                let (result, carry, overflow, aux_carry) = operand1.alu_sub(operand2);
+                let (result, carry, _, _) = operand1.alu_sub(operand2);
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(operand1, operand2, result);
                 // Return the operand1 unchanged
                 operand1
             }
@@ -709,4 +780,104 @@ mod tests {
         assert_eq!(cpu.dx, 1); // dx will contain overflow from ax @ 65536
         */
     }
+
+    #[test]
+    fn test_lazy_szp_flags() {
+        let mut cpu = Cpu::default();
+
+        for value in 0..=0xFFFFu16 {
+            cpu.set_szp_flags_from_result_u16(value);
+            assert_eq!(cpu.get_flag(Flag::Sign), value & 0x8000 != 0);
+            assert_eq!(cpu.get_flag(Flag::Zero), value == 0);
+            assert_eq!(cpu.get_flag(Flag::Parity), PARITY_TABLE[(value & 0xFF) as usize]);
+        }
+
+        // An explicit flag update after a deferred result must not be overwritten by it.
+        cpu.set_szp_flags_from_result_u8(0);
+        cpu.clear_flag(Flag::Zero);
+        cpu.set_flag(Flag::Carry);
+        assert!(!cpu.get_flag(Flag::Zero));
+        assert!(cpu.get_flag(Flag::Parity));
+        assert_eq!(cpu.get_flags() & CPU_FLAG_ZERO, 0);
+
+        // Setting the flags register directly discards a deferred result.
+        cpu.set_szp_flags_from_result_u8(0x80);
+        cpu.set_flags(0);
+        assert!(!cpu.get_flag(Flag::Sign));
+    }
+
+    #[test]
+    fn test_lazy_arith_flags() {
+        let mut cpu = Cpu::default();
+        let arith_mask =
+            CPU_FLAG_CARRY | CPU_FLAG_PARITY | CPU_FLAG_AUX_CARRY | CPU_FLAG_ZERO | CPU_FLAG_SIGN | CPU_FLAG_OVERFLOW;
+        let assert_flags = |cpu: &Cpu, expected: u16| {
+            assert_eq!(cpu.get_flags() & arith_mask, expected);
+            assert_eq!(cpu.get_flag(Flag::Parity), expected & CPU_FLAG_PARITY != 0);
+            assert_eq!(cpu.get_flag(Flag::AuxCarry), expected & CPU_FLAG_AUX_CARRY != 0);
+            assert_eq!(cpu.get_flag(Flag::Zero), expected & CPU_FLAG_ZERO != 0);
+            assert_eq!(cpu.get_flag(Flag::Sign), expected & CPU_FLAG_SIGN != 0);
+            assert_eq!(cpu.get_flag(Flag::Overflow), expected & CPU_FLAG_OVERFLOW != 0);
+        };
+
+        // Deferred flags, whether read individually or together, match those computed eagerly by
+        // the ALU helpers.
+        let expected = |result: u8, carry: bool, overflow: bool, aux_carry: bool| {
+            let mut flags = 0;
+            for (state, flag) in [
+                (carry, CPU_FLAG_CARRY),
+                (PARITY_TABLE[result as usize], CPU_FLAG_PARITY),
+                (aux_carry, CPU_FLAG_AUX_CARRY),
+                (result == 0, CPU_FLAG_ZERO),
+                (result & 0x80 != 0, CPU_FLAG_SIGN),
+                (overflow, CPU_FLAG_OVERFLOW),
+            ] {
+                if state {
+                    flags |= flag;
+                }
+            }
+            flags
+        };
+        for a in 0..=0xFFu8 {
+            for b in 0..=0xFFu8 {
+                for carry_in in [false, true] {
+                    cpu.set_flag_state(Flag::Carry, carry_in);
+                    cpu.math_op8(Mnemonic::ADC, a, b);
+                    let (result, carry, overflow, aux_carry) = a.alu_adc(b, carry_in);
+                    assert_flags(&cpu, expected(result, carry, overflow, aux_carry));
+
+                    cpu.set_flag_state(Flag::Carry, carry_in);
+                    cpu.math_op8(Mnemonic::SBB, a, b);
+                    let (result, carry, overflow, aux_carry) = a.alu_sbb(b, carry_in);
+                    assert_flags(&cpu, expected(result, carry, overflow, aux_carry));
+                }
+            }
+        }
+
+        // A logical operation leaves AF as set by a pending addition, and clears OF.
+        cpu.math_op16(Mnemonic::ADD, 0x7FFF, 0x0001);
+        cpu.math_op16(Mnemonic::OR, 0x0000, 0x0000);
+        assert!(cpu.get_flag(Flag::AuxCarry));
+        assert!(!cpu.get_flag(Flag::Overflow));
+        assert!(cpu.get_flag(Flag::Zero));
+
+        // A shift leaves OF as set by a pending logical operation, and AF as set before it.
+        cpu.math_op8(Mnemonic::SUB, 0x80, 0x01);
+        cpu.math_op8(Mnemonic::AND, 0xFF, 0x80);
+        cpu.set_szp_flags_from_result_u8(0);
+        assert!(!cpu.get_flag(Flag::Overflow));
+        assert!(cpu.get_flag(Flag::AuxCarry));
+
+        // SAHF does not replace a pending OF.
+        cpu.math_op8(Mnemonic::ADD, 0x7F, 0x01);
+        cpu.store_flags(0);
+        assert!(cpu.get_flag(Flag::Overflow));
+        assert!(!cpu.get_flag(Flag::AuxCarry));
+
+        // An explicit update of a deferred flag is not overwritten by it.
+        cpu.math_op8(Mnemonic::ADD, 0x0F, 0x01);
+        cpu.clear_flag(Flag::AuxCarry);
+        assert!(!cpu.get_flag(Flag::AuxCarry));
+        assert_eq!(cpu.get_flags() & CPU_FLAG_AUX_CARRY, 0);
+    }
 }
@@ -123,20 +123,20 @@ impl Cpu {
     }
 
     /// Ascii adjust before Divison
-    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
+    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register.
+    /// The OF, AF and CF flags are documented as undefined, but on the 8088 the final addition is performed
+    /// by the ALU and they are set as for ADD AL, (AH * imm8). This holds for any immediate, not just 10.
     pub fn aad(&mut self, imm8: u8) {
         self.cycles_i(3, &[0x170, 0x171, MC_JUMP]);
         let product_native = (self.ah as u16).wrapping_mul(imm8 as u16) as u8;
         let (_, product) = 0u8.corx(self, self.ah as u16, imm8 as u16, false);
         assert!((product as u8) == product_native);
 
-        self.set_register8(Register8::AL, self.al.wrapping_add(product as u8));
+        let result = self.math_op8(Mnemonic::ADD, self.al, product as u8);
+        self.set_register8(Register8::AL, result);
         self.set_register8(Register8::AH, 0);
 
         self.cycles_i(2, &[0x172, 0x173]);
-
-        // Other sources set flags from AX register. Intel's documentation specifies AL
-        self.set_szp_flags_from_result_u8(self.al);
     }
 
     /// DAA — Decimal Adjust AL after Addition
@@ -244,25 +244,96 @@ impl Cpu {
     }
 
     /// AAM - Ascii adjust AX After multiply
-    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
+    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register.
+    /// The OF, AF and CF flags are documented as undefined; on the 8088 they are cleared, as the flags
+    /// are set by passing AL through the ALU. Any immediate divisor may be used, not just 10.
     /// As AAM is implemented via CORD, it can throw an exception. This is indicated by a return value
-    /// of false.
+    /// of false. A divisor of 0 is the only way to fault, and leaves the flags of CORD's initial
+    /// 0 - 0 subtraction, as the divide error path of DIV does.
     pub fn aam(&mut self, imm8: u8) -> bool {
         self.cycles_i(3, &[0x175, 0x176, MC_JUMP]);
         // 176: A->tmpc   | UNC CORD
         // Jump delay
 
         match 0u8.cord(self, 0, imm8 as u16, self.al as u16) {
-            Ok((quotient, remainder, _)) => {
+            Ok((quotient, remainder, _, _)) => {
                 // 177:          | COM1 tmpc
                 self.set_register8(Register8::AH, !(quotient as u8));
                 self.set_register8(Register8::AL, remainder as u8);
                 self.cycle_i(0x177);
                 // Other sources set flags from AX register. Intel's documentation specifies AL
                 self.set_szp_flags_from_result_u8(self.al);
-                return true;
+                self.clear_flag(Flag::Overflow);
+                self.clear_flag(Flag::AuxCarry);
+                self.clear_flag(Flag::Carry);
+                true
+            }
+            Err(flags) => {
+                self.set_mc_flags(flags);
+                false
             }
-            Err(_) => return false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_808x::*;
+
+    #[test]
+    fn test_aad_flags() {
+        let mut cpu = test_cpu(CpuType::Intel8088);
+
+        // 0x7F + (0x01 * 0x01) overflows into the sign bit and carries out of the low nibble.
+        cpu.set_register16(Register16::AX, 0x017F);
+        cpu.aad(0x01);
+        assert_eq!(cpu.get_register16(Register16::AX), 0x0080);
+        assert!(cpu.get_flag(Flag::Overflow));
+        assert!(cpu.get_flag(Flag::AuxCarry));
+        assert!(!cpu.get_flag(Flag::Carry));
+        assert!(cpu.get_flag(Flag::Sign));
+
+        // 0xF0 + (0x02 * 0x08) carries out of AL.
+        cpu.set_register16(Register16::AX, 0x02F0);
+        cpu.aad(0x08);
+        assert_eq!(cpu.get_register16(Register16::AX), 0x0000);
+        assert!(cpu.get_flag(Flag::Carry));
+        assert!(cpu.get_flag(Flag::Zero));
+        assert!(!cpu.get_flag(Flag::Overflow));
+    }
+
+    #[test]
+    fn test_aam_immediates() {
+        let mut cpu = test_cpu(CpuType::Intel8088);
+
+        // AAM 16 splits AL into nibbles. The undefined flags are cleared.
+        cpu.set_register16(Register16::AX, 0x005A);
+        cpu.set_flag(Flag::Carry);
+        cpu.set_flag(Flag::AuxCarry);
+        cpu.set_flag(Flag::Overflow);
+        assert!(cpu.aam(0x10));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x050A);
+        assert!(!cpu.get_flag(Flag::Carry));
+        assert!(!cpu.get_flag(Flag::AuxCarry));
+        assert!(!cpu.get_flag(Flag::Overflow));
+        assert!(!cpu.get_flag(Flag::Zero));
+
+        // AAM 1 moves AL into AH, leaving a zero remainder.
+        cpu.set_register16(Register16::AX, 0x0083);
+        assert!(cpu.aam(0x01));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x8300);
+        assert!(cpu.get_flag(Flag::Zero));
+        assert!(cpu.get_flag(Flag::Parity));
+
+        // AAM 0 is a divide error, with the flags of a zero result.
+        cpu.set_register16(Register16::AX, 0x0083);
+        cpu.set_flag(Flag::Carry);
+        cpu.set_flag(Flag::Sign);
+        assert!(!cpu.aam(0x00));
+        assert_eq!(cpu.get_register16(Register16::AX), 0x0083);
+        assert!(cpu.get_flag(Flag::Zero));
+        assert!(cpu.get_flag(Flag::Parity));
+        assert!(!cpu.get_flag(Flag::Sign));
+        assert!(!cpu.get_flag(Flag::Carry));
+    }
+}
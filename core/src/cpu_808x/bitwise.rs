@@ -221,8 +221,14 @@ impl Cpu {
 
     /// Perform various 8-bit binary shift operations
     pub fn bitshift_op8(&mut self, opcode: Mnemonic, operand1: u8, operand2: u8) -> u8 {
+        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
+        let rot_count = match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
+            _ => operand2 & 0x1F,
+        };
+
         // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
+        if rot_count == 0 {
             // Flags are not changed if shift amount is 0
             return operand1;
         }
@@ -230,16 +236,6 @@ impl Cpu {
         let result: u8;
         let carry: bool;
 
-        /*
-        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
-        let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
-            _=> operand2 & 0x1F
-        };
-        */
-
-        let rot_count = operand2;
-
         match opcode {
             Mnemonic::ROL => {
                 (result, carry) = Cpu::rol_u8_with_carry(operand1, rot_count);
@@ -357,8 +353,14 @@ impl Cpu {
 
     /// Peform various 16-bit binary shift operations
     pub fn bitshift_op16(&mut self, opcode: Mnemonic, operand1: u16, operand2: u8) -> u16 {
+        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
+        let rot_count = match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
+            _ => operand2 & 0x1F,
+        };
+
         // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
+        if rot_count == 0 {
             // Flags are not changed if shift amount is 0
             return operand1;
         }
@@ -366,16 +368,6 @@ impl Cpu {
         let result: u16;
         let carry: bool;
 
-        /*
-        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
-        let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
-            _=> operand2 & 0x1F
-        };
-        */
-
-        let rot_count = operand2;
-
         match opcode {
             Mnemonic::ROL => {
                 // Rotate Left
@@ -514,6 +506,18 @@ impl Cpu {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_shift_count_masking() {
+        // The 8088 uses the full count in CL. A count of 32 shifts every bit out.
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        assert_eq!(cpu.bitshift_op16(Mnemonic::SHL, 0x1234, 0x20), 0);
+        assert_eq!(cpu.bitshift_op8(Mnemonic::ROL, 0x81, 0x21), 0x03);
+        // The 80286 masks the count to 5 bits, so a count of 32 leaves the operand unchanged.
+        let mut cpu = test_cpu(CpuType::Intel80286);
+        assert_eq!(cpu.bitshift_op16(Mnemonic::SHL, 0x1234, 0x20), 0x1234);
+        assert_eq!(cpu.bitshift_op8(Mnemonic::ROL, 0x81, 0x21), 0x03);
+    }
+
     #[test]
     fn test_shr() {
         let (result, carry) = Cpu::shr_u8_with_carry(0x80, 7);
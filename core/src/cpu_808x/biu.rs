@@ -63,6 +63,10 @@ impl ByteQueue for Cpu {
         self.mc_pc = pc;
     }
 
+    fn elapsed(&self) -> u32 {
+        self.instr_cycle
+    }
+
     fn q_read_u8(&mut self, dtype: QueueType, reader: QueueReader) -> u8 {
         self.biu_queue_read(dtype, reader)
     }
@@ -129,6 +133,10 @@ impl Cpu {
         let byte;
         //trace_print!(self, "biu_queue_read()");
 
+        if self.fast_core {
+            return self.biu_queue_read_fast(dtype);
+        }
+
         if let Some(preload_byte) = self.queue.get_preload() {
             // We have a pre-loaded byte from finalizing the last instruction.
             self.last_queue_op = QueueOp::First;
@@ -193,6 +201,20 @@ impl Cpu {
         byte
     }
 
+    /// Read an instruction byte directly from memory at CS:PC in fast core mode. The first byte of
+    /// an instruction is considered prefetched and is free; subsequent bytes take one cycle, as they
+    /// would when read from a non-empty queue.
+    fn biu_queue_read_fast(&mut self, dtype: QueueType) -> u8 {
+        let addr = self.calc_linear_address_seg(Segment::CS, self.pc);
+        let (byte, _cost) = self.bus.read_u8(addr as usize, 0).unwrap();
+        self.pc = self.pc.wrapping_add(1);
+
+        if let QueueType::Subsequent = dtype {
+            self.cycle();
+        }
+        byte
+    }
+
     pub fn biu_fetch_on_queue_read(&mut self) {
         // TODO: What if queue is read during transitional state?
         if matches!(self.biu_state_new, BiuStateNew::Idle) && self.queue.len() == 3 {
@@ -207,6 +229,11 @@ impl Cpu {
     ///
     /// We consider this byte 'preloaded' - this does not correspond to a real CPU state
     pub fn biu_fetch_next(&mut self) {
+        // The fast core has no queue to fill; the next instruction is read directly from memory.
+        if self.fast_core {
+            return;
+        }
+
         // Don't fetch if we are in a string instruction that is still repeating.
         if !self.in_rep {
             self.trace_comment("FETCH");
@@ -397,6 +424,11 @@ impl Cpu {
         self.queue_op = QueueOp::Flush;
         self.trace_comment("FLUSH");
 
+        // A flush follows every change of CS, so refresh the CS descriptor cache before fetching resumes.
+        if self.is_protected_mode() {
+            self.sync_segment_cache(Segment::CS);
+        }
+
         //trace_print!("Fetch state to idle");
         self.fetch_state = FetchState::Idle;
         self.fetch_suspended = false;
@@ -422,7 +454,7 @@ impl Cpu {
     pub fn biu_queue_has_room(&mut self) -> bool {
         match self.cpu_type {
             CpuType::Intel8088 => self.queue.len() < 4,
-            CpuType::Intel8086 => {
+            CpuType::Intel8086 | CpuType::Intel80286 => {
                 // 8086 fetches two bytes at a time, so must be two free bytes in queue
                 self.queue.len() < 5
             }
@@ -552,6 +584,12 @@ impl Cpu {
         self.final_transfer = true;
 
         self.cycle();
+
+        if self.fast_core {
+            // No T-states are simulated, so end the halt bus cycle here.
+            self.bus_status = BusStatus::Passive;
+            self.bus_status_latch = BusStatus::Passive;
+        }
     }
 
     /// Issue an interrupt acknowledge, consisting of two consecutive INTA bus cycles.
@@ -595,11 +633,14 @@ impl Cpu {
         );
         let _cycles_waited = self.biu_bus_wait_finish();
 
-        (self.data_bus & 0x00FF) as u8
+        let byte = (self.data_bus & 0x00FF) as u8;
+        self.trace_mem_operand(false, seg, offset, false, byte as u16);
+        byte
     }
 
     pub fn biu_write_u8(&mut self, seg: Segment, offset: u16, byte: u8, flag: ReadWriteFlag) {
         let addr = self.calc_linear_address_seg(seg, offset);
+        self.trace_mem_operand(true, seg, offset, false, byte as u16);
 
         self.biu_bus_begin(
             BusStatus::MemWrite,
@@ -652,6 +693,23 @@ impl Cpu {
     pub fn biu_io_read_u16(&mut self, addr: u16, flag: ReadWriteFlag) -> u16 {
         let mut word;
 
+        if self.cpu_type.has_16bit_bus() && addr & 1 == 0 {
+            self.biu_bus_begin(
+                BusStatus::IoRead,
+                Segment::None,
+                addr as u32,
+                0,
+                TransferSize::Word,
+                OperandSize::Operand16,
+                true,
+            );
+            match flag {
+                ReadWriteFlag::Normal => self.biu_bus_wait_finish(),
+                ReadWriteFlag::RNI => self.biu_bus_wait_until_tx(),
+            };
+            return self.data_bus;
+        }
+
         self.biu_bus_begin(
             BusStatus::IoRead,
             Segment::None,
@@ -686,6 +744,23 @@ impl Cpu {
     }
 
     pub fn biu_io_write_u16(&mut self, addr: u16, word: u16, flag: ReadWriteFlag) {
+        if self.cpu_type.has_16bit_bus() && addr & 1 == 0 {
+            self.biu_bus_begin(
+                BusStatus::IoWrite,
+                Segment::None,
+                addr as u32,
+                word,
+                TransferSize::Word,
+                OperandSize::Operand16,
+                true,
+            );
+            match flag {
+                ReadWriteFlag::Normal => self.biu_bus_wait_finish(),
+                ReadWriteFlag::RNI => self.biu_bus_wait_until_tx(),
+            };
+            return;
+        }
+
         self.biu_bus_begin(
             BusStatus::IoWrite,
             Segment::None,
@@ -715,11 +790,28 @@ impl Cpu {
     }
 
     /// Request a word size (16-bit) bus read transfer from the BIU.
-    /// The 8088 divides word transfers up into two consecutive byte size transfers.
+    /// The 8088 divides word transfers up into two consecutive byte size transfers. CPUs with a
+    /// 16-bit data bus read an aligned word in a single transfer.
     pub fn biu_read_u16(&mut self, seg: Segment, offset: u16, flag: ReadWriteFlag) -> u16 {
         let mut word;
         let mut addr = self.calc_linear_address_seg(seg, offset);
 
+        if self.cpu_type.has_16bit_bus() && addr & 1 == 0 {
+            self.biu_bus_begin(
+                BusStatus::MemRead,
+                seg,
+                addr,
+                0,
+                TransferSize::Word,
+                OperandSize::Operand16,
+                true,
+            );
+            self.biu_bus_wait_finish();
+            word = self.data_bus;
+            self.trace_mem_operand(false, seg, offset, true, word);
+            return word;
+        }
+
         self.biu_bus_begin(
             BusStatus::MemRead,
             seg,
@@ -752,13 +844,33 @@ impl Cpu {
         };
         word |= (self.data_bus & 0x00FF) << 8;
 
+        self.trace_mem_operand(false, seg, offset, true, word);
         word
     }
 
     /// Request a word size (16-bit) bus write transfer from the BIU.
-    /// The 8088 divides word transfers up into two consecutive byte size transfers.
+    /// The 8088 divides word transfers up into two consecutive byte size transfers. CPUs with a
+    /// 16-bit data bus write an aligned word in a single transfer.
     pub fn biu_write_u16(&mut self, seg: Segment, offset: u16, word: u16, flag: ReadWriteFlag) {
         let mut addr = self.calc_linear_address_seg(seg, offset);
+        self.trace_mem_operand(true, seg, offset, true, word);
+
+        if self.cpu_type.has_16bit_bus() && addr & 1 == 0 {
+            self.biu_bus_begin(
+                BusStatus::MemWrite,
+                seg,
+                addr,
+                word,
+                TransferSize::Word,
+                OperandSize::Operand16,
+                true,
+            );
+            match flag {
+                ReadWriteFlag::Normal => self.biu_bus_wait_finish(),
+                ReadWriteFlag::RNI => self.biu_bus_wait_until_tx(),
+            };
+            return;
+        }
 
         // 8088 performs two consecutive byte transfers
         self.biu_bus_begin(
@@ -936,7 +1048,17 @@ impl Cpu {
         // Check this address for a memory access breakpoint
         if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 {
             // Breakpoint hit
-            self.state = CpuState::BreakpointHit;
+            self.break_on(BreakpointTrigger::MemAccess(address));
+        }
+
+        if self.fast_core {
+            self.transfer_n = if first { 1 } else { 2 };
+            self.bus_segment = bus_segment;
+            self.data_bus = data;
+            self.transfer_size = size;
+            self.operand_size = op_size;
+            self.biu_bus_fast(new_bus_status, address);
+            return;
         }
 
         if new_bus_status != BusStatus::CodeFetch {
@@ -1061,6 +1183,38 @@ impl Cpu {
         self.operand_size = op_size;
     }
 
+    /// Perform a bus transfer immediately in fast core mode. Rather than simulating T-states, each
+    /// transfer is charged a fixed four-cycle bus cycle plus any wait states. The caller sets up the
+    /// segment, data and transfer size.
+    fn biu_bus_fast(&mut self, new_bus_status: BusStatus, address: u32) {
+        self.bus_status = new_bus_status;
+        self.bus_status_latch = new_bus_status;
+        self.address_bus = address;
+        self.address_latch = address;
+
+        let mut wait_states = match new_bus_status {
+            BusStatus::CodeFetch | BusStatus::MemRead => self
+                .bus
+                .get_read_wait(address as usize, self.instr_elapsed)
+                .unwrap_or(0),
+            BusStatus::MemWrite => self
+                .bus
+                .get_write_wait(address as usize, self.instr_elapsed)
+                .unwrap_or(0),
+            BusStatus::IoRead | BusStatus::IoWrite => 1 + self.bus.get_io_wait((address & 0xFFFF) as u16),
+            _ => 0,
+        };
+        if !self.enable_wait_states {
+            wait_states = 0;
+        }
+        self.instr_wait_states += wait_states;
+
+        self.do_bus_transfer();
+        self.biu_bus_end();
+        self.bus_status_latch = BusStatus::Passive;
+        self.cycles(4 + wait_states);
+    }
+
     pub fn biu_bus_end(&mut self) {
         // Reset i8288 signals
         self.i8288.mrdc = false;
@@ -88,6 +88,11 @@ impl Cpu {
             self.trace_instr = instr;
         }
 
+        if self.fast_core {
+            self.cycles_fast(1);
+            return;
+        }
+
         if self.t_cycle == TCycle::Tinit {
             self.t_cycle = TCycle::T1;
         }
@@ -157,11 +162,9 @@ impl Cpu {
                                     .get_write_wait(self.address_latch as usize, self.instr_elapsed)
                                     .unwrap();
                             }
-                            BusStatus::IoRead => {
-                                self.bus_wait_states = 1;
-                            }
-                            BusStatus::IoWrite => {
-                                self.bus_wait_states = 1;
+                            BusStatus::IoRead | BusStatus::IoWrite => {
+                                // IO cycles incur one wait state, plus any configured per-port waits.
+                                self.bus_wait_states = 1 + self.bus.get_io_wait((self.address_latch & 0xFFFF) as u16);
                             }
                             _ => {}
                         }
@@ -237,6 +240,7 @@ impl Cpu {
                     let token_vec = self.cycle_state_tokens(dma_count, false);
                     //self.trace_print(&state_str);
                     self.trace_token_vec.push(token_vec);
+                    self.trace_cycle_record();
 
                     self.trace_comment.clear();
                     self.trace_instr = MC_NONE;
@@ -244,6 +248,10 @@ impl Cpu {
                 TraceMode::CycleSigrok => {
                     self.trace_csv_line();
                 }
+                TraceMode::CycleJson | TraceMode::CycleBinary => {
+                    self.trace_cycle_record();
+                    self.trace_instr = MC_NONE;
+                }
                 _ => {}
             }
         }
@@ -254,20 +262,36 @@ impl Cpu {
             self.cycle_states.push(cycle_state);
         }
 
-        // Do DRAM refresh (DMA channel 0) simulation
-        if self.enable_wait_states && self.dram_refresh_simulation {
-            self.dram_refresh_cycle_num = self.dram_refresh_cycle_num.saturating_sub(1);
+        // Do DRAM refresh (DMA channel 0) and device DMA transfer simulation
+        if self.enable_wait_states
+            && (self.dram_refresh_simulation
+                || !matches!(self.dma_state, DmaState::Idle)
+                || self.bus.dma_hold_request())
+        {
+            if self.dram_refresh_simulation {
+                self.dram_refresh_cycle_num = self.dram_refresh_cycle_num.saturating_sub(1);
+            }
 
             match &mut self.dma_state {
                 DmaState::Idle => {
-                    if self.dram_refresh_cycle_num == 0 && self.dram_refresh_cycle_period > 0 {
+                    if self.dram_refresh_simulation
+                        && self.dram_refresh_cycle_num == 0
+                        && self.dram_refresh_cycle_period > 0
+                    {
                         // DRAM refresh cycle counter has hit terminal count.
                         // Begin DMA transfer simulation by issuing a DREQ.
                         self.dma_state = DmaState::Dreq;
+                        self.dma_hold_device = false;
 
                         // Reset counter.
                         self.dram_refresh_cycle_num = self.dram_refresh_cycle_period;
                     }
+                    else if self.bus.dma_hold_request() {
+                        // A device has made a DMA transfer that must be paid for by holding
+                        // the CPU off the bus. Refresh takes precedence as channel 0.
+                        self.dma_state = DmaState::Dreq;
+                        self.dma_hold_device = true;
+                    }
                 }
                 DmaState::TimerTrigger => {
                     // Timer channel #1 begins rising immediately, but with a slow enough
@@ -300,21 +324,45 @@ impl Cpu {
                 DmaState::HoldA => {
                     // DMA Hold Acknowledge has been issued. DMA controller will enter S1
                     // on next cycle.
-                    self.dma_state = DmaState::Operating(4);
-                    self.dma_aen = true;
+                    if self.dma_hold_device {
+                        // The DMA controller arbitrates between channels with pending
+                        // transfers and reports the timing of the winning channel's transfer.
+                        self.dma_hold_grant = self.bus.dma_mut().as_mut().and_then(|dma| dma.acknowledge_hold());
+                        match self.dma_hold_grant {
+                            Some(grant) => {
+                                self.dma_state = DmaState::Operating(grant.cycles);
+                                self.dma_aen = true;
+                            }
+                            None => {
+                                // Request was withdrawn.
+                                self.dma_state = DmaState::Idle;
+                            }
+                        }
+                    }
+                    else {
+                        self.dma_hold_grant = None;
+                        self.dma_state = DmaState::Operating(DMA_NORMAL_TRANSFER_CYCLES);
+                        self.dma_aen = true;
+                    }
                 }
                 DmaState::Operating(cycles) => {
                     // the DMA controller has control of the bus now.
                     // Run DMA transfer cycles.
+                    let (transfer_cycles, transfer_wait_states) = match self.dma_hold_grant {
+                        Some(grant) => (grant.cycles, grant.wait_states),
+                        None => (DMA_NORMAL_TRANSFER_CYCLES, self.dram_refresh_wait_states),
+                    };
                     *cycles = cycles.saturating_sub(1);
-                    if *cycles == 3 {
+                    if *cycles == transfer_cycles - 1 {
                         // DMAWAIT asserted on S2
-                        self.dma_wait_states = 7; // Effectively 6 as this is decremented this cycle
+                        // Add one, as this is decremented this cycle
+                        self.dma_wait_states = transfer_wait_states + 1;
                         self.ready = false;
                     }
                     if *cycles == 0 {
                         // Transfer cycles have elapsed, so move to next state.
                         self.dma_aen = false;
+                        self.dma_hold_grant = None;
                         self.dma_state = DmaState::Idle
                     }
                 }
@@ -361,6 +409,7 @@ impl Cpu {
             }
             TCycle::T2 => {
                 self.wait_states += self.bus_wait_states;
+                self.instr_wait_states += self.bus_wait_states;
                 TCycle::T3
             }
             TCycle::T3 => {
@@ -474,6 +523,23 @@ impl Cpu {
         self.last_queue_len = self.queue.len();
     }
 
+    /// Execute the specified number of CPU cycles in fast core mode. No bus or prefetcher state is
+    /// simulated, so the counters used for instruction and device timing are advanced in one step.
+    #[inline]
+    fn cycles_fast(&mut self, ct: u32) {
+        if self.in_int {
+            self.int_elapsed += ct;
+        }
+        else {
+            self.instr_elapsed += ct;
+        }
+
+        self.instr_cycle += ct;
+        self.device_cycles += ct;
+        self.cycle_num += ct as u64;
+        self.t_stamp += self.t_step * ct as f64;
+    }
+
     /*    /// Temporary function to increment pc. Needed to handle wraparound
     /// of code segment.  This should be unnecessary once pc is converted to u16.
     pub fn inc_pc(&mut self) {
@@ -584,6 +650,9 @@ impl Cpu {
             }
             (BusStatus::IoWrite, TransferSize::Byte) => {
                 self.i8288.iowc = true;
+                if self.bus.video_trace_enabled() {
+                    self.bus.set_video_trace_source(self.cs, self.instruction_ip);
+                }
                 self.bus.io_write_u8(
                     (self.address_latch & 0xFFFF) as u16,
                     (self.data_bus & 0x00FF) as u8,
@@ -593,6 +662,19 @@ impl Cpu {
 
                 validate_write_u8!(self, self.address_latch, (self.data_bus & 0x00FF) as u8, BusType::Io);
             }
+            (BusStatus::IoRead, TransferSize::Word) => {
+                self.i8288.iorc = true;
+                self.data_bus = self
+                    .bus
+                    .io_read_u16((self.address_latch & 0xFFFF) as u16, self.instr_elapsed);
+                self.instr_elapsed = 0;
+            }
+            (BusStatus::IoWrite, TransferSize::Word) => {
+                self.i8288.iowc = true;
+                self.bus
+                    .io_write_u16((self.address_latch & 0xFFFF) as u16, self.data_bus, self.instr_elapsed);
+                self.instr_elapsed = 0;
+            }
             (BusStatus::InterruptAck, TransferSize::Byte) => {
                 // The vector is read from the PIC directly before we even enter an INTA bus state, so there's
                 // nothing to do.
@@ -612,6 +694,8 @@ impl Cpu {
             }
         }
 
+        // Latch the transferred byte so that a subsequent open bus read can return it.
+        self.bus.set_open_bus_latch((self.data_bus & 0x00FF) as u8);
         self.bus_status = BusStatus::Passive;
         self.address_bus = (self.address_bus & !0xFF) | (self.data_bus as u32);
     }
@@ -620,7 +704,7 @@ impl Cpu {
         if let BiuStateNew::Prefetch | BiuStateNew::ToPrefetch(_) = self.biu_state_new {
             //trace_print!(self, "scheduling fetch: {}", self.queue.len());
 
-            let addr = Cpu::calc_linear_address(self.cs, self.pc);
+            let addr = self.calc_linear_address_seg(Segment::CS, self.pc);
             if self.biu_queue_has_room() {
                 //trace_print!(self, "Setting address bus to PC: {:05X}", self.pc);
                 self.fetch_state = FetchState::InProgress;
@@ -668,6 +752,11 @@ impl Cpu {
 
     #[inline]
     pub fn cycles(&mut self, ct: u32) {
+        if self.fast_core {
+            self.trace_instr = self.mc_pc;
+            self.cycles_fast(ct);
+            return;
+        }
         for _ in 0..ct {
             self.cycle();
         }
@@ -675,6 +764,14 @@ impl Cpu {
 
     #[inline]
     pub fn cycles_i(&mut self, ct: u32, instrs: &[u16]) {
+        if self.fast_core {
+            if let Some(&instr) = instrs[..ct as usize].last() {
+                self.mc_pc = instr;
+                self.trace_instr = instr;
+            }
+            self.cycles_fast(ct);
+            return;
+        }
         for i in 0..ct as usize {
             self.cycle_i(instrs[i]);
         }
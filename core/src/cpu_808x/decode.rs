@@ -99,13 +99,21 @@ impl Display for InstructionDecodeError {
 }
 
 impl Cpu {
-    #[rustfmt::skip]
+    /// Decode an instruction as the 8088 would.
     pub fn decode(bytes: &mut impl ByteQueue) -> Result<Instruction, Box<dyn std::error::Error>> {
+        Cpu::decode_cpu(bytes, CpuType::Intel8088)
+    }
+
+    /// Decode an instruction for the specified CPU type. The 80286 defines opcodes that are
+    /// aliases of other instructions on the 8088.
+    #[rustfmt::skip]
+    pub fn decode_cpu(bytes: &mut impl ByteQueue, cpu_type: CpuType) -> Result<Instruction, Box<dyn std::error::Error>> {
 
         let mut operand1_type: OperandType = OperandType::NoOperand;
         let mut operand2_type: OperandType = OperandType::NoOperand;
         let mut operand1_size: OperandSize = OperandSize::NoOperand;
         let mut operand2_size: OperandSize = OperandSize::NoOperand;
+        let mut operand3_type: OperandType = OperandType::NoOperand;
 
         let mut opcode = bytes.q_read_u8(QueueType::First, QueueReader::Biu);
         let mut size: u32 = 1;
@@ -350,6 +358,66 @@ impl Cpu {
 
         let mut modrm = Default::default();
 
+        // Override the 8088's aliases with the instructions the 80286 defines in their place.
+        if cpu_type == CpuType::Intel80286 {
+            (mnemonic, operand1_template, operand2_template, op_flags) = match opcode {
+                0x0F => {
+                    // Two-byte system instructions.
+                    let opcode2 = bytes.q_read_u8(QueueType::Subsequent, QueueReader::Biu);
+                    size += 1;
+
+                    match opcode2 {
+                        0x00 | 0x01 => {
+                            let modrm_len;
+                            (modrm, modrm_len) = ModRmByte::read(bytes);
+                            size += modrm_len;
+                            loaded_modrm = true;
+
+                            match (opcode2, modrm.get_op_extension()) {
+                                (0x00, 0x00) => (Mnemonic::SLDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x00, 0x01) => (Mnemonic::STR,  OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x00, 0x02) => (Mnemonic::LLDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM | I_LOAD_EA),
+                                (0x00, 0x03) => (Mnemonic::LTR,  OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM | I_LOAD_EA),
+                                (0x00, 0x04) => (Mnemonic::VERR, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM | I_LOAD_EA),
+                                (0x00, 0x05) => (Mnemonic::VERW, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM | I_LOAD_EA),
+                                (0x01, 0x00) => (Mnemonic::SGDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x01, 0x01) => (Mnemonic::SIDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x01, 0x02) => (Mnemonic::LGDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x01, 0x03) => (Mnemonic::LIDT, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x01, 0x04) => (Mnemonic::SMSW, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM),
+                                (0x01, 0x06) => (Mnemonic::LMSW, OperandTemplate::ModRM16, OperandTemplate::NoOperand, I_HAS_MODRM | I_LOAD_EA),
+                                _ => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, I_HAS_MODRM),
+                            }
+                        }
+                        0x02 => (Mnemonic::LAR,  OperandTemplate::Register16, OperandTemplate::ModRM16,   I_LOAD_EA),
+                        0x03 => (Mnemonic::LSL,  OperandTemplate::Register16, OperandTemplate::ModRM16,   I_LOAD_EA),
+                        0x06 => (Mnemonic::CLTS, OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                        _ => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+                    }
+                }
+                0x60 => (Mnemonic::PUSHA, OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0x61 => (Mnemonic::POPA,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0x62 => (Mnemonic::BOUND, OperandTemplate::Register16, OperandTemplate::ModRM16,   I_LOAD_EA),
+                0x63 => (Mnemonic::ARPL,  OperandTemplate::ModRM16,    OperandTemplate::Register16, I_LOAD_EA),
+                0x64..=0x67 => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+                0x68 => (Mnemonic::PUSH,  OperandTemplate::Immediate16, OperandTemplate::NoOperand, 0),
+                0x69 => (Mnemonic::IMUL,  OperandTemplate::Register16, OperandTemplate::ModRM16,   I_LOAD_EA),
+                0x6A => (Mnemonic::PUSH,  OperandTemplate::Immediate8SignExtended, OperandTemplate::NoOperand, 0),
+                0x6B => (Mnemonic::IMUL,  OperandTemplate::Register16, OperandTemplate::ModRM16,   I_LOAD_EA),
+                0x6C => (Mnemonic::INSB,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0x6D => (Mnemonic::INSW,  OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0x6E => (Mnemonic::OUTSB, OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0x6F => (Mnemonic::OUTSW, OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                // 0xC0 and 0xC1 are groups, decoded below
+                0xC0 | 0xC1 => (Mnemonic::NoOpcode, OperandTemplate::NoTemplate, OperandTemplate::NoTemplate, 0),
+                0xC8 => (Mnemonic::ENTER, OperandTemplate::Immediate16, OperandTemplate::NoTemplate, 0),
+                0xC9 => (Mnemonic::LEAVE, OperandTemplate::NoOperand,  OperandTemplate::NoOperand, 0),
+                0xD6 => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+                0xF1 => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+                _ => (mnemonic, operand1_template, operand2_template, op_flags),
+            };
+        }
+
         // If we haven't had a match yet, we are in a group instruction
         if mnemonic == Mnemonic::NoOpcode {
 
@@ -466,6 +534,32 @@ impl Cpu {
                 _=> (Mnemonic::NoOpcode, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0)
             };
 
+            if cpu_type == CpuType::Intel80286 {
+                (mnemonic, operand1_template, operand2_template, op_flags) = match (opcode, op_ext) {
+                    (0xC0, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x03) => (Mnemonic::RCR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x04) => (Mnemonic::SHL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x05) => (Mnemonic::SHR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x06) => (Mnemonic::SHL,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC0, 0x07) => (Mnemonic::SAR,   OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+
+                    (0xC1, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x03) => (Mnemonic::RCR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x04) => (Mnemonic::SHL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x05) => (Mnemonic::SHR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x06) => (Mnemonic::SHL,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                    (0xC1, 0x07) => (Mnemonic::SAR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+
+                    // The 8088's byte forms of CALL, JMP and PUSH, and FF /7, are undefined on the 80286.
+                    (0xFE, 0x02..=0x07) | (0xFF, 0x07) => (Mnemonic::Undefined, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+                    _ => (mnemonic, operand1_template, operand2_template, op_flags),
+                };
+            }
+
             op_flags |= I_HAS_MODRM;
         }
 
@@ -646,6 +740,28 @@ impl Cpu {
             _=> (operand2_type, operand2_size) = match_op(operand2_template)
         }
 
+        // The 80286 adds instructions with a third immediate operand, or a second immediate that
+        // follows a 16-bit one.
+        if cpu_type == CpuType::Intel80286 {
+            match (opcode, mnemonic) {
+                (0x69, Mnemonic::IMUL) => {
+                    operand3_type = OperandType::Immediate16(bytes.q_peek_u16());
+                    size += 2;
+                }
+                (0x6B, Mnemonic::IMUL) => {
+                    operand3_type = OperandType::Immediate8s(bytes.q_peek_i8());
+                    size += 1;
+                }
+                (0xC8, Mnemonic::ENTER) => {
+                    let (level, _frame_size) = bytes.q_peek_farptr16();
+                    operand2_type = OperandType::Immediate8((level & 0xFF) as u8);
+                    operand2_size = OperandSize::Operand8;
+                    size += 1;
+                }
+                _ => {}
+            }
+        }
+
         // Set a flag if either of the instruction operands is a memory operand.
         if let OperandType::AddressingMode(_) = operand1_type {
             op_flags |= I_USES_MEM;
@@ -671,7 +787,85 @@ impl Cpu {
             operand1_type,
             operand1_size,
             operand2_type,
-            operand2_size
+            operand2_size,
+            operand3_type,
+            ea_cycles: modrm.ea_cycles(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusInterface;
+
+    fn decode_bytes(bytes: &[u8]) -> Instruction {
+        let mut bus = BusInterface::default();
+        bus.copy_from(bytes, 0, 0, false).unwrap();
+        bus.seek(0);
+        Cpu::decode(&mut bus).unwrap()
+    }
+
+    #[test]
+    fn test_decode_undocumented_aliases() {
+        // 0x60-0x6F alias the conditional jumps at 0x70-0x7F
+        assert_eq!(decode_bytes(&[0x64, 0x10]).mnemonic, Mnemonic::JZ);
+        // 0x82 aliases 0x80
+        let i = decode_bytes(&[0x82, 0xC8, 0x01]);
+        assert_eq!(i.mnemonic, Mnemonic::OR);
+        assert!(matches!(i.operand1_type, OperandType::Register8(Register8::AL)));
+        // 0xC0/0xC1 alias RETN, 0xC8/0xC9 alias RETF
+        assert_eq!(decode_bytes(&[0xC0, 0x04, 0x00]).mnemonic, Mnemonic::RETN);
+        assert_eq!(decode_bytes(&[0xC1]).mnemonic, Mnemonic::RETN);
+        assert_eq!(decode_bytes(&[0xC8, 0x04, 0x00]).mnemonic, Mnemonic::RETF);
+        assert_eq!(decode_bytes(&[0xC9]).mnemonic, Mnemonic::RETF);
+        // 0x8F ignores the reg field
+        let i = decode_bytes(&[0x8F, 0xD8]);
+        assert_eq!(i.mnemonic, Mnemonic::POP);
+        assert!(matches!(i.operand1_type, OperandType::Register16(Register16::AX)));
+        // 0xF1 is an alias of the LOCK prefix
+        let i = decode_bytes(&[0xF1, 0x90]);
+        assert_eq!(i.mnemonic, Mnemonic::NOP);
+        assert_ne!(i.prefixes & OPCODE_PREFIX_LOCK, 0);
+        // SALC and POP CS
+        assert_eq!(decode_bytes(&[0xD6]).mnemonic, Mnemonic::SALC);
+        assert_eq!(decode_bytes(&[0x0F]).mnemonic, Mnemonic::POP);
+        // Segment register field is only 2 bits wide
+        let i = decode_bytes(&[0x8E, 0xE0]);
+        assert!(matches!(i.operand1_type, OperandType::Register16(Register16::ES)));
+    }
+
+    #[test]
+    fn test_decode_286() {
+        let decode_286 = |bytes: &[u8]| {
+            let mut bus = BusInterface::default();
+            bus.copy_from(bytes, 0, 0, false).unwrap();
+            bus.seek(0);
+            Cpu::decode_cpu(&mut bus, CpuType::Intel80286).unwrap()
+        };
+
+        // 0x60-0x6F no longer alias the conditional jumps
+        assert_eq!(decode_286(&[0x60]).mnemonic, Mnemonic::PUSHA);
+        assert_eq!(decode_286(&[0x64]).mnemonic, Mnemonic::Undefined);
+        // IMUL with an immediate third operand
+        let i = decode_286(&[0x6B, 0xC3, 0xFE]);
+        assert_eq!(i.mnemonic, Mnemonic::IMUL);
+        assert!(matches!(i.operand1_type, OperandType::Register16(Register16::AX)));
+        assert!(matches!(i.operand3_type, OperandType::Immediate8s(-2)));
+        assert_eq!(i.size, 3);
+        // Shift by immediate
+        let i = decode_286(&[0xC1, 0xE0, 0x04]);
+        assert_eq!(i.mnemonic, Mnemonic::SHL);
+        assert!(matches!(i.operand2_type, OperandType::Immediate8(4)));
+        // ENTER takes a 16-bit frame size and an 8-bit nesting level
+        let i = decode_286(&[0xC8, 0x10, 0x00, 0x01]);
+        assert_eq!(i.mnemonic, Mnemonic::ENTER);
+        assert!(matches!(i.operand1_type, OperandType::Immediate16(0x10)));
+        assert!(matches!(i.operand2_type, OperandType::Immediate8(1)));
+        assert_eq!(i.size, 4);
+        // Two-byte system instructions
+        assert_eq!(decode_286(&[0x0F, 0x01, 0xE0]).mnemonic, Mnemonic::SMSW);
+        assert_eq!(decode_286(&[0x0F, 0x06]).mnemonic, Mnemonic::CLTS);
+        assert_eq!(decode_286(&[0xD6]).mnemonic, Mnemonic::Undefined);
+    }
+}
@@ -0,0 +1,230 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_808x::decode_cache.rs
+
+    Implements an optional cache of decoded instructions keyed by linear
+    address, for execution and disassembly paths that decode directly from
+    memory rather than through the processor instruction queue.
+
+    The bytes of each cached instruction are marked in the bus code map. The
+    bus records writes to marked bytes, and the cache discards the affected
+    instructions before its next lookup. This catches self-modifying code
+    without comparing instruction bytes on every lookup.
+
+*/
+
+use std::{collections::HashMap, error::Error};
+
+use crate::{
+    bus::BusInterface,
+    bytequeue::ByteQueue,
+    cpu_808x::{Cpu, Instruction, MAX_INSTRUCTION_SIZE},
+    cpu_common::CpuType,
+};
+
+/// Maximum number of cached instructions. The cache is cleared when this is exceeded.
+pub const DECODE_CACHE_MAX_ENTRIES: usize = 0x10000;
+
+/// The work done by the fast core's decoder for an instruction, so that it can be repeated on a
+/// cache hit. Immediate operands are read from the instruction stream during execution, so
+/// `fetched` can be less than the size of the instruction.
+#[derive(Copy, Clone)]
+pub struct DecodeTiming {
+    /// Number of bytes read from the instruction stream by the decoder.
+    pub fetched: u16,
+    /// Number of cycles spent by the decoder.
+    pub cycles:  u32,
+}
+
+#[derive(Copy, Clone)]
+pub struct CachedInstruction {
+    pub i: Instruction,
+    /// Decoder timing, if the instruction was decoded by the fast core.
+    pub timing: Option<DecodeTiming>,
+}
+
+#[derive(Default)]
+pub struct DecodeCache {
+    entries: HashMap<u32, CachedInstruction>,
+    hits:    u64,
+    misses:  u64,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Decode the instruction at the specified linear address, returning a cached decode if the
+    /// instruction has not been written to since it was made.
+    pub fn decode(&mut self, bus: &mut BusInterface, address: u32) -> Result<Instruction, Box<dyn Error>> {
+        self.decode_cpu(bus, address, CpuType::Intel8088)
+    }
+
+    /// Decode the instruction at the specified linear address for the specified CPU type.
+    pub fn decode_cpu(
+        &mut self,
+        bus: &mut BusInterface,
+        address: u32,
+        cpu_type: CpuType,
+    ) -> Result<Instruction, Box<dyn Error>> {
+        if let Some(entry) = self.lookup(bus, address) {
+            return Ok(entry.i);
+        }
+
+        bus.seek(address as usize);
+        let mut i = Cpu::decode_cpu(bus, cpu_type)?;
+        i.address = address;
+        self.insert(bus, CachedInstruction { i, timing: None });
+        Ok(i)
+    }
+
+    /// Return the cached instruction at the specified linear address, if present. Instructions
+    /// that have been written to since the last lookup are discarded first.
+    pub fn lookup(&mut self, bus: &mut BusInterface, address: u32) -> Option<CachedInstruction> {
+        bus.drain_code_writes(|write_address| self.invalidate(write_address, 1));
+        match self.entries.get(&address) {
+            Some(entry) => {
+                self.hits += 1;
+                Some(*entry)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add a decoded instruction to the cache, marking its bytes in the bus code map.
+    /// Instructions that extend past the end of conventional address space, or that lie in
+    /// memory-mapped regions the code map can't track, are not cached.
+    pub fn insert(&mut self, bus: &mut BusInterface, entry: CachedInstruction) {
+        let address = entry.i.address;
+        let size = entry.i.size as usize;
+        if size == 0 || size > MAX_INSTRUCTION_SIZE || !bus.mark_code(address as usize, size) {
+            self.entries.remove(&address);
+            return;
+        }
+        if self.entries.len() >= DECODE_CACHE_MAX_ENTRIES {
+            self.entries.clear();
+            bus.clear_code_map();
+            bus.mark_code(address as usize, size);
+        }
+        self.entries.insert(address, entry);
+    }
+
+    /// Discard any cached instructions that overlap the specified range.
+    pub fn invalidate(&mut self, address: u32, len: u32) {
+        let first = address.saturating_sub(MAX_INSTRUCTION_SIZE as u32 - 1);
+        let end = address.saturating_add(len);
+        for start in first..end {
+            if let Some(entry) = self.entries.get(&start) {
+                if start + entry.i.size > address {
+                    self.entries.remove(&start);
+                }
+            }
+        }
+    }
+
+    /// Discard all cached instructions. Bytes left marked in the bus code map only cause
+    /// redundant invalidations.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the number of cache hits and misses since the cache was created.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_808x::mnemonic::Mnemonic;
+
+    #[test]
+    fn test_decode_cache() {
+        let mut bus = BusInterface::default();
+        bus.set_code_tracking(true);
+        let mut cache = DecodeCache::new();
+
+        // MOV AX, 1234h
+        bus.copy_from(&[0xB8, 0x34, 0x12], 0x100, 0, false).unwrap();
+        let i = cache.decode(&mut bus, 0x100).unwrap();
+        assert_eq!(i.mnemonic, Mnemonic::MOV);
+        let i = cache.decode(&mut bus, 0x100).unwrap();
+        assert_eq!(i.size, 3);
+        assert_eq!(cache.stats(), (1, 1));
+
+        // Writing to the instruction's immediate operand must cause a new decode.
+        bus.write_u8(0x102, 0x56, 0).unwrap();
+        let i = cache.decode(&mut bus, 0x100).unwrap();
+        assert_eq!(i.mnemonic, Mnemonic::MOV);
+        assert_eq!(cache.stats(), (1, 2));
+
+        // Writes next to, but not within, the instruction leave it cached.
+        bus.write_u16(0x103, 0x9090, 0).unwrap();
+        bus.write_u8(0xFF, 0x90, 0).unwrap();
+        cache.decode(&mut bus, 0x100).unwrap();
+        assert_eq!(cache.stats(), (2, 2));
+
+        // Replacing the opcode with a word write.
+        bus.write_u16(0x0FF, 0x9090, 0).unwrap();
+        let i = cache.decode(&mut bus, 0x100).unwrap();
+        assert_eq!(i.mnemonic, Mnemonic::NOP);
+        assert_eq!(cache.stats(), (2, 3));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_decode_cache_hma() {
+        let mut bus = BusInterface::default();
+        bus.set_code_tracking(true);
+        let mut cache = DecodeCache::new();
+
+        // Instructions crossing the top of the 1MB address space, or in the HMA, are never cached,
+        // so that they can't alias instructions at the bottom of memory.
+        bus.copy_from(&[0xB8, 0x34, 0x12], 0x0, 0, false).unwrap();
+        bus.copy_from(&[0xB8, 0x34], 0xFFFFE, 0, false).unwrap();
+        cache.decode(&mut bus, 0x0).unwrap();
+        cache.decode(&mut bus, 0xFFFFE).unwrap();
+        let i = cache.decode(&mut bus, 0x10_0000).unwrap();
+        assert_ne!(i.mnemonic, Mnemonic::MOV);
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -38,9 +38,11 @@ use crate::cpu_808x::{addressing::AddressingMode, mnemonic::Mnemonic, *};
 use crate::syntax_token::SyntaxToken;
 
 #[derive(Copy, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum OperandSelect {
     FirstOperand,
     SecondOperand,
+    ThirdOperand,
 }
 
 fn mnemonic_to_str(op: Mnemonic) -> &'static str {
@@ -149,6 +151,32 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::XCHG => "XCHG",
         Mnemonic::XLAT => "XLAT",
         Mnemonic::XOR => "XOR",
+        Mnemonic::ARPL => "ARPL",
+        Mnemonic::BOUND => "BOUND",
+        Mnemonic::CLTS => "CLTS",
+        Mnemonic::ENTER => "ENTER",
+        Mnemonic::INSB => "INSB",
+        Mnemonic::INSW => "INSW",
+        Mnemonic::LAR => "LAR",
+        Mnemonic::LEAVE => "LEAVE",
+        Mnemonic::LGDT => "LGDT",
+        Mnemonic::LIDT => "LIDT",
+        Mnemonic::LLDT => "LLDT",
+        Mnemonic::LMSW => "LMSW",
+        Mnemonic::LSL => "LSL",
+        Mnemonic::LTR => "LTR",
+        Mnemonic::OUTSB => "OUTSB",
+        Mnemonic::OUTSW => "OUTSW",
+        Mnemonic::POPA => "POPA",
+        Mnemonic::PUSHA => "PUSHA",
+        Mnemonic::SGDT => "SGDT",
+        Mnemonic::SIDT => "SIDT",
+        Mnemonic::SLDT => "SLDT",
+        Mnemonic::SMSW => "SMSW",
+        Mnemonic::STR => "STR",
+        Mnemonic::VERR => "VERR",
+        Mnemonic::VERW => "VERW",
+        Mnemonic::Undefined => "UNDEFINED",
         _ => "INVALID",
     }
 }
@@ -298,6 +326,12 @@ impl fmt::Display for Instruction {
             instruction_string.push_str(&op2);
         }
 
+        let op3: String = operand_to_string(self, OperandSelect::ThirdOperand, op_size);
+        if !op3.is_empty() {
+            instruction_string.push_str(", ");
+            instruction_string.push_str(&op3);
+        }
+
         write!(f, "{}", instruction_string)
     }
 }
@@ -329,7 +363,9 @@ impl Cpu {
         i_vec.0.push(SyntaxToken::Mnemonic(mnemonic));
 
         let op1_vec = tokenize_operand(i, OperandSelect::FirstOperand, op_size);
-        i_vec.append(op1_vec, Some(SyntaxToken::Formatter(SyntaxFormatType::Space)), None);
+        if !op1_vec.is_empty() {
+            i_vec.append(op1_vec, Some(SyntaxToken::Formatter(SyntaxFormatType::Space)), None);
+        }
 
         let op2_vec = tokenize_operand(i, OperandSelect::SecondOperand, op_size);
 
@@ -338,6 +374,12 @@ impl Cpu {
             i_vec.append(op2_vec, Some(SyntaxToken::Formatter(SyntaxFormatType::Space)), None);
         }
 
+        let op3_vec = tokenize_operand(i, OperandSelect::ThirdOperand, op_size);
+        if !op3_vec.is_empty() {
+            i_vec.0.push(SyntaxToken::Comma);
+            i_vec.append(op3_vec, Some(SyntaxToken::Formatter(SyntaxFormatType::Space)), None);
+        }
+
         i_vec.0
     }
 }
@@ -407,6 +449,7 @@ fn operand_to_string(i: &Instruction, op: OperandSelect, lvalue: OperandSize) ->
     let (op_type, op_size) = match op {
         OperandSelect::FirstOperand => (i.operand1_type, i.operand1_size),
         OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size),
+        OperandSelect::ThirdOperand => (i.operand3_type, OperandSize::NoOperand),
     };
 
     let instruction_string: String = match op_type {
@@ -595,6 +638,7 @@ fn tokenize_operand(i: &Instruction, op: OperandSelect, lvalue: OperandSize) ->
     let (op_type, op_size) = match op {
         OperandSelect::FirstOperand => (i.operand1_type, i.operand1_size),
         OperandSelect::SecondOperand => (i.operand2_type, i.operand2_size),
+        OperandSelect::ThirdOperand => (i.operand3_type, OperandSize::NoOperand),
     };
 
     let mut op_vec = Vec::new();
@@ -823,8 +867,12 @@ fn override_prefix_to_string(i: &Instruction) -> Option<String> {
         None
     }
     else {
-        match i.opcode {
-            0xA4 | 0xA5 | 0xAA | 0xAB | 0xAC | 0xAD | 0xA6 | 0xA7 | 0xAE | 0xAF => {
+        let string_op = match i.opcode {
+            0xA4 | 0xA5 | 0xAA | 0xAB | 0xAC | 0xAD | 0xA6 | 0xA7 | 0xAE | 0xAF => true,
+            _ => matches!(i.mnemonic, Mnemonic::OUTSB | Mnemonic::OUTSW),
+        };
+        match string_op {
+            true => {
                 let segment: String = match i.segment_override {
                     SegmentOverride::ES => "es".to_string(),
                     SegmentOverride::CS => "cs".to_string(),
@@ -833,11 +881,20 @@ fn override_prefix_to_string(i: &Instruction) -> Option<String> {
                 };
                 Some(segment)
             }
-            _ => None,
+            false => None,
         }
     }
 }
 
+/// INS and OUTS share their opcodes with the conditional jump aliases on the 8088, so they are
+/// identified by mnemonic.
+fn is_io_string_op(i: &Instruction) -> bool {
+    matches!(
+        i.mnemonic,
+        Mnemonic::INSB | Mnemonic::INSW | Mnemonic::OUTSB | Mnemonic::OUTSW
+    )
+}
+
 fn prefix_to_string(i: &Instruction) -> Option<String> {
     // Handle REPx prefixes
     // TODO: IS F2 valid on 6C, 6D, etc?
@@ -850,6 +907,7 @@ fn prefix_to_string(i: &Instruction) -> Option<String> {
             0xF6 | 0xF7 => None, // Don't show REP prefix on div.
             0xA4 | 0xA5 | 0xAA | 0xAB | 0xAC | 0xAD => Some("rep".to_string()),
             0xA6 | 0xA7 | 0xAE | 0xAF => Some("repne".to_string()),
+            _ if is_io_string_op(i) => Some("rep".to_string()),
             _ => None,
         }
     }
@@ -858,6 +916,7 @@ fn prefix_to_string(i: &Instruction) -> Option<String> {
             0xF6 | 0xF7 => None, // Don't show REP prefix on div.
             0xA4 | 0xA5 | 0xAA | 0xAB | 0xAC | 0xAD => Some("rep".to_string()),
             0xA6 | 0xA7 | 0xAE | 0xAF => Some("repe".to_string()),
+            _ if is_io_string_op(i) => Some("rep".to_string()),
             _ => None,
         }
     }
@@ -868,31 +927,13 @@ fn prefix_to_string(i: &Instruction) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    #[cfg(feature = "cpu_validator")]
-    use crate::cpu_validator;
-
     use crate::{cpu_808x::*, syntax_token::*};
 
     #[test]
     fn test_display_methods_match() {
         let test_ct = 1_000_000;
 
-        #[cfg(feature = "cpu_validator")]
-        use cpu_validator::ValidatorMode;
-
-        let mut cpu = Cpu::new(
-            CpuType::Intel8088,
-            TraceMode::None,
-            TraceLogger::None,
-            #[cfg(feature = "cpu_validator")]
-            ValidatorType::None,
-            #[cfg(feature = "cpu_validator")]
-            TraceLogger::None,
-            #[cfg(feature = "cpu_validator")]
-            ValidatorMode::Instruction,
-            #[cfg(feature = "cpu_validator")]
-            1_000_000,
-        );
+        let mut cpu = test_cpu(CpuType::Intel8088);
 
         cpu.randomize_seed(1234);
         cpu.randomize_mem();
@@ -901,20 +942,18 @@ mod tests {
             cpu.reset();
             cpu.randomize_regs();
 
-            if cpu.get_register16(Register16::IP) > 0xFFF0 {
+            if cpu.ip() > 0xFFF0 {
                 // Avoid IP wrapping issues for now
                 continue;
             }
             let opcodes: Vec<u8> = (0u8..=255u8).collect();
 
-            let mut instruction_address =
-                Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.get_register16(Register16::IP));
+            let mut instruction_address = Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip());
 
-            while (cpu.get_register16(Register16::IP) > 0xFFF0) || ((instruction_address & 0xFFFFF) > 0xFFFF0) {
+            while (cpu.ip() > 0xFFF0) || ((instruction_address & 0xFFFFF) > 0xFFFF0) {
                 // Avoid IP wrapping issues for now
                 cpu.randomize_regs();
-                instruction_address =
-                    Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.get_register16(Register16::IP));
+                instruction_address = Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip());
             }
 
             cpu.random_inst_from_opcodes(&opcodes);
@@ -35,7 +35,6 @@ use crate::{
     util,
 };
 
-
 /*
 macro_rules! read_operand {
     ($self:ident, $op: expr) => {
@@ -142,7 +141,8 @@ impl Cpu {
             let mut invalid_rep = false;
 
             match self.i.mnemonic {
-                Mnemonic::STOSB | Mnemonic::STOSW | Mnemonic::LODSB | Mnemonic::LODSW | Mnemonic::MOVSB | Mnemonic::MOVSW => {
+                Mnemonic::STOSB | Mnemonic::STOSW | Mnemonic::LODSB | Mnemonic::LODSW | Mnemonic::MOVSB | Mnemonic::MOVSW |
+                Mnemonic::INSB | Mnemonic::INSW | Mnemonic::OUTSB | Mnemonic::OUTSW => {
                     self.rep_type = RepType::Rep;
                 }
                 Mnemonic::SCASB | Mnemonic::SCASW | Mnemonic::CMPSB | Mnemonic::CMPSW => {
@@ -203,7 +203,11 @@ impl Cpu {
             self.opcode0_counter = 0;
         }
 
+        // Instructions the 80286 added or changed are handled separately.
+        let handled_286 = self.cpu_type == CpuType::Intel80286 && self.execute_286_instruction(&mut jump);
+
         match self.i.opcode {
+            _ if handled_286 => {}
             0x00 | 0x02 | 0x04 |  // ADD r/m8, r8 | r8, r/m8 | al, imm8
             0x08 | 0x0A | 0x0C |  // OR  r/m8, r8 | r8, r/m8 | al, imm8
             0x10 | 0x12 | 0x14 |  // ADC r/m8, r8 | r8, r/m8 | al, imm8 
@@ -781,15 +785,16 @@ impl Cpu {
                                 self.cycle_i(MC_JUMP); // Jump to RPTI
                                 self.rep_interrupt();
                             }   
-    
-                            // Check for REP end condition #2 (CX==0)
-                            self.cycle_i(0x12b);
-                            if self.cx == 0 {
-                                self.rep_end();
-                                // Next instruction is 1f4: RNI, so don't spend cycle
-                            }                 
                             else {
-                                self.cycle_i(MC_JUMP); // Jump to line 1: 121
+                                // Check for REP end condition #2 (CX==0)
+                                self.cycle_i(0x12b);
+                                if self.cx == 0 {
+                                    self.rep_end();
+                                    // Next instruction is 1f4: RNI, so don't spend cycle
+                                }                 
+                                else {
+                                    self.cycle_i(MC_JUMP); // Jump to line 1: 121
+                                }
                             }
                         }
                     }
@@ -829,18 +834,21 @@ impl Cpu {
                         // Check for interrupt
                         self.cycle_i(0x11f);
                         if self.intr_pending {
+                            // RPTI stores the decremented count in CX (118)
+                            self.decrement_register16(Register16::CX);
                             self.cycle_i(MC_JUMP); // Jump to RPTI
                             self.rep_interrupt();
                         }
-                        
-                        self.cycle_i(0x1f0);
-                        self.decrement_register16(Register16::CX); //1f0
-                        if self.cx == 0 {
-                            self.rep_end();
-                        }
                         else {
-                            // Jump to 1
-                            self.cycle_i(MC_JUMP);
+                            self.cycle_i(0x1f0);
+                            self.decrement_register16(Register16::CX); //1f0
+                            if self.cx == 0 {
+                                self.rep_end();
+                            }
+                            else {
+                                // Jump to 1
+                                self.cycle_i(MC_JUMP);
+                            }
                         }
                     }
                     else {
@@ -1128,10 +1136,7 @@ impl Cpu {
                 let op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
                 
                 if !self.aam(op1_value) {
-                    self.set_szp_flags_from_result_u8(0);
-                    self.clear_flag(Flag::AuxCarry);
-                    self.clear_flag(Flag::Carry);
-                    self.clear_flag(Flag::Overflow);
+                    // The flags were left by the divide microcode.
                     self.int0();
                     jump = true;    
                     exception = CpuException::DivideError;
@@ -1402,12 +1407,12 @@ impl Cpu {
 
                 if self.intr {
                     // If an intr is pending now, execute it without actually halting.
-                    log::trace!("Halt overriden at [{:05X}]", Cpu::calc_linear_address(self.cs, self.ip()));
+                    log::trace!("Halt overriden at [{:05X}]", self.flat_ip());
                     self.halt_not_hold = false;
                 }
                 else {
                     // Actually halt
-                    log::trace!("Halt at [{:05X}]", Cpu::calc_linear_address(self.cs, self.ip()));
+                    log::trace!("Halt at [{:05X}]", self.flat_ip());
                     self.halted = true;
                     self.biu_halt();
                 }
@@ -1499,13 +1504,8 @@ impl Cpu {
                                 self.set_register8(Register8::AH, ah); // Remainder in AH
                             }
                             Err(_) => {
-
-                                self.set_szp_flags_from_result_u8(self.ah);
-                                //self.set_flag(Flag::Zero);
-                                //self.clear_flag(Flag::Sign);
-                                self.clear_flag(Flag::AuxCarry);
-                                self.clear_flag(Flag::Carry);
-                                self.clear_flag(Flag::Overflow);
+                                // The flags were left by the last flag-setting line of the
+                                // divide microcode. The return address is the next instruction.
                                 self.int0();
                                 exception = CpuException::DivideError;
                             }
@@ -1527,16 +1527,6 @@ impl Cpu {
                                 self.set_register8(Register8::AH, ah); // Remainder in AH
                             }
                             Err(_) => {
-
-                                self.set_szp_flags_from_result_u8(self.ah);
-                                //self.set_flag(Flag::Zero);
-                                //self.clear_flag(Flag::Sign);                                
-                                self.clear_flag(Flag::AuxCarry);
-                                self.clear_flag(Flag::Carry);
-                                self.clear_flag(Flag::Overflow);
-
-                                // Don't include REP prefix as part of instruction size
-                                //let size_adj = if self.i.prefixes & (OPCODE_PREFIX_REP1 | OPCODE_PREFIX_REP2) != 0 { 1 } else { 0 };
                                 self.int0();
                                 exception = CpuException::DivideError;
                             }
@@ -1627,15 +1617,7 @@ impl Cpu {
                                 self.set_register16(Register16::DX, remainder); // Remainder in DX
                             }
                             Err(_) => {
-
-                                self.set_szp_flags_from_result_u8(self.ah);
-                                //self.set_flag(Flag::Zero);
-                                //self.clear_flag(Flag::Sign);
-                                self.clear_flag(Flag::AuxCarry);
-                                self.clear_flag(Flag::Carry);
-                                self.clear_flag(Flag::Overflow);
                                 self.int0();
-
                                 exception = CpuException::DivideError;
                             }
                         }
@@ -1656,13 +1638,6 @@ impl Cpu {
                                 self.set_register16(Register16::DX, remainder); // Remainder in DX
                             }
                             Err(_) => {
-
-                                self.set_szp_flags_from_result_u8(self.ah);
-                                //self.set_flag(Flag::Zero);
-                                //self.clear_flag(Flag::Sign);
-                                self.clear_flag(Flag::AuxCarry);
-                                self.clear_flag(Flag::Carry);
-                                self.clear_flag(Flag::Overflow);
                                 self.int0();
                                 exception = CpuException::DivideError;
                             }
@@ -2083,7 +2058,10 @@ impl Cpu {
             if let RepType::MulDiv = self.rep_type {
                 // Rep prefix on MUL/DIV just sets flags, do not rep
                 self.in_rep = false;
-                ExecutionResult::Okay
+                match exception {
+                    CpuException::DivideError => ExecutionResult::ExceptionError(exception),
+                    CpuException::NoException => ExecutionResult::Okay,
+                }
             }
             else {
                 self.rep_init = true;
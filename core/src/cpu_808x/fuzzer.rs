@@ -89,6 +89,43 @@ impl Cpu {
         //self.set_flags(0);
     }
 
+    /// Load a register state previously captured from a fuzzer case. Mirrors randomize_regs()
+    /// so that a case can be replayed exactly from its saved registers.
+    #[cfg(feature = "cpu_validator")]
+    pub fn set_fuzz_regs(&mut self, regs: &crate::cpu_validator::VRegisters) {
+        self.cs = regs.cs;
+        self.pc = regs.ip;
+
+        self.set_reset_vector(CpuAddress::Segmented(self.cs, self.pc));
+        self.reset();
+
+        self.set_register16(Register16::AX, regs.ax);
+        self.set_register16(Register16::BX, regs.bx);
+        self.set_register16(Register16::CX, regs.cx);
+        self.set_register16(Register16::DX, regs.dx);
+        self.set_register16(Register16::SP, regs.sp);
+        self.set_register16(Register16::BP, regs.bp);
+        self.set_register16(Register16::SI, regs.si);
+        self.set_register16(Register16::DI, regs.di);
+
+        // Flush queue
+        self.queue.flush();
+
+        self.ds = regs.ds;
+        self.ss = regs.ss;
+        self.es = regs.es;
+
+        self.set_flags(regs.flags);
+    }
+
+    /// Copy instruction bytes to memory at CS:IP.
+    pub fn write_fuzz_bytes(&mut self, bytes: &[u8]) {
+        let addr = Cpu::calc_linear_address(self.cs, self.pc);
+        self.bus
+            .copy_from(bytes, (addr & 0xFFFFF) as usize, 0, false)
+            .expect("Mem err writing instruction");
+    }
+
     #[allow(dead_code)]
     pub fn randomize_mem(&mut self) {
         for i in 0..self.bus.size() {
@@ -30,12 +30,20 @@
 
 */
 
-use crate::cpu_808x::*;
+use crate::{
+    cpu_808x::*,
+    log_event,
+    tracelogger::{LogCategory, LogLevel},
+};
 
 impl Cpu {
     /// Execute the IRET microcode routine.
     pub fn iret_routine(&mut self) {
         self.cycle_i(0x0c8);
+        if self.is_protected_mode() {
+            self.protected_mode_return(0, true);
+            return;
+        }
         self.farret(true);
         self.pop_flags();
         self.cycle_i(0x0ca);
@@ -51,7 +59,9 @@ impl Cpu {
                     // out of laziness.
                     self.service_events.push_back(ServiceEvent::TriggerPITLogging);
 
-                    log::debug!(
+                    log_event!(
+                        LogCategory::Cpu,
+                        LogLevel::Debug,
                         "Received emulator trap interrupt: CS: {:04X} IP: {:04X}",
                         self.bx,
                         self.cx
@@ -78,8 +88,22 @@ impl Cpu {
             return;
         }
 
+        if self.int_flags[interrupt as usize] & INTERRUPT_BREAKPOINT != 0 {
+            self.break_on(BreakpointTrigger::Interrupt(interrupt));
+        }
+        if self.int_flags[interrupt as usize] & INTERRUPT_HOOK != 0 {
+            self.service_events.push_back(ServiceEvent::InterruptHook(interrupt));
+        }
+
         self.cycles_i(3, &[0x19d, 0x19e, 0x19f]);
 
+        if self.is_protected_mode() {
+            self.biu_suspend_fetch();
+            self.protected_mode_interrupt(interrupt, InterruptType::Software, None);
+            self.int_count += 1;
+            return;
+        }
+
         // Read the IVT
         let vec_addr = (interrupt as usize * INTERRUPT_VEC_LEN) as u16;
 
@@ -124,7 +148,9 @@ impl Cpu {
             self.push_u16(self.ip, ReadWriteFlag::Normal);
 
             if exception == 0x0 {
-                log::trace!(
+                log_event!(
+                    LogCategory::Cpu,
+                    LogLevel::Trace,
                     "CPU Exception: {:02X} Saving return: {:04X}:{:04X}",
                     exception,
                     self.cs,
@@ -213,7 +239,10 @@ impl Cpu {
     pub fn intr_routine(&mut self, vector: u8, itype: InterruptType, skip_first: bool) {
         // Check for interrupt breakpoint.
         if self.int_flags[vector as usize] & INTERRUPT_BREAKPOINT != 0 {
-            self.set_breakpoint_flag();
+            self.break_on(BreakpointTrigger::Interrupt(vector));
+        }
+        if self.int_flags[vector as usize] & INTERRUPT_HOOK != 0 {
+            self.service_events.push_back(ServiceEvent::InterruptHook(vector));
         }
 
         if !skip_first {
@@ -221,6 +250,12 @@ impl Cpu {
         }
         self.cycles_i(2, &[0x19e, 0x19f]);
 
+        if self.is_protected_mode() {
+            self.biu_suspend_fetch();
+            self.protected_mode_interrupt(vector, itype, None);
+            return;
+        }
+
         // Read the IVT
         let vec_addr = (vector as usize * INTERRUPT_VEC_LEN) as u16;
 
@@ -277,6 +312,7 @@ impl Cpu {
 
     /// Perform INT1 (Trap)
     pub fn int1(&mut self) {
+        self.run_single_step_hook();
         self.cycles_i(2, &[0x198, MC_JUMP]);
         self.intr_routine(1, InterruptType::Exception, true);
         self.int_count += 1;
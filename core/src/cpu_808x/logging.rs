@@ -26,17 +26,20 @@
 
     cpu_808x::logging.rs
 
-    Implements cycle-state logging facilities.
+    Implements cycle-state logging facilities and instruction history export.
 
 */
 
 use crate::{
     cpu_808x::{
         microcode::{MC_CORR, MC_JUMP, MC_NONE, MC_RTN, MICROCODE_NUL, MICROCODE_SRC_8088},
+        trace_format::*,
         BiuStateNew,
         BusStatus,
         Cpu,
         DmaState,
+        HistoryEntry,
+        MemOperand,
         QueueOp,
         Segment,
         TCycle,
@@ -49,9 +52,12 @@ use crate::{
         CPU_FLAG_SIGN,
         CPU_FLAG_TRAP,
         CPU_FLAG_ZERO,
+        MEM_OPERAND_TRACE_LEN,
     },
+    cpu_common::{HistoryExportFormat, TraceMode},
     syntax_token::SyntaxToken,
 };
+use std::io::Write;
 
 impl Cpu {
     pub fn instruction_state_string(&self, last_cs: u16, last_ip: u16) -> String {
@@ -70,11 +76,77 @@ impl Cpu {
             "CS: {:04x} DS: {:04x} ES: {:04x} SS: {:04x}\n",
             self.cs, self.ds, self.es, self.ss
         ));
-        instr_str.push_str(&format!("IP: {:04x} FLAGS: {:04x}", self.ip(), self.flags));
+        instr_str.push_str(&format!("IP: {:04x} FLAGS: {:04x}", self.ip(), self.resolved_flags()));
 
         instr_str
     }
 
+    /// Record a memory access for the instruction trace, if memory operand tracing is enabled.
+    #[inline]
+    pub fn trace_mem_operand(&mut self, write: bool, seg: Segment, offset: u16, word: bool, value: u16) {
+        if !self.trace_mem_operands {
+            return;
+        }
+        if self.mem_operands.len() < MEM_OPERAND_TRACE_LEN {
+            self.mem_operands.push(MemOperand {
+                write,
+                seg,
+                offset,
+                address: self.calc_linear_address_seg(seg, offset),
+                word,
+                value,
+            });
+        }
+        else {
+            self.mem_operands_dropped += 1;
+        }
+    }
+
+    /// Discard the memory operands recorded for the previous instruction.
+    #[inline]
+    pub fn clear_mem_operands(&mut self) {
+        self.mem_operands.clear();
+        self.mem_operands_dropped = 0;
+    }
+
+    /// Return the memory operands recorded for the current instruction.
+    pub fn mem_operands(&self) -> &[MemOperand] {
+        &self.mem_operands
+    }
+
+    /// Format the memory operands recorded for the current instruction as a trace line, one
+    /// access per entry: direction, segment:offset, linear address and the value transferred.
+    pub fn mem_operand_string(&self) -> String {
+        let mut op_str = String::from("MEM:");
+        for op in self.mem_operands.iter() {
+            let seg_str = match op.seg {
+                Segment::ES => "es",
+                Segment::CS => "cs",
+                Segment::SS => "ss",
+                Segment::DS => "ds",
+                Segment::None => "--",
+            };
+            let value_str = if op.word {
+                format!("{:04x}", op.value)
+            }
+            else {
+                format!("{:02x}", op.value)
+            };
+            op_str.push_str(&format!(
+                " {} {}:{:04x} [{:05x}]={}",
+                if op.write { "W" } else { "R" },
+                seg_str,
+                op.offset,
+                op.address,
+                value_str
+            ));
+        }
+        if self.mem_operands_dropped > 0 {
+            op_str.push_str(&format!(" (+{} more)", self.mem_operands_dropped));
+        }
+        op_str
+    }
+
     pub fn trace_csv_line(&mut self) {
         let q = self.last_queue_op as u8;
         let s = self.bus_status as u8;
@@ -138,6 +210,87 @@ impl Cpu {
         ));
     }
 
+    /// Capture the state of the current cycle for the machine-readable trace formats.
+    pub fn cycle_trace_record(&self) -> CycleTraceRecord {
+        let t_cycle = match self.t_cycle {
+            TCycle::Tinit => 0,
+            TCycle::Ti => 1,
+            TCycle::T1 => 2,
+            TCycle::T2 => 3,
+            TCycle::T3 => 4,
+            TCycle::Tw => 5,
+            TCycle::T4 => 6,
+        };
+
+        let segment = match self.bus_segment {
+            Segment::ES => 0,
+            Segment::SS => 1,
+            Segment::CS => 2,
+            Segment::DS => 3,
+            Segment::None => 4,
+        };
+
+        let signals = [
+            (self.i8288.ale, SIGNAL_ALE),
+            (self.i8288.mrdc, SIGNAL_MRDC),
+            (self.i8288.amwc, SIGNAL_AMWC),
+            (self.i8288.mwtc, SIGNAL_MWTC),
+            (self.i8288.iorc, SIGNAL_IORC),
+            (self.i8288.aiowc, SIGNAL_AIOWC),
+            (self.i8288.iowc, SIGNAL_IOWC),
+            (self.i8288.inta, SIGNAL_INTA),
+        ]
+        .iter()
+        .filter(|(active, _)| *active)
+        .fold(0, |acc, (_, mask)| acc | mask);
+
+        let mut flags = 0;
+        if self.wait_states == 0 {
+            flags |= FLAG_READY;
+        }
+        if self.intr {
+            flags |= FLAG_INTR;
+        }
+
+        CycleTraceRecord {
+            cycle: self.cycle_num,
+            instr_cycle: self.instr_cycle,
+            address: self.address_latch,
+            data_bus: self.data_bus,
+            mc_line: self.trace_instr,
+            bus_status: self.bus_status_latch as u8,
+            t_cycle,
+            segment,
+            signals,
+            flags,
+            queue_op: self.last_queue_op as u8,
+            queue_len: self.last_queue_len as u8,
+            queue_byte: self.last_queue_byte,
+        }
+    }
+
+    /// Return the writer for the current trace mode, if it is a machine-readable cycle format.
+    pub fn cycle_trace_writer(&self) -> Option<&'static dyn CycleTraceWriter> {
+        match self.trace_mode {
+            TraceMode::CycleCsv => Some(&CsvTraceWriter),
+            TraceMode::CycleJson => Some(&JsonLinesTraceWriter),
+            TraceMode::CycleBinary => Some(&BinaryTraceWriter),
+            _ => None,
+        }
+    }
+
+    /// Write the current cycle to the trace log in the current machine-readable cycle format.
+    pub fn trace_cycle_record(&mut self) {
+        if let Some(writer) = self.cycle_trace_writer() {
+            if self.trace_logger.is_some() {
+                let record = self.cycle_trace_record();
+                if let Err(e) = writer.write_record(&mut self.trace_logger, &record) {
+                    log::error!("Failed to write cycle trace record: {}", e);
+                }
+            }
+        }
+    }
+
     pub fn cycle_state_string(&self, dma_count: u16, short: bool) -> String {
         let ale_str = match self.i8288.ale {
             true => "A:",
@@ -628,6 +781,93 @@ impl Cpu {
         ]
     }
 
+    /// Write the full instruction history, oldest entry first, in the specified format.
+    /// Each entry includes the cycle timestamp, address, disassembly, cycle count and the
+    /// register state after the instruction executed. JSON output additionally includes the
+    /// instruction bytes and the registers each instruction changed.
+    pub fn export_instruction_history(&self, out: &mut impl Write, format: HistoryExportFormat) -> std::io::Result<()> {
+        if let HistoryExportFormat::Json = format {
+            // JSON is exported from the structured records, which also carry instruction bytes and
+            // the registers changed by each instruction.
+            let records = self.instruction_history(self.instruction_history.len());
+            serde_json::to_writer_pretty(&mut *out, &records)?;
+            return writeln!(out);
+        }
+        if let HistoryExportFormat::Csv = format {
+            writeln!(
+                out,
+                "timestamp,address,cs,ip,instruction,cycles,ax,bx,cx,dx,sp,bp,si,di,ds,es,ss,flags"
+            )?;
+        }
+
+        for entry in &self.instruction_history {
+            let HistoryEntry::Entry {
+                cs,
+                ip,
+                cycles,
+                timestamp,
+                i,
+                regs,
+                ..
+            } = entry;
+
+            match format {
+                HistoryExportFormat::Text => {
+                    writeln!(
+                        out,
+                        "{:>12} {:05X} [{:04X}:{:04X}] {:<32} {:>4} \
+                        AX:{:04X} BX:{:04X} CX:{:04X} DX:{:04X} SP:{:04X} BP:{:04X} SI:{:04X} DI:{:04X} \
+                        DS:{:04X} ES:{:04X} SS:{:04X} F:{}",
+                        timestamp,
+                        i.address,
+                        cs,
+                        ip,
+                        i.to_string(),
+                        cycles,
+                        regs.ax,
+                        regs.bx,
+                        regs.cx,
+                        regs.dx,
+                        regs.sp,
+                        regs.bp,
+                        regs.si,
+                        regs.di,
+                        regs.ds,
+                        regs.es,
+                        regs.ss,
+                        Cpu::flags_string(regs.flags)
+                    )?;
+                }
+                HistoryExportFormat::Csv => {
+                    writeln!(
+                        out,
+                        "{},{:05X},{:04X},{:04X},\"{}\",{},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X},{:04X}",
+                        timestamp,
+                        i.address,
+                        cs,
+                        ip,
+                        i.to_string().replace('"', "\"\""),
+                        cycles,
+                        regs.ax,
+                        regs.bx,
+                        regs.cx,
+                        regs.dx,
+                        regs.sp,
+                        regs.bp,
+                        regs.si,
+                        regs.di,
+                        regs.ds,
+                        regs.es,
+                        regs.ss,
+                        regs.flags
+                    )?;
+                }
+                HistoryExportFormat::Json => unreachable!("JSON is exported from records above"),
+            }
+        }
+        Ok(())
+    }
+
     pub fn flags_string(f: u16) -> String {
         let c_chr = if CPU_FLAG_CARRY & f != 0 { 'C' } else { 'c' };
         let p_chr = if CPU_FLAG_PARITY & f != 0 { 'P' } else { 'p' };
@@ -139,6 +139,34 @@ pub enum Mnemonic {
     XCHG,
     XLAT,
     XOR,
+    // 80286 instructions
+    ARPL,
+    BOUND,
+    CLTS,
+    ENTER,
+    INSB,
+    INSW,
+    LAR,
+    LEAVE,
+    LGDT,
+    LIDT,
+    LLDT,
+    LMSW,
+    LSL,
+    LTR,
+    OUTSB,
+    OUTSW,
+    POPA,
+    PUSHA,
+    SGDT,
+    SIDT,
+    SLDT,
+    SMSW,
+    STR,
+    VERR,
+    VERW,
+    /// An opcode the 80286 does not define. Executing one raises the invalid opcode exception.
+    Undefined,
 }
 
 impl Default for Mnemonic {
@@ -33,21 +33,27 @@
 #![allow(dead_code)]
 #![allow(clippy::unusual_byte_groupings)]
 
-use std::{collections::VecDeque, error::Error, fmt, path::Path};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    fmt,
+};
 
 use core::fmt::Display;
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 
 // Pull in all CPU module components
 mod addressing;
 mod alu;
 mod bcd;
 mod bitwise;
-mod biu;
+pub(crate) mod biu;
 mod cycle;
 mod decode;
+pub mod decode_cache;
 mod display;
 mod execute;
 mod fuzzer;
@@ -62,20 +68,31 @@ mod queue;
 mod stack;
 mod step;
 mod string;
-
-use crate::cpu_808x::{addressing::AddressingMode, microcode::*, mnemonic::Mnemonic, queue::InstructionQueue};
+pub mod trace_format;
+
+use crate::cpu_808x::{
+    addressing::AddressingMode,
+    decode_cache::{CachedInstruction, DecodeCache, DecodeTiming},
+    microcode::*,
+    mnemonic::Mnemonic,
+    queue::InstructionQueue,
+};
 // Make ReadWriteFlag available to benchmarks
 pub use crate::cpu_808x::biu::ReadWriteFlag;
 
-use crate::cpu_common::{CpuOption, CpuType, TraceMode};
+use crate::{
+    cpu_286::{DescriptorTableRegister, ProtectionFault, SegmentCache},
+    cpu_common::{CpuOption, CpuType, TraceMode},
+};
 
 #[cfg(feature = "cpu_validator")]
 use crate::cpu_validator::ValidatorType;
 
 use crate::{
-    breakpoints::BreakPointType,
+    breakpoints::{BreakPointType, BreakpointTrigger},
     bus::{BusInterface, MEM_BPA_BIT, MEM_BPE_BIT, MEM_RET_BIT},
     bytequeue::*,
+    devices::dma::{DmaHoldGrant, DMA_NORMAL_TRANSFER_CYCLES},
 };
 //use crate::interrupt::log_post_interrupt;
 
@@ -99,6 +116,8 @@ use crate::cpu_validator::{
 
 #[cfg(feature = "arduino_validator")]
 use crate::arduino8088_validator::ArduinoValidator;
+#[cfg(feature = "cpu_validator")]
+use crate::remote_validator::{RemoteValidator, REMOTE_VALIDATOR_DEFAULT_ADDRESS};
 
 macro_rules! trace_print {
     ($self:ident, $($t:tt)*) => {{
@@ -114,11 +133,48 @@ use trace_print;
 const QUEUE_MAX: usize = 6;
 const FETCH_DELAY: u8 = 2;
 
-const CPU_HISTORY_LEN: usize = 32;
+// Default depth of the instruction history, and the number of entries shown by the viewer and
+// included in error dumps.
+pub const CPU_HISTORY_LEN: usize = 32;
+// Upper bound for a configured instruction history depth.
+pub const CPU_HISTORY_MAX_LEN: usize = 100_000;
 const CPU_CALL_STACK_LEN: usize = 128;
+// Instruction bytes recorded per history entry. Longer (prefixed) instructions are truncated.
+pub const HISTORY_MAX_BYTES: usize = 8;
+// Memory operands recorded per instruction for the trace log. A REP string instruction can access
+// far more than this; the excess is counted but not recorded.
+const MEM_OPERAND_TRACE_LEN: usize = 32;
 
 const INTERRUPT_VEC_LEN: usize = 4;
 const INTERRUPT_BREAKPOINT: u8 = 1;
+const INTERRUPT_HOOK: u8 = 2;
+
+// Default trap flag delays, in instructions, applied when POPF or IRET changes the trap flag.
+pub const TRAP_ENABLE_DELAY: u32 = 2;
+pub const TRAP_DISABLE_DELAY: u32 = 1;
+
+/// A callback invoked on every INT 01 single-step trap, before the trap frame is pushed. CS:IP
+/// address the next instruction to execute, and any change the callback makes to the registers or
+/// flags (such as clearing the trap flag) is reflected in the frame the handler sees.
+pub type SingleStepHook = Box<dyn FnMut(&mut Cpu)>;
+
+/// The action requested by an instruction hook.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InstructionHookAction {
+    /// Execute the instruction normally.
+    Continue,
+    /// Do not execute the instruction. Execution resumes at the following instruction. Only
+    /// meaningful from a pre-instruction hook; post-instruction hooks should return Continue.
+    Skip,
+}
+
+/// A callback invoked before or after each instruction with the decoded instruction and a snapshot
+/// of the registers. Changes the callback makes to the snapshot are written back to the CPU,
+/// except for `pc`, which is ignored in favor of `ip`. In a pre-instruction hook, `ip` is the
+/// address of the instruction about to execute, and changing CS or IP skips the instruction and
+/// resumes execution at the new address. This allows an instruction such as INT 13h to be
+/// replaced by a host implementation without modifying guest memory.
+pub type InstructionHook = Box<dyn FnMut(&Instruction, &mut CpuRegisterState) -> InstructionHookAction>;
 
 pub const CPU_FLAG_CARRY: u16 = 0b0000_0000_0000_0001;
 pub const CPU_FLAG_RESERVED1: u16 = 0b0000_0000_0000_0010;
@@ -140,15 +196,20 @@ const CPU_FLAG_RESERVED14: u16 = 0b0100_0000_0000_0000;
 const CPU_FLAG_RESERVED15: u16 = 0b1000_0000_0000_0000;
 */
 
-const CPU_FLAGS_RESERVED_ON: u16 = 0b1111_0000_0000_0010;
+pub(crate) const CPU_FLAGS_RESERVED_ON: u16 = 0b1111_0000_0000_0010;
 const CPU_FLAGS_RESERVED_OFF: u16 = !(CPU_FLAG_RESERVED3 | CPU_FLAG_RESERVED5);
 
-const FLAGS_POP_MASK: u16 = 0b0000_1111_1101_0101;
+pub(crate) const FLAGS_POP_MASK: u16 = 0b0000_1111_1101_0101;
 
 const REGISTER_HI_MASK: u16 = 0b0000_0000_1111_1111;
 const REGISTER_LO_MASK: u16 = 0b1111_1111_0000_0000;
 
 pub const MAX_INSTRUCTION_SIZE: usize = 15;
+/// Number of cycles executed per step while halted, unless changed with set_halt_cycles().
+pub const DEFAULT_HALT_CYCLES: u32 = 3;
+/// Minimum cycles charged per instruction byte in fast core mode, approximating the time the 8088's
+/// prefetcher needs to fetch the instruction.
+pub const FAST_CORE_CYCLES_PER_BYTE: u32 = 4;
 
 const OPCODE_REGISTER_SELECT_MASK: u8 = 0b0000_0111;
 
@@ -266,6 +327,8 @@ impl Display for CpuError {
 #[derive(Copy, Clone, Debug)]
 pub enum ServiceEvent {
     TriggerPITLogging,
+    /// A vector registered with set_interrupt_hook() was entered.
+    InterruptHook(u8),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -305,6 +368,22 @@ pub enum Flag {
     Overflow,
 }
 
+/// The ALU operation whose flags are pending evaluation. See Cpu::resolved_flags().
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) enum LazyFlags {
+    /// The flags register is up to date.
+    #[default]
+    None,
+    /// SF, ZF and PF are pending.
+    Szp,
+    /// SF, ZF and PF are pending and OF is cleared, as by a logical operation.
+    Logic,
+    /// SF, ZF, PF, AF and OF are pending from an addition.
+    Add,
+    /// SF, ZF, PF, AF and OF are pending from a subtraction.
+    Sub,
+}
+
 /*
 pub enum Register {
     AH,
@@ -435,6 +514,17 @@ impl Default for RepType {
     }
 }
 
+/// A memory access made by an instruction, recorded for the instruction trace log.
+#[derive(Copy, Clone, Debug)]
+pub struct MemOperand {
+    pub write: bool,
+    pub seg: Segment,
+    pub offset: u16,
+    pub address: u32,
+    pub word: bool,
+    pub value: u16,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Segment {
     None,
@@ -483,8 +573,93 @@ pub enum InterruptType {
     Hardware,
 }
 
+/// Register state captured after an instruction in the instruction history has executed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HistoryRegisters {
+    pub ax:    u16,
+    pub bx:    u16,
+    pub cx:    u16,
+    pub dx:    u16,
+    pub sp:    u16,
+    pub bp:    u16,
+    pub si:    u16,
+    pub di:    u16,
+    pub cs:    u16,
+    pub ds:    u16,
+    pub ss:    u16,
+    pub es:    u16,
+    pub ip:    u16,
+    pub flags: u16,
+}
+
+impl HistoryRegisters {
+    fn named(&self) -> [(&'static str, u16); 14] {
+        [
+            ("ax", self.ax),
+            ("bx", self.bx),
+            ("cx", self.cx),
+            ("dx", self.dx),
+            ("sp", self.sp),
+            ("bp", self.bp),
+            ("si", self.si),
+            ("di", self.di),
+            ("cs", self.cs),
+            ("ds", self.ds),
+            ("ss", self.ss),
+            ("es", self.es),
+            ("ip", self.ip),
+            ("flags", self.flags),
+        ]
+    }
+
+    /// Return the registers that differ from `prev`, or all registers if there is no previous state.
+    pub fn changed_from(&self, prev: Option<&HistoryRegisters>) -> BTreeMap<&'static str, u16> {
+        match prev {
+            Some(prev) => self
+                .named()
+                .into_iter()
+                .zip(prev.named())
+                .filter(|((_, value), (_, prev_value))| value != prev_value)
+                .map(|(reg, _)| reg)
+                .collect(),
+            None => self.named().into_iter().collect(),
+        }
+    }
+}
+
 pub enum HistoryEntry {
-    Entry { cs: u16, ip: u16, cycles: u16, i: Instruction },
+    Entry {
+        cs: u16,
+        ip: u16,
+        cycles: u16,
+        /// Value of the CPU cycle counter when the instruction completed.
+        timestamp: u64,
+        i: Instruction,
+        /// Instruction bytes as read from memory when the instruction completed.
+        bytes: [u8; HISTORY_MAX_BYTES],
+        regs: HistoryRegisters,
+    },
+}
+
+/// A structured instruction history entry, as returned by Cpu::instruction_history().
+#[derive(Clone, Debug, Serialize)]
+pub struct InstructionRecord {
+    /// Value of the CPU cycle counter when the instruction completed.
+    pub timestamp: u64,
+    pub address: u32,
+    pub cs: u16,
+    pub ip: u16,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    pub cycles: u16,
+    /// Cycles of `cycles` spent calculating the effective address, including reading any
+    /// displacement. See cpu_common::ea for the fixed portion of this cost.
+    pub ea_cycles: u16,
+    /// Register state after the instruction executed.
+    pub regs: HistoryRegisters,
+    /// Registers changed by the instruction. The oldest entry in the history has no previous
+    /// state to compare against and lists every register.
+    pub regs_changed: BTreeMap<&'static str, u16>,
 }
 
 #[derive(Copy, Clone)]
@@ -517,6 +692,8 @@ pub struct Instruction {
     pub operand1_size: OperandSize,
     pub operand2_type: OperandType,
     pub operand2_size: OperandSize,
+    pub operand3_type: OperandType, // Immediate operand of the 80286 three-operand IMUL
+    pub ea_cycles: u8,              // Cycles spent in effective address calculation during decode
 }
 
 impl Default for Instruction {
@@ -533,6 +710,8 @@ impl Default for Instruction {
             operand1_size: OperandSize::NoOperand,
             operand2_type: OperandType::NoOperand,
             operand2_size: OperandSize::NoOperand,
+            operand3_type: OperandType::NoOperand,
+            ea_cycles: 0,
         }
     }
 }
@@ -623,45 +802,52 @@ pub struct Cpu {
     cpu_type: CpuType,
     state:    CpuState,
 
-    ah:    u8,
-    al:    u8,
-    ax:    u16,
-    bh:    u8,
-    bl:    u8,
-    bx:    u16,
-    ch:    u8,
-    cl:    u8,
-    cx:    u16,
-    dh:    u8,
-    dl:    u8,
-    dx:    u16,
-    sp:    u16,
-    bp:    u16,
-    si:    u16,
-    di:    u16,
-    cs:    u16,
-    ds:    u16,
-    ss:    u16,
-    es:    u16,
+    pub(crate) ah:    u8,
+    pub(crate) al:    u8,
+    pub(crate) ax:    u16,
+    pub(crate) bh:    u8,
+    pub(crate) bl:    u8,
+    pub(crate) bx:    u16,
+    pub(crate) ch:    u8,
+    pub(crate) cl:    u8,
+    pub(crate) cx:    u16,
+    pub(crate) dh:    u8,
+    pub(crate) dl:    u8,
+    pub(crate) dx:    u16,
+    pub(crate) sp:    u16,
+    pub(crate) bp:    u16,
+    pub(crate) si:    u16,
+    pub(crate) di:    u16,
+    pub(crate) cs:    u16,
+    pub(crate) ds:    u16,
+    pub(crate) ss:    u16,
+    pub(crate) es:    u16,
     //ip:    u16,
-    flags: u16,
+    pub(crate) flags: u16,
+    // SF, ZF and PF, and AF and OF for arithmetic operations, are evaluated lazily from the
+    // operands and result of the last operation that set them.
+    pub(crate) lazy_flags: LazyFlags,
+    lazy_word: bool,
+    lazy_operand1: u16,
+    lazy_operand2: u16,
+    lazy_result: u16,
 
     address_bus: u32,
     address_latch: u32,
     data_bus: u16,
-    last_ea: u16,      // Last calculated effective address. Used by 0xFE instructions
-    bus: BusInterface, // CPU owns Bus
-    i8288: I8288,      // Intel 8288 Bus Controller
-    pc: u16,           // Program counter points to the next instruction to be fetched
-    mc_pc: u16,        // Microcode program counter.
+    last_ea: u16,                 // Last calculated effective address. Used by 0xFE instructions
+    pub(crate) bus: BusInterface, // CPU owns Bus
+    i8288: I8288,                 // Intel 8288 Bus Controller
+    pub(crate) pc: u16,           // Program counter points to the next instruction to be fetched
+    mc_pc: u16,                   // Microcode program counter.
     nx: bool,
     rni: bool,
     ea_opr: u16, // Operand loaded by EALOAD. Masked to 8 bits as appropriate.
 
-    intr: bool,         // State of INTR line
-    intr_pending: bool, // INTR line active and not processed
+    intr: bool,                    // State of INTR line
+    pub(crate) intr_pending: bool, // INTR line active and not processed
     in_int: bool,
-    int_count: u64,
+    pub(crate) int_count: u64,
     iret_count: u64,
     interrupt_inhibit: bool,
 
@@ -677,7 +863,7 @@ pub struct Cpu {
     // BIU stuff
     biu_state_new: BiuStateNew, // State of BIU: Idle, EU, PF (Prefetcher) or transition state
     ready: bool,                // READY line from 8284
-    queue: InstructionQueue,
+    pub(crate) queue: InstructionQueue,
     fetch_size: TransferSize,
     fetch_state: FetchState,
     next_fetch_state: FetchState,
@@ -698,10 +884,12 @@ pub struct Cpu {
     final_transfer: bool, // Flag that determines if the current bus transfer is the final transfer for this bus request
     bus_wait_states: u32,
     wait_states: u32,
+    instr_wait_states: u32, // Bus wait states incurred during the current instruction.
     lock: bool, // LOCK pin. Asserted during 2nd INTA bus cycle.
 
     // Halt-related stuff
     halted: bool,
+    halt_cycles: u32,    // Cycles to execute per step while halted
     halt_not_hold: bool, // Internal halt signal
     wake_timer: u32,
 
@@ -709,7 +897,7 @@ pub struct Cpu {
     is_error:   bool,
 
     // Rep prefix handling
-    in_rep: bool,
+    pub(crate) in_rep: bool,
     rep_init: bool,
     rep_mnemonic: Mnemonic,
     rep_type: RepType,
@@ -723,16 +911,18 @@ pub struct Cpu {
     int_elapsed: u32,
     instr_elapsed: u32,
     instruction_count: u64,
-    i: Instruction, // Currently executing instruction
-    instruction_ip: u16,
+    pub(crate) i: Instruction, // Currently executing instruction
+    pub(crate) instruction_ip: u16,
     instruction_address: u32,
     instruction_history_on: bool,
+    instruction_history_len: usize,
     instruction_history: VecDeque<HistoryEntry>,
     call_stack: VecDeque<CallStackEntry>,
     exec_result: ExecutionResult,
 
     // Breakpoints
     breakpoints: Vec<BreakPointType>,
+    breakpoint_trigger: Option<BreakpointTrigger>,
 
     step_over_target: Option<CpuAddress>,
 
@@ -750,6 +940,7 @@ pub struct Cpu {
     enable_wait_states: bool,
     off_rails_detection: bool,
     opcode0_counter: u32,
+    fast_core: bool,
 
     rng: Option<rand::rngs::StdRng>,
 
@@ -776,19 +967,43 @@ pub struct Cpu {
     dram_refresh_cycle_period: u32,
     dram_refresh_cycle_num: u32,
     dram_refresh_adjust: u32,
+    dram_refresh_wait_states: u32,
     dma_aen: bool,
     dma_wait_states: u32,
+    dma_hold_grant: Option<DmaHoldGrant>, // Device transfer being serviced, or None for DRAM refresh.
+    dma_hold_device: bool,                // The current hold was requested by a device transfer.
 
     // Trap stuff
-    trap_enable_delay:  u32,  // Number of cycles to delay trap flag enablement.
-    trap_disable_delay: u32,  // Number of cycles to delay trap flag disablement.
-    trap_suppressed:    bool, // Suppress trap handling for the last executed instruction.
+    pub(crate) trap_enable_delay: u32,     // Number of cycles to delay trap flag enablement.
+    trap_disable_delay: u32,               // Number of cycles to delay trap flag disablement.
+    trap_suppressed: bool,                 // Suppress trap handling for the last executed instruction.
+    pub(crate) trap_enable_delay_len: u32, // Delay applied when the trap flag is set.
+    trap_disable_delay_len: u32,           // Delay applied when the trap flag is cleared.
+    single_step_hook: Option<SingleStepHook>,
+    pre_instruction_hook: Option<InstructionHook>,
+    post_instruction_hook: Option<InstructionHook>,
 
     nmi: bool,           // Status of NMI line.
     nmi_triggered: bool, // Has NMI been edge-triggered?
 
     halt_resume_delay: u32,
     int_flags: Vec<u8>,
+
+    decode_cache: Option<DecodeCache>,
+
+    trace_mem_operands: bool,
+    mem_operands: Vec<MemOperand>,
+    mem_operands_dropped: usize,
+
+    // 80286 state
+    pub(crate) msw: u16,                      // Machine Status Word
+    pub(crate) gdtr: DescriptorTableRegister, // Global descriptor table
+    pub(crate) idtr: DescriptorTableRegister, // Interrupt descriptor table
+    pub(crate) ldtr: SegmentCache,            // Local descriptor table selector and descriptor
+    pub(crate) tr: SegmentCache,              // Task register selector and descriptor
+    pub(crate) seg_cache: [SegmentCache; 4],  // Descriptor caches for ES, CS, SS and DS
+    pub(crate) iopl_nt: u16,                  // IOPL and NT flag bits, only visible in protected mode
+    pub(crate) pending_fault: Option<ProtectionFault>,
 }
 
 #[cfg(feature = "cpu_validator")]
@@ -807,6 +1022,7 @@ impl Default for CpuValidatorState {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct CpuRegisterState {
     pub ah:    u8,
     pub al:    u8,
@@ -833,7 +1049,7 @@ pub struct CpuRegisterState {
     pub flags: u16,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Hash)]
 pub struct CpuStringState {
     pub ah: String,
     pub al: String,
@@ -871,6 +1087,7 @@ pub struct CpuStringState {
     pub piq: String,
     pub instruction_count: String,
     pub cycle_count: String,
+    pub wait_states: String,
 }
 
 /*
@@ -1000,6 +1217,7 @@ impl Cpu {
         #[cfg(feature = "cpu_validator")] validator_trace: TraceLogger,
         #[cfg(feature = "cpu_validator")] validator_mode: ValidatorMode,
         #[cfg(feature = "cpu_validator")] validator_baud: u32,
+        #[cfg(feature = "cpu_validator")] validator_address: Option<String>,
     ) -> Self {
         let mut cpu: Cpu = Default::default();
 
@@ -1008,7 +1226,7 @@ impl Cpu {
                 cpu.queue.set_size(4);
                 cpu.fetch_size = TransferSize::Byte;
             }
-            CpuType::Intel8086 => {
+            CpuType::Intel8086 | CpuType::Intel80286 => {
                 cpu.queue.set_size(6);
                 cpu.fetch_size = TransferSize::Word;
             }
@@ -1019,6 +1237,15 @@ impl Cpu {
             cpu.validator = match validator_type {
                 #[cfg(feature = "arduino_validator")]
                 ValidatorType::Arduino8088 => Some(Box::new(ArduinoValidator::new(validator_trace, validator_baud))),
+                ValidatorType::Remote => {
+                    let address = validator_address.unwrap_or(REMOTE_VALIDATOR_DEFAULT_ADDRESS.to_string());
+                    match RemoteValidator::new(validator_trace, &address) {
+                        Ok(validator) => Some(Box::new(validator)),
+                        Err(e) => {
+                            panic!("Failed to connect to remote validator at {}: {}", address, e);
+                        }
+                    }
+                }
                 _ => None,
             };
 
@@ -1036,10 +1263,22 @@ impl Cpu {
         cpu.trace_mode = trace_mode;
         cpu.cpu_type = cpu_type;
 
+        if let Some(writer) = cpu.cycle_trace_writer() {
+            if cpu.trace_logger.is_some() {
+                if let Err(e) = writer.write_header(&mut cpu.trace_logger) {
+                    log::error!("Failed to write cycle trace header: {}", e);
+                }
+            }
+        }
+
         //cpu.instruction_history_on = true; // Control this from config/GUI instead
-        cpu.instruction_history = VecDeque::with_capacity(16);
+        cpu.instruction_history_len = CPU_HISTORY_LEN;
+        cpu.instruction_history = VecDeque::with_capacity(CPU_HISTORY_LEN);
 
         cpu.reset_vector = CpuAddress::Segmented(0xFFFF, 0x0000);
+        cpu.dram_refresh_wait_states = 6;
+        cpu.trap_enable_delay_len = TRAP_ENABLE_DELAY;
+        cpu.trap_disable_delay_len = TRAP_DISABLE_DELAY;
         cpu.reset();
         cpu
     }
@@ -1091,6 +1330,9 @@ impl Cpu {
         self.set_register16(Register16::DS, 0);
 
         self.flags = CPU_FLAGS_RESERVED_ON;
+        self.lazy_flags = LazyFlags::None;
+
+        self.reset_286();
 
         self.queue.flush();
 
@@ -1126,7 +1368,10 @@ impl Cpu {
         self.is_error = false;
         self.instruction_history.clear();
         self.call_stack.clear();
-        self.int_flags = vec![0; 256];
+        self.breakpoint_trigger = None;
+        // Interrupt hooks are registered by the machine and survive a reset.
+        self.int_flags.resize(256, 0);
+        self.int_flags.iter_mut().for_each(|flags| *flags &= INTERRUPT_HOOK);
 
         self.queue_op = QueueOp::Idle;
         self.last_queue_op = QueueOp::Idle;
@@ -1147,6 +1392,7 @@ impl Cpu {
         self.rni = false;
 
         self.halt_resume_delay = 4;
+        self.halt_cycles = DEFAULT_HALT_CYCLES;
 
         // Reset takes 6 cycles before first fetch
         self.cycle();
@@ -1183,11 +1429,11 @@ impl Cpu {
     /// Return the resolved flat address of CS:CORR(PC)
     #[inline]
     pub fn flat_ip(&self) -> u32 {
-        Cpu::calc_linear_address(self.cs, self.ip())
+        self.calc_linear_address_seg(Segment::CS, self.ip())
     }
 
     pub fn flat_sp(&self) -> u32 {
-        Cpu::calc_linear_address(self.ss, self.sp)
+        self.calc_linear_address_seg(Segment::SS, self.sp)
     }
 
     /// Execute the CORR (Correct PC) microcode routine.
@@ -1204,6 +1450,10 @@ impl Cpu {
         self.in_rep
     }
 
+    pub fn cpu_type(&self) -> CpuType {
+        self.cpu_type
+    }
+
     pub fn bus(&self) -> &BusInterface {
         &self.bus
     }
@@ -1257,8 +1507,9 @@ impl Cpu {
 
     #[cfg(feature = "cpu_validator")]
     pub fn get_cycle_state(&mut self) -> CycleState {
-        let mut q = [0; 4];
-        self.queue.to_slice(&mut q);
+        let mut q = [0; QUEUE_MAX];
+        let size = self.queue.size();
+        self.queue.to_slice(&mut q[..size]);
 
         CycleState {
             n: self.instr_cycle,
@@ -1328,6 +1579,9 @@ impl Cpu {
             // self.interrupt_inhibit = true;
             //}
         }
+        if let Flag::Parity | Flag::AuxCarry | Flag::Zero | Flag::Sign | Flag::Overflow = flag {
+            self.resolve_flags();
+        }
 
         self.flags |= match flag {
             Flag::Carry => CPU_FLAG_CARRY,
@@ -1344,6 +1598,9 @@ impl Cpu {
 
     #[inline(always)]
     pub fn clear_flag(&mut self, flag: Flag) {
+        if let Flag::Parity | Flag::AuxCarry | Flag::Zero | Flag::Sign | Flag::Overflow = flag {
+            self.resolve_flags();
+        }
         self.flags &= match flag {
             Flag::Carry => !CPU_FLAG_CARRY,
             Flag::Parity => !CPU_FLAG_PARITY,
@@ -1364,6 +1621,7 @@ impl Cpu {
         flags |= CPU_FLAGS_RESERVED_ON;
 
         self.flags = flags;
+        self.lazy_flags = LazyFlags::None;
     }
 
     #[inline(always)]
@@ -1379,6 +1637,8 @@ impl Cpu {
     pub fn store_flags(&mut self, bits: u16) {
         // Clear SF, ZF, AF, PF & CF flags
         let flag_mask = !(CPU_FLAG_CARRY | CPU_FLAG_PARITY | CPU_FLAG_AUX_CARRY | CPU_FLAG_ZERO | CPU_FLAG_SIGN);
+        // A pending Overflow flag is not replaced, so must be committed.
+        self.resolve_flags();
         self.flags &= flag_mask;
 
         // Copy flag state
@@ -1387,11 +1647,14 @@ impl Cpu {
 
     pub fn load_flags(&mut self) -> u16 {
         // Return 8 LO bits of flags register
-        self.flags & 0x00FF
+        self.resolved_flags() & 0x00FF
     }
 
     #[inline]
     pub fn get_flag(&self, flag: Flag) -> bool {
+        if let Some(state) = self.pending_flag(&flag) {
+            return state;
+        }
         let mut flags = self.flags;
         flags &= match flag {
             Flag::Carry => CPU_FLAG_CARRY,
@@ -1429,7 +1692,7 @@ impl Cpu {
             si:    self.si,
             di:    self.di,
             ip:    self.ip(),
-            flags: self.flags,
+            flags: self.resolved_flags(),
         }
     }
 
@@ -1497,7 +1760,7 @@ impl Cpu {
 
     #[inline]
     pub fn get_flags(&self) -> u16 {
-        self.flags
+        self.resolved_flags()
     }
 
     // Sets one of the 8 bit registers.
@@ -1647,8 +1910,72 @@ impl Cpu {
             es:    self.es,
             ip:    self.ip(),
             pc:    self.pc,
-            flags: self.flags,
+            flags: self.resolved_flags(),
+        }
+    }
+
+    /// Return the current SS:SP, identifying the stack frame of the executing procedure.
+    pub fn stack_frame(&self) -> (u16, u16) {
+        (self.ss, self.sp)
+    }
+
+    /// Return whether the last instruction executed was a RET, RETF or IRET that returned from
+    /// the stack frame at the specified SS:SP. Returns from procedures called within that frame
+    /// leave SP at or below the frame, so are not matched.
+    pub fn returned_from(&self, ss: u16, sp: u16) -> bool {
+        matches!(self.i.mnemonic, Mnemonic::RETN | Mnemonic::RETF | Mnemonic::IRET)
+            && self.ss == ss
+            && (self.sp.wrapping_sub(sp) as i16) > 0
+    }
+
+    /// Return the CS:IP of the instruction the next step will execute. If a REP string
+    /// instruction is in progress, this is the start of that instruction.
+    pub fn instruction_csip(&self) -> (u16, u16) {
+        if self.in_rep {
+            (self.cs, self.instruction_ip)
+        }
+        else {
+            (self.cs, self.ip())
+        }
+    }
+
+    /// Capture the register state at the current instruction boundary for step-back. If a REP
+    /// string instruction is in progress, IP addresses the start of that instruction so that it
+    /// resumes from the restored count.
+    pub fn rewind_state(&self) -> CpuRegisterState {
+        let mut state = self.get_state();
+        (_, state.ip) = self.instruction_csip();
+        state
+    }
+
+    /// Restore a register state captured by rewind_state(). Any REP string instruction in
+    /// progress is abandoned, and any fetch in progress is completed before the queue is flushed,
+    /// so execution resumes by fetching from the restored CS:IP.
+    pub fn restore_state(&mut self, state: &CpuRegisterState, halted: bool) {
+        // Let any fetch in progress complete before PC is replaced.
+        self.biu_suspend_fetch();
+        self.cycles(2);
+
+        self.set_register16(Register16::AX, state.ax);
+        self.set_register16(Register16::BX, state.bx);
+        self.set_register16(Register16::CX, state.cx);
+        self.set_register16(Register16::DX, state.dx);
+        self.set_register16(Register16::SP, state.sp);
+        self.set_register16(Register16::BP, state.bp);
+        self.set_register16(Register16::SI, state.si);
+        self.set_register16(Register16::DI, state.di);
+        self.set_register16(Register16::CS, state.cs);
+        self.set_register16(Register16::DS, state.ds);
+        self.set_register16(Register16::SS, state.ss);
+        self.set_register16(Register16::ES, state.es);
+        self.set_register16(Register16::PC, state.ip);
+        self.set_flags(state.flags);
+
+        if self.in_rep {
+            self.rep_end();
         }
+        self.halted = halted;
+        self.biu_queue_flush();
     }
 
     /// Get a string representation of the CPU state.
@@ -1678,46 +2005,47 @@ impl Cpu {
             ip:   format!("{:04x}", self.ip()),
             pc:   format!("{:04x}", self.pc),
             c_fl: {
-                let fl = self.flags & CPU_FLAG_CARRY > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_CARRY > 0;
                 format!("{:1}", fl as u8)
             },
             p_fl: {
-                let fl = self.flags & CPU_FLAG_PARITY > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_PARITY > 0;
                 format!("{:1}", fl as u8)
             },
             a_fl: {
-                let fl = self.flags & CPU_FLAG_AUX_CARRY > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_AUX_CARRY > 0;
                 format!("{:1}", fl as u8)
             },
             z_fl: {
-                let fl = self.flags & CPU_FLAG_ZERO > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_ZERO > 0;
                 format!("{:1}", fl as u8)
             },
             s_fl: {
-                let fl = self.flags & CPU_FLAG_SIGN > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_SIGN > 0;
                 format!("{:1}", fl as u8)
             },
             t_fl: {
-                let fl = self.flags & CPU_FLAG_TRAP > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_TRAP > 0;
                 format!("{:1}", fl as u8)
             },
             i_fl: {
-                let fl = self.flags & CPU_FLAG_INT_ENABLE > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_INT_ENABLE > 0;
                 format!("{:1}", fl as u8)
             },
             d_fl: {
-                let fl = self.flags & CPU_FLAG_DIRECTION > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_DIRECTION > 0;
                 format!("{:1}", fl as u8)
             },
             o_fl: {
-                let fl = self.flags & CPU_FLAG_OVERFLOW > 0;
+                let fl = self.resolved_flags() & CPU_FLAG_OVERFLOW > 0;
                 format!("{:1}", fl as u8)
             },
 
             piq: self.queue.to_string(),
-            flags: format!("{:04}", self.flags),
+            flags: format!("{:04}", self.resolved_flags()),
             instruction_count: format!("{}", self.instruction_count),
             cycle_count: format!("{}", self.cycle_num),
+            wait_states: format!("{}", self.instr_wait_states),
         }
     }
 
@@ -1828,6 +2156,14 @@ impl Cpu {
         }
     }
 
+    /// Return the vector of the innermost interrupt on the call stack, if any.
+    pub fn current_interrupt(&self) -> Option<u8> {
+        self.call_stack.iter().rev().find_map(|entry| match entry {
+            CallStackEntry::Interrupt { number, .. } => Some(*number),
+            _ => None,
+        })
+    }
+
     /// Rewind the call stack to the specified address.
     ///
     /// We have to rewind the call stack to the earliest appearance of this address we returned to,
@@ -1870,6 +2206,108 @@ impl Cpu {
         }
     }
 
+    /// Decode the instruction at the specified linear address directly from memory, bypassing the
+    /// instruction queue. The decode cache is used if enabled.
+    pub fn decode_at(&mut self, address: u32) -> Result<Instruction, Box<dyn Error>> {
+        match &mut self.decode_cache {
+            Some(cache) => cache.decode_cpu(&mut self.bus, address, self.cpu_type),
+            None => {
+                self.bus.seek(address as usize);
+                let mut i = Cpu::decode_cpu(&mut self.bus, self.cpu_type)?;
+                i.address = address;
+                Ok(i)
+            }
+        }
+    }
+
+    /// Decode the next instruction in fast core mode through the decode cache. A cached decode
+    /// advances PC and charges cycles as the decoder did when the instruction was first decoded,
+    /// so execution and timing match decoding it again.
+    pub(crate) fn decode_fast_cached(&mut self, address: u32) -> Result<Instruction, Box<dyn Error>> {
+        // Instructions that may wrap around the end of the code segment are not cached.
+        let cacheable = self.pc as usize + MAX_INSTRUCTION_SIZE <= 0x10000;
+        if cacheable {
+            let cached = self
+                .decode_cache
+                .as_mut()
+                .and_then(|cache| cache.lookup(&mut self.bus, address))
+                .and_then(|entry| entry.timing.map(|timing| (entry.i, timing)));
+            if let Some((i, timing)) = cached {
+                self.pc = self.pc.wrapping_add(timing.fetched);
+                self.cycles(timing.cycles);
+                return Ok(i);
+            }
+        }
+
+        let (start_pc, start_cycle) = (self.pc, self.instr_cycle);
+        let cpu_type = self.cpu_type;
+        let mut i = Cpu::decode_cpu(self, cpu_type)?;
+        i.address = address;
+        if cacheable {
+            let timing = DecodeTiming {
+                fetched: self.pc.wrapping_sub(start_pc),
+                cycles:  self.instr_cycle - start_cycle,
+            };
+            if let Some(cache) = &mut self.decode_cache {
+                cache.insert(
+                    &mut self.bus,
+                    CachedInstruction {
+                        i,
+                        timing: Some(timing),
+                    },
+                );
+            }
+        }
+        Ok(i)
+    }
+
+    /// Return the decode cache hit and miss counts, if the decode cache is enabled.
+    pub fn decode_cache_stats(&self) -> Option<(u64, u64)> {
+        self.decode_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    #[inline]
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Set the number of cycles to execute in a single step while halted. Larger values reduce
+    /// overhead while the guest is idle, at the cost of delaying recognition of an interrupt
+    /// raised during the step.
+    pub fn set_halt_cycles(&mut self, cycles: u32) {
+        self.halt_cycles = cycles.max(1);
+    }
+
+    /// Switch between the cycle-accurate core and the fast core. This should only be called on an
+    /// instruction boundary.
+    ///
+    /// The fast core does not simulate the BIU, prefetcher or individual bus T-states. Instructions
+    /// are read directly from memory, bus transfers complete immediately with a fixed cost, and
+    /// instruction timing is approximated from the execution unit's cycle counts.
+    pub fn set_fast_core(&mut self, state: bool) {
+        if state == self.fast_core {
+            return;
+        }
+
+        // Discard the contents of the queue, pointing PC at the next instruction to execute.
+        self.pc = self.ip();
+        self.queue.flush();
+        self.bus_status = BusStatus::Passive;
+        self.bus_status_latch = BusStatus::Passive;
+        self.t_cycle = TCycle::T1;
+        self.fast_core = state;
+
+        if !state {
+            // Restart prefetching so that the next instruction can be fetched.
+            self.biu_queue_flush();
+        }
+    }
+
+    #[inline]
+    pub fn is_fast_core(&self) -> bool {
+        self.fast_core
+    }
+
     /// Resume from halted state
     pub fn resume(&mut self) {
         if self.halted {
@@ -1923,10 +2361,11 @@ impl Cpu {
                 self.bus.clear_flags(*addr as usize, MEM_BPA_BIT);
             }
             BreakPointType::Interrupt(vector) => {
-                self.int_flags[*vector as usize] = 0;
+                self.int_flags[*vector as usize] &= !INTERRUPT_BREAKPOINT;
             }
             _ => {}
         });
+        self.bus.clear_video_breakpoints();
 
         // Replace current breakpoint list
         self.breakpoints = bp_list;
@@ -1942,7 +2381,10 @@ impl Cpu {
                 self.bus.set_flags(*addr as usize, MEM_BPA_BIT);
             }
             BreakPointType::Interrupt(vector) => {
-                self.int_flags[*vector as usize] = INTERRUPT_BREAKPOINT;
+                self.int_flags[*vector as usize] |= INTERRUPT_BREAKPOINT;
+            }
+            BreakPointType::VideoWrite(bp) => {
+                self.bus.add_video_breakpoint(*bp);
             }
             _ => {}
         });
@@ -1961,16 +2403,123 @@ impl Cpu {
         self.state = CpuState::BreakpointHit;
     }
 
+    /// Enter the BreakpointHit state, recording what caused it.
+    pub fn break_on(&mut self, trigger: BreakpointTrigger) {
+        self.breakpoint_trigger = Some(trigger);
+        self.state = CpuState::BreakpointHit;
+    }
+
+    /// Return what caused the most recent BreakpointHit state, if known, and clear it.
+    pub fn take_breakpoint_trigger(&mut self) -> Option<BreakpointTrigger> {
+        self.breakpoint_trigger.take()
+    }
+
     pub fn clear_breakpoint_flag(&mut self) {
         self.state = CpuState::Normal;
     }
 
+    /// Record a completed instruction in the instruction history, discarding the oldest entry
+    /// once the configured depth is reached.
+    fn push_instruction_history(&mut self, cs: u16, ip: u16) {
+        while self.instruction_history.len() >= self.instruction_history_len {
+            self.instruction_history.pop_front();
+        }
+        let mut bytes = [0; HISTORY_MAX_BYTES];
+        for (n, byte) in bytes.iter_mut().take(self.i.size as usize).enumerate() {
+            *byte = self.bus.peek_u8(self.i.address as usize + n).unwrap_or(0);
+        }
+        self.instruction_history.push_back(HistoryEntry::Entry {
+            cs,
+            ip,
+            cycles: self.instr_cycle as u16,
+            timestamp: self.cycle_num,
+            i: self.i,
+            bytes,
+            regs: HistoryRegisters {
+                ax:    self.ax,
+                bx:    self.bx,
+                cx:    self.cx,
+                dx:    self.dx,
+                sp:    self.sp,
+                bp:    self.bp,
+                si:    self.si,
+                di:    self.di,
+                cs:    self.cs,
+                ds:    self.ds,
+                ss:    self.ss,
+                es:    self.es,
+                ip:    self.ip(),
+                flags: self.resolved_flags(),
+            },
+        });
+    }
+
+    /// Return the most recent `count` entries of the instruction history, oldest first.
+    fn recent_instruction_history(&self, count: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.instruction_history
+            .iter()
+            .skip(self.instruction_history.len().saturating_sub(count))
+    }
+
+    /// Return the most recent `count` entries of the instruction history as structured records,
+    /// oldest first.
+    pub fn instruction_history(&self, count: usize) -> Vec<InstructionRecord> {
+        let skip = self.instruction_history.len().saturating_sub(count);
+        let mut prev_regs = skip.checked_sub(1).and_then(|n| {
+            self.instruction_history
+                .get(n)
+                .map(|HistoryEntry::Entry { regs, .. }| *regs)
+        });
+
+        let mut records = Vec::with_capacity(self.instruction_history.len() - skip);
+        for entry in self.instruction_history.iter().skip(skip) {
+            let HistoryEntry::Entry {
+                cs,
+                ip,
+                cycles,
+                timestamp,
+                i,
+                bytes,
+                regs,
+            } = entry;
+
+            let len = (i.size as usize).min(HISTORY_MAX_BYTES);
+            records.push(InstructionRecord {
+                timestamp: *timestamp,
+                address: i.address,
+                cs: *cs,
+                ip: *ip,
+                bytes: bytes[..len].to_vec(),
+                disassembly: i.to_string(),
+                cycles: *cycles,
+                ea_cycles: i.ea_cycles as u16,
+                regs: *regs,
+                regs_changed: regs.changed_from(prev_regs.as_ref()),
+            });
+            prev_regs = Some(*regs);
+        }
+        records
+    }
+
+    /// Return the number of bus wait states incurred by the last executed instruction, including
+    /// wait states from memory-mapped devices, memory range descriptors and IO.
+    pub fn instr_wait_states(&self) -> u32 {
+        self.instr_wait_states
+    }
+
+    pub fn instruction_history_len(&self) -> usize {
+        self.instruction_history_len
+    }
+
+    /// Dump the most recent instructions in the history as a string. Only the last CPU_HISTORY_LEN
+    /// entries are included regardless of the configured depth; use export_instruction_history()
+    /// to retrieve the full history.
     pub fn dump_instruction_history_string(&self) -> String {
         let mut disassembly_string = String::new();
 
-        for i in &self.instruction_history {
+        for i in self.recent_instruction_history(CPU_HISTORY_LEN) {
             match i {
-                HistoryEntry::Entry { cs, ip, cycles: _, i } => {
+                HistoryEntry::Entry { cs, ip, i, .. } => {
                     let i_string = format!("{:05X} [{:04X}:{:04X}] {}\n", i.address, *cs, *ip, i);
                     disassembly_string.push_str(&i_string);
                 }
@@ -1979,13 +2528,14 @@ impl Cpu {
         disassembly_string
     }
 
-    pub fn dump_instruction_history_tokens(&self) -> Vec<Vec<SyntaxToken>> {
+    /// Tokenize the most recent `count` instructions in the history for display.
+    pub fn dump_instruction_history_tokens(&self, count: usize) -> Vec<Vec<SyntaxToken>> {
         let mut history_vec = Vec::new();
 
-        for i in &self.instruction_history {
+        for i in self.recent_instruction_history(count) {
             let mut i_token_vec = Vec::new();
             match i {
-                HistoryEntry::Entry { cs, ip, cycles, i } => {
+                HistoryEntry::Entry { cs, ip, cycles, i, .. } => {
                     i_token_vec.push(SyntaxToken::MemoryAddressFlat(i.address, format!("{:05X}", i.address)));
                     i_token_vec.push(SyntaxToken::MemoryAddressSeg16(
                         *cs,
@@ -1995,7 +2545,12 @@ impl Cpu {
                     i_token_vec.push(SyntaxToken::InstructionBytes(format!("{:012}", "".to_string())));
                     i_token_vec.extend(i.tokenize());
                     i_token_vec.push(SyntaxToken::Formatter(SyntaxFormatType::Tab));
-                    i_token_vec.push(SyntaxToken::Text(format!("{}", *cycles)));
+                    if i.ea_cycles > 0 {
+                        i_token_vec.push(SyntaxToken::Text(format!("{} (EA {})", *cycles, i.ea_cycles)));
+                    }
+                    else {
+                        i_token_vec.push(SyntaxToken::Text(format!("{}", *cycles)));
+                    }
                 }
             }
             history_vec.push(i_token_vec);
@@ -2096,33 +2651,141 @@ impl Cpu {
         assert_eq!(self.cx, cx_should);
         assert_eq!(self.dx, dx_should);
 
-        let should_be_off = self.flags & !CPU_FLAGS_RESERVED_OFF;
+        let should_be_off = self.resolved_flags() & !CPU_FLAGS_RESERVED_OFF;
         assert_eq!(should_be_off, 0);
 
-        let should_be_set = self.flags & CPU_FLAGS_RESERVED_ON;
+        let should_be_set = self.resolved_flags() & CPU_FLAGS_RESERVED_ON;
         assert_eq!(should_be_set, CPU_FLAGS_RESERVED_ON);
     }
 
-    pub fn dump_cs(&self, path: &Path) {
-        let filename = path.to_path_buf();
+    pub fn get_service_event(&mut self) -> Option<ServiceEvent> {
+        self.service_events.pop_front()
+    }
+
+    /// Request a ServiceEvent::InterruptHook whenever the specified interrupt vector is entered.
+    pub fn set_interrupt_hook(&mut self, vector: u8, state: bool) {
+        if state {
+            self.int_flags[vector as usize] |= INTERRUPT_HOOK;
+        }
+        else {
+            self.int_flags[vector as usize] &= !INTERRUPT_HOOK;
+        }
+    }
 
-        let len = 0x10000;
-        let address = (self.cs as usize) << 4;
-        log::debug!("Dumping {} bytes at address {:05X}", len, address);
-        let cs_slice = self.bus.get_slice_at(address, len);
+    /// Install a callback to run on every single-step trap, or remove it with None.
+    pub fn set_single_step_hook(&mut self, hook: Option<SingleStepHook>) {
+        self.single_step_hook = hook;
+    }
 
-        match std::fs::write(filename.clone(), &cs_slice) {
-            Ok(_) => {
-                log::debug!("Wrote memory dump: {}", filename.display())
+    /// Invoke the single-step hook, if one is installed.
+    pub(crate) fn run_single_step_hook(&mut self) {
+        // Take the hook for the duration of the call so it can borrow the CPU mutably.
+        if let Some(mut hook) = self.single_step_hook.take() {
+            hook(self);
+            // Keep the hook unless the callback installed a replacement.
+            if self.single_step_hook.is_none() {
+                self.single_step_hook = Some(hook);
             }
-            Err(e) => {
-                log::error!("Failed to write memory dump '{}': {}", filename.display(), e)
+        }
+    }
+
+    /// Install a callback to run before each instruction executes, or remove it with None. The
+    /// hook runs once per instruction, not on each iteration of a REP string instruction.
+    pub fn set_pre_instruction_hook(&mut self, hook: Option<InstructionHook>) {
+        self.pre_instruction_hook = hook;
+    }
+
+    /// Install a callback to run after each instruction completes, or remove it with None.
+    pub fn set_post_instruction_hook(&mut self, hook: Option<InstructionHook>) {
+        self.post_instruction_hook = hook;
+    }
+
+    /// Invoke the pre-instruction hook, if one is installed, and return whether the current
+    /// instruction should be skipped.
+    pub(crate) fn run_pre_instruction_hook(&mut self) -> bool {
+        if let Some(mut hook) = self.pre_instruction_hook.take() {
+            let mut before = self.get_state();
+            // The queue has already been read past the start of the instruction.
+            before.ip = self.instruction_ip;
+            let mut state = before.clone();
+
+            let action = hook(&self.i, &mut state);
+            self.pre_instruction_hook = Some(hook);
+
+            if action == InstructionHookAction::Skip && state.cs == before.cs && state.ip == before.ip {
+                // Operands are read from the queue as the instruction executes, so a skipped
+                // instruction must resume at the next instruction explicitly.
+                state.ip = before.ip.wrapping_add(self.i.size as u16);
             }
+            let redirected = self.apply_hook_state(&before, &state);
+            return redirected || action == InstructionHookAction::Skip;
         }
+        false
     }
 
-    pub fn get_service_event(&mut self) -> Option<ServiceEvent> {
-        self.service_events.pop_front()
+    /// Invoke the post-instruction hook, if one is installed.
+    pub(crate) fn run_post_instruction_hook(&mut self) {
+        if let Some(mut hook) = self.post_instruction_hook.take() {
+            let before = self.get_state();
+            let mut state = before.clone();
+
+            _ = hook(&self.i, &mut state);
+            self.post_instruction_hook = Some(hook);
+
+            self.apply_hook_state(&before, &state);
+        }
+    }
+
+    /// Write back any registers changed by an instruction hook. Returns true if CS:IP changed, in
+    /// which case the instruction queue has been flushed to resume execution at the new address.
+    fn apply_hook_state(&mut self, before: &CpuRegisterState, after: &CpuRegisterState) -> bool {
+        if before == after {
+            return false;
+        }
+
+        // A change to a full register takes precedence over a change to its halves.
+        let word = |old: u16, new: u16, hi: u8, lo: u8| {
+            if new != old {
+                new
+            }
+            else {
+                (hi as u16) << 8 | lo as u16
+            }
+        };
+        let regs = [
+            (Register16::AX, word(before.ax, after.ax, after.ah, after.al)),
+            (Register16::BX, word(before.bx, after.bx, after.bh, after.bl)),
+            (Register16::CX, word(before.cx, after.cx, after.ch, after.cl)),
+            (Register16::DX, word(before.dx, after.dx, after.dh, after.dl)),
+            (Register16::SP, after.sp),
+            (Register16::BP, after.bp),
+            (Register16::SI, after.si),
+            (Register16::DI, after.di),
+            (Register16::DS, after.ds),
+            (Register16::SS, after.ss),
+            (Register16::ES, after.es),
+        ];
+        for (reg, value) in regs {
+            if self.get_register16(reg) != value {
+                self.set_register16(reg, value);
+            }
+        }
+
+        if after.flags != before.flags {
+            self.set_flags(after.flags);
+        }
+
+        if after.cs != before.cs || after.ip != before.ip {
+            // Let any code fetch in progress complete, including its T4, so that the fetched
+            // byte is discarded by the flush.
+            self.biu_suspend_fetch();
+            self.cycle();
+            self.cs = after.cs;
+            self.pc = after.ip;
+            self.biu_queue_flush();
+            return true;
+        }
+        false
     }
 
     pub fn set_option(&mut self, opt: CpuOption) {
@@ -2132,6 +2795,14 @@ impl Cpu {
                 self.instruction_history.clear();
                 self.instruction_history_on = state;
             }
+            CpuOption::InstructionHistoryDepth(depth) => {
+                let depth = depth.clamp(1, CPU_HISTORY_MAX_LEN);
+                log::debug!("Setting InstructionHistoryDepth to: {}", depth);
+                while self.instruction_history.len() > depth {
+                    self.instruction_history.pop_front();
+                }
+                self.instruction_history_len = depth;
+            }
             CpuOption::SimulateDramRefresh(state, cycle_target, cycles) => {
                 log::trace!(
                     "Setting SimulateDramRefresh to: {:?} ({},{})",
@@ -2147,6 +2818,10 @@ impl Cpu {
                 log::debug!("Setting DramRefreshAdjust to: {}", adj);
                 self.dram_refresh_adjust = adj;
             }
+            CpuOption::DramRefreshWaitStates(ws) => {
+                log::debug!("Setting DramRefreshWaitStates to: {}", ws);
+                self.dram_refresh_wait_states = ws;
+            }
             CpuOption::HaltResumeDelay(delay) => {
                 log::debug!("Setting HaltResumeDelay to: {}", delay);
                 self.halt_resume_delay = delay;
@@ -2173,19 +2848,45 @@ impl Cpu {
                 log::debug!("Setting EnableServiceInterrupt to: {:?}", state);
                 self.enable_service_interrupt = state;
             }
+            CpuOption::DecodeCache(state) => {
+                log::debug!("Setting DecodeCache to: {:?}", state);
+                self.decode_cache = if state { Some(DecodeCache::new()) } else { None };
+                self.bus.set_code_tracking(state);
+            }
+            CpuOption::FastCore(state) => {
+                log::debug!("Setting FastCore to: {:?}", state);
+                self.set_fast_core(state);
+            }
+            CpuOption::TraceMemoryOperands(state) => {
+                log::debug!("Setting TraceMemoryOperands to: {:?}", state);
+                self.trace_mem_operands = state;
+                self.mem_operands.clear();
+                self.mem_operands_dropped = 0;
+            }
+            CpuOption::TrapDelays(enable, disable) => {
+                log::debug!("Setting TrapDelays to: enable: {} disable: {}", enable, disable);
+                self.trap_enable_delay_len = enable;
+                self.trap_disable_delay_len = disable;
+            }
         }
     }
 
     pub fn get_option(&mut self, opt: CpuOption) -> bool {
         match opt {
             CpuOption::InstructionHistory(_) => self.instruction_history_on,
+            CpuOption::InstructionHistoryDepth(_) => true,
             CpuOption::SimulateDramRefresh(..) => self.dram_refresh_simulation,
             CpuOption::DramRefreshAdjust(..) => true,
+            CpuOption::DramRefreshWaitStates(..) => true,
             CpuOption::HaltResumeDelay(..) => true,
             CpuOption::OffRailsDetection(_) => self.off_rails_detection,
             CpuOption::EnableWaitStates(_) => self.enable_wait_states,
             CpuOption::TraceLoggingEnabled(_) => self.trace_enabled,
             CpuOption::EnableServiceInterrupt(_) => self.enable_service_interrupt,
+            CpuOption::DecodeCache(_) => self.decode_cache.is_some(),
+            CpuOption::FastCore(_) => self.fast_core,
+            CpuOption::TraceMemoryOperands(_) => self.trace_mem_operands,
+            CpuOption::TrapDelays(..) => true,
         }
     }
 
@@ -2206,3 +2907,23 @@ impl Cpu {
         &self.validator
     }
 }
+
+/// Create a CPU of the specified type for unit tests, with tracing and validation disabled.
+#[cfg(test)]
+pub(crate) fn test_cpu(cpu_type: CpuType) -> Cpu {
+    Cpu::new(
+        cpu_type,
+        TraceMode::None,
+        TraceLogger::None,
+        #[cfg(feature = "cpu_validator")]
+        ValidatorType::None,
+        #[cfg(feature = "cpu_validator")]
+        TraceLogger::None,
+        #[cfg(feature = "cpu_validator")]
+        ValidatorMode::Instruction,
+        #[cfg(feature = "cpu_validator")]
+        1_000_000,
+        #[cfg(feature = "cpu_validator")]
+        None,
+    )
+}
@@ -32,6 +32,7 @@
 use crate::{
     bytequeue::*,
     cpu_808x::{addressing::AddressingMode, *},
+    cpu_common::ea::{ea_index, EA_POST_DISP_CYCLES, EA_PRE_DISP_CYCLES},
 };
 
 pub const MODRM_REG_MASK: u8 = 0b00_111_000;
@@ -87,6 +88,7 @@ pub struct ModRmByte {
     disp_mc: u16,
     disp: Displacement,
     addressing_mode: AddressingMode,
+    ea_cycles: u8,
 }
 
 impl Default for ModRmByte {
@@ -101,6 +103,7 @@ impl Default for ModRmByte {
             disp_mc: 0,
             disp: Displacement::NoDisp,
             addressing_mode: AddressingMode::BxSi,
+            ea_cycles: 0,
         }
     }
 }
@@ -172,6 +175,7 @@ const MODRM_TABLE: [ModRmByte; 256] = {
         disp_mc: 0,
         disp: Displacement::NoDisp,
         addressing_mode: AddressingMode::BxSi,
+        ea_cycles: 0,
     }; 256];
     let mut byte = 0;
 
@@ -201,36 +205,18 @@ const MODRM_TABLE: [ModRmByte; 256] = {
         // Set the EA calculation costs for each addressing mode.
         // We divide these into two values, representing microcode instructions before and after
         // loading the displacement. Time spent loading the displacement itself is dependent on the
-        // state of the prefetch queue, so can't be known ahead of time.
-        //
-        // Oddly, fetching an 8-bit displacement takes longer than 16-bit!
-        // This is due to an extra jump at microcode line 1de.
-        let (pre_disp_cost, post_disp_cost, disp_mc) = match byte & MODRM_ADDR_MASK {
-            MODRM_ADDR_BX_SI => (4, 0, 0),
-            MODRM_ADDR_BX_DI => (5, 0, 0),
-            MODRM_ADDR_BP_SI => (5, 0, 0),
-            MODRM_ADDR_BP_DI => (4, 0, 0),
-            MODRM_ADDR_SI => (2, 0, 0),
-            MODRM_ADDR_DI => (2, 0, 0),
-            MODRM_ADDR_DISP16 => (0, 1, 0x1DC),
-            MODRM_ADDR_BX => (2, 0, 0),
-            MODRM_ADDR_BX_SI_DISP8 => (4, 3, 0x1DE),
-            MODRM_ADDR_BX_DI_DISP8 => (5, 3, 0x1DE),
-            MODRM_ADDR_BP_SI_DISP8 => (5, 3, 0x1DE),
-            MODRM_ADDR_BP_DI_DISP8 => (4, 3, 0x1DE),
-            MODRM_ADDR_SI_DISP8 => (2, 3, 0x1DE),
-            MODRM_ADDR_DI_DISP8 => (2, 3, 0x1DE),
-            MODRM_ADDR_BP_DISP8 => (2, 3, 0x1DE),
-            MODRM_ADDR_BX_DISP8 => (2, 3, 0x1DE),
-            MODRM_ADDR_BX_SI_DISP16 => (4, 2, 0x1DE),
-            MODRM_ADDR_BX_DI_DISP16 => (5, 2, 0x1DE),
-            MODRM_ADDR_BP_SI_DISP16 => (5, 2, 0x1DE),
-            MODRM_ADDR_BP_DI_DISP16 => (4, 2, 0x1DE),
-            MODRM_ADDR_SI_DISP16 => (2, 2, 0x1DE),
-            MODRM_ADDR_DI_DISP16 => (2, 2, 0x1DE),
-            MODRM_ADDR_BP_DISP16 => (2, 2, 0x1DE),
-            MODRM_ADDR_BX_DISP16 => (2, 2, 0x1DE),
-            _ => (0, 0, 0),
+        // state of the prefetch queue, so can't be known ahead of time. See cpu_common::ea.
+        let (pre_disp_cost, post_disp_cost) = match ea_index(byte) {
+            Some(idx) => (EA_PRE_DISP_CYCLES[idx], EA_POST_DISP_CYCLES[idx]),
+            None => (0, 0),
+        };
+
+        // Microcode line at which the displacement is read.
+        let disp_mc = match displacement {
+            Displacement::Pending8 => 0x1DE,
+            Displacement::Pending16 if b_mod == 0b00 => 0x1DC,
+            Displacement::Pending16 => 0x1DE,
+            _ => 0,
         };
 
         // Set the addressing mode based on the cominbation of Mod and R/M bitfields + Displacement.
@@ -279,6 +265,7 @@ const MODRM_TABLE: [ModRmByte; 256] = {
             disp_mc,
             disp: displacement,
             addressing_mode,
+            ea_cycles: 0,
         };
 
         if byte < 255 {
@@ -302,6 +289,7 @@ impl ModRmByte {
 
         // If modrm is an addressing mode, spend cycles in EA calculation
         if modrm.b_mod != 0b11 {
+            let ea_start = bytes.elapsed();
             bytes.wait_i(1, &[MC_JUMP]);
             bytes.wait_i(
                 modrm.pre_disp_cost as u32,
@@ -315,11 +303,19 @@ impl ModRmByte {
                 modrm.post_disp_cost as u32,
                 &EA_INSTR_TABLE_POST[(modrm.b_mod << 3 | modrm.b_rm) as usize],
             );
+            modrm.ea_cycles = bytes.elapsed().saturating_sub(ea_start) as u8;
         }
 
         (modrm, disp_size + 1)
     }
 
+    /// Return the number of cycles spent calculating the effective address when this modrm was
+    /// read, including reading any displacement. Always 0 if the modrm was not read through the
+    /// processor instruction queue.
+    pub fn ea_cycles(&self) -> u8 {
+        self.ea_cycles
+    }
+
     /// Load any displacement the modrm might have. The modrm table only has 'pending' displacement values,
     /// which must be resolved to actual displacement values.
     pub fn load_displacement(&mut self, bytes: &mut impl ByteQueue) -> u32 {
@@ -439,3 +435,29 @@ impl ModRmByte {
         self.addressing_mode
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ea_tables_match_microcode() {
+        // Each cycle of an EA procedure executes one line of microcode (or a jump).
+        let executed = |lines: &[u16]| lines.iter().filter(|&&line| line != MC_NONE).count() as u8;
+
+        for (idx, (pre, post)) in EA_INSTR_TABLE_PRE.iter().zip(EA_INSTR_TABLE_POST.iter()).enumerate() {
+            assert_eq!(
+                executed(pre),
+                EA_PRE_DISP_CYCLES[idx],
+                "pre-displacement, index {}",
+                idx
+            );
+            assert_eq!(
+                executed(post),
+                EA_POST_DISP_CYCLES[idx],
+                "post-displacement, index {}",
+                idx
+            );
+        }
+    }
+}
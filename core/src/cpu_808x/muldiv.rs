@@ -32,8 +32,18 @@
 */
 
 use crate::{cpu_808x::*, cpu_common::alu::*};
+/// Arithmetic flags produced by a microcode line with the F bit.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct McFlags {
+    result: u16,
+    word: bool,
+    carry: bool,
+    overflow: bool,
+    aux_carry: bool,
+}
+
 pub trait Cord<B = Self>: Sized {
-    fn cord(self, cpu: &mut Cpu, a: u16, b: u16, c: u16) -> Result<(u16, u16, bool), bool>;
+    fn cord(self, cpu: &mut Cpu, a: u16, b: u16, c: u16) -> Result<(u16, u16, bool, McFlags), McFlags>;
 }
 
 macro_rules! impl_cord {
@@ -41,7 +51,9 @@ macro_rules! impl_cord {
         impl Cord for $prim {
             /// Implementation of the 8088 microcode CORD division co-routine.
             /// Implemented for either 8 bit or 16 bit operand.
-            fn cord(self, cpu: &mut Cpu, a: u16, b: u16, c: u16) -> Result<(u16, u16, bool), bool> {
+            /// Returns the flags of the last line with the F bit, which the caller only commits
+            /// on a divide error.
+            fn cord(self, cpu: &mut Cpu, a: u16, b: u16, c: u16) -> Result<(u16, u16, bool, McFlags), McFlags> {
                 let mut internal_counter;
 
                 let mut tmpa: u16 = a;
@@ -52,10 +64,19 @@ macro_rules! impl_cord {
 
                 let mut carry;
                 let mut carry_sub;
+                let mut overflow;
+                let mut aux_carry;
 
                 // 188:           | SUBT tmpa
-                (_, carry, _, _) = (tmpa as Self).alu_sub(tmpb as Self);
-                // 189: SIGMA->.  | MAXC
+                (sigma_s, carry, overflow, aux_carry) = (tmpa as Self).alu_sub(tmpb as Self);
+                // 189: SIGMA->.  | MAXC  F
+                let mut flags = McFlags {
+                    result: sigma_s as u16,
+                    word: Self::BITS == 16,
+                    carry,
+                    overflow,
+                    aux_carry,
+                };
                 internal_counter = Self::BITS;
 
                 cpu.cycles_i(3, &[0x188, 0x189, 0x18a]);
@@ -65,7 +86,7 @@ macro_rules! impl_cord {
                     // Jump delay to INT0 procedure
                     cpu.cycle_i(MC_JUMP);
                     //log::debug!("cord: div overflow");
-                    return Err(false);
+                    return Err(flags);
                 }
 
                 // The main CORD loop is between 18b and 196.
@@ -80,7 +101,7 @@ macro_rules! impl_cord {
 
                     // 18d:
                     tmpa = sigma_s as u16;
-                    (sigma_s, carry_sub, _, _) = (tmpa as Self).alu_sub(tmpb as Self);
+                    (sigma_s, carry_sub, overflow, aux_carry) = (tmpa as Self).alu_sub(tmpb as Self);
                     sigma = sigma_s as u16;
 
                     cpu.cycles_i(4, &[0x18b, 0x18c, 0x18d, 0x18e]);
@@ -108,7 +129,13 @@ macro_rules! impl_cord {
                     else {
                         // 18f: SIGMA->.     | F
                         carry = carry_sub;
-                        // SET FLAGS HERE
+                        flags = McFlags {
+                            result: sigma,
+                            word: Self::BITS == 16,
+                            carry,
+                            overflow,
+                            aux_carry,
+                        };
 
                         cpu.cycles_i(2, &[0x18f, 0x190]);
 
@@ -160,7 +187,7 @@ macro_rules! impl_cord {
                 cpu.cycles_i(4, &[0x192, 0x193, 0x194, MC_RTN]);
                 //println!("cord_finish(): tmpc: {} tmpa: {}", tmpc, tmpa);
 
-                Ok((tmpc, tmpa, carry))
+                Ok((tmpc, tmpa, carry, flags))
             }
         }
     };
@@ -326,6 +353,23 @@ impl_cor_negate!(u8);
 impl_cor_negate!(u16);
 
 impl Cpu {
+    /// Set the arithmetic flags from the last microcode line with the F bit.
+    /// This is only done on a divide error, so that the handler sees the flags of the last
+    /// subtraction CORD performed. The flags left by a successful division are not modeled
+    /// and are left unchanged.
+    #[inline]
+    pub(crate) fn set_mc_flags(&mut self, flags: McFlags) {
+        self.set_flag_state(Flag::Carry, flags.carry);
+        self.set_flag_state(Flag::Overflow, flags.overflow);
+        self.set_flag_state(Flag::AuxCarry, flags.aux_carry);
+        if flags.word {
+            self.set_szp_flags_from_result_u16(flags.result);
+        }
+        else {
+            self.set_szp_flags_from_result_u8(flags.result as u8);
+        }
+    }
+
     #[allow(dead_code)]
     #[allow(unused_assignments)] // This isn't pretty but we are trying to mirror the microcode
     /// Microcode routine for multiplication, 8 bit
@@ -633,9 +677,11 @@ impl Cpu {
 
         // 163
         self.cycles_i(2, &[0x163, MC_JUMP]);
-        (tmpc, tmpa, carry) = match (tmpa as u8).cord(self, tmpa, tmpb, tmpc) {
-            Ok((tmpc, tmpa, carry)) => (tmpc, tmpa, carry),
-            Err(_) => {
+        let flags;
+        (tmpc, tmpa, carry, flags) = match (tmpa as u8).cord(self, tmpa, tmpb, tmpc) {
+            Ok(result) => result,
+            Err(flags) => {
+                self.set_mc_flags(flags);
                 return Err(false);
             }
         };
@@ -656,6 +702,7 @@ impl Cpu {
             // 1c4:         | NCY INT0
             if !carry {
                 self.cycle_i(MC_JUMP);
+                self.set_mc_flags(flags);
                 return Err(false);
             }
 
@@ -699,7 +746,7 @@ impl Cpu {
                 self.cycle_i(MC_JUMP);
             }
 
-            // clear carry, overflow flag here
+            // 1cc:              | CCOF RTN
             self.cycles_i(2, &[0x1cc, MC_RTN]);
         }
 
@@ -754,9 +801,13 @@ impl Cpu {
 
         // 16b:
         self.cycles_i(2, &[0x163, MC_JUMP]);
-        (tmpc, tmpa, carry) = match tmpa.cord(self, tmpa, tmpb, tmpc) {
-            Ok((tmpc, tmpa, carry)) => (tmpc, tmpa, carry),
-            Err(_) => return Err(false),
+        let flags;
+        (tmpc, tmpa, carry, flags) = match tmpa.cord(self, tmpa, tmpb, tmpc) {
+            Ok(result) => result,
+            Err(flags) => {
+                self.set_mc_flags(flags);
+                return Err(false);
+            }
         };
 
         // 16c        | COM1 tmpc
@@ -775,6 +826,7 @@ impl Cpu {
             // 1c4:         | NCY INT0
             if !carry {
                 self.cycle_i(MC_JUMP);
+                self.set_mc_flags(flags);
                 return Err(false);
             }
 
@@ -815,7 +867,7 @@ impl Cpu {
                 self.cycle_i(MC_JUMP);
             }
 
-            // clear carry, overflow flag here
+            // 1cc:              | CCOF RTN
             self.cycles_i(2, &[0x1cc, MC_RTN]);
         }
 
@@ -827,3 +879,57 @@ impl Cpu {
         Ok((tmpc, tmpa))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_808x::*;
+
+    const ARITH_FLAGS: u16 =
+        CPU_FLAG_CARRY | CPU_FLAG_PARITY | CPU_FLAG_AUX_CARRY | CPU_FLAG_ZERO | CPU_FLAG_SIGN | CPU_FLAG_OVERFLOW;
+
+    #[test]
+    fn test_divide_flags_unchanged_on_success() {
+        let mut cpu = test_cpu(CpuType::Intel8088);
+
+        for preset in [0, ARITH_FLAGS] {
+            cpu.set_flags(preset);
+            assert_eq!(cpu.div8(0x0064, 0x07, false, false), Ok((14, 2)));
+            assert_eq!(cpu.get_flags() & ARITH_FLAGS, preset);
+
+            assert_eq!(cpu.div8(0xFF9C, 0x07, true, false), Ok((0xF2, 0xFE)));
+            assert_eq!(cpu.get_flags() & ARITH_FLAGS, preset);
+
+            assert_eq!(cpu.div16(0x0001_0000, 0x0003, false, false), Ok((0x5555, 1)));
+            assert_eq!(cpu.get_flags() & ARITH_FLAGS, preset);
+
+            assert_eq!(cpu.div16(0xFFFF_0000, 0x0100, true, false), Ok((0xFF00, 0)));
+            assert_eq!(cpu.get_flags() & ARITH_FLAGS, preset);
+        }
+    }
+
+    #[test]
+    fn test_divide_error_flags() {
+        let mut cpu = test_cpu(CpuType::Intel8088);
+
+        // The quotient overflows before CORD's loop: the flags are those of AH - divisor.
+        for preset in [0, ARITH_FLAGS] {
+            cpu.set_flags(preset);
+            assert!(cpu.div8(0x0500, 0x05, false, false).is_err());
+            assert_eq!(cpu.get_flags() & ARITH_FLAGS, CPU_FLAG_ZERO | CPU_FLAG_PARITY);
+
+            // 0x8000 - 0x0001 sets OF and AF and clears SF.
+            cpu.set_flags(preset);
+            assert!(cpu.div16(0x8000_0000, 0x0001, false, false).is_err());
+            assert_eq!(
+                cpu.get_flags() & ARITH_FLAGS,
+                CPU_FLAG_OVERFLOW | CPU_FLAG_AUX_CARRY | CPU_FLAG_PARITY
+            );
+        }
+
+        // A signed quotient that only overflows after CORD completes also faults, and does not
+        // leave the flags untouched.
+        cpu.set_flags(ARITH_FLAGS);
+        assert!(cpu.div8(0x4000, 0x7F, true, false).is_err());
+        assert_ne!(cpu.get_flags() & ARITH_FLAGS, ARITH_FLAGS);
+    }
+}
@@ -66,6 +66,12 @@ impl InstructionQueue {
         self.size = size;
     }
 
+    /// Return the size of the queue, which depends on the CPU type.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.len
@@ -62,6 +62,8 @@ impl Cpu {
             Register16::BX => self.bx,
             Register16::CX => self.cx,
             Register16::DX => self.dx,
+            // The 80286 pushes the value of SP from before the push.
+            Register16::SP if self.cpu_type == CpuType::Intel80286 => self.sp.wrapping_add(2),
             Register16::SP => self.sp,
             Register16::BP => self.bp,
             Register16::SI => self.si,
@@ -113,7 +115,11 @@ impl Cpu {
     pub fn push_flags(&mut self, wflag: ReadWriteFlag) {
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_sub(2);
-        self.biu_write_u16(Segment::SS, self.sp, self.flags, wflag);
+        let flags = match self.cpu_type {
+            CpuType::Intel80286 => self.pushed_flags_286(),
+            _ => self.resolved_flags(),
+        };
+        self.biu_write_u16(Segment::SS, self.sp, flags, wflag);
     }
 
     pub fn pop_flags(&mut self) {
@@ -123,17 +129,21 @@ impl Cpu {
 
         // Ensure state of reserved flag bits
         self.flags = result & FLAGS_POP_MASK;
+        self.lazy_flags = LazyFlags::None;
         self.flags |= CPU_FLAGS_RESERVED_ON;
+        if self.cpu_type == CpuType::Intel80286 {
+            self.pop_flags_286(result);
+        }
 
         // Was trap flag just set? Set trap enable delay.
         let trap_is_set = self.get_flag(Flag::Trap);
         if !trap_was_set && trap_is_set {
-            self.trap_enable_delay = 2;
+            self.trap_enable_delay = self.trap_enable_delay_len;
         }
 
         // Was trap flag just disabled? Set trap disable delay.
         if trap_was_set && !trap_is_set {
-            self.trap_disable_delay = 1;
+            self.trap_disable_delay = self.trap_disable_delay_len;
         }
 
         // Stack pointer grows downwards
@@ -30,7 +30,7 @@
 
 */
 
-use crate::cpu_808x::{*};
+use crate::cpu_808x::*;
 
 impl Cpu {
     /// Run a single instruction.
@@ -43,6 +43,7 @@ impl Cpu {
     /// be checked.
     pub fn step(&mut self, skip_breakpoint: bool) -> Result<(StepResult, u32), CpuError> {
         self.instr_cycle = 0;
+        self.instr_wait_states = 0;
         self.instr_elapsed = self.int_elapsed;
 
         // If tracing is enabled, clear the trace string vector that holds the trace from the last instruction.
@@ -120,18 +121,17 @@ impl Cpu {
         */
 
         // Halt state can be expensive since if we only executing a single cycle.
-        // See if we can get away with executing 3 halt cycles at at time - demo effects may require more precision
-
-        // TODO: Adjust this value based on Timer channel 0 count - if no interrupt is pending soon we can do more
-        // cycles per halt.
+        // By default we execute 3 halt cycles at at time - demo effects may require more precision.
+        // The Machine may raise this when it knows no interrupt is due soon (see set_halt_cycles()).
         if self.halted {
-            self.cycle_i(self.mc_pc);
-            self.cycle_i(self.mc_pc);
-            self.cycle_i(self.mc_pc);
-            return Ok((StepResult::Normal, 3));
+            for _ in 0..self.halt_cycles {
+                self.cycle_i(self.mc_pc);
+            }
+            return Ok((StepResult::Normal, self.halt_cycles));
         }
 
         let mut instruction_address = self.instruction_address;
+        let mut fetch_cycles = 0;
 
         // Fetch the next instruction unless we are executing a REP
         if !self.in_rep {
@@ -142,7 +142,7 @@ impl Cpu {
             // Sometimes it is more convenient for us to think of the current ip address, which can be calculated on the
             // fly from PC by the ip() instruction, but only usefully on instruction boundaries, such as now.
             self.instruction_ip = self.ip();
-            self.instruction_address = Cpu::calc_linear_address(self.cs, self.instruction_ip);
+            self.instruction_address = self.calc_linear_address_seg(Segment::CS, self.instruction_ip);
             instruction_address = self.instruction_address;
             //log::warn!("instruction address: {:05X}", instruction_address);
 
@@ -150,6 +150,16 @@ impl Cpu {
                 return Ok((StepResult::ProgramEnd, 0));
             }
 
+            // Check if the last instruction wrote to a watch region with the Break action.
+            if self.bus.take_watch_break() {
+                log::debug!("Watch region break before instruction at {:05X}", instruction_address);
+                self.breakpoint_trigger = self
+                    .bus
+                    .take_video_break()
+                    .map(|(address, data)| BreakpointTrigger::VideoWrite(address, data));
+                self.set_breakpoint_flag();
+            }
+
             // Check if we are in BreakpointHit state. This state must be cleared before we can execute another instruction.
             if self.get_breakpoint_flag() {
                 return Ok((StepResult::BreakpointHit, 0));
@@ -159,7 +169,7 @@ impl Cpu {
             if !skip_breakpoint && self.bus.get_flags(instruction_address as usize) & MEM_BPE_BIT != 0 {
                 // Breakpoint hit.
                 log::debug!("Breakpoint hit at {:05X}", instruction_address);
-                self.set_breakpoint_flag();
+                self.break_on(BreakpointTrigger::Execute(instruction_address));
                 return Ok((StepResult::BreakpointHit, 0));
             }
 
@@ -175,8 +185,7 @@ impl Cpu {
             // This of course now requires decoding each instruction twice, but cycle tracing is pretty slow
             // anyway.
             if self.trace_mode == TraceMode::CycleText {
-                self.bus.seek(instruction_address as usize);
-                self.i = match Cpu::decode(&mut self.bus) {
+                self.i = match self.decode_at(instruction_address) {
                     Ok(i) => i,
                     Err(_) => {
                         self.is_running = false;
@@ -185,13 +194,27 @@ impl Cpu {
                     }
                 };
                 //log::trace!("Fetching instruction...");
-                self.i.address = instruction_address;
+            }
+
+            // Memory operands accumulate across the iterations of a REP string instruction, and are
+            // only cleared when a new instruction begins. Accesses made by interrupt entry are
+            // discarded here.
+            if self.trace_mem_operands {
+                self.clear_mem_operands();
             }
 
             // Fetch and decode the current instruction. This uses the CPU's own ByteQueue trait
             // implementation, which fetches instruction bytes through the processor instruction queue.
             //log::warn!("decoding instruction...");
-            self.i = match Cpu::decode(self) {
+            // The fast core has no queue to keep in step with, so it can decode through the cache.
+            let decoded = if self.fast_core && self.decode_cache.is_some() {
+                self.decode_fast_cached(instruction_address)
+            }
+            else {
+                let cpu_type = self.cpu_type;
+                Cpu::decode_cpu(self, cpu_type)
+            };
+            self.i = match decoded {
                 Ok(i) => i,
                 Err(_) => {
                     self.is_running = false;
@@ -200,6 +223,10 @@ impl Cpu {
                 }
             };
 
+            if self.fast_core {
+                fetch_cycles = self.i.size * FAST_CORE_CYCLES_PER_BYTE;
+            }
+
             // Begin the current instruction validation context.
             #[cfg(feature = "cpu_validator")]
             {
@@ -210,6 +237,12 @@ impl Cpu {
         // Since Cpu::decode doesn't know anything about the current IP, it can't set it, so we do that now.
         self.i.address = instruction_address;
 
+        // Give the pre-instruction hook a chance to replace the instruction.
+        if !self.in_rep && self.pre_instruction_hook.is_some() && self.run_pre_instruction_hook() {
+            self.intr_pending = false;
+            return Ok((StepResult::Normal, self.device_cycles));
+        }
+
         // Uncomment to debug instruction fetch
         //self.debug_fetch(instruction_address);
 
@@ -228,25 +261,36 @@ impl Cpu {
         // Execute the current decoded instruction.
         self.exec_result = self.execute_instruction();
 
+        // Attribute any interrupt vector table writes to this instruction.
+        if self.bus.ivt_written() {
+            self.bus.commit_vector_changes(last_cs, last_ip);
+        }
+
+        // In fast core mode, an instruction can't complete faster than it could have been fetched.
+        if self.instr_cycle < fetch_cycles {
+            self.cycles(fetch_cycles - self.instr_cycle);
+        }
+
+        if self.post_instruction_hook.is_some()
+            && matches!(self.exec_result, ExecutionResult::Okay | ExecutionResult::OkayJump)
+        {
+            self.run_post_instruction_hook();
+        }
+
         let step_result = match &self.exec_result {
             ExecutionResult::Okay => {
                 // Normal non-jump instruction updates CS:IP to next instruction during execute()
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(HistoryEntry::Entry {
-                        cs: last_cs,
-                        ip: last_ip,
-                        cycles: self.instr_cycle as u16,
-                        i: self.i,
-                    });
+                    self.push_instruction_history(last_cs, last_ip);
                 }
                 self.instruction_count += 1;
 
                 // Perform instruction tracing, if enabled
                 if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
                     self.trace_print(&self.instruction_state_string(last_cs, last_ip));
+                    if self.trace_mem_operands && !self.mem_operands.is_empty() {
+                        self.trace_print(&self.mem_operand_string());
+                    }
                 }
 
                 Ok((StepResult::Normal, self.device_cycles))
@@ -254,21 +298,16 @@ impl Cpu {
             ExecutionResult::OkayJump => {
                 // A control flow instruction updated CS:IP.
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(HistoryEntry::Entry {
-                        cs: last_cs,
-                        ip: last_ip,
-                        cycles: self.instr_cycle as u16,
-                        i: self.i,
-                    });
+                    self.push_instruction_history(last_cs, last_ip);
                 }
                 self.instruction_count += 1;
 
                 // Perform instruction tracing, if enabled
                 if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
                     self.trace_print(&self.instruction_state_string(last_cs, last_ip));
+                    if self.trace_mem_operands && !self.mem_operands.is_empty() {
+                        self.trace_print(&self.mem_operand_string());
+                    }
                 }
 
                 // Only CALLS will set a step over target.
@@ -287,16 +326,7 @@ impl Cpu {
                 // earlier so that a REP string operation can call RPTI to be ready for
                 // an interrupt to occur.
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-
-                    self.instruction_history.push_back(HistoryEntry::Entry {
-                        cs: last_cs,
-                        ip: last_ip,
-                        cycles: self.instr_cycle as u16,
-                        i: self.i,
-                    });
+                    self.push_instruction_history(last_cs, last_ip);
                 }
                 self.instruction_count += 1;
 
@@ -332,6 +362,11 @@ impl Cpu {
                     CpuException::DivideError => {
                         // Moved int0 handling into aam/div instructions directly.
                         //self.handle_exception(0);
+                        // The faulting instruction still completed, with int0 updating CS:IP.
+                        if self.instruction_history_on {
+                            self.push_instruction_history(last_cs, last_ip);
+                        }
+                        self.instruction_count += 1;
                         Ok((StepResult::Normal, self.device_cycles))
                     }
                     _ => {
@@ -362,7 +397,7 @@ impl Cpu {
     /// we do not want to fetch the next byte - we want to jump directly into the
     /// interrupt routine - *unless* we are in a REP, in which case we set a flag
     /// so that the interrupt execution can occur on the next call to step() to simulate
-    /// the string instruction calling RPTI.
+    /// the string instruction calling RPTI. NMI and trap are deferred the same way.
     ///
     /// This function effectively simulates the RNI microcode routine.
     pub fn step_finish(&mut self) -> Result<StepResult, CpuError> {
@@ -375,7 +410,26 @@ impl Cpu {
         self.int_elapsed = 0;
         self.device_cycles = 0;
 
-        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
+        // A protected mode segment load may have faulted during the instruction. Deliver the
+        // fault before anything else.
+        self.sync_descriptor_caches();
+
+        if self.deliver_pending_fault() {
+            step_result = StepResult::Call(CpuAddress::Segmented(self.cs, self.ip()));
+        }
+        else if self.in_rep {
+            // We're in a REP-prefixed string instruction. NMI, INTR and trap are only recognized
+            // by the string instruction itself when it executes RPTI. At that point the REP
+            // terminates with IP rewound to the last prefix, and the interrupt is processed as
+            // normal on the next call to step_finish().
+            if (self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered)
+                || (self.intr && self.interrupts_enabled())
+                || self.trap_enabled()
+            {
+                self.intr_pending = true;
+            }
+        }
+        else if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
             // NMI takes priority over trap and INTR.
             if self.halted {
                 // Resume from halt on interrupt
@@ -388,47 +442,37 @@ impl Cpu {
         }
         else if self.intr && self.interrupts_enabled() {
             // An interrupt needs to be processed.
-
-            if self.in_rep {
-                // We're in an REP prefixed-string instruction.
-                // Delay processing of the interrupt so that the string
-                // instruction can execute RPTI. At that point, the REP
-                // will terminate and we can process the interrupt as normal.
-                self.intr_pending = true;
+            if self.halted {
+                // Resume from halt on interrupt
+                self.resume();
             }
-            else {
-                // We are not in a REP prefixed string instruction, so we
-                // can process an interrupt normally.
-
-                if self.halted {
-                    // Resume from halt on interrupt
-                    self.resume();
-                }
 
-                // Query the PIC to get the interrupt vector.
-                // This is a bit artificial as we don't actually read the IV during the 2nd
-                // INTA cycle like the CPU does, instead we save the value now and simulate it later.
-                // TODO: Think about changing this to query during INTA
-                if let Some(pic) = self.bus.pic_mut().as_mut() {
-                    // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
-                    if pic.query_interrupt_line() {
-                        if let Some(iv) = pic.get_interrupt_vector() {
-                            irq = iv;
-                        }
-                    }
+            // Query the PIC to get the interrupt vector.
+            // This is a bit artificial as we don't actually read the IV during the 2nd
+            // INTA cycle like the CPU does, instead we save the value now and simulate it later.
+            // TODO: Think about changing this to query during INTA
+            let intr_active = self
+                .bus
+                .pic_mut()
+                .as_ref()
+                .is_some_and(|pic| pic.query_interrupt_line());
+            if intr_active {
+                // The bus resolves the vector through a cascaded secondary PIC, if present.
+                if let Some(iv) = self.bus.get_interrupt_vector() {
+                    irq = iv;
                 }
+            }
 
-                // We will be jumping into an ISR now. Set the step result to Call and return
-                // the address of the next instruction. (Step Over skips ISRs)
-                step_result = StepResult::Call(CpuAddress::Segmented(self.cs, self.ip()));
+            // We will be jumping into an ISR now. Set the step result to Call and return
+            // the address of the next instruction. (Step Over skips ISRs)
+            step_result = StepResult::Call(CpuAddress::Segmented(self.cs, self.ip()));
 
-                if self.int_flags[irq as usize] != 0 {
-                    // This interrupt has a breakpoint
-                    self.set_breakpoint_flag();
-                }
-                self.hw_interrupt(irq);
-                self.biu_fetch_next();
+            if self.int_flags[irq as usize] & INTERRUPT_BREAKPOINT != 0 {
+                // This interrupt has a breakpoint
+                self.break_on(BreakpointTrigger::Interrupt(irq));
             }
+            self.hw_interrupt(irq);
+            self.biu_fetch_next();
         }
         else if self.trap_enabled() {
             // Trap has lowest priority.
@@ -512,11 +556,10 @@ impl Cpu {
                 let mut v_flags = 0;
 
                 if let ExecutionResult::ExceptionError(CpuException::DivideError) = self.exec_result {
-                    // In the case of a divide exception, undefined flags get pushed to the stack.
-                    // So until we figure out the actual logic behind setting those undefined flags,
-                    // we can't validate writes. Also the cycle timing seems to vary a little when
-                    // executing int0, so allow a one cycle variance.
-                    v_flags |= VAL_NO_WRITES | VAL_NO_FLAGS | VAL_ALLOW_ONE;
+                    // In the case of a divide exception, the undefined flags left by the divide
+                    // microcode get pushed to the stack. The cycle timing seems to vary a little
+                    // when executing int0, so allow a one cycle variance.
+                    v_flags |= VAL_ALLOW_ONE;
                 }
 
                 match self.i.mnemonic {
@@ -605,3 +648,642 @@ impl Cpu {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu_808x::*, cpu_common::HistoryExportFormat};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Run a small loop summing 10..1 into AX and storing the result, returning AX, the stored
+    /// byte and the total number of cycles executed.
+    fn run_sum_loop(fast_core: bool) -> (u16, u8, u32) {
+        #[rustfmt::skip]
+        let program = [
+            0xB9, 0x0A, 0x00, // MOV CX, 10
+            0x31, 0xC0,       // XOR AX, AX
+            0x01, 0xC8,       // ADD AX, CX
+            0xE2, 0xFC,       // LOOP -4
+            0xA3, 0x00, 0x02, // MOV [0200h], AX
+        ];
+        let (ax, byte, cycles, _) = run_program(&program, fast_core, false);
+        (ax, byte, cycles)
+    }
+
+    /// Run a loop that replaces a NOP at its start with INC AX on the first iteration, adding 19
+    /// to AX over 10 iterations, and store the result.
+    fn run_self_modifying_loop(fast_core: bool, decode_cache: bool) -> (u16, u8, u32, Option<(u64, u64)>) {
+        #[rustfmt::skip]
+        let program = [
+            0xB9, 0x0A, 0x00,             // MOV CX, 10
+            0x31, 0xC0,                   // XOR AX, AX
+            0x90,                         // NOP
+            0x05, 0x01, 0x00,             // ADD AX, 1
+            0xC6, 0x06, 0x05, 0x01, 0x40, // MOV BYTE [0105h], 40h ; INC AX
+            0xE2, 0xF5,                   // LOOP -11
+            0xA3, 0x00, 0x02,             // MOV [0200h], AX
+        ];
+        run_program(&program, fast_core, decode_cache)
+    }
+
+    /// Run a program loaded at 0000:0100, returning AX, the byte stored at 0200h, the total
+    /// number of cycles executed and the decode cache statistics.
+    fn run_program(program: &[u8], fast_core: bool, decode_cache: bool) -> (u16, u8, u32, Option<(u64, u64)>) {
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_option(CpuOption::FastCore(fast_core));
+        cpu.set_option(CpuOption::DecodeCache(decode_cache));
+        cpu.set_end_address(0x100 + program.len());
+
+        let mut cycles = 0;
+        loop {
+            match cpu.step(false).unwrap() {
+                (StepResult::ProgramEnd, _) => break,
+                (_, step_cycles) => cycles += step_cycles,
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        let ax = cpu.get_register16(Register16::AX);
+        let (byte, _) = cpu.bus_mut().read_u8(0x200, 0).unwrap();
+        (ax, byte, cycles, cpu.decode_cache_stats())
+    }
+
+    #[test]
+    fn test_fast_core() {
+        let (ax, byte, cycles) = run_sum_loop(false);
+        let (fast_ax, fast_byte, fast_cycles) = run_sum_loop(true);
+
+        assert_eq!(ax, 55);
+        assert_eq!(byte, 55);
+        assert_eq!(fast_ax, ax);
+        assert_eq!(fast_byte, byte);
+        // Fast core timing is only approximate, but should be in the same ballpark.
+        assert!(fast_cycles * 2 > cycles && fast_cycles < cycles * 2);
+    }
+
+    #[test]
+    fn test_fast_core_decode_cache() {
+        let (ax, byte, cycles, _) = run_self_modifying_loop(true, false);
+        let (cached_ax, cached_byte, cached_cycles, stats) = run_self_modifying_loop(true, true);
+
+        assert_eq!(ax, 19);
+        assert_eq!(byte, 19);
+        // The cache must see the modified instruction, and must not change timing.
+        assert_eq!(cached_ax, ax);
+        assert_eq!(cached_byte, byte);
+        assert_eq!(cached_cycles, cycles);
+
+        // ADD, MOV and LOOP are decoded once. The rewritten instruction misses on every
+        // iteration, as each write to it invalidates the cached decode.
+        let (hits, misses) = stats.unwrap();
+        assert_eq!(hits, 27);
+        assert_eq!(misses, 16);
+
+        // The cycle-accurate core decodes through the queue and doesn't use the cache.
+        let (ax, _, _, stats) = run_self_modifying_loop(false, true);
+        assert_eq!(ax, 19);
+        assert_eq!(stats, Some((0, 0)));
+    }
+
+    #[test]
+    fn test_trace_mem_operands() {
+        #[rustfmt::skip]
+        let program = [
+            0xB8, 0x34, 0x12, // MOV AX, 1234h
+            0xA3, 0x00, 0x02, // MOV [0200h], AX
+            0xA0, 0x01, 0x02, // MOV AL, [0201h]
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_option(CpuOption::TraceMemoryOperands(true));
+        cpu.set_end_address(0x100 + program.len());
+
+        let mut operands = Vec::new();
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            operands.push(cpu.mem_operands().to_vec());
+            cpu.step_finish().unwrap();
+        }
+
+        assert!(operands[0].is_empty());
+        assert_eq!(operands[1].len(), 1);
+        let store = operands[1][0];
+        assert!(store.write && store.word);
+        assert_eq!((store.offset, store.address, store.value), (0x0200, 0x00200, 0x1234));
+        assert_eq!(operands[2].len(), 1);
+        let load = operands[2][0];
+        assert!(!load.write && !load.word);
+        assert_eq!((load.offset, load.value), (0x0201, 0x12));
+    }
+
+    #[test]
+    fn test_instruction_history_depth() {
+        #[rustfmt::skip]
+        let program = [
+            0xB9, 0x0A, 0x00, // MOV CX, 10
+            0x31, 0xC0,       // XOR AX, AX
+            0x01, 0xC8,       // ADD AX, CX
+            0xE2, 0xFC,       // LOOP -4
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_option(CpuOption::InstructionHistory(true));
+        cpu.set_option(CpuOption::InstructionHistoryDepth(4));
+        cpu.set_end_address(0x100 + program.len());
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        assert_eq!(cpu.dump_instruction_history_tokens(CPU_HISTORY_LEN).len(), 4);
+
+        let mut csv = Vec::new();
+        cpu.export_instruction_history(&mut csv, HistoryExportFormat::Csv)
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("timestamp,address"));
+
+        // The last entry is the final LOOP, after which AX holds the sum and CX is exhausted.
+        let fields: Vec<&str> = lines[4].split(',').collect();
+        assert_eq!(fields[1], "00107");
+        assert_eq!(fields[6], "0037");
+        assert_eq!(fields[8], "0000");
+    }
+
+    #[test]
+    fn test_instruction_history_records() {
+        #[rustfmt::skip]
+        let program = [
+            0xB8, 0x34, 0x12, // MOV AX, 1234h
+            0x40,             // INC AX
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_option(CpuOption::InstructionHistory(true));
+        cpu.set_end_address(0x100 + program.len());
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        let records = cpu.instruction_history(CPU_HISTORY_LEN);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].cs, records[0].ip), (0x0000, 0x0100));
+        assert_eq!(records[0].bytes, vec![0xB8, 0x34, 0x12]);
+        // The oldest record has nothing to compare against and reports every register.
+        assert_eq!(records[0].regs_changed.len(), 14);
+        assert_eq!(records[1].bytes, vec![0x40]);
+        assert_eq!(records[1].regs_changed.get("ax"), Some(&0x1235));
+        assert!(!records[1].regs_changed.contains_key("bx"));
+
+        // Requesting only the last record still reports the delta against the previous entry.
+        let last = cpu.instruction_history(1);
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].regs_changed, records[1].regs_changed);
+
+        let mut json = Vec::new();
+        cpu.export_instruction_history(&mut json, HistoryExportFormat::Json)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[1]["disassembly"], records[1].disassembly.as_str());
+        assert_eq!(json[1]["regs_changed"]["ax"], 0x1235);
+    }
+
+    #[test]
+    fn test_instr_wait_states() {
+        #[rustfmt::skip]
+        let program = [
+            0xA0, 0x00, 0x02, // MOV AL, [0200h]
+            0x40,             // INC AX
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.bus_mut().set_descriptor(0x200, 0x10, 3, false);
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_option(CpuOption::EnableWaitStates(true));
+        cpu.set_end_address(0x100 + program.len());
+
+        let mut wait_states = Vec::new();
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            wait_states.push(cpu.instr_wait_states());
+            cpu.step_finish().unwrap();
+        }
+
+        // Only the memory read from the slow range incurs wait states.
+        assert_eq!(wait_states, vec![3, 0]);
+    }
+
+    #[test]
+    fn test_returned_from() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x10, // MOV SP, 1000h
+            0xE8, 0x02, 0x00, // CALL 0108h
+            0x40,             // INC AX
+            0x90,             // NOP (end)
+            0x50,             // 0108: PUSH AX
+            0xE8, 0x02, 0x00, // CALL 010Eh
+            0x58,             // POP AX
+            0xC3,             // RET
+            0xC3,             // 010E: RET
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x107);
+
+        // Step out of the procedure at 0108h once it has been entered.
+        let mut frame = None;
+        let mut returns = Vec::new();
+        loop {
+            if cpu.ip() == 0x108 {
+                frame = Some(cpu.stack_frame());
+            }
+            let ip = cpu.ip();
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+            if let Some((ss, sp)) = frame {
+                if cpu.returned_from(ss, sp) {
+                    returns.push((ip, cpu.ip()));
+                }
+            }
+        }
+
+        // The inner RET at 010Eh does not return from the frame.
+        assert_eq!(returns, vec![(0x10D, 0x106)]);
+    }
+
+    /// Run a program to its end while journaling writes, then step back through every
+    /// instruction and run it again.
+    #[test]
+    fn test_rewind_restores_state() {
+        #[rustfmt::skip]
+        let program = [
+            0xB8, 0x34, 0x12, // MOV AX, 1234h
+            0xA3, 0x00, 0x02, // MOV [0200h], AX
+            0xB9, 0x03, 0x00, // MOV CX, 3
+            0xBF, 0x00, 0x03, // MOV DI, 0300h
+            0xF3, 0xAA,       // REP STOSB
+            0x40,             // INC AX
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x100 + program.len());
+        cpu.bus_mut().set_write_journal(true);
+
+        let memory = |cpu: &Cpu| {
+            [0x200, 0x201, 0x300, 0x301, 0x302]
+                .map(|address| cpu.bus().peek_u8(address).unwrap())
+                .to_vec()
+        };
+        let run = |cpu: &mut Cpu| {
+            let mut frames = Vec::new();
+            loop {
+                let regs = cpu.rewind_state();
+                if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                    break;
+                }
+                cpu.step_finish().unwrap();
+                frames.push((regs, cpu.bus_mut().take_write_journal()));
+            }
+            frames
+        };
+
+        let start = (cpu.rewind_state(), memory(&cpu));
+        let frames = run(&mut cpu);
+        let end = (cpu.rewind_state(), memory(&cpu));
+        assert_eq!(end.0.ax, 0x1235);
+        assert_eq!(end.1, vec![0x34, 0x12, 0x34, 0x34, 0x34]);
+
+        for (regs, journal) in frames.iter().rev() {
+            cpu.bus_mut().undo_writes(journal);
+            cpu.restore_state(regs, false);
+        }
+        assert_eq!((cpu.rewind_state(), memory(&cpu)), start);
+
+        run(&mut cpu);
+        assert_eq!((cpu.rewind_state(), memory(&cpu)), end);
+    }
+
+    /// Set the trap flag with POPF and single-step the following instructions through an IRET
+    /// handler, returning the IP reported to the single-step hook for each trap.
+    fn run_single_step(trap_delays: Option<(u32, u32)>, clear_trap: bool) -> Vec<u16> {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x10, // MOV SP, 1000h
+            0x9C,             // PUSHF
+            0x58,             // POP AX
+            0x0D, 0x00, 0x01, // OR AX, 0100h
+            0x50,             // PUSH AX
+            0x9D,             // POPF
+            0x40,             // INC AX
+            0x41,             // INC CX
+            0x42,             // INC DX
+            0x43,             // INC BX
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        // INT 01 vector points to an IRET at 0000:0300.
+        cpu.bus_mut()
+            .copy_from(&[0x00, 0x03, 0x00, 0x00], 0x04, 0, false)
+            .unwrap();
+        cpu.bus_mut().copy_from(&[0xCF], 0x300, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        if let Some((enable, disable)) = trap_delays {
+            cpu.set_option(CpuOption::TrapDelays(enable, disable));
+        }
+        cpu.set_end_address(0x100 + program.len());
+
+        let traps = Rc::new(RefCell::new(Vec::new()));
+        let hook_traps = traps.clone();
+        cpu.set_single_step_hook(Some(Box::new(move |cpu: &mut Cpu| {
+            hook_traps.borrow_mut().push(cpu.ip());
+            if clear_trap {
+                cpu.clear_flag(Flag::Trap);
+            }
+        })));
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        let traps = traps.borrow().clone();
+        traps
+    }
+
+    #[test]
+    fn test_single_step_hook() {
+        // With the default delays, two instructions run after the POPF that sets the trap flag. The
+        // handler's IRET sets the flag again and applies the same delay.
+        assert_eq!(run_single_step(None, false), vec![0x10C, 0x10E]);
+        // An enable delay of 1 traps every instruction after the POPF.
+        assert_eq!(run_single_step(Some((1, 1)), false), vec![0x10B, 0x10C, 0x10D, 0x10E]);
+        // Clearing the trap flag from the hook ends the stepping session after one trap.
+        assert_eq!(run_single_step(None, true), vec![0x10C]);
+    }
+
+    #[test]
+    fn test_divide_error() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x04, // MOV SP, 0400h
+            0xB8, 0x00, 0x05, // MOV AX, 0500h
+            0xB3, 0x05,       // MOV BL, 5
+            0xF6, 0xF3,       // DIV BL
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        // Point the divide error vector at 0000:0200.
+        cpu.bus_mut().copy_from(&[0x00, 0x02, 0x00, 0x00], 0, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x200);
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        // The quotient would overflow AL, so AX is unchanged and the handler returns to the
+        // instruction after DIV.
+        assert_eq!(cpu.get_register16(Register16::AX), 0x0500);
+        assert_eq!(cpu.get_register16(Register16::SP), 0x03FA);
+        let (ret_ip, _) = cpu.bus_mut().read_u16(0x3FA, 0).unwrap();
+        assert_eq!(ret_ip, 0x010A);
+
+        // The pushed flags are those of the microcode's initial AH - divisor subtraction.
+        let (flags, _) = cpu.bus_mut().read_u16(0x3FE, 0).unwrap();
+        let arith =
+            CPU_FLAG_CARRY | CPU_FLAG_PARITY | CPU_FLAG_AUX_CARRY | CPU_FLAG_ZERO | CPU_FLAG_SIGN | CPU_FLAG_OVERFLOW;
+        assert_eq!(flags & arith, CPU_FLAG_ZERO | CPU_FLAG_PARITY);
+    }
+
+    #[test]
+    fn test_rep_interrupt() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x04, // MOV SP, 0400h
+            0xB9, 0x04, 0x00, // MOV CX, 4
+            0xBE, 0x00, 0x03, // MOV SI, 0300h
+            0xBF, 0x00, 0x05, // MOV DI, 0500h
+            0xFB,             // STI
+            0x26, 0xF3, 0xA4, // ES: REP MOVSB
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        // Point vector 7, used when no PIC supplies one, at 0000:0200.
+        cpu.bus_mut()
+            .copy_from(&[0x00, 0x02, 0x00, 0x00], 0x1C, 0, false)
+            .unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x200);
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            // Raise INTR during the first iteration of the string instruction.
+            if cpu.in_rep() {
+                cpu.set_intr(true);
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        // The REP completes one more iteration before RPTI terminates it.
+        assert!(!cpu.in_rep());
+        assert_eq!(cpu.get_register16(Register16::CX), 2);
+        assert_eq!(cpu.get_register16(Register16::DI), 0x0502);
+
+        // The return address points at the last prefix, so the segment override is lost on
+        // resumption, as on a real 8088.
+        let (ret_ip, _) = cpu.bus_mut().read_u16(0x3FA, 0).unwrap();
+        assert_eq!(ret_ip, 0x010E);
+    }
+
+    #[test]
+    fn test_rep_trap() {
+        #[rustfmt::skip]
+        let program = [
+            0xBC, 0x00, 0x04, // MOV SP, 0400h
+            0xB9, 0x05, 0x00, // MOV CX, 5
+            0xBF, 0x00, 0x05, // MOV DI, 0500h
+            0x9C,             // PUSHF
+            0x58,             // POP AX
+            0x0D, 0x00, 0x01, // OR AX, 0100h
+            0x50,             // PUSH AX
+            0x9D,             // POPF
+            0xF3, 0xAA,       // REP STOSB
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        // Point the trap vector at a NOP at 0000:0200, which lets the last stack write complete.
+        cpu.bus_mut()
+            .copy_from(&[0x00, 0x02, 0x00, 0x00], 0x04, 0, false)
+            .unwrap();
+        cpu.bus_mut().copy_from(&[0x90], 0x200, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x201);
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        // The trap is recognized through RPTI, so the REP terminates cleanly and the handler
+        // returns to it with the remaining count.
+        assert!(!cpu.in_rep());
+        assert!(cpu.get_register16(Register16::CX) > 0);
+        let (ret_ip, _) = cpu.bus_mut().read_u16(0x3FA, 0).unwrap();
+        assert_eq!(ret_ip, 0x0110);
+    }
+
+    #[test]
+    fn test_instruction_hooks() {
+        #[rustfmt::skip]
+        let program = [
+            0xF9,             // STC
+            0xB4, 0x02,       // MOV AH, 2
+            0xCD, 0x13,       // INT 13h
+            0x89, 0xC3,       // MOV BX, AX
+        ];
+
+        let mut cpu = test_cpu(CpuType::Intel8088);
+        cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+        cpu.reset();
+        cpu.set_end_address(0x100 + program.len());
+
+        // Service INT 13h from the host. The IVT is empty, so the interrupt would otherwise jump
+        // into zeroed memory.
+        let hooked_ip = Rc::new(RefCell::new(None));
+        let hooked_ip_clone = hooked_ip.clone();
+        cpu.set_pre_instruction_hook(Some(Box::new(move |i, regs| {
+            if i.opcode == 0xCD && regs.ah == 0x02 {
+                *hooked_ip_clone.borrow_mut() = Some(regs.ip);
+                regs.ax = 0x0001;
+                regs.flags &= !CPU_FLAG_CARRY;
+                return InstructionHookAction::Skip;
+            }
+            InstructionHookAction::Continue
+        })));
+
+        let completed = Rc::new(RefCell::new(Vec::new()));
+        let completed_clone = completed.clone();
+        cpu.set_post_instruction_hook(Some(Box::new(move |i, _regs| {
+            completed_clone.borrow_mut().push(i.opcode);
+            InstructionHookAction::Continue
+        })));
+
+        loop {
+            if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                break;
+            }
+            cpu.step_finish().unwrap();
+        }
+
+        assert_eq!(*hooked_ip.borrow(), Some(0x103));
+        assert_eq!(*completed.borrow(), vec![0xF9, 0xB4, 0x89]);
+        assert_eq!(cpu.get_register16(Register16::BX), 0x0001);
+        assert_eq!(cpu.get_flags() & CPU_FLAG_CARRY, 0);
+    }
+
+    #[test]
+    fn test_ea_cycles() {
+        // EA times published by Intel for each addressing mode, indexed by ea_index().
+        #[rustfmt::skip]
+        const INTEL_EA_CYCLES: [u32; 24] = [
+            7, 8, 8, 7, 5, 5, 6, 5,
+            11, 12, 12, 11, 9, 9, 9, 9,
+            11, 12, 12, 11, 9, 9, 9, 9,
+        ];
+
+        for ea_idx in 0..24u8 {
+            let modrm = ((ea_idx >> 3) << 6) | (ea_idx & 0x07);
+            let disp_len = match modrm >> 6 {
+                0b00 if modrm & 0x07 == 0b110 => 2,
+                0b00 => 0,
+                0b01 => 1,
+                _ => 2,
+            };
+
+            // Give the queue time to fill before the LEA, so that the displacement is read from
+            // the queue.
+            #[rustfmt::skip]
+            let mut program = vec![
+                0xB1, 0x08,       // MOV CL, 8
+                0xD3, 0xE0,       // SHL AX, CL
+                0x8D, modrm,      // LEA AX, [modrm]
+            ];
+            program.extend(std::iter::repeat(0).take(disp_len));
+
+            let mut cpu = test_cpu(CpuType::Intel8088);
+            cpu.bus_mut().copy_from(&program, 0x100, 0, false).unwrap();
+            cpu.set_reset_vector(CpuAddress::Segmented(0x0000, 0x0100));
+            cpu.reset();
+            cpu.set_option(CpuOption::InstructionHistory(true));
+            cpu.set_end_address(0x100 + program.len());
+
+            loop {
+                if let (StepResult::ProgramEnd, _) = cpu.step(false).unwrap() {
+                    break;
+                }
+                cpu.step_finish().unwrap();
+            }
+
+            // Each displacement byte takes one cycle to read from a full queue.
+            let record = cpu.instruction_history(1).pop().unwrap();
+            let measured = record.ea_cycles as u32;
+            let expected = crate::cpu_common::ea::ea_cycles(modrm).unwrap() + disp_len as u32;
+            assert_eq!(measured, expected, "modrm {:02X}", modrm);
+            // Intel's published figures are two cycles longer in every mode.
+            assert_eq!(measured + 2, INTEL_EA_CYCLES[ea_idx as usize], "modrm {:02X}", modrm);
+        }
+    }
+}
@@ -170,12 +170,10 @@ impl Cpu {
                 let data = self.biu_read_u8(Segment::ES, self.di);
                 self.cycles_i(3, &[0x126, 0x127, 0x128]);
 
-                let (result, carry, overflow, aux_carry) = Cpu::sub_u8(self.al, data, false);
+                let (result, carry, _, _) = Cpu::sub_u8(self.al, data, false);
                 // Test operation behaves like CMP
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(self.al, data, result);
 
                 match self.get_flag(Flag::Direction) {
                     false => {
@@ -197,12 +195,10 @@ impl Cpu {
                 let data = self.biu_read_u16(Segment::ES, self.di, ReadWriteFlag::Normal);
                 self.cycles_i(3, &[0x126, 0x127, 0x128]);
 
-                let (result, carry, overflow, aux_carry) = Cpu::sub_u16(self.ax, data, false);
+                let (result, carry, _, _) = Cpu::sub_u16(self.ax, data, false);
                 // Test operation behaves like CMP
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(self.ax, data, result);
 
                 match self.get_flag(Flag::Direction) {
                     false => {
@@ -226,13 +222,11 @@ impl Cpu {
                 let esdi_op = self.biu_read_u8(Segment::ES, self.di);
                 self.cycles_i(3, &[0x126, 0x127, 0x128]);
 
-                let (result, carry, overflow, aux_carry) = Cpu::sub_u8(dssi_op, esdi_op, false);
+                let (result, carry, _, _) = Cpu::sub_u8(dssi_op, esdi_op, false);
 
                 // Test operation behaves like CMP
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u8(result);
+                self.set_sub_flags_u8(dssi_op, esdi_op, result);
 
                 match self.get_flag(Flag::Direction) {
                     false => {
@@ -258,13 +252,11 @@ impl Cpu {
                 let esdi_op = self.biu_read_u16(Segment::ES, self.di, ReadWriteFlag::Normal);
                 self.cycles_i(3, &[0x126, 0x127, 0x128]);
 
-                let (result, carry, overflow, aux_carry) = Cpu::sub_u16(dssi_op, esdi_op, false);
+                let (result, carry, _, _) = Cpu::sub_u16(dssi_op, esdi_op, false);
 
                 // Test operation behaves like CMP
                 self.set_flag_state(Flag::Carry, carry);
-                self.set_flag_state(Flag::Overflow, overflow);
-                self.set_flag_state(Flag::AuxCarry, aux_carry);
-                self.set_szp_flags_from_result_u16(result);
+                self.set_sub_flags_u16(dssi_op, esdi_op, result);
 
                 match self.get_flag(Flag::Direction) {
                     false => {
@@ -279,6 +271,48 @@ impl Cpu {
                     }
                 }
             }
+            Mnemonic::INSB | Mnemonic::INSW => {
+                // INSB, INSW: Input from port DX to [es:di] (ES prefix cannot be overridden)
+                // No flags affected
+                let delta = match opcode {
+                    Mnemonic::INSB => {
+                        let data = self.biu_io_read_u8(self.dx);
+                        self.biu_write_u8(Segment::ES, self.di, data, ReadWriteFlag::Normal);
+                        1
+                    }
+                    _ => {
+                        let data = self.biu_io_read_u16(self.dx, ReadWriteFlag::Normal);
+                        self.biu_write_u16(Segment::ES, self.di, data, ReadWriteFlag::Normal);
+                        2
+                    }
+                };
+
+                match self.get_flag(Flag::Direction) {
+                    false => self.di = self.di.wrapping_add(delta),
+                    true => self.di = self.di.wrapping_sub(delta),
+                }
+            }
+            Mnemonic::OUTSB | Mnemonic::OUTSW => {
+                // OUTSB, OUTSW: Output [ds:si] to port DX (Segment overrideable)
+                // No flags affected
+                let delta = match opcode {
+                    Mnemonic::OUTSB => {
+                        let data = self.biu_read_u8(segment_base_ds, self.si);
+                        self.biu_io_write_u8(self.dx, data, ReadWriteFlag::Normal);
+                        1
+                    }
+                    _ => {
+                        let data = self.biu_read_u16(segment_base_ds, self.si, ReadWriteFlag::Normal);
+                        self.biu_io_write_u16(self.dx, data, ReadWriteFlag::Normal);
+                        2
+                    }
+                };
+
+                match self.get_flag(Flag::Direction) {
+                    false => self.si = self.si.wrapping_add(delta),
+                    true => self.si = self.si.wrapping_sub(delta),
+                }
+            }
             _ => {
                 panic!("CPU: Unhandled opcode to string_op(): {:?}", opcode);
             }
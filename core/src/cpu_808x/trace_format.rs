@@ -0,0 +1,364 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_808x::trace_format.rs
+
+    Implements machine-readable output formats for per-cycle CPU traces.
+
+    Each cycle is captured as a fixed CycleTraceRecord and handed to a
+    CycleTraceWriter, which may emit CSV, JSON Lines or a compact binary
+    encoding. Binary traces are roughly an order of magnitude smaller than
+    text traces and can be read back with CycleTraceReader or converted to
+    one of the text formats after the fact with convert_binary_trace().
+
+*/
+
+use std::io::{self, Read, Write};
+
+use serde::Serialize;
+
+use crate::cpu_808x::microcode::{MC_CORR, MC_JUMP, MC_NONE, MC_RTN};
+
+/// Magic bytes at the start of a binary cycle trace.
+pub const BINARY_TRACE_MAGIC: &[u8; 4] = b"MTRC";
+pub const BINARY_TRACE_VERSION: u16 = 1;
+/// Size in bytes of a single encoded record in a binary cycle trace.
+pub const BINARY_TRACE_RECORD_SIZE: usize = 28;
+
+// Bits of CycleTraceRecord::signals, reflecting the 8288 bus controller outputs.
+pub const SIGNAL_ALE: u8 = 0b0000_0001;
+pub const SIGNAL_MRDC: u8 = 0b0000_0010;
+pub const SIGNAL_AMWC: u8 = 0b0000_0100;
+pub const SIGNAL_MWTC: u8 = 0b0000_1000;
+pub const SIGNAL_IORC: u8 = 0b0001_0000;
+pub const SIGNAL_AIOWC: u8 = 0b0010_0000;
+pub const SIGNAL_IOWC: u8 = 0b0100_0000;
+pub const SIGNAL_INTA: u8 = 0b1000_0000;
+
+// Bits of CycleTraceRecord::flags, reflecting CPU input pins.
+pub const FLAG_READY: u8 = 0b0000_0001;
+pub const FLAG_INTR: u8 = 0b0000_0010;
+
+// Names for the encoded enum fields of a record, indexed by value.
+const BUS_STATUS_NAMES: [&str; 8] = ["IRQA", "IOR", "IOW", "HALT", "CODE", "MEMR", "MEMW", "PASV"];
+const T_CYCLE_NAMES: [&str; 7] = ["Tx", "Ti", "T1", "T2", "T3", "Tw", "T4"];
+const SEGMENT_NAMES: [&str; 5] = ["ES", "SS", "CS", "DS", ""];
+const QUEUE_OP_NAMES: [&str; 4] = ["", "F", "E", "S"];
+
+/// The state of the CPU bus and queue for a single clock cycle.
+/// Enum fields are stored in their encoded form:
+///  - bus_status: the S2-S0 status lines, 0 (INTA) through 7 (passive)
+///  - t_cycle: 0 = Tinit, 1 = Ti, 2 = T1, 3 = T2, 4 = T3, 5 = Tw, 6 = T4
+///  - segment: the S4-S3 status lines, 0 = ES, 1 = SS, 2 = CS, 3 = DS, 4 = none
+///  - queue_op: the QS1-QS0 status lines, 0 = idle, 1 = first, 2 = flush, 3 = subsequent
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CycleTraceRecord {
+    pub cycle: u64,
+    pub instr_cycle: u32,
+    pub address: u32,
+    pub data_bus: u16,
+    pub mc_line: u16,
+    pub bus_status: u8,
+    pub t_cycle: u8,
+    pub segment: u8,
+    pub signals: u8,
+    pub flags: u8,
+    pub queue_op: u8,
+    pub queue_len: u8,
+    pub queue_byte: u8,
+}
+
+impl CycleTraceRecord {
+    pub fn to_bytes(&self) -> [u8; BINARY_TRACE_RECORD_SIZE] {
+        let mut buf = [0u8; BINARY_TRACE_RECORD_SIZE];
+        buf[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.instr_cycle.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.address.to_le_bytes());
+        buf[16..18].copy_from_slice(&self.data_bus.to_le_bytes());
+        buf[18..20].copy_from_slice(&self.mc_line.to_le_bytes());
+        buf[20] = self.bus_status;
+        buf[21] = self.t_cycle;
+        buf[22] = self.segment;
+        buf[23] = self.signals;
+        buf[24] = self.flags;
+        buf[25] = self.queue_op;
+        buf[26] = self.queue_len;
+        buf[27] = self.queue_byte;
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; BINARY_TRACE_RECORD_SIZE]) -> Self {
+        CycleTraceRecord {
+            cycle: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            instr_cycle: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            address: u32::from_le_bytes(buf[12..16].try_into().unwrap()),
+            data_bus: u16::from_le_bytes(buf[16..18].try_into().unwrap()),
+            mc_line: u16::from_le_bytes(buf[18..20].try_into().unwrap()),
+            bus_status: buf[20],
+            t_cycle: buf[21],
+            segment: buf[22],
+            signals: buf[23],
+            flags: buf[24],
+            queue_op: buf[25],
+            queue_len: buf[26],
+            queue_byte: buf[27],
+        }
+    }
+
+    #[inline]
+    pub fn signal(&self, mask: u8) -> bool {
+        self.signals & mask != 0
+    }
+
+    #[inline]
+    pub fn flag(&self, mask: u8) -> bool {
+        self.flags & mask != 0
+    }
+
+    fn mc_line_str(&self) -> String {
+        match self.mc_line {
+            MC_NONE => String::new(),
+            MC_JUMP => "JMP".to_string(),
+            MC_RTN => "RET".to_string(),
+            MC_CORR => "COR".to_string(),
+            line => format!("{:03X}", line),
+        }
+    }
+}
+
+fn name_of(names: &[&'static str], value: u8) -> &'static str {
+    names.get(value as usize).copied().unwrap_or("?")
+}
+
+/// An output format for cycle trace records. Writers are stateless; the header is written once
+/// at the start of the trace, followed by one call to write_record() per cycle.
+pub trait CycleTraceWriter {
+    fn write_header(&self, out: &mut dyn Write) -> io::Result<()>;
+    fn write_record(&self, out: &mut dyn Write, record: &CycleTraceRecord) -> io::Result<()>;
+}
+
+/// Writes one comma-separated line per cycle, with enum fields written by name and bus values
+/// in hexadecimal.
+pub struct CsvTraceWriter;
+
+impl CycleTraceWriter for CsvTraceWriter {
+    fn write_header(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "cycle,instr_cycle,address,segment,bus_status,t_cycle,ale,mrdc,amwc,mwtc,iorc,aiowc,iowc,inta,ready,intr,data_bus,queue_op,queue_len,queue_byte,mc_line"
+        )
+    }
+
+    fn write_record(&self, out: &mut dyn Write, record: &CycleTraceRecord) -> io::Result<()> {
+        let bit = |set: bool| if set { 1 } else { 0 };
+        writeln!(
+            out,
+            "{},{},{:05X},{},{},{},{},{},{},{},{},{},{},{},{},{},{:04X},{},{},{:02X},{}",
+            record.cycle,
+            record.instr_cycle,
+            record.address,
+            name_of(&SEGMENT_NAMES, record.segment),
+            name_of(&BUS_STATUS_NAMES, record.bus_status),
+            name_of(&T_CYCLE_NAMES, record.t_cycle),
+            bit(record.signal(SIGNAL_ALE)),
+            bit(record.signal(SIGNAL_MRDC)),
+            bit(record.signal(SIGNAL_AMWC)),
+            bit(record.signal(SIGNAL_MWTC)),
+            bit(record.signal(SIGNAL_IORC)),
+            bit(record.signal(SIGNAL_AIOWC)),
+            bit(record.signal(SIGNAL_IOWC)),
+            bit(record.signal(SIGNAL_INTA)),
+            bit(record.flag(FLAG_READY)),
+            bit(record.flag(FLAG_INTR)),
+            record.data_bus,
+            name_of(&QUEUE_OP_NAMES, record.queue_op),
+            record.queue_len,
+            record.queue_byte,
+            record.mc_line_str(),
+        )
+    }
+}
+
+/// Writes one JSON object per line, with all fields in their raw numeric form.
+pub struct JsonLinesTraceWriter;
+
+impl CycleTraceWriter for JsonLinesTraceWriter {
+    fn write_header(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_record(&self, out: &mut dyn Write, record: &CycleTraceRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut *out, record)?;
+        writeln!(out)
+    }
+}
+
+/// Writes a short file header followed by fixed-size little-endian records.
+pub struct BinaryTraceWriter;
+
+impl CycleTraceWriter for BinaryTraceWriter {
+    fn write_header(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(BINARY_TRACE_MAGIC)?;
+        out.write_all(&BINARY_TRACE_VERSION.to_le_bytes())?;
+        out.write_all(&(BINARY_TRACE_RECORD_SIZE as u16).to_le_bytes())
+    }
+
+    fn write_record(&self, out: &mut dyn Write, record: &CycleTraceRecord) -> io::Result<()> {
+        out.write_all(&record.to_bytes())
+    }
+}
+
+/// Reads records back from a binary cycle trace.
+pub struct CycleTraceReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> CycleTraceReader<R> {
+    /// Validate the binary trace header and return a reader positioned at the first record.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header)?;
+
+        if &header[0..4] != BINARY_TRACE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a binary cycle trace"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        let record_size = u16::from_le_bytes([header[6], header[7]]) as usize;
+        if version != BINARY_TRACE_VERSION || record_size != BINARY_TRACE_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Unsupported binary cycle trace version {} (record size {})",
+                    version, record_size
+                ),
+            ));
+        }
+
+        Ok(CycleTraceReader { input })
+    }
+}
+
+impl<R: Read> Iterator for CycleTraceReader<R> {
+    type Item = io::Result<CycleTraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0u8; BINARY_TRACE_RECORD_SIZE];
+        match self.input.read_exact(&mut buf) {
+            Ok(()) => Some(Ok(CycleTraceRecord::from_bytes(&buf))),
+            // A trace cut short mid-record (ie, by a crash) ends at the last complete record.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Convert a binary cycle trace to another format. Returns the number of records converted.
+pub fn convert_binary_trace(input: impl Read, out: &mut dyn Write, format: &dyn CycleTraceWriter) -> io::Result<u64> {
+    let reader = CycleTraceReader::new(input)?;
+    let mut count = 0;
+
+    format.write_header(out)?;
+    for record in reader {
+        format.write_record(out, &record?)?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_records() -> Vec<CycleTraceRecord> {
+        vec![
+            CycleTraceRecord {
+                cycle: 0x1_0000_0001,
+                instr_cycle: 3,
+                address: 0xFFFF0,
+                data_bus: 0x00EA,
+                mc_line: MC_NONE,
+                bus_status: 4,
+                t_cycle: 4,
+                segment: 2,
+                signals: SIGNAL_MRDC,
+                flags: FLAG_READY,
+                queue_op: 0,
+                queue_len: 1,
+                queue_byte: 0,
+            },
+            CycleTraceRecord {
+                cycle: 0x1_0000_0002,
+                instr_cycle: 0,
+                address: 0x00400,
+                data_bus: 0x1234,
+                mc_line: 0x118,
+                bus_status: 6,
+                t_cycle: 3,
+                segment: 3,
+                signals: SIGNAL_AMWC | SIGNAL_MWTC,
+                flags: FLAG_READY | FLAG_INTR,
+                queue_op: 1,
+                queue_len: 3,
+                queue_byte: 0xA4,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let records = test_records();
+        let mut trace = Vec::new();
+        BinaryTraceWriter.write_header(&mut trace).unwrap();
+        for record in &records {
+            BinaryTraceWriter.write_record(&mut trace, record).unwrap();
+        }
+        // Simulate a truncated final record.
+        trace.extend_from_slice(&[0u8; 5]);
+        assert_eq!(trace.len(), 8 + 2 * BINARY_TRACE_RECORD_SIZE + 5);
+
+        let read_back: Vec<CycleTraceRecord> = CycleTraceReader::new(trace.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read_back, records);
+
+        let mut csv = Vec::new();
+        let count = convert_binary_trace(trace.as_slice(), &mut csv, &CsvTraceWriter).unwrap();
+        assert_eq!(count, 2);
+
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("cycle,instr_cycle,address"));
+        assert_eq!(
+            lines[2],
+            "4294967298,0,00400,DS,MEMW,T2,0,0,1,1,0,0,0,0,1,1,1234,F,3,A4,118"
+        );
+
+        assert!(CycleTraceReader::new(&b"NOPE\x01\x00\x1c\x00"[..]).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_common::ea.rs
+
+    Effective address calculation timings for the 8088 and 8086.
+
+    The cost of an EA calculation is determined by the microcode procedure
+    selected by the mod and r/m fields of the modrm byte. Each procedure is
+    entered with a single jump, spends a fixed number of cycles combining
+    the base and index registers, reads any displacement from the prefetch
+    queue, and then spends a fixed number of cycles adding the displacement.
+
+    Only the time spent reading the displacement depends on the state of the
+    queue, so the remaining costs are given here as tables indexed by
+    ea_index(). Costs were taken from the microcode disassembly by reenigne:
+    https://www.reenigne.org/blog/8086-microcode-disassembled/
+
+*/
+
+/// Cycles spent jumping into the EA procedure, for every memory addressing mode.
+pub const EA_ENTRY_CYCLES: u32 = 1;
+
+/// Cycles spent in each EA procedure before the displacement is read, indexed by ea_index().
+///
+/// BX+SI and BP+DI are a cycle faster than BX+DI and BP+SI, as the latter pair share microcode
+/// with the former after an extra jump.
+pub const EA_PRE_DISP_CYCLES: [u8; 24] = [
+    4, 5, 5, 4, 2, 2, 0, 2, // mod 00: [BX+SI] [BX+DI] [BP+SI] [BP+DI] [SI] [DI] [disp16] [BX]
+    4, 5, 5, 4, 2, 2, 2, 2, // mod 01: as above + disp8, with [BP+disp8] in place of [disp16]
+    4, 5, 5, 4, 2, 2, 2, 2, // mod 10: as above + disp16, with [BP+disp16] in place of [disp16]
+];
+
+/// Cycles spent in each EA procedure after the displacement is read, indexed by ea_index().
+///
+/// An 8-bit displacement costs a cycle more than a 16-bit displacement, due to an extra jump
+/// at microcode line 1DE.
+pub const EA_POST_DISP_CYCLES: [u8; 24] = [
+    0, 0, 0, 0, 0, 0, 1, 0, // mod 00
+    3, 3, 3, 3, 3, 3, 3, 3, // mod 01
+    2, 2, 2, 2, 2, 2, 2, 2, // mod 10
+];
+
+/// Return the index of the addressing mode selected by a modrm byte into the EA cycle tables, or
+/// None if the modrm byte selects a register operand.
+pub const fn ea_index(modrm: u8) -> Option<usize> {
+    let b_mod = modrm >> 6;
+    if b_mod == 0b11 {
+        None
+    }
+    else {
+        Some(((b_mod << 3) | (modrm & 0x07)) as usize)
+    }
+}
+
+/// Return the number of cycles the EA calculation for a modrm byte takes, not including the
+/// time spent reading a displacement from the queue, or None if the modrm byte selects a register
+/// operand.
+pub const fn ea_cycles(modrm: u8) -> Option<u32> {
+    match ea_index(modrm) {
+        Some(idx) => Some(EA_ENTRY_CYCLES + EA_PRE_DISP_CYCLES[idx] as u32 + EA_POST_DISP_CYCLES[idx] as u32),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ea_cycles() {
+        // Register operands have no EA calculation.
+        assert_eq!(ea_cycles(0xC0), None);
+        assert_eq!(ea_cycles(0xFF), None);
+
+        // [BX+SI], [BX+DI], [disp16], [BX+disp8], [BP+DI+disp16]
+        assert_eq!(ea_cycles(0b00_000_000), Some(5));
+        assert_eq!(ea_cycles(0b00_111_001), Some(6));
+        assert_eq!(ea_cycles(0b00_000_110), Some(2));
+        assert_eq!(ea_cycles(0b01_000_111), Some(6));
+        assert_eq!(ea_cycles(0b10_010_011), Some(7));
+
+        // The reg field does not affect timing.
+        for modrm in 0..0xC0u8 {
+            assert_eq!(ea_cycles(modrm), ea_cycles(modrm & 0b11_000_111));
+        }
+    }
+}
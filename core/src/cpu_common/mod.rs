@@ -43,10 +43,20 @@ pub enum HaltMode {
     Stop,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum CpuType {
     Intel8088,
     Intel8086,
+    Intel80286,
+}
+
+impl CpuType {
+    /// Returns true if the BIU transfers an aligned word in a single bus cycle.
+    /// The 8086 is excluded: its word transfers still take the 8088's two-cycle path, which is
+    /// the timing its validation has been done against.
+    pub fn has_16bit_bus(&self) -> bool {
+        matches!(self, CpuType::Intel80286)
+    }
 }
 
 pub enum CycleTraceMode {
@@ -55,12 +65,33 @@ pub enum CycleTraceMode {
     Sigrok,
 }
 
+/// Output format for exporting the CPU instruction history.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum HistoryExportFormat {
+    #[default]
+    Text,
+    Csv,
+    Json,
+}
+
+impl HistoryExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HistoryExportFormat::Text => "txt",
+            HistoryExportFormat::Csv => "csv",
+            HistoryExportFormat::Json => "json",
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum TraceMode {
     None,
     CycleText,
     CycleCsv,
     CycleSigrok,
+    CycleJson,
+    CycleBinary,
     Instruction,
 }
 
@@ -75,6 +106,8 @@ impl FromStr for TraceMode {
             "cycletext" => Ok(TraceMode::CycleText),
             "cyclecsv" => Ok(TraceMode::CycleCsv),
             "cyclesigrok" => Ok(TraceMode::CycleSigrok),
+            "cyclejson" => Ok(TraceMode::CycleJson),
+            "cyclebinary" => Ok(TraceMode::CycleBinary),
             "instruction" => Ok(TraceMode::Instruction),
             _ => Err("Bad value for tracemode".to_string()),
         }
@@ -95,18 +128,26 @@ impl Default for CpuType {
 #[derive(Debug)]
 pub enum CpuOption {
     InstructionHistory(bool),
+    InstructionHistoryDepth(usize),
     SimulateDramRefresh(bool, u32, u32),
     DramRefreshAdjust(u32),
+    DramRefreshWaitStates(u32),
     HaltResumeDelay(u32),
     OffRailsDetection(bool),
     EnableWaitStates(bool),
     TraceLoggingEnabled(bool),
     EnableServiceInterrupt(bool),
+    DecodeCache(bool),
+    FastCore(bool),
+    TraceMemoryOperands(bool),
+    /// Instructions to delay a trap after the trap flag is set and cleared, respectively.
+    TrapDelays(u32, u32),
 }
 
 use crate::cpu_808x::*;
 
 pub mod alu;
+pub mod ea;
 
 impl Cpu {
     pub fn common_test(&self) {
@@ -46,7 +46,7 @@ use serde::{
     Serializer,
 };
 
-use crate::cpu_808x::QueueOp;
+use crate::cpu_808x::{CpuRegisterState, QueueOp};
 
 pub const VAL_NO_READS: u8 = 0b0000_0001; // Don't validate read op data
 pub const VAL_NO_WRITES: u8 = 0b0000_0010; // Don't validate write op data
@@ -60,6 +60,7 @@ pub enum ValidatorType {
     None,
     Pi8088,
     Arduino8088,
+    Remote,
 }
 
 impl Default for ValidatorType {
@@ -76,6 +77,7 @@ impl FromStr for ValidatorType {
         match s.to_lowercase().as_str() {
             "pi8088" => Ok(ValidatorType::Pi8088),
             "arduino8088" => Ok(ValidatorType::Arduino8088),
+            "remote" => Ok(ValidatorType::Remote),
             _ => Err("Bad value for validatortype".to_string()),
         }
     }
@@ -168,6 +170,92 @@ impl Display for VRegisters {
     }
 }
 
+impl From<&CpuRegisterState> for VRegisters {
+    fn from(state: &CpuRegisterState) -> Self {
+        VRegisters {
+            ax:    state.ax,
+            bx:    state.bx,
+            cx:    state.cx,
+            dx:    state.dx,
+            cs:    state.cs,
+            ss:    state.ss,
+            ds:    state.ds,
+            es:    state.es,
+            sp:    state.sp,
+            bp:    state.bp,
+            si:    state.si,
+            di:    state.di,
+            ip:    state.ip,
+            flags: state.flags,
+        }
+    }
+}
+
+impl VRegisters {
+    /// Register names in the order used by to_hex_string() and from_str().
+    pub const NAMES: [&'static str; 14] = [
+        "AX", "BX", "CX", "DX", "SP", "BP", "SI", "DI", "CS", "DS", "ES", "SS", "IP", "FLAGS",
+    ];
+
+    fn to_array(self) -> [u16; 14] {
+        [
+            self.ax, self.bx, self.cx, self.dx, self.sp, self.bp, self.si, self.di, self.cs, self.ds, self.es, self.ss,
+            self.ip, self.flags,
+        ]
+    }
+
+    /// Format the registers as space-separated hex words in the order of VRegisters::NAMES.
+    /// This is the register encoding used by the network validation protocols.
+    pub fn to_hex_string(&self) -> String {
+        self.to_array()
+            .iter()
+            .map(|r| format!("{:04X}", r))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Return the name and values of each register that differs between two register sets.
+    /// Only the bits of FLAGS set in flag_mask are compared.
+    pub fn diff(&self, other: &VRegisters, flag_mask: u16) -> Vec<(&'static str, u16, u16)> {
+        let mut diffs = Vec::new();
+        for (i, (a, b)) in self.to_array().iter().zip(other.to_array().iter()).enumerate() {
+            let mask = if i == 13 { flag_mask } else { 0xFFFF };
+            if a & mask != b & mask {
+                diffs.push((VRegisters::NAMES[i], *a, *b));
+            }
+        }
+        diffs
+    }
+}
+
+impl FromStr for VRegisters {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut r = [0u16; 14];
+        let mut words = s.split_whitespace();
+        for reg in r.iter_mut() {
+            let word = words.next().ok_or_else(|| "Too few registers".to_string())?;
+            *reg = u16::from_str_radix(word, 16).map_err(|e| format!("Bad register value '{}': {}", word, e))?;
+        }
+        Ok(VRegisters {
+            ax:    r[0],
+            bx:    r[1],
+            cx:    r[2],
+            dx:    r[3],
+            sp:    r[4],
+            bp:    r[5],
+            si:    r[6],
+            di:    r[7],
+            cs:    r[8],
+            ds:    r[9],
+            es:    r[10],
+            ss:    r[11],
+            ip:    r[12],
+            flags: r[13],
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum ValidatorError {
     ParameterError,
@@ -252,7 +340,7 @@ pub struct CycleState {
     pub q_op: QueueOp,
     pub q_byte: u8,
     pub q_len: u32,
-    pub q: [u8; 4],
+    pub q: [u8; 6], // Large enough for the 6-byte queue of the 8086 and 80286
     pub data_bus: u16,
 }
 
@@ -459,7 +547,7 @@ impl<'de> de::Deserialize<'de> for CycleState {
                     q_op,
                     q_byte,
                     q_len: 0,
-                    q: [0; 4],
+                    q: [0; 6],
                     data_bus,
                 })
             }
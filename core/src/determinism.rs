@@ -0,0 +1,308 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    determinism.rs
+
+    Implements a determinism audit. Two identically configured machines are
+    run side by side and hashes of their state are compared at regular
+    intervals. When the hashes diverge, the run is repeated from power-on
+    and the diverging segment is single-stepped to find the first
+    instruction at which the machines differ.
+*/
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::Error;
+
+use crate::machine::{ExecutionControl, ExecutionState, Machine, MachineState};
+
+/// Components of machine state that are hashed individually, so that a divergence can be
+/// attributed to a device.
+pub const STATE_COMPONENTS: [&str; 8] = ["cpu", "memory", "pit", "pic", "dma", "ppi", "keyboard", "video"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateHashes {
+    pub cycle: u64,
+    pub instruction: u64,
+    pub components: Vec<(&'static str, u64)>,
+}
+
+impl StateHashes {
+    /// Hash the current state of the machine.
+    /// The device state getters reset the 'dirty' flags used by the debugger display, so this
+    /// should not be used on a machine being viewed in the debugger.
+    pub fn from_machine(machine: &mut Machine) -> Self {
+        let mut components = Vec::with_capacity(STATE_COMPONENTS.len());
+
+        components.push(("cpu", hash_of(&machine.cpu().get_string_state())));
+
+        let bus = machine.bus();
+        components.push(("memory", hash_of(&bus.get_slice_at(0, bus.size()))));
+
+        components.push(("pit", hash_of(&machine.pit_state())));
+        components.push(("pic", hash_of(&machine.pic_state())));
+        components.push(("dma", hash_of(&machine.dma_state())));
+        components.push(("ppi", hash_of(&machine.ppi_state())));
+        // KeyboardState contains floats, so hash its debug representation.
+        components.push(("keyboard", hash_of(&format!("{:?}", machine.keyboard_state()))));
+
+        // VideoCardState is a HashMap, so sort it to get a stable iteration order.
+        let video_hash = match machine.videocard_state() {
+            Some(state) => {
+                let mut entries: Vec<_> = state.into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                hash_of(&entries)
+            }
+            None => 0,
+        };
+        // Video memory is held by the card rather than the bus, so hash it separately.
+        let vram_hash = match machine.bus().primary_video() {
+            Some(video) => hash_of(&video.copy_vram()),
+            None => 0,
+        };
+        components.push(("video", hash_of(&(video_hash, vram_hash))));
+
+        Self {
+            cycle: machine.cpu_cycles(),
+            instruction: machine.cpu_instructions(),
+            components,
+        }
+    }
+
+    /// Combine all component hashes into a single value.
+    pub fn combined(&self) -> u64 {
+        hash_of(&self.components)
+    }
+
+    /// Return the names of the components that differ between two sets of hashes.
+    pub fn diverged_components(&self, other: &StateHashes) -> Vec<&'static str> {
+        self.components
+            .iter()
+            .zip(other.components.iter())
+            .filter(|(a, b)| a.1 != b.1)
+            .map(|(a, _)| a.0)
+            .collect()
+    }
+}
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DeterminismAuditParams {
+    /// Total number of CPU cycles to run each machine for.
+    pub cycles: u64,
+    /// Number of CPU cycles to run between state comparisons.
+    pub segment_cycles: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DivergenceReport {
+    /// The index of the segment at the end of which the machines were first seen to differ.
+    pub segment: u64,
+    /// The cycle count of the first machine when the divergence was detected.
+    pub cycle: u64,
+    /// The instruction count of the first machine when the divergence was detected.
+    pub instruction: u64,
+    /// The components whose state hashes differed.
+    pub components: Vec<&'static str>,
+    /// True if the divergence was narrowed down to a single instruction. False if the divergence
+    /// did not reproduce on the second run, and so is only known to the segment.
+    pub exact: bool,
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} divergence at cycle {} (instruction {}, segment {}) in: {}",
+            if self.exact { "Exact" } else { "Segment" },
+            self.cycle,
+            self.instruction,
+            self.segment,
+            self.components.join(", ")
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum DeterminismAuditResult {
+    /// Both machines stayed in sync. Contains the number of cycles run and the final rolling hash.
+    Deterministic(u64, u64),
+    Diverged(DivergenceReport),
+}
+
+pub struct DeterminismAudit<F>
+where
+    F: FnMut() -> Result<Machine, Error>,
+{
+    params: DeterminismAuditParams,
+    build:  F,
+}
+
+impl<F> DeterminismAudit<F>
+where
+    F: FnMut() -> Result<Machine, Error>,
+{
+    /// Create a new audit. `build` is called to create each machine, and must return identically
+    /// configured machines. The machines are powered on by the audit.
+    pub fn new(params: DeterminismAuditParams, build: F) -> Self {
+        Self { params, build }
+    }
+
+    pub fn run(&mut self) -> Result<DeterminismAuditResult, Error> {
+        let segment_cycles = self.params.segment_cycles.max(1);
+        let (mut machine_a, mut exec_a) = self.create_machine()?;
+        let (mut machine_b, mut exec_b) = self.create_machine()?;
+
+        let mut rolling_hash = 0;
+        let mut segment = 0;
+
+        while machine_a.cpu_cycles() < self.params.cycles {
+            machine_a.run(segment_cycles, &mut exec_a);
+            machine_b.run(segment_cycles, &mut exec_b);
+
+            let hashes_a = StateHashes::from_machine(&mut machine_a);
+            let hashes_b = StateHashes::from_machine(&mut machine_b);
+
+            if hashes_a != hashes_b {
+                let mut components = hashes_a.diverged_components(&hashes_b);
+                if hashes_a.cycle != hashes_b.cycle || hashes_a.instruction != hashes_b.instruction {
+                    components.insert(0, "timing");
+                }
+                log::warn!(
+                    "Determinism audit: machines diverged in segment {} at cycle {}",
+                    segment,
+                    hashes_a.cycle
+                );
+
+                let report = match self.narrow(segment)? {
+                    Some(report) => report,
+                    None => DivergenceReport {
+                        segment,
+                        cycle: hashes_a.cycle,
+                        instruction: hashes_a.instruction,
+                        components,
+                        exact: false,
+                    },
+                };
+                return Ok(DeterminismAuditResult::Diverged(report));
+            }
+
+            rolling_hash = hash_of(&(rolling_hash, hashes_a.combined()));
+            segment += 1;
+
+            if machine_a.get_error_str().is_some() || matches!(exec_a.get_state(), ExecutionState::Halted) {
+                log::warn!("Determinism audit: machine stopped at cycle {}", machine_a.cpu_cycles());
+                break;
+            }
+        }
+
+        Ok(DeterminismAuditResult::Deterministic(
+            machine_a.cpu_cycles(),
+            rolling_hash,
+        ))
+    }
+
+    /// Re-run both machines from power-on up to the start of the diverging segment, then step
+    /// them one instruction at a time, comparing state after each instruction.
+    fn narrow(&mut self, diverged_segment: u64) -> Result<Option<DivergenceReport>, Error> {
+        let segment_cycles = self.params.segment_cycles.max(1);
+        let (mut machine_a, mut exec_a) = self.create_machine()?;
+        let (mut machine_b, mut exec_b) = self.create_machine()?;
+
+        for _ in 0..diverged_segment {
+            machine_a.run(segment_cycles, &mut exec_a);
+            machine_b.run(segment_cycles, &mut exec_b);
+        }
+
+        let hashes_a = StateHashes::from_machine(&mut machine_a);
+        let hashes_b = StateHashes::from_machine(&mut machine_b);
+        if hashes_a != hashes_b {
+            log::warn!("Determinism audit: divergence occurred earlier on re-run; cannot narrow it down.");
+            return Ok(None);
+        }
+
+        let segment_end = hashes_a.cycle + segment_cycles as u64;
+        while machine_a.cpu_cycles() < segment_end {
+            machine_a.run(1, &mut exec_a);
+            machine_b.run(1, &mut exec_b);
+
+            let hashes_a = StateHashes::from_machine(&mut machine_a);
+            let hashes_b = StateHashes::from_machine(&mut machine_b);
+            if hashes_a != hashes_b {
+                let mut components = hashes_a.diverged_components(&hashes_b);
+                if hashes_a.cycle != hashes_b.cycle {
+                    components.insert(0, "timing");
+                }
+                return Ok(Some(DivergenceReport {
+                    segment: diverged_segment,
+                    cycle: hashes_a.cycle,
+                    instruction: hashes_a.instruction,
+                    components,
+                    exact: true,
+                }));
+            }
+        }
+
+        log::warn!("Determinism audit: divergence did not reproduce on re-run.");
+        Ok(None)
+    }
+
+    fn create_machine(&mut self) -> Result<(Machine, ExecutionControl), Error> {
+        let mut machine = (self.build)()?;
+        machine.change_state(MachineState::On);
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+        Ok((machine, exec_control))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diverged_components() {
+        let a = StateHashes {
+            cycle: 100,
+            instruction: 20,
+            components: vec![("cpu", 1), ("memory", 2), ("pit", 3)],
+        };
+        let mut b = a.clone();
+        assert!(a.diverged_components(&b).is_empty());
+        assert_eq!(a.combined(), b.combined());
+
+        b.components[1].1 = 5;
+        assert_eq!(a.diverged_components(&b), vec!["memory"]);
+        assert_ne!(a.combined(), b.combined());
+    }
+}
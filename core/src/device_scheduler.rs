@@ -0,0 +1,133 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    device_scheduler.rs
+
+    Implements run scheduling for devices that are idle most of the time,
+    such as the floppy and hard disk controllers and serial ports. Rather
+    than being run on every device slice, each such device reports the time
+    until it next needs to run, and is skipped until then. Time elapsed
+    while waiting for a deadline is accumulated and delivered when the
+    device is next run. A device with no deadline is idle, and does not
+    observe the time that passes until it has something to do again.
+
+    A device can be woken to run on the next slice regardless of its
+    deadline. The bus does this whenever one of the device's IO ports is
+    accessed, since that may have started an operation.
+*/
+
+/// Tracks elapsed time and wake state for a single scheduled device.
+#[derive(Default)]
+pub struct DeviceSchedule {
+    elapsed: f64,
+    wake:    bool,
+}
+
+impl DeviceSchedule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Request that the device be run on the next slice.
+    #[inline]
+    pub fn wake(&mut self) {
+        self.wake = true;
+    }
+
+    /// Advance the schedule by the specified number of microseconds. 'deadline' is the time in
+    /// microseconds since the device last ran at which it next needs to run, or None if the
+    /// device is idle. If the device is due, returns the total elapsed time to run it for.
+    #[inline]
+    pub fn tick(&mut self, us: f64, deadline: Option<f64>) -> Option<f64> {
+        if deadline.is_none() && !self.wake {
+            self.elapsed = 0.0;
+            return None;
+        }
+
+        self.elapsed += us;
+
+        if self.wake || deadline.is_some_and(|d| self.elapsed >= d) {
+            let elapsed = self.elapsed;
+            self.elapsed = 0.0;
+            self.wake = false;
+            Some(elapsed)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Return the time in microseconds until the device is next due to run, or None if it is
+    /// idle. 'deadline' is interpreted as in tick().
+    #[inline]
+    pub fn remaining(&self, deadline: Option<f64>) -> Option<f64> {
+        if self.wake {
+            return Some(0.0);
+        }
+        deadline.map(|d| (d - self.elapsed).max(0.0))
+    }
+}
+
+/// Return the earlier of two device deadlines.
+pub fn earliest_deadline(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_schedule() {
+        let mut schedule = DeviceSchedule::new();
+
+        // An idle device is never run, and does not accumulate time.
+        assert_eq!(schedule.tick(10.0, None), None);
+        assert_eq!(schedule.tick(10.0, None), None);
+        schedule.wake();
+        assert_eq!(schedule.tick(5.0, None), Some(5.0));
+
+        // A device is run once its deadline has passed.
+        assert_eq!(schedule.tick(10.0, Some(15.0)), None);
+        assert_eq!(schedule.tick(10.0, Some(15.0)), Some(20.0));
+        assert_eq!(schedule.tick(1.0, Some(0.0)), Some(1.0));
+
+        // The time remaining accounts for the time already elapsed.
+        assert_eq!(schedule.remaining(None), None);
+        assert_eq!(schedule.tick(4.0, Some(10.0)), None);
+        assert_eq!(schedule.remaining(Some(10.0)), Some(6.0));
+        assert_eq!(schedule.remaining(Some(2.0)), Some(0.0));
+        schedule.wake();
+        assert_eq!(schedule.remaining(None), Some(0.0));
+
+        assert_eq!(earliest_deadline(Some(3.0), None), Some(3.0));
+        assert_eq!(earliest_deadline(Some(3.0), Some(1.0)), Some(1.0));
+        assert_eq!(earliest_deadline(None, None), None);
+    }
+}
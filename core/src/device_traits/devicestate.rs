@@ -0,0 +1,68 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    device_traits::devicestate.rs
+
+    Defines the DeviceState trait, which allows a device's internal state to
+    be captured and restored independently of the rest of the machine.
+
+*/
+
+use anyhow::Error;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A trait for devices whose state can be captured, restored, and dumped in a structured form.
+///
+/// A device's state is represented by a plain serializable struct. The state captures the
+/// device's registers and internal latches, but not attached media such as disk images, nor
+/// host-side resources such as serial port bridges.
+pub trait DeviceState {
+    type State: Serialize + DeserializeOwned;
+
+    /// Capture the current state of the device.
+    fn save_state(&self) -> Self::State;
+
+    /// Restore the device to a previously captured state.
+    fn load_state(&mut self, state: Self::State) -> Result<(), Error>;
+
+    /// Return the current state of the device as a structured JSON value, suitable for
+    /// inclusion in a bug report.
+    fn dump_state(&self) -> serde_json::Value {
+        serde_json::to_value(self.save_state()).unwrap_or_default()
+    }
+
+    /// Serialize the current state of the device to a JSON string.
+    fn serialize_state(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(&self.save_state())?)
+    }
+
+    /// Restore the device from a JSON string produced by `serialize_state`.
+    fn deserialize_state(&mut self, text: &str) -> Result<(), Error> {
+        let state = serde_json::from_str(text)?;
+        self.load_state(state)
+    }
+}
@@ -30,4 +30,6 @@
 
 */
 
+pub mod devicestate;
+pub mod nonvolatile;
 pub mod videocard;
@@ -0,0 +1,51 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    device_traits::nonvolatile.rs
+
+    Defines the NonVolatile trait, implemented by devices with battery-backed
+    or EEPROM storage whose contents should survive between sessions.
+
+*/
+
+use anyhow::Error;
+
+/// A trait for devices with non-volatile memory, such as the RAM of a battery-backed clock chip.
+///
+/// The contents are exchanged as a raw byte image, which the NVRAM store persists to a host file
+/// named after the device. A device should reject an image it can't use with an error rather
+/// than panic, as the file may have been written by an older version or edited by hand.
+pub trait NonVolatile {
+    /// A short name for the device's NVRAM image, unique within a machine. Used as a file name.
+    fn nvram_name(&self) -> String;
+
+    /// Return the current contents of the device's non-volatile memory.
+    fn nvram(&self) -> Vec<u8>;
+
+    /// Replace the contents of the device's non-volatile memory.
+    fn load_nvram(&mut self, data: &[u8]) -> Result<(), Error>;
+}
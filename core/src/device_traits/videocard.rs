@@ -68,7 +68,7 @@ use crate::bus::DeviceRunTimeUnit;
 use crate::devices::ega::EGACard;
 #[cfg(feature = "vga")]
 use crate::devices::vga::VGACard;
-use crate::devices::{cga::CGACard, mda::MDACard};
+use crate::devices::{cga::CGACard, compaq_video::CompaqVideoCard, mda::MDACard};
 
 use crate::devices::pic::Pic;
 use serde::Deserialize;
@@ -80,6 +80,7 @@ use serde_derive::Serialize;
 pub enum VideoType {
     MDA,
     CGA,
+    CompaqDual,
     #[cfg(feature = "ega")]
     EGA,
     #[cfg(feature = "vga")]
@@ -101,6 +102,7 @@ impl FromStr for VideoType {
         match s {
             "MDA" => Ok(VideoType::MDA),
             "CGA" => Ok(VideoType::CGA),
+            "CompaqDual" => Ok(VideoType::CompaqDual),
             #[cfg(feature = "ega")]
             "EGA" => Ok(VideoType::EGA),
             #[cfg(feature = "vga")]
@@ -142,10 +144,12 @@ impl FromStr for ClockingMode {
 // This enum holds variants that hold the various implementors of the VideoCard trait.
 // This is used for enum dispatch, to avoid overhead of dynamic dispatch when calling
 // video card methods.
+#[allow(clippy::large_enum_variant)]
 pub enum VideoCardDispatch {
     None,
     Mda(MDACard),
     Cga(CGACard),
+    Compaq(CompaqVideoCard),
     #[cfg(feature = "ega")]
     Ega(EGACard),
     #[cfg(feature = "vga")]
@@ -173,6 +177,55 @@ pub enum VideoOption {
     EnableSnow(bool),
 }
 
+/// Register files of a video card, used to identify and filter register write traces.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub enum VideoRegisterGroup {
+    Crtc,
+    Mode,
+    Palette,
+    Sequencer,
+    Graphics,
+    Attribute,
+}
+
+/// A video register targeted by an IO write.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VideoRegister {
+    pub group: VideoRegisterGroup,
+    pub index: u8,
+    /// The current value of the register, if the card can report it.
+    pub value: Option<u8>,
+}
+
+/// Records the last value written to each register of a video card. Many video registers are
+/// write-only, so the shadow is the only way to inspect them without decoding the card's
+/// internal state.
+#[derive(Clone, Debug, Default)]
+pub struct VideoRegisterShadow {
+    groups: HashMap<VideoRegisterGroup, Vec<u8>>,
+}
+
+impl VideoRegisterShadow {
+    pub fn record(&mut self, group: VideoRegisterGroup, index: u8, value: u8) {
+        let regs = self.groups.entry(group).or_default();
+        let index = index as usize;
+        if regs.len() <= index {
+            regs.resize(index + 1, 0);
+        }
+        regs[index] = value;
+    }
+
+    /// Return the shadowed registers in the specified group, indexed by register number.
+    /// Registers that have never been written read as 0.
+    pub fn group(&self, group: VideoRegisterGroup) -> &[u8] {
+        self.groups.get(&group).map(|regs| regs.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+    }
+}
+
 // This enum determines the rendering method of the given videocard device.
 // Direct mode means the video card draws to a double buffering scheme itself,
 // Indirect mode means that the video renderer draws the device's VRAM. I think
@@ -193,6 +246,7 @@ pub enum RenderBpp {
 //pub const TEXTMODE_MEM_ADDRESS: usize = 0xB8000;
 
 #[allow(dead_code)]
+#[derive(Hash)]
 pub enum VideoCardStateEntry {
     Value8(u8),
     Value16(u16),
@@ -204,7 +258,7 @@ pub type VideoCardState = HashMap<String, Vec<(String, VideoCardStateEntry)>>;
 
 /// All valid graphics modes for CGA, EGA and VGA Cards
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum DisplayMode {
     Disabled,
     Mode0TextBw40,
@@ -245,6 +299,39 @@ pub struct FontInfo {
     pub font_data: &'static [u8],
 }
 
+/// The glyphs of a character generator, stored as 256 glyphs of `height` bytes each. Each byte
+/// is one row of a glyph, with the leftmost pixel in the most significant bit.
+#[derive(Clone, Debug)]
+pub struct GlyphSet {
+    /// Width of a character cell in pixels. This may be wider than 8 where the card generates
+    /// extra columns itself, such as the 9th column of the MDA.
+    pub width:  u32,
+    pub height: u32,
+    pub data:   Vec<u8>,
+}
+
+impl GlyphSet {
+    pub const GLYPH_COUNT: usize = 256;
+
+    /// Build a glyph set from a font bitmap stored as rows of `span` bytes, holding one byte per
+    /// glyph per row. This is the layout of the character ROM dumps used by the video cards.
+    pub fn from_row_bitmap(width: u32, height: u32, bitmap: &[u8], span: usize) -> Self {
+        let mut data = Vec::with_capacity(Self::GLYPH_COUNT * height as usize);
+        for glyph in 0..Self::GLYPH_COUNT {
+            for row in 0..height as usize {
+                data.push(bitmap.get(row * span + glyph).copied().unwrap_or(0));
+            }
+        }
+        Self { width, height, data }
+    }
+
+    /// Return the rows of the specified glyph.
+    pub fn glyph(&self, glyph: u8) -> &[u8] {
+        let start = glyph as usize * self.height as usize;
+        &self.data[start..start + self.height as usize]
+    }
+}
+
 pub enum CGAPalette {
     Monochrome(CGAColor),
     MagentaCyanWhite(CGAColor),
@@ -344,8 +431,24 @@ pub trait VideoCard {
     /// Override the clocking mode for the adapter.
     fn set_clocking_mode(&mut self, mode: ClockingMode);
 
-    /// Returns a slice of u8 representing video memory
-    //fn get_vram(&self) -> &[u8];
+    /// Return the number of VRAM planes. Adapters with linear memory, such as the CGA, have a
+    /// single plane.
+    fn get_vram_plane_count(&self) -> usize;
+
+    /// Return a read-only slice of the specified VRAM plane, or None if the plane doesn't exist.
+    fn get_vram(&self, plane: usize) -> Option<&[u8]>;
+
+    /// Return a copy of every VRAM plane.
+    fn copy_vram(&self) -> Vec<Vec<u8>> {
+        (0..self.get_vram_plane_count())
+            .filter_map(|plane| self.get_vram(plane).map(|slice| slice.to_vec()))
+            .collect()
+    }
+
+    /// Return the shadow of the adapter's register files, if the adapter keeps one.
+    fn get_register_shadow(&self) -> Option<&VideoRegisterShadow> {
+        None
+    }
 
     /// Return the size (width, height) of the last rendered frame.
     fn get_display_size(&self) -> (u32, u32);
@@ -401,10 +504,18 @@ pub trait VideoCard {
     /// Return a FontInfo struct describing the currently selected font
     fn get_current_font(&self) -> FontInfo;
 
+    /// Return the glyphs of the active character generator. For cards with a RAM character
+    /// generator, such as the EGA, this includes any font the guest has loaded.
+    fn get_glyphs(&self) -> GlyphSet;
+
     /// Returns the currently programmed character height
     /// (CRTC Maximum Scanline + 1)
     fn get_character_height(&self) -> u8;
 
+    /// Returns the number of character rows displayed in text mode, as programmed into the
+    /// CRTC, or None if the adapter is in a graphics mode.
+    fn get_text_mode_rows(&self) -> Option<u32>;
+
     /// Returns the current CGA-compatible palette and intensity attribute
     fn get_cga_palette(&self) -> (CGAPalette, bool);
 
@@ -449,4 +560,11 @@ pub trait VideoCard {
     /// Return a vector of Strings representing the current text on screen. If the adapter is not in
     /// text mode, an empty vector should be returned.
     fn get_text_mode_strings(&self) -> Vec<String>;
+
+    /// Return the register that a write to the specified IO port would modify, without side
+    /// effects. Writes that only select a register (such as to the CRTC address register) resolve
+    /// to None. Used for register write tracing.
+    fn get_io_register(&self, _port: u16) -> Option<VideoRegister> {
+        None
+    }
 }
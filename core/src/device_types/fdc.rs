@@ -31,57 +31,106 @@
 
 use crate::device_types::chs::DiskChs;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Recording method of a disk's sectors. The NEC FDC selects the method with the MFM bit of
+/// each read, write and format command.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiskEncoding {
+    /// Single density
+    Fm,
+    /// Double density
+    #[default]
+    Mfm,
+}
+
+/// FDC data rate, as selected by the Configuration Control Register. FM media is recorded at
+/// half the selected rate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataRate {
+    #[default]
+    Rate250Kbps,
+    Rate300Kbps,
+    Rate500Kbps,
+    Rate1Mbps,
+}
+
+impl DataRate {
+    /// Decode the rate select bits of a Configuration Control Register write.
+    pub fn from_ccr(byte: u8) -> DataRate {
+        match byte & 0x03 {
+            0 => DataRate::Rate500Kbps,
+            1 => DataRate::Rate300Kbps,
+            2 => DataRate::Rate250Kbps,
+            _ => DataRate::Rate1Mbps,
+        }
+    }
+
+    /// Return the data rate in kilobits per second.
+    pub fn kbps(&self) -> f64 {
+        match self {
+            DataRate::Rate250Kbps => 250.0,
+            DataRate::Rate300Kbps => 300.0,
+            DataRate::Rate500Kbps => 500.0,
+            DataRate::Rate1Mbps => 1000.0,
+        }
+    }
+}
+
 pub struct DiskFormat {
     pub chs: DiskChs,
+    pub sector_size: usize,
+    pub encoding: DiskEncoding,
+    pub data_rate: DataRate,
+    /// 8" media can only be inserted into 8" drives and vice versa.
+    pub eight_inch: bool,
+}
+
+impl DiskFormat {
+    fn new(chs: DiskChs, sector_size: usize, encoding: DiskEncoding, data_rate: DataRate) -> Self {
+        Self {
+            chs,
+            sector_size,
+            encoding,
+            data_rate,
+            eight_inch: false,
+        }
+    }
+
+    fn new_8inch(chs: DiskChs, sector_size: usize, encoding: DiskEncoding) -> Self {
+        Self {
+            chs,
+            sector_size,
+            encoding,
+            // 8" drives spin at 360rpm and need the 500Kbps clock for both FM and MFM media.
+            data_rate: DataRate::Rate500Kbps,
+            eight_inch: true,
+        }
+    }
+}
+
+/// Convert a sector size in bytes into a sector size code (the 'N' parameter of FDC commands).
+pub fn sector_size_to_code(size: usize) -> u8 {
+    (size / 128).max(1).trailing_zeros() as u8
 }
 
 lazy_static! {
     pub static ref DISK_FORMATS: HashMap<usize, DiskFormat> = {
+        use DataRate::*;
+        use DiskEncoding::*;
         let map = HashMap::from([
-            (
-                163_840,
-                DiskFormat {
-                    chs: DiskChs::new(40, 1, 8),
-                },
-            ),
-            (
-                184_320,
-                DiskFormat {
-                    chs: DiskChs::new(40, 1, 9),
-                },
-            ),
-            (
-                327_680,
-                DiskFormat {
-                    chs: DiskChs::new(40, 2, 8),
-                },
-            ),
-            (
-                368_640,
-                DiskFormat {
-                    chs: DiskChs::new(40, 2, 9),
-                },
-            ),
-            (
-                737_280,
-                DiskFormat {
-                    chs: DiskChs::new(80, 2, 9),
-                },
-            ),
-            (
-                1_228_800,
-                DiskFormat {
-                    chs: DiskChs::new(80, 2, 15),
-                },
-            ),
-            (
-                1_474_560,
-                DiskFormat {
-                    chs: DiskChs::new(80, 2, 18),
-                },
-            ),
+            (163_840, DiskFormat::new(DiskChs::new(40, 1, 8), 512, Mfm, Rate250Kbps)),
+            (184_320, DiskFormat::new(DiskChs::new(40, 1, 9), 512, Mfm, Rate250Kbps)),
+            (327_680, DiskFormat::new(DiskChs::new(40, 2, 8), 512, Mfm, Rate250Kbps)),
+            (368_640, DiskFormat::new(DiskChs::new(40, 2, 9), 512, Mfm, Rate250Kbps)),
+            (737_280, DiskFormat::new(DiskChs::new(80, 2, 9), 512, Mfm, Rate250Kbps)),
+            (1_228_800, DiskFormat::new(DiskChs::new(80, 2, 15), 512, Mfm, Rate500Kbps)),
+            (1_474_560, DiskFormat::new(DiskChs::new(80, 2, 18), 512, Mfm, Rate500Kbps)),
+            // 8" single sided, single density (IBM 3740), the CP/M interchange format.
+            (256_256, DiskFormat::new_8inch(DiskChs::new(77, 1, 26), 128, Fm)),
+            // 8" double sided, double density, 8 x 1024 byte sectors (CP/M-86).
+            (1_261_568, DiskFormat::new_8inch(DiskChs::new(77, 2, 8), 1024, Mfm)),
         ]);
         map
     };
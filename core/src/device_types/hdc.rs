@@ -224,3 +224,9 @@ lazy_static! {
         None,
     ];
 }
+
+/// Return the drive types that can be selected by the switches or jumpers of an XT hard disk
+/// controller.
+pub fn xt_hard_disk_formats() -> Vec<HardDiskFormat> {
+    XT_HARD_DISK_TYPES.iter().flatten().cloned().collect()
+}
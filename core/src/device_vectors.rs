@@ -0,0 +1,310 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    device_vectors.rs
+
+    Implements a data-driven test harness for the PIT, PIC and DMA
+    controller. A test vector is a sequence of port writes and reads,
+    device runs, and checks of the PIC INTR line and PIT outputs. Vectors
+    are loaded from TOML or JSON files and run against a bare bus, without
+    a CPU or the rest of the machine.
+*/
+
+use std::{collections::VecDeque, fs, path::Path};
+
+use anyhow::{anyhow, bail, Error};
+use ringbuf::RingBuffer;
+use serde_derive::Deserialize;
+
+use crate::{
+    bus::BusInterface,
+    devices::keyboard::KeyboardType,
+    machine::KeybufferEntry,
+    machine_config::{get_machine_descriptor, ConventionalMemoryConfig, MachineConfiguration, MemoryConfig},
+    machine_types::MachineType,
+};
+
+const SPEAKER_BUF_LEN: usize = 4096;
+
+fn _default_machine() -> MachineType {
+    MachineType::Ibm5160
+}
+const fn _default_mask() -> u8 {
+    0xFF
+}
+const fn _default_true() -> bool {
+    true
+}
+
+/// A file of device test vectors.
+#[derive(Debug, Deserialize)]
+pub struct DeviceTestFile {
+    pub vectors: Vec<DeviceTestVector>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceTestVector {
+    pub name:    String,
+    #[serde(default = "_default_machine")]
+    pub machine: MachineType,
+    pub steps:   Vec<VectorStep>,
+}
+
+/// A single step of a test vector. Tick counts are in system ticks.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorStep {
+    /// Write a byte to an IO port.
+    Write { port: u16, data: u8 },
+    /// Read a byte from an IO port and compare it, under `mask`, to `expect`.
+    Read {
+        port:   u16,
+        expect: u8,
+        #[serde(default = "_default_mask")]
+        mask:   u8,
+    },
+    /// Run the devices for the specified number of system ticks.
+    Run { ticks: u32 },
+    /// Raise or lower an IRQ line on the primary PIC.
+    Irq {
+        line:   u8,
+        #[serde(default = "_default_true")]
+        active: bool,
+    },
+    /// Acknowledge an interrupt and check the vector returned by the PIC.
+    Inta { expect: u8 },
+    /// Check the state of the PIC INTR line.
+    ExpectIntr { active: bool },
+    /// Check the state of a PIT channel output.
+    ExpectOutput { channel: usize, active: bool },
+    /// Run until the PIC INTR line is raised, which must happen after exactly `ticks` ticks.
+    WaitIntr { ticks: u32 },
+    /// Run until a PIT channel output reaches the given state, which must happen after exactly
+    /// `ticks` ticks. PIT channel 1 drives DREQ0, so this is also used to check DRQ timing.
+    WaitOutput { channel: usize, active: bool, ticks: u32 },
+}
+
+impl DeviceTestFile {
+    /// Load a test file. Files with a .json extension are parsed as JSON, all others as TOML.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Run every vector in the file, returning the name and result of each.
+    pub fn run(&self) -> Vec<(String, Result<(), Error>)> {
+        self.vectors.iter().map(|v| (v.name.clone(), v.run())).collect()
+    }
+}
+
+/// A bus with only the motherboard devices installed.
+struct VectorBus {
+    bus: BusInterface,
+    us_per_tick: f64,
+    kb_buf: VecDeque<KeybufferEntry>,
+    speaker: ringbuf::Producer<u8>,
+    // The consumer is kept so that the speaker buffer stays valid. It is never read.
+    _speaker_out: ringbuf::Consumer<u8>,
+}
+
+impl VectorBus {
+    fn new(machine_type: MachineType) -> Result<Self, Error> {
+        let machine_desc = *get_machine_descriptor(machine_type)
+            .ok_or_else(|| anyhow!("No machine descriptor for {:?}", machine_type))?;
+
+        let machine_config = MachineConfiguration {
+            speaker: false,
+            speaker_profile: None,
+            ppi_turbo: None,
+            turbo_clock: None,
+            reset_vector: None,
+            dram_refresh: None,
+            wait_states: None,
+            open_bus: None,
+            descriptor: None,
+            dip_switches: None,
+            machine_type,
+            memory: MemoryConfig {
+                conventional: ConventionalMemoryConfig {
+                    size: 0x10000,
+                    wait_states: 0,
+                },
+                address_wrap: true,
+            },
+            keyboard: None,
+            serial_mouse: None,
+            game_port: None,
+            rtc: None,
+            video: Vec::new(),
+            serial: Vec::new(),
+            fdc: None,
+            hdc: None,
+            media: None,
+        };
+
+        let mut bus = BusInterface::new(machine_desc.cpu_factor, machine_desc, KeyboardType::ModelF);
+        bus.install_devices(&machine_desc, &machine_config)?;
+
+        let (speaker, speaker_out) = RingBuffer::<u8>::new(SPEAKER_BUF_LEN).split();
+
+        Ok(Self {
+            bus,
+            us_per_tick: 1.0 / machine_desc.system_crystal,
+            kb_buf: VecDeque::new(),
+            speaker,
+            _speaker_out: speaker_out,
+        })
+    }
+
+    /// Run the devices one tick at a time. Running them in larger steps would let DMA requests
+    /// raised during the step coalesce, as DMA is only serviced once per run_devices() call.
+    fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.bus
+                .run_devices(self.us_per_tick, 1, None, &mut self.kb_buf, &mut self.speaker);
+        }
+    }
+
+    fn intr(&mut self) -> bool {
+        self.bus.pic_mut().as_ref().unwrap().query_interrupt_line()
+    }
+
+    fn pit_output(&self, channel: usize) -> bool {
+        self.bus.pit().as_ref().unwrap().get_output_state(channel)
+    }
+
+    /// Run one tick at a time until `cond` is true. Fails unless it first becomes true after
+    /// exactly `ticks` ticks.
+    fn wait<F>(&mut self, ticks: u32, what: &str, mut cond: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        for elapsed in 1..=ticks {
+            self.run(1);
+            if cond(self) {
+                if elapsed != ticks {
+                    bail!("{} after {} ticks, expected {}", what, elapsed, ticks);
+                }
+                return Ok(());
+            }
+        }
+        bail!("no {} within {} ticks", what, ticks)
+    }
+}
+
+impl DeviceTestVector {
+    pub fn run(&self) -> Result<(), Error> {
+        let mut vbus = VectorBus::new(self.machine)?;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            self.run_step(&mut vbus, step)
+                .map_err(|e| anyhow!("step {} ({:?}): {}", i, step, e))?;
+        }
+        Ok(())
+    }
+
+    fn run_step(&self, vbus: &mut VectorBus, step: &VectorStep) -> Result<(), Error> {
+        match *step {
+            VectorStep::Write { port, data } => vbus.bus.io_write_u8(port, data, 0),
+            VectorStep::Read { port, expect, mask } => {
+                let byte = vbus.bus.io_read_u8(port, 0);
+                if byte & mask != expect & mask {
+                    bail!("read {:02X}, expected {:02X} (mask {:02X})", byte, expect, mask);
+                }
+            }
+            VectorStep::Run { ticks } => vbus.run(ticks),
+            VectorStep::Irq { line, active } => {
+                let pic = vbus.bus.pic_mut().as_mut().unwrap();
+                match active {
+                    true => pic.request_interrupt(line),
+                    false => pic.clear_interrupt(line),
+                }
+            }
+            VectorStep::Inta { expect } => {
+                let vector = vbus.bus.pic_mut().as_mut().unwrap().get_interrupt_vector();
+                if vector != Some(expect) {
+                    bail!("got vector {:02X?}, expected {:02X}", vector, expect);
+                }
+            }
+            VectorStep::ExpectIntr { active } => {
+                if vbus.intr() != active {
+                    bail!("INTR is {}, expected {}", !active, active);
+                }
+            }
+            VectorStep::ExpectOutput { channel, active } => {
+                if vbus.pit_output(channel) != active {
+                    bail!("PIT output {} is {}, expected {}", channel, !active, active);
+                }
+            }
+            VectorStep::WaitIntr { ticks } => {
+                vbus.wait(ticks, "INTR", |vbus| vbus.intr())?;
+            }
+            VectorStep::WaitOutput { channel, active, ticks } => {
+                vbus.wait(ticks, &format!("PIT output {} {}", channel, active), |vbus| {
+                    vbus.pit_output(channel) == active
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn device_test_vectors() {
+        let mut dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        dir.push("tests/device_vectors");
+
+        let mut failures = Vec::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let file = DeviceTestFile::from_path(&path).unwrap_or_else(|e| panic!("{:?}: {}", path, e));
+            for (name, result) in file.run() {
+                if let Err(e) = result {
+                    failures.push(format!("{:?}: {}: {}", path.file_name().unwrap(), name, e));
+                }
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "Device test vectors failed:\n{}",
+            failures.join("\n")
+        );
+    }
+}
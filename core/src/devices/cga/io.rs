@@ -30,9 +30,9 @@
 
 */
 use super::*;
-use crate::bus::IoDevice;
+use crate::bus::{IoDevice, NO_IO_BYTE};
 
-// CRTC registers are mirrored from 0x3D0 - 0x3D5 due to incomplete
+// CRTC registers are mirrored from 0x3D0 - 0x3D7 due to incomplete
 // address decoding.
 pub const CRTC_REGISTER_SELECT0: u16 = 0x3D0;
 pub const CRTC_REGISTER0: u16 = 0x3D1;
@@ -40,6 +40,8 @@ pub const CRTC_REGISTER_SELECT1: u16 = 0x3D2;
 pub const CRTC_REGISTER1: u16 = 0x3D3;
 pub const CRTC_REGISTER_SELECT2: u16 = 0x3D4;
 pub const CRTC_REGISTER2: u16 = 0x3D5;
+pub const CRTC_REGISTER_SELECT3: u16 = 0x3D6;
+pub const CRTC_REGISTER3: u16 = 0x3D7;
 
 pub const CRTC_REGISTER_BASE: u16 = 0x3D0;
 pub const CRTC_REGISTER_MASK: u16 = 0x007;
@@ -57,31 +59,33 @@ impl IoDevice for CGACard {
 
         //self.rw_op(ticks, 0, port as u32, RwSlotType::Io);
 
+        // Only the CRTC data register and the status register drive the data bus on read.
+        // Reads from write-only registers return the floating bus value.
         if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Read is from CRTC register.
             if port & 0x01 != 0 {
                 self.handle_crtc_register_read()
             }
             else {
-                0
+                NO_IO_BYTE
             }
         }
         else {
             match port {
                 CGA_MODE_CONTROL_REGISTER => {
-                    log::error!("CGA: Read from Mode control register!");
-                    0
+                    log::debug!("CGA: Read from write-only Mode control register");
+                    NO_IO_BYTE
                 }
                 CGA_STATUS_REGISTER => self.handle_status_register_read(),
                 CGA_LIGHTPEN_LATCH_RESET => {
                     self.clear_lp_latch();
-                    0
+                    NO_IO_BYTE
                 }
                 CGA_LIGHTPEN_LATCH_SET => {
                     self.set_lp_latch();
-                    0
+                    NO_IO_BYTE
                 }
-                _ => 0,
+                _ => NO_IO_BYTE,
             }
         }
     }
@@ -94,6 +98,10 @@ impl IoDevice for CGACard {
 
         //self.rw_op(ticks, data, port as u32, RwSlotType::Io);
 
+        if let Some(reg) = self.get_io_register(port) {
+            self.reg_shadow.record(reg.group, reg.index, data);
+        }
+
         if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Write is to CRTC register.
             if port & 0x01 == 0 {
@@ -129,6 +137,8 @@ impl IoDevice for CGACard {
             CRTC_REGISTER1,
             CRTC_REGISTER_SELECT2,
             CRTC_REGISTER2,
+            CRTC_REGISTER_SELECT3,
+            CRTC_REGISTER3,
             CGA_MODE_CONTROL_REGISTER,
             CGA_COLOR_CONTROL_REGISTER,
             CGA_LIGHTPEN_LATCH_RESET,
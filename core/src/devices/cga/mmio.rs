@@ -33,30 +33,34 @@
 use super::*;
 use crate::bus::MemoryMappedDevice;
 
+impl CGACard {
+    /// Look up the wait states for a CPU access to VRAM, given the last ticked clock cycle +
+    /// elapsed cycles passed in. Returns the clock phase along with the wait states.
+    #[inline]
+    fn mmio_wait(&self, cycles: u32) -> (usize, u32) {
+        let phase = (self.cycles + cycles as u64 + 1) as usize & (0x0F as usize);
+        (phase, WAIT_TABLE[phase])
+    }
+}
+
 /// Unlike the EGA or VGA the CGA doesn't do any operations on video memory on read/write,
 /// but we handle the mirroring of VRAM this way, and for consistency with other devices
 impl MemoryMappedDevice for CGACard {
     fn get_read_wait(&mut self, _address: usize, cycles: u32) -> u32 {
-        // Look up wait states given the last ticked clock cycle + elapsed cycles
-        // passed in.
-        let phase = (self.cycles + cycles as u64 + 1) as usize & (0x0F as usize);
-        let waits = WAIT_TABLE[phase];
+        let (phase, waits) = self.mmio_wait(cycles);
 
         trace!(self, "READ_U8 (T2): PHASE: {:02X}, WAITS: {}", phase, waits);
         waits
     }
 
     fn get_write_wait(&mut self, _address: usize, cycles: u32) -> u32 {
-        // Look up wait states given the last ticked clock cycle + elapsed cycles
-        // passed in.
-        let phase = (self.cycles + cycles as u64 + 1) as usize & (0x0F as usize);
-        let waits = WAIT_TABLE[phase];
+        let (phase, waits) = self.mmio_wait(cycles);
 
         trace!(self, "WRITE_U8 (T2): PHASE: {:02X}, WAITS: {}", phase, waits);
         waits
     }
 
-    fn mmio_read_u8(&mut self, address: usize, _cycles: u32) -> (u8, u32) {
+    fn mmio_read_u8(&mut self, address: usize, cycles: u32) -> (u8, u32) {
         /*
         if self.enable_snow {
             // Catch up to CPU state.
@@ -81,7 +85,7 @@ impl MemoryMappedDevice for CGACard {
             }
 
             trace!(self, "READ_U8: {:04X}:{:02X}", a_offset, self.mem[a_offset],);
-            (self.mem[a_offset], 0)
+            (self.mem[a_offset], self.mmio_wait(cycles).1)
         }
         else {
             // Read out of range, shouldn't happen...
@@ -96,12 +100,14 @@ impl MemoryMappedDevice for CGACard {
     }
 
     fn mmio_peek_u16(&self, address: usize) -> u16 {
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
+        // The high byte may wrap around to the start of VRAM via the mirror.
+        let lo_byte = MemoryMappedDevice::mmio_peek_u8(self, address);
+        let ho_byte = MemoryMappedDevice::mmio_peek_u8(self, CGA_MEM_ADDRESS + ((address + 1) & CGA_APERTURE_MASK));
 
-        (self.mem[a_offset] as u16) << 8 | self.mem[a_offset + 1] as u16
+        (ho_byte as u16) << 8 | lo_byte as u16
     }
 
-    fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32) -> u32 {
+    fn mmio_write_u8(&mut self, address: usize, byte: u8, cycles: u32) -> u32 {
         let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
         if a_offset < CGA_MEM_SIZE {
             // Save bus parameters for snow emulation
@@ -113,7 +119,7 @@ impl MemoryMappedDevice for CGACard {
             self.mem[a_offset] = byte;
 
             trace!(self, "WRITE_U8: {:04X}:{:02X}", a_offset, byte);
-            0
+            self.mmio_wait(cycles).1
         }
         else {
             // Write out of range, shouldn't happen...
@@ -121,17 +127,23 @@ impl MemoryMappedDevice for CGACard {
         }
     }
 
-    fn mmio_read_u16(&mut self, address: usize, _cycles: u32) -> (u16, u32) {
-        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, 0);
-        let (ho_byte, wait2) = MemoryMappedDevice::mmio_read_u8(self, address + 1, 0);
+    fn mmio_read_u16(&mut self, address: usize, cycles: u32) -> (u16, u32) {
+        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, cycles);
+        let (ho_byte, wait2) =
+            MemoryMappedDevice::mmio_read_u8(self, CGA_MEM_ADDRESS + ((address + 1) & CGA_APERTURE_MASK), cycles);
 
-        log::warn!("Unsupported 16 bit read from VRAM");
-        return ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2);
+        ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2)
     }
 
-    fn mmio_write_u16(&mut self, _address: usize, _data: u16, _cycles: u32) -> u32 {
+    fn mmio_write_u16(&mut self, address: usize, data: u16, cycles: u32) -> u32 {
         //trace!(self, "16 byte write to VRAM, {:04X} -> {:05X} ", data, address);
-        log::warn!("Unsupported 16 bit write to VRAM");
-        0
+        let wait1 = MemoryMappedDevice::mmio_write_u8(self, address, (data & 0xFF) as u8, cycles);
+        let wait2 = MemoryMappedDevice::mmio_write_u8(
+            self,
+            CGA_MEM_ADDRESS + ((address + 1) & CGA_APERTURE_MASK),
+            (data >> 8) as u8,
+            cycles,
+        );
+        wait1 + wait2
     }
 }
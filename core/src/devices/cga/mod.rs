@@ -44,15 +44,19 @@ use std::{collections::HashMap, convert::TryInto, path::Path};
 mod io;
 mod draw;
 mod mmio;
+mod state;
 mod tablegen;
 mod videocard;
 
+pub use state::CgaState;
+
 use super::*;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
     device_traits::videocard::*,
-    tracelogger::TraceLogger,
+    log_event,
+    tracelogger::{LogCategory, LogLevel, TraceLogger},
 };
 
 #[derive(Copy, Clone)]
@@ -92,6 +96,7 @@ pub const CGA_MEM_ADDRESS: usize = 0xB8000;
 pub const CGA_MEM_APERTURE: usize = 0x8000;
 pub const CGA_MEM_SIZE: usize = 0x4000; // 16384 bytes
 pub const CGA_MEM_MASK: usize = !0x4000; // Applying this mask will implement memory mirror.
+pub const CGA_APERTURE_MASK: usize = CGA_MEM_APERTURE - 1;
 
 pub const CGA_MODE_ENABLE_MASK: u8 = 0b1_0111;
 
@@ -183,6 +188,9 @@ const MODE_ENABLE: u8 = 0b0000_1000;
 const MODE_HIRES_GRAPHICS: u8 = 0b0001_0000;
 const MODE_BLINKING: u8 = 0b0010_0000;
 
+const CRTC_REGISTER_SELECT_MASK: u8 = 0b0001_1111;
+const CRTC_INTERLACE_SYNC: u8 = 0b01;
+const CRTC_INTERLACE_SYNC_VIDEO: u8 = 0b11;
 const CURSOR_LINE_MASK: u8 = 0b0001_1111;
 const CURSOR_ATTR_MASK: u8 = 0b0110_0000;
 const CURSOR_ENABLE_MASK: u8 = 0b0010_0000;
@@ -526,6 +534,8 @@ pub struct CGACard {
     hsc_c3l: u8,     // Horizontal sync counter - counts during hsync period
     vtac_c5: u8,
     in_vta: bool,
+    odd_field: bool,    // True if we are scanning the odd field of an interlaced frame
+    in_half_line: bool, // True if we are in the extra half scanline that ends an even interlaced field
     effective_vta: u8,
     vma: usize,              // VMA register - Video memory address
     vma_t: usize,            // VMA' register - Video memory address temporary
@@ -541,6 +551,7 @@ pub struct CGACard {
     clocks_accum: u32,
 
     mem: Box<[u8; CGA_MEM_SIZE]>,
+    reg_shadow: VideoRegisterShadow,
 
     back_buf: usize,
     front_buf: usize,
@@ -578,6 +589,8 @@ pub enum CRTCRegister {
     CursorAddressL,
     LightPenPositionH,
     LightPenPositionL,
+    // R18-R31 are not implemented by the MC6845. Writes are ignored and reads return 0.
+    Unimplemented,
 }
 
 // CGA implementation of Default for DisplayExtents.
@@ -711,6 +724,8 @@ impl Default for CGACard {
             hsc_c3l: 0,
             vtac_c5: 0,
             in_vta: false,
+            odd_field: false,
+            in_half_line: false,
             effective_vta: 0,
             vma: 0,
             vma_t: 0,
@@ -727,6 +742,7 @@ impl Default for CGACard {
             pixel_clocks_owed: 0,
 
             mem: vec![0; CGA_MEM_SIZE].into_boxed_slice().try_into().unwrap(),
+            reg_shadow: VideoRegisterShadow::default(),
 
             back_buf:  1,
             front_buf: 0,
@@ -870,7 +886,7 @@ impl CGACard {
         if self.ticks_advanced % CGA_LCHAR_CLOCK as u32 > 0 {
             // We have advanced the CGA card out of phase with the character clock. Count
             // how many pixel clocks we need to tick by to be back in phase.
-            ((!self.cycles + 1) & 0x0F) as u32
+            (self.cycles.wrapping_neg() & 0x0F) as u32
         }
         else {
             0
@@ -879,7 +895,7 @@ impl CGACard {
 
     #[inline]
     fn calc_phase_offset(&mut self) -> u32 {
-        ((!self.cycles + 1) & 0x0F) as u32
+        (self.cycles.wrapping_neg() & 0x0F) as u32
     }
 
     fn set_lp_latch(&mut self) {
@@ -957,6 +973,8 @@ impl CGACard {
 
     fn handle_crtc_register_select(&mut self, byte: u8) {
         //log::trace!("CGA: CRTC register {:02X} selected", byte);
+        // The MC6845 address register is only 5 bits wide, so register selects wrap every 32 values.
+        let byte = byte & CRTC_REGISTER_SELECT_MASK;
         self.crtc_register_select_byte = byte;
         self.crtc_register_selected = match byte {
             0x00 => CRTCRegister::HorizontalTotal,
@@ -978,9 +996,13 @@ impl CGACard {
             0x10 => CRTCRegister::LightPenPositionH,
             0x11 => CRTCRegister::LightPenPositionL,
             _ => {
-                log::debug!("CGA: Select to invalid CRTC register");
-                self.crtc_register_select_byte = 0;
-                CRTCRegister::HorizontalTotal
+                log_event!(
+                    LogCategory::Video,
+                    LogLevel::Debug,
+                    "CGA: Select to unimplemented CRTC register: {:02X}",
+                    byte
+                );
+                CRTCRegister::Unimplemented
             }
         }
     }
@@ -1029,7 +1051,7 @@ impl CGACard {
             }
             CRTCRegister::VerticalDisplayed => {
                 // (R6) 7 bit write only
-                self.crtc_vertical_displayed = byte;
+                self.crtc_vertical_displayed = byte & 0x7F;
             }
             CRTCRegister::VerticalSync => {
                 // (R7) 7 bit write only
@@ -1043,10 +1065,12 @@ impl CGACard {
                 )
             }
             CRTCRegister::InterlaceMode => {
-                self.crtc_interlace_mode = byte;
+                // (R8) 2 bit write only
+                self.crtc_interlace_mode = byte & 0x03;
             }
             CRTCRegister::MaximumScanLineAddress => {
-                self.crtc_maximum_scanline_address = byte;
+                // (R9) 5 bit write only
+                self.crtc_maximum_scanline_address = byte & 0x1F;
                 self.update_cursor_data();
             }
             CRTCRegister::CursorStartLine => {
@@ -1079,7 +1103,8 @@ impl CGACard {
                 self.update_cursor_data();
             }
             CRTCRegister::CursorAddressH => {
-                self.crtc_cursor_address_ho = byte;
+                // (R14) 6 bit read/write
+                self.crtc_cursor_address_ho = byte & 0x3F;
                 self.update_cursor_address();
             }
             CRTCRegister::CursorAddressL => {
@@ -1100,6 +1125,14 @@ impl CGACard {
                 trace!(self, "CRTC Register Write (0Dh): StartAddressL updated: {:02X}", byte);
                 self.update_start_address();
             }
+            CRTCRegister::Unimplemented => {
+                trace!(
+                    self,
+                    "Write to unimplemented CRTC register {:02X}: {:02X}",
+                    self.crtc_register_select_byte,
+                    byte
+                );
+            }
             _ => {
                 trace!(
                     self,
@@ -1116,10 +1149,12 @@ impl CGACard {
         }
     }
 
+    /// Handle a read from the CRTC data register.
+    ///
+    /// On the MC6845 only the cursor address (R14-R15) and light pen (R16-R17) registers
+    /// can be read. All other registers, including R18-R31, read as 0.
     fn handle_crtc_register_read(&mut self) -> u8 {
         match self.crtc_register_selected {
-            CRTCRegister::CursorStartLine => self.crtc_cursor_start_line,
-            CRTCRegister::CursorEndLine => self.crtc_cursor_end_line,
             CRTCRegister::CursorAddressH => {
                 //log::debug!("CGA: Read from CRTC register: {:?}: {:02}", self.crtc_register_selected, self.crtc_cursor_address_ho );
                 self.crtc_cursor_address_ho
@@ -1140,7 +1175,7 @@ impl CGACard {
             }
             _ => {
                 log::debug!(
-                    "CGA: Read from unsupported CRTC register: {:?}",
+                    "CGA: Read from write-only CRTC register: {:?}",
                     self.crtc_register_selected
                 );
                 0
@@ -1224,7 +1259,12 @@ impl CGACard {
             0b1_0010 => DisplayMode::Mode7LowResComposite,
             _ => {
                 trace!(self, "Invalid display mode selected: {:02X}", self.mode_byte & 0x1F);
-                log::warn!("CGA: Invalid display mode selected: {:02X}", self.mode_byte & 0x1F);
+                log_event!(
+                    LogCategory::Video,
+                    LogLevel::Warn,
+                    "CGA: Invalid display mode selected: {:02X}",
+                    self.mode_byte & 0x1F
+                );
                 DisplayMode::Mode3TextCo80
             }
         };
@@ -1255,24 +1295,29 @@ impl CGACard {
     #[inline]
     fn update_clock(&mut self) {
         if self.clock_pending && (self.cycles & 0x0F == 0) {
-            // Clock divisor is 1 in high res text mode, 2 in all other modes
-            // We draw pixels twice when clock divisor is 2 to simulate slower scanning.
-            (
-                self.clock_divisor,
-                self.char_clock,
-                self.char_clock_mask,
-                self.char_clock_odd_mask,
-            ) = if self.mode_hires_txt {
-                (1, CGA_HCHAR_CLOCK as u32, 0x07, 0x0F)
-            }
-            else {
-                (2, (CGA_HCHAR_CLOCK as u32) * 2, 0x0F, 0x1F)
-            };
-
+            self.set_char_clock(self.mode_hires_txt);
             self.clock_pending = false;
         }
     }
 
+    /// Set the character clock for high resolution text mode, or for all other modes.
+    #[inline]
+    fn set_char_clock(&mut self, hires_txt: bool) {
+        // Clock divisor is 1 in high res text mode, 2 in all other modes
+        // We draw pixels twice when clock divisor is 2 to simulate slower scanning.
+        (
+            self.clock_divisor,
+            self.char_clock,
+            self.char_clock_mask,
+            self.char_clock_odd_mask,
+        ) = if hires_txt {
+            (1, CGA_HCHAR_CLOCK as u32, 0x07, 0x0F)
+        }
+        else {
+            (2, (CGA_HCHAR_CLOCK as u32) * 2, 0x0F, 0x1F)
+        };
+    }
+
     /// Handle a write to the CGA mode register. Defer the mode change if it would change
     /// from graphics mode to text mode or back (Need to measure this on real hardware)
     fn handle_mode_register(&mut self, mode_byte: u8) {
@@ -1984,6 +2029,32 @@ impl CGACard {
         }
     }
 
+    /// Return true if R8 specifies interlace sync or interlace sync and video mode.
+    #[inline]
+    fn crtc_interlaced(&self) -> bool {
+        self.crtc_interlace_mode & CRTC_INTERLACE_SYNC != 0
+    }
+
+    /// Return true if R8 specifies interlace sync and video mode. In this mode each field scans out
+    /// alternate lines of each character row.
+    #[inline]
+    fn crtc_interlace_video(&self) -> bool {
+        self.crtc_interlace_mode == CRTC_INTERLACE_SYNC_VIDEO
+    }
+
+    /// Return the first scanline of a character row for the current field.
+    #[inline]
+    fn crtc_row_start_line(&self) -> u8 {
+        (self.crtc_interlace_video() && self.odd_field) as u8
+    }
+
+    /// Return the last scanline of a character row for the current field. In interlace sync and video
+    /// mode R9 holds the number of scanlines per row minus two, and the odd field ends one line later.
+    #[inline]
+    fn crtc_row_end_line(&self) -> u8 {
+        self.crtc_maximum_scanline_address + self.crtc_row_start_line()
+    }
+
     /// Update the CRTC logic for next character.
     pub fn tick_crtc_char(&mut self) {
         // Update horizontal character counter
@@ -2080,7 +2151,7 @@ impl CGACard {
         if self.hcc_c0 == self.crtc_horizontal_displayed {
             // C0 == R1. Entering right overscan.
 
-            if self.vlc_c9 == self.crtc_maximum_scanline_address {
+            if self.vlc_c9 == self.crtc_row_end_line() {
                 // Save VMA in VMA'
                 //log::debug!("Updating vma_t: {:04X}", self.vma_t);
                 self.vma_t = self.vma;
@@ -2120,7 +2191,8 @@ impl CGACard {
             // Reset Horizontal Character Counter and increment character row counter
             self.hcc_c0 = 0;
             self.hborder = false;
-            self.vlc_c9 += 1;
+            // In interlace sync and video mode, each field scans every other line of the character row.
+            self.vlc_c9 += if self.crtc_interlace_video() { 2 } else { 1 };
             // Return video memory address to starting position for next character row
             self.vma = self.vma_t;
 
@@ -2134,10 +2206,10 @@ impl CGACard {
                 }
             }
 
-            if self.vlc_c9 > self.crtc_maximum_scanline_address {
+            if self.vlc_c9 > self.crtc_row_end_line() {
                 // C9 == R9 We finished drawing this row of characters
 
-                self.vlc_c9 = 0;
+                self.vlc_c9 = self.crtc_row_start_line();
                 // Advance Vertical Character Counter
                 self.vcc_c4 = self.vcc_c4.wrapping_add(1);
 
@@ -2203,22 +2275,42 @@ impl CGACard {
                     self.in_vta = false;
                     self.vtac_c5 = 0;
 
-                    self.hcc_c0 = 0;
-                    self.vcc_c4 = 0;
-                    self.vlc_c9 = 0;
-                    self.char_col = 0;
-                    self.crtc_frame_address = self.crtc_start_address;
-                    self.vma = self.crtc_start_address;
-                    self.vma_t = self.vma;
-                    self.in_display_area = true;
-                    self.vborder = false;
-                    self.in_crtc_vblank = false;
-
-                    // Load first char + attr
-                    self.set_char_addr();
+                    if self.crtc_interlaced() && !self.odd_field {
+                        // An even interlaced field is extended by half a scanline.
+                        self.in_half_line = true;
+                    }
+                    else {
+                        self.crtc_begin_frame();
+                    }
                 }
             }
         }
+
+        if self.in_half_line && self.hcc_c0 as u16 == (self.crtc_horizontal_total as u16).div_ceil(2) {
+            // We have completed the extra half scanline of an even field. The odd field begins on the next
+            // character, half a scanline out of phase with hsync, which is what makes the monitor offset its lines.
+            self.in_half_line = false;
+            self.crtc_begin_frame();
+        }
+    }
+
+    /// Reset the vertical counters and latch the start address for the next frame. In interlaced modes,
+    /// this also flips the current field.
+    fn crtc_begin_frame(&mut self) {
+        self.odd_field = self.crtc_interlaced() && !self.odd_field;
+        self.hcc_c0 = 0;
+        self.vcc_c4 = 0;
+        self.vlc_c9 = self.crtc_row_start_line();
+        self.char_col = 0;
+        self.crtc_frame_address = self.crtc_start_address;
+        self.vma = self.crtc_start_address;
+        self.vma_t = self.vma;
+        self.in_display_area = true;
+        self.vborder = false;
+        self.in_crtc_vblank = false;
+
+        // Load first char + attr
+        self.set_char_addr();
     }
 
     pub fn do_vsync(&mut self) {
@@ -2288,3 +2380,215 @@ impl CGACard {
         println!("{}", self.vtac_c5);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{io::*, *};
+    use crate::{
+        bus::{IoDevice, MemoryMappedDevice, NO_IO_BYTE},
+        device_traits::devicestate::DeviceState,
+    };
+
+    fn new_cga() -> CGACard {
+        CGACard::new(TraceLogger::None, ClockingMode::Default, false)
+    }
+
+    fn crtc_write(cga: &mut CGACard, select_port: u16, reg: u8, data: u8) {
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+        IoDevice::write_u8(cga, select_port, reg, None, delta);
+        IoDevice::write_u8(cga, select_port + 1, data, None, delta);
+    }
+
+    fn crtc_read(cga: &mut CGACard, select_port: u16, reg: u8) -> u8 {
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+        IoDevice::write_u8(cga, select_port, reg, None, delta);
+        IoDevice::read_u8(cga, select_port + 1, delta)
+    }
+
+    #[test]
+    fn test_vram_mirror() {
+        let mut cga = new_cga();
+
+        MemoryMappedDevice::mmio_write_u8(&mut cga, CGA_MEM_ADDRESS + 0x1234, 0x5A, 0);
+        assert_eq!(MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS + 0x1234), 0x5A);
+        assert_eq!(
+            MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE + 0x1234),
+            0x5A
+        );
+
+        // Writes to the upper mirror are visible in the lower copy.
+        MemoryMappedDevice::mmio_write_u8(&mut cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE + 0x10, 0xA5, 0);
+        let (byte, _) = MemoryMappedDevice::mmio_read_u8(&mut cga, CGA_MEM_ADDRESS + 0x10, 0);
+        assert_eq!(byte, 0xA5);
+    }
+
+    #[test]
+    fn test_vram_word_wrap() {
+        let mut cga = new_cga();
+
+        // A word access at the end of a mirror continues into the next one, which maps to the start of VRAM.
+        MemoryMappedDevice::mmio_write_u16(&mut cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE - 1, 0x1122, 0);
+        assert_eq!(
+            MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE - 1),
+            0x22
+        );
+        assert_eq!(MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS), 0x11);
+        assert_eq!(
+            MemoryMappedDevice::mmio_peek_u16(&cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE - 1),
+            0x1122
+        );
+
+        // The same applies at the very end of the aperture.
+        MemoryMappedDevice::mmio_write_u16(&mut cga, CGA_MEM_ADDRESS + CGA_MEM_APERTURE - 1, 0x3344, 0);
+        assert_eq!(
+            MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS + CGA_MEM_SIZE - 1),
+            0x44
+        );
+        assert_eq!(MemoryMappedDevice::mmio_peek_u8(&cga, CGA_MEM_ADDRESS), 0x33);
+        let (word, _) = MemoryMappedDevice::mmio_read_u16(&mut cga, CGA_MEM_ADDRESS + CGA_MEM_APERTURE - 1, 0);
+        assert_eq!(word, 0x3344);
+    }
+
+    #[test]
+    fn test_crtc_port_aliasing() {
+        let mut cga = new_cga();
+
+        // Every even/odd port pair from 3D0-3D7 addresses the CRTC.
+        for (i, port) in [0x3D0, 0x3D2, 0x3D4, 0x3D6].into_iter().enumerate() {
+            crtc_write(&mut cga, port, 0x0F, 0x10 + i as u8);
+            assert_eq!(crtc_read(&mut cga, 0x3D4, 0x0F), 0x10 + i as u8);
+        }
+        assert!(cga.port_list().contains(&CRTC_REGISTER3));
+    }
+
+    #[test]
+    fn test_crtc_register_select_wrap() {
+        let mut cga = new_cga();
+
+        // The address register is 5 bits, so 0x2E selects R14.
+        crtc_write(&mut cga, 0x3D4, 0x2E, 0xFF);
+        // R14 is only 6 bits wide.
+        assert_eq!(crtc_read(&mut cga, 0x3D4, 0x0E), 0x3F);
+
+        // R18-R31 do not exist. Writing them must not alias onto R0.
+        crtc_write(&mut cga, 0x3D4, 0x00, 0x71);
+        crtc_write(&mut cga, 0x3D4, 0x12, 0x55);
+        assert_eq!(cga.crtc_horizontal_total, 0x71);
+        assert_eq!(crtc_read(&mut cga, 0x3D4, 0x12), 0);
+    }
+
+    #[test]
+    fn test_crtc_readback() {
+        let mut cga = new_cga();
+
+        // Write-only registers read back as 0.
+        crtc_write(&mut cga, 0x3D4, 0x0A, 0x26);
+        crtc_write(&mut cga, 0x3D4, 0x0C, 0x12);
+        assert_eq!(crtc_read(&mut cga, 0x3D4, 0x0A), 0);
+        assert_eq!(crtc_read(&mut cga, 0x3D4, 0x0C), 0);
+
+        // Unimplemented register bits are dropped.
+        crtc_write(&mut cga, 0x3D4, 0x09, 0xE7);
+        crtc_write(&mut cga, 0x3D4, 0x08, 0xFF);
+        assert_eq!(cga.crtc_maximum_scanline_address, 0x07);
+        assert_eq!(cga.crtc_interlace_mode, 0x03);
+
+        // Write-only ports float.
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+        assert_eq!(
+            IoDevice::read_u8(&mut cga, CGA_MODE_CONTROL_REGISTER, delta),
+            NO_IO_BYTE
+        );
+        assert_eq!(IoDevice::read_u8(&mut cga, CRTC_REGISTER_SELECT2, delta), NO_IO_BYTE);
+        // The status register always reads back its unused upper bits as set.
+        assert_eq!(IoDevice::read_u8(&mut cga, CGA_STATUS_REGISTER, delta) & 0xF0, 0xF0);
+    }
+
+    #[test]
+    fn test_raw_vram_and_shadow() {
+        let mut cga = new_cga();
+
+        MemoryMappedDevice::mmio_write_u8(&mut cga, CGA_MEM_ADDRESS + 0x20, 0x41, 0);
+        assert_eq!(cga.get_vram_plane_count(), 1);
+        assert_eq!(cga.get_vram(0).unwrap()[0x20], 0x41);
+        assert!(cga.get_vram(1).is_none());
+        assert_eq!(cga.copy_vram()[0].len(), CGA_MEM_SIZE);
+
+        // Write-only CRTC registers are visible through the shadow.
+        crtc_write(&mut cga, CRTC_REGISTER_SELECT0, 0x02, 0x5A);
+        IoDevice::write_u8(
+            &mut cga,
+            CGA_MODE_CONTROL_REGISTER,
+            0x09,
+            None,
+            DeviceRunTimeUnit::SystemTicks(0),
+        );
+        let shadow = cga.get_register_shadow().unwrap();
+        assert_eq!(shadow.group(VideoRegisterGroup::Crtc)[2], 0x5A);
+        assert_eq!(shadow.group(VideoRegisterGroup::Mode), &[0x09]);
+        assert!(shadow.group(VideoRegisterGroup::Sequencer).is_empty());
+    }
+
+    #[test]
+    fn test_text_mode_rows() {
+        let mut cga = new_cga();
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+
+        IoDevice::write_u8(&mut cga, CGA_MODE_CONTROL_REGISTER, 0x09, None, delta);
+        crtc_write(&mut cga, CRTC_REGISTER_SELECT2, 0x06, 0x19);
+        assert_eq!(cga.get_text_mode_rows(), Some(25));
+
+        // Programs that reprogram the CRTC for more rows are reported as such.
+        crtc_write(&mut cga, CRTC_REGISTER_SELECT2, 0x06, 0x32);
+        assert_eq!(cga.get_text_mode_rows(), Some(50));
+
+        // Graphics modes have no text rows. The mode change is latched at the next hsync.
+        IoDevice::write_u8(&mut cga, CGA_MODE_CONTROL_REGISTER, 0x0A, None, delta);
+        cga.run(DeviceRunTimeUnit::SystemTicks(2000), &mut None);
+        assert_eq!(cga.get_text_mode_rows(), None);
+    }
+
+    #[test]
+    fn test_cga_state_roundtrip() {
+        let mut cga = new_cga();
+        let delta = DeviceRunTimeUnit::SystemTicks(0);
+
+        // 80x25 text mode with a blinking cursor.
+        for (reg, byte) in [0x71, 0x50, 0x5A, 0x0A, 0x1F, 0x06, 0x19, 0x1C, 0x02, 0x07, 0x66, 0x07, 0x00, 0x10]
+            .into_iter()
+            .enumerate()
+        {
+            crtc_write(&mut cga, CRTC_REGISTER_SELECT2, reg as u8, byte);
+        }
+        IoDevice::write_u8(&mut cga, CGA_MODE_CONTROL_REGISTER, 0x09, None, delta);
+        IoDevice::write_u8(&mut cga, CGA_COLOR_CONTROL_REGISTER, 0x21, None, delta);
+        for i in 0..CGA_MEM_SIZE {
+            MemoryMappedDevice::mmio_write_u8(&mut cga, CGA_MEM_ADDRESS + i, (i * 7) as u8, 0);
+        }
+
+        // Save in the middle of a frame, with a mode change to graphics mode pending.
+        cga.run(DeviceRunTimeUnit::SystemTicks(100_000), &mut None);
+        IoDevice::write_u8(&mut cga, CGA_MODE_CONTROL_REGISTER, 0x0A, None, delta);
+        crtc_write(&mut cga, CRTC_REGISTER_SELECT2, 0x0E, 0x01);
+        let json = cga.serialize_state().unwrap();
+
+        let mut restored = new_cga();
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), cga.dump_state());
+        assert_eq!(crtc_read(&mut restored, CRTC_REGISTER_SELECT2, 0x0E), 0x01);
+        assert_eq!(
+            restored.get_register_shadow().unwrap().group(VideoRegisterGroup::Mode),
+            &[0x0A]
+        );
+
+        // Both cards produce the same frames from here on. The first frame completed after the
+        // restore was begun before it, so compare the display once a second one has completed.
+        let frame_count = cga.get_frame_count();
+        while cga.get_frame_count() < frame_count + 2 {
+            cga.run(DeviceRunTimeUnit::SystemTicks(120_000), &mut None);
+            restored.run(DeviceRunTimeUnit::SystemTicks(120_000), &mut None);
+            assert_eq!(restored.dump_state(), cga.dump_state());
+        }
+        assert!(restored.get_display_buf() == cga.get_display_buf());
+    }
+}
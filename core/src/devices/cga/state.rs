@@ -0,0 +1,314 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::cga::state.rs
+
+    Implementation of the DeviceState interface for the IBM CGA.
+
+*/
+
+use super::*;
+use crate::device_traits::devicestate::DeviceState;
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+/// The serializable state of the CGA: its registers, the CRTC and monitor counters, and video
+/// memory. The render buffers are not included; the frame being drawn when the state is restored
+/// is completed from the restored counters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CgaState {
+    // Registers
+    pub mode_byte: u8,
+    /// The mode in effect, which lags mode_byte while a mode change is pending.
+    pub active_mode_byte: u8,
+    pub mode_pending: bool,
+    pub clock_pending: bool,
+    pub clock_divisor: u8,
+    pub cc_register: u8,
+    pub crtc_register_select_byte: u8,
+    pub crtc_registers: [u8; 16],
+    pub lightpen_latch: bool,
+    pub lightpen_addr: usize,
+
+    // CRTC counters
+    pub hcc_c0: u8,
+    pub vlc_c9: u8,
+    pub vcc_c4: u8,
+    pub vsc_c3h: u8,
+    pub hsc_c3l: u8,
+    pub vtac_c5: u8,
+    pub in_vta: bool,
+    pub odd_field: bool,
+    pub in_half_line: bool,
+    pub vma: usize,
+    pub vma_t: usize,
+    pub crtc_frame_address: usize,
+    pub in_crtc_hblank: bool,
+    pub in_crtc_vblank: bool,
+    pub in_last_vblank_line: bool,
+    pub hborder: bool,
+    pub vborder: bool,
+    pub in_display_area: bool,
+    pub blink_state: bool,
+    pub blink_accum_clocks: u32,
+
+    // Character being drawn
+    pub cur_char: u8,
+    pub cur_attr: u8,
+    pub cur_fg: u8,
+    pub cur_bg: u8,
+    pub cur_blink: bool,
+    pub char_col: u8,
+    pub snow_char: u8,
+    pub dirty_snow: bool,
+    pub last_bus_value: u8,
+    pub last_bus_addr: usize,
+
+    // Monitor
+    pub beam_x: u32,
+    pub beam_y: u32,
+    pub in_monitor_hsync: bool,
+    pub monitor_hsc: u32,
+    pub scanline: u32,
+    pub missed_hsyncs: u32,
+    pub overscan_right_start: u32,
+    pub rba: usize,
+
+    // Clocking
+    pub cycles: u64,
+    pub last_vsync_cycles: u64,
+    pub cur_screen_cycles: u64,
+    pub cycles_per_vsync: u64,
+    pub sink_cycles: u32,
+    pub ticks_advanced: u32,
+    pub pixel_clocks_owed: u32,
+    pub clocks_accum: u32,
+    pub frame_count: u64,
+
+    pub mem: Vec<u8>,
+}
+
+impl CGACard {
+    /// Return the value last written to the specified CRTC register, as far as the CRTC retains it.
+    fn crtc_register(&self, reg: usize) -> u8 {
+        match reg {
+            0 => self.crtc_horizontal_total,
+            1 => self.crtc_horizontal_displayed,
+            2 => self.crtc_horizontal_sync_pos,
+            3 => self.crtc_sync_width,
+            4 => self.crtc_vertical_total,
+            5 => self.crtc_vertical_total_adjust,
+            6 => self.crtc_vertical_displayed,
+            7 => self.crtc_vertical_sync_pos,
+            8 => self.crtc_interlace_mode,
+            9 => self.crtc_maximum_scanline_address,
+            10 => self.crtc_cursor_start_line | (self.cursor_attr << 5),
+            11 => self.crtc_cursor_end_line,
+            12 => self.crtc_start_address_ho,
+            13 => self.crtc_start_address_lo,
+            14 => self.crtc_cursor_address_ho,
+            15 => self.crtc_cursor_address_lo,
+            _ => 0,
+        }
+    }
+
+    /// Return the mode register value corresponding to the mode currently in effect.
+    fn active_mode_byte(&self) -> u8 {
+        let mut byte = 0;
+        for (set, bit) in [
+            (self.mode_hires_txt, MODE_HIRES_TEXT),
+            (self.mode_graphics, MODE_GRAPHICS),
+            (self.mode_bw, MODE_BW),
+            (self.mode_enable, MODE_ENABLE),
+            (self.mode_hires_gfx, MODE_HIRES_GRAPHICS),
+            (self.mode_blinking, MODE_BLINKING),
+        ] {
+            if set {
+                byte |= bit;
+            }
+        }
+        byte
+    }
+}
+
+impl DeviceState for CGACard {
+    type State = CgaState;
+
+    fn save_state(&self) -> CgaState {
+        let mut crtc_registers = [0; 16];
+        for (reg, byte) in crtc_registers.iter_mut().enumerate() {
+            *byte = self.crtc_register(reg);
+        }
+
+        CgaState {
+            mode_byte: self.mode_byte,
+            active_mode_byte: self.active_mode_byte(),
+            mode_pending: self.mode_pending,
+            clock_pending: self.clock_pending,
+            clock_divisor: self.clock_divisor,
+            cc_register: self.cc_register,
+            crtc_register_select_byte: self.crtc_register_select_byte,
+            crtc_registers,
+            lightpen_latch: self.lightpen_latch,
+            lightpen_addr: self.lightpen_addr,
+
+            hcc_c0: self.hcc_c0,
+            vlc_c9: self.vlc_c9,
+            vcc_c4: self.vcc_c4,
+            vsc_c3h: self.vsc_c3h,
+            hsc_c3l: self.hsc_c3l,
+            vtac_c5: self.vtac_c5,
+            in_vta: self.in_vta,
+            odd_field: self.odd_field,
+            in_half_line: self.in_half_line,
+            vma: self.vma,
+            vma_t: self.vma_t,
+            crtc_frame_address: self.crtc_frame_address,
+            in_crtc_hblank: self.in_crtc_hblank,
+            in_crtc_vblank: self.in_crtc_vblank,
+            in_last_vblank_line: self.in_last_vblank_line,
+            hborder: self.hborder,
+            vborder: self.vborder,
+            in_display_area: self.in_display_area,
+            blink_state: self.blink_state,
+            blink_accum_clocks: self.blink_accum_clocks,
+
+            cur_char: self.cur_char,
+            cur_attr: self.cur_attr,
+            cur_fg: self.cur_fg,
+            cur_bg: self.cur_bg,
+            cur_blink: self.cur_blink,
+            char_col: self.char_col,
+            snow_char: self.snow_char,
+            dirty_snow: self.dirty_snow,
+            last_bus_value: self.last_bus_value,
+            last_bus_addr: self.last_bus_addr,
+
+            beam_x: self.beam_x,
+            beam_y: self.beam_y,
+            in_monitor_hsync: self.in_monitor_hsync,
+            monitor_hsc: self.monitor_hsc,
+            scanline: self.scanline,
+            missed_hsyncs: self.missed_hsyncs,
+            overscan_right_start: self.overscan_right_start,
+            rba: self.rba,
+
+            cycles: self.cycles,
+            last_vsync_cycles: self.last_vsync_cycles,
+            cur_screen_cycles: self.cur_screen_cycles,
+            cycles_per_vsync: self.cycles_per_vsync,
+            sink_cycles: self.sink_cycles,
+            ticks_advanced: self.ticks_advanced,
+            pixel_clocks_owed: self.pixel_clocks_owed,
+            clocks_accum: self.clocks_accum,
+            frame_count: self.frame_count,
+
+            mem: self.mem.to_vec(),
+        }
+    }
+
+    fn load_state(&mut self, state: CgaState) -> Result<(), Error> {
+        if state.mem.len() != CGA_MEM_SIZE {
+            bail!("CGA state has {} bytes of VRAM, expected {}", state.mem.len(), CGA_MEM_SIZE);
+        }
+
+        // Registers are replayed through their write handlers so that the values derived from
+        // them are recalculated. The register shadow is rebuilt to match.
+        self.reg_shadow.clear();
+        for (reg, byte) in state.crtc_registers.iter().enumerate() {
+            self.handle_crtc_register_select(reg as u8);
+            self.handle_crtc_register_write(*byte);
+            self.reg_shadow.record(VideoRegisterGroup::Crtc, reg as u8, *byte);
+        }
+        self.handle_crtc_register_select(state.crtc_register_select_byte);
+
+        self.cycles = state.cycles;
+        self.cc_register = state.cc_register;
+        self.mode_byte = state.active_mode_byte;
+        self.update_mode();
+        self.mode_byte = state.mode_byte;
+        self.mode_pending = state.mode_pending;
+        self.clock_pending = state.clock_pending;
+        self.set_char_clock(state.clock_divisor == 1);
+        self.reg_shadow.record(VideoRegisterGroup::Mode, 0, state.mode_byte);
+        self.reg_shadow.record(VideoRegisterGroup::Palette, 0, state.cc_register);
+        self.lightpen_latch = state.lightpen_latch;
+        self.lightpen_addr = state.lightpen_addr;
+
+        self.hcc_c0 = state.hcc_c0;
+        self.vlc_c9 = state.vlc_c9;
+        self.vcc_c4 = state.vcc_c4;
+        self.vsc_c3h = state.vsc_c3h;
+        self.hsc_c3l = state.hsc_c3l;
+        self.vtac_c5 = state.vtac_c5;
+        self.in_vta = state.in_vta;
+        self.odd_field = state.odd_field;
+        self.in_half_line = state.in_half_line;
+        self.vma = state.vma;
+        self.vma_t = state.vma_t;
+        self.crtc_frame_address = state.crtc_frame_address;
+        self.in_crtc_hblank = state.in_crtc_hblank;
+        self.in_crtc_vblank = state.in_crtc_vblank;
+        self.in_last_vblank_line = state.in_last_vblank_line;
+        self.hborder = state.hborder;
+        self.vborder = state.vborder;
+        self.in_display_area = state.in_display_area;
+        self.blink_state = state.blink_state;
+        self.blink_accum_clocks = state.blink_accum_clocks;
+
+        self.cur_char = state.cur_char;
+        self.cur_attr = state.cur_attr;
+        self.cur_fg = state.cur_fg;
+        self.cur_bg = state.cur_bg;
+        self.cur_blink = state.cur_blink;
+        self.char_col = state.char_col;
+        self.snow_char = state.snow_char;
+        self.dirty_snow = state.dirty_snow;
+        self.last_bus_value = state.last_bus_value;
+        self.last_bus_addr = state.last_bus_addr;
+
+        self.beam_x = state.beam_x;
+        self.beam_y = state.beam_y;
+        self.in_monitor_hsync = state.in_monitor_hsync;
+        self.monitor_hsc = state.monitor_hsc;
+        self.scanline = state.scanline;
+        self.missed_hsyncs = state.missed_hsyncs;
+        self.overscan_right_start = state.overscan_right_start;
+        self.rba = state.rba;
+
+        self.last_vsync_cycles = state.last_vsync_cycles;
+        self.cur_screen_cycles = state.cur_screen_cycles;
+        self.cycles_per_vsync = state.cycles_per_vsync;
+        self.sink_cycles = state.sink_cycles;
+        self.ticks_advanced = state.ticks_advanced;
+        self.pixel_clocks_owed = state.pixel_clocks_owed;
+        self.clocks_accum = state.clocks_accum;
+        self.frame_count = state.frame_count;
+
+        self.mem.copy_from_slice(&state.mem);
+        Ok(())
+    }
+}
@@ -30,7 +30,7 @@
 
 */
 
-use super::*;
+use super::{io::*, *};
 use crate::{device_traits::videocard::*, devices::pic::Pic};
 
 // Helper macro for pushing video card state entries.
@@ -285,10 +285,18 @@ impl VideoCard for CGACard {
         }
     }
 
+    fn get_glyphs(&self) -> GlyphSet {
+        GlyphSet::from_row_bitmap(CGA_HCHAR_CLOCK as u32, CRTC_FONT_HEIGHT as u32, CGA_FONT, CGA_FONT_SPAN)
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc_maximum_scanline_address + 1
     }
 
+    fn get_text_mode_rows(&self) -> Option<u32> {
+        (!self.mode_graphics).then_some(self.crtc_vertical_displayed as u32)
+    }
+
     /// Return the current palette number, intensity attribute bit, and alt color
     fn get_cga_palette(&self) -> (CGAPalette, bool) {
         let intensity = self.cc_register & CC_BRIGHT_BIT != 0;
@@ -374,6 +382,7 @@ impl VideoCard for CGACard {
         internal_vec.push((format!("vsc_c3h:"), VideoCardStateEntry::String(format!("{}", self.vsc_c3h))));
         internal_vec.push((format!("hsc_c3l:"), VideoCardStateEntry::String(format!("{}", self.hsc_c3l))));
         internal_vec.push((format!("vtac_c5:"), VideoCardStateEntry::String(format!("{}", self.vtac_c5))));
+        internal_vec.push(("odd field:".to_string(), VideoCardStateEntry::String(format!("{}", self.odd_field))));
         internal_vec.push((format!("vma:"), VideoCardStateEntry::String(format!("{:04X}", self.vma))));
         internal_vec.push((format!("vma':"), VideoCardStateEntry::String(format!("{:04X}", self.vma_t))));
         internal_vec.push((format!("vmws:"), VideoCardStateEntry::String(format!("{}", self.vmws))));
@@ -560,6 +569,18 @@ impl VideoCard for CGACard {
         &DUMMY_PLANE
     }
 
+    fn get_vram_plane_count(&self) -> usize {
+        1
+    }
+
+    fn get_vram(&self, plane: usize) -> Option<&[u8]> {
+        (plane == 0).then(|| &self.mem[..])
+    }
+
+    fn get_register_shadow(&self) -> Option<&VideoRegisterShadow> {
+        Some(&self.reg_shadow)
+    }
+
     fn get_frame_count(&self) -> u64 {
         self.frame_count
     }
@@ -586,6 +607,34 @@ impl VideoCard for CGACard {
         self.trace_logger.flush();
     }
 
+    fn get_io_register(&self, port: u16) -> Option<VideoRegister> {
+        if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
+            if port & 0x01 == 0 {
+                return None;
+            }
+            Some(VideoRegister {
+                group: VideoRegisterGroup::Crtc,
+                index: self.crtc_register_select_byte,
+                value: None,
+            })
+        }
+        else {
+            match port {
+                CGA_MODE_CONTROL_REGISTER => Some(VideoRegister {
+                    group: VideoRegisterGroup::Mode,
+                    index: 0,
+                    value: Some(self.mode_byte),
+                }),
+                CGA_COLOR_CONTROL_REGISTER => Some(VideoRegister {
+                    group: VideoRegisterGroup::Palette,
+                    index: 0,
+                    value: Some(self.cc_register),
+                }),
+                _ => None,
+            }
+        }
+    }
+
     fn get_text_mode_strings(&self) -> Vec<String> {
         let mut strings = Vec::new();
 
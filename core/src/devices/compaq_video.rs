@@ -0,0 +1,348 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::compaq_video.rs
+
+    Implementation of the Compaq Portable dual-mode video board.
+
+    The Compaq board combines an MDA-compatible and a CGA-compatible adapter
+    in one slot. Its built-in monitor is dual-frequency: when the monochrome
+    personality is enabled it displays MDA-timed 9x14 text, and when the
+    color personality is enabled it displays ordinary CGA output. The
+    external video connector only carries CGA timings.
+
+    Each personality is emulated by the existing MDA and CGA devices, which
+    share the board's address decoding. The personality whose mode control
+    register most recently enabled video output is the one presented to the
+    frontend.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemoryMappedDevice},
+    device_traits::{
+        devicestate::DeviceState,
+        videocard::{ClockingMode, VideoCard, VideoRegister, VideoType},
+    },
+    devices::{
+        cga::{self, CGACard, CgaState},
+        lpt_port::ParallelPortMode,
+        mda::{MDACard, MdaState},
+        pic::Pic,
+    },
+    machine_types::CompaqDisplay,
+    tracelogger::TraceLogger,
+};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+const MDA_PORT_BASE: u16 = 0x3B0;
+const MDA_PORT_MASK: u16 = !0x00F;
+const MDA_MODE_CONTROL_REGISTER: u16 = 0x3B8;
+const CGA_MODE_CONTROL_REGISTER: u16 = 0x3D8;
+const MODE_ENABLE: u8 = 0b0000_1000;
+
+// Accumulate this many system ticks before running the CGA, as the bus does for a standalone card.
+const CGA_TICK_THRESHOLD: u32 = 8;
+
+pub struct CompaqVideoCard {
+    mda: MDACard,
+    cga: CGACard,
+    display: CompaqDisplay,
+    active: VideoType,
+    cga_tick_accum: u32,
+}
+
+impl CompaqVideoCard {
+    pub fn new(
+        trace_logger: TraceLogger,
+        clock_mode: ClockingMode,
+        display: CompaqDisplay,
+        lpt_mode: ParallelPortMode,
+        video_frame_debug: bool,
+    ) -> Self {
+        Self {
+            // The board's parallel port is decoded alongside the monochrome registers.
+            mda: MDACard::new(TraceLogger::None, clock_mode, Some(lpt_mode), video_frame_debug),
+            cga: CGACard::new(trace_logger, clock_mode, video_frame_debug),
+            display,
+            active: VideoType::CGA,
+            cga_tick_accum: 0,
+        }
+    }
+
+    /// Return the personality currently being displayed.
+    pub fn active(&self) -> &dyn VideoCard {
+        match self.active {
+            VideoType::MDA => &self.mda,
+            _ => &self.cga,
+        }
+    }
+
+    pub fn active_mut(&mut self) -> &mut dyn VideoCard {
+        match self.active {
+            VideoType::MDA => &mut self.mda,
+            _ => &mut self.cga,
+        }
+    }
+
+    pub fn display(&self) -> CompaqDisplay {
+        self.display
+    }
+
+    /// Select the monitor being driven. The external connector cannot display the monochrome
+    /// personality, so switching to it always shows CGA output.
+    pub fn set_display(&mut self, display: CompaqDisplay) {
+        self.display = display;
+        if let CompaqDisplay::External = display {
+            self.active = VideoType::CGA;
+        }
+    }
+
+    /// Run both personalities. The inactive one keeps running so that its status register
+    /// and vertical retrace interrupt stay consistent while software switches between them.
+    pub fn run(&mut self, us: f64, sys_ticks: u32, pic: &mut Option<Pic>) {
+        self.mda.run(DeviceRunTimeUnit::Microseconds(us), pic);
+
+        self.cga_tick_accum += sys_ticks;
+        if self.cga_tick_accum > CGA_TICK_THRESHOLD {
+            self.cga.run(DeviceRunTimeUnit::SystemTicks(self.cga_tick_accum), pic);
+            self.cga_tick_accum = 0;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.mda.reset();
+        self.cga.reset();
+        self.active = VideoType::CGA;
+        self.cga_tick_accum = 0;
+    }
+
+    pub fn get_io_register(&self, port: u16) -> Option<VideoRegister> {
+        if Self::is_mda_port(port) {
+            self.mda.get_io_register(port)
+        }
+        else {
+            self.cga.get_io_register(port)
+        }
+    }
+
+    #[inline]
+    fn is_mda_port(port: u16) -> bool {
+        port & MDA_PORT_MASK == MDA_PORT_BASE
+    }
+
+    #[inline]
+    fn is_mda_address(address: usize) -> bool {
+        address < cga::CGA_MEM_ADDRESS
+    }
+
+    fn select_personality(&mut self, port: u16, data: u8) {
+        if data & MODE_ENABLE == 0 {
+            return;
+        }
+        self.active = match (port, self.display) {
+            (MDA_MODE_CONTROL_REGISTER, CompaqDisplay::Internal) => VideoType::MDA,
+            (CGA_MODE_CONTROL_REGISTER, _) => VideoType::CGA,
+            _ => return,
+        };
+    }
+}
+
+impl IoDevice for CompaqVideoCard {
+    fn read_u8(&mut self, port: u16, delta: DeviceRunTimeUnit) -> u8 {
+        if Self::is_mda_port(port) {
+            self.mda.read_u8(port, delta)
+        }
+        else {
+            self.cga.read_u8(port, delta)
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, bus: Option<&mut BusInterface>, delta: DeviceRunTimeUnit) {
+        self.select_personality(port, data);
+        if Self::is_mda_port(port) {
+            self.mda.write_u8(port, data, bus, delta)
+        }
+        else {
+            self.cga.write_u8(port, data, bus, delta)
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        let mut ports = self.mda.port_list();
+        ports.extend(self.cga.port_list());
+        ports
+    }
+}
+
+impl MemoryMappedDevice for CompaqVideoCard {
+    fn get_read_wait(&mut self, address: usize, cycles: u32) -> u32 {
+        if Self::is_mda_address(address) {
+            self.mda.get_read_wait(address, cycles)
+        }
+        else {
+            self.cga.get_read_wait(address, cycles)
+        }
+    }
+
+    fn mmio_read_u8(&mut self, address: usize, cycles: u32) -> (u8, u32) {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_read_u8(address, cycles)
+        }
+        else {
+            self.cga.mmio_read_u8(address, cycles)
+        }
+    }
+
+    fn mmio_read_u16(&mut self, address: usize, cycles: u32) -> (u16, u32) {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_read_u16(address, cycles)
+        }
+        else {
+            self.cga.mmio_read_u16(address, cycles)
+        }
+    }
+
+    fn mmio_peek_u8(&self, address: usize) -> u8 {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_peek_u8(address)
+        }
+        else {
+            self.cga.mmio_peek_u8(address)
+        }
+    }
+
+    fn mmio_peek_u16(&self, address: usize) -> u16 {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_peek_u16(address)
+        }
+        else {
+            self.cga.mmio_peek_u16(address)
+        }
+    }
+
+    fn get_write_wait(&mut self, address: usize, cycles: u32) -> u32 {
+        if Self::is_mda_address(address) {
+            self.mda.get_write_wait(address, cycles)
+        }
+        else {
+            self.cga.get_write_wait(address, cycles)
+        }
+    }
+
+    fn mmio_write_u8(&mut self, address: usize, data: u8, cycles: u32) -> u32 {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_write_u8(address, data, cycles)
+        }
+        else {
+            self.cga.mmio_write_u8(address, data, cycles)
+        }
+    }
+
+    fn mmio_write_u16(&mut self, address: usize, data: u16, cycles: u32) -> u32 {
+        if Self::is_mda_address(address) {
+            self.mda.mmio_write_u16(address, data, cycles)
+        }
+        else {
+            self.cga.mmio_write_u16(address, data, cycles)
+        }
+    }
+}
+
+/// The serializable state of the Compaq video board: the state of both personalities and which
+/// one is presented. The selected monitor is configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompaqVideoState {
+    pub mda: MdaState,
+    pub cga: CgaState,
+    pub mda_active: bool,
+    pub cga_tick_accum: u32,
+}
+
+impl DeviceState for CompaqVideoCard {
+    type State = CompaqVideoState;
+
+    fn save_state(&self) -> CompaqVideoState {
+        CompaqVideoState {
+            mda: self.mda.save_state(),
+            cga: self.cga.save_state(),
+            mda_active: matches!(self.active, VideoType::MDA),
+            cga_tick_accum: self.cga_tick_accum,
+        }
+    }
+
+    fn load_state(&mut self, state: CompaqVideoState) -> Result<(), Error> {
+        self.mda.load_state(state.mda)?;
+        self.cga.load_state(state.cga)?;
+        self.active = match state.mda_active {
+            true => VideoType::MDA,
+            false => VideoType::CGA,
+        };
+        self.cga_tick_accum = state.cga_tick_accum;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compaq_personality_switch() {
+        let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
+        let mut card = CompaqVideoCard::new(
+            TraceLogger::None,
+            ClockingMode::Default,
+            CompaqDisplay::Internal,
+            ParallelPortMode::Standard,
+            false,
+        );
+        assert_eq!(card.active().get_video_type(), VideoType::CGA);
+
+        // Enabling the monochrome personality switches the internal monitor to MDA text.
+        card.write_u8(MDA_MODE_CONTROL_REGISTER, 0x29, None, nul_delta);
+        assert_eq!(card.active().get_video_type(), VideoType::MDA);
+
+        // Disabling video on the color personality does not select it.
+        card.write_u8(CGA_MODE_CONTROL_REGISTER, 0x01, None, nul_delta);
+        assert_eq!(card.active().get_video_type(), VideoType::MDA);
+        card.write_u8(CGA_MODE_CONTROL_REGISTER, 0x29, None, nul_delta);
+        assert_eq!(card.active().get_video_type(), VideoType::CGA);
+
+        // The external connector only shows CGA output.
+        card.set_display(CompaqDisplay::External);
+        card.write_u8(MDA_MODE_CONTROL_REGISTER, 0x29, None, nul_delta);
+        assert_eq!(card.active().get_video_type(), VideoType::CGA);
+
+        // Memory is decoded to the matching personality.
+        card.mmio_write_u8(0xB0000, 0x41, 0);
+        card.mmio_write_u8(0xB8000, 0x42, 0);
+        assert_eq!(card.mmio_peek_u8(0xB0000), 0x41);
+        assert_eq!(card.mmio_peek_u8(0xB8000), 0x42);
+    }
+}
@@ -30,7 +30,13 @@
 
 */
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::devicestate::DeviceState,
+};
 
 pub const DMA_CHANNEL_0_ADDR_PORT: u16 = 0x00; // R/W
 pub const DMA_CHANNEL_0_WC_PORT: u16 = 0x01; // R/W
@@ -61,6 +67,14 @@ pub const DMA_CHANNEL_1_PAGE_REGISTER: u16 = 0x83; // R/W
 pub const DMA_CHANNEL_2_PAGE_REGISTER: u16 = 0x81; // R/W
 pub const DMA_CHANNEL_3_PAGE_REGISTER: u16 = 0x82; // R/W
 
+// The secondary DMA controller of the AT decodes its registers at word-aligned ports starting at
+// 0xC0. Channel 4 is used to cascade the primary controller.
+pub const DMA2_PORT_BASE: u16 = 0xC0;
+pub const DMA2_CHANNEL_4_PAGE_REGISTER: u16 = 0x8F; // R/W
+pub const DMA2_CHANNEL_5_PAGE_REGISTER: u16 = 0x8B; // R/W
+pub const DMA2_CHANNEL_6_PAGE_REGISTER: u16 = 0x89; // R/W
+pub const DMA2_CHANNEL_7_PAGE_REGISTER: u16 = 0x8A; // R/W
+
 // Control byte bit fields - not all of these are implemented
 pub const DMA_COMMAND_MEM_TO_MEM: u8 = 0x01;
 pub const DMA_COMMAND_CHANNEL_0_HOLD: u8 = 0x02;
@@ -70,17 +84,30 @@ pub const DMA_COMMAND_PRIORITY: u8 = 0x10;
 
 pub const DMA_CHANNEL_COUNT: usize = 4;
 
+// Bus cycles taken by a single transfer once the 8237 owns the bus. Normal timing runs S1-S4,
+// compressed timing drops S1 and S3.
+pub const DMA_NORMAL_TRANSFER_CYCLES: u8 = 4;
+pub const DMA_COMPRESSED_TRANSFER_CYCLES: u8 = 2;
+// The PC's DMA WAIT logic inserts one wait state into every DMA transfer.
+pub const DMA_TRANSFER_WAIT_STATES: u32 = 1;
+// Maximum number of completed transfers per channel that may await a bus hold. Transfers past
+// this limit are not charged to the CPU, which keeps the backlog bounded when the CPU is not
+// simulating wait states.
+pub const DMA_MAX_PENDING_HOLDS: u32 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TimingMode {
     NormalTiming,
     CompressedTiming,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum PriorityMode {
     Fixed,
     Rotating,
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum ServiceMode {
     Demand,
     Single,
@@ -92,7 +119,7 @@ impl Default for ServiceMode {
         ServiceMode::Demand
     }
 }
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum AddressMode {
     Increment,
     Decrement,
@@ -103,7 +130,7 @@ impl Default for AddressMode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum TransferType {
     Verify,
     Write,
@@ -134,9 +161,58 @@ pub struct DMAChannel {
     request: bool,
     masked: bool,
     page: u8,
+    // Total bytes transferred, for observers that poll the controller. Not part of saved state.
+    transfer_ct: u64,
 }
 
-#[derive(Default)]
+/// The serializable state of a single DMA channel.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DMAChannelState {
+    pub current_address_reg: u16,
+    pub current_word_count_reg: u16,
+    pub base_address_reg: u16,
+    pub base_word_count_reg: u16,
+    pub mode_reg: u8,
+    pub auto_init: bool,
+    pub service_mode: ServiceMode,
+    pub address_mode: AddressMode,
+    pub transfer_type: TransferType,
+    pub terminal_count: bool,
+    pub terminal_count_reached: bool,
+    pub request: bool,
+    pub masked: bool,
+    pub page: u8,
+}
+
+/// The serializable state of the DMA controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DMAControllerState {
+    pub enabled: bool,
+    pub mem_to_mem_enabled: bool,
+    pub channel_0_hold_enabled: bool,
+    pub timing_mode: TimingMode,
+    pub priority_mode: PriorityMode,
+    pub flipflop: bool,
+    pub channels: Vec<DMAChannelState>,
+    pub command_register: u8,
+    pub request_reg: u8,
+    pub status_reg: u8,
+    pub temp_reg: u8,
+    pub dreq: bool,
+    pub pending_holds: [u32; DMA_CHANNEL_COUNT],
+    pub last_serviced: usize,
+}
+
+/// A bus hold granted to a DMA channel. The CPU surrenders the bus for `cycles` cycles plus
+/// `wait_states` DMA wait states to pay for one transfer made on `channel`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DmaHoldGrant {
+    pub channel: usize,
+    pub cycles: u8,
+    pub wait_states: u32,
+}
+
+#[derive(Default, Hash)]
 pub struct DMAChannelStringState {
     pub current_address_reg: String,
     pub current_word_count_reg: String,
@@ -153,7 +229,7 @@ pub struct DMAChannelStringState {
     pub page: String,
 }
 
-#[derive(Default)]
+#[derive(Default, Hash)]
 pub struct DMAControllerStringState {
     pub enabled: String,
     pub flipflop: String,
@@ -161,6 +237,7 @@ pub struct DMAControllerStringState {
     pub dma_channel_state: Vec<DMAChannelStringState>,
 }
 pub struct DMAController {
+    secondary: bool,
     enabled: bool,
     mem_to_mem_enabled: bool,
     channel_0_hold_enabled: bool,
@@ -176,11 +253,17 @@ pub struct DMAController {
     temp_reg: u8,
 
     dreq: bool,
+
+    // Transfers made by each channel that have not yet been paid for with a bus hold.
+    pending_holds: [u32; DMA_CHANNEL_COUNT],
+    // The channel serviced most recently. In rotating priority mode, this channel becomes the
+    // lowest priority channel.
+    last_serviced: usize,
 }
 
 impl IoDevice for DMAController {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
-        match port {
+        match self.register_port(port) {
             DMA_CHANNEL_0_ADDR_PORT => self.handle_addr_port_read(0),
             DMA_CHANNEL_1_ADDR_PORT => self.handle_addr_port_read(1),
             DMA_CHANNEL_2_ADDR_PORT => self.handle_addr_port_read(2),
@@ -203,7 +286,7 @@ impl IoDevice for DMAController {
     }
 
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
-        match port {
+        match self.register_port(port) {
             DMA_CHANNEL_0_ADDR_PORT => {
                 self.handle_addr_port_write(0, data);
             }
@@ -272,6 +355,16 @@ impl IoDevice for DMAController {
     }
 
     fn port_list(&self) -> Vec<u16> {
+        if self.secondary {
+            let mut ports: Vec<u16> = (0..16).map(|reg| DMA2_PORT_BASE + reg * 2).collect();
+            ports.extend([
+                DMA2_CHANNEL_4_PAGE_REGISTER,
+                DMA2_CHANNEL_5_PAGE_REGISTER,
+                DMA2_CHANNEL_6_PAGE_REGISTER,
+                DMA2_CHANNEL_7_PAGE_REGISTER,
+            ]);
+            return ports;
+        }
         vec![
             DMA_CHANNEL_0_ADDR_PORT,
             DMA_CHANNEL_0_WC_PORT,
@@ -299,9 +392,96 @@ impl IoDevice for DMAController {
     }
 }
 
+impl DeviceState for DMAController {
+    type State = DMAControllerState;
+
+    fn save_state(&self) -> DMAControllerState {
+        DMAControllerState {
+            enabled: self.enabled,
+            mem_to_mem_enabled: self.mem_to_mem_enabled,
+            channel_0_hold_enabled: self.channel_0_hold_enabled,
+            timing_mode: self.timing_mode,
+            priority_mode: self.priority_mode,
+            flipflop: self.flipflop,
+            channels: self
+                .channels
+                .iter()
+                .map(|c| DMAChannelState {
+                    current_address_reg: c.current_address_reg,
+                    current_word_count_reg: c.current_word_count_reg,
+                    base_address_reg: c.base_address_reg,
+                    base_word_count_reg: c.base_word_count_reg,
+                    mode_reg: c.mode_reg,
+                    auto_init: c.auto_init,
+                    service_mode: c.service_mode,
+                    address_mode: c.address_mode,
+                    transfer_type: c.transfer_type,
+                    terminal_count: c.terminal_count,
+                    terminal_count_reached: c.terminal_count_reached,
+                    request: c.request,
+                    masked: c.masked,
+                    page: c.page,
+                })
+                .collect(),
+            command_register: self.command_register,
+            request_reg: self.request_reg,
+            status_reg: self.status_reg,
+            temp_reg: self.temp_reg,
+            dreq: self.dreq,
+            pending_holds: self.pending_holds,
+            last_serviced: self.last_serviced,
+        }
+    }
+
+    fn load_state(&mut self, state: DMAControllerState) -> Result<(), Error> {
+        if state.channels.len() != DMA_CHANNEL_COUNT {
+            bail!(
+                "DMA state has {} channels, expected {}",
+                state.channels.len(),
+                DMA_CHANNEL_COUNT
+            );
+        }
+        if state.last_serviced >= DMA_CHANNEL_COUNT {
+            bail!("DMA state has invalid last serviced channel: {}", state.last_serviced);
+        }
+
+        self.enabled = state.enabled;
+        self.mem_to_mem_enabled = state.mem_to_mem_enabled;
+        self.channel_0_hold_enabled = state.channel_0_hold_enabled;
+        self.timing_mode = state.timing_mode;
+        self.priority_mode = state.priority_mode;
+        self.flipflop = state.flipflop;
+        for (c, cs) in self.channels.iter_mut().zip(state.channels) {
+            c.current_address_reg = cs.current_address_reg;
+            c.current_word_count_reg = cs.current_word_count_reg;
+            c.base_address_reg = cs.base_address_reg;
+            c.base_word_count_reg = cs.base_word_count_reg;
+            c.mode_reg = cs.mode_reg;
+            c.auto_init = cs.auto_init;
+            c.service_mode = cs.service_mode;
+            c.address_mode = cs.address_mode;
+            c.transfer_type = cs.transfer_type;
+            c.terminal_count = cs.terminal_count;
+            c.terminal_count_reached = cs.terminal_count_reached;
+            c.request = cs.request;
+            c.masked = cs.masked;
+            c.page = cs.page;
+        }
+        self.command_register = state.command_register;
+        self.request_reg = state.request_reg;
+        self.status_reg = state.status_reg;
+        self.temp_reg = state.temp_reg;
+        self.dreq = state.dreq;
+        self.pending_holds = state.pending_holds;
+        self.last_serviced = state.last_serviced;
+        Ok(())
+    }
+}
+
 impl DMAController {
     pub fn new() -> Self {
         Self {
+            secondary: false,
             enabled: true,
             mem_to_mem_enabled: true,
             channel_0_hold_enabled: false,
@@ -321,12 +501,42 @@ impl DMAController {
             temp_reg: 0,
 
             dreq: false,
+
+            pending_holds: [0; DMA_CHANNEL_COUNT],
+            last_serviced: DMA_CHANNEL_COUNT - 1,
+        }
+    }
+
+    /// Create the secondary DMA controller of an AT, handling channels 4-7.
+    /// Registers are mapped to the same handlers as the primary controller. Word transfers are not
+    /// yet implemented; no devices request service on the secondary channels.
+    pub fn new_secondary() -> Self {
+        Self {
+            secondary: true,
+            ..DMAController::new()
+        }
+    }
+
+    /// Translate an IO port to the equivalent primary controller register port.
+    fn register_port(&self, port: u16) -> u16 {
+        if !self.secondary {
+            return port;
+        }
+        match port {
+            DMA2_CHANNEL_4_PAGE_REGISTER => DMA_CHANNEL_0_PAGE_REGISTER,
+            DMA2_CHANNEL_5_PAGE_REGISTER => DMA_CHANNEL_1_PAGE_REGISTER,
+            DMA2_CHANNEL_6_PAGE_REGISTER => DMA_CHANNEL_2_PAGE_REGISTER,
+            DMA2_CHANNEL_7_PAGE_REGISTER => DMA_CHANNEL_3_PAGE_REGISTER,
+            _ => port.wrapping_sub(DMA2_PORT_BASE) >> 1,
         }
     }
 
     /// Reset the DMA controller
     pub fn reset(&mut self) {
-        // TODO:
+        // TODO: Reset channel registers.
+        self.request_reg = 0;
+        self.pending_holds = [0; DMA_CHANNEL_COUNT];
+        self.last_serviced = DMA_CHANNEL_COUNT - 1;
     }
 
     pub fn handle_addr_port_read(&mut self, channel: usize) -> u8 {
@@ -549,6 +759,11 @@ impl DMAController {
         self.status_reg = 0;
         self.temp_reg = 0;
         self.flipflop = false;
+        self.request_reg = 0;
+        self.pending_holds = [0; DMA_CHANNEL_COUNT];
+        self.priority_mode = PriorityMode::Fixed;
+        self.timing_mode = TimingMode::NormalTiming;
+        self.last_serviced = DMA_CHANNEL_COUNT - 1;
     }
 
     pub fn handle_clear_mask_register(&mut self) {
@@ -637,9 +852,98 @@ impl DMAController {
     }
 
     /// Get the state of the DACK line for the specified DMA channel.
-    /// DACK signals whether the requestin device can be serviced.
-    pub fn read_dma_acknowledge(&self, _channel: usize) -> bool {
-        true
+    /// DACK signals whether the requesting device can be serviced. It is only asserted for the
+    /// unmasked requesting channel that wins priority arbitration, so a device may have to wait
+    /// while a higher priority channel (such as DRAM refresh) is being serviced.
+    pub fn read_dma_acknowledge(&self, channel: usize) -> bool {
+        if channel >= DMA_CHANNEL_COUNT {
+            panic!("Invalid DMA Channel");
+        }
+        if !self.enabled {
+            return false;
+        }
+        let requesting = self.request_reg & !self.mask_bits();
+        self.arbitrate(requesting) == Some(channel)
+    }
+
+    /// Return a bitfield of the masked channels.
+    fn mask_bits(&self) -> u8 {
+        self.channels.iter().enumerate().fold(0, |acc, (i, chan)| {
+            if chan.masked {
+                acc | (0x01 << i)
+            }
+            else {
+                acc
+            }
+        })
+    }
+
+    /// Return the highest priority channel in the provided bitfield of channels, given the
+    /// current priority mode. In fixed priority, channel 0 is highest. In rotating priority,
+    /// the channel following the most recently serviced channel is highest.
+    fn arbitrate(&self, channels: u8) -> Option<usize> {
+        let first = match self.priority_mode {
+            PriorityMode::Fixed => 0,
+            PriorityMode::Rotating => (self.last_serviced + 1) % DMA_CHANNEL_COUNT,
+        };
+        (0..DMA_CHANNEL_COUNT)
+            .map(|i| (first + i) % DMA_CHANNEL_COUNT)
+            .find(|&c| channels & (0x01 << c) != 0)
+    }
+
+    /// Record a completed transfer on the specified channel that must be paid for by holding
+    /// the CPU off the bus.
+    fn add_pending_hold(&mut self, channel: usize) {
+        if self.pending_holds[channel] < DMA_MAX_PENDING_HOLDS {
+            self.pending_holds[channel] += 1;
+        }
+    }
+
+    /// Return the state of the HRQ (Hold Request) line. HRQ is asserted while any channel has
+    /// transfers awaiting a bus hold.
+    #[inline]
+    pub fn hold_request(&self) -> bool {
+        self.pending_holds.iter().any(|&n| n > 0)
+    }
+
+    /// Return the number of transfers on the specified channel awaiting a bus hold.
+    pub fn pending_holds(&self, channel: usize) -> u32 {
+        self.pending_holds[channel]
+    }
+
+    /// Acknowledge a hold request (HLDA). The highest priority channel with a pending transfer
+    /// is granted the bus, and the timing of the transfer is returned so the CPU can be held
+    /// off the bus for its duration.
+    pub fn acknowledge_hold(&mut self) -> Option<DmaHoldGrant> {
+        let pending = self.pending_holds.iter().enumerate().fold(0u8, |acc, (i, &n)| {
+            if n > 0 {
+                acc | (0x01 << i)
+            }
+            else {
+                acc
+            }
+        });
+
+        let channel = self.arbitrate(pending)?;
+        self.pending_holds[channel] -= 1;
+        self.last_serviced = channel;
+
+        let cycles = match self.timing_mode {
+            TimingMode::NormalTiming => DMA_NORMAL_TRANSFER_CYCLES,
+            TimingMode::CompressedTiming => DMA_COMPRESSED_TRANSFER_CYCLES,
+        };
+
+        Some(DmaHoldGrant {
+            channel,
+            cycles,
+            wait_states: DMA_TRANSFER_WAIT_STATES,
+        })
+    }
+
+    /// Return the total number of bytes transferred on the specified channel, including verify
+    /// transfers and DRAM refresh cycles.
+    pub fn transfer_count(&self, channel: usize) -> u64 {
+        self.channels[channel].transfer_ct
     }
 
     pub fn check_terminal_count(&self, channel: usize) -> bool {
@@ -650,7 +954,13 @@ impl DMAController {
         self.channels[channel].terminal_count
     }
 
+    /// Perform a DMA read transfer on the specified channel. The transfer is charged to the CPU
+    /// as a pending bus hold.
     pub fn do_dma_read_u8(&mut self, bus: &mut BusInterface, channel: usize) -> u8 {
+        self.dma_read_u8(bus, channel, true)
+    }
+
+    fn dma_read_u8(&mut self, bus: &mut BusInterface, channel: usize, hold: bool) -> u8 {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
         }
@@ -667,6 +977,10 @@ impl DMAController {
             AddressMode::Increment => {
                 if self.channels[channel].current_word_count_reg > 0 {
                     (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+                    if hold {
+                        self.add_pending_hold(channel);
+                    }
+                    self.channels[channel].transfer_ct += 1;
 
                     if self.channels[channel].current_word_count_reg == 1 {
                         //log::trace!("car: {} cwc: {} ", self.channels[channel].current_address_reg, self.channels[channel].current_word_count_reg);
@@ -682,6 +996,10 @@ impl DMAController {
                 else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
                     // Transfer one more on a 0 count, then set TC
                     (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+                    if hold {
+                        self.add_pending_hold(channel);
+                    }
+                    self.channels[channel].transfer_ct += 1;
 
                     //self.channels[channel].current_address_reg += 1;
 
@@ -708,6 +1026,8 @@ impl DMAController {
         data
     }
 
+    /// Perform a DMA write transfer on the specified channel. The transfer is charged to the CPU
+    /// as a pending bus hold, even in Verify mode where no data is written.
     pub fn do_dma_write_u8(&mut self, bus: &mut BusInterface, channel: usize, data: u8) {
         if channel >= DMA_CHANNEL_COUNT {
             panic!("Invalid DMA Channel");
@@ -722,6 +1042,8 @@ impl DMAController {
                     if let TransferType::Write = self.channels[channel].transfer_type {
                         bus.write_u8(bus_address, data, 0).unwrap();
                     }
+                    self.add_pending_hold(channel);
+                    self.channels[channel].transfer_ct += 1;
 
                     self.channels[channel].current_address_reg =
                         self.channels[channel].current_address_reg.wrapping_add(1);
@@ -734,6 +1056,8 @@ impl DMAController {
                     if let TransferType::Write = self.channels[channel].transfer_type {
                         bus.write_u8(bus_address, data, 0).unwrap();
                     }
+                    self.add_pending_hold(channel);
+                    self.channels[channel].transfer_ct += 1;
                     //self.channels[channel].current_address_reg += 1;
 
                     //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
@@ -770,7 +1094,8 @@ impl DMAController {
                         match self.channels[i].transfer_type {
                             TransferType::Read | TransferType::Verify => {
                                 if i == 0 {
-                                    self.do_dma_read_u8(bus, i);
+                                    // DRAM refresh bus holds are simulated by the CPU directly.
+                                    self.dma_read_u8(bus, i, false);
                                 }
                             }
                             TransferType::Write => {
@@ -792,3 +1117,81 @@ impl DMAController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dma_hold_arbitration() {
+        let mut dma = DMAController::new();
+
+        // DACK is only asserted for the highest priority requesting channel.
+        dma.request_service(0);
+        dma.request_service(3);
+        assert!(dma.read_dma_acknowledge(0));
+        assert!(!dma.read_dma_acknowledge(3));
+        dma.clear_service(0);
+        assert!(dma.read_dma_acknowledge(3));
+
+        // Masked channels are never acknowledged.
+        dma.handle_channel_mask_register_write(0x04 | 3);
+        assert!(!dma.read_dma_acknowledge(3));
+
+        // Fixed priority services the lowest numbered channel first.
+        assert!(!dma.hold_request());
+        dma.add_pending_hold(3);
+        dma.add_pending_hold(3);
+        dma.add_pending_hold(2);
+        assert!(dma.hold_request());
+        let grant = dma.acknowledge_hold().unwrap();
+        assert_eq!(grant.channel, 2);
+        assert_eq!(grant.cycles, DMA_NORMAL_TRANSFER_CYCLES);
+        assert_eq!(grant.wait_states, DMA_TRANSFER_WAIT_STATES);
+        assert_eq!(dma.acknowledge_hold().unwrap().channel, 3);
+        assert_eq!(dma.pending_holds(3), 1);
+
+        // Rotating priority makes the last serviced channel the lowest priority, and compressed
+        // timing shortens each transfer.
+        dma.handle_command_register_write(DMA_COMMAND_PRIORITY | DMA_COMMAND_TIMING);
+        dma.add_pending_hold(2);
+        let grant = dma.acknowledge_hold().unwrap();
+        assert_eq!(grant.channel, 2);
+        assert_eq!(grant.cycles, DMA_COMPRESSED_TRANSFER_CYCLES);
+        assert_eq!(dma.acknowledge_hold().unwrap().channel, 3);
+        assert!(dma.acknowledge_hold().is_none());
+
+        // The backlog of transfers awaiting a hold is bounded.
+        for _ in 0..DMA_MAX_PENDING_HOLDS * 2 {
+            dma.add_pending_hold(1);
+        }
+        assert_eq!(dma.pending_holds(1), DMA_MAX_PENDING_HOLDS);
+        dma.handle_master_clear();
+        assert!(!dma.hold_request());
+    }
+
+    #[test]
+    fn test_dma_state_roundtrip() {
+        let mut dma = DMAController::new();
+        dma.handle_command_register_write(DMA_COMMAND_PRIORITY);
+        dma.handle_addr_port_write(2, 0x34);
+        dma.handle_addr_port_write(2, 0x12);
+        dma.handle_page_register_write(2, 0x05);
+        dma.add_pending_hold(1);
+
+        let json = dma.serialize_state().unwrap();
+
+        let mut restored = DMAController::new();
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), dma.dump_state());
+        assert_eq!(restored.channels[2].current_address_reg, 0x1234);
+        assert_eq!(restored.channels[2].page, 0x05);
+        assert_eq!(restored.pending_holds(1), 1);
+        assert_eq!(restored.priority_mode, PriorityMode::Rotating);
+
+        // A state with the wrong number of channels is rejected.
+        let mut state = dma.save_state();
+        state.channels.pop();
+        assert!(restored.load_state(state).is_err());
+    }
+}
@@ -31,6 +31,7 @@
 */
 
 use super::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug)]
 pub enum AttributeRegister {
@@ -101,6 +102,7 @@ pub struct APaletteRegister {
 }
 
 #[bitfield]
+#[derive(Copy, Clone)]
 pub struct AModeControl {
     #[bits = 1]
     pub mode: AttributeMode,
@@ -127,6 +129,7 @@ pub struct AOverscanColor {
 }
 
 #[bitfield]
+#[derive(Copy, Clone)]
 pub struct AColorPlaneEnable {
     pub enable_plane: B4,
     pub video_status_mux: B2,
@@ -163,6 +166,17 @@ impl AttributePaletteEntry {
     }
 }
 
+/// The serializable state of the Attribute Controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttributeControllerSavedState {
+    pub data_flipflop: bool,
+    pub register_selected: u8,
+    pub registers: [u8; 20],
+    pub last_den: bool,
+    /// The pixels not yet shifted out, as the high and low halves of the shift register.
+    pub shift_reg: [u64; 2],
+}
+
 pub struct AttributeController {
     register_flipflop: AttributeRegisterFlipFlop,
     register_select_byte: u8,
@@ -208,6 +222,15 @@ impl AttributeController {
         Self::default()
     }
 
+    /// Return the index of the register the next write to 0x3C0 will modify, or None if the
+    /// next write will select a register.
+    pub fn data_register(&self) -> Option<u8> {
+        match self.register_flipflop {
+            AttributeRegisterFlipFlop::Address => None,
+            AttributeRegisterFlipFlop::Data => Some(self.register_selected as u8),
+        }
+    }
+
     pub fn reset_flipflop(&mut self) {
         self.register_flipflop = AttributeRegisterFlipFlop::Address;
     }
@@ -497,6 +520,41 @@ impl AttributeController {
         self.palette_registers[(pel & 0x0F) as usize].four_to_six
     }
 
+    pub fn save_state(&self) -> AttributeControllerSavedState {
+        let mut registers = [0; 20];
+        for (byte, entry) in registers.iter_mut().zip(self.palette_registers.iter()) {
+            *byte = entry.six;
+        }
+        registers[AttributeRegister::ModeControl as usize] = self.mode_control.into_bytes()[0];
+        registers[AttributeRegister::OverscanColor as usize] = self.overscan_color.six;
+        registers[AttributeRegister::ColorPlaneEnable as usize] = self.color_plane_enable.into_bytes()[0];
+        registers[AttributeRegister::HorizontalPelPanning as usize] = self.pel_panning;
+
+        AttributeControllerSavedState {
+            data_flipflop: matches!(self.register_flipflop, AttributeRegisterFlipFlop::Data),
+            register_selected: self.register_selected as u8,
+            registers,
+            last_den: self.last_den,
+            shift_reg: [(self.shift_reg >> 64) as u64, self.shift_reg as u64],
+        }
+    }
+
+    /// Restore a saved state. The registers are replayed so that the palette and plane enable
+    /// values derived from them are recalculated.
+    pub fn load_state(&mut self, state: AttributeControllerSavedState) {
+        self.reset_flipflop();
+        for (idx, byte) in state.registers.iter().enumerate() {
+            self.write_attribute_register(idx as u8);
+            self.write_attribute_register(*byte);
+        }
+        self.write_attribute_register(state.register_selected);
+        if !state.data_flipflop {
+            self.reset_flipflop();
+        }
+        self.last_den = state.last_den;
+        self.shift_reg = (state.shift_reg[0] as u128) << 64 | state.shift_reg[1] as u128;
+    }
+
     #[rustfmt::skip]
     pub fn get_state(&self) -> Vec<(String, VideoCardStateEntry)> {
         let mut attribute_vec = Vec::new();
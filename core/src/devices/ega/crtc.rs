@@ -31,6 +31,7 @@
 */
 
 use super::*;
+use serde::{Deserialize, Serialize};
 
 pub const EGA_VBLANK_MASK: u16 = 0x001F;
 pub const EGA_VSYNC_MASK: u16 = 0x001F;
@@ -142,7 +143,7 @@ pub struct CModeControl {
     pub hardware_reset: B1,
 }
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
 pub struct CrtcStatus {
     pub begin_hsync: bool,
     pub begin_vsync: bool,
@@ -158,6 +159,39 @@ pub struct CrtcStatus {
     pub cref: bool,
 }
 
+/// The serializable state of the EGA CRTC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EgaCrtcSavedState {
+    pub register_select_byte: u8,
+    pub registers: Vec<u8>,
+    pub vertical_retrace_end_norm: u16,
+    pub start_address_latch: u16,
+    pub hcc: u8,
+    pub vlc: u8,
+    pub vcc: u8,
+    pub slc: u16,
+    pub hsc: u8,
+    pub vsc: u8,
+    pub vtac_c5: u8,
+    pub in_vta: bool,
+    pub in_hrd: bool,
+    pub hrdc: u8,
+    pub effective_vta: u8,
+    pub vma: u16,
+    pub vma_sl: u16,
+    pub vma_t: u16,
+    pub vmws: usize,
+    pub den_skew_front: bool,
+    pub den_skew_back: bool,
+    pub dsc: u8,
+    pub status: CrtcStatus,
+    pub blink_state: bool,
+    pub monitor_hsync: bool,
+    pub monitor_vsync: bool,
+    pub in_last_vblank_line: bool,
+    pub frame: u64,
+}
+
 pub struct EgaCrtc {
     // CRTC registers
     register_select_byte: u8,
@@ -306,6 +340,11 @@ impl EgaCrtc {
         Self::default()
     }
 
+    /// Return the index of the currently selected CRTC register.
+    pub fn register_select_byte(&self) -> u8 {
+        self.register_select_byte
+    }
+
     pub fn write_crtc_register_address(&mut self, byte: u8) {
         //log::trace!("CGA: CRTC register {:02X} selected", byte);
         self.register_select_byte = byte & 0x1F;
@@ -1119,6 +1158,111 @@ impl EgaCrtc {
         crtc_vec
     }
 
+    /// Return the value last written to the specified CRTC register. Registers holding the low
+    /// byte of a 9-bit value return that byte; bit 8 is retained by the Overflow register.
+    fn register(&self, idx: u8) -> u8 {
+        match idx {
+            0x00 => self.crtc_horizontal_total,
+            0x01 => self.crtc_horizontal_display_end,
+            0x02 => self.crtc_start_horizontal_blank,
+            0x03 => self.crtc_end_horizontal_blank.into_bytes()[0],
+            0x04 => self.crtc_start_horizontal_retrace,
+            0x05 => self.crtc_end_horizontal_retrace.into_bytes()[0],
+            0x06 => self.crtc_vertical_total as u8,
+            0x07 => self.crtc_overflow,
+            0x08 => self.crtc_preset_row_scan,
+            0x09 => self.crtc_maximum_scanline,
+            0x0A => self.crtc_cursor_start,
+            0x0B => self.crtc_cursor_end.into_bytes()[0],
+            0x0C => self.crtc_start_address_ho,
+            0x0D => self.crtc_start_address_lo,
+            0x0E => self.crtc_cursor_address_ho,
+            0x0F => self.crtc_cursor_address_lo,
+            0x10 => self.crtc_vertical_retrace_start as u8,
+            0x11 => self.crtc_vertical_retrace_end.into_bytes()[0],
+            0x12 => self.crtc_vertical_display_end as u8,
+            0x13 => self.crtc_offset,
+            0x14 => self.crtc_underline_location,
+            0x15 => self.crtc_start_vertical_blank as u8,
+            0x16 => self.crtc_end_vertical_blank as u8,
+            0x17 => self.crtc_mode_control.into_bytes()[0],
+            0x18 => self.crtc_line_compare as u8,
+            _ => 0,
+        }
+    }
+
+    pub fn save_state(&self) -> EgaCrtcSavedState {
+        EgaCrtcSavedState {
+            register_select_byte: self.register_select_byte,
+            registers: (0..=0x18).map(|idx| self.register(idx)).collect(),
+            vertical_retrace_end_norm: self.crtc_vertical_retrace_end_norm,
+            start_address_latch: self.start_address_latch,
+            hcc: self.hcc,
+            vlc: self.vlc,
+            vcc: self.vcc,
+            slc: self.slc,
+            hsc: self.hsc,
+            vsc: self.vsc,
+            vtac_c5: self.vtac_c5,
+            in_vta: self.in_vta,
+            in_hrd: self.in_hrd,
+            hrdc: self.hrdc,
+            effective_vta: self.effective_vta,
+            vma: self.vma,
+            vma_sl: self.vma_sl,
+            vma_t: self.vma_t,
+            vmws: self.vmws,
+            den_skew_front: self.den_skew_front,
+            den_skew_back: self.den_skew_back,
+            dsc: self.dsc,
+            status: self.status,
+            blink_state: self.blink_state,
+            monitor_hsync: self.monitor_hsync,
+            monitor_vsync: self.monitor_vsync,
+            in_last_vblank_line: self.in_last_vblank_line,
+            frame: self.frame,
+        }
+    }
+
+    /// Restore a saved state. The registers are replayed in order so that the values derived from
+    /// them are recalculated.
+    pub fn load_state(&mut self, state: EgaCrtcSavedState) {
+        for (idx, byte) in state.registers.iter().enumerate() {
+            self.write_crtc_register_address(idx as u8);
+            self.write_crtc_register_data(*byte);
+        }
+        self.write_crtc_register_address(state.register_select_byte);
+        // A write to the Overflow register does not renormalize Vertical Retrace End, so the saved
+        // value may predate the current bit 8 of Vertical Retrace Start.
+        self.crtc_vertical_retrace_end_norm = state.vertical_retrace_end_norm;
+
+        self.start_address_latch = state.start_address_latch;
+        self.hcc = state.hcc;
+        self.vlc = state.vlc;
+        self.vcc = state.vcc;
+        self.slc = state.slc;
+        self.hsc = state.hsc;
+        self.vsc = state.vsc;
+        self.vtac_c5 = state.vtac_c5;
+        self.in_vta = state.in_vta;
+        self.in_hrd = state.in_hrd;
+        self.hrdc = state.hrdc;
+        self.effective_vta = state.effective_vta;
+        self.vma = state.vma;
+        self.vma_sl = state.vma_sl;
+        self.vma_t = state.vma_t;
+        self.vmws = state.vmws;
+        self.den_skew_front = state.den_skew_front;
+        self.den_skew_back = state.den_skew_back;
+        self.dsc = state.dsc;
+        self.status = state.status;
+        self.blink_state = state.blink_state;
+        self.monitor_hsync = state.monitor_hsync;
+        self.monitor_vsync = state.monitor_vsync;
+        self.in_last_vblank_line = state.in_last_vblank_line;
+        self.frame = state.frame;
+    }
+
     #[rustfmt::skip]
     pub fn get_counter_state(&self) ->  Vec<(String, VideoCardStateEntry)> {
         let mut internal_vec = Vec::new();
@@ -32,6 +32,7 @@
 */
 
 use super::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug)]
 pub enum GraphicsRegister {
@@ -47,6 +48,7 @@ pub enum GraphicsRegister {
 }
 
 #[bitfield]
+#[derive(Copy, Clone)]
 pub struct GDataRotateRegister {
     pub count: B3,
     #[bits = 2]
@@ -122,6 +124,15 @@ pub enum ShiftMode {
     CGACompatible,
 }
 
+/// The serializable state of the Graphics Controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GraphicsControllerSavedState {
+    pub register_select_byte: u8,
+    pub register_selected: u8,
+    pub registers: [u8; 9],
+    pub latches: [u8; 4],
+}
+
 pub struct GraphicsController {
     graphics_register_select_byte: u8,
     graphics_register_selected: GraphicsRegister,
@@ -203,6 +214,11 @@ impl GraphicsController {
         self.graphics_micellaneous.chain_odd_even()
     }
 
+    /// Return the index of the currently selected graphics register.
+    pub fn register_select_byte(&self) -> u8 {
+        self.graphics_register_select_byte
+    }
+
     /// Handle a write to the Graphics Address Register
     pub fn write_graphics_address(&mut self, byte: u8) {
         self.graphics_register_select_byte = byte & 0x0F;
@@ -305,7 +321,7 @@ impl GraphicsController {
     /// address manipulation, and executes the pixel pipeline.
     pub fn cpu_read_u8(&mut self, seq: &Sequencer, address: usize, page_select: PageSelect) -> u8 {
         // Validate address is within current memory map and get the offset
-        let (offset, a0) = match self.map_address(address, page_select) {
+        let (offset, a0) = match self.map_address(address, page_select, seq.vram.plane_len()) {
             Some((offset, a0)) => (offset, a0),
             None => {
                 return 0;
@@ -358,7 +374,7 @@ impl GraphicsController {
     /// setting latches.
     pub fn cpu_peek_u8(&self, seq: &Sequencer, address: usize, page_select: PageSelect) -> u8 {
         // Validate address is within current memory map and get the offset
-        let (offset, a0) = match self.map_address(address, page_select) {
+        let (offset, a0) = match self.map_address(address, page_select, seq.vram.plane_len()) {
             Some((offset, a0)) => (offset, a0),
             None => {
                 return 0;
@@ -374,7 +390,7 @@ impl GraphicsController {
 
     pub fn cpu_write_u8(&mut self, seq: &mut Sequencer, address: usize, page_select: PageSelect, byte: u8) {
         // Validate address is within current memory map and get the offset
-        let (offset, a0) = match self.map_address(address, page_select) {
+        let (offset, a0) = match self.map_address(address, page_select, seq.vram.plane_len()) {
             Some((offset, a0)) => (offset, a0),
             None => return,
         };
@@ -552,14 +568,21 @@ impl GraphicsController {
         comparison
     }
 
-    pub fn map_address(&self, address: usize, page_select: PageSelect) -> Option<(usize, usize)> {
+    /// Map a CPU address to a plane offset and the state of A0, or None if the address is outside
+    /// the current memory map. `plane_size` is the size of each plane of installed memory.
+    pub fn map_address(&self, address: usize, page_select: PageSelect, plane_size: usize) -> Option<(usize, usize)> {
         let offset;
         match self.graphics_micellaneous.memory_map() {
             MemoryMap::A0000_128k => {
                 if let EGA_MEM_ADDRESS..=EGA_MEM_END_128 = address {
                     // 128k aperture is usually used with chain odd/even mode.
                     if self.graphics_micellaneous.chain_odd_even() {
-                        if address > 0xFFFF {
+                        if plane_size < EGA_GFX_PLANE_SIZE {
+                            // With less than 256K installed, bit 0 is replaced with the first address bit
+                            // past the end of a plane, so the chained planes appear linear to the CPU.
+                            offset = Self::chain_offset(address - EGA_MEM_ADDRESS, plane_size);
+                        }
+                        else if address > 0xFFFF {
                             // Replace bit 0 with bit 16
                             offset = (address & !1) | (((address & 0x10000) >> 16) & 1);
                         }
@@ -581,8 +604,13 @@ impl GraphicsController {
             MemoryMap::A0000_64K => {
                 if let EGA_MEM_ADDRESS..=EGA_MEM_END_64 = address {
                     if self.graphics_micellaneous.chain_odd_even() {
-                        // Replace bit 0 with the page select bit
-                        offset = (address & !1) | page_select as usize;
+                        if plane_size < EGA_GFX_PLANE_SIZE {
+                            offset = Self::chain_offset(address - EGA_MEM_ADDRESS, plane_size);
+                        }
+                        else {
+                            // Replace bit 0 with the page select bit
+                            offset = (address & !1) | page_select as usize;
+                        }
                     }
                     else {
                         offset = address - EGA_MEM_ADDRESS;
@@ -603,7 +631,15 @@ impl GraphicsController {
             _ => return None,
         }
 
-        Some((offset & 0xFFFF, address & 1))
+        Some((offset & (plane_size - 1), address & 1))
+    }
+
+    /// Calculate a plane offset in chain odd/even mode on a card with less than 256K installed.
+    /// Address bit 0 selects the even or odd plane, and is replaced by the address bit that
+    /// would otherwise fall off the end of a plane.
+    #[inline]
+    fn chain_offset(offset: usize, plane_size: usize) -> usize {
+        (offset & !1) | ((offset / plane_size) & 1)
     }
 
     pub(crate) fn memory_map(&self) -> MemoryMap {
@@ -619,6 +655,36 @@ impl GraphicsController {
         self.graphics_mode.shift_mode()
     }
 
+    pub fn save_state(&self) -> GraphicsControllerSavedState {
+        GraphicsControllerSavedState {
+            register_select_byte: self.graphics_register_select_byte,
+            register_selected: self.graphics_register_selected as u8,
+            registers: [
+                self.graphics_set_reset,
+                self.graphics_enable_set_reset,
+                self.graphics_color_compare,
+                self.graphics_data_rotate.into_bytes()[0],
+                self.graphics_read_map_select,
+                self.graphics_mode.into_bytes()[0],
+                self.graphics_micellaneous.into_bytes()[0],
+                self.graphics_color_dont_care,
+                self.graphics_bitmask,
+            ],
+            latches: self.latches,
+        }
+    }
+
+    pub fn load_state(&mut self, state: GraphicsControllerSavedState) {
+        for (idx, byte) in state.registers.iter().enumerate() {
+            self.write_graphics_address(idx as u8);
+            self.write_graphics_data(*byte);
+        }
+        // An out-of-range address leaves the previous register selected.
+        self.write_graphics_address(state.register_selected);
+        self.write_graphics_address(state.register_select_byte);
+        self.latches = state.latches;
+    }
+
     #[rustfmt::skip]
     pub fn get_state(&self) -> Vec<(String, VideoCardStateEntry)> {
         let mut graphics_vec = Vec::new();
@@ -75,6 +75,10 @@ impl IoDevice for EGACard {
     }
 
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        if let Some(reg) = self.get_io_register(port) {
+            self.reg_shadow.record(reg.group, reg.index, data);
+        }
+
         match port {
             MISC_OUTPUT_REGISTER => {
                 self.write_external_misc_output_register(data);
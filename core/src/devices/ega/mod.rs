@@ -45,7 +45,10 @@ use modular_bitfield::prelude::*;
 //#![allow(dead_code)]
 use log;
 
-use crate::tracelogger::TraceLogger;
+use crate::{
+    machine_types::{EgaMemorySize, EgaMonitorType},
+    tracelogger::TraceLogger,
+};
 
 use crate::device_traits::videocard::*;
 
@@ -57,10 +60,13 @@ mod io;
 mod mmio;
 mod planes;
 mod sequencer;
+mod state;
 mod tablegen;
 mod videocard;
 mod vram;
 
+pub use state::EgaState;
+
 use attribute_controller::*;
 
 use crate::devices::ega::crtc::{EgaCrtc, WordOrByteMode};
@@ -636,7 +642,10 @@ pub struct EGACard {
     debug: bool,
     debug_draw: bool,
 
+    monitor: EgaMonitorType,
+    memory: EgaMemorySize,
     dip_sw: u8,
+    monitor_sync: bool,
 
     ticks_accum: f64,
     clock_mode: ClockingMode,
@@ -691,6 +700,7 @@ pub struct EGACard {
     current_font: u8,
 
     misc_output_register: EMiscellaneousOutputRegister,
+    reg_shadow: VideoRegisterShadow,
 
     // Direct display buffer stuff
     back_buf: usize,
@@ -768,7 +778,10 @@ impl Default for EGACard {
             debug: false,
             debug_draw: true,
 
+            monitor: EgaMonitorType::EnhancedColor,
+            memory: EgaMemorySize::default(),
             dip_sw: DEFAULT_DIP_SWITCH,
+            monitor_sync: true,
 
             ticks_accum: 0.0,
             clock_mode: ClockingMode::Cycle,
@@ -821,6 +834,7 @@ impl Default for EGACard {
 
             current_font: 0,
             misc_output_register: EMiscellaneousOutputRegister::new(),
+            reg_shadow: VideoRegisterShadow::default(),
 
             back_buf:  1,
             front_buf: 0,
@@ -870,9 +884,18 @@ impl Default for DisplayExtents {
 }*/
 
 impl EGACard {
-    pub fn new(trace_logger: TraceLogger, clock_mode: ClockingMode, video_frame_debug: bool) -> Self {
+    pub fn new(
+        trace_logger: TraceLogger,
+        clock_mode: ClockingMode,
+        monitor: EgaMonitorType,
+        memory: EgaMemorySize,
+        video_frame_debug: bool,
+    ) -> Self {
         let mut ega = Self::default();
 
+        ega.set_monitor_type(monitor);
+        ega.set_memory_size(memory);
+
         ega.trace_logger = trace_logger;
         ega.debug = video_frame_debug;
         //ega.debug_draw = video_frame_debug;
@@ -904,6 +927,8 @@ impl EGACard {
 
         *self = Self {
             debug: self.debug,
+            monitor: self.monitor,
+            memory: self.memory,
             dip_sw: self.dip_sw,
             debug_draw: self.debug_draw,
             clock_mode: self.clock_mode,
@@ -911,6 +936,68 @@ impl EGACard {
             trace_logger,
             ..Self::default()
         };
+        self.set_memory_size(self.memory);
+        self.update_monitor_sync();
+    }
+
+    /// Set the amount of installed video memory. The EGA BIOS sizes memory by checking where
+    /// writes to the planes wrap around, so this should be done before the EGA BIOS initializes.
+    pub fn set_memory_size(&mut self, memory: EgaMemorySize) {
+        self.memory = memory;
+        self.sequencer.vram.set_plane_size(memory.plane_size());
+    }
+
+    pub fn get_memory_size(&self) -> EgaMemorySize {
+        self.memory
+    }
+
+    /// Attach the card to the specified type of monitor. This sets the configuration switches
+    /// to match, so it should be done before the EGA BIOS initializes.
+    pub fn set_monitor_type(&mut self, monitor: EgaMonitorType) {
+        self.monitor = monitor;
+        self.dip_sw = match monitor {
+            EgaMonitorType::EnhancedColor => EGA_DIP_SWITCH_EGA,
+            EgaMonitorType::NormalColor => EGA_DIP_SWITCH_NORMAL,
+            EgaMonitorType::Color => EGA_DIP_SWITCH_CGA,
+            EgaMonitorType::Monochrome => EGA_DIP_SWITCH_MDA,
+        };
+        self.update_monitor_sync();
+    }
+
+    pub fn get_monitor_type(&self) -> EgaMonitorType {
+        self.monitor
+    }
+
+    /// Return whether the attached monitor can sync to the currently selected clock.
+    pub fn is_monitor_synced(&self) -> bool {
+        self.monitor_sync
+    }
+
+    /// A CGA-frequency monitor can only sync to the 15.7Khz horizontal rate produced by the
+    /// 14Mhz clock, while the 350 line modes of the Enhanced Color and Monochrome displays
+    /// require the 16Mhz clock. The Enhanced Color Display can also sync to 200 line modes.
+    /// A monitor that cannot sync displays nothing, so the display buffer is blanked until
+    /// sync is regained.
+    fn update_monitor_sync(&mut self) {
+        let clock = self.misc_output_register.clock_select();
+        let synced = match self.monitor {
+            EgaMonitorType::EnhancedColor | EgaMonitorType::NormalColor => {
+                matches!(clock, ClockSelect::Clock14 | ClockSelect::Clock16)
+            }
+            EgaMonitorType::Color => clock == ClockSelect::Clock14,
+            EgaMonitorType::Monochrome => clock == ClockSelect::Clock16,
+        };
+
+        if synced != self.monitor_sync {
+            if synced {
+                log::debug!("EGA: {:?} monitor regained sync with clock {:?}", self.monitor, clock);
+            }
+            else {
+                log::warn!("EGA: {:?} monitor cannot sync to clock {:?}", self.monitor, clock);
+                self.buf[self.front_buf].fill(0);
+            }
+        }
+        self.monitor_sync = synced;
     }
 
     fn get_cursor_span(&self) -> (u8, u8) {
@@ -935,6 +1022,7 @@ impl EGACard {
             // Clock updated.
             self.sequencer.clock_change_pending = true;
             self.update_clock();
+            self.update_monitor_sync();
         }
 
         log::trace!(
@@ -1469,6 +1557,11 @@ impl EGACard {
         self.back_buf = self.front_buf;
         self.front_buf = tmp;
         self.buf[self.back_buf].fill(0);
+
+        // An unsynced monitor shows a blank screen instead of the frame just drawn.
+        if !self.monitor_sync {
+            self.buf[self.front_buf].fill(0);
+        }
     }
 
     fn update_clock(&mut self) {
@@ -1558,4 +1651,199 @@ mod tests {
         let result = ega.pixel_op_compare();
         assert_eq!(result, 0b00100111);*/
     }
+
+    #[test]
+    fn test_monitor_sync() {
+        use crate::bus::{DeviceRunTimeUnit, IoDevice};
+
+        fn select_clock(ega: &mut EGACard, clock: ClockSelect) {
+            let byte = 0x02 | (clock as u8) << 2;
+            IoDevice::write_u8(ega, MISC_OUTPUT_REGISTER, byte, None, DeviceRunTimeUnit::SystemTicks(0));
+        }
+        fn draw_frame(ega: &mut EGACard) {
+            let back_buf = ega.back_buf;
+            ega.buf[back_buf].fill(0x3F);
+            ega.raster_y = EGA_MONITOR_VSYNC_MIN + 1;
+            ega.do_vsync();
+        }
+
+        let mut ega = EGACard::new(
+            TraceLogger::None,
+            ClockingMode::Character,
+            EgaMonitorType::Color,
+            EgaMemorySize::Ega256K,
+            false,
+        );
+        draw_frame(&mut ega);
+        assert!(ega.is_monitor_synced());
+        assert!(ega.get_display_buf().iter().all(|&p| p == 0x3F));
+
+        // A CGA monitor cannot sync to the 16Mhz clock, so the display goes blank at once and
+        // stays blank for frames drawn in that mode.
+        select_clock(&mut ega, ClockSelect::Clock16);
+        assert!(!ega.is_monitor_synced());
+        assert!(ega.get_display_buf().iter().all(|&p| p == 0));
+        draw_frame(&mut ega);
+        assert!(ega.get_display_buf().iter().all(|&p| p == 0));
+
+        // Returning to the 14Mhz clock restores the display.
+        select_clock(&mut ega, ClockSelect::Clock14);
+        assert!(ega.is_monitor_synced());
+        draw_frame(&mut ega);
+        assert!(ega.get_display_buf().iter().all(|&p| p == 0x3F));
+
+        // A monochrome monitor cannot sync to the 14Mhz clock selected after reset.
+        ega.set_monitor_type(EgaMonitorType::Monochrome);
+        ega.reset_private();
+        assert!(!ega.is_monitor_synced());
+    }
+
+    #[test]
+    fn test_memory_size() {
+        use crate::bus::{DeviceRunTimeUnit, IoDevice, MemoryMappedDevice};
+
+        fn write_reg(ega: &mut EGACard, port: u16, reg: u8, data: u8) {
+            IoDevice::write_u8(ega, port, reg, None, DeviceRunTimeUnit::SystemTicks(0));
+            IoDevice::write_u8(ega, port + 1, data, None, DeviceRunTimeUnit::SystemTicks(0));
+        }
+
+        for (memory, wraps) in [(EgaMemorySize::Ega64K, true), (EgaMemorySize::Ega256K, false)] {
+            let mut ega = EGACard::new(
+                TraceLogger::None,
+                ClockingMode::Character,
+                EgaMonitorType::EnhancedColor,
+                memory,
+                false,
+            );
+
+            // Enable RAM, write all planes sequentially through the 64K window at A0000.
+            IoDevice::write_u8(
+                &mut ega,
+                MISC_OUTPUT_REGISTER,
+                0x02,
+                None,
+                DeviceRunTimeUnit::SystemTicks(0),
+            );
+            write_reg(&mut ega, SEQUENCER_ADDRESS_REGISTER, 0x02, 0x0F);
+            write_reg(&mut ega, SEQUENCER_ADDRESS_REGISTER, 0x04, 0x04);
+            write_reg(&mut ega, EGA_GRAPHICS_ADDRESS, 0x06, 0x04);
+            write_reg(&mut ega, EGA_GRAPHICS_ADDRESS, 0x08, 0xFF);
+            assert_eq!(ega.get_vram(0).unwrap().len(), memory.plane_size());
+
+            // Writes past the end of a plane wrap around on a 64K card, which is how the BIOS sizes memory.
+            MemoryMappedDevice::mmio_write_u8(&mut ega, EGA_MEM_ADDRESS + 0x4000, 0x55, 0);
+            let (byte, _) = MemoryMappedDevice::mmio_read_u8(&mut ega, EGA_MEM_ADDRESS, 0);
+            assert_eq!(byte == 0x55, wraps);
+
+            // After a reset the card keeps its memory size.
+            ega.reset_private();
+            assert_eq!(ega.get_vram(0).unwrap().len(), memory.plane_size());
+        }
+
+        // In chain odd/even mode on a 64K card, A0 selects the plane and the bit past the end of a
+        // plane replaces A0, so 32K of CPU address space covers a pair of 16K planes.
+        let mut ega = EGACard::new(
+            TraceLogger::None,
+            ClockingMode::Character,
+            EgaMonitorType::EnhancedColor,
+            EgaMemorySize::Ega64K,
+            false,
+        );
+        write_reg(&mut ega, EGA_GRAPHICS_ADDRESS, 0x06, 0x06);
+        let plane_size = EgaMemorySize::Ega64K.plane_size();
+        assert_eq!(
+            ega.gc
+                .map_address(EGA_MEM_ADDRESS + 0x0001, PageSelect::LowPage, plane_size),
+            Some((0x0000, 1))
+        );
+        assert_eq!(
+            ega.gc
+                .map_address(EGA_MEM_ADDRESS + 0x4000, PageSelect::LowPage, plane_size),
+            Some((0x0001, 0))
+        );
+        assert_eq!(
+            ega.gc
+                .map_address(EGA_MEM_ADDRESS + 0x7FFF, PageSelect::LowPage, plane_size),
+            Some((0x3FFF, 1))
+        );
+    }
+
+    #[test]
+    fn test_ega_state_roundtrip() {
+        use crate::{
+            bus::{DeviceRunTimeUnit, IoDevice},
+            device_traits::devicestate::DeviceState,
+        };
+
+        fn write_reg(ega: &mut EGACard, port: u16, reg: u8, data: u8) {
+            IoDevice::write_u8(ega, port, reg, None, DeviceRunTimeUnit::Microseconds(0.0));
+            IoDevice::write_u8(ega, port + 1, data, None, DeviceRunTimeUnit::Microseconds(0.0));
+        }
+        fn new_ega() -> EGACard {
+            EGACard::new(
+                TraceLogger::None,
+                ClockingMode::Character,
+                EgaMonitorType::EnhancedColor,
+                EgaMemorySize::Ega256K,
+                false,
+            )
+        }
+
+        // 80x25 text mode on an enhanced color monitor.
+        let mut ega = new_ega();
+        let delta = DeviceRunTimeUnit::Microseconds(0.0);
+        IoDevice::write_u8(&mut ega, MISC_OUTPUT_REGISTER, 0xA7, None, delta);
+        for (reg, byte) in [0x03, 0x01, 0x03, 0x00, 0x03].into_iter().enumerate() {
+            write_reg(&mut ega, SEQUENCER_ADDRESS_REGISTER, reg as u8, byte);
+        }
+        for (reg, byte) in [
+            0x5B, 0x4F, 0x53, 0x37, 0x51, 0x5B, 0x6C, 0x1F, 0x00, 0x0D, 0x0B, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x5E,
+            0x2B, 0x5D, 0x28, 0x0F, 0x5E, 0x0A, 0xA3, 0xFF,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            write_reg(&mut ega, CRTC_REGISTER_ADDRESS, reg as u8, byte);
+        }
+        for (reg, byte) in [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF].into_iter().enumerate() {
+            write_reg(&mut ega, EGA_GRAPHICS_ADDRESS, reg as u8, byte);
+        }
+        for (reg, byte) in [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x08,
+            0x00, 0x0F, 0x00,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            IoDevice::write_u8(&mut ega, ATTRIBUTE_REGISTER, reg as u8, None, delta);
+            IoDevice::write_u8(&mut ega, ATTRIBUTE_REGISTER, byte, None, delta);
+        }
+        for p in 0..4 {
+            for i in 0..EGA_GFX_PLANE_SIZE {
+                ega.sequencer.vram.plane_set(p, i, (i * (p + 3)) as u8);
+            }
+        }
+
+        // Save in the middle of a frame.
+        ega.run(DeviceRunTimeUnit::Microseconds(7_000.0), &mut None);
+        write_reg(&mut ega, CRTC_REGISTER_ADDRESS, 0x0E, 0x01);
+
+        let mut restored = new_ega();
+        restored.load_state(ega.save_state()).unwrap();
+        assert_eq!(restored.dump_state(), ega.dump_state());
+        assert_eq!(
+            restored.get_register_shadow().unwrap().group(VideoRegisterGroup::Crtc)[0x0E],
+            0x01
+        );
+
+        // The first frame completed after the restore was begun before it, so compare the display
+        // once a second one has completed.
+        let frame_count = ega.get_frame_count();
+        while ega.get_frame_count() < frame_count + 2 {
+            ega.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            restored.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            assert_eq!(restored.dump_state(), ega.dump_state());
+        }
+        assert!(restored.get_display_buf() == ega.get_display_buf());
+    }
 }
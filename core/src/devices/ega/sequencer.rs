@@ -35,6 +35,7 @@ use crate::{
     devices::ega::{tablegen::BIT_EXTEND_TABLE64, vram::Vram, EGA_CHARACTER_HEIGHT},
 };
 use modular_bitfield::{bitfield, prelude::*, BitfieldSpecifier};
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug)]
 pub enum SequencerRegister {
@@ -98,6 +99,14 @@ pub enum DotClock {
 
 const ODD_EVEN_MASK: u8 = 0b0101;
 
+/// The serializable state of the Sequencer. Video memory is saved separately.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SequencerSavedState {
+    pub address_byte: u8,
+    pub registers: [u8; 5],
+    pub clock_change_pending: bool,
+}
+
 pub struct Sequencer {
     pub address_byte: u8,
     pub register_selected: SequencerRegister,
@@ -339,6 +348,11 @@ impl Sequencer {
         BIT_EXTEND_TABLE64[self.vram.read_glyph(self.get_glyph_address(glyph, font, row)) as usize]
     }
 
+    /// Return the specified row of a font glyph from plane 2.
+    pub fn read_glyph_row(&self, glyph: u8, font: u8, row: u8) -> u8 {
+        self.vram.read_glyph(self.get_glyph_address(glyph, font, row))
+    }
+
     pub fn test_glyph_span(&self, row: u8) -> u64 {
         // Return a test character
         match row {
@@ -363,6 +377,31 @@ impl Sequencer {
         offset + ((glyph as usize) * EGA_CHARACTER_HEIGHT) + row as usize
     }
 
+    pub fn save_state(&self) -> SequencerSavedState {
+        SequencerSavedState {
+            address_byte: self.address_byte,
+            registers: [
+                self.reset,
+                self.clocking_mode.into_bytes()[0],
+                self.map_mask,
+                self.character_map_select.into_bytes()[0],
+                self.memory_mode.into_bytes()[0],
+            ],
+            clock_change_pending: self.clock_change_pending,
+        }
+    }
+
+    /// Restore a saved state. The registers are replayed so that the clock and font values derived
+    /// from them are recalculated.
+    pub fn load_state(&mut self, state: SequencerSavedState) {
+        for (idx, byte) in state.registers.iter().enumerate() {
+            self.write_address(idx as u8);
+            self.write_data(*byte);
+        }
+        self.write_address(state.address_byte);
+        self.clock_change_pending = state.clock_change_pending;
+    }
+
     #[rustfmt::skip]
     pub fn get_state(&self) -> Vec<(String, VideoCardStateEntry)> {
         let mut sequencer_vec = Vec::new();
@@ -0,0 +1,172 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    ega::state.rs
+
+    Implementation of the DeviceState interface for the IBM EGA.
+
+*/
+
+use super::*;
+use crate::device_traits::devicestate::DeviceState;
+use anyhow::{bail, Error};
+use attribute_controller::AttributeControllerSavedState;
+use crtc::EgaCrtcSavedState;
+use graphics_controller::GraphicsControllerSavedState;
+use serde::{Deserialize, Serialize};
+use sequencer::SequencerSavedState;
+
+/// The serializable state of the EGA: the registers and internal state of each of its
+/// components, the raster counters, and the contents of the four memory planes. The render
+/// buffers are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EgaState {
+    // Registers
+    pub misc_output_register: u8,
+    pub crtc: EgaCrtcSavedState,
+    pub sequencer: SequencerSavedState,
+    pub gc: GraphicsControllerSavedState,
+    pub ac: AttributeControllerSavedState,
+
+    // Character being drawn
+    pub vma: usize,
+    pub cur_char: u8,
+    pub next_char: u8,
+    pub cur_attr: u8,
+    pub next_attr: u8,
+    pub blink_state: bool,
+    pub pel_pan_latch: u8,
+
+    // Raster
+    pub raster_x: u32,
+    pub raster_y: u32,
+    pub rba: usize,
+    pub scanline: u32,
+    pub frame: u64,
+
+    // Clocking
+    pub cycles: u64,
+    pub ticks_accum: f64,
+    pub intr: bool,
+    pub last_intr: bool,
+
+    pub planes: Vec<Vec<u8>>,
+}
+
+impl DeviceState for EGACard {
+    type State = EgaState;
+
+    fn save_state(&self) -> EgaState {
+        EgaState {
+            misc_output_register: self.misc_output_register.into_bytes()[0],
+            crtc: self.crtc.save_state(),
+            sequencer: self.sequencer.save_state(),
+            gc: self.gc.save_state(),
+            ac: self.ac.save_state(),
+
+            vma: self.vma,
+            cur_char: self.cur_char,
+            next_char: self.next_char,
+            cur_attr: self.cur_attr,
+            next_attr: self.next_attr,
+            blink_state: self.blink_state,
+            pel_pan_latch: self.pel_pan_latch,
+
+            raster_x: self.raster_x,
+            raster_y: self.raster_y,
+            rba: self.rba,
+            scanline: self.scanline,
+            frame: self.frame,
+
+            cycles: self.cycles,
+            ticks_accum: self.ticks_accum,
+            intr: self.intr,
+            last_intr: self.last_intr,
+
+            planes: (0..4).map(|p| self.sequencer.vram.plane_slice(p).to_vec()).collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: EgaState) -> Result<(), Error> {
+        let plane_len = self.sequencer.vram.plane_len();
+        if state.planes.len() != 4 || state.planes.iter().any(|plane| plane.len() != plane_len) {
+            bail!(
+                "EGA state does not have four planes of {} bytes. Is the card configured with the same memory size?",
+                plane_len
+            );
+        }
+
+        // Registers are replayed through their write handlers so that the values derived from
+        // them are recalculated. The register shadow is rebuilt to match.
+        self.reg_shadow.clear();
+        self.write_external_misc_output_register(state.misc_output_register);
+        self.reg_shadow
+            .record(VideoRegisterGroup::Mode, 0, state.misc_output_register);
+        for (idx, byte) in state.crtc.registers.iter().enumerate() {
+            self.reg_shadow.record(VideoRegisterGroup::Crtc, idx as u8, *byte);
+        }
+        for (idx, byte) in state.sequencer.registers.iter().enumerate() {
+            self.reg_shadow.record(VideoRegisterGroup::Sequencer, idx as u8, *byte);
+        }
+        for (idx, byte) in state.gc.registers.iter().enumerate() {
+            self.reg_shadow.record(VideoRegisterGroup::Graphics, idx as u8, *byte);
+        }
+        for (idx, byte) in state.ac.registers.iter().enumerate() {
+            let group = match idx {
+                0x00..=0x0F => VideoRegisterGroup::Palette,
+                _ => VideoRegisterGroup::Attribute,
+            };
+            self.reg_shadow.record(group, idx as u8, *byte);
+        }
+        self.crtc.load_state(state.crtc);
+        self.sequencer.load_state(state.sequencer);
+        self.gc.load_state(state.gc);
+        self.ac.load_state(state.ac);
+        self.recalculate_mode();
+
+        self.vma = state.vma;
+        self.cur_char = state.cur_char;
+        self.next_char = state.next_char;
+        self.cur_attr = state.cur_attr;
+        self.next_attr = state.next_attr;
+        self.blink_state = state.blink_state;
+        self.pel_pan_latch = state.pel_pan_latch;
+
+        self.raster_x = state.raster_x;
+        self.raster_y = state.raster_y;
+        self.rba = state.rba;
+        self.scanline = state.scanline;
+        self.frame = state.frame;
+
+        self.cycles = state.cycles;
+        self.ticks_accum = state.ticks_accum;
+        self.intr = state.intr;
+        self.last_intr = state.last_intr;
+
+        self.sequencer.vram.load_planes(&state.planes);
+        Ok(())
+    }
+}
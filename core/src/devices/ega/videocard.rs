@@ -213,10 +213,28 @@ impl VideoCard for EGACard {
         FontInfo { w, h, font_data: data }
     }
 
+    fn get_glyphs(&self) -> GlyphSet {
+        // The EGA's character generator is RAM in plane 2, loaded by the BIOS or the guest.
+        let height = self.get_character_height() as u32;
+        let mut data = Vec::with_capacity(GlyphSet::GLYPH_COUNT * height as usize);
+        for glyph in 0..GlyphSet::GLYPH_COUNT {
+            for row in 0..height {
+                data.push(self.sequencer.read_glyph_row(glyph as u8, self.current_font, row as u8));
+            }
+        }
+        GlyphSet { width: 8, height, data }
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc.maximum_scanline() + 1
     }
 
+    fn get_text_mode_rows(&self) -> Option<u32> {
+        // The EGA has no character row count; divide the displayed scanlines by the character height.
+        (!self.mode_graphics)
+            .then(|| (self.crtc.vertical_display_end() as u32 + 1) / self.get_character_height() as u32)
+    }
+
     /// Return the current palette number, intensity attribute bit, and alt color
     fn get_cga_palette(&self) -> (CGAPalette, bool) {
         let intensity = self.cc_register & CC_BRIGHT_BIT != 0;
@@ -269,6 +287,9 @@ impl VideoCard for EGACard {
         general_vec.push(("Adapter Type:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.get_video_type()))));
         general_vec.push(("Display Mode:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.get_display_mode()))));
         general_vec.push(("Pixel Clock:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.misc_output_register.clock_select()))));
+        general_vec.push(("Monitor:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.monitor))));
+        general_vec.push(("Memory:".to_string(), VideoCardStateEntry::String(format!("{}K", self.memory.plane_size() * 4 / 1024))));
+        general_vec.push(("Monitor Sync:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.monitor_sync))));
         general_vec.push(("Clock Divisor:".to_string(), VideoCardStateEntry::String(format!("{:?}", self.sequencer.clock_divisor))));
         general_vec.push((
             "Field:".to_string(),
@@ -456,6 +477,18 @@ impl VideoCard for EGACard {
         self.sequencer.vram.plane_slice(plane)
     }
 
+    fn get_vram_plane_count(&self) -> usize {
+        4
+    }
+
+    fn get_vram(&self, plane: usize) -> Option<&[u8]> {
+        (plane < 4).then(|| self.sequencer.vram.plane_slice(plane))
+    }
+
+    fn get_register_shadow(&self) -> Option<&VideoRegisterShadow> {
+        Some(&self.reg_shadow)
+    }
+
     fn dump_mem(&self, path: &Path) {
         for i in 0..4 {
             let mut filename = path.to_path_buf();
@@ -484,6 +517,25 @@ impl VideoCard for EGACard {
         //self.trace_logger.print(msg);
     }
 
+    fn get_io_register(&self, port: u16) -> Option<VideoRegister> {
+        let (group, index) = match port {
+            MISC_OUTPUT_REGISTER => (VideoRegisterGroup::Mode, 0),
+            CRTC_REGISTER => (VideoRegisterGroup::Crtc, self.crtc.register_select_byte()),
+            EGA_GRAPHICS_DATA => (VideoRegisterGroup::Graphics, self.gc.register_select_byte()),
+            SEQUENCER_DATA_REGISTER => (VideoRegisterGroup::Sequencer, self.sequencer.address_byte),
+            ATTRIBUTE_REGISTER | ATTRIBUTE_REGISTER_ALT => match self.ac.data_register()? {
+                index @ 0x00..=0x0F => (VideoRegisterGroup::Palette, index),
+                index => (VideoRegisterGroup::Attribute, index),
+            },
+            _ => return None,
+        };
+        Some(VideoRegister {
+            group,
+            index,
+            value: None,
+        })
+    }
+
     fn get_text_mode_strings(&self) -> Vec<String> {
         Vec::new()
     }
@@ -29,6 +29,8 @@
     Implement the IBM EGA card's video RAM.
 
     A fully equipped EGA has four planes of 64k each, for a total of 256k.
+    Cards with less memory installed have smaller planes. The upper address
+    lines are not decoded, so accesses beyond the end of a plane wrap around.
 
     This module supplies an interface for reading and writing to the video RAM.
     Writes to video RAM are linearized, to assist with rasterization routines,
@@ -49,6 +51,7 @@ pub struct Vram {
     planes: Box<[[u8; EGA_GFX_PLANE_SIZE]; 4]>,
     linear_buf: Box<[u8; EGA_GFX_PLANE_SIZE * 8]>,
     linear_cga_buf: Box<[u8; EGA_GFX_PLANE_SIZE * 4]>,
+    plane_mask: usize,
 }
 
 impl Vram {
@@ -65,49 +68,67 @@ impl Vram {
             .unwrap(),
             linear_buf: vec![0; EGA_GFX_PLANE_SIZE * 8].into_boxed_slice().try_into().unwrap(),
             linear_cga_buf: vec![0; EGA_GFX_PLANE_SIZE * 4].into_boxed_slice().try_into().unwrap(),
+            plane_mask: EGA_GFX_PLANE_SIZE - 1,
         }
     }
 
+    /// Set the size of each memory plane. This must be a power of two no larger than 64K.
+    pub fn set_plane_size(&mut self, size: usize) {
+        assert!(size.is_power_of_two() && size <= EGA_GFX_PLANE_SIZE);
+        self.plane_mask = size - 1;
+    }
+
     #[inline]
     pub fn read_glyph(&self, offset: usize) -> u8 {
-        self.planes[2][offset & 0xFFFF]
+        self.planes[2][offset & self.plane_mask]
     }
 
     #[inline]
     pub fn peek_u8(&self, plane: usize, offset: usize) -> u8 {
-        self.planes[plane][offset & 0xFFFF]
+        self.planes[plane][offset & self.plane_mask]
     }
 
     #[inline]
     pub fn read_u8(&self, plane: usize, offset: usize) -> u8 {
-        self.planes[plane][offset & 0xFFFF]
+        self.planes[plane][offset & self.plane_mask]
     }
 
     #[inline]
     pub fn write_u8(&mut self, plane: usize, offset: usize, data: u8) {
+        let offset = offset & self.plane_mask;
         self.planes[plane][offset] = data;
         self.deplane(offset);
     }
 
     #[inline]
     pub fn read_linear(&self, offset: usize) -> u8 {
-        self.linear_buf[offset]
+        self.linear_buf[offset & (self.plane_mask << 3 | 0x07)]
     }
 
     /// Return a slice of 8 pixels from the linear buffer. This represents serialization of one byte from the
     /// four display planes.
     #[inline]
     pub fn serialize_linear(&self, offset: usize) -> &[u8] {
-        let offset = offset << 3;
+        let offset = (offset & self.plane_mask) << 3;
         &self.linear_buf[offset..offset + 8]
     }
 
     pub fn plane_len(&self) -> usize {
-        self.planes[0].len()
+        self.plane_mask + 1
     }
 
     pub fn plane_slice(&self, plane: usize) -> &[u8] {
-        &self.planes[plane]
+        &self.planes[plane][..self.plane_len()]
+    }
+
+    /// Replace the contents of the four planes, then rebuild the linear buffer from them.
+    pub fn load_planes(&mut self, planes: &[Vec<u8>]) {
+        for (plane, data) in self.planes.iter_mut().zip(planes) {
+            plane[..data.len()].copy_from_slice(data);
+        }
+        for offset in 0..self.plane_len() {
+            self.deplane(offset);
+        }
     }
 
     pub fn deplane(&mut self, offset: usize) {
@@ -124,18 +145,21 @@ impl Vram {
 
     #[inline]
     pub fn plane_set(&mut self, p: usize, offset: usize, data: u8) {
+        let offset = offset & self.plane_mask;
         self.planes[p][offset] = data;
         self.deplane(offset);
     }
 
     #[inline]
     pub fn plane_and(&mut self, p: usize, offset: usize, data: u8) {
+        let offset = offset & self.plane_mask;
         self.planes[p][offset] &= data;
         self.deplane(offset);
     }
 
     #[inline]
     pub fn plane_or(&mut self, p: usize, offset: usize, data: u8) {
+        let offset = offset & self.plane_mask;
         self.planes[p][offset] |= data;
         self.deplane(offset);
     }
@@ -33,10 +33,20 @@
 
 use std::{collections::VecDeque, default::Default};
 
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
-    device_types::{chs::DiskChs, fdc::DISK_FORMATS},
+    device_traits::devicestate::DeviceState,
+    device_types::{
+        chs::DiskChs,
+        fdc::{sector_size_to_code, DataRate, DiskEncoding},
+    },
     devices::{dma, floppy_drive::FloppyDiskDrive},
+    log_event,
+    machine_types::FloppyDriveType,
+    tracelogger::{LogCategory, LogLevel},
 };
 
 pub const FDC_IRQ: u8 = 0x06;
@@ -48,6 +58,7 @@ pub const SECTOR_SIZE: usize = 512;
 pub const FDC_DIGITAL_OUTPUT_REGISTER: u16 = 0x3F2;
 pub const FDC_STATUS_REGISTER: u16 = 0x3F4;
 pub const FDC_DATA_REGISTER: u16 = 0x3F5;
+pub const FDC_CONFIG_CONTROL_REGISTER: u16 = 0x3F7;
 
 // Main Status Register Bit Definitions
 // --------------------------------------------------------------------------------
@@ -86,6 +97,7 @@ pub const DOR_MOTOR_FDD_C: u8 = 0b0100_0000;
 pub const DOR_MOTOR_FDD_D: u8 = 0b1000_0000;
 
 pub const COMMAND_MASK: u8 = 0b0001_1111;
+pub const COMMAND_MFM: u8 = 0b0100_0000;
 pub const COMMAND_READ_TRACK: u8 = 0x02;
 pub const COMMAND_WRITE_SECTOR: u8 = 0x05;
 pub const COMMAND_READ_SECTOR: u8 = 0x06;
@@ -112,6 +124,8 @@ pub const ST0_RESET: u8 = 0b1100_0000;
 pub const ST1_NO_ID: u8 = 0b0000_0001;
 pub const ST1_WRITE_PROTECT: u8 = 0b0000_0010;
 pub const ST1_NODATA: u8 = 0b0000_0100;
+pub const ST1_OVERRUN: u8 = 0b0001_0000;
+pub const ST1_END_OF_CYLINDER: u8 = 0b1000_0000;
 
 pub const ST3_ESIG: u8 = 0b1000_0000;
 pub const ST3_WRITE_PROTECT: u8 = 0b0100_0000;
@@ -121,13 +135,14 @@ pub const ST3_DOUBLESIDED: u8 = 0b0000_1000;
 pub const ST3_HEAD: u8 = 0b0000_0100;
 
 /// Represent the state of the DIO bit of the Main Status Register in a readable way.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum IoMode {
     ToCpu,
     FromCpu,
 }
 
 /// Represent the various commands that the NEC FDC knows how to handle.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Command {
     NoCommand,
     ReadTrack,
@@ -168,7 +183,7 @@ pub enum InterruptCode {
 /// Attempt to classify every general error condition a virtual disk drive may experience.
 /// These states are used to build the status bytes presented after a command has been
 /// executed. The exact mapping between error conditions and status flags is uncertain...
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum DriveError {
     NoError,
     NoMedia,
@@ -176,7 +191,12 @@ pub enum DriveError {
     BadRead,
     BadWrite,
     WriteProtect,
+    NoAddressMark,
     DMAError,
+    /// The CPU did not service a data request in non-DMA mode before the next byte was due.
+    Overrun,
+    /// A non-DMA transfer ran to the end of the track without a terminal count.
+    EndOfCylinder,
 }
 
 pub struct OperationSpecifier {
@@ -191,7 +211,7 @@ pub struct OperationSpecifier {
 /// terminate, and is called on a repeated basis by the run() method until complete.
 ///
 /// Operations usually involve DMA transfers.
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Operation {
     NoOperation,
     ReadSector(u8, u8, u8, u8, u8, u8, u8), // cylinder, head, sector, sector_size, track_len, gap3_len, data_len
@@ -220,8 +240,15 @@ pub struct FloppyController {
     command: Command,
     command_fn: Option<CommandDispatchFn>,
     last_command: Command,
+    // Count of commands dispatched, and the most recent one, for observers that poll the controller.
+    dispatch_ct: u64,
+    last_dispatched: Command,
     receiving_command: bool,
     command_byte_n: u32,
+    command_mfm: bool,
+    // The data rate selected through the Configuration Control Register. The XT controller has no
+    // CCR and a fixed clock matching its drives, so the data rate isn't checked until one is selected.
+    data_rate: Option<DataRate>,
     operation: Operation,
     operation_init: bool,
     send_interrupt: bool,
@@ -244,6 +271,185 @@ pub struct FloppyController {
     xfer_size_sectors: u32,
     xfer_size_bytes: usize,
     xfer_completed_sectors: u32,
+
+    // Non-DMA transfer state. While pio_pending is set the data register holds a byte for the CPU
+    // to read, or awaits a byte from the CPU, and must be serviced before the next byte time elapses.
+    in_pio: bool,
+    pio_pending: bool,
+    pio_us: f64,
+}
+
+/// The serializable state of a floppy drive attached to the FDC. Media presence and write
+/// protection are captured for reference, but follow the drive's image when state is restored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FloppyDriveState {
+    pub chs: (u8, u8, u8),
+    pub error_signal: bool,
+    pub ready: bool,
+    pub motor_on: bool,
+    pub positioning: bool,
+    pub have_disk: bool,
+    pub write_protected: bool,
+}
+
+/// The serializable state of the FDC and its drives. Disk images are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FloppyControllerState {
+    pub status_byte: u8,
+    pub reset_flag: bool,
+    pub reset_sense_count: u8,
+    pub mrq: bool,
+    pub data_register: u8,
+    pub dma: bool,
+    pub dor: u8,
+    pub busy: bool,
+    pub dio: IoMode,
+    pub reading_command: bool,
+    pub command: Command,
+    pub last_command: Command,
+    pub receiving_command: bool,
+    pub command_byte_n: u32,
+    pub command_mfm: bool,
+    pub data_rate: Option<DataRate>,
+    pub operation: Operation,
+    pub operation_init: bool,
+    pub send_interrupt: bool,
+    pub pending_interrupt: bool,
+    pub end_interrupt: bool,
+    pub last_error: DriveError,
+    pub data_register_out: Vec<u8>,
+    pub data_register_in: Vec<u8>,
+    pub format_buffer: Vec<u8>,
+    pub drives: Vec<FloppyDriveState>,
+    pub drive_select: usize,
+    pub in_dma: bool,
+    pub dma_byte_count: usize,
+    pub dma_bytes_left: usize,
+    pub xfer_size_sectors: u32,
+    pub xfer_size_bytes: usize,
+    pub xfer_completed_sectors: u32,
+    pub in_pio: bool,
+    pub pio_pending: bool,
+    pub pio_us: f64,
+}
+
+impl DeviceState for FloppyController {
+    type State = FloppyControllerState;
+
+    fn save_state(&self) -> FloppyControllerState {
+        FloppyControllerState {
+            status_byte: self.status_byte,
+            reset_flag: self.reset_flag,
+            reset_sense_count: self.reset_sense_count,
+            mrq: self.mrq,
+            data_register: self.data_register,
+            dma: self.dma,
+            dor: self.dor,
+            busy: self.busy,
+            dio: self.dio,
+            reading_command: self.reading_command,
+            command: self.command,
+            last_command: self.last_command,
+            receiving_command: self.receiving_command,
+            command_byte_n: self.command_byte_n,
+            command_mfm: self.command_mfm,
+            data_rate: self.data_rate,
+            operation: self.operation,
+            operation_init: self.operation_init,
+            send_interrupt: self.send_interrupt,
+            pending_interrupt: self.pending_interrupt,
+            end_interrupt: self.end_interrupt,
+            last_error: self.last_error,
+            data_register_out: self.data_register_out.iter().copied().collect(),
+            data_register_in: self.data_register_in.iter().copied().collect(),
+            format_buffer: self.format_buffer.iter().copied().collect(),
+            drives: self.drives[..self.drive_ct]
+                .iter()
+                .map(|d| FloppyDriveState {
+                    chs: d.chs.get(),
+                    error_signal: d.error_signal,
+                    ready: d.ready,
+                    motor_on: d.motor_on,
+                    positioning: d.positioning,
+                    have_disk: d.have_disk,
+                    write_protected: d.write_protected,
+                })
+                .collect(),
+            drive_select: self.drive_select,
+            in_dma: self.in_dma,
+            dma_byte_count: self.dma_byte_count,
+            dma_bytes_left: self.dma_bytes_left,
+            xfer_size_sectors: self.xfer_size_sectors,
+            xfer_size_bytes: self.xfer_size_bytes,
+            xfer_completed_sectors: self.xfer_completed_sectors,
+            in_pio: self.in_pio,
+            pio_pending: self.pio_pending,
+            pio_us: self.pio_us,
+        }
+    }
+
+    fn load_state(&mut self, state: FloppyControllerState) -> Result<(), Error> {
+        // The handler for a partially received command can't be recovered from its state.
+        if state.receiving_command {
+            bail!("Can't restore FDC state while a command is being received");
+        }
+        if state.drives.len() != self.drive_ct {
+            bail!(
+                "FDC state has {} drives, expected {}",
+                state.drives.len(),
+                self.drive_ct
+            );
+        }
+        if state.drive_select >= FDC_MAX_DRIVES {
+            bail!("FDC state has invalid drive select: {}", state.drive_select);
+        }
+
+        self.status_byte = state.status_byte;
+        self.reset_flag = state.reset_flag;
+        self.reset_sense_count = state.reset_sense_count;
+        self.mrq = state.mrq;
+        self.data_register = state.data_register;
+        self.dma = state.dma;
+        self.dor = state.dor;
+        self.busy = state.busy;
+        self.dio = state.dio;
+        self.reading_command = state.reading_command;
+        self.command = state.command;
+        self.command_fn = None;
+        self.last_command = state.last_command;
+        self.receiving_command = false;
+        self.command_byte_n = state.command_byte_n;
+        self.command_mfm = state.command_mfm;
+        self.data_rate = state.data_rate;
+        self.operation = state.operation;
+        self.operation_init = state.operation_init;
+        self.send_interrupt = state.send_interrupt;
+        self.pending_interrupt = state.pending_interrupt;
+        self.end_interrupt = state.end_interrupt;
+        self.last_error = state.last_error;
+        self.data_register_out = state.data_register_out.into();
+        self.data_register_in = state.data_register_in.into();
+        self.format_buffer = state.format_buffer.into();
+        for (drive, ds) in self.drives.iter_mut().zip(state.drives) {
+            let (c, h, s) = ds.chs;
+            drive.chs.set(c, h, s);
+            drive.error_signal = ds.error_signal;
+            drive.ready = ds.ready;
+            drive.motor_on = ds.motor_on;
+            drive.positioning = ds.positioning;
+        }
+        self.drive_select = state.drive_select;
+        self.in_dma = state.in_dma;
+        self.dma_byte_count = state.dma_byte_count;
+        self.dma_bytes_left = state.dma_bytes_left;
+        self.xfer_size_sectors = state.xfer_size_sectors;
+        self.xfer_size_bytes = state.xfer_size_bytes;
+        self.xfer_completed_sectors = state.xfer_completed_sectors;
+        self.in_pio = state.in_pio;
+        self.pio_pending = state.pio_pending;
+        self.pio_us = state.pio_us;
+        Ok(())
+    }
 }
 
 /// IO Port handlers for the FDC
@@ -256,6 +462,10 @@ impl IoDevice for FloppyController {
             }
             FDC_STATUS_REGISTER => self.handle_status_register_read(),
             FDC_DATA_REGISTER => self.handle_data_register_read(),
+            FDC_CONFIG_CONTROL_REGISTER => {
+                log::warn!("Read from Write-only CCR register");
+                0
+            }
             _ => unreachable!("FLOPPY: Bad port #"),
         }
     }
@@ -271,12 +481,21 @@ impl IoDevice for FloppyController {
             FDC_DATA_REGISTER => {
                 self.handle_data_register_write(data);
             }
+            FDC_CONFIG_CONTROL_REGISTER => {
+                self.data_rate = Some(DataRate::from_ccr(data));
+                log::debug!("FDC data rate set to {:?}", self.data_rate.unwrap());
+            }
             _ => unreachable!("FLOPPY: Bad port #"),
         }
     }
 
     fn port_list(&self) -> Vec<u16> {
-        vec![FDC_DIGITAL_OUTPUT_REGISTER, FDC_STATUS_REGISTER, FDC_DATA_REGISTER]
+        vec![
+            FDC_DIGITAL_OUTPUT_REGISTER,
+            FDC_STATUS_REGISTER,
+            FDC_DATA_REGISTER,
+            FDC_CONFIG_CONTROL_REGISTER,
+        ]
     }
 }
 
@@ -296,7 +515,11 @@ impl Default for FloppyController {
             command: Command::NoCommand,
             command_fn: None,
             last_command: Command::NoCommand,
+            dispatch_ct: 0,
+            last_dispatched: Command::NoCommand,
             command_byte_n: 0,
+            command_mfm: true,
+            data_rate: None,
             receiving_command: false,
             operation: Operation::NoOperation,
             operation_init: false,
@@ -312,10 +535,10 @@ impl Default for FloppyController {
             format_buffer: VecDeque::new(),
 
             drives: [
-                FloppyDiskDrive::new(),
-                FloppyDiskDrive::new(),
-                FloppyDiskDrive::new(),
-                FloppyDiskDrive::new(),
+                FloppyDiskDrive::default(),
+                FloppyDiskDrive::default(),
+                FloppyDiskDrive::default(),
+                FloppyDiskDrive::default(),
             ],
             drive_ct: 0,
             drive_select: 0,
@@ -326,16 +549,24 @@ impl Default for FloppyController {
             xfer_size_sectors: 0,
             xfer_size_bytes: 0,
             xfer_completed_sectors: 0,
+
+            in_pio: false,
+            pio_pending: false,
+            pio_us: 0.0,
         }
     }
 }
 
 impl FloppyController {
-    pub fn new(drive_ct: usize) -> Self {
-        Self {
-            drive_ct,
+    pub fn new(drive_types: &[FloppyDriveType]) -> Self {
+        let mut fdc = Self {
+            drive_ct: drive_types.len().min(FDC_MAX_DRIVES),
             ..Default::default()
+        };
+        for (drive, drive_type) in fdc.drives.iter_mut().zip(drive_types.iter()) {
+            *drive = FloppyDiskDrive::new(*drive_type);
         }
+        fdc
     }
 
     /// Reset the Floppy Drive Controller
@@ -370,6 +601,12 @@ impl FloppyController {
         self.in_dma = false;
         self.dma_byte_count = 0;
         self.dma_bytes_left = 0;
+
+        // A reset returns the controller to DMA mode until the next Specify command.
+        self.dma = true;
+        self.in_pio = false;
+        self.pio_pending = false;
+        self.pio_us = 0.0;
     }
 
     pub fn drive_ct(&self) -> usize {
@@ -387,43 +624,19 @@ impl FloppyController {
             return Err("Invalid drive selection");
         }
 
-        let image_len: usize = src_vec.len();
-
-        // Disk images must contain whole sectors
-        if image_len % SECTOR_SIZE > 0 {
-            return Err("Invalid image length");
-        }
-
-        // Look up disk parameters based on image size
-        if let Some(fmt) = DISK_FORMATS.get(&image_len) {
-            self.drives[drive_select].max_cylinders = fmt.chs.c();
-            self.drives[drive_select].max_heads = fmt.chs.h();
-            self.drives[drive_select].max_sectors = fmt.chs.s();
-        }
-        else {
-            // No image format found.
-            if image_len < 163_840 {
-                // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
-                // This is useful for loading things like boot sector images without having to copy them to
-                // a full disk image
-                self.drives[drive_select].max_cylinders = 40;
-                self.drives[drive_select].max_heads = 1;
-                self.drives[drive_select].max_sectors = 8;
-            }
-            else {
-                return Err("Invalid image length");
-            }
-        }
+        self.drives[drive_select].set_media(src_vec.len())?;
 
         self.drives[drive_select].have_disk = true;
         self.drives[drive_select].disk_image = src_vec;
         log::debug!(
-            "Loaded floppy image, drive: {} size: {} c: {} h: {} s: {}",
+            "Loaded floppy image, drive: {} size: {} c: {} h: {} s: {} sector size: {} encoding: {:?}",
             drive_select,
             self.drives[drive_select].disk_image.len(),
             self.drives[drive_select].max_cylinders,
             self.drives[drive_select].max_heads,
-            self.drives[drive_select].max_sectors
+            self.drives[drive_select].max_sectors,
+            self.drives[drive_select].sector_size,
+            self.drives[drive_select].encoding
         );
 
         self.drives[drive_select].write_protected = write_protect;
@@ -451,6 +664,8 @@ impl FloppyController {
         drive.max_cylinders = 40;
         drive.max_heads = 1;
         drive.max_sectors = 8;
+        drive.sector_size = SECTOR_SIZE;
+        drive.encoding = DiskEncoding::Mfm;
         drive.have_disk = false;
         drive.disk_image.clear();
     }
@@ -467,7 +682,8 @@ impl FloppyController {
             msr_byte |= FDC_STATUS_FDC_BUSY;
         }
 
-        if !self.dma {
+        // The NDM bit is only set during the execution phase of a non-DMA transfer.
+        if self.in_pio {
             msr_byte |= FDC_STATUS_NON_DMA_MODE;
         }
 
@@ -600,6 +816,9 @@ impl FloppyController {
         st1_byte |= match self.last_error {
             DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => ST1_NODATA,
             DriveError::WriteProtect => ST1_WRITE_PROTECT | ST1_NO_ID,
+            DriveError::NoAddressMark => ST1_NO_ID,
+            DriveError::Overrun => ST1_OVERRUN,
+            DriveError::EndOfCylinder => ST1_END_OF_CYLINDER,
             _ => 0,
         };
 
@@ -655,6 +874,10 @@ impl FloppyController {
     }
 
     pub fn handle_data_register_read(&mut self) -> u8 {
+        if self.in_pio {
+            return self.pio_data_read();
+        }
+
         let mut out_byte = 0;
 
         if self.data_register_out.len() > 0 {
@@ -680,6 +903,17 @@ impl FloppyController {
         self.command_byte_n = n_bytes;
     }
 
+    fn note_dispatch(&mut self, command: Command) {
+        self.dispatch_ct += 1;
+        self.last_dispatched = command;
+    }
+
+    /// Return the number of commands dispatched since the controller was created, and the most
+    /// recently dispatched command.
+    pub fn dispatched_commands(&self) -> (u64, Command) {
+        (self.dispatch_ct, self.last_dispatched)
+    }
+
     pub fn send_data_register(&mut self) {
         self.busy = true;
         self.dio = IoMode::ToCpu;
@@ -704,6 +938,47 @@ impl FloppyController {
         return false;
     }
 
+    /// Returns whether the media in the specified drive can be read with the current command's
+    /// encoding, the selected data rate and the given sector size code. On real hardware a
+    /// mismatch means the controller never finds an address mark.
+    pub fn is_media_compatible(&self, drive_select: usize, sector_size: u8) -> bool {
+        let drive = &self.drives[drive_select];
+
+        let encoding = match self.command_mfm {
+            true => DiskEncoding::Mfm,
+            false => DiskEncoding::Fm,
+        };
+        if encoding != drive.encoding {
+            log::debug!(
+                "is_media_compatible(): false due to encoding: {:?} media: {:?}",
+                encoding,
+                drive.encoding
+            );
+            return false;
+        }
+
+        if let Some(data_rate) = self.data_rate {
+            if data_rate != drive.data_rate {
+                log::debug!(
+                    "is_media_compatible(): false due to data rate: {:?} media: {:?}",
+                    data_rate,
+                    drive.data_rate
+                );
+                return false;
+            }
+        }
+
+        if sector_size != sector_size_to_code(drive.sector_size) {
+            log::debug!(
+                "is_media_compatible(): false due to sector size code: {} media: {}",
+                sector_size,
+                drive.sector_size
+            );
+            return false;
+        }
+        true
+    }
+
     /// Handle a write to the Data Register, 0x3F5.
     ///
     /// This register receives various commands which may be up to 8 bytes long.
@@ -715,8 +990,14 @@ impl FloppyController {
     /// time like DMA transfers.
     pub fn handle_data_register_write(&mut self, data: u8) {
         //log::trace!("Data Register Write");
+        if self.in_pio {
+            self.pio_data_write(data);
+            return;
+        }
+
         if !self.receiving_command {
             let command = data & COMMAND_MASK;
+            self.command_mfm = data & COMMAND_MFM != 0;
             match command {
                 COMMAND_READ_TRACK => {
                     log::trace!("Received Read Track command: {:02}", command);
@@ -761,6 +1042,7 @@ impl FloppyController {
                 COMMAND_SENSE_INT_STATUS => {
                     log::trace!("Received Sense Interrupt Status command: {:02}", command);
                     // Sense Interrupt command has no input bytes, so execute directly
+                    self.note_dispatch(Command::SenseIntStatus);
                     self.command_sense_interrupt();
                 }
                 COMMAND_READ_SECTOR_ID => {
@@ -772,7 +1054,12 @@ impl FloppyController {
                     self.set_command(Command::SeekParkHead, 2, FloppyController::command_seek_head);
                 }
                 _ => {
-                    log::warn!("Received invalid command byte: {:02}", command);
+                    log_event!(
+                        LogCategory::Fdc,
+                        LogLevel::Warn,
+                        "Received invalid command byte: {:02}",
+                        command
+                    );
                 }
             }
         }
@@ -791,6 +1078,7 @@ impl FloppyController {
                             log::error!("No associated method for command: {:?}!", self.command)
                         }
                         Some(command_fn) => {
+                            self.note_dispatch(self.command);
                             result = command_fn(self);
                         }
                     }
@@ -865,7 +1153,7 @@ impl FloppyController {
                 };
 
                 let code = match self.last_error {
-                    DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => {
+                    DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek | DriveError::NoAddressMark => {
                         InterruptCode::AbnormalTermination
                     }
                     _ => InterruptCode::NormalTermination,
@@ -898,15 +1186,19 @@ impl FloppyController {
     }
 
     /// Perform the Fix Drive Data command.
-    /// We don't do anything currently with the provided values which are only useful for real drive timings.
+    /// Only the ND bit, which selects non-DMA mode, is used. The remaining values are only useful for
+    /// real drive timings.
     pub fn command_fix_drive_data(&mut self) -> Continuation {
         let steprate_unload = self.data_register_in.pop_front().unwrap();
         let headload_ndm = self.data_register_in.pop_front().unwrap();
 
+        self.dma = headload_ndm & 0x01 == 0;
+
         log::trace!(
-            "command_fix_drive_data completed: {:08b},{:08b}",
+            "command_fix_drive_data completed: {:08b},{:08b} dma: {}",
             steprate_unload,
-            headload_ndm
+            headload_ndm,
+            self.dma
         );
 
         Continuation::CommandComplete
@@ -979,7 +1271,9 @@ impl FloppyController {
         // Seek to values given in command
         self.drives[drive_select].chs.seek(cylinder, head_select, 1);
 
-        log::trace!(
+        log_event!(
+            LogCategory::Fdc,
+            LogLevel::Trace,
             "command_seek_head completed: {} new chs: {}",
             drive_head_select,
             self.drives[drive_select].chs
@@ -1037,6 +1331,19 @@ impl FloppyController {
             return Continuation::CommandComplete;
         }
 
+        // Can the controller read this media?
+        if !self.is_media_compatible(drive_select, sector_size) {
+            self.last_error = DriveError::NoAddressMark;
+            self.send_results_phase(
+                InterruptCode::AbnormalTermination,
+                drive_select,
+                DiskChs::new(cylinder, head, sector),
+                sector_size,
+            );
+            self.send_interrupt = true;
+            return Continuation::CommandComplete;
+        }
+
         // Seek to values given in command
         self.drives[drive_select].chs.seek(cylinder, head, sector);
 
@@ -1046,8 +1353,8 @@ impl FloppyController {
         // Clear MRQ until operation completion so there is no attempt to read result values
         self.mrq = false;
 
-        // DMA now in progress (TODO: Support PIO mode?)
-        self.in_dma = true;
+        // Start the transfer in DMA or non-DMA mode, as selected by the last Specify command
+        self.begin_transfer();
 
         // The IBM PC BIOS only seems to ever set a track_len of 8. How do we support 9 sector (365k) floppies?
         // Answer: DOS seems to know to request sector #9 and the BIOS doesn't complain
@@ -1063,8 +1370,19 @@ impl FloppyController {
         //}
         //self.dma_bytes_left = max_sectors as usize * SECTOR_SIZE;
 
-        log::trace!("command_read_sector: drive: {} cyl:{} head:{} sector:{} sector_size:{} track_len:{} gap3_len:{} data_len:{}",
-            drive_select, cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
+        log_event!(
+            LogCategory::Fdc,
+            LogLevel::Trace,
+            "command_read_sector: drive: {} cyl:{} head:{} sector:{} sector_size:{} track_len:{} gap3_len:{} data_len:{}",
+            drive_select,
+            cylinder,
+            head,
+            sector,
+            sector_size,
+            track_len,
+            gap3_len,
+            data_len
+        );
         //log::trace!("command_read_sector: may operate on maximum of {} sectors", max_sectors);
 
         let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
@@ -1095,6 +1413,20 @@ impl FloppyController {
             log::warn!("command_write_sector: non-matching head specifiers");
         }
 
+        // Can the controller write this media?
+        if self.drives[drive_select].have_disk && !self.is_media_compatible(drive_select, sector_size) {
+            self.drive_select = drive_select;
+            self.last_error = DriveError::NoAddressMark;
+            self.send_results_phase(
+                InterruptCode::AbnormalTermination,
+                drive_select,
+                DiskChs::new(cylinder, head, sector),
+                sector_size,
+            );
+            self.send_interrupt = true;
+            return Continuation::CommandComplete;
+        }
+
         // Seek to values given in command
         self.drives[drive_select].chs.seek(cylinder, head, sector);
 
@@ -1104,10 +1436,12 @@ impl FloppyController {
         // Clear MRQ until operation completion so there is no attempt to read result values
         self.mrq = false;
 
-        // DMA now in progress (TODO: Support PIO mode?)
-        self.in_dma = true;
+        // Start the transfer in DMA or non-DMA mode, as selected by the last Specify command
+        self.begin_transfer();
 
-        log::trace!(
+        log_event!(
+            LogCategory::Fdc,
+            LogLevel::Trace,
             "command_write_sector: cyl:{} head:{} sector:{} sector_size:{} track_len:{} gap3_len:{} data_len:{}",
             cylinder,
             head,
@@ -1150,7 +1484,10 @@ impl FloppyController {
         // Clear MRQ until operation completion so there is no attempt to read result values
         self.mrq = false;
 
-        // DMA now in progress (TODO: Support PIO mode?)
+        // DMA now in progress. Formatting in non-DMA mode is not supported.
+        if !self.dma {
+            log::warn!("command_format_track: non-DMA mode not supported, using DMA");
+        }
         self.in_dma = true;
 
         log::trace!(
@@ -1172,11 +1509,24 @@ impl FloppyController {
         let drive_select = (drive_head_select & 0x03) as usize;
         let _head_select = (drive_head_select >> 2) & 0x01;
 
+        let sector_size = sector_size_to_code(self.drives[drive_select].sector_size);
+        if !self.is_media_compatible(drive_select, sector_size) {
+            self.last_error = DriveError::NoAddressMark;
+            self.send_results_phase(
+                InterruptCode::AbnormalTermination,
+                drive_select,
+                self.drives[drive_select].chs,
+                sector_size,
+            );
+            self.send_interrupt = true;
+            return Continuation::CommandComplete;
+        }
+
         self.send_results_phase(
             InterruptCode::NormalTermination,
             drive_select,
             self.drives[drive_select].chs,
-            0x02,
+            sector_size,
         );
 
         self.send_interrupt = true;
@@ -1192,7 +1542,7 @@ impl FloppyController {
         let hpc = self.drives[drive_select].max_heads as usize;
         let spt = self.drives[drive_select].max_sectors as usize;
         let lba: usize = (cylinder as usize * hpc + (head as usize)) * spt + (sector as usize - 1);
-        lba * SECTOR_SIZE
+        lba * self.drives[drive_select].sector_size
     }
 
     pub fn get_chs_sector_offset(
@@ -1280,14 +1630,16 @@ impl FloppyController {
 
         // Is read valid?
 
+        let sector_bytes = self.drives[self.drive_select].sector_size;
+
         if !self.operation_init {
             let xfer_size = dma.get_dma_transfer_size(FDC_DMA);
 
-            if xfer_size % SECTOR_SIZE != 0 {
+            if !xfer_size.is_multiple_of(sector_bytes) {
                 log::warn!("DMA word count not multiple of sector size");
             }
 
-            let xfer_sectors = xfer_size / SECTOR_SIZE;
+            let xfer_sectors = xfer_size / sector_bytes;
             log::trace!("DMA programmed for transfer of {} sectors", xfer_sectors);
 
             let dst_address = dma.get_dma_transfer_address(FDC_DMA);
@@ -1295,8 +1647,8 @@ impl FloppyController {
 
             self.xfer_size_sectors = xfer_sectors as u32;
             self.xfer_completed_sectors = 0;
-            self.xfer_size_bytes = xfer_sectors * SECTOR_SIZE;
-            self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
+            self.xfer_size_bytes = xfer_sectors * sector_bytes;
+            self.dma_bytes_left = xfer_sectors * sector_bytes;
             self.operation_init = true;
         }
 
@@ -1304,7 +1656,7 @@ impl FloppyController {
             // Bytes left to transfer
 
             // Calculate how many sectors we've done
-            if (self.dma_bytes_left < self.xfer_size_bytes) && (self.dma_bytes_left % SECTOR_SIZE == 0) {
+            if (self.dma_bytes_left < self.xfer_size_bytes) && self.dma_bytes_left.is_multiple_of(sector_bytes) {
                 // Completed one sector
 
                 self.xfer_completed_sectors += 1;
@@ -1417,21 +1769,23 @@ impl FloppyController {
             return;
         }
 
+        let sector_bytes = self.drives[self.drive_select].sector_size;
+
         if !self.operation_init {
             let xfer_size = dma.get_dma_transfer_size(FDC_DMA);
 
-            if xfer_size % SECTOR_SIZE != 0 {
+            if !xfer_size.is_multiple_of(sector_bytes) {
                 log::warn!("DMA word count not multiple of sector size");
             }
 
-            let xfer_sectors = xfer_size / SECTOR_SIZE;
+            let xfer_sectors = xfer_size / sector_bytes;
             log::trace!("DMA programmed for transfer of {} sectors", xfer_sectors);
 
-            self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
+            self.dma_bytes_left = xfer_sectors * sector_bytes;
             self.operation_init = true;
         }
 
-        if self.dma_bytes_left == SECTOR_SIZE {
+        if self.dma_bytes_left == sector_bytes {
             let dst_address = dma.get_dma_transfer_address(FDC_DMA);
             log::trace!("DMA source address: {:05X}", dst_address)
         }
@@ -1637,8 +1991,190 @@ impl FloppyController {
 
     pub fn format_sector(&mut self, _cylinder: u8, _head: u8, _sector: u8, _fill_byte: u8) {}
 
+    /// Enter the execution phase of a data transfer, using DMA or non-DMA mode as selected by the
+    /// ND bit of the last Specify command.
+    fn begin_transfer(&mut self) {
+        if self.dma {
+            self.in_dma = true;
+        }
+        else {
+            self.in_pio = true;
+            self.pio_pending = false;
+            self.pio_us = 0.0;
+            self.busy = true;
+        }
+    }
+
+    /// Return the time in microseconds for one byte to pass under the head at the current data rate.
+    fn pio_byte_time(&self) -> f64 {
+        let data_rate = self.data_rate.unwrap_or(self.drives[self.drive_select].data_rate);
+        let bits = if self.command_mfm { 8.0 } else { 16.0 };
+        bits * 1000.0 / data_rate.kbps()
+    }
+
+    /// Present a byte to the CPU, or request one from it, raising an interrupt.
+    fn pio_request(&mut self, dio: IoMode) {
+        self.dio = dio;
+        self.mrq = true;
+        self.pio_pending = true;
+        self.send_interrupt = true;
+    }
+
+    /// Handle a read of the Data Register during the execution phase of a non-DMA transfer.
+    fn pio_data_read(&mut self) -> u8 {
+        if self.pio_pending && matches!(self.dio, IoMode::ToCpu) {
+            self.pio_pending = false;
+            self.mrq = false;
+            self.end_interrupt = true;
+        }
+        else {
+            log::warn!("Data Register read during non-DMA transfer with no byte ready");
+        }
+        self.data_register
+    }
+
+    /// Handle a write to the Data Register during the execution phase of a non-DMA transfer.
+    fn pio_data_write(&mut self, data: u8) {
+        if self.pio_pending && matches!(self.dio, IoMode::FromCpu) {
+            self.data_register = data;
+            self.pio_pending = false;
+            self.mrq = false;
+            self.end_interrupt = true;
+        }
+        else {
+            log::warn!("Data Register write during non-DMA transfer with no byte requested");
+        }
+    }
+
+    /// Terminate a non-DMA transfer, entering the result phase.
+    fn end_pio_transfer(&mut self, result: InterruptCode, chs: DiskChs, sector_size: u8) {
+        self.in_pio = false;
+        self.pio_pending = false;
+        self.pio_us = 0.0;
+        self.dma_byte_count = 0;
+        self.dma_bytes_left = 0;
+
+        self.send_results_phase(result, self.drive_select, chs, sector_size);
+        self.drives[self.drive_select].chs.seek_to(&chs);
+
+        self.operation = Operation::NoOperation;
+        self.send_interrupt = true;
+    }
+
+    /// Run a Read or Write Sector operation in non-DMA mode.
+    ///
+    /// One byte at a time is presented to, or requested from the CPU through the Data Register, with
+    /// an interrupt for each. If the CPU has not serviced the Data Register by the time the next byte
+    /// passes under the head, the operation terminates with an overrun. There is no terminal count
+    /// without DMA, so a transfer that is serviced in time runs to the end of the track (EOT) and
+    /// terminates with the End of Cylinder flag set.
+    fn operation_pio_transfer(&mut self, us: f64, write: bool, chs: DiskChs, sector_size: u8, track_len: u8) {
+        let drive_select = self.drive_select;
+
+        // Fail operation if disk is write protected
+        if write && self.drives[drive_select].write_protected {
+            log::warn!("WriteSector operation on write protected disk!");
+            self.last_error = DriveError::WriteProtect;
+            self.end_pio_transfer(InterruptCode::AbnormalPolling, chs, sector_size);
+            return;
+        }
+
+        let sector_bytes = self.drives[drive_select].sector_size;
+        let base_address = self.get_image_address(drive_select, chs.c(), chs.h(), chs.s());
+
+        if !self.operation_init {
+            let xfer_sectors = track_len.saturating_sub(chs.s()) as usize + 1;
+            let image_left = self.drives[drive_select].disk_image.len().saturating_sub(base_address);
+
+            self.dma_byte_count = 0;
+            self.dma_bytes_left = (xfer_sectors * sector_bytes).min(image_left);
+            self.operation_init = true;
+            log::trace!(
+                "operation_pio_transfer: non-DMA transfer of {} sectors, write: {}",
+                xfer_sectors,
+                write
+            );
+
+            if write && self.dma_bytes_left > 0 {
+                self.pio_request(IoMode::FromCpu);
+            }
+        }
+
+        self.pio_us += us;
+        if self.pio_us < self.pio_byte_time() {
+            return;
+        }
+        // Time the next byte from now, so a late run doesn't cause a spurious overrun.
+        self.pio_us = 0.0;
+
+        if self.pio_pending {
+            log::warn!(
+                "operation_pio_transfer: overrun after {} byte(s), write: {}",
+                self.dma_byte_count,
+                write
+            );
+            let completed_sectors = (self.dma_byte_count / sector_bytes) as u32;
+            let (c, h, s) = self.get_chs_sector_offset(drive_select, completed_sectors, chs.c(), chs.h(), chs.s());
+            self.last_error = DriveError::Overrun;
+            self.end_pio_transfer(InterruptCode::AbnormalTermination, DiskChs::new(c, h, s), sector_size);
+            return;
+        }
+
+        if write {
+            if self.dma_bytes_left > 0 {
+                // The CPU supplied the requested byte in time.
+                let byte_address = base_address + self.dma_byte_count;
+                self.drives[drive_select].disk_image[byte_address] = self.data_register;
+                self.dma_byte_count += 1;
+                self.dma_bytes_left -= 1;
+            }
+
+            if self.dma_bytes_left > 0 {
+                self.pio_request(IoMode::FromCpu);
+                return;
+            }
+        }
+        else if self.dma_bytes_left > 0 {
+            let byte_address = base_address + self.dma_byte_count;
+            self.data_register = self.drives[drive_select].disk_image[byte_address];
+            self.dma_byte_count += 1;
+            self.dma_bytes_left -= 1;
+            self.pio_request(IoMode::ToCpu);
+            return;
+        }
+
+        // All bytes to the end of the track have been transferred.
+        let (c, h, s) = self.get_chs_sector_offset(
+            drive_select,
+            (self.dma_byte_count / sector_bytes) as u32,
+            chs.c(),
+            chs.h(),
+            chs.s(),
+        );
+        log::trace!(
+            "operation_pio_transfer completed: {} byte(s) transferred",
+            self.dma_byte_count
+        );
+        self.last_error = DriveError::EndOfCylinder;
+        self.end_pio_transfer(InterruptCode::AbnormalTermination, DiskChs::new(c, h, s), sector_size);
+    }
+
+    /// Return the time in microseconds until the FDC next needs to be run, or None if it is idle.
+    pub fn next_deadline(&self) -> Option<f64> {
+        if self.in_pio && !self.send_interrupt && !self.end_interrupt {
+            // Non-DMA transfers only need to run when the next byte is due.
+            Some((self.pio_byte_time() - self.pio_us).max(0.0))
+        }
+        else if self.send_interrupt || self.end_interrupt || !matches!(self.operation, Operation::NoOperation) {
+            Some(0.0)
+        }
+        else {
+            None
+        }
+    }
+
     /// Run the Floppy Drive Controller. Process running Operations.
-    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, _us: f64) {
+    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64) {
         // Send an interrupt if one is queued
         if self.send_interrupt {
             bus.pic_mut().as_mut().unwrap().request_interrupt(FDC_IRQ);
@@ -1659,6 +2195,28 @@ impl FloppyController {
             Operation::NoOperation => {
                 // Do nothing
             }
+            Operation::ReadSector(cylinder, head, sector, sector_size, track_len, _gap3_len, _data_len)
+                if self.in_pio =>
+            {
+                self.operation_pio_transfer(
+                    us,
+                    false,
+                    DiskChs::from((cylinder, head, sector)),
+                    sector_size,
+                    track_len,
+                )
+            }
+            Operation::WriteSector(cylinder, head, sector, sector_size, track_len, _gap3_len, _data_len)
+                if self.in_pio =>
+            {
+                self.operation_pio_transfer(
+                    us,
+                    true,
+                    DiskChs::from((cylinder, head, sector)),
+                    sector_size,
+                    track_len,
+                )
+            }
             Operation::ReadSector(cylinder, head, sector, sector_size, track_len, _gap3_len, _data_len) => {
                 self.operation_read_sector(dma, bus, cylinder, head, sector, sector_size, track_len)
             }
@@ -1679,3 +2237,97 @@ impl FloppyController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fdc_8inch_fm_media() {
+        let mut fdc = FloppyController::new(&[FloppyDriveType::Floppy360K, FloppyDriveType::Floppy8Inch]);
+        let image = vec![0; 256_256];
+
+        // 8" media doesn't fit a 5.25" drive.
+        assert!(fdc.load_image_from(0, image.clone(), false).is_err());
+        assert!(fdc.load_image_from(1, image, false).is_ok());
+
+        // IBM 3740 format: 26 x 128 byte sectors per track.
+        assert_eq!(fdc.get_image_address(1, 1, 0, 1), 26 * 128);
+        assert_eq!(fdc.get_image_address(1, 0, 0, 26), 25 * 128);
+
+        // The media can only be read with an FM command and a sector size code of 0.
+        fdc.command_mfm = true;
+        assert!(!fdc.is_media_compatible(1, 0));
+        fdc.command_mfm = false;
+        assert!(fdc.is_media_compatible(1, 0));
+        assert!(!fdc.is_media_compatible(1, 2));
+
+        // 8" drives need the 500Kbps clock once a data rate has been selected.
+        fdc.write_u8(
+            FDC_CONFIG_CONTROL_REGISTER,
+            0x02,
+            None,
+            DeviceRunTimeUnit::Microseconds(0.0),
+        );
+        assert!(!fdc.is_media_compatible(1, 0));
+        fdc.write_u8(
+            FDC_CONFIG_CONTROL_REGISTER,
+            0x00,
+            None,
+            DeviceRunTimeUnit::Microseconds(0.0),
+        );
+        assert!(fdc.is_media_compatible(1, 0));
+    }
+
+    #[test]
+    fn test_fdc_non_dma_transfer() {
+        let mut fdc = FloppyController::new(&[FloppyDriveType::Floppy360K]);
+        let mut image = vec![0; 368_640];
+        for (i, byte) in image.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        fdc.load_image_from(0, image, false).unwrap();
+
+        let write_data = |fdc: &mut FloppyController, bytes: &[u8]| {
+            for byte in bytes {
+                fdc.write_u8(FDC_DATA_REGISTER, *byte, None, DeviceRunTimeUnit::Microseconds(0.0));
+            }
+        };
+
+        // Specify with the ND bit set selects non-DMA mode.
+        write_data(&mut fdc, &[COMMAND_FIX_DRIVE_DATA, 0xCF, 0x03]);
+        assert!(!fdc.dma);
+
+        // Read sector 1 to EOT=1. Every byte is presented through the data register.
+        let read_sector = [COMMAND_READ_SECTOR | COMMAND_MFM, 0x00, 0, 0, 1, 2, 1, 0x2A, 0xFF];
+        write_data(&mut fdc, &read_sector);
+        assert!(fdc.in_pio);
+        let byte_time = fdc.pio_byte_time();
+        assert_eq!(byte_time, 32.0);
+
+        let chs = DiskChs::new(0, 0, 1);
+        for i in 0..512 {
+            fdc.operation_pio_transfer(byte_time, false, chs, 2, 1);
+            let msr = fdc.handle_status_register_read();
+            assert_eq!(msr & 0xE0, FDC_STATUS_MRQ | FDC_STATUS_DIO | FDC_STATUS_NON_DMA_MODE);
+            assert_eq!(fdc.handle_data_register_read(), i as u8);
+        }
+        fdc.operation_pio_transfer(byte_time, false, chs, 2, 1);
+        assert!(!fdc.in_pio);
+
+        // Without a terminal count, the transfer ends at EOT with the End of Cylinder flag.
+        let st0 = fdc.handle_data_register_read();
+        let st1 = fdc.handle_data_register_read();
+        assert_eq!(st0 & 0xC0, ST0_ABNORMAL_TERMINATION);
+        assert_eq!(st1, ST1_END_OF_CYLINDER);
+
+        // Failing to read a byte before the next one arrives is an overrun.
+        write_data(&mut fdc, &read_sector);
+        fdc.operation_pio_transfer(byte_time, false, chs, 2, 1);
+        fdc.operation_pio_transfer(byte_time / 2.0, false, chs, 2, 1);
+        assert!(fdc.in_pio);
+        fdc.operation_pio_transfer(byte_time / 2.0, false, chs, 2, 1);
+        assert!(!fdc.in_pio);
+        assert_eq!(fdc.data_register_out[1], ST1_OVERRUN);
+    }
+}
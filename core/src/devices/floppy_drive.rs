@@ -30,13 +30,18 @@
 */
 
 use crate::{
-    device_types::{chs::DiskChs, fdc::DISK_FORMATS},
+    device_types::{
+        chs::DiskChs,
+        fdc::{DataRate, DiskEncoding, DISK_FORMATS},
+    },
     devices::fdc::SECTOR_SIZE,
+    machine_types::FloppyDriveType,
 };
 use anyhow::{anyhow, Error};
 
 pub struct FloppyDiskDrive {
     pub(crate) error_signal: bool,
+    pub(crate) drive_type:   FloppyDriveType,
 
     pub(crate) chs: DiskChs,
     media_geom: DiskChs,
@@ -45,6 +50,9 @@ pub struct FloppyDiskDrive {
     pub(crate) max_cylinders: u8,
     pub(crate) max_heads: u8,
     pub(crate) max_sectors: u8,
+    pub(crate) sector_size: usize,
+    pub(crate) encoding: DiskEncoding,
+    pub(crate) data_rate: DataRate,
     pub(crate) ready: bool,
     pub(crate) motor_on: bool,
     pub(crate) positioning: bool,
@@ -57,12 +65,16 @@ impl Default for FloppyDiskDrive {
     fn default() -> Self {
         Self {
             error_signal: false,
+            drive_type: FloppyDriveType::Floppy360K,
             chs: Default::default(),
             media_geom: Default::default(),
             drive_geom: Default::default(),
             max_cylinders: 0,
             max_heads: 0,
             max_sectors: 0,
+            sector_size: SECTOR_SIZE,
+            encoding: Default::default(),
+            data_rate: Default::default(),
             ready: false,
             motor_on: false,
             positioning: false,
@@ -73,8 +85,11 @@ impl Default for FloppyDiskDrive {
     }
 }
 impl FloppyDiskDrive {
-    pub fn new() -> Self {
-        Default::default()
+    pub fn new(drive_type: FloppyDriveType) -> Self {
+        Self {
+            drive_type,
+            ..Default::default()
+        }
     }
 
     /// Reset the drive to default state. Like other device patterns we use default after preserving persistent state.
@@ -84,12 +99,16 @@ impl FloppyDiskDrive {
         let image = std::mem::replace(&mut self.disk_image, Vec::new());
 
         *self = Self {
+            drive_type: self.drive_type,
             ready: self.have_disk,
             have_disk: self.have_disk,
             write_protected: self.write_protected,
             max_cylinders: self.max_cylinders,
             max_heads: self.max_heads,
             max_sectors: self.max_sectors,
+            sector_size: self.sector_size,
+            encoding: self.encoding,
+            data_rate: self.data_rate,
             motor_on: false,
             positioning: false,
             disk_image: image,
@@ -97,22 +116,26 @@ impl FloppyDiskDrive {
         };
     }
 
-    /// Load a disk into the specified drive
-    pub fn load_image_from(&mut self, src_vec: Vec<u8>) -> Result<(), Error> {
-        let image_len: usize = src_vec.len();
-
-        // Disk images must contain whole sectors
-        if image_len % SECTOR_SIZE > 0 {
-            return Err(anyhow!("Invalid image length"));
-        }
-
+    /// Set the media parameters of the drive from the length of a disk image.
+    pub(crate) fn set_media(&mut self, image_len: usize) -> Result<(), &'static str> {
         // Look up disk parameters based on image size
         if let Some(fmt) = DISK_FORMATS.get(&image_len) {
+            if fmt.eight_inch != matches!(self.drive_type, FloppyDriveType::Floppy8Inch) {
+                return Err("Image format not supported by drive");
+            }
             self.max_cylinders = fmt.chs.c();
             self.max_heads = fmt.chs.h();
             self.max_sectors = fmt.chs.s();
+            self.sector_size = fmt.sector_size;
+            self.encoding = fmt.encoding;
+            self.data_rate = fmt.data_rate;
         }
         else {
+            // Disk images must contain whole sectors
+            if !image_len.is_multiple_of(SECTOR_SIZE) {
+                return Err("Invalid image length");
+            }
+
             // No image format found.
             if image_len < 163_840 {
                 // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
@@ -121,21 +144,32 @@ impl FloppyDiskDrive {
                 self.max_cylinders = 40;
                 self.max_heads = 1;
                 self.max_sectors = 8;
+                self.sector_size = SECTOR_SIZE;
+                self.encoding = DiskEncoding::Mfm;
+                self.data_rate = DataRate::Rate250Kbps;
             }
             else {
-                return Err(anyhow!("Invalid image length"));
+                return Err("Invalid image length");
             }
         }
+        Ok(())
+    }
+
+    /// Load a disk into the specified drive
+    pub fn load_image_from(&mut self, src_vec: Vec<u8>) -> Result<(), Error> {
+        self.set_media(src_vec.len()).map_err(|e| anyhow!(e))?;
 
         self.have_disk = true;
         self.disk_image = src_vec;
 
         log::debug!(
-            "Loaded floppy image, size: {} c: {} h: {} s: {}",
+            "Loaded floppy image, size: {} c: {} h: {} s: {} sector size: {} encoding: {:?}",
             self.disk_image.len(),
             self.max_cylinders,
             self.max_heads,
-            self.max_sectors
+            self.max_sectors,
+            self.sector_size,
+            self.encoding
         );
 
         Ok(())
@@ -0,0 +1,364 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    -------------------------------------------------------------------------
+
+    devices::game_port.rs
+
+    Implements the IBM Game Control Adapter and a mapping layer from abstract
+    host gamepad axes and buttons onto the emulated joysticks.
+
+    Each of the adapter's four axes is a 558 one-shot whose period is set by
+    the resistance of a joystick potentiometer. Writing any value to the port
+    fires all four one-shots; reading the port returns the one-shot outputs in
+    bits 0-3, which remain high until each timer expires, and the state of the
+    four buttons in bits 4-7, which read low while pressed. Software measures
+    the position of each stick by counting how long its bit stays high.
+
+*/
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::devicestate::DeviceState,
+    machine_config::{AxisCalibration, GamePortConfig},
+};
+
+pub const GAMEPORT_IO_BASE: u16 = 0x201;
+pub const GAMEPORT_AXES: usize = 4;
+pub const GAMEPORT_BUTTONS: usize = 4;
+
+// One-shot period is approximately 24.2us + 0.011us per ohm of joystick resistance.
+const ONESHOT_BASE_US: f64 = 24.2;
+const ONESHOT_US_PER_OHM: f64 = 0.011;
+// Resistance of a standard joystick potentiometer at full deflection.
+pub const JOYSTICK_MAX_OHMS: f64 = 100_000.0;
+
+/// An abstract host gamepad axis. Stick axes range from -1.0 to 1.0, triggers from 0.0 to 1.0.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// An abstract host gamepad button, named by position.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+}
+
+/// Maps a single host axis onto one of the game port's four analog inputs.
+#[derive(Clone, Debug)]
+pub struct AxisMap {
+    pub host: GamepadAxis,
+    pub axis: usize,
+    pub invert: bool,
+    pub deadzone: f64,
+    pub curve: f64,
+    pub calibration: AxisCalibration,
+}
+
+impl AxisMap {
+    /// Convert a host axis value to a potentiometer position, as a fraction of full resistance.
+    pub fn position(&self, value: f64) -> f64 {
+        let mut value = value.clamp(-1.0, 1.0);
+
+        // Values inside the deadzone are centered; the remaining range is rescaled so that
+        // the stick still reaches full deflection.
+        if value.abs() < self.deadzone {
+            value = 0.0;
+        }
+        else if self.deadzone > 0.0 {
+            value = value.signum() * (value.abs() - self.deadzone) / (1.0 - self.deadzone);
+        }
+
+        if self.curve != 1.0 {
+            value = value.signum() * value.abs().powf(self.curve);
+        }
+        if self.invert {
+            value = -value;
+        }
+
+        // Piecewise linear interpolation through the calibration points.
+        let cal = &self.calibration;
+        if value < 0.0 {
+            cal.center + value * (cal.center - cal.min)
+        }
+        else {
+            cal.center + value * (cal.max - cal.center)
+        }
+    }
+}
+
+/// Maps a single host button onto one of the game port's four button inputs.
+#[derive(Copy, Clone, Debug)]
+pub struct ButtonMap {
+    pub host:   GamepadButton,
+    pub button: usize,
+}
+
+pub struct GamePort {
+    io_base: u16,
+    position: [f64; GAMEPORT_AXES],
+    timers: [f64; GAMEPORT_AXES],
+    buttons: [bool; GAMEPORT_BUTTONS],
+    axis_map: Vec<AxisMap>,
+    button_map: Vec<ButtonMap>,
+}
+
+impl GamePort {
+    pub fn new(config: &GamePortConfig) -> Self {
+        let axis_map = config
+            .axis
+            .iter()
+            .filter(|a| a.axis < GAMEPORT_AXES)
+            .map(|a| AxisMap {
+                host: a.host,
+                axis: a.axis,
+                invert: a.invert,
+                deadzone: a.deadzone.clamp(0.0, 0.99),
+                curve: a.curve.unwrap_or(1.0).max(0.01),
+                calibration: a.calibration.clone().unwrap_or_default(),
+            })
+            .collect();
+
+        let button_map = config
+            .button
+            .iter()
+            .filter(|b| b.button < GAMEPORT_BUTTONS)
+            .map(|b| ButtonMap {
+                host:   b.host,
+                button: b.button,
+            })
+            .collect();
+
+        Self {
+            io_base: config.io_base.unwrap_or(GAMEPORT_IO_BASE),
+            position: [AxisCalibration::default().center; GAMEPORT_AXES],
+            timers: [0.0; GAMEPORT_AXES],
+            buttons: [false; GAMEPORT_BUTTONS],
+            axis_map,
+            button_map,
+        }
+    }
+
+    /// Update the position of every emulated axis mapped to the specified host axis.
+    pub fn set_host_axis(&mut self, host: GamepadAxis, value: f64) {
+        for map in self.axis_map.iter().filter(|m| m.host == host) {
+            self.position[map.axis] = map.position(value);
+        }
+    }
+
+    /// Update the state of every emulated button mapped to the specified host button.
+    pub fn set_host_button(&mut self, host: GamepadButton, pressed: bool) {
+        for map in self.button_map.iter().filter(|m| m.host == host) {
+            self.buttons[map.button] = pressed;
+        }
+    }
+
+    /// Set the position of an emulated axis directly, as a fraction of full resistance.
+    pub fn set_axis(&mut self, axis: usize, position: f64) {
+        if axis < GAMEPORT_AXES {
+            self.position[axis] = position.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        if button < GAMEPORT_BUTTONS {
+            self.buttons[button] = pressed;
+        }
+    }
+
+    pub fn axis_position(&self, axis: usize) -> f64 {
+        self.position[axis]
+    }
+
+    /// Return the one-shot period in microseconds for the current position of an axis.
+    pub fn axis_period(&self, axis: usize) -> f64 {
+        ONESHOT_BASE_US + ONESHOT_US_PER_OHM * self.position[axis].clamp(0.0, 1.0) * JOYSTICK_MAX_OHMS
+    }
+
+    pub fn run(&mut self, us: f64) {
+        for timer in self.timers.iter_mut() {
+            if *timer > 0.0 {
+                *timer = (*timer - us).max(0.0);
+            }
+        }
+    }
+
+    /// Return whether any one-shot is running. The port does not need to be run otherwise.
+    pub fn active(&self) -> bool {
+        self.timers.iter().any(|t| *t > 0.0)
+    }
+}
+
+/// The serializable state of the game port. The axis and button mappings are configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GamePortState {
+    pub position: [f64; GAMEPORT_AXES],
+    pub timers:   [f64; GAMEPORT_AXES],
+    pub buttons:  [bool; GAMEPORT_BUTTONS],
+}
+
+impl DeviceState for GamePort {
+    type State = GamePortState;
+
+    fn save_state(&self) -> GamePortState {
+        GamePortState {
+            position: self.position,
+            timers:   self.timers,
+            buttons:  self.buttons,
+        }
+    }
+
+    fn load_state(&mut self, state: GamePortState) -> Result<(), Error> {
+        self.position = state.position;
+        self.timers = state.timers;
+        self.buttons = state.buttons;
+        Ok(())
+    }
+}
+
+impl IoDevice for GamePort {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        let mut byte = 0;
+        for (i, timer) in self.timers.iter().enumerate() {
+            if *timer > 0.0 {
+                byte |= 1 << i;
+            }
+        }
+        for (i, pressed) in self.buttons.iter().enumerate() {
+            if !*pressed {
+                byte |= 0x10 << i;
+            }
+        }
+        byte
+    }
+
+    fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        // Any write fires all four one-shots.
+        for axis in 0..GAMEPORT_AXES {
+            self.timers[axis] = self.axis_period(axis);
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        vec![self.io_base]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> GamePortConfig {
+        toml::from_str(
+            r#"
+            [[axis]]
+            host = "LeftX"
+            axis = 0
+            deadzone = 0.2
+            calibration = { min = 0.1, center = 0.3, max = 0.9 }
+
+            [[axis]]
+            host = "LeftY"
+            axis = 1
+            invert = true
+
+            [[button]]
+            host = "South"
+            button = 1
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gameport_mapping() {
+        let mut gp = GamePort::new(&test_config());
+
+        // Calibration points are reached at center and full deflection.
+        gp.set_host_axis(GamepadAxis::LeftX, -1.0);
+        assert!((gp.axis_position(0) - 0.1).abs() < 1e-9);
+        gp.set_host_axis(GamepadAxis::LeftX, 0.1);
+        assert!((gp.axis_position(0) - 0.3).abs() < 1e-9);
+        gp.set_host_axis(GamepadAxis::LeftX, 1.0);
+        assert!((gp.axis_position(0) - 0.9).abs() < 1e-9);
+        // Halfway between the deadzone edge and full deflection.
+        gp.set_host_axis(GamepadAxis::LeftX, 0.6);
+        assert!((gp.axis_position(0) - 0.6).abs() < 1e-9);
+
+        gp.set_host_axis(GamepadAxis::LeftY, 1.0);
+        assert_eq!(gp.axis_position(1), 0.0);
+
+        gp.set_host_button(GamepadButton::South, true);
+        gp.write_u8(GAMEPORT_IO_BASE, 0, None, DeviceRunTimeUnit::Microseconds(0.0));
+        assert_eq!(gp.read_u8(GAMEPORT_IO_BASE, DeviceRunTimeUnit::Microseconds(0.0)), 0xDF);
+
+        // Axis 1 is at minimum resistance and expires first; axis 0 is at 90%.
+        gp.run(30.0);
+        assert_eq!(gp.read_u8(GAMEPORT_IO_BASE, DeviceRunTimeUnit::Microseconds(0.0)), 0xDD);
+        gp.run(gp.axis_period(0));
+        assert_eq!(gp.read_u8(GAMEPORT_IO_BASE, DeviceRunTimeUnit::Microseconds(0.0)), 0xD0);
+    }
+
+    #[test]
+    fn test_gameport_state_roundtrip() {
+        let mut gp = GamePort::new(&test_config());
+        gp.set_axis(0, 0.25);
+        gp.set_button(2, true);
+        gp.write_u8(GAMEPORT_IO_BASE, 0, None, DeviceRunTimeUnit::Microseconds(0.0));
+        gp.run(100.0);
+
+        let mut restored = GamePort::new(&test_config());
+        restored.load_state(gp.save_state()).unwrap();
+        assert_eq!(restored.dump_state(), gp.dump_state());
+        for _ in 0..4 {
+            assert_eq!(
+                restored.read_u8(GAMEPORT_IO_BASE, DeviceRunTimeUnit::Microseconds(0.0)),
+                gp.read_u8(GAMEPORT_IO_BASE, DeviceRunTimeUnit::Microseconds(0.0))
+            );
+            restored.run(500.0);
+            gp.run(500.0);
+        }
+    }
+}
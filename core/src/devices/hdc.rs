@@ -26,7 +26,14 @@
 
     devices::hdc.rs
 
-    Implements the IBM/Xebec 20MB Fixed Disk Adapter
+    Implements the IBM/Xebec 20MB Fixed Disk Adapter, and the mostly
+    compatible Western Digital WD1002-WX1 / DTC 5150 controllers.
+
+    The Xebec BIOS only supports the drive types selected by the card's DIP
+    switches. The WD and DTC BIOSes program the controller with the drive
+    geometry using Initialize Drive Characteristics, so any standard MFM
+    geometry can be used, and their low-level format routines rely on the
+    Format Drive and Format Track commands.
 
 */
 
@@ -36,12 +43,22 @@ use std::{collections::VecDeque, error::Error};
 
 use core::fmt::Display;
 
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
+    device_traits::devicestate::DeviceState,
     devices::dma,
+    tracelogger::TraceLogger,
 };
 //use crate::fdc::Operation;
-use crate::{bus::IoDevice, device_types::hdc::HardDiskFormat, vhd::VirtualHardDisk};
+use crate::{
+    bus::IoDevice,
+    device_types::hdc::{xt_hard_disk_formats, HardDiskFormat},
+    machine_types::HardDiskControllerType,
+    vhd::VirtualHardDisk,
+};
 
 // Public consts
 pub const HDC_IRQ: u8 = 0x05;
@@ -80,10 +97,16 @@ const ERR_SEEK_ERROR: u8 = 0b01_0101;
 const ERR_INVALID_COMMAND: u8 = 0b10_0000;
 const ERR_ILLEGAL_ACCESS: u8 = 0b10_0001;
 
+const MFM_SECTORS: u8 = 17; // Sectors per track of MFM drives
+const WD_MAX_CYLINDERS: u16 = 1024; // Maximum geometry that can be set with Initialize Drive Characteristics
+const WD_MAX_HEADS: u8 = 16;
+const FORMAT_FILL_BYTE: u8 = 0x00; // Data field contents written by Format commands
+
 const RESET_DELAY_US: f64 = 200_000.0; // 200ms
+const ACCESS_LOG_LEN: usize = 256; // Maximum number of access log entries retained
 
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum OperationError {
     NoError,
     NoReadySignal,
@@ -114,7 +137,7 @@ impl Display for ControllerError {
 }
 
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum State {
     Reset,
     WaitingForCommand,
@@ -126,7 +149,7 @@ pub enum State {
 }
 
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Command {
     None,
     TestDriveReady,
@@ -150,11 +173,35 @@ pub enum Command {
     WriteLongTrack,
 }
 
+/// The completion status of a logged disk access.
+#[derive(Copy, Clone, Debug)]
+pub enum HdcAccessStatus {
+    InProgress,
+    Complete,
+    Error(OperationError),
+}
+
+/// A sector-level command executed by the controller, as recorded by the access log.
+#[derive(Clone, Debug)]
+pub struct HdcAccessEntry {
+    /// Emulated time the command was received, in microseconds.
+    pub time_us: f64,
+    /// Time taken to complete the command, in microseconds. 0 while in progress.
+    pub duration_us: f64,
+    pub command: Command,
+    pub drive: usize,
+    pub c: u16,
+    pub h: u8,
+    pub s: u8,
+    pub count: u8,
+    pub status: HdcAccessStatus,
+}
+
 type CommandDispatchFn = fn(&mut HardDiskController, &mut BusInterface) -> Continuation;
 
 impl IoDevice for HardDiskController {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
-        match port {
+        match self.normalize_port(port) {
             HDC_DATA_REGISTER => self.handle_data_register_read(),
             HDC_STATUS_REGISTER => self.handle_status_register_read(),
             HDC_READ_DIP_REGISTER => self.handle_dip_register_read(),
@@ -166,7 +213,7 @@ impl IoDevice for HardDiskController {
     }
 
     fn write_u8(&mut self, port: u16, data: u8, bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
-        match port {
+        match self.normalize_port(port) {
             HDC_DATA_REGISTER => {
                 // Bus will always call us with Bus defined, so safe to unwrap
                 self.handle_data_register_write(data, bus.unwrap());
@@ -194,6 +241,9 @@ impl IoDevice for HardDiskController {
             HDC_CONTROLLER_SELECT,
             HDC_WRITE_MASK_REGISTER,
         ]
+        .into_iter()
+        .map(|port| port - HDC_DATA_REGISTER + self.io_base)
+        .collect()
     }
 }
 
@@ -247,7 +297,7 @@ impl HardDisk {
 }
 
 #[allow(dead_code)]
-#[derive(Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct OperationStatus {
     drive_select: usize,
     buffer_idx: usize,
@@ -277,6 +327,10 @@ pub struct DeviceControlBlock {
 
 #[allow(dead_code)]
 pub struct HardDiskController {
+    hdc_type: HardDiskControllerType,
+    io_base: u16,
+    irq: u8,
+    dma: usize,
     drives: [HardDisk; 2],
     drive_ct: usize,
     drive_select: usize,
@@ -291,6 +345,9 @@ pub struct HardDiskController {
     command: Command,
     command_fn: Option<CommandDispatchFn>,
     last_command: Command,
+    // Count of commands dispatched, and the most recent one, for observers that poll the controller.
+    dispatch_ct: u64,
+    last_dispatched: Command,
     command_byte_n: u32,
     command_result_pending: bool,
 
@@ -310,11 +367,20 @@ pub struct HardDiskController {
     dreq_active: bool,
 
     state_accumulator: f64,
+
+    access_log_enabled: bool,
+    access_log: VecDeque<HdcAccessEntry>,
+    access_pending: Option<HdcAccessEntry>,
+    access_trace: TraceLogger,
 }
 
 impl Default for HardDiskController {
     fn default() -> Self {
         Self {
+            hdc_type: HardDiskControllerType::IbmXebec,
+            io_base: HDC_DATA_REGISTER,
+            irq: HDC_IRQ,
+            dma: HDC_DMA,
             drives: [HardDisk::new(), HardDisk::new()],
             drive_ct: 1,
             drive_select: 0,
@@ -334,6 +400,8 @@ impl Default for HardDiskController {
             command: Command::None,
             command_fn: None,
             last_command: Command::None,
+            dispatch_ct: 0,
+            last_dispatched: Command::None,
             command_byte_n: 0,
             command_result_pending: false,
             data_register_in: VecDeque::new(),
@@ -349,17 +417,31 @@ impl Default for HardDiskController {
             dreq_active: false,
 
             state_accumulator: 0.0,
+
+            access_log_enabled: false,
+            access_log: VecDeque::new(),
+            access_pending: None,
+            access_trace: TraceLogger::None,
         }
     }
 }
 
 impl HardDiskController {
-    pub fn new(drive_ct: usize, drive_type_dip: u8) -> Self {
-        Self {
+    pub fn new(hdc_type: HardDiskControllerType, drive_ct: usize, drive_type_dip: u8) -> Self {
+        let mut hdc = Self {
+            hdc_type,
             drive_ct,
             drive_type_dip,
             ..Default::default()
+        };
+        if let HardDiskControllerType::Wd1002 = hdc_type {
+            hdc.supported_formats = xt_hard_disk_formats();
         }
+        hdc
+    }
+
+    pub fn hdc_type(&self) -> HardDiskControllerType {
+        self.hdc_type
     }
 
     pub fn reset(&mut self) {
@@ -380,12 +462,116 @@ impl HardDiskController {
         self.command = Command::None;
         self.command_fn = None;
         self.command_byte_n = 0;
+        self.access_pending = None;
+    }
+
+    /// Relocate the controller to a new IO base address, IRQ and DMA channel, as if the card's
+    /// jumpers had been changed. This should be done before the controller's ports are mapped.
+    pub fn set_resources(&mut self, io_base: u16, irq: u8, dma: usize) {
+        self.io_base = io_base;
+        self.irq = irq;
+        self.dma = dma;
+    }
+
+    /// Translate an IO address into the corresponding address at the default IO base.
+    fn normalize_port(&self, port: u16) -> u16 {
+        port.wrapping_sub(self.io_base).wrapping_add(HDC_DATA_REGISTER)
     }
 
     pub fn drive_ct(&self) -> usize {
         self.drive_ct
     }
 
+    /// Return the number of commands dispatched since the controller was created, and the most
+    /// recently dispatched command.
+    pub fn dispatched_commands(&self) -> (u64, Command) {
+        (self.dispatch_ct, self.last_dispatched)
+    }
+
+    /// Enable or disable the access log. Completed accesses are also written to `logger`.
+    pub fn set_access_log(&mut self, enabled: bool, logger: TraceLogger) {
+        self.access_trace.flush();
+        self.access_log_enabled = enabled;
+        self.access_trace = logger;
+        self.access_log.clear();
+        self.access_pending = None;
+    }
+
+    /// Return the retained access log entries, oldest first, including any access in progress.
+    pub fn access_log(&self) -> Vec<HdcAccessEntry> {
+        self.access_log
+            .iter()
+            .chain(self.access_pending.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Remove and return the completed access log entries, oldest first.
+    pub fn drain_access_log(&mut self) -> Vec<HdcAccessEntry> {
+        self.access_log.drain(..).collect()
+    }
+
+    /// Open an access log entry for a sector-level command whose DCB has just been received.
+    fn begin_access(&mut self, time_us: f64) {
+        if !self.access_log_enabled {
+            return;
+        }
+        match self.command {
+            Command::Read
+            | Command::Write
+            | Command::Seek
+            | Command::ReadyVerify
+            | Command::FormatDrive
+            | Command::FormatTrack
+            | Command::FormatBadTrack
+            | Command::ReadLongTrack
+            | Command::WriteLongTrack => {}
+            _ => return,
+        }
+
+        let dcb = self.read_dcb();
+        self.access_pending = Some(HdcAccessEntry {
+            time_us,
+            duration_us: 0.0,
+            command: self.command,
+            drive: dcb.drive_select,
+            c: dcb.c,
+            h: dcb.h,
+            s: dcb.s,
+            count: dcb.block_count,
+            status: HdcAccessStatus::InProgress,
+        });
+    }
+
+    /// Close the open access log entry with the controller's current error status.
+    fn end_access(&mut self, time_us: f64) {
+        if let Some(mut entry) = self.access_pending.take() {
+            entry.duration_us = time_us - entry.time_us;
+            entry.status = match (self.error_flag, self.last_error) {
+                (false, _) | (true, OperationError::NoError) => HdcAccessStatus::Complete,
+                (true, error) => HdcAccessStatus::Error(error),
+            };
+
+            self.access_trace.println(format!(
+                "[{:14.3}] {:?} drive:{} c:{} h:{} s:{} count:{} -> {:?} ({:.3}us)",
+                entry.time_us,
+                entry.command,
+                entry.drive,
+                entry.c,
+                entry.h,
+                entry.s,
+                entry.count,
+                entry.status,
+                entry.duration_us
+            ));
+
+            if self.access_log.len() == ACCESS_LOG_LEN {
+                self.access_log.pop_front();
+            }
+            self.access_log.push_back(entry);
+        }
+    }
+
     pub fn get_supported_formats(&self) -> Vec<HardDiskFormat> {
         self.supported_formats.clone()
     }
@@ -395,16 +581,26 @@ impl HardDiskController {
             return Err(ControllerError::InvalidDevice);
         }
 
-        // Check that the VHD geometry is in the list of supported formats
-        // (Currently there is only one supported format but that might change)
         let mut supported = false;
-        for format in &self.supported_formats {
-            if vhd.max_cylinders as u16 == format.max_cylinders
-                && vhd.max_heads as u8 == format.max_heads
-                && vhd.max_sectors as u8 == format.max_sectors
-            {
-                supported = true;
-                break;
+        match self.hdc_type {
+            HardDiskControllerType::IbmXebec => {
+                // Check that the VHD geometry is in the list of supported formats
+                // (Currently there is only one supported format but that might change)
+                for format in &self.supported_formats {
+                    if vhd.max_cylinders as u16 == format.max_cylinders
+                        && vhd.max_heads as u8 == format.max_heads
+                        && vhd.max_sectors as u8 == format.max_sectors
+                    {
+                        supported = true;
+                        break;
+                    }
+                }
+            }
+            HardDiskControllerType::Wd1002 => {
+                // The geometry is programmed by the BIOS, so any MFM drive within the controller's limits will do.
+                supported = vhd.max_cylinders <= WD_MAX_CYLINDERS as u32
+                    && vhd.max_heads <= WD_MAX_HEADS as u32
+                    && vhd.max_sectors == MFM_SECTORS as u32;
             }
         }
 
@@ -584,6 +780,7 @@ impl HardDiskController {
                     0b000_00100 => {
                         // Format drive
                         log::trace!("Received Format Drive Command");
+                        self.set_command(Command::FormatDrive, DBC_LEN, HardDiskController::command_format_drive);
                     }
                     0b000_00101 => {
                         // Read Verify
@@ -593,6 +790,7 @@ impl HardDiskController {
                     0b000_00110 => {
                         // Format Track
                         log::trace!("Received Format Track Command");
+                        self.set_command(Command::FormatTrack, DBC_LEN, HardDiskController::command_format_track);
                     }
                     0b000_00111 => {
                         // Format Bad Track
@@ -702,12 +900,17 @@ impl HardDiskController {
                             log::error!("No associated method for command: {:?}!", self.command)
                         }
                         Some(command_fn) => {
+                            self.dispatch_ct += 1;
+                            self.last_dispatched = self.command;
+                            self.begin_access(bus.elapsed_us());
                             result = command_fn(self, bus);
                         }
                     }
 
                     // Clear command if complete
                     if let Continuation::CommandComplete = result {
+                        self.end_access(bus.elapsed_us());
+
                         if let Command::RequestSense = self.command {
                             // Present Sense Bytes after Sense Status command
                             self.state = State::HaveSenseBytes
@@ -848,7 +1051,7 @@ impl HardDiskController {
         let dcb = self.read_dcb();
         self.data_register_in.clear();
 
-        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(HDC_DMA);
+        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(self.dma);
         log::trace!(
             "Command Read: drive: {} c: {} h: {} s: {}, xfer_size:{}",
             dcb.drive_select,
@@ -916,7 +1119,7 @@ impl HardDiskController {
         let dcb = self.read_dcb();
         self.data_register_in.clear();
 
-        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(HDC_DMA);
+        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(self.dma);
         log::trace!(
             "Command Write: drive: {} c: {} h: {} s: {} bc: {}, xfer_size:{}",
             dcb.drive_select,
@@ -1021,7 +1224,7 @@ impl HardDiskController {
     fn command_read_sector_buffer(&mut self, bus: &mut BusInterface) -> Continuation {
         // Don't care about DBC bytes
 
-        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(HDC_DMA);
+        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(self.dma);
         if xfer_size != SECTOR_SIZE {
             log::warn!("Command ReadSectorBuffer: DMA word count != sector size");
         }
@@ -1042,7 +1245,7 @@ impl HardDiskController {
     fn command_write_sector_buffer(&mut self, bus: &mut BusInterface) -> Continuation {
         // Don't care about DBC bytes
 
-        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(HDC_DMA);
+        let xfer_size = bus.dma_mut().as_mut().unwrap().get_dma_transfer_size(self.dma);
         if xfer_size != SECTOR_SIZE {
             log::warn!("Command WriteSectorBuffer: DMA word count != sector size");
         }
@@ -1084,6 +1287,22 @@ impl HardDiskController {
             max_cylinders
         );
 
+        // The WD and DTC BIOSes set the geometry of the drive with this command rather than relying on a fixed
+        // drive type, so it had better match the VHD.
+        if let HardDiskControllerType::Wd1002 = self.hdc_type {
+            let drive = &self.drives[dcb.drive_select];
+            if drive.vhd.is_some() && (max_cylinders != drive.max_cylinders || max_heads != drive.max_heads) {
+                log::warn!(
+                    "Drive {} initialized with geometry c:{} h:{} but VHD geometry is c:{} h:{}",
+                    dcb.drive_select,
+                    max_cylinders,
+                    max_heads,
+                    drive.max_cylinders,
+                    drive.max_heads
+                );
+            }
+        }
+
         // HDC BIOS seems to indicate it expects this command to succeed even on an unattached drive. After all
         // there is no jumper setting for "No Drive"
         log::trace!(
@@ -1096,6 +1315,91 @@ impl HardDiskController {
         Continuation::CommandComplete
     }
 
+    /// Perform the Format Drive Command.
+    /// Formats every track from the cylinder and head specified in the DCB to the end of the drive.
+    fn command_format_drive(&mut self, _bus: &mut BusInterface) -> Continuation {
+        let dcb = self.read_dcb();
+        self.data_register_in.clear();
+
+        log::trace!(
+            "Command Format Drive: drive: {} c: {} h: {} interleave: {}",
+            dcb.drive_select,
+            dcb.c,
+            dcb.h,
+            dcb.interleave
+        );
+
+        let drive = &self.drives[dcb.drive_select];
+        let (max_cylinders, max_heads) = (drive.max_cylinders, drive.max_heads);
+
+        let mut error = self.check_format(dcb.drive_select, dcb.c, dcb.h);
+        let (mut c, mut h) = (dcb.c, dcb.h);
+        while matches!(error, OperationError::NoError) && c < max_cylinders {
+            error = self.format_track(dcb.drive_select, c, h);
+            h += 1;
+            if h == max_heads {
+                h = 0;
+                c += 1;
+            }
+        }
+
+        self.set_error(error, dcb.drive_select);
+        self.send_interrupt = true;
+        Continuation::CommandComplete
+    }
+
+    /// Perform the Format Track Command.
+    fn command_format_track(&mut self, _bus: &mut BusInterface) -> Continuation {
+        let dcb = self.read_dcb();
+        self.data_register_in.clear();
+
+        log::trace!(
+            "Command Format Track: drive: {} c: {} h: {} interleave: {}",
+            dcb.drive_select,
+            dcb.c,
+            dcb.h,
+            dcb.interleave
+        );
+
+        let mut error = self.check_format(dcb.drive_select, dcb.c, dcb.h);
+        if let OperationError::NoError = error {
+            error = self.format_track(dcb.drive_select, dcb.c, dcb.h);
+        }
+
+        self.set_error(error, dcb.drive_select);
+        self.send_interrupt = true;
+        Continuation::CommandComplete
+    }
+
+    /// Check that a Format command addresses a track on a present drive.
+    fn check_format(&mut self, drive_select: usize, c: u16, h: u8) -> OperationError {
+        if !self.drive_present(drive_select) {
+            return OperationError::NoReadySignal;
+        }
+        let drive = &self.drives[drive_select];
+        if c >= drive.max_cylinders || h >= drive.max_heads {
+            return OperationError::IllegalAccess;
+        }
+        OperationError::NoError
+    }
+
+    /// Fill every sector of the specified track with the format fill byte.
+    fn format_track(&mut self, drive_select: usize, c: u16, h: u8) -> OperationError {
+        let drive = &mut self.drives[drive_select];
+        let max_sectors = drive.max_sectors;
+        let fill = [FORMAT_FILL_BYTE; SECTOR_SIZE];
+
+        if let Some(vhd) = &mut drive.vhd {
+            for s in 0..max_sectors {
+                if let Err(e) = vhd.write_sector(&fill, c, h, s) {
+                    log::error!("VHD write_sector() failed: c:{} h:{} s:{} Error: {}", c, h, s, e);
+                    return OperationError::IllegalAccess;
+                }
+            }
+        }
+        OperationError::NoError
+    }
+
     /// Perform the Test Drive Ready Command.
     fn command_test_drive_ready(&mut self, _bus: &mut BusInterface) -> Continuation {
         // Get the drive number from DCB
@@ -1173,12 +1477,13 @@ impl HardDiskController {
     }
 
     /// End a Command that utilized DMA service.
-    fn end_dma_command(&mut self, _drive: u32, error: bool) {
+    fn end_dma_command(&mut self, _drive: u32, error: bool, time_us: f64) {
         self.clear_dreq = true;
         self.operation_status.dma_byte_count = 0;
         self.operation_status.dma_bytes_left = 0;
 
         self.error_flag = error;
+        self.end_access(time_us);
         self.send_interrupt = true;
         log::trace!("End of DMA command. Changing state to HaveCommandStatus");
         self.state = State::HaveCommandStatus;
@@ -1187,17 +1492,17 @@ impl HardDiskController {
     /// Process the Read Sector Buffer operation.
     /// This operation continues until the DMA transfer is complete.
     fn opearation_read_sector_buffer(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
-        if self.dreq_active && dma.read_dma_acknowledge(HDC_DMA) {
+        if self.dreq_active && dma.read_dma_acknowledge(self.dma) {
             if self.operation_status.dma_bytes_left > 0 {
                 let byte = self.drives[self.drive_select].sector_buf[self.operation_status.buffer_idx & 0x1FF];
                 self.operation_status.buffer_idx += 1;
                 // Bytes left to transfer
-                dma.do_dma_write_u8(bus, HDC_DMA, byte);
+                dma.do_dma_write_u8(bus, self.dma, byte);
                 self.operation_status.dma_byte_count += 1;
                 self.operation_status.dma_bytes_left -= 1;
 
                 // See if we are done based on DMA controller
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if tc {
                     log::trace!("DMA terminal count triggered end of ReadSectorBuffer command.");
                     if self.operation_status.dma_bytes_left != 0 {
@@ -1209,18 +1514,18 @@ impl HardDiskController {
                     }
 
                     log::trace!("Completed ReadSectorBuffer command.");
-                    self.end_dma_command(0, false);
+                    self.end_dma_command(0, false, bus.elapsed_us());
                 }
             }
             else {
                 // No more bytes left to transfer. Finalize operation
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if !tc {
                     log::warn!("ReadSectorBuffer complete without DMA terminal count.");
                 }
 
                 log::trace!("Completed ReadSectorBuffer command.");
-                self.end_dma_command(0, false);
+                self.end_dma_command(0, false, bus.elapsed_us());
             }
         }
         else if !self.dreq_active {
@@ -1231,15 +1536,15 @@ impl HardDiskController {
     /// Process the Write Sector Buffer operation.
     /// This operation continues until the DMA transfer is complete.
     fn opearation_write_sector_buffer(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
-        if self.dreq_active && dma.read_dma_acknowledge(HDC_DMA) {
+        if self.dreq_active && dma.read_dma_acknowledge(self.dma) {
             if self.operation_status.dma_bytes_left > 0 {
                 // Bytes left to transfer
-                let _byte = dma.do_dma_read_u8(bus, HDC_DMA);
+                let _byte = dma.do_dma_read_u8(bus, self.dma);
                 self.operation_status.dma_byte_count += 1;
                 self.operation_status.dma_bytes_left -= 1;
 
                 // See if we are done based on DMA controller
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if tc {
                     log::trace!("DMA terminal count triggered end of WriteSectorBuffer command.");
                     if self.operation_status.dma_bytes_left != 0 {
@@ -1251,18 +1556,18 @@ impl HardDiskController {
                     }
 
                     log::trace!("Completed WriteSectorBuffer command.");
-                    self.end_dma_command(0, false);
+                    self.end_dma_command(0, false, bus.elapsed_us());
                 }
             }
             else {
                 // No more bytes left to transfer. Finalize operation
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if !tc {
                     log::warn!("WriteSectorBuffer complete without DMA terminal count.");
                 }
 
                 log::trace!("Completed WriteSectorBuffer command.");
-                self.end_dma_command(0, false);
+                self.end_dma_command(0, false, bus.elapsed_us());
             }
         }
         else if !self.dreq_active {
@@ -1273,12 +1578,12 @@ impl HardDiskController {
     /// Process the Read Sector operation.
     /// This operation continues until the DMA transfer is complete.
     fn operation_read_sector(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
-        if self.dreq_active && dma.read_dma_acknowledge(HDC_DMA) {
+        if self.dreq_active && dma.read_dma_acknowledge(self.dma) {
             if self.operation_status.dma_bytes_left > 0 {
                 // Bytes left to transfer
 
                 let byte = self.drives[self.drive_select].sector_buf[self.operation_status.buffer_idx];
-                dma.do_dma_write_u8(bus, HDC_DMA, byte);
+                dma.do_dma_write_u8(bus, self.dma, byte);
                 self.operation_status.buffer_idx += 1;
                 self.operation_status.dma_byte_count += 1;
                 self.operation_status.dma_bytes_left -= 1;
@@ -1321,7 +1626,7 @@ impl HardDiskController {
                 }
 
                 // See if we are done based on DMA controller
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if tc {
                     log::trace!("DMA terminal count triggered end of Read command.");
                     if self.operation_status.dma_bytes_left != 0 {
@@ -1333,18 +1638,18 @@ impl HardDiskController {
                     }
 
                     log::trace!("Completed Read Command");
-                    self.end_dma_command(0, false);
+                    self.end_dma_command(0, false, bus.elapsed_us());
                 }
             }
             else {
                 // No more bytes left to transfer. Finalize operation
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if !tc {
                     log::warn!("Command Read complete without DMA terminal count.");
                 }
 
                 log::trace!("Completed Read Command");
-                self.end_dma_command(0, false);
+                self.end_dma_command(0, false, bus.elapsed_us());
             }
         }
         else if !self.dreq_active {
@@ -1353,11 +1658,11 @@ impl HardDiskController {
     }
 
     fn operation_write_sector(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
-        if self.dreq_active && dma.read_dma_acknowledge(HDC_DMA) {
+        if self.dreq_active && dma.read_dma_acknowledge(self.dma) {
             if self.operation_status.dma_bytes_left > 0 {
                 // Bytes left to transfer
 
-                let byte = dma.do_dma_read_u8(bus, HDC_DMA);
+                let byte = dma.do_dma_read_u8(bus, self.dma);
                 self.drives[self.drive_select].sector_buf[self.operation_status.buffer_idx] = byte;
                 self.operation_status.buffer_idx += 1;
                 self.operation_status.dma_byte_count += 1;
@@ -1407,7 +1712,7 @@ impl HardDiskController {
                 }
 
                 // See if we are done based on DMA controller
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if tc {
                     log::trace!("DMA terminal count triggered end of Write command.");
                     if self.operation_status.dma_bytes_left != 0 {
@@ -1418,17 +1723,17 @@ impl HardDiskController {
                         );
                     }
 
-                    self.end_dma_command(0, false);
+                    self.end_dma_command(0, false, bus.elapsed_us());
                 }
             }
             else {
                 // No more bytes left to transfer. Finalize operation
-                let tc = dma.check_terminal_count(HDC_DMA);
+                let tc = dma.check_terminal_count(self.dma);
                 if !tc {
                     log::warn!("Command Write complete without DMA terminal count.");
                 }
 
-                self.end_dma_command(0, false);
+                self.end_dma_command(0, false, bus.elapsed_us());
             }
         }
         else if !self.dreq_active {
@@ -1436,13 +1741,26 @@ impl HardDiskController {
         }
     }
 
+    /// Return the time in microseconds until the HDC next needs to be run, or None if it is idle.
+    pub fn next_deadline(&self) -> Option<f64> {
+        if self.send_interrupt || self.clear_interrupt || self.send_dreq || self.clear_dreq {
+            return Some(0.0);
+        }
+
+        match self.state {
+            State::Reset => Some((RESET_DELAY_US - self.state_accumulator).max(0.0)),
+            State::ExecutingCommand => Some(0.0),
+            _ => None,
+        }
+    }
+
     /// Run the HDC device.
     pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64) {
         // Handle interrupts
         if self.send_interrupt {
             if self.irq_enabled {
                 //log::trace!(">>> Firing HDC IRQ 5");
-                bus.pic_mut().as_mut().unwrap().request_interrupt(HDC_IRQ);
+                bus.pic_mut().as_mut().unwrap().request_interrupt(self.irq);
                 self.send_interrupt = false;
                 self.interrupt_active = true;
             }
@@ -1454,19 +1772,19 @@ impl HardDiskController {
         }
 
         if self.clear_interrupt {
-            bus.pic_mut().as_mut().unwrap().clear_interrupt(HDC_IRQ);
+            bus.pic_mut().as_mut().unwrap().clear_interrupt(self.irq);
             self.clear_interrupt = false;
             self.interrupt_active = false;
         }
 
         if self.send_dreq {
-            dma.request_service(HDC_DMA);
+            dma.request_service(self.dma);
             self.send_dreq = false;
             self.dreq_active = true;
         }
 
         if self.clear_dreq {
-            dma.clear_service(HDC_DMA);
+            dma.clear_service(self.dma);
             self.clear_dreq = false;
             self.dreq_active = false;
         }
@@ -1507,3 +1825,257 @@ impl HardDiskController {
         }
     }
 }
+
+/// The serializable state of a hard disk attached to the controller. The drive geometry follows
+/// the attached VHD, which is not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HardDiskState {
+    pub cylinder: u16,
+    pub head: u8,
+    pub sector: u8,
+    pub sector_buf: Vec<u8>,
+}
+
+/// The serializable state of the hard disk controller and its drives. The controller type, drive
+/// type DIP switches and resources are configuration, and the access log is diagnostic, so none
+/// of these are included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HardDiskControllerState {
+    pub drives: Vec<HardDiskState>,
+    pub drive_select: usize,
+    pub state: State,
+    pub last_error: OperationError,
+    pub last_error_drive: usize,
+    pub error_flag: bool,
+    pub receiving_dcb: bool,
+    pub command: Command,
+    pub last_command: Command,
+    pub command_byte_n: u32,
+    pub command_result_pending: bool,
+    pub data_register_in: Vec<u8>,
+    pub data_register_out: Vec<u8>,
+    pub operation_status: OperationStatus,
+    pub dma_enabled: bool,
+    pub irq_enabled: bool,
+    pub send_interrupt: bool,
+    pub clear_interrupt: bool,
+    pub interrupt_active: bool,
+    pub send_dreq: bool,
+    pub clear_dreq: bool,
+    pub dreq_active: bool,
+    pub state_accumulator: f64,
+}
+
+impl DeviceState for HardDiskController {
+    type State = HardDiskControllerState;
+
+    fn save_state(&self) -> HardDiskControllerState {
+        HardDiskControllerState {
+            drives: self.drives[..self.drive_ct]
+                .iter()
+                .map(|d| HardDiskState {
+                    cylinder: d.cylinder,
+                    head: d.head,
+                    sector: d.sector,
+                    sector_buf: d.sector_buf.clone(),
+                })
+                .collect(),
+            drive_select: self.drive_select,
+            state: self.state,
+            last_error: self.last_error,
+            last_error_drive: self.last_error_drive,
+            error_flag: self.error_flag,
+            receiving_dcb: self.receiving_dcb,
+            command: self.command,
+            last_command: self.last_command,
+            command_byte_n: self.command_byte_n,
+            command_result_pending: self.command_result_pending,
+            data_register_in: self.data_register_in.iter().copied().collect(),
+            data_register_out: self.data_register_out.iter().copied().collect(),
+            operation_status: self.operation_status.clone(),
+            dma_enabled: self.dma_enabled,
+            irq_enabled: self.irq_enabled,
+            send_interrupt: self.send_interrupt,
+            clear_interrupt: self.clear_interrupt,
+            interrupt_active: self.interrupt_active,
+            send_dreq: self.send_dreq,
+            clear_dreq: self.clear_dreq,
+            dreq_active: self.dreq_active,
+            state_accumulator: self.state_accumulator,
+        }
+    }
+
+    fn load_state(&mut self, state: HardDiskControllerState) -> Result<(), anyhow::Error> {
+        // The handler for a partially received command can't be recovered from its state.
+        if let State::ReceivingCommand = state.state {
+            bail!("Can't restore HDC state while a command is being received");
+        }
+        if let State::ExecutingCommand = state.state {
+            if !matches!(
+                state.command,
+                Command::Read | Command::Write | Command::ReadSectorBuffer | Command::WriteSectorBuffer
+            ) {
+                bail!(
+                    "HDC state is executing a command with no operation: {:?}",
+                    state.command
+                );
+            }
+        }
+        if state.drives.len() != self.drive_ct {
+            bail!(
+                "HDC state has {} drives, expected {}",
+                state.drives.len(),
+                self.drive_ct
+            );
+        }
+        if state.drive_select >= self.drive_ct || state.operation_status.drive_select >= self.drive_ct {
+            bail!("HDC state has invalid drive select: {}", state.drive_select);
+        }
+        if state.drives.iter().any(|d| d.sector_buf.len() != SECTOR_SIZE) {
+            bail!("HDC state has an invalid sector buffer");
+        }
+
+        for (drive, d) in self.drives.iter_mut().zip(state.drives) {
+            drive.cylinder = d.cylinder;
+            drive.head = d.head;
+            drive.sector = d.sector;
+            drive.sector_buf = d.sector_buf;
+        }
+        self.drive_select = state.drive_select;
+        self.state = state.state;
+        self.last_error = state.last_error;
+        self.last_error_drive = state.last_error_drive;
+        self.error_flag = state.error_flag;
+        self.receiving_dcb = state.receiving_dcb;
+        self.command = state.command;
+        self.command_fn = None;
+        self.last_command = state.last_command;
+        self.command_byte_n = state.command_byte_n;
+        self.command_result_pending = state.command_result_pending;
+        self.data_register_in = state.data_register_in.into();
+        self.data_register_out = state.data_register_out.into();
+        self.operation_status = state.operation_status;
+        self.dma_enabled = state.dma_enabled;
+        self.irq_enabled = state.irq_enabled;
+        self.send_interrupt = state.send_interrupt;
+        self.clear_interrupt = state.clear_interrupt;
+        self.interrupt_active = state.interrupt_active;
+        self.send_dreq = state.send_dreq;
+        self.clear_dreq = state.clear_dreq;
+        self.dreq_active = state.dreq_active;
+        self.state_accumulator = state.state_accumulator;
+        self.access_pending = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hdc_state_roundtrip() {
+        let mut hdc = HardDiskController::new(HardDiskControllerType::IbmXebec, 1, DRIVE_TYPE2_DIP);
+        hdc.state = State::HaveCommandStatus;
+        hdc.last_command = Command::Seek;
+        hdc.drives[0].cylinder = 100;
+        hdc.drives[0].sector_buf[0] = 0xAA;
+        hdc.data_register_out.push_back(0x20);
+
+        let json = hdc.serialize_state().unwrap();
+
+        let mut restored = HardDiskController::new(HardDiskControllerType::IbmXebec, 1, DRIVE_TYPE2_DIP);
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), hdc.dump_state());
+        assert_eq!(restored.drives[0].cylinder, 100);
+        assert_eq!(restored.drives[0].sector_buf[0], 0xAA);
+
+        // A partially received command can't be restored.
+        let mut state = hdc.save_state();
+        state.state = State::ReceivingCommand;
+        assert!(restored.load_state(state).is_err());
+
+        // Nor can a state for a different number of drives.
+        let mut state = hdc.save_state();
+        state.drives.push(state.drives[0].clone());
+        assert!(restored.load_state(state).is_err());
+    }
+
+    #[test]
+    fn test_hdc_access_log() {
+        let mut hdc = HardDiskController::new(HardDiskControllerType::IbmXebec, 1, DRIVE_TYPE2_DIP);
+
+        // Accesses aren't recorded until the log is enabled.
+        hdc.command = Command::Read;
+        hdc.data_register_in.extend([0x21, 0x45, 0x10, 0x04, 0x05]);
+        hdc.begin_access(0.0);
+        hdc.end_access(1.0);
+        assert!(hdc.access_log().is_empty());
+
+        hdc.set_access_log(true, TraceLogger::None);
+        hdc.begin_access(10.0);
+        assert!(matches!(hdc.access_log()[0].status, HdcAccessStatus::InProgress));
+        hdc.end_access(25.0);
+
+        // Drive 1, head 1, cylinder 0x110, sector 5, 4 blocks.
+        let log = hdc.drain_access_log();
+        assert_eq!(log.len(), 1);
+        assert_eq!(
+            (log[0].drive, log[0].c, log[0].h, log[0].s, log[0].count),
+            (1, 0x110, 1, 5, 4)
+        );
+        assert_eq!(log[0].duration_us, 15.0);
+        assert!(matches!(log[0].status, HdcAccessStatus::Complete));
+
+        // Commands that don't address sectors aren't logged, and errors are reported.
+        hdc.command = Command::RequestSense;
+        hdc.begin_access(30.0);
+        hdc.end_access(31.0);
+        hdc.command = Command::Seek;
+        hdc.begin_access(40.0);
+        hdc.set_error(OperationError::NoReadySignal, 1);
+        hdc.end_access(41.0);
+
+        let log = hdc.drain_access_log();
+        assert_eq!(log.len(), 1);
+        assert!(matches!(
+            log[0].status,
+            HdcAccessStatus::Error(OperationError::NoReadySignal)
+        ));
+    }
+
+    #[test]
+    fn test_hdc_wd1002_geometry_and_format() {
+        let path = std::env::temp_dir().join(format!("martypc_hdc_test_{}.vhd", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        crate::vhd::create_vhd(path.clone().into_os_string(), 20, 2, 17).unwrap();
+        let open_vhd = || {
+            let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+            VirtualHardDisk::from_file(file).unwrap()
+        };
+
+        // The Xebec only supports the drive types it has switches for.
+        let mut xebec = HardDiskController::new(HardDiskControllerType::IbmXebec, 1, DRIVE_TYPE2_DIP);
+        assert!(xebec.set_vhd(0, open_vhd()).is_err());
+
+        // The WD accepts any MFM geometry its BIOS can program.
+        let mut hdc = HardDiskController::new(HardDiskControllerType::Wd1002, 1, DRIVE_TYPE2_DIP);
+        assert_eq!(hdc.get_supported_formats().len(), 4);
+        hdc.set_vhd(0, open_vhd()).unwrap();
+
+        let mut buf = vec![0x55; SECTOR_SIZE];
+        let vhd = hdc.drives[0].vhd.as_mut().unwrap();
+        vhd.write_sector(&buf, 19, 1, 16).unwrap();
+
+        assert!(matches!(hdc.check_format(0, 20, 0), OperationError::IllegalAccess));
+        assert!(matches!(hdc.check_format(1, 0, 0), OperationError::NoReadySignal));
+        assert!(matches!(hdc.format_track(0, 19, 1), OperationError::NoError));
+
+        let vhd = hdc.drives[0].vhd.as_mut().unwrap();
+        vhd.read_sector(&mut buf, 19, 1, 16).unwrap();
+        assert!(buf.iter().all(|&b| b == FORMAT_FILL_BYTE));
+
+        drop(hdc);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -40,10 +40,10 @@ use std::{
 };
 use strum::IntoEnumIterator;
 
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use toml;
 
-use crate::{keys::MartyKey, machine::KeybufferEntry};
+use crate::{device_traits::devicestate::DeviceState, keys::MartyKey, machine::KeybufferEntry};
 
 // Define the various types of keyboard we can emulate.
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
@@ -65,6 +65,264 @@ impl FromStr for KeyboardType {
         }
     }
 }
+/// Scancode sets a keyboard can produce. The Model F only produces Set 1.
+/// Internally, all keys and translations are defined in terms of Set 1 and converted to the
+/// active set when sent.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ScancodeSet {
+    #[default]
+    Set1,
+    Set2,
+    Set3,
+}
+
+impl ScancodeSet {
+    /// The value reported by the keyboard in response to a set query (F0 00).
+    pub fn id(&self) -> u8 {
+        match self {
+            ScancodeSet::Set1 => 0x01,
+            ScancodeSet::Set2 => 0x02,
+            ScancodeSet::Set3 => 0x03,
+        }
+    }
+}
+
+// Keyboard commands.
+pub const KB_CMD_SET_LEDS: u8 = 0xED;
+pub const KB_CMD_ECHO: u8 = 0xEE;
+pub const KB_CMD_SCANCODE_SET: u8 = 0xF0;
+pub const KB_CMD_IDENTIFY: u8 = 0xF2;
+pub const KB_CMD_TYPEMATIC: u8 = 0xF3;
+pub const KB_CMD_ENABLE: u8 = 0xF4;
+pub const KB_CMD_DEFAULT_DISABLE: u8 = 0xF5;
+pub const KB_CMD_SET_DEFAULT: u8 = 0xF6;
+pub const KB_CMD_RESEND: u8 = 0xFE;
+pub const KB_CMD_RESET: u8 = 0xFF;
+
+// Keyboard responses.
+pub const KB_RESP_BAT_OK: u8 = 0xAA;
+pub const KB_RESP_ECHO: u8 = 0xEE;
+pub const KB_RESP_ACK: u8 = 0xFA;
+pub const KB_RESP_RESEND: u8 = 0xFE;
+pub const KB_RESP_OVERFLOW: u8 = 0xFF;
+
+// Scancode set 2 and 3 break prefix.
+pub const KB_BREAK_PREFIX: u8 = 0xF0;
+// Scancode set 1 and 2 prefix for the extended keys of the 101-key keyboard.
+pub const KB_EXTENDED_PREFIX: u8 = 0xE0;
+// Scancode set 1 and 2 prefix of the Pause key sequence.
+pub const KB_PAUSE_PREFIX: u8 = 0xE1;
+
+/// The Set 1 make sequence of the Pause key, which has no break code.
+pub const SET1_PAUSE: [u8; 6] = [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5];
+const SET2_PAUSE: [u8; 8] = [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77];
+const SET3_PAUSE: u8 = 0x62;
+
+const MODEL_M_BUFFER_SIZE: usize = 16;
+
+// Default typematic parameters, as set by the Set Default command.
+const DEFAULT_TYPEMATIC_DELAY: f64 = 500.0;
+const DEFAULT_TYPEMATIC_RATE: f64 = 1000.0 / 10.9;
+
+/// Keyboard lock status indicators.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyboardLeds {
+    pub scroll_lock: bool,
+    pub num_lock:    bool,
+    pub caps_lock:   bool,
+}
+
+impl KeyboardLeds {
+    /// Decode the parameter byte of the Set LEDs (0xED) command.
+    pub fn from_command_byte(byte: u8) -> Self {
+        Self {
+            scroll_lock: byte & 0x01 != 0,
+            num_lock:    byte & 0x02 != 0,
+            caps_lock:   byte & 0x04 != 0,
+        }
+    }
+
+    /// Decode the shift flags byte in the BIOS data area at 0040:0017.
+    pub fn from_bios_flags(byte: u8) -> Self {
+        Self {
+            scroll_lock: byte & 0x10 != 0,
+            num_lock:    byte & 0x20 != 0,
+            caps_lock:   byte & 0x40 != 0,
+        }
+    }
+}
+
+/// A snapshot of guest-controlled keyboard state, so that a frontend can mirror the lock
+/// indicators and typematic settings of the emulated keyboard.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyboardState {
+    pub leds: KeyboardLeds,
+    pub typematic: bool,
+    pub typematic_delay: f64,
+    pub typematic_rate: f64,
+    pub scancode_set: ScancodeSet,
+    pub enabled: bool,
+}
+
+/// The 8042 keyboard controller's translation table, converting Set 2 codes into Set 1 codes.
+/// This is applied to every byte received from the keyboard while translation is enabled,
+/// including command responses.
+#[rustfmt::skip]
+pub const KBC_TRANSLATION_TABLE: [u8; 128] = [
+    0xFF, 0x43, 0x41, 0x3F, 0x3D, 0x3B, 0x3C, 0x58, 0x64, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29, 0x59,
+    0x65, 0x38, 0x2A, 0x70, 0x1D, 0x10, 0x02, 0x5A, 0x66, 0x71, 0x2C, 0x1F, 0x1E, 0x11, 0x03, 0x5B,
+    0x67, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x5C, 0x68, 0x39, 0x2F, 0x21, 0x14, 0x13, 0x06, 0x5D,
+    0x69, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x5E, 0x6A, 0x72, 0x32, 0x24, 0x16, 0x08, 0x09, 0x5F,
+    0x6B, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x60, 0x6C, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0C, 0x61,
+    0x6D, 0x73, 0x28, 0x74, 0x1A, 0x0D, 0x62, 0x6E, 0x3A, 0x36, 0x1C, 0x1B, 0x75, 0x2B, 0x63, 0x76,
+    0x55, 0x56, 0x77, 0x78, 0x79, 0x7A, 0x0E, 0x7B, 0x7C, 0x4F, 0x7D, 0x4B, 0x47, 0x7E, 0x7F, 0x6F,
+    0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45, 0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x54,
+];
+
+/// Set 1 to Set 2 conversion for the base (non-extended) keys, the inverse of the 8042
+/// translation table.
+const SET1_TO_SET2_TABLE: [u8; 128] = {
+    let mut table = [0u8; 128];
+    let mut i = 0;
+    while i < 128 {
+        let set1 = KBC_TRANSLATION_TABLE[i] as usize;
+        if set1 < 128 && table[set1] == 0 {
+            table[set1] = i as u8;
+        }
+        i += 1;
+    }
+    // F7 is the only base key with a Set 2 code above 0x7F.
+    table[0x41] = 0x83;
+    // Alt-SysRq
+    table[0x54] = 0x84;
+    table
+};
+
+/// Convert a Set 1 make code for a base key into its Set 3 make code. Set 3 matches Set 2
+/// except for the keys below.
+const fn set1_to_set3(set1: u8) -> u8 {
+    match set1 {
+        0x01 => 0x08, // Escape
+        0x3B => 0x07, // F1
+        0x3C => 0x0F, // F2
+        0x3D => 0x17, // F3
+        0x3E => 0x1F, // F4
+        0x3F => 0x27, // F5
+        0x40 => 0x2F, // F6
+        0x41 => 0x37, // F7
+        0x42 => 0x3F, // F8
+        0x43 => 0x47, // F9
+        0x44 => 0x4F, // F10
+        0x57 => 0x56, // F11
+        0x58 => 0x5E, // F12
+        0x1D => 0x11, // Left Control
+        0x38 => 0x19, // Left Alt
+        0x3A => 0x14, // Caps Lock
+        0x45 => 0x76, // Num Lock
+        0x46 => 0x5F, // Scroll Lock
+        0x37 => 0x7E, // Keypad *
+        0x4A => 0x84, // Keypad -
+        0x4E => 0x7C, // Keypad +
+        0x2B => 0x5C, // Backslash
+        0x56 => 0x13, // 102nd key
+        0x54 => 0x57, // SysRq
+        _ => SET1_TO_SET2_TABLE[(set1 & 0x7F) as usize],
+    }
+}
+
+/// Convert a Set 1 make code for an extended (E0-prefixed) key into its Set 3 make code.
+/// Set 3 has no prefixes, so each extended key has a code of its own.
+const fn set1_extended_to_set3(set1: u8) -> u8 {
+    match set1 {
+        0x1D => 0x58, // Right Control
+        0x38 => 0x39, // Right Alt
+        0x52 => 0x67, // Insert
+        0x53 => 0x64, // Delete
+        0x47 => 0x6E, // Home
+        0x4F => 0x65, // End
+        0x49 => 0x6F, // Page Up
+        0x51 => 0x6D, // Page Down
+        0x48 => 0x63, // Up
+        0x50 => 0x60, // Down
+        0x4B => 0x61, // Left
+        0x4D => 0x6A, // Right
+        0x35 => 0x77, // Keypad /
+        0x1C => 0x79, // Keypad Enter
+        0x37 => 0x57, // Print Screen
+        _ => set1_to_set3(set1),
+    }
+}
+
+/// A single key within a sequence of Set 1 make codes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Set1Key {
+    Base(u8),
+    Extended(u8),
+    Pause,
+}
+
+/// Split a sequence of Set 1 make codes into keys, consuming the E0 and E1 prefixes.
+fn set1_keys(set1_codes: &[u8]) -> Vec<Set1Key> {
+    let mut keys = Vec::with_capacity(set1_codes.len());
+    let mut i = 0;
+    while i < set1_codes.len() {
+        if set1_codes[i..].starts_with(&SET1_PAUSE) {
+            keys.push(Set1Key::Pause);
+            i += SET1_PAUSE.len();
+        }
+        else if set1_codes[i] == KB_EXTENDED_PREFIX && i + 1 < set1_codes.len() {
+            keys.push(Set1Key::Extended(set1_codes[i + 1]));
+            i += 2;
+        }
+        else {
+            keys.push(Set1Key::Base(set1_codes[i]));
+            i += 1;
+        }
+    }
+    keys
+}
+
+/// Emulates the 8042 keyboard controller's Set 2 to Set 1 translation. The controller
+/// is stateful as Set 2 break codes are two bytes.
+#[derive(Clone, Debug, Default)]
+pub struct KbcTranslator {
+    break_pending: bool,
+}
+
+impl KbcTranslator {
+    /// Translate a byte received from the keyboard. Returns None if the byte was
+    /// consumed (a break prefix).
+    pub fn translate(&mut self, byte: u8) -> Option<u8> {
+        if byte == KB_BREAK_PREFIX {
+            self.break_pending = true;
+            return None;
+        }
+        if byte == KB_EXTENDED_PREFIX || byte == KB_PAUSE_PREFIX {
+            // Prefixes are the same in both sets, and precede any break prefix.
+            return Some(byte);
+        }
+
+        let translated = match byte {
+            0x00..=0x7F => KBC_TRANSLATION_TABLE[byte as usize],
+            0x83 => 0x41, // F7
+            0x84 => 0x54, // Alt-SysRq
+            _ => byte,
+        };
+
+        if self.break_pending {
+            self.break_pending = false;
+            Some(translated | 0x80)
+        }
+        else {
+            Some(translated)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.break_pending = false;
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct KeyboardModifiers {
     pub control: bool,
@@ -88,6 +346,21 @@ impl KeyboardModifiers {
     pub fn have_any(&self) -> bool {
         self.control || self.alt || self.shift || self.meta
     }
+
+    /// Return the modifiers held by the specified set of keys.
+    pub fn from_keys(keys: &[MartyKey]) -> Self {
+        let mut modifiers = KeyboardModifiers::default();
+        for key in keys {
+            match key {
+                MartyKey::ControlLeft | MartyKey::ControlRight => modifiers.control = true,
+                MartyKey::AltLeft | MartyKey::AltRight => modifiers.alt = true,
+                MartyKey::ShiftLeft | MartyKey::ShiftRight => modifiers.shift = true,
+                MartyKey::MetaLeft | MartyKey::MetaRight => modifiers.meta = true,
+                _ => {}
+            }
+        }
+        modifiers
+    }
 }
 
 /// Incoming keycode-presses can be translated two possible ways.
@@ -139,7 +412,12 @@ pub struct KeyboardDefinition {
 
 #[derive(Debug, Deserialize)]
 pub struct Modelf {
+    #[serde(default)]
+    scancode_table: HashMap<String, Vec<u8>>,
+    #[serde(default)]
     keycode_mappings: Vec<KeycodeMapping>,
+    #[serde(default)]
+    char_table: HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -169,9 +447,18 @@ pub struct Keyboard {
     typematic_delay: f64, // Typematic repeat delay from initial keypress (ms)
     typematic_rate: f64,  // Typematic repeat rate (ms)
     kb_buffer_size: usize,
-    kb_buffer: Vec<u8>, // Keyboard buffer. Variable length depending on keyboard model.
+    kb_buffer: VecDeque<u8>, // Keyboard buffer. Variable length depending on keyboard model.
     kb_buffer_overflow: bool,
+    scancode_table: HashMap<MartyKey, Vec<u8>>, // Per-layout overrides of the built-in scancode table.
     keycode_mappings: Vec<KeycodeMapping>,
+    char_table: HashMap<char, Vec<MartyKey>>, // Keys to press to type a character, for text entry.
+    scancode_set: ScancodeSet,
+    enabled: bool,
+    pending_command: Option<u8>,
+    last_sent: u8,
+    controller_translation: bool,
+    translator: KbcTranslator,
+    leds: KeyboardLeds,
 }
 
 impl Default for Keyboard {
@@ -185,9 +472,18 @@ impl Default for Keyboard {
             typematic_delay: 500.0,
             typematic_rate: 100.0,
             kb_buffer_size: 1,
-            kb_buffer: Vec::new(),
+            kb_buffer: VecDeque::new(),
             kb_buffer_overflow: false,
+            scancode_table: HashMap::new(),
             keycode_mappings: Vec::new(),
+            char_table: HashMap::new(),
+            scancode_set: ScancodeSet::Set1,
+            enabled: true,
+            pending_command: None,
+            last_sent: 0,
+            controller_translation: false,
+            translator: KbcTranslator::default(),
+            leds: KeyboardLeds::default(),
         }
     }
 }
@@ -205,6 +501,7 @@ impl Keyboard {
             kb.kb_hash.insert(martykey, KeyState::default());
         }
 
+        kb.set_type(kb_type);
         kb
     }
 
@@ -234,27 +531,316 @@ impl Keyboard {
         );
     }
 
+    /// Load a keyboard translation file. Any previously loaded translation table is replaced.
     pub fn load_mapping(&mut self, map_file: &Path) -> Result<()> {
         let toml_mapping_str = read_to_string(map_file)?;
-        let toml_mapping: KeyboardMappingFile = toml::from_str(&toml_mapping_str)?;
+        self.load_mapping_str(&toml_mapping_str)
+    }
 
-        match self.kb_type {
-            KeyboardType::ModelF => {
-                self.keycode_mappings = toml_mapping.keyboard.modelf.keycode_mappings;
+    /// Load a keyboard translation table from a TOML string. This allows a frontend to supply
+    /// a layout without going through the filesystem.
+    pub fn load_mapping_str(&mut self, toml_mapping_str: &str) -> Result<()> {
+        let toml_mapping: KeyboardMappingFile = toml::from_str(toml_mapping_str)?;
+
+        // Mappings are defined in terms of Set 1 scancodes and are converted to the active set
+        // on output, so the Model M can share the Model F mappings.
+        let definition = match self.kb_type {
+            KeyboardType::ModelF | KeyboardType::ModelM => toml_mapping.keyboard.modelf,
+        };
+
+        let mut scancode_table = HashMap::new();
+        for (keycode_str, scancodes) in definition.scancode_table {
+            let keycode = match MartyKey::from_str(&keycode_str) {
+                Ok(keycode) => keycode,
+                Err(_) => bail!("Invalid keycode in scancode table: {}", keycode_str),
+            };
+            if self.kb_type == KeyboardType::ModelF && scancodes.len() > 1 {
+                bail!("Scancode table entry for {} has more than one scancode", keycode_str);
+            }
+            scancode_table.insert(keycode, scancodes);
+        }
+
+        for mapping in &definition.keycode_mappings {
+            if MartyKey::from_str(&mapping.keycode).is_err() {
+                bail!("Invalid keycode in keycode mapping: {}", mapping.keycode);
+            }
+            if mapping.modifiers.is_empty() {
+                bail!("Keycode mapping for {} has no modifiers specified", mapping.keycode);
             }
-            _ => unimplemented!(),
         }
 
+        let mut char_table = HashMap::new();
+        for (char_str, keycode_strs) in definition.char_table {
+            let mut chars = char_str.chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => bail!("Character table entry {:?} is not a single character", char_str),
+            };
+            if keycode_strs.is_empty() {
+                bail!("Character table entry {:?} has no keys", char_str);
+            }
+            let mut keys = Vec::new();
+            for keycode_str in &keycode_strs {
+                match MartyKey::from_str(keycode_str) {
+                    Ok(keycode) => keys.push(keycode),
+                    Err(_) => bail!("Invalid keycode in character table: {}", keycode_str),
+                }
+            }
+            char_table.insert(c, keys);
+        }
+
+        log::debug!(
+            "Loaded keyboard translation table: {} scancode overrides, {} keycode mappings, {} characters",
+            scancode_table.len(),
+            definition.keycode_mappings.len(),
+            char_table.len()
+        );
+        self.scancode_table = scancode_table;
+        self.keycode_mappings = definition.keycode_mappings;
+        self.char_table = char_table;
         Ok(())
     }
 
+    /// Remove any loaded translation table, reverting to the built-in US layout.
+    pub fn clear_mapping(&mut self) {
+        self.scancode_table.clear();
+        self.keycode_mappings.clear();
+        self.char_table.clear();
+    }
+
+    /// Return the keys to press, in order, to type the specified character. Keys are released
+    /// in reverse order. The loaded layout's character table is consulted before falling back
+    /// to the US layout. Returns None if the character can't be typed.
+    pub fn char_to_keys(&self, c: char) -> Option<Vec<MartyKey>> {
+        if let Some(keys) = self.char_table.get(&c) {
+            return Some(keys.clone());
+        }
+        match MartyKey::from_char(c)? {
+            (key, true) => Some(vec![MartyKey::ShiftLeft, key]),
+            (key, false) => Some(vec![key]),
+        }
+    }
+
+    /// Override the scancodes produced by a single key. Passing None restores the built-in
+    /// scancode for that key. Scancodes are specified in Set 1.
+    pub fn set_scancode_override(&mut self, key_code: MartyKey, scancodes: Option<Vec<u8>>) {
+        match scancodes {
+            Some(scancodes) => {
+                self.scancode_table.insert(key_code, scancodes);
+            }
+            None => {
+                self.scancode_table.remove(&key_code);
+            }
+        }
+    }
+
     pub fn get_type(&self) -> KeyboardType {
         self.kb_type
     }
 
     pub fn set_type(&mut self, kb_type: KeyboardType) {
         self.kb_type = kb_type;
-        // Do any reinitialization here
+
+        match kb_type {
+            KeyboardType::ModelF => {
+                // The Model F has no buffer and only speaks Set 1.
+                self.kb_buffer_size = 1;
+                self.scancode_set = ScancodeSet::Set1;
+                self.controller_translation = false;
+            }
+            KeyboardType::ModelM => {
+                // The Model M powers on in Set 2. Translation is enabled by default as the
+                // guest expects Set 1 from the keyboard controller.
+                self.kb_buffer_size = MODEL_M_BUFFER_SIZE;
+                self.scancode_set = ScancodeSet::Set2;
+                self.controller_translation = true;
+            }
+        }
+        self.kb_buffer.clear();
+        self.translator.reset();
+        self.leds = KeyboardLeds::default();
+    }
+
+    /// Return the currently active scancode set.
+    pub fn get_scancode_set(&self) -> ScancodeSet {
+        self.scancode_set
+    }
+
+    /// Select the active scancode set. The Model F only supports Set 1.
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        if self.kb_type == KeyboardType::ModelF && set != ScancodeSet::Set1 {
+            log::warn!("Keyboard: Model F does not support scancode {:?}", set);
+            return;
+        }
+        log::debug!("Keyboard: selected scancode {:?}", set);
+        self.scancode_set = set;
+    }
+
+    /// Enable or disable Set 2 to Set 1 translation, as performed by an 8042 keyboard controller.
+    pub fn set_controller_translation(&mut self, state: bool) {
+        self.controller_translation = state;
+        self.translator.reset();
+    }
+
+    pub fn get_controller_translation(&self) -> bool {
+        self.controller_translation
+    }
+
+    /// Convert a sequence of Set 1 make codes into make codes for the active scancode set.
+    /// Extended keys keep their E0 prefix in Set 2, and have codes of their own in Set 3.
+    pub fn encode_make(&self, set1_codes: &[u8]) -> Vec<u8> {
+        if self.scancode_set == ScancodeSet::Set1 {
+            return set1_codes.to_vec();
+        }
+
+        let mut codes = Vec::with_capacity(set1_codes.len());
+        for key in set1_keys(set1_codes) {
+            match (self.scancode_set, key) {
+                (ScancodeSet::Set2, Set1Key::Base(c)) => codes.push(SET1_TO_SET2_TABLE[(c & 0x7F) as usize]),
+                (ScancodeSet::Set2, Set1Key::Extended(c)) => {
+                    codes.extend([KB_EXTENDED_PREFIX, SET1_TO_SET2_TABLE[(c & 0x7F) as usize]])
+                }
+                (ScancodeSet::Set2, Set1Key::Pause) => codes.extend(SET2_PAUSE),
+                (_, Set1Key::Base(c)) => codes.push(set1_to_set3(c)),
+                (_, Set1Key::Extended(c)) => codes.push(set1_extended_to_set3(c)),
+                (_, Set1Key::Pause) => codes.push(SET3_PAUSE),
+            }
+        }
+        codes
+    }
+
+    /// Convert a sequence of Set 1 make codes into break codes for the active scancode set.
+    /// The Pause key sends no break code, except in Set 3.
+    pub fn encode_break(&self, set1_codes: &[u8]) -> Vec<u8> {
+        let mut codes = Vec::with_capacity(set1_codes.len() * 2);
+        for key in set1_keys(set1_codes) {
+            match (self.scancode_set, key) {
+                (ScancodeSet::Set1, Set1Key::Base(c)) => codes.push(c | 0x80),
+                (ScancodeSet::Set1, Set1Key::Extended(c)) => codes.extend([KB_EXTENDED_PREFIX, c | 0x80]),
+                (ScancodeSet::Set2, Set1Key::Extended(c)) => codes.extend([
+                    KB_EXTENDED_PREFIX,
+                    KB_BREAK_PREFIX,
+                    SET1_TO_SET2_TABLE[(c & 0x7F) as usize],
+                ]),
+                (ScancodeSet::Set1 | ScancodeSet::Set2, Set1Key::Pause) => {}
+                (_, key) => {
+                    codes.push(KB_BREAK_PREFIX);
+                    codes.extend(self.encode_make(&Self::set1_key_codes(key)));
+                }
+            }
+        }
+        codes
+    }
+
+    /// Return the Set 1 make codes of a single key.
+    fn set1_key_codes(key: Set1Key) -> Vec<u8> {
+        match key {
+            Set1Key::Base(c) => vec![c],
+            Set1Key::Extended(c) => vec![KB_EXTENDED_PREFIX, c],
+            Set1Key::Pause => SET1_PAUSE.to_vec(),
+        }
+    }
+
+    /// Receive a command byte from the host. Only the Model M accepts commands; the Model F
+    /// has no host to keyboard data path.
+    pub fn write_command(&mut self, byte: u8) {
+        if self.kb_type == KeyboardType::ModelF {
+            log::warn!("Keyboard: Model F ignoring command byte: {:02X}", byte);
+            return;
+        }
+
+        // The keyboard discards its output buffer when it receives a byte from the host.
+        if byte != KB_CMD_RESEND {
+            self.kb_buffer.clear();
+            self.kb_buffer_overflow = false;
+        }
+
+        if let Some(command) = self.pending_command.take() {
+            // This byte is a parameter for a previous command.
+            match command {
+                KB_CMD_SCANCODE_SET => match byte {
+                    0x00 => {
+                        self.send_response(&[KB_RESP_ACK, self.scancode_set.id()]);
+                        return;
+                    }
+                    0x01 => self.set_scancode_set(ScancodeSet::Set1),
+                    0x02 => self.set_scancode_set(ScancodeSet::Set2),
+                    0x03 => self.set_scancode_set(ScancodeSet::Set3),
+                    _ => {
+                        self.send_response(&[KB_RESP_RESEND]);
+                        return;
+                    }
+                },
+                KB_CMD_SET_LEDS => {
+                    self.leds = KeyboardLeds::from_command_byte(byte);
+                    log::debug!("Keyboard: set LEDs: {:?}", self.leds);
+                }
+                KB_CMD_TYPEMATIC => {
+                    // Bits 0-4 select the repeat rate, bits 5-6 the delay in units of 250ms.
+                    let period = (8.0 + (byte & 0x07) as f64) * (1 << ((byte >> 3) & 0x03)) as f64 * 4.17;
+                    let delay = (((byte >> 5) & 0x03) as f64 + 1.0) * 250.0;
+                    self.set_typematic_params(None, Some(delay), Some(period));
+                }
+                _ => {}
+            }
+            self.send_response(&[KB_RESP_ACK]);
+            return;
+        }
+
+        match byte {
+            KB_CMD_ECHO => self.send_response(&[KB_RESP_ECHO]),
+            KB_CMD_SET_LEDS | KB_CMD_TYPEMATIC | KB_CMD_SCANCODE_SET => {
+                self.pending_command = Some(byte);
+                self.send_response(&[KB_RESP_ACK]);
+            }
+            KB_CMD_IDENTIFY => self.send_response(&[KB_RESP_ACK, 0xAB, 0x83]),
+            KB_CMD_ENABLE => {
+                self.enabled = true;
+                self.send_response(&[KB_RESP_ACK]);
+            }
+            KB_CMD_DEFAULT_DISABLE | KB_CMD_SET_DEFAULT => {
+                self.enabled = byte == KB_CMD_SET_DEFAULT;
+                self.set_typematic_params(None, Some(DEFAULT_TYPEMATIC_DELAY), Some(DEFAULT_TYPEMATIC_RATE));
+                self.send_response(&[KB_RESP_ACK]);
+            }
+            KB_CMD_RESEND => {
+                let last = self.last_sent;
+                self.send_response(&[last]);
+            }
+            KB_CMD_RESET => {
+                self.enabled = true;
+                self.leds = KeyboardLeds::default();
+                self.set_typematic_params(None, Some(DEFAULT_TYPEMATIC_DELAY), Some(DEFAULT_TYPEMATIC_RATE));
+                self.scancode_set = ScancodeSet::Set2;
+                self.send_response(&[KB_RESP_ACK, KB_RESP_BAT_OK]);
+            }
+            _ => {
+                log::warn!("Keyboard: unsupported command byte: {:02X}", byte);
+                self.send_response(&[KB_RESP_RESEND]);
+            }
+        }
+    }
+
+    /// Return the lock indicator state last set by the guest.
+    pub fn get_leds(&self) -> KeyboardLeds {
+        self.leds
+    }
+
+    /// Return a snapshot of guest-controlled keyboard state.
+    pub fn get_state(&self) -> KeyboardState {
+        KeyboardState {
+            leds: self.leds,
+            typematic: self.typematic,
+            typematic_delay: self.typematic_delay,
+            typematic_rate: self.typematic_rate,
+            scancode_set: self.scancode_set,
+            enabled: self.enabled,
+        }
+    }
+
+    /// Queue command response bytes.
+    fn send_response(&mut self, bytes: &[u8]) {
+        self.kb_buffer.extend(bytes);
     }
 
     /// Get the KeyState for the corresponding key.
@@ -330,10 +916,48 @@ impl Keyboard {
     /// Convert a MartyKey key code into a physical scancode based on the configured
     /// keyboard model.
     pub fn keycode_to_scancodes(&self, key_code: MartyKey) -> Vec<u8> {
+        // A loaded translation table takes precedence over the built-in table.
+        if let Some(scancodes) = self.scancode_table.get(&key_code) {
+            return scancodes.clone();
+        }
+
         let mut scancodes = Vec::new();
 
+        if self.kb_type == KeyboardType::ModelM {
+            // Keys added or duplicated by the 101-key layout send an E0 prefix, so that they can be
+            // told apart from their counterparts on the numeric keypad.
+            let extended = match key_code {
+                MartyKey::ControlRight => Some(0x1D),
+                MartyKey::AltRight => Some(0x38),
+                MartyKey::Insert => Some(0x52),
+                MartyKey::Delete => Some(0x53),
+                MartyKey::Home => Some(0x47),
+                MartyKey::End => Some(0x4F),
+                MartyKey::PageUp => Some(0x49),
+                MartyKey::PageDown => Some(0x51),
+                MartyKey::ArrowUp => Some(0x48),
+                MartyKey::ArrowDown => Some(0x50),
+                MartyKey::ArrowLeft => Some(0x4B),
+                MartyKey::ArrowRight => Some(0x4D),
+                MartyKey::NumpadDivide => Some(0x35),
+                MartyKey::NumpadEnter => Some(0x1C),
+                MartyKey::PrintScreen => Some(0x37),
+                _ => None,
+            };
+            if let Some(code) = extended {
+                return vec![KB_EXTENDED_PREFIX, code];
+            }
+
+            match key_code {
+                MartyKey::Pause => return SET1_PAUSE.to_vec(),
+                // No longer shared with Print Screen.
+                MartyKey::NumpadMultiply => return vec![0x37],
+                _ => {}
+            }
+        }
+
         match self.kb_type {
-            KeyboardType::ModelF => {
+            KeyboardType::ModelF | KeyboardType::ModelM => {
                 // The model F was the original keyboard shipped with the IBM PC.
                 // It had two variants, an 83-key version without lock status lights
                 // and an 84-key version with an added 'sysreq' key.
@@ -430,6 +1054,10 @@ impl Keyboard {
                     MartyKey::NumpadDivide => None,      // Can't directly map to shift-7
                     MartyKey::NumpadMultiply => None,    // Can't directly map to shift-8
                     MartyKey::NumpadEqual => Some(0x0D), // Present on Mac
+                    // Keys added by the Model M.
+                    MartyKey::F11 if self.kb_type == KeyboardType::ModelM => Some(0x57),
+                    MartyKey::F12 if self.kb_type == KeyboardType::ModelM => Some(0x58),
+                    MartyKey::IntlBackslash if self.kb_type == KeyboardType::ModelM => Some(0x56),
                     _ => None,
                 };
 
@@ -438,9 +1066,6 @@ impl Keyboard {
                     scancodes.push(s);
                 }
             }
-            _ => {
-                unimplemented!();
-            }
         }
 
         scancodes
@@ -492,7 +1117,8 @@ impl Keyboard {
                             key.pressed_time = 0.0;
 
                             self.keys_pressed.push(key_code);
-                            self.send_scancodes(&svec);
+                            let make = self.encode_make(&svec);
+                            self.send_scancodes(&make);
                         }
                     }
                 }
@@ -520,9 +1146,9 @@ impl Keyboard {
             }
         }
 
-        if let Some(mut to_convert) = convert_translation {
-            self.translate_keyup(self.kb_type, &mut to_convert);
-            self.send_scancodes(&to_convert);
+        if let Some(to_convert) = convert_translation {
+            let key_up = self.translate_keyup(&to_convert);
+            self.send_scancodes(&key_up);
         }
 
         // Remove this key from keys_pressed.
@@ -539,17 +1165,23 @@ impl Keyboard {
     /// Send the corresponding scancodes to the keyboard buffer.
     pub fn send_scancodes(&mut self, keys: &[u8]) {
         if keys.len() > 0 {
+            if !self.enabled {
+                return;
+            }
             if self.kb_buffer_size > 1 {
                 // We have a keyboard buffer
-                if self.kb_buffer.len() + keys.len() >= self.kb_buffer_size {
+                if self.kb_buffer.len() + keys.len() > self.kb_buffer_size {
                     // KB overflow!
                     self.kb_buffer_overflow = true;
                 }
+                else {
+                    self.kb_buffer.extend(keys);
+                }
             }
             else if self.kb_buffer_size == 1 {
                 // No keyboard buffer (kb_buffer_size == 1). Just set one scancode.
                 self.kb_buffer.clear();
-                self.kb_buffer.push(keys[0]);
+                self.kb_buffer.push_back(keys[0]);
             }
             else {
                 panic!("invalid kb_buffer_size");
@@ -558,14 +1190,25 @@ impl Keyboard {
     }
 
     /// Read out a scancode from the keyboard or None if no key in buffer.
+    /// If controller translation is enabled, the scancode is translated to Set 1.
     pub fn recv_scancode(&mut self) -> Option<u8> {
-        if self.kb_buffer_overflow {
-            // Send the keyboard overflow scancode
-            self.kb_buffer_overflow = false;
-            Some(0xFF)
-        }
-        else {
-            self.kb_buffer.pop()
+        loop {
+            let byte = if self.kb_buffer_overflow {
+                // Send the keyboard overflow scancode
+                self.kb_buffer_overflow = false;
+                KB_RESP_OVERFLOW
+            }
+            else {
+                self.kb_buffer.pop_front()?
+            };
+            self.last_sent = byte;
+
+            if !self.controller_translation {
+                return Some(byte);
+            }
+            if let Some(translated) = self.translator.translate(byte) {
+                return Some(translated);
+            }
         }
     }
 
@@ -584,7 +1227,7 @@ impl Keyboard {
                 }
                 else if trans.modifiers[0].eq_ignore_ascii_case("none") {
                     // Use this translation if there are no modifiers
-                    if trans_modifiers.have_any() {
+                    if !modifiers.have_any() {
                         matched = true;
                     }
                 }
@@ -641,26 +1284,21 @@ impl Keyboard {
     }
 
     /// Convert a translated scancode sequence to its corresponding keyup sequence.
-    fn translate_keyup(&self, kb_type: KeyboardType, translation: &mut [u8]) {
-        match kb_type {
-            KeyboardType::ModelF => {
-                // ModelF has no keyboard buffer, therefore, translations should only have one keycode.
-                assert_eq!(translation.len(), 1);
-
-                if self.debug {
-                    log::debug!(
-                        "translate_keyup(): sending key_up: {:02X} for keydown translation: {:02X}",
-                        translation[0] | 0x80,
-                        translation[0]
-                    );
-                }
+    fn translate_keyup(&self, translation: &[u8]) -> Vec<u8> {
+        if self.kb_type == KeyboardType::ModelF {
+            // ModelF has no keyboard buffer, therefore, translations should only have one keycode.
+            assert_eq!(translation.len(), 1);
+        }
 
-                translation[0] = translation[0] | 0x80;
-            }
-            _ => {
-                unimplemented!();
-            }
+        let key_up = self.encode_break(translation);
+        if self.debug {
+            log::debug!(
+                "translate_keyup(): sending key_up: {:02X?} for keydown translation: {:02X?}",
+                key_up,
+                translation
+            );
         }
+        key_up
     }
 
     /// Run the keyboard device for the specified number of microseconds.
@@ -701,7 +1339,8 @@ impl Keyboard {
         // Only repeat the oldest pressed key
         if let Some(key) = repeating_keys.pop() {
             if let Some(translation) = key.translation {
-                self.send_scancodes(&translation);
+                let make = self.encode_make(&translation);
+                self.send_scancodes(&make);
             }
         }
     }
@@ -718,7 +1357,8 @@ impl Keyboard {
             | MartyKey::NumLock
             | MartyKey::ScrollLock
             | MartyKey::CapsLock
-            | MartyKey::Insert => false,
+            | MartyKey::Insert
+            | MartyKey::Pause => false,
             _ => {
                 // All other keys ok to repeat
                 true
@@ -726,3 +1366,310 @@ impl Keyboard {
         }
     }
 }
+
+/// The serializable state of a key held down on the keyboard.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PressedKeyState {
+    pub key: MartyKey,
+    pub pressed_time: f64,
+    pub repeat_time: f64,
+    pub translation: Option<Vec<u8>>,
+}
+
+/// The serializable state of the keyboard: the keys held down, the keyboard buffer and the
+/// settings made by the guest. The keyboard type and layout are configuration, and are not
+/// included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyboardDeviceState {
+    pub keys_pressed: Vec<PressedKeyState>,
+    pub typematic: bool,
+    pub typematic_delay: f64,
+    pub typematic_rate: f64,
+    pub kb_buffer: Vec<u8>,
+    pub kb_buffer_overflow: bool,
+    pub scancode_set: ScancodeSet,
+    pub enabled: bool,
+    pub pending_command: Option<u8>,
+    pub last_sent: u8,
+    pub controller_translation: bool,
+    pub break_pending: bool,
+    pub leds: KeyboardLeds,
+}
+
+impl DeviceState for Keyboard {
+    type State = KeyboardDeviceState;
+
+    fn save_state(&self) -> KeyboardDeviceState {
+        KeyboardDeviceState {
+            keys_pressed: self
+                .keys_pressed
+                .iter()
+                .filter_map(|key| {
+                    self.kb_hash.get(key).map(|state| PressedKeyState {
+                        key: *key,
+                        pressed_time: state.pressed_time,
+                        repeat_time: state.repeat_time,
+                        translation: state.translation.clone(),
+                    })
+                })
+                .collect(),
+            typematic: self.typematic,
+            typematic_delay: self.typematic_delay,
+            typematic_rate: self.typematic_rate,
+            kb_buffer: self.kb_buffer.iter().copied().collect(),
+            kb_buffer_overflow: self.kb_buffer_overflow,
+            scancode_set: self.scancode_set,
+            enabled: self.enabled,
+            pending_command: self.pending_command,
+            last_sent: self.last_sent,
+            controller_translation: self.controller_translation,
+            break_pending: self.translator.break_pending,
+            leds: self.leds,
+        }
+    }
+
+    fn load_state(&mut self, state: KeyboardDeviceState) -> Result<()> {
+        if state.kb_buffer.len() > self.kb_buffer_size {
+            bail!(
+                "Keyboard state has {} buffered bytes, the keyboard holds {}",
+                state.kb_buffer.len(),
+                self.kb_buffer_size
+            );
+        }
+
+        for key_state in self.kb_hash.values_mut() {
+            *key_state = KeyState::default();
+        }
+        self.keys_pressed.clear();
+        for pressed in state.keys_pressed {
+            self.kb_hash.insert(
+                pressed.key,
+                KeyState {
+                    pressed: true,
+                    pressed_time: pressed.pressed_time,
+                    repeat_time: pressed.repeat_time,
+                    translation: pressed.translation,
+                },
+            );
+            self.keys_pressed.push(pressed.key);
+        }
+
+        self.typematic = state.typematic;
+        self.typematic_delay = state.typematic_delay;
+        self.typematic_rate = state.typematic_rate;
+        self.kb_buffer = state.kb_buffer.into();
+        self.kb_buffer_overflow = state.kb_buffer_overflow;
+        self.scancode_set = state.scancode_set;
+        self.enabled = state.enabled;
+        self.pending_command = state.pending_command;
+        self.last_sent = state.last_sent;
+        self.controller_translation = state.controller_translation;
+        self.translator.break_pending = state.break_pending;
+        self.leds = state.leds;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set2_translation_roundtrip() {
+        // Every base key converted to Set 2 and passed through the 8042 translation
+        // must come back as the original Set 1 code.
+        let mut translator = KbcTranslator::default();
+        for set1 in 0x01..=0x58u8 {
+            let set2 = SET1_TO_SET2_TABLE[set1 as usize];
+            assert_eq!(translator.translate(set2), Some(set1), "make {:02X}", set1);
+            assert_eq!(translator.translate(KB_BREAK_PREFIX), None);
+            assert_eq!(translator.translate(set2), Some(set1 | 0x80), "break {:02X}", set1);
+        }
+    }
+
+    #[test]
+    fn test_scancode_set_selection() {
+        let mut kb = Keyboard::new(KeyboardType::ModelM, false);
+        kb.set_controller_translation(false);
+        assert_eq!(kb.get_scancode_set(), ScancodeSet::Set2);
+
+        // Query the current set.
+        kb.write_command(KB_CMD_SCANCODE_SET);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        kb.write_command(0x00);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        assert_eq!(kb.recv_scancode(), Some(0x02));
+
+        // Select Set 3.
+        kb.write_command(KB_CMD_SCANCODE_SET);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        kb.write_command(0x03);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        assert_eq!(kb.get_scancode_set(), ScancodeSet::Set3);
+
+        // Escape in Set 3.
+        kb.key_down(MartyKey::Escape, &KeyboardModifiers::default(), None);
+        kb.key_up(MartyKey::Escape);
+        assert_eq!(kb.recv_scancode(), Some(0x08));
+        assert_eq!(kb.recv_scancode(), Some(KB_BREAK_PREFIX));
+        assert_eq!(kb.recv_scancode(), Some(0x08));
+        assert_eq!(kb.recv_scancode(), None);
+    }
+
+    #[test]
+    fn test_translated_set_query() {
+        // With translation enabled the set query reply is itself translated.
+        let mut kb = Keyboard::new(KeyboardType::ModelM, false);
+        kb.write_command(KB_CMD_SCANCODE_SET);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        kb.write_command(0x00);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        assert_eq!(kb.recv_scancode(), Some(0x41));
+
+        // Keys arrive as Set 1.
+        kb.key_down(MartyKey::KeyA, &KeyboardModifiers::default(), None);
+        kb.key_up(MartyKey::KeyA);
+        assert_eq!(kb.recv_scancode(), Some(0x1E));
+        assert_eq!(kb.recv_scancode(), Some(0x9E));
+    }
+
+    #[test]
+    fn test_scancode_table_override() {
+        // Swap Y and Z, as on a German layout.
+        let mapping = r#"
+            [keyboard.modelf]
+            scancode_table = { KeyY = [0x2C], KeyZ = [0x15] }
+        "#;
+        let mut kb = Keyboard::new(KeyboardType::ModelF, false);
+        kb.load_mapping_str(mapping).unwrap();
+        assert_eq!(kb.keycode_to_scancodes(MartyKey::KeyY), vec![0x2C]);
+        assert_eq!(kb.keycode_to_scancodes(MartyKey::KeyZ), vec![0x15]);
+        assert_eq!(kb.keycode_to_scancodes(MartyKey::KeyA), vec![0x1E]);
+
+        kb.clear_mapping();
+        assert_eq!(kb.keycode_to_scancodes(MartyKey::KeyY), vec![0x15]);
+
+        let bad_mapping = r#"
+            [keyboard.modelf]
+            scancode_table = { NotAKey = [0x01] }
+        "#;
+        assert!(kb.load_mapping_str(bad_mapping).is_err());
+    }
+
+    #[test]
+    fn test_char_table() {
+        let mapping = r#"
+            [keyboard.modelf]
+            char_table = { "z" = ["KeyY"], "@" = ["ControlLeft", "AltLeft", "Semicolon"] }
+        "#;
+        let mut kb = Keyboard::new(KeyboardType::ModelF, false);
+        assert_eq!(kb.char_to_keys('z'), Some(vec![MartyKey::KeyZ]));
+        assert_eq!(kb.char_to_keys('@'), Some(vec![MartyKey::ShiftLeft, MartyKey::Digit2]));
+
+        kb.load_mapping_str(mapping).unwrap();
+        assert_eq!(kb.char_to_keys('z'), Some(vec![MartyKey::KeyY]));
+        assert_eq!(
+            kb.char_to_keys('@'),
+            Some(vec![MartyKey::ControlLeft, MartyKey::AltLeft, MartyKey::Semicolon])
+        );
+        // Characters missing from the table use the US layout.
+        assert_eq!(kb.char_to_keys('A'), Some(vec![MartyKey::ShiftLeft, MartyKey::KeyA]));
+        assert_eq!(kb.char_to_keys('\u{e9}'), None);
+
+        kb.clear_mapping();
+        assert_eq!(kb.char_to_keys('z'), Some(vec![MartyKey::KeyZ]));
+
+        for bad_mapping in [
+            r#"char_table = { "zz" = ["KeyY"] }"#,
+            r#"char_table = { "z" = [] }"#,
+            r#"char_table = { "z" = ["NotAKey"] }"#,
+        ] {
+            let bad_mapping = format!("[keyboard.modelf]\n{}", bad_mapping);
+            assert!(kb.load_mapping_str(&bad_mapping).is_err(), "{}", bad_mapping);
+        }
+    }
+
+    #[test]
+    fn test_led_and_typematic_state() {
+        let mut kb = Keyboard::new(KeyboardType::ModelM, false);
+        kb.set_controller_translation(false);
+
+        kb.write_command(KB_CMD_SET_LEDS);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        kb.write_command(0x06);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        let leds = kb.get_state().leds;
+        assert!(leds.num_lock && leds.caps_lock && !leds.scroll_lock);
+
+        // Fastest rate (30cps) with a 1 second delay.
+        kb.write_command(KB_CMD_TYPEMATIC);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        kb.write_command(0x60);
+        assert_eq!(kb.recv_scancode(), Some(KB_RESP_ACK));
+        let state = kb.get_state();
+        assert_eq!(state.typematic_delay, 1000.0);
+        assert!((state.typematic_rate - 33.36).abs() < 0.01);
+
+        kb.write_command(KB_CMD_RESET);
+        assert_eq!(kb.get_leds(), KeyboardLeds::default());
+    }
+
+    #[test]
+    fn test_keyboard_state_roundtrip() {
+        let mut kb = Keyboard::new(KeyboardType::ModelM, false);
+        kb.write_command(KB_CMD_SET_LEDS);
+        kb.write_command(0x04);
+        kb.key_down(MartyKey::ShiftLeft, &KeyboardModifiers::default(), None);
+        kb.key_down(MartyKey::KeyA, &KeyboardModifiers::default(), None);
+        kb.run(100.0);
+
+        let json = kb.serialize_state().unwrap();
+
+        let mut restored = Keyboard::new(KeyboardType::ModelM, false);
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), kb.dump_state());
+        assert!(restored.get_leds().caps_lock);
+        assert!(restored.get_keycode_state(MartyKey::KeyA).unwrap().pressed);
+
+        // Releasing a restored key sends its break code.
+        while restored.recv_scancode().is_some() {}
+        restored.key_up(MartyKey::KeyA);
+        assert_eq!(restored.recv_scancode(), Some(0x1E | 0x80));
+
+        // A buffer larger than the keyboard's is rejected.
+        let mut state = kb.save_state();
+        state.kb_buffer = vec![0; MODEL_M_BUFFER_SIZE + 1];
+        assert!(restored.load_state(state).is_err());
+    }
+
+    #[test]
+    fn test_extended_keys() {
+        let none = KeyboardModifiers::default();
+        let mut kb = Keyboard::new(KeyboardType::ModelM, false);
+        let press = |kb: &mut Keyboard, key: MartyKey| {
+            kb.key_down(key, &none, None);
+            kb.key_up(key);
+            std::iter::from_fn(|| kb.recv_scancode()).collect::<Vec<u8>>()
+        };
+
+        // Through the 8042, the arrow keys are distinct from the keypad.
+        assert_eq!(press(&mut kb, MartyKey::ArrowUp), vec![0xE0, 0x48, 0xE0, 0xC8]);
+        assert_eq!(press(&mut kb, MartyKey::Numpad8), vec![0x48, 0xC8]);
+        // Pause has no break code.
+        assert_eq!(press(&mut kb, MartyKey::Pause), SET1_PAUSE.to_vec());
+
+        // Untranslated Set 2.
+        kb.set_controller_translation(false);
+        assert_eq!(press(&mut kb, MartyKey::ArrowUp), vec![0xE0, 0x75, 0xE0, 0xF0, 0x75]);
+        assert_eq!(press(&mut kb, MartyKey::Pause), SET2_PAUSE.to_vec());
+
+        // Set 3 has no prefixes.
+        kb.set_scancode_set(ScancodeSet::Set3);
+        assert_eq!(press(&mut kb, MartyKey::ArrowUp), vec![0x63, 0xF0, 0x63]);
+        assert_eq!(press(&mut kb, MartyKey::Pause), vec![0x62, 0xF0, 0x62]);
+
+        // The Model F has no extended keys.
+        let kb = Keyboard::new(KeyboardType::ModelF, false);
+        assert_eq!(kb.keycode_to_scancodes(MartyKey::ArrowUp), vec![0x48]);
+    }
+}
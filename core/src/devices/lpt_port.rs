@@ -30,13 +30,28 @@
     implementation, and must be embedded into a card implementation that can
     decode the proper port address.
 
+    The port may optionally operate in PS/2-style bidirectional mode, where
+    bit 5 of the control register turns off the data output drivers so that
+    the data register reads the lines driven by an attached device.
+
 */
 
-use crate::tracelogger::TraceLogger;
+use crate::{device_traits::devicestate::DeviceState, tracelogger::TraceLogger};
+use anyhow::Error;
 use modular_bitfield::{bitfield, prelude::*};
+use serde::{Deserialize, Serialize};
 
 pub const LPT_DEFAULT_IRQ: u16 = 7;
 
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum ParallelPortMode {
+    /// Output-only data port, as on the original IBM printer adapters.
+    #[default]
+    Standard,
+    /// PS/2-style bidirectional data port.
+    Bidirectional,
+}
+
 #[bitfield]
 #[derive(Copy, Clone)]
 pub struct ParallelStatus {
@@ -57,13 +72,16 @@ pub struct ParallelControl {
     pub initialize: B1,
     pub select_in: B1,
     pub enable_irq: B1,
+    pub direction: B1, // 1 = input. Only implemented in bidirectional mode.
     #[skip]
-    pub unused2: B3,
+    pub unused2: B2,
 }
 
 #[allow(dead_code)]
 pub struct ParallelPort {
+    mode: ParallelPortMode,
     data: u8,
+    input_data: u8,
     status: ParallelStatus,
     control: ParallelControl,
     irq: u16,
@@ -73,7 +91,9 @@ pub struct ParallelPort {
 impl Default for ParallelPort {
     fn default() -> Self {
         Self {
+            mode: ParallelPortMode::Standard,
             data: 0,
+            input_data: 0xFF,
             status: ParallelStatus::from_bytes([0]),
             control: ParallelControl::from_bytes([0]),
             irq: LPT_DEFAULT_IRQ,
@@ -82,9 +102,40 @@ impl Default for ParallelPort {
     }
 }
 
+/// The serializable state of a parallel port's registers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParallelPortState {
+    pub data: u8,
+    pub input_data: u8,
+    pub status: u8,
+    pub control: u8,
+}
+
+impl DeviceState for ParallelPort {
+    type State = ParallelPortState;
+
+    fn save_state(&self) -> ParallelPortState {
+        ParallelPortState {
+            data: self.data,
+            input_data: self.input_data,
+            status: self.status.into_bytes()[0],
+            control: self.control.into_bytes()[0],
+        }
+    }
+
+    fn load_state(&mut self, state: ParallelPortState) -> Result<(), Error> {
+        self.data = state.data;
+        self.input_data = state.input_data;
+        self.status = ParallelStatus::from_bytes([state.status]);
+        self.control = ParallelControl::from_bytes([state.control]);
+        Ok(())
+    }
+}
+
 impl ParallelPort {
-    pub fn new(irq: Option<u16>, trace_logger: TraceLogger) -> Self {
+    pub fn new(irq: Option<u16>, mode: ParallelPortMode, trace_logger: TraceLogger) -> Self {
         Self {
+            mode,
             irq: irq.unwrap_or(LPT_DEFAULT_IRQ),
             trace_logger,
             ..Default::default()
@@ -135,14 +186,21 @@ impl ParallelPort {
 
     pub fn control_register_write(&mut self, data: u8) {
         self.control = ParallelControl::from_bytes([data]);
+        if self.mode == ParallelPortMode::Standard {
+            // The direction bit doesn't exist on a standard port.
+            self.control.set_direction(0);
+        }
         self.trace_logger
             .print(format!("LPT: Control register write: {:#02X}", data));
     }
 
     pub fn data_register_read(&mut self) -> u8 {
+        // With the output drivers off, the data lines are driven by the attached device.
+        // Otherwise we read back the output latch.
+        let byte = if self.is_input() { self.input_data } else { self.data };
         self.trace_logger
-            .print(format!("LPT: Data register read: {:#02X}", self.data));
-        self.data
+            .print(format!("LPT: Data register read: {:#02X}", byte));
+        byte
     }
 
     pub fn status_register_read(&mut self) -> u8 {
@@ -158,4 +216,64 @@ impl ParallelPort {
             .print(format!("LPT: Control register read: {:#02X}", byte));
         byte
     }
+
+    pub fn mode(&self) -> ParallelPortMode {
+        self.mode
+    }
+
+    /// Returns true if the data port is in input mode, with its output drivers disabled.
+    pub fn is_input(&self) -> bool {
+        self.mode == ParallelPortMode::Bidirectional && self.control.direction() != 0
+    }
+
+    /// Set the state of the data lines as driven by an attached device. Only visible to the
+    /// guest while the port is in input mode.
+    pub fn set_input_data(&mut self, data: u8) {
+        self.input_data = data;
+    }
+
+    /// Return the byte the port is driving onto the data lines, or None if the port is in
+    /// input mode.
+    pub fn output_data(&self) -> Option<u8> {
+        if self.is_input() {
+            None
+        }
+        else {
+            Some(self.data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lpt_bidirectional() {
+        // A standard port ignores the direction bit and reads back its output latch.
+        let mut lpt = ParallelPort::new(None, ParallelPortMode::Standard, TraceLogger::None);
+        lpt.set_input_data(0x5A);
+        lpt.port_write(0x378, 0xA5);
+        lpt.port_write(0x37A, 0x20);
+        assert_eq!(lpt.port_read(0x37A) & 0x20, 0);
+        assert_eq!(lpt.port_read(0x378), 0xA5);
+        assert_eq!(lpt.output_data(), Some(0xA5));
+
+        // A bidirectional port reads the attached device's data lines in input mode.
+        let mut lpt = ParallelPort::new(None, ParallelPortMode::Bidirectional, TraceLogger::None);
+        lpt.set_input_data(0x5A);
+        lpt.port_write(0x378, 0xA5);
+        assert_eq!(lpt.port_read(0x378), 0xA5);
+        lpt.port_write(0x37A, 0x20);
+        assert_eq!(lpt.port_read(0x37A) & 0x20, 0x20);
+        assert_eq!(lpt.port_read(0x378), 0x5A);
+        assert_eq!(lpt.output_data(), None);
+
+        // Writes in input mode are latched and driven once the port returns to output mode.
+        lpt.port_write(0x378, 0x3C);
+        assert_eq!(lpt.port_read(0x378), 0x5A);
+        lpt.port_write(0x37A, 0x00);
+        assert_eq!(lpt.port_read(0x378), 0x3C);
+        assert_eq!(lpt.output_data(), Some(0x3C));
+    }
 }
@@ -32,6 +32,7 @@
 */
 
 use crate::{device_traits::videocard::VideoCardStateEntry, tracelogger::TraceLogger};
+use serde::{Deserialize, Serialize};
 
 const CURSOR_LINE_MASK: u8 = 0b0000_1111;
 const CURSOR_ATTR_MASK: u8 = 0b0011_0000;
@@ -45,6 +46,9 @@ const CRTC_ROW_MAX: usize = 32;
 const REGISTER_MAX: usize = 17;
 const REGISTER_UNREADABLE_VALUE: u8 = 0xFF;
 
+const INTERLACE_SYNC: u8 = 0b01;
+const INTERLACE_SYNC_VIDEO: u8 = 0b11;
+
 #[derive(Copy, Clone, Debug)]
 pub enum CursorStatus {
     Solid,
@@ -113,7 +117,7 @@ macro_rules! push_reg_str {
 
 pub type HBlankCallback = dyn FnMut() -> u8;
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
 pub struct CrtcStatus {
     pub hblank: bool,
     pub vblank: bool,
@@ -125,6 +129,32 @@ pub struct CrtcStatus {
     pub vsync: bool,
 }
 
+/// The serializable state of a Crtc6845.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Crtc6845SavedState {
+    pub reg: [u8; 18],
+    pub reg_select: u8,
+    pub start_address_latch: u16,
+    pub lightpen_position: u16,
+    pub blink_state: bool,
+    pub cursor_blink_ct: u8,
+    pub hcc_c0: u8,
+    pub char_col: u8,
+    pub vlc_c9: u8,
+    pub vcc_c4: u8,
+    pub vsc_c3h: u8,
+    pub hsc_c3l: u8,
+    pub vtac_c5: u8,
+    pub in_vta: bool,
+    pub odd_field: bool,
+    pub in_half_line: bool,
+    pub vma: u16,
+    pub vma_t: u16,
+    pub hsync_target: u8,
+    pub status: CrtcStatus,
+    pub in_last_vblank_line: bool,
+}
+
 pub struct Crtc6845 {
     pub reg:    [u8; 18],     // Externally-accessible CRTC register file
     reg_select: CrtcRegister, // Selected CRTC register
@@ -150,8 +180,10 @@ pub struct Crtc6845 {
     hsc_c3l: u8,
     vtac_c5: u8,
     in_vta: bool,
-    vma: u16,   // VMA register - Video memory address
-    vma_t: u16, // VMA' register - Video memory address temporary
+    odd_field: bool,    // True if we are scanning the odd field of an interlaced frame
+    in_half_line: bool, // True if we are in the extra half scanline that ends an even interlaced field
+    vma: u16,           // VMA register - Video memory address
+    vma_t: u16,         // VMA' register - Video memory address temporary
 
     hsync_target: u8,
     status: CrtcStatus,
@@ -187,6 +219,8 @@ impl Crtc6845 {
             hsc_c3l: 0,
             vtac_c5: 0,
             in_vta: false,
+            odd_field: false,
+            in_half_line: false,
             vma: 0,
             vma_t: 0,
 
@@ -385,6 +419,11 @@ impl Crtc6845 {
     }
 
     #[inline]
+    /// Return the index of the currently selected register.
+    pub fn selected_register(&self) -> usize {
+        self.reg_select as usize
+    }
+
     pub fn start_address(&self) -> u16 {
         self.start_address_latch
     }
@@ -399,6 +438,38 @@ impl Crtc6845 {
         self.vlc_c9
     }
 
+    /// Return true if the CRTC is scanning out the odd field of an interlaced frame.
+    #[inline]
+    pub fn odd_field(&self) -> bool {
+        self.odd_field
+    }
+
+    /// Return true if R8 specifies interlace sync or interlace sync and video mode.
+    #[inline]
+    pub fn interlaced(&self) -> bool {
+        self.reg[8] & INTERLACE_SYNC != 0
+    }
+
+    /// Return true if R8 specifies interlace sync and video mode. In this mode each field scans out
+    /// alternate lines of each character row.
+    #[inline]
+    fn interlace_video(&self) -> bool {
+        self.reg[8] & 0x03 == INTERLACE_SYNC_VIDEO
+    }
+
+    /// Return the first scanline of a character row for the current field.
+    #[inline]
+    fn row_start_line(&self) -> u8 {
+        (self.interlace_video() && self.odd_field) as u8
+    }
+
+    /// Return the last scanline of a character row for the current field. In interlace sync and video
+    /// mode R9 holds the number of scanlines per row minus two, and the odd field ends one line later.
+    #[inline]
+    fn row_end_line(&self) -> u8 {
+        self.reg[9] + self.row_start_line()
+    }
+
     pub fn status(&self) -> &CrtcStatus {
         &self.status
     }
@@ -562,7 +633,7 @@ impl Crtc6845 {
 
         if self.hcc_c0 == self.reg[1] {
             // C0 == R1 (HorizontalDisplayed): Entering right overscan.
-            if self.vlc_c9 == self.reg[9] {
+            if self.vlc_c9 == self.row_end_line() {
                 // C9 == R9 (MaximumScanlineAddress): We are at the last character row
                 // Save VMA in VMA'
                 self.vma_t = self.vma;
@@ -596,7 +667,8 @@ impl Crtc6845 {
             // Reset Horizontal Character Counter and increment character row counter
             self.hcc_c0 = 0;
             self.status.hborder = false;
-            self.vlc_c9 += 1;
+            // In interlace sync and video mode, each field scans every other line of the character row.
+            self.vlc_c9 += if self.interlace_video() { 2 } else { 1 };
             // Return video memory address to starting position for next character row
             self.vma = self.vma_t;
 
@@ -611,9 +683,9 @@ impl Crtc6845 {
                 }
             }
 
-            if self.vlc_c9 > self.reg[9] {
+            if self.vlc_c9 > self.row_end_line() {
                 // C9 == R9 (MaxScanlineAddress): We finished drawing this row of characters
-                self.vlc_c9 = 0;
+                self.vlc_c9 = self.row_start_line();
                 // Advance Vertical Character Counter
                 self.vcc_c4 = self.vcc_c4.wrapping_add(1);
                 // Set vma to starting position for next character row
@@ -658,28 +730,107 @@ impl Crtc6845 {
                     // C5 == R5 (VerticalTotalAdjust): We are at the end of the top overscan.
                     self.in_vta = false;
                     self.vtac_c5 = 0;
-                    self.hcc_c0 = 0;
-                    self.vcc_c4 = 0;
-                    self.vlc_c9 = 0;
-                    self.char_col = 0;
-                    self.start_address_latch = self.start_address;
-                    self.vma = self.start_address;
-                    self.vma_t = self.vma;
-                    self.status.den = true;
-                    self.status.vborder = false;
-                    self.status.vblank = false;
 
-                    // Load first char + attr
-                    //self.set_char_addr();
+                    if self.interlaced() && !self.odd_field {
+                        // An even interlaced field is extended by half a scanline.
+                        self.in_half_line = true;
+                    }
+                    else {
+                        self.begin_frame();
+                    }
                 }
             }
         }
 
+        if self.in_half_line && self.hcc_c0 as u16 == (self.reg[0] as u16).div_ceil(2) {
+            // We have completed the extra half scanline of an even field. The odd field begins on the next character, half a
+            // scanline out of phase with hsync, which is what makes the monitor offset its lines.
+            self.in_half_line = false;
+            self.begin_frame();
+        }
+
         self.status.cursor = self.cursor();
 
         (&self.status, self.vma)
     }
 
+    /// Reset the vertical counters and latch the start address for the next frame. In interlaced modes,
+    /// this also flips the current field.
+    fn begin_frame(&mut self) {
+        self.odd_field = self.interlaced() && !self.odd_field;
+        self.hcc_c0 = 0;
+        self.vcc_c4 = 0;
+        self.vlc_c9 = self.row_start_line();
+        self.char_col = 0;
+        self.start_address_latch = self.start_address;
+        self.vma = self.start_address;
+        self.vma_t = self.vma;
+        self.status.den = true;
+        self.status.vborder = false;
+        self.status.vblank = false;
+
+        // Load first char + attr
+        //self.set_char_addr();
+    }
+
+    pub(crate) fn save_state(&self) -> Crtc6845SavedState {
+        Crtc6845SavedState {
+            reg: self.reg,
+            reg_select: self.reg_select as u8,
+            start_address_latch: self.start_address_latch,
+            lightpen_position: self.lightpen_position,
+            blink_state: self.blink_state,
+            cursor_blink_ct: self.cursor_blink_ct,
+            hcc_c0: self.hcc_c0,
+            char_col: self.char_col,
+            vlc_c9: self.vlc_c9,
+            vcc_c4: self.vcc_c4,
+            vsc_c3h: self.vsc_c3h,
+            hsc_c3l: self.hsc_c3l,
+            vtac_c5: self.vtac_c5,
+            in_vta: self.in_vta,
+            odd_field: self.odd_field,
+            in_half_line: self.in_half_line,
+            vma: self.vma,
+            vma_t: self.vma_t,
+            hsync_target: self.hsync_target,
+            status: self.status,
+            in_last_vblank_line: self.in_last_vblank_line,
+        }
+    }
+
+    /// Restore a saved state. The writable registers are replayed so that the cursor and start
+    /// address values derived from them are recalculated.
+    pub(crate) fn load_state(&mut self, state: Crtc6845SavedState) {
+        for (idx, byte) in state.reg.iter().enumerate().take(LightPenPositionH as usize) {
+            self.select_register(idx);
+            self.write_register(*byte);
+        }
+        self.reg[LightPenPositionH as usize] = state.reg[LightPenPositionH as usize];
+        self.reg[LightPenPositionL as usize] = state.reg[LightPenPositionL as usize];
+        self.select_register(state.reg_select as usize);
+
+        self.start_address_latch = state.start_address_latch;
+        self.lightpen_position = state.lightpen_position;
+        self.blink_state = state.blink_state;
+        self.cursor_blink_ct = state.cursor_blink_ct;
+        self.hcc_c0 = state.hcc_c0;
+        self.char_col = state.char_col;
+        self.vlc_c9 = state.vlc_c9;
+        self.vcc_c4 = state.vcc_c4;
+        self.vsc_c3h = state.vsc_c3h;
+        self.hsc_c3l = state.hsc_c3l;
+        self.vtac_c5 = state.vtac_c5;
+        self.in_vta = state.in_vta;
+        self.odd_field = state.odd_field;
+        self.in_half_line = state.in_half_line;
+        self.vma = state.vma;
+        self.vma_t = state.vma_t;
+        self.hsync_target = state.hsync_target;
+        self.status = state.status;
+        self.in_last_vblank_line = state.in_last_vblank_line;
+    }
+
     #[rustfmt::skip]
     pub fn get_reg_state(&self) -> Vec<(String, VideoCardStateEntry)> {
         let mut crtc_vec = Vec::new();
@@ -693,6 +844,7 @@ impl Crtc6845 {
         push_reg_str!(crtc_vec, VerticalDisplayed, "[R6]", self.reg[6]);
         push_reg_str!(crtc_vec, VerticalSync, "[R7]", self.reg[7]);
         push_reg_str!(crtc_vec, InterlaceMode, "[R8]", self.reg[8]);
+        crtc_vec.push(("Field".to_string(), VideoCardStateEntry::String(if self.odd_field { "Odd" } else { "Even" }.to_string())));
         push_reg_str!(crtc_vec, MaximumScanlineAddress, "[R9]", self.reg[9]);
         push_reg_str!(crtc_vec, CursorStartLine, "[R10]", self.reg[10]);
         push_reg_str!(crtc_vec, CursorEndLine, "[R11]", self.reg[11]);
@@ -705,3 +857,74 @@ impl Crtc6845 {
         crtc_vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crtc_write(crtc: &mut Crtc6845, reg: u8, data: u8) {
+        crtc.port_write(0, reg);
+        crtc.port_write(1, data);
+    }
+
+    #[test]
+    fn test_interlace_sync_and_video() {
+        let mut crtc = Crtc6845::new(TraceLogger::None);
+        let mut hblank_callback = || 0u8;
+
+        // 10 characters per line, 4 character rows of 8 scanlines, no hblank or vsync.
+        for (reg, data) in [
+            (0, 9),
+            (1, 8),
+            (2, 20),
+            (4, 3),
+            (5, 0),
+            (6, 4),
+            (7, 0x7F),
+            (8, 0x03),
+            (9, 6),
+        ] {
+            crtc_write(&mut crtc, reg, data);
+        }
+
+        // Run into the first interlaced frame.
+        while !crtc.odd_field() {
+            crtc.tick(&mut hblank_callback);
+        }
+
+        let mut field_lens = [0u32; 4];
+        let mut field_lines = [[false; 8]; 2];
+        for field_len in field_lens.iter_mut() {
+            let odd = crtc.odd_field();
+            while crtc.odd_field() == odd {
+                let den = crtc.tick(&mut hblank_callback).0.den;
+                if den {
+                    field_lines[crtc.odd_field() as usize][crtc.vlc() as usize] = true;
+                }
+                *field_len += 1;
+            }
+        }
+
+        // Each field scans alternate lines of each character row.
+        assert_eq!(field_lines[0], [true, false, true, false, true, false, true, false]);
+        assert_eq!(field_lines[1], [false, true, false, true, false, true, false, true]);
+
+        // The even field is half a scanline longer than the odd field.
+        assert_eq!(field_lens[0], field_lens[2]);
+        assert_eq!(field_lens[1], field_lens[3]);
+        assert_eq!(field_lens[1] - field_lens[0], 5);
+
+        // Leaving interlace mode returns to the even field and normal row scanning.
+        crtc_write(&mut crtc, 8, 0x00);
+        crtc_write(&mut crtc, 9, 7);
+        while crtc.odd_field() || crtc.vlc() != 0 {
+            crtc.tick(&mut hblank_callback);
+        }
+        let mut lines = [false; 8];
+        for _ in 0..80 {
+            crtc.tick(&mut hblank_callback);
+            lines[crtc.vlc() as usize] = true;
+        }
+        assert_eq!(lines, [true; 8]);
+    }
+}
@@ -97,6 +97,10 @@ impl IoDevice for MDACard {
 
         //self.rw_op(ticks, data, port as u32, RwSlotType::Io);
 
+        if let Some(reg) = self.get_io_register(port) {
+            self.reg_shadow.record(reg.group, reg.index, data);
+        }
+
         if (port & CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
             // Write is to CRTC register.
             self.crtc.port_write(port, data);
@@ -42,9 +42,12 @@ mod io;
 mod attr;
 mod draw;
 mod mmio;
+mod state;
 mod tablegen;
 mod videocard;
 
+pub use state::MdaState;
+
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
     device_traits::videocard::*,
@@ -347,7 +350,7 @@ macro_rules! trace_regs {
 }
 
 use crate::devices::{
-    lpt_port::ParallelPort,
+    lpt_port::{ParallelPort, ParallelPortMode},
     mc6845::{Crtc6845, CrtcStatus, HBlankCallback},
     mda::io::LPT_DEFAULT_IO_BASE,
 };
@@ -455,6 +458,7 @@ pub struct MDACard {
     clocks_accum: u32,
 
     mem: Box<[u8; MDA_MEM_SIZE]>,
+    reg_shadow: VideoRegisterShadow,
 
     back_buf: usize,
     front_buf: usize,
@@ -608,6 +612,7 @@ impl Default for MDACard {
             pixel_clocks_owed: 0,
 
             mem: vec![0; MDA_MEM_SIZE].into_boxed_slice().try_into().unwrap(),
+            reg_shadow: VideoRegisterShadow::default(),
 
             back_buf:  1,
             front_buf: 0,
@@ -643,7 +648,12 @@ impl Default for MDACard {
 }
 
 impl MDACard {
-    pub fn new(trace_logger: TraceLogger, clock_mode: ClockingMode, lpt: bool, video_frame_debug: bool) -> Self {
+    pub fn new(
+        trace_logger: TraceLogger,
+        clock_mode: ClockingMode,
+        lpt: Option<ParallelPortMode>,
+        video_frame_debug: bool,
+    ) -> Self {
         let mut mda = Self::default();
 
         mda.trace_logger = trace_logger;
@@ -656,9 +666,9 @@ impl MDACard {
             mda.clock_mode = clock_mode;
         }
 
-        if lpt {
+        if let Some(lpt_mode) = lpt {
             // None IRQ will use default which is 7, correct for MDA LPT
-            mda.lpt = Some(ParallelPort::new(None, TraceLogger::None));
+            mda.lpt = Some(ParallelPort::new(None, lpt_mode, TraceLogger::None));
         }
 
         // MDA does not need to cut hblank short for any reason, so always return a big value
@@ -1283,3 +1293,62 @@ impl MDACard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{io::*, *};
+    use crate::{
+        bus::{IoDevice, MemoryMappedDevice},
+        device_traits::devicestate::DeviceState,
+    };
+
+    fn new_mda() -> MDACard {
+        MDACard::new(TraceLogger::None, ClockingMode::Default, None, false)
+    }
+
+    fn crtc_write(mda: &mut MDACard, reg: u8, data: u8) {
+        let delta = DeviceRunTimeUnit::Microseconds(0.0);
+        IoDevice::write_u8(mda, CRTC_REGISTER_SELECT2, reg, None, delta);
+        IoDevice::write_u8(mda, CRTC_REGISTER2, data, None, delta);
+    }
+
+    #[test]
+    fn test_mda_state_roundtrip() {
+        let mut mda = new_mda();
+        let delta = DeviceRunTimeUnit::Microseconds(0.0);
+
+        // 80x25 text mode.
+        for (reg, byte) in [0x61, 0x50, 0x52, 0x0F, 0x19, 0x06, 0x19, 0x19, 0x02, 0x0D, 0x0B, 0x0C, 0x00, 0x00]
+            .into_iter()
+            .enumerate()
+        {
+            crtc_write(&mut mda, reg as u8, byte);
+        }
+        IoDevice::write_u8(&mut mda, MDA_MODE_CONTROL_REGISTER, 0x29, None, delta);
+        for i in 0..MDA_MEM_SIZE {
+            MemoryMappedDevice::mmio_write_u8(&mut mda, MDA_MEM_ADDRESS + i, (i * 7) as u8, 0);
+        }
+
+        // Save in the middle of a frame.
+        mda.run(DeviceRunTimeUnit::Microseconds(7_000.0), &mut None);
+        crtc_write(&mut mda, 0x0E, 0x01);
+
+        let mut restored = new_mda();
+        restored.load_state(mda.save_state()).unwrap();
+        assert_eq!(restored.dump_state(), mda.dump_state());
+        assert_eq!(
+            restored.get_register_shadow().unwrap().group(VideoRegisterGroup::Mode),
+            &[0x29]
+        );
+
+        // The first frame completed after the restore was begun before it, so compare the display
+        // once a second one has completed.
+        let frame_count = mda.get_frame_count();
+        while mda.get_frame_count() < frame_count + 2 {
+            mda.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            restored.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            assert_eq!(restored.dump_state(), mda.dump_state());
+        }
+        assert!(restored.get_display_buf() == mda.get_display_buf());
+    }
+}
@@ -0,0 +1,203 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::mda::state.rs
+
+    Implementation of the DeviceState interface for the IBM MDA.
+
+*/
+
+use super::*;
+use crate::{
+    device_traits::devicestate::DeviceState,
+    devices::{lpt_port::ParallelPortState, mc6845::Crtc6845SavedState},
+};
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+/// The serializable state of the MDA: its registers, the CRTC and monitor counters, the parallel
+/// port if one is installed, and video memory. The render buffers are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MdaState {
+    // Registers
+    pub mode_byte: u8,
+    pub crtc: Crtc6845SavedState,
+    pub lightpen_latch: bool,
+    pub lightpen_addr: usize,
+    pub lpt: Option<ParallelPortState>,
+
+    // Character being drawn
+    pub cur_char: u8,
+    pub cur_attr: u8,
+    pub cur_fg: u8,
+    pub cur_bg: u8,
+    pub cur_blink: bool,
+    pub cur_ul: bool,
+    pub char_col: u8,
+    pub vma: usize,
+    pub cursor_blink_state: bool,
+    pub text_blink_state: bool,
+    pub last_bit: bool,
+    pub snow_char: u8,
+    pub dirty_snow: bool,
+    pub last_bus_value: u8,
+    pub last_bus_addr: usize,
+
+    // Monitor
+    pub beam_x: u32,
+    pub beam_y: u32,
+    pub in_monitor_hsync: bool,
+    pub monitor_hsc: u32,
+    pub scanline: u32,
+    pub missed_hsyncs: u32,
+    pub rba: usize,
+
+    // Clocking
+    pub cycles: u64,
+    pub last_vsync_cycles: u64,
+    pub cur_screen_cycles: u64,
+    pub cycles_per_vsync: u64,
+    pub sink_cycles: u32,
+    pub ticks_advanced: u32,
+    pub pixel_clocks_owed: u32,
+    pub ticks_accum: f64,
+    pub clocks_accum: u32,
+    pub frame_count: u64,
+
+    pub mem: Vec<u8>,
+}
+
+impl DeviceState for MDACard {
+    type State = MdaState;
+
+    fn save_state(&self) -> MdaState {
+        MdaState {
+            mode_byte: self.mode.into_bytes()[0],
+            crtc: self.crtc.save_state(),
+            lightpen_latch: self.lightpen_latch,
+            lightpen_addr: self.lightpen_addr,
+            lpt: self.lpt.as_ref().map(|lpt| lpt.save_state()),
+
+            cur_char: self.cur_char,
+            cur_attr: self.cur_attr,
+            cur_fg: self.cur_fg,
+            cur_bg: self.cur_bg,
+            cur_blink: self.cur_blink,
+            cur_ul: self.cur_ul,
+            char_col: self.char_col,
+            vma: self.vma,
+            cursor_blink_state: self.cursor_blink_state,
+            text_blink_state: self.text_blink_state,
+            last_bit: self.last_bit,
+            snow_char: self.snow_char,
+            dirty_snow: self.dirty_snow,
+            last_bus_value: self.last_bus_value,
+            last_bus_addr: self.last_bus_addr,
+
+            beam_x: self.beam_x,
+            beam_y: self.beam_y,
+            in_monitor_hsync: self.in_monitor_hsync,
+            monitor_hsc: self.monitor_hsc,
+            scanline: self.scanline,
+            missed_hsyncs: self.missed_hsyncs,
+            rba: self.rba,
+
+            cycles: self.cycles,
+            last_vsync_cycles: self.last_vsync_cycles,
+            cur_screen_cycles: self.cur_screen_cycles,
+            cycles_per_vsync: self.cycles_per_vsync,
+            sink_cycles: self.sink_cycles,
+            ticks_advanced: self.ticks_advanced,
+            pixel_clocks_owed: self.pixel_clocks_owed,
+            ticks_accum: self.ticks_accum,
+            clocks_accum: self.clocks_accum,
+            frame_count: self.frame_count,
+
+            mem: self.mem.to_vec(),
+        }
+    }
+
+    fn load_state(&mut self, state: MdaState) -> Result<(), Error> {
+        if state.mem.len() != MDA_MEM_SIZE {
+            bail!("MDA state has {} bytes of VRAM, expected {}", state.mem.len(), MDA_MEM_SIZE);
+        }
+        match (&mut self.lpt, state.lpt) {
+            (Some(lpt), Some(lpt_state)) => lpt.load_state(lpt_state)?,
+            (None, None) => {}
+            (Some(_), None) => bail!("MDA state has no parallel port, but this card has one"),
+            (None, Some(_)) => bail!("MDA state has a parallel port, but this card has none"),
+        }
+
+        // The register shadow is rebuilt from the restored registers.
+        self.reg_shadow.clear();
+        self.crtc.load_state(state.crtc);
+        for (reg, byte) in self.crtc.reg.iter().enumerate().take(16) {
+            self.reg_shadow.record(VideoRegisterGroup::Crtc, reg as u8, *byte);
+        }
+        self.handle_mode_register(state.mode_byte);
+        self.reg_shadow.record(VideoRegisterGroup::Mode, 0, state.mode_byte);
+        self.lightpen_latch = state.lightpen_latch;
+        self.lightpen_addr = state.lightpen_addr;
+
+        self.cur_char = state.cur_char;
+        self.cur_attr = state.cur_attr;
+        self.cur_fg = state.cur_fg;
+        self.cur_bg = state.cur_bg;
+        self.cur_blink = state.cur_blink;
+        self.cur_ul = state.cur_ul;
+        self.char_col = state.char_col;
+        self.vma = state.vma;
+        self.cursor_blink_state = state.cursor_blink_state;
+        self.text_blink_state = state.text_blink_state;
+        self.last_bit = state.last_bit;
+        self.snow_char = state.snow_char;
+        self.dirty_snow = state.dirty_snow;
+        self.last_bus_value = state.last_bus_value;
+        self.last_bus_addr = state.last_bus_addr;
+
+        self.beam_x = state.beam_x;
+        self.beam_y = state.beam_y;
+        self.in_monitor_hsync = state.in_monitor_hsync;
+        self.monitor_hsc = state.monitor_hsc;
+        self.scanline = state.scanline;
+        self.missed_hsyncs = state.missed_hsyncs;
+        self.rba = state.rba;
+
+        self.cycles = state.cycles;
+        self.last_vsync_cycles = state.last_vsync_cycles;
+        self.cur_screen_cycles = state.cur_screen_cycles;
+        self.cycles_per_vsync = state.cycles_per_vsync;
+        self.sink_cycles = state.sink_cycles;
+        self.ticks_advanced = state.ticks_advanced;
+        self.pixel_clocks_owed = state.pixel_clocks_owed;
+        self.ticks_accum = state.ticks_accum;
+        self.clocks_accum = state.clocks_accum;
+        self.frame_count = state.frame_count;
+
+        self.mem.copy_from_slice(&state.mem);
+        Ok(())
+    }
+}
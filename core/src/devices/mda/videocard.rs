@@ -29,7 +29,7 @@
     Implements the VideoCard trait for the IBM MDA card.
 
 */
-use super::*;
+use super::{io::*, *};
 use crate::{device_traits::videocard::*, devices::pic::Pic};
 
 impl VideoCard for MDACard {
@@ -234,10 +234,18 @@ impl VideoCard for MDACard {
         }
     }
 
+    fn get_glyphs(&self) -> GlyphSet {
+        GlyphSet::from_row_bitmap(MDA_CHAR_CLOCK as u32, CRTC_FONT_HEIGHT as u32, MDA_FONT, MDA_FONT_SPAN)
+    }
+
     fn get_character_height(&self) -> u8 {
         self.crtc.reg[9] + 1
     }
 
+    fn get_text_mode_rows(&self) -> Option<u32> {
+        (!self.mode_graphics).then_some(self.crtc.reg[6] as u32)
+    }
+
     fn get_cga_palette(&self) -> (CGAPalette, bool) {
         (Default::default(), false)
     }
@@ -465,6 +473,18 @@ impl VideoCard for MDACard {
         &DUMMY_PLANE
     }
 
+    fn get_vram_plane_count(&self) -> usize {
+        1
+    }
+
+    fn get_vram(&self, plane: usize) -> Option<&[u8]> {
+        (plane == 0).then(|| &self.mem[..])
+    }
+
+    fn get_register_shadow(&self) -> Option<&VideoRegisterShadow> {
+        Some(&self.reg_shadow)
+    }
+
     fn get_frame_count(&self) -> u64 {
         self.frame_count
     }
@@ -491,6 +511,30 @@ impl VideoCard for MDACard {
         self.trace_logger.flush();
     }
 
+    fn get_io_register(&self, port: u16) -> Option<VideoRegister> {
+        if (port & CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
+            if port & 0x01 == 0 {
+                return None;
+            }
+            let index = self.crtc.selected_register();
+            Some(VideoRegister {
+                group: VideoRegisterGroup::Crtc,
+                index: index as u8,
+                value: Some(self.crtc.reg[index]),
+            })
+        }
+        else if port == MDA_MODE_CONTROL_REGISTER {
+            Some(VideoRegister {
+                group: VideoRegisterGroup::Mode,
+                index: 0,
+                value: Some(self.mode_byte),
+            })
+        }
+        else {
+            None
+        }
+    }
+
     fn get_text_mode_strings(&self) -> Vec<String> {
         let mut strings = Vec::new();
         let start_addr = self.crtc.start_address();
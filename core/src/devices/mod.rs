@@ -31,6 +31,7 @@
 */
 
 pub mod cga;
+pub mod compaq_video;
 #[cfg(feature = "ega")]
 pub mod ega;
 pub mod mda;
@@ -40,6 +41,7 @@ pub mod vga;
 pub mod dma;
 pub mod fdc;
 pub mod floppy_drive;
+pub mod game_port;
 pub mod hdc;
 pub mod keyboard;
 pub mod lpt_port;
@@ -48,4 +50,5 @@ pub mod mouse;
 pub mod pic;
 pub mod pit;
 pub mod ppi;
+pub mod rtc;
 pub mod serial;
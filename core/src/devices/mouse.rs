@@ -31,11 +31,18 @@
 */
 use std::collections::VecDeque;
 
-use crate::devices::serial::SerialPortController;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
 
-// Scale factor for real vs emulated mouse deltas. Need to play with
+use crate::{
+    device_scheduler::earliest_deadline,
+    device_traits::devicestate::DeviceState,
+    devices::serial::SerialPortController,
+};
+
+// Default scale factor for real vs emulated mouse deltas. Need to play with
 // this value until it feels right.
-const MOUSE_SCALE: f64 = 0.25;
+pub const MOUSE_SCALE: f64 = 0.25;
 
 // Microseconds with RTS low before mouse considers itself reset
 const MOUSE_RESET_TIME: f64 = 10_000.0;
@@ -52,15 +59,27 @@ const MOUSE_UPDATE_LO_BITS: u8 = 0b0011_1111;
 
 #[allow(dead_code)]
 pub struct Mouse {
-    updates: VecDeque<MouseUpdate>,
+    updates: VecDeque<MouseReport>,
     rts: bool,
     rts_low_timer: f64,
     dtr: bool,
     port: usize,
+    scale: f64,
+    invert_x: bool,
+    invert_y: bool,
+    report_interval: f64, // Minimum time between reports (us). 0 sends a report every update.
+    report_timer: f64,
 }
 
-pub enum MouseUpdate {
-    Update(u8, u8, u8),
+/// A report waiting to be sent. Motion from host updates is added to the last report waiting,
+/// as long as the button state is the same, so no motion is lost while the report interval
+/// holds reports back.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MouseReport {
+    pub l_button: bool,
+    pub r_button: bool,
+    pub delta_x:  i32,
+    pub delta_y:  i32,
 }
 
 impl Mouse {
@@ -71,12 +90,51 @@ impl Mouse {
             rts_low_timer: 0.0,
             dtr: false,
             port,
+            scale: MOUSE_SCALE,
+            invert_x: false,
+            invert_y: false,
+            report_interval: 0.0,
+            report_timer: 0.0,
         }
     }
 
+    /// Return the serial port the mouse is attached to.
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    /// Set the scale factor applied to host mouse deltas.
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    pub fn get_scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Set whether the X and Y axes are inverted.
+    pub fn set_invert(&mut self, invert_x: bool, invert_y: bool) {
+        self.invert_x = invert_x;
+        self.invert_y = invert_y;
+    }
+
+    /// Set the minimum interval between reports sent to the serial port, in milliseconds.
+    /// Motion from updates received in the meantime is combined into the next report.
+    pub fn set_report_interval(&mut self, ms: f64) {
+        self.report_interval = ms.max(0.0) * 1000.0;
+    }
+
+    /// Report host mouse motion and button state to the mouse.
     pub fn update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) {
-        let mut scaled_x = delta_x * MOUSE_SCALE;
-        let mut scaled_y = delta_y * MOUSE_SCALE;
+        let mut scaled_x = delta_x * self.scale;
+        let mut scaled_y = delta_y * self.scale;
+
+        if self.invert_x {
+            scaled_x = -scaled_x;
+        }
+        if self.invert_y {
+            scaled_y = -scaled_y;
+        }
 
         // Mouse scale can cause fractional integer updates. Adjust to Minimum movement of one unit
         if scaled_x > 0.0 && scaled_x < 1.0 {
@@ -91,9 +149,26 @@ impl Mouse {
         if scaled_y < 0.0 && scaled_y > -1.0 {
             scaled_y = -1.0;
         }
-        let delta_x_i8 = scaled_x as i8;
-        let delta_y_i8 = scaled_y as i8;
 
+        // Queue update
+        let delta_x = scaled_x as i32;
+        let delta_y = scaled_y as i32;
+        match self.updates.back_mut() {
+            Some(report) if report.l_button == l_button_pressed && report.r_button == r_button_pressed => {
+                report.delta_x += delta_x;
+                report.delta_y += delta_y;
+            }
+            _ => self.updates.push_back(MouseReport {
+                l_button: l_button_pressed,
+                r_button: r_button_pressed,
+                delta_x,
+                delta_y,
+            }),
+        }
+    }
+
+    /// Encode a report into the three bytes sent over the serial port.
+    fn encode_report(l_button_pressed: bool, r_button_pressed: bool, delta_x: i8, delta_y: i8) -> [u8; 3] {
         let mut byte1 = MOUSE_UPDATE_STARTBIT;
 
         if l_button_pressed {
@@ -111,32 +186,66 @@ impl Mouse {
         }
 
         // Pack HO 2 bits of Y into byte1
-        byte1 |= ((delta_y_i8 as u8) & MOUSE_UPDATE_HO_BITS) >> 4;
+        byte1 |= ((delta_y as u8) & MOUSE_UPDATE_HO_BITS) >> 4;
         // Pack HO 2 bits of X into byte1;
-        byte1 |= ((delta_x_i8 as u8) & MOUSE_UPDATE_HO_BITS) >> 6;
+        byte1 |= ((delta_x as u8) & MOUSE_UPDATE_HO_BITS) >> 6;
 
         // LO 6 bits of X into byte 2
-        let byte2 = (delta_x_i8 as u8) & MOUSE_UPDATE_LO_BITS;
+        let byte2 = (delta_x as u8) & MOUSE_UPDATE_LO_BITS;
         // LO 6 bits of Y into byte 3
-        let byte3 = (delta_y_i8 as u8) & MOUSE_UPDATE_LO_BITS;
+        let byte3 = (delta_y as u8) & MOUSE_UPDATE_LO_BITS;
 
-        // Queue update
+        [byte1, byte2, byte3]
+    }
 
-        self.updates.push_back(MouseUpdate::Update(byte1, byte2, byte3));
-        /*
-        let mut serial = self.serial_ctrl.borrow_mut();
-        serial.queue_byte(MOUSE_PORT, byte1);
-        serial.queue_byte(MOUSE_PORT, byte2);
-        serial.queue_byte(MOUSE_PORT, byte3);*/
+    /// Called when the mouse is plugged into a live serial port. A Microsoft mouse is powered
+    /// from the port's control lines, so if RTS is already high it powers up immediately and
+    /// identifies itself.
+    pub fn attach(&mut self, serial: &mut SerialPortController) {
+        self.rts = serial.get_rts(self.port);
+        self.rts_low_timer = 0.0;
+        if self.rts {
+            log::trace!("Sending power-on ident byte: {:02X}", MOUSE_RESET_ACK_BYTE);
+            serial.queue_byte(self.port, MOUSE_RESET_ACK_BYTE);
+        }
+    }
+
+    /// Return the time in microseconds until the mouse next needs to be run, or None if it is idle.
+    /// The mouse is busy while it has updates to report, or while timing how long RTS has been low.
+    /// Changes in RTS are picked up when the serial port is run.
+    pub fn next_deadline(&self) -> Option<f64> {
+        let mut deadline = None;
+
+        if !self.updates.is_empty() {
+            deadline = Some((self.report_interval - self.report_timer).max(0.0));
+        }
+        if !self.rts && self.rts_low_timer <= MOUSE_RESET_TIME {
+            deadline = earliest_deadline(deadline, Some(MOUSE_RESET_TIME - self.rts_low_timer));
+        }
+        deadline
     }
 
     /// Run the mouse device for the specified number of microseconds
     pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
-        // Send a queued update.
-        if let Some(MouseUpdate::Update(byte1, byte2, byte3)) = self.updates.pop_front() {
-            serial.queue_byte(self.port, byte1);
-            serial.queue_byte(self.port, byte2);
-            serial.queue_byte(self.port, byte3);
+        // Send a queued update if the report interval has elapsed.
+        self.report_timer += us;
+        if self.report_timer >= self.report_interval {
+            if let Some(report) = self.updates.front_mut() {
+                // A report can only carry 8 bits of motion per axis. Any excess is left for the
+                // next report.
+                let delta_x = report.delta_x.clamp(i8::MIN as i32, i8::MAX as i32);
+                let delta_y = report.delta_y.clamp(i8::MIN as i32, i8::MAX as i32);
+                report.delta_x -= delta_x;
+                report.delta_y -= delta_y;
+                let bytes = Mouse::encode_report(report.l_button, report.r_button, delta_x as i8, delta_y as i8);
+                if report.delta_x == 0 && report.delta_y == 0 {
+                    self.updates.pop_front();
+                }
+                for byte in bytes {
+                    serial.queue_byte(self.port, byte);
+                }
+                self.report_timer = 0.0;
+            }
         }
 
         // Check RTS line for mouse reset
@@ -166,3 +275,103 @@ impl Mouse {
         }
     }
 }
+
+/// The serializable state of the mouse: its view of the serial control lines and the reports
+/// it has yet to send. The port, scale, axis inversion and report interval are configuration,
+/// and are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MouseState {
+    pub rts: bool,
+    pub rts_low_timer: f64,
+    pub dtr: bool,
+    pub report_timer: f64,
+    pub updates: Vec<MouseReport>,
+}
+
+impl DeviceState for Mouse {
+    type State = MouseState;
+
+    fn save_state(&self) -> MouseState {
+        MouseState {
+            rts: self.rts,
+            rts_low_timer: self.rts_low_timer,
+            dtr: self.dtr,
+            report_timer: self.report_timer,
+            updates: self.updates.iter().copied().collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: MouseState) -> Result<(), Error> {
+        self.rts = state.rts;
+        self.rts_low_timer = state.rts_low_timer;
+        self.dtr = state.dtr;
+        self.report_timer = state.report_timer;
+        self.updates = state.updates.into_iter().collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mouse_state_roundtrip() {
+        let mut mouse = Mouse::new(0);
+        mouse.set_report_interval(10.0);
+        mouse.update(true, false, 8.0, -8.0);
+        mouse.update(false, false, 4.0, 0.0);
+
+        let json = mouse.serialize_state().unwrap();
+
+        let mut restored = Mouse::new(0);
+        restored.set_report_interval(10.0);
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), mouse.dump_state());
+        assert_eq!(restored.updates.len(), 2);
+        assert_eq!(restored.next_deadline(), mouse.next_deadline());
+    }
+
+    fn sent_bytes(serial: &SerialPortController) -> Vec<u8> {
+        serial.save_state().ports[0].rx_queue.clone()
+    }
+
+    #[test]
+    fn test_report_interval_accumulates() {
+        let mut serial = SerialPortController::new();
+        let mut mouse = Mouse::new(0);
+        mouse.set_scale(1.0);
+        mouse.set_report_interval(10.0);
+
+        // Motion between reports is combined into a single report.
+        mouse.update(false, false, 5.0, -1.0);
+        mouse.update(false, false, 7.0, -2.0);
+        mouse.update(false, false, 3.0, 0.0);
+        mouse.run(&mut serial, 10_000.0);
+        assert_eq!(sent_bytes(&serial), Mouse::encode_report(false, false, 15, -3));
+        assert!(mouse.updates.is_empty());
+
+        // A button change starts a new report, so the click is not lost.
+        let mut serial = SerialPortController::new();
+        mouse.update(false, false, 1.0, 0.0);
+        mouse.update(true, false, 2.0, 0.0);
+        mouse.update(true, false, 2.0, 0.0);
+        mouse.run(&mut serial, 10_000.0);
+        mouse.run(&mut serial, 5_000.0);
+        assert_eq!(sent_bytes(&serial), Mouse::encode_report(false, false, 1, 0));
+        mouse.run(&mut serial, 5_000.0);
+        let mut expected = Mouse::encode_report(false, false, 1, 0).to_vec();
+        expected.extend(Mouse::encode_report(true, false, 4, 0));
+        assert_eq!(sent_bytes(&serial), expected);
+
+        // Motion too large for one report is carried over to the next.
+        let mut serial = SerialPortController::new();
+        mouse.update(false, false, 200.0, -150.0);
+        mouse.run(&mut serial, 10_000.0);
+        mouse.run(&mut serial, 10_000.0);
+        let mut expected = Mouse::encode_report(false, false, 127, -128).to_vec();
+        expected.extend(Mouse::encode_report(false, false, 73, -22));
+        assert_eq!(sent_bytes(&serial), expected);
+        assert!(mouse.updates.is_empty());
+    }
+}
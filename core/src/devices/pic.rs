@@ -34,15 +34,24 @@
 
 //use std::io::Read;
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::devicestate::DeviceState,
+};
 
 //pub const PIC_INTERRUPT_OFFSET: u8 = 8;
 
 pub const PIC_COMMAND_PORT: u16 = 0x20;
 pub const PIC_DATA_PORT: u16 = 0x21;
+pub const PIC2_COMMAND_PORT: u16 = 0xA0;
+pub const PIC2_DATA_PORT: u16 = 0xA1;
+pub const PIC_CASCADE_IRQ: u8 = 2; // IR line of the primary PIC the secondary PIC is attached to on the AT
 
 const ICW1_ICW4_NEEDED: u8 = 0b0000_0001; // Bit set if a 4th control world is required (not supported)
-const ICW1_SINGLE_MODE: u8 = 0b0000_0010; // Bit is set if PIC is operating in single mode (only supported configuration)
+const ICW1_SINGLE_MODE: u8 = 0b0000_0010; // Bit is set if PIC is operating in single mode, otherwise cascaded
 const ICW1_ADI: u8 = 0b0000_0100; // Bit is set if PIC is using a call address interval of 4, otherwise 8
 const ICW1_LTIM: u8 = 0b0000_1000; // Bit is set if PIC is in Level Triggered Mode
 const ICW1_IS_ICW1: u8 = 0b0001_0000; // Bit determines if input is ICW1
@@ -63,19 +72,21 @@ const OCW3_RR_COMMAND: u8 = 0b0000_0011;
 
 const SPURIOUS_INTERRUPT: u8 = 7;
 
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum InitializationState {
     Normal,        // Normal operation, can receive an ICW1 at any point
     ExpectingICW2, // In initialization sequence, expecting ICW2
+    ExpectingICW3, // In initialization sequence, expecting ICW3 (cascade mode only)
     ExpectingICW4, // In initialization sequence, expecting ICW4
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TriggerMode {
     Edge,
     Level,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum ReadSelect {
     ISR,
     IRR,
@@ -98,8 +109,40 @@ impl InterruptStats {
     }
 }
 
+/// The timing of a delivered interrupt, as recorded by the interrupt trace. Times are in
+/// system ticks since the PIC was created.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IrqTraceEntry {
+    pub irq: u8,
+    pub vector: u8,
+    /// Time the device raised the IR line.
+    pub request_ticks: u64,
+    /// Time the PIC raised INTR.
+    pub intr_ticks: u64,
+    /// Time the CPU acknowledged the interrupt (INTA).
+    pub inta_ticks: u64,
+}
+
+impl IrqTraceEntry {
+    /// Time from the IR line being raised to the PIC raising INTR.
+    pub fn intr_latency(&self) -> u64 {
+        self.intr_ticks.saturating_sub(self.request_ticks)
+    }
+
+    /// Time from the PIC raising INTR to the CPU acknowledging the interrupt.
+    pub fn inta_latency(&self) -> u64 {
+        self.inta_ticks.saturating_sub(self.intr_ticks)
+    }
+
+    /// Total time from the IR line being raised to the CPU acknowledging the interrupt.
+    pub fn latency(&self) -> u64 {
+        self.inta_ticks.saturating_sub(self.request_ticks)
+    }
+}
+
 pub type PicRequestFn = fn(&mut Pic, interrupt: u8);
 pub struct Pic {
+    port_base: u16,                  // Base IO port (command port). The data port follows it.
     init_state: InitializationState, // Initialization state for expecting various ICWs
     int_offset: u8,                  // Interrupt Vector Offset (Always 8 on IBM PC)
     imr: u8,                         // Interrupt Mask Register
@@ -119,14 +162,53 @@ pub struct Pic {
     expecting_icw2: bool,
     expecting_icw4: bool, // ICW3 not supported in Single mode operation
     error: bool,          // We encountered an invalid condition or request
+    cascade: bool,        // Cascade mode (ICW1 single mode bit clear)
+    icw3: u8,             // Slave lines (master) or slave ID (slave), as programmed by ICW3
+    cascade_ack: bool,    // The last acknowledged interrupt was on a cascaded slave line
 
     interrupt_stats: Vec<InterruptStats>,
 
     intr_scheduled: bool,
     intr_timer: u32,
+
+    ticks: u64,                            // System ticks elapsed, used to timestamp the interrupt trace
+    trace_enabled: bool,                   // Record the timing of delivered interrupts
+    trace_request_ticks: [Option<u64>; 8], // Time each pending IR line was raised
+    trace_intr_ticks: Option<u64>,         // Time INTR was raised
+    trace: Vec<IrqTraceEntry>,
 }
 
-#[derive(Clone, Default)]
+/// The serializable state of the PIC. Interrupt statistics are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PicState {
+    pub init_state: InitializationState,
+    pub int_offset: u8,
+    pub imr: u8,
+    pub isr: u8,
+    pub irr: u8,
+    pub ir: u8,
+    pub read_select: ReadSelect,
+    pub irq: u8,
+    pub intr: bool,
+    pub buffered: bool,
+    pub nested: bool,
+    pub special_nested: bool,
+    pub polled: bool,
+    pub auto_eoi: bool,
+    pub rotate_on_aeoi: bool,
+    pub trigger_mode: TriggerMode,
+    pub expecting_icw2: bool,
+    pub expecting_icw4: bool,
+    pub error: bool,
+    pub intr_scheduled: bool,
+    pub intr_timer: u32,
+    #[serde(default)]
+    pub cascade: bool,
+    #[serde(default)]
+    pub icw3: u8,
+}
+
+#[derive(Clone, Default, Hash)]
 pub struct PicStringState {
     pub imr: String,
     pub isr: String,
@@ -140,18 +222,18 @@ pub struct PicStringState {
 
 impl IoDevice for Pic {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
-        match port {
-            PIC_COMMAND_PORT => self.handle_command_register_read(),
-            PIC_DATA_PORT => self.handle_data_register_read(),
+        match port.wrapping_sub(self.port_base) {
+            0 => self.handle_command_register_read(),
+            1 => self.handle_data_register_read(),
             _ => unreachable!("PIC: Bad port #"),
         }
     }
     fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
-        match port {
-            PIC_COMMAND_PORT => {
+        match port.wrapping_sub(self.port_base) {
+            0 => {
                 self.handle_command_register_write(data);
             }
-            PIC_DATA_PORT => {
+            1 => {
                 self.handle_data_register_write(data);
             }
             _ => unreachable!("PIC: Bad port #"),
@@ -159,13 +241,79 @@ impl IoDevice for Pic {
     }
 
     fn port_list(&self) -> Vec<u16> {
-        vec![PIC_COMMAND_PORT, PIC_DATA_PORT]
+        vec![self.port_base, self.port_base + 1]
+    }
+}
+
+impl DeviceState for Pic {
+    type State = PicState;
+
+    fn save_state(&self) -> PicState {
+        PicState {
+            init_state: self.init_state,
+            int_offset: self.int_offset,
+            imr: self.imr,
+            isr: self.isr,
+            irr: self.irr,
+            ir: self.ir,
+            read_select: self.read_select,
+            irq: self.irq,
+            intr: self.intr,
+            buffered: self.buffered,
+            nested: self.nested,
+            special_nested: self.special_nested,
+            polled: self.polled,
+            auto_eoi: self.auto_eoi,
+            rotate_on_aeoi: self.rotate_on_aeoi,
+            trigger_mode: self.trigger_mode,
+            expecting_icw2: self.expecting_icw2,
+            expecting_icw4: self.expecting_icw4,
+            error: self.error,
+            intr_scheduled: self.intr_scheduled,
+            intr_timer: self.intr_timer,
+            cascade: self.cascade,
+            icw3: self.icw3,
+        }
+    }
+
+    fn load_state(&mut self, state: PicState) -> Result<(), Error> {
+        self.init_state = state.init_state;
+        self.int_offset = state.int_offset;
+        self.imr = state.imr;
+        self.isr = state.isr;
+        self.irr = state.irr;
+        self.ir = state.ir;
+        self.read_select = state.read_select;
+        self.irq = state.irq;
+        self.intr = state.intr;
+        self.buffered = state.buffered;
+        self.nested = state.nested;
+        self.special_nested = state.special_nested;
+        self.polled = state.polled;
+        self.auto_eoi = state.auto_eoi;
+        self.rotate_on_aeoi = state.rotate_on_aeoi;
+        self.trigger_mode = state.trigger_mode;
+        self.expecting_icw2 = state.expecting_icw2;
+        self.expecting_icw4 = state.expecting_icw4;
+        self.error = state.error;
+        self.intr_scheduled = state.intr_scheduled;
+        self.intr_timer = state.intr_timer;
+        self.cascade = state.cascade;
+        self.icw3 = state.icw3;
+        Ok(())
     }
 }
 
 impl Pic {
     pub fn new() -> Self {
+        Pic::with_port_base(PIC_COMMAND_PORT)
+    }
+
+    /// Create a PIC decoding its command and data registers at `port_base` and `port_base + 1`.
+    /// The secondary PIC of an AT is located at PIC2_COMMAND_PORT.
+    pub fn with_port_base(port_base: u16) -> Self {
         Self {
+            port_base,
             init_state: InitializationState::Normal,
             int_offset: 0,
             imr: 0xFF, // All IRQs initially masked
@@ -185,10 +333,19 @@ impl Pic {
             expecting_icw2: false,
             expecting_icw4: false,
             error: false,
+            cascade: false,
+            icw3: 0,
+            cascade_ack: false,
             interrupt_stats: vec![InterruptStats::new(); 8],
 
             intr_scheduled: false,
             intr_timer: 0,
+
+            ticks: 0,
+            trace_enabled: false,
+            trace_request_ticks: [None; 8],
+            trace_intr_ticks: None,
+            trace: Vec::new(),
         }
     }
 
@@ -210,6 +367,11 @@ impl Pic {
         self.expecting_icw2 = false;
         self.expecting_icw4 = false;
         self.error = false;
+        self.cascade = false;
+        self.icw3 = 0;
+        self.cascade_ack = false;
+        self.trace_request_ticks = [None; 8];
+        self.trace_intr_ticks = None;
 
         for stat_entry in &mut self.interrupt_stats {
             stat_entry.imr_masked_count = 0;
@@ -233,10 +395,7 @@ impl Pic {
                 log::warn!("PIC: Warning: Received unexpected ICW1: {:02X}", byte);
             }
 
-            if byte & ICW1_SINGLE_MODE == 0 {
-                log::error!("PIC: Error: Chained mode not supported");
-                self.error = true;
-            }
+            self.cascade = byte & ICW1_SINGLE_MODE == 0;
 
             if byte & ICW1_ADI != 0 {
                 log::error!("PIC: Error: 4 byte ADI unsupported");
@@ -291,7 +450,7 @@ impl Pic {
             // Is there a corresponding bit set in the IRR?
             if Pic::check_bit(self.irr, ir) {
                 // Raise INTR for new interrupt.
-                self.intr = true;
+                self.raise_intr();
             }
         }
         else {
@@ -301,11 +460,16 @@ impl Pic {
             // Is there a corresponding bit set in the IRR?
             if Pic::check_bit(self.irr, ir) {
                 // Raise INTR for new interrupt.
-                self.intr = true;
+                self.raise_intr();
             }
         }
     }
 
+    /// Return the Interrupt Request Register.
+    pub fn irr(&self) -> u8 {
+        self.irr
+    }
+
     pub fn get_highest_priority_ir(&self) -> u8 {
         let mask: u8 = 0x01;
         let mut ir = 0;
@@ -360,7 +524,7 @@ impl Pic {
     }
 
     pub fn handle_data_register_write(&mut self, byte: u8) {
-        // Handle ICW2 & ICW4 (ICW3 skipped in Single mode)
+        // Handle ICW2, ICW3 & ICW4 (ICW3 skipped in Single mode)
         match self.init_state {
             InitializationState::Normal => {
                 // We aren't expecting any ICWs, so treat this write as a set of the IMR
@@ -372,9 +536,19 @@ impl Pic {
                 // This value should be an ICW2 based on just receiving an ICW1 on control port
                 log::debug!("PIC: Read ICW2: {:02X}", byte);
                 self.int_offset = byte & ICW2_MASK;
-                self.init_state = InitializationState::ExpectingICW4;
+                self.init_state = match self.cascade {
+                    true => InitializationState::ExpectingICW3,
+                    false => InitializationState::ExpectingICW4,
+                };
                 return;
             }
+            InitializationState::ExpectingICW3 => {
+                // In cascade mode, the master receives a bitfield of IR lines with slaves attached,
+                // and a slave receives its slave ID.
+                log::debug!("PIC: Read ICW3: {:02X}", byte);
+                self.icw3 = byte;
+                self.init_state = InitializationState::ExpectingICW4;
+            }
             InitializationState::ExpectingICW4 => {
                 // This value should be an ICW4 based on receiving an ICW2 (ICW3 skipped in Single mode)
                 log::debug!("PIC: Read ICW4: {:02X}", byte);
@@ -437,6 +611,7 @@ impl Pic {
 
         // Interrupts 0-7 map to bits 0-7 in IMR register
         let intr_bit: u8 = 0x01 << interrupt;
+        self.trace_request(interrupt);
         // Set IR line high and set the request bit in the IRR register
         self.ir |= intr_bit;
         self.irr |= intr_bit;
@@ -452,7 +627,7 @@ impl Pic {
         else {
             // Interrupt is not masked or already in service, process it...
             // (Set INT request line high)
-            self.intr = true;
+            self.raise_intr();
             self.interrupt_stats[interrupt as usize].serviced_count += 1;
         }
     }
@@ -472,6 +647,7 @@ impl Pic {
         // Set the request bit in the IRR register directly.
         // Since the IR line is 'pulsed' we clear it now. It is likely too short to register in any
         // debug display anyway (kb IR is ~100ns)
+        self.trace_request(interrupt);
         self.ir &= !intr_bit;
         self.irr |= intr_bit;
 
@@ -485,7 +661,7 @@ impl Pic {
         }
         else {
             // Interrupt is not masked or already in service, elevate it...
-            self.intr = true;
+            self.raise_intr();
             self.interrupt_stats[interrupt as usize].serviced_count += 1;
         }
     }
@@ -506,6 +682,46 @@ impl Pic {
         self.intr
     }
 
+    /// Raise the INTR line, recording the time for the interrupt trace.
+    fn raise_intr(&mut self) {
+        if self.trace_enabled && !self.intr {
+            self.trace_intr_ticks = Some(self.ticks);
+        }
+        self.intr = true;
+    }
+
+    /// Record the time an IR line requested service for the interrupt trace. Only the first
+    /// request is timed until the interrupt is acknowledged.
+    fn trace_request(&mut self, interrupt: u8) {
+        if self.trace_enabled && self.trace_request_ticks[interrupt as usize].is_none() {
+            self.trace_request_ticks[interrupt as usize] = Some(self.ticks);
+        }
+    }
+
+    /// Enable or disable the interrupt trace. Any recorded entries are discarded.
+    pub fn set_irq_trace(&mut self, enabled: bool) {
+        self.trace_enabled = enabled;
+        self.trace_request_ticks = [None; 8];
+        self.trace_intr_ticks = None;
+        self.trace.clear();
+    }
+
+    /// Remove and return the interrupt trace entries recorded since the last call, oldest first.
+    pub fn take_irq_trace(&mut self) -> Vec<IrqTraceEntry> {
+        std::mem::take(&mut self.trace)
+    }
+
+    /// Return the state of the IR lines.
+    pub fn ir(&self) -> u8 {
+        self.ir
+    }
+
+    /// Returns true if the interrupt acknowledged by the last call to get_interrupt_vector() was
+    /// on an IR line with a cascaded slave PIC attached. In that case, the slave provides the vector.
+    pub fn cascade_acknowledged(&self) -> bool {
+        self.cascade_ack
+    }
+
     /// Represents the PIC's response to the 2nd INTA pulse. The PIC will put the
     /// highest-priority interrupt vector onto the bus. If there is no pending IRR
     /// bit set, it will return the spurious interrupt #7.
@@ -540,6 +756,18 @@ impl Pic {
 
                 // Finally, set INTR line low
                 self.intr = false;
+                self.cascade_ack = self.cascade && self.icw3 & ir_bit != 0;
+
+                if self.trace_enabled {
+                    let inta_ticks = self.ticks;
+                    self.trace.push(IrqTraceEntry {
+                        irq,
+                        vector: irq | self.int_offset,
+                        request_ticks: self.trace_request_ticks[irq as usize].take().unwrap_or(inta_ticks),
+                        intr_ticks: self.trace_intr_ticks.take().unwrap_or(inta_ticks),
+                        inta_ticks,
+                    });
+                }
 
                 return Some(irq | self.int_offset);
             }
@@ -549,6 +777,7 @@ impl Pic {
         // If no bit in the IRR was found to be set, then a spurious interrupt occurs.
         // Note that in the event of a spurious interrupt, no bit in the ISR is set to indicate an interrupt is being
         // serviced. This provides a method of determining whether an IR7 is spurious or real.
+        self.cascade_ack = false;
         Some(SPURIOUS_INTERRUPT)
     }
 
@@ -582,10 +811,12 @@ impl Pic {
     /// Run the PIC. This is primarily used to effect a delay in raising INTR when the IMR is
     /// changed.
     pub fn run(&mut self, sys_ticks: u32) {
+        self.ticks += sys_ticks as u64;
+
         if self.intr_scheduled {
             self.intr_timer = self.intr_timer.saturating_sub(sys_ticks);
             if self.intr_timer == 0 {
-                self.intr = true;
+                self.raise_intr();
                 self.intr_scheduled = false;
             }
         }
@@ -608,3 +839,57 @@ impl Pic {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_pic(pic: &mut Pic, icw1: u8, icw2: u8, icw3: Option<u8>) {
+        pic.handle_command_register_write(icw1);
+        pic.handle_data_register_write(icw2);
+        if let Some(icw3) = icw3 {
+            pic.handle_data_register_write(icw3);
+        }
+        pic.handle_data_register_write(ICW4_8088_MODE);
+        // Unmask all IRQs
+        pic.handle_data_register_write(0x00);
+    }
+
+    #[test]
+    fn test_irq_trace() {
+        let mut pic = Pic::new();
+        init_pic(&mut pic, 0x13, 0x08, None);
+        pic.set_irq_trace(true);
+
+        pic.run(10);
+        pic.request_interrupt(0);
+        assert!(pic.query_interrupt_line());
+        pic.run(25);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x08));
+
+        let trace = pic.take_irq_trace();
+        assert_eq!(trace.len(), 1);
+        assert_eq!((trace[0].irq, trace[0].vector), (0, 0x08));
+        assert_eq!(trace[0].request_ticks, 10);
+        assert_eq!(trace[0].intr_latency(), 0);
+        assert_eq!(trace[0].inta_latency(), 25);
+        assert!(pic.take_irq_trace().is_empty());
+    }
+
+    #[test]
+    fn test_cascade_init() {
+        // ICW1 without the single mode bit expects an ICW3.
+        let mut pic = Pic::new();
+        init_pic(&mut pic, 0x11, 0x08, Some(0x04));
+        assert!(!pic.error);
+
+        pic.request_interrupt(PIC_CASCADE_IRQ);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0A));
+        assert!(pic.cascade_acknowledged());
+
+        pic.eoi(None);
+        pic.request_interrupt(0);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x08));
+        assert!(!pic.cascade_acknowledged());
+    }
+}
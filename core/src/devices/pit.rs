@@ -30,13 +30,18 @@
 
 */
 
-use log;
-
 use std::collections::{BTreeMap, VecDeque};
 
+use anyhow::{bail, Error};
 use modular_bitfield::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::devicestate::DeviceState,
+    log_event,
+    tracelogger::{LogCategory, LogLevel},
+};
 
 use crate::{syntax_token::*, updatable::*};
 
@@ -59,7 +64,7 @@ pub const PIT_MHZ: f64 = 1.193182;
 pub const PIT_TICK_US: f64 = 1.0 / PIT_MHZ;
 //pub const PIT_DIVISOR: f64 = 0.25;
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ChannelMode {
     InterruptOnTerminalCount,
     HardwareRetriggerableOneShot,
@@ -87,13 +92,13 @@ impl From<u8> for ChannelMode {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ReloadFlag {
     Normal,
     ReloadNextCycle,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, BitfieldSpecifier)]
+#[derive(Debug, Copy, Clone, PartialEq, BitfieldSpecifier, Serialize, Deserialize)]
 pub enum PitType {
     Model8253,
     Model8254,
@@ -107,7 +112,7 @@ enum RwModeField {
     LsbMsb,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum RwMode {
     Lsb,
     Msb,
@@ -123,7 +128,7 @@ pub struct ControlByte {
     channel: B2,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ChannelState {
     WaitingForReload,
     WaitingForGate,
@@ -132,20 +137,20 @@ pub enum ChannelState {
     Counting(ReloadFlag),
 }
 
-#[derive(Debug, PartialEq)]
-enum LoadState {
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LoadState {
     WaitingForLsb,
     WaitingForMsb,
     //Loaded
 }
 
-#[derive(Debug, PartialEq)]
-enum LoadType {
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LoadType {
     InitialLoad,
     SubsequentLoad,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ReadState {
     NoRead,
     ReadLsb,
@@ -181,7 +186,7 @@ pub struct Channel {
 #[allow(dead_code)]
 pub struct ProgrammableIntervalTimer {
     ptype: PitType,
-    _crystal: f64,
+    crystal: f64,
     clock_divisor: u32,
     pit_cycles: u64,
     sys_tick_accumulator: u32,
@@ -193,6 +198,44 @@ pub struct ProgrammableIntervalTimer {
 }
 pub type Pit = ProgrammableIntervalTimer;
 
+/// The serializable state of a single PIT channel.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelSavedState {
+    pub mode: ChannelMode,
+    pub rw_mode: RwMode,
+    pub channel_state: ChannelState,
+    pub cycles_in_state: u32,
+    pub count_register: u16,
+    pub load_state: LoadState,
+    pub load_type: LoadType,
+    pub load_mask: u16,
+    pub counting_element: u16,
+    pub ce_undefined: bool,
+    pub armed: bool,
+    pub read_state: ReadState,
+    pub count_is_latched: bool,
+    pub output: bool,
+    pub output_on_reload: bool,
+    pub reload_on_trigger: bool,
+    pub output_latch: u16,
+    pub bcd_mode: bool,
+    pub gate: bool,
+    pub incomplete_reload: bool,
+    pub ticked: bool,
+}
+
+/// The serializable state of the PIT. Buffered speaker samples are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PitState {
+    pub ptype: PitType,
+    pub clock_divisor: u32,
+    pub pit_cycles: u64,
+    pub sys_tick_accumulator: u32,
+    pub sys_ticks_advance: u32,
+    pub cycle_accumulator: f64,
+    pub channels: Vec<ChannelSavedState>,
+}
+
 #[derive(Default, Clone)]
 pub struct PitStringState {
     pub c0_value: SyntaxToken,
@@ -254,6 +297,57 @@ impl IoDevice for ProgrammableIntervalTimer {
 }
 
 impl Channel {
+    fn save_state(&self) -> ChannelSavedState {
+        ChannelSavedState {
+            mode: *self.mode,
+            rw_mode: *self.rw_mode,
+            channel_state: self.channel_state,
+            cycles_in_state: self.cycles_in_state,
+            count_register: *self.count_register,
+            load_state: self.load_state,
+            load_type: self.load_type,
+            load_mask: self.load_mask,
+            counting_element: *self.counting_element,
+            ce_undefined: self.ce_undefined,
+            armed: self.armed,
+            read_state: self.read_state,
+            count_is_latched: self.count_is_latched,
+            output: *self.output,
+            output_on_reload: self.output_on_reload,
+            reload_on_trigger: self.reload_on_trigger,
+            output_latch: *self.output_latch,
+            bcd_mode: self.bcd_mode,
+            gate: *self.gate,
+            incomplete_reload: self.incomplete_reload,
+            ticked: self.ticked,
+        }
+    }
+
+    fn load_state(&mut self, state: ChannelSavedState) {
+        self.mode.update(state.mode);
+        self.rw_mode.update(state.rw_mode);
+        self.channel_state = state.channel_state;
+        self.cycles_in_state = state.cycles_in_state;
+        self.count_register.update(state.count_register);
+        self.load_state = state.load_state;
+        self.load_type = state.load_type;
+        self.load_mask = state.load_mask;
+        self.counting_element.update(state.counting_element);
+        self.ce_undefined = state.ce_undefined;
+        self.armed = state.armed;
+        self.read_state = state.read_state;
+        self.count_is_latched = state.count_is_latched;
+        self.output.update(state.output);
+        self.output_on_reload = state.output_on_reload;
+        self.reload_on_trigger = state.reload_on_trigger;
+        self.output_latch.update(state.output_latch);
+        self.bcd_mode = state.bcd_mode;
+        self.gate.update(state.gate);
+        self.incomplete_reload = state.incomplete_reload;
+        self.ticked = state.ticked;
+        self.dirty = true;
+    }
+
     pub fn new(c: usize, ptype: PitType) -> Self {
         Channel {
             c,
@@ -295,8 +389,10 @@ impl Channel {
         // Default load mask
         //self.load_mask = 0xFFFF;
 
-        log::debug!(
-            "PIT: Channel {} selected, channel_mode {:?}, rw mode {:?}, bcd: {:?}",
+        log_event!(
+            LogCategory::Pit,
+            LogLevel::Debug,
+            "Channel {} selected, channel_mode {:?}, rw mode {:?}, bcd: {:?}",
             self.c,
             mode,
             rw_mode,
@@ -368,6 +464,25 @@ impl Channel {
         (is_dirty, is_counting, self.ticked)
     }
 
+    /// Return the number of PIT cycles until the output may next change, or None if it will not
+    /// change until the channel is reprogrammed. The estimate may be early, but never late.
+    pub fn cycles_until_output_change(&self) -> Option<u32> {
+        // Counting is suspended while the gate is low.
+        if !matches!(self.channel_state, ChannelState::Counting(_)) || !*self.gate {
+            return None;
+        }
+        let count = *self.counting_element as u32;
+        match *self.mode {
+            // The output stays high after terminal count until a new count is written.
+            ChannelMode::InterruptOnTerminalCount if *self.output => None,
+            // The output goes low for the cycle in which the count reaches one.
+            ChannelMode::RateGenerator => Some(count.saturating_sub(1)),
+            // The count is decremented by two per cycle, and the output toggles when it expires.
+            ChannelMode::SquareWaveGenerator => Some(count / 2),
+            _ => Some(count),
+        }
+    }
+
     pub fn change_output_state(&mut self, state: bool, bus: &mut BusInterface) {
         if *self.output != state {
             self.output.set(state);
@@ -382,8 +497,13 @@ impl Channel {
                     dma.request_service(0);
                 }
                 (1, false) => {}
-                (2, true) => {}
-                (2, false) => {}
+                (2, _) => {
+                    // Channel 2's output is read back on PPI port C and ANDed with PB1 to drive
+                    // the speaker.
+                    if let Some(ppi) = bus.ppi_mut() {
+                        ppi.set_pit_output_bit(state);
+                    }
+                }
                 (_, _) => {}
             }
         }
@@ -821,8 +941,47 @@ impl Channel {
     }
 }
 
+impl DeviceState for ProgrammableIntervalTimer {
+    type State = PitState;
+
+    fn save_state(&self) -> PitState {
+        PitState {
+            ptype: self.ptype,
+            clock_divisor: self.clock_divisor,
+            pit_cycles: self.pit_cycles,
+            sys_tick_accumulator: self.sys_tick_accumulator,
+            sys_ticks_advance: self.sys_ticks_advance,
+            cycle_accumulator: self.cycle_accumulator,
+            channels: self.channels.iter().map(|c| c.save_state()).collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: PitState) -> Result<(), Error> {
+        if state.ptype != self.ptype {
+            bail!("PIT state is for a {:?}, not a {:?}", state.ptype, self.ptype);
+        }
+        if state.channels.len() != self.channels.len() {
+            bail!(
+                "PIT state has {} channels, expected {}",
+                state.channels.len(),
+                self.channels.len()
+            );
+        }
+
+        self.clock_divisor = state.clock_divisor;
+        self.pit_cycles = state.pit_cycles;
+        self.sys_tick_accumulator = state.sys_tick_accumulator;
+        self.sys_ticks_advance = state.sys_ticks_advance;
+        self.cycle_accumulator = state.cycle_accumulator;
+        for (channel, cs) in self.channels.iter_mut().zip(state.channels) {
+            channel.load_state(cs);
+        }
+        Ok(())
+    }
+}
+
 impl ProgrammableIntervalTimer {
-    pub fn new(ptype: PitType, _crystal: f64, clock_divisor: u32) -> Self {
+    pub fn new(ptype: PitType, crystal: f64, clock_divisor: u32) -> Self {
         /*
             The Intel documentation says:
             "Prior to initialization, the mode, count, and output of all counters is undefined."
@@ -836,7 +995,7 @@ impl ProgrammableIntervalTimer {
         }
         Self {
             ptype,
-            _crystal,
+            crystal,
             clock_divisor,
             pit_cycles: 0,
             sys_tick_accumulator: 0,
@@ -866,7 +1025,10 @@ impl ProgrammableIntervalTimer {
         }
     }
 
-    fn catch_up(&mut self, bus: &mut BusInterface, delta: DeviceRunTimeUnit) {
+    /// Tick the PIT up to the current CPU cycle within the current device slice. This should be
+    /// done before any external change to the PIT's inputs, such as the channel 2 gate, so that
+    /// the change lands on the correct PIT cycle.
+    pub fn catch_up(&mut self, bus: &mut BusInterface, delta: DeviceRunTimeUnit) {
         // Catch PIT up to CPU.
         let ticks = self.ticks_from_time(delta, self.timewarp);
 
@@ -1067,6 +1229,13 @@ impl ProgrammableIntervalTimer {
         self.sys_tick_accumulator
     }
 
+    /// Return the time in microseconds until the output of channel 0, which drives IRQ0, may next
+    /// change, or None if it will not change until the channel is reprogrammed.
+    pub fn next_deadline(&self) -> Option<f64> {
+        let cycles = self.channels[0].cycles_until_output_change()?;
+        Some(cycles as f64 * self.clock_divisor as f64 / self.crystal)
+    }
+
     /// Return the dirty flags for the specified timer channel. See the description of is_dirty under Channel.
     #[inline]
     pub fn is_dirty(&mut self, channel: usize) -> (bool, bool, bool) {
@@ -1249,3 +1418,116 @@ impl ProgrammableIntervalTimer {
         state_vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        device_traits::videocard::VideoType,
+        devices::{
+            pic::Pic,
+            ppi::{Ppi, PPI_PORT_B, PPI_PORT_C},
+        },
+        machine_types::MachineType,
+    };
+
+    const NO_DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::SystemTicks(0);
+
+    fn write_port_b(bus: &mut BusInterface, byte: u8) {
+        bus.ppi_mut()
+            .as_mut()
+            .unwrap()
+            .write_u8(PPI_PORT_B, byte, None, NO_DELTA);
+    }
+
+    fn read_port_c(bus: &mut BusInterface) -> u8 {
+        bus.ppi_mut().as_mut().unwrap().read_u8(PPI_PORT_C, NO_DELTA)
+    }
+
+    fn tick(pit: &mut Pit, bus: &mut BusInterface, ticks: usize) {
+        for _ in 0..ticks {
+            pit.tick(bus, None);
+        }
+    }
+
+    #[test]
+    fn test_pit_gate2_and_speaker_readback() {
+        let mut bus = BusInterface::default();
+        let dip_switches = Ppi::default_dip_switches(MachineType::Ibm5160, 0xA0000, false, &[VideoType::CGA], 1);
+        *bus.ppi_mut() = Some(Ppi::new(MachineType::Ibm5160, dip_switches));
+        let mut pit = Pit::new(PitType::Model8253, PIT_MHZ * 4.0, 4);
+
+        // Channel 2, LSB then MSB, mode 0 with the gate held low.
+        write_port_b(&mut bus, 0x00);
+        pit.write_u8(PIT_COMMAND_REGISTER, 0b1011_0000, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_2_DATA_PORT, 4, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_2_DATA_PORT, 0, Some(&mut bus), NO_DELTA);
+        tick(&mut pit, &mut bus, 10);
+        assert!(!pit.get_output_state(2));
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0);
+
+        // Raising PB0 opens the gate. The count reaches terminal count and the output is read
+        // back on PC5. The speaker monitor on PC4 stays low while PB1 is low.
+        write_port_b(&mut bus, 0x01);
+        tick(&mut pit, &mut bus, 4);
+        assert!(pit.get_output_state(2));
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0x20);
+
+        write_port_b(&mut bus, 0x03);
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0x30);
+
+        // Mode 3 square wave. Dropping the gate stops the count and forces the output high.
+        pit.write_u8(PIT_COMMAND_REGISTER, 0b1011_0110, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_2_DATA_PORT, 8, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_2_DATA_PORT, 0, Some(&mut bus), NO_DELTA);
+        tick(&mut pit, &mut bus, 6);
+        assert!(!pit.get_output_state(2));
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0);
+
+        write_port_b(&mut bus, 0x02);
+        tick(&mut pit, &mut bus, 1);
+        assert!(pit.get_output_state(2));
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0x30);
+        tick(&mut pit, &mut bus, 20);
+        assert!(pit.get_output_state(2));
+
+        // Clearing PB1 silences the speaker line without affecting the timer readback.
+        write_port_b(&mut bus, 0x00);
+        assert_eq!(read_port_c(&mut bus) & 0x30, 0x20);
+    }
+
+    #[test]
+    fn test_pit_next_deadline() {
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        let mut pit = Pit::new(PitType::Model8253, PIT_MHZ * 4.0, 4);
+        pit.set_channel_gate(0, true, &mut bus);
+        assert_eq!(pit.next_deadline(), None);
+
+        // Mode 3 counts down by two per cycle. After the load cycle and ten more, 80 remains.
+        pit.write_u8(PIT_COMMAND_REGISTER, 0b0011_0110, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_0_DATA_PORT, 100, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_0_DATA_PORT, 0, Some(&mut bus), NO_DELTA);
+        tick(&mut pit, &mut bus, 11);
+        let deadline = pit.next_deadline().expect("Channel 0 should be counting");
+        let cycles = (deadline / PIT_TICK_US).round();
+        assert_eq!(cycles, 40.0);
+
+        // Ticking to the deadline changes the output.
+        let output = pit.get_output_state(0);
+        tick(&mut pit, &mut bus, cycles as usize - 1);
+        assert_eq!(pit.get_output_state(0), output);
+        tick(&mut pit, &mut bus, 1);
+        assert_ne!(pit.get_output_state(0), output);
+
+        // Mode 0 has no deadline once the output has gone high at terminal count.
+        pit.write_u8(PIT_COMMAND_REGISTER, 0b0011_0000, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_0_DATA_PORT, 10, Some(&mut bus), NO_DELTA);
+        pit.write_u8(PIT_CHANNEL_0_DATA_PORT, 0, Some(&mut bus), NO_DELTA);
+        tick(&mut pit, &mut bus, 2);
+        assert!(pit.next_deadline().is_some());
+        tick(&mut pit, &mut bus, 20);
+        assert!(pit.get_output_state(0));
+        assert_eq!(pit.next_deadline(), None);
+    }
+}
@@ -36,9 +36,12 @@
 
 use std::cell::Cell;
 
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
-    device_traits::videocard::VideoType,
+    device_traits::{devicestate::DeviceState, videocard::VideoType},
     devices::pic,
     machine_types::MachineType,
 };
@@ -82,6 +85,7 @@ pub const SW1_HAVE_MDA: u8 = 0b0000_0000;
 pub const SW1_HAVE_CGA_LORES: u8 = 0b0010_0000;
 pub const SW1_HAVE_CGA_HIRES: u8 = 0b0001_0000;
 pub const SW1_HAVE_EXPANSION: u8 = 0b0011_0000;
+pub const SW1_VIDEO_MASK: u8 = 0b0011_0000;
 
 // SW8_7: ON, ON: One floppy
 // SW8_7: ON, OFF: Two floppies
@@ -152,12 +156,15 @@ pub const PORTB_PULL_KB_LOW: u8 = 0b0100_0000;
 pub const PORTB_KB_CLEAR: u8 = 0b1000_0000;
 pub const PORTB_PRESENT_SW1_PORTA: u8 = 0b1000_0000;
 
-#[derive(Debug)]
+// PORT C INPUTS
+pub const PORTC_PARITY_CHECK: u8 = 0b1000_0000;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum PortAMode {
     SwitchBlock1,
     KeyboardByte,
 }
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum PortCMode {
     Switch2OneToFour,
     Switch2Five,
@@ -184,6 +191,7 @@ pub struct Ppi {
     dip_sw2: u8,
     timer_in: bool,
     speaker_in: bool,
+    parity_check: bool,
 }
 
 // This structure implements an interface for wires connected to the PPI from
@@ -197,7 +205,40 @@ pub struct PpiWires {
     speaker_monitor: Cell<bool>,
 }
 
-#[derive(Default)]
+/// The serializable state of the PPI, including the DIP switch settings.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PpiState {
+    pub port_a_mode: PortAMode,
+    pub port_c_mode: PortCMode,
+    pub kb_clock_low: bool,
+    pub kb_counting_low: bool,
+    pub kb_low_count: f64,
+    pub kb_do_reset: bool,
+    pub kb_count_until_reset_byte: f64,
+    pub kb_resets_counter: u32,
+    pub pb_byte: u8,
+    pub kb_byte: u8,
+    pub kb_byte_last: u8,
+    pub keyboard_clear_scheduled: bool,
+    pub ksr_cleared: bool,
+    pub kb_enabled: bool,
+    pub dip_sw1: u8,
+    pub dip_sw2: u8,
+    pub timer_in: bool,
+    pub speaker_in: bool,
+    #[serde(default)]
+    pub parity_check: bool,
+}
+
+/// The settings of the two motherboard DIP switch blocks. Bit 0 corresponds to switch 1, and a
+/// set bit means the switch is ON. The PPI reads switches inverted, so an ON switch reads as 0.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DipSwitches {
+    pub sw1: u8,
+    pub sw2: u8,
+}
+
+#[derive(Default, Hash)]
 pub struct PpiStringState {
     pub port_a_mode: String,
     pub port_a_value_bin: String,
@@ -208,65 +249,28 @@ pub struct PpiStringState {
     pub kb_resets_counter: String,
     pub port_c_mode: String,
     pub port_c_value: String,
+    pub dip_sw1: String,
+    pub dip_sw2: String,
 }
 
 impl Ppi {
-    pub fn new(
-        machine_type: MachineType,
-        conventional_mem: u32,
-        mut have_expansion: bool,
-        video_types: Vec<VideoType>,
-        num_floppies: u32,
-    ) -> Self {
-        // Creation of the PPI is primarily concerned with setting up the DIP switches.
-        let (sw2_ram_dip_bits, sw1_bank_bits) = Ppi::get_ram_dip(machine_type, conventional_mem);
-        log::debug!(
-            "Ppi::new(): Have {:06X} bytes of conventional memory: DIP2: {:08b}",
-            conventional_mem,
-            sw2_ram_dip_bits
-        );
-        let (sw1_floppy_ct_bits, sw1_master_floppy_bit) = match num_floppies {
-            1 => (SW1_ONE_FLOPPY, SW1_HAS_FLOPPIES),
-            2 => (SW1_TWO_FLOPPIES, SW1_HAS_FLOPPIES),
-            3 => (SW1_THREE_FLOPPIES, SW1_HAS_FLOPPIES),
-            4 => (SW1_FOUR_FLOPPIES, SW1_HAS_FLOPPIES),
-            _ => (0, 1),
-        };
-
-        #[cfg(feature = "ega")]
-        {
-            have_expansion |= video_types.contains(&VideoType::EGA);
-        }
-        #[cfg(feature = "vga")]
-        {
-            have_expansion |= video_types.contains(&VideoType::VGA);
-        }
-
-        let sw1_video_bits = if have_expansion {
-            // We have a card that requires an expansion BIOs.
-            SW1_HAVE_EXPANSION
-        }
-        else if video_types.contains(&VideoType::CGA) {
-            // We have a CGA card.
-            SW1_HAVE_CGA_HIRES
-        }
-        else {
-            // MDA or no card.
-            SW1_HAVE_MDA
-        };
+    pub fn new(machine_type: MachineType, dip_switches: DipSwitches) -> Self {
+        log::debug!("DIP SW1: {:08b} DIP SW2: {:08b}", dip_switches.sw1, dip_switches.sw2);
 
         Self {
             machine_type,
             port_a_mode: match machine_type {
                 MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => PortAMode::SwitchBlock1,
-                MachineType::Ibm5160 => PortAMode::KeyboardByte,
+                MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable => PortAMode::KeyboardByte,
                 _ => {
                     panic!("Machine type: {:?} has no PPI", machine_type);
                 }
             },
             port_c_mode: match machine_type {
                 MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => PortCMode::Switch2OneToFour,
-                MachineType::Ibm5160 => PortCMode::Switch1FiveToEight,
+                MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable => {
+                    PortCMode::Switch1FiveToEight
+                }
                 _ => {
                     panic!("Machine type: {:?} has no PPI", machine_type);
                 }
@@ -283,29 +287,99 @@ impl Ppi {
             keyboard_clear_scheduled: false,
             ksr_cleared: true,
             kb_enabled: true,
-            dip_sw1: match machine_type {
-                MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => {
-                    let dip_sw1 = sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit;
-                    log::debug!("DIP SW1: {:08b}", dip_sw1);
-                    !dip_sw1
-                }
-                MachineType::Ibm5160 => {
-                    let dip_sw1 = sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit;
-                    log::debug!("DIP SW1: {:08b}", dip_sw1);
-                    !dip_sw1
-                }
-                _ => {
-                    log::error!("Machine type: {:?} has no PPI", machine_type);
-                    0
-                }
-            },
-            dip_sw2: !sw2_ram_dip_bits,
+            // Switch values are stored inverted.
+            dip_sw1: !dip_switches.sw1,
+            dip_sw2: !dip_switches.sw2,
             timer_in: false,
             speaker_in: false,
+            parity_check: false,
+        }
+    }
+
+    /// Return the DIP switch settings that describe the specified hardware. These are the
+    /// settings used unless a machine configuration overrides them.
+    pub fn default_dip_switches(
+        machine_type: MachineType,
+        conventional_mem: u32,
+        have_expansion: bool,
+        video_types: &[VideoType],
+        num_floppies: u32,
+    ) -> DipSwitches {
+        let (sw2_ram_dip_bits, sw1_bank_bits) = Ppi::get_ram_dip(machine_type, conventional_mem);
+        log::debug!(
+            "Ppi::default_dip_switches(): Have {:06X} bytes of conventional memory: DIP2: {:08b}",
+            conventional_mem,
+            sw2_ram_dip_bits
+        );
+        let (sw1_floppy_ct_bits, sw1_master_floppy_bit) = match num_floppies {
+            1 => (SW1_ONE_FLOPPY, SW1_HAS_FLOPPIES),
+            2 => (SW1_TWO_FLOPPIES, SW1_HAS_FLOPPIES),
+            3 => (SW1_THREE_FLOPPIES, SW1_HAS_FLOPPIES),
+            4 => (SW1_FOUR_FLOPPIES, SW1_HAS_FLOPPIES),
+            _ => (0, 1),
+        };
+
+        let sw1_video_bits = Ppi::get_video_dip(have_expansion, video_types);
+
+        DipSwitches {
+            sw1: sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit,
+            sw2: sw2_ram_dip_bits,
+        }
+    }
+
+    /// Return the current DIP switch settings.
+    pub fn dip_switches(&self) -> DipSwitches {
+        DipSwitches {
+            sw1: !self.dip_sw1,
+            sw2: !self.dip_sw2,
+        }
+    }
+
+    /// Set the DIP switches. As on real hardware, the new settings are visible to software
+    /// immediately, but the BIOS only samples them during POST, so they take effect on the next
+    /// reboot.
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        log::debug!("DIP SW1: {:08b} DIP SW2: {:08b}", dip_switches.sw1, dip_switches.sw2);
+        self.dip_sw1 = !dip_switches.sw1;
+        self.dip_sw2 = !dip_switches.sw2;
+    }
+
+    /// Return the SW1 video type bits for the specified set of installed video cards.
+    fn get_video_dip(have_expansion: bool, video_types: &[VideoType]) -> u8 {
+        let needs_expansion = |video_type: &VideoType| match video_type {
+            VideoType::MDA | VideoType::CGA | VideoType::CompaqDual => false,
+            #[cfg(feature = "ega")]
+            VideoType::EGA => true,
+            #[cfg(feature = "vga")]
+            VideoType::VGA => true,
+        };
+
+        if have_expansion || video_types.iter().any(needs_expansion) {
+            // We have a card that requires an expansion BIOs.
+            SW1_HAVE_EXPANSION
+        }
+        else if video_types.contains(&VideoType::CGA) || video_types.contains(&VideoType::CompaqDual) {
+            // We have a CGA card. The Compaq dual-mode board starts in its CGA personality.
+            SW1_HAVE_CGA_HIRES
+        }
+        else {
+            // MDA or no card.
+            SW1_HAVE_MDA
         }
     }
 
+    /// Update the SW1 video type switches after the set of installed video cards has changed.
+    pub fn set_video_types(&mut self, have_expansion: bool, video_types: &[VideoType]) {
+        let sw1_video_bits = Ppi::get_video_dip(have_expansion, video_types);
+        // Switch values are stored inverted.
+        self.dip_sw1 = (self.dip_sw1 | SW1_VIDEO_MASK) & !sw1_video_bits;
+        log::debug!("DIP SW1: {:08b}", !self.dip_sw1);
+    }
+
     fn get_ram_dip(machine_type: MachineType, conventional_mem: u32) -> (u8, u8) {
+        // The memory DIP switches can only represent up to 640K. Memory beyond that must be
+        // found by software.
+        let conventional_mem = std::cmp::min(conventional_mem, 0xA0000);
         match machine_type {
             MachineType::Ibm5150v64K => match conventional_mem {
                 0x04000 => (SW2_V1_RAM_16K, SW1_RAM_BANKS_1),
@@ -362,6 +436,57 @@ impl Ppi {
     }
 }
 
+impl DeviceState for Ppi {
+    type State = PpiState;
+
+    fn save_state(&self) -> PpiState {
+        PpiState {
+            port_a_mode: self.port_a_mode,
+            port_c_mode: self.port_c_mode,
+            kb_clock_low: self.kb_clock_low,
+            kb_counting_low: self.kb_counting_low,
+            kb_low_count: self.kb_low_count,
+            kb_do_reset: self.kb_do_reset,
+            kb_count_until_reset_byte: self.kb_count_until_reset_byte,
+            kb_resets_counter: self.kb_resets_counter,
+            pb_byte: self.pb_byte,
+            kb_byte: self.kb_byte,
+            kb_byte_last: self.kb_byte_last,
+            keyboard_clear_scheduled: self.keyboard_clear_scheduled,
+            ksr_cleared: self.ksr_cleared,
+            kb_enabled: self.kb_enabled,
+            dip_sw1: self.dip_sw1,
+            dip_sw2: self.dip_sw2,
+            timer_in: self.timer_in,
+            speaker_in: self.speaker_in,
+            parity_check: self.parity_check,
+        }
+    }
+
+    fn load_state(&mut self, state: PpiState) -> Result<(), Error> {
+        self.port_a_mode = state.port_a_mode;
+        self.port_c_mode = state.port_c_mode;
+        self.kb_clock_low = state.kb_clock_low;
+        self.kb_counting_low = state.kb_counting_low;
+        self.kb_low_count = state.kb_low_count;
+        self.kb_do_reset = state.kb_do_reset;
+        self.kb_count_until_reset_byte = state.kb_count_until_reset_byte;
+        self.kb_resets_counter = state.kb_resets_counter;
+        self.pb_byte = state.pb_byte;
+        self.kb_byte = state.kb_byte;
+        self.kb_byte_last = state.kb_byte_last;
+        self.keyboard_clear_scheduled = state.keyboard_clear_scheduled;
+        self.ksr_cleared = state.ksr_cleared;
+        self.kb_enabled = state.kb_enabled;
+        self.dip_sw1 = state.dip_sw1;
+        self.dip_sw2 = state.dip_sw2;
+        self.timer_in = state.timer_in;
+        self.speaker_in = state.speaker_in;
+        self.parity_check = state.parity_check;
+        Ok(())
+    }
+}
+
 impl IoDevice for Ppi {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
         //log::trace!("PPI Read from port: {:04X}", port);
@@ -420,7 +545,9 @@ impl Ppi {
     pub fn turbo_bit(&self) -> bool {
         match self.machine_type {
             MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => false,
-            MachineType::Ibm5160 => self.pb_byte & PORTB_SW2_SELECT != 0,
+            MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable => {
+                self.pb_byte & PORTB_SW2_SELECT != 0
+            }
             _ => {
                 log::error!("turbo_bit(): Machine type has no PPI!");
                 false
@@ -435,6 +562,12 @@ impl Ppi {
     pub fn handle_portb_write(&mut self, byte: u8) {
         //log::debug!("PPI: Write to Port B: {:02X}", byte);
         self.pb_byte = byte;
+        self.update_speaker_bit();
+
+        // Disabling the RAM parity check also resets the parity check latch.
+        if byte & PORTB_PARITY_MB_EN != 0 {
+            self.parity_check = false;
+        }
 
         match self.machine_type {
             MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => {
@@ -459,7 +592,7 @@ impl Ppi {
                     self.port_a_mode = PortAMode::KeyboardByte
                 }
             }
-            MachineType::Ibm5160 => {
+            MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable => {
                 // 5160 Behavior only
                 if byte & PORTB_SW1_SELECT == 0 {
                     // If Bit 3 is OFF, PC0-PC3 represent SW1 S1-S4
@@ -520,14 +653,15 @@ impl Ppi {
 
     pub fn calc_port_c_value(&self) -> u8 {
         let mut speaker_bit = 0;
-        if let MachineType::Ibm5160 = self.machine_type {
+        if let MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable = self.machine_type {
             speaker_bit = (self.speaker_in as u8) << 4;
         }
         let timer_bit = (self.timer_in as u8) << 5;
+        let parity_bit = if self.parity_check { PORTC_PARITY_CHECK } else { 0 };
 
-        match (&self.machine_type, &self.port_c_mode) {
+        let port_c = match (&self.machine_type, &self.port_c_mode) {
             (MachineType::Ibm5150v64K | MachineType::Ibm5150v256K, PortCMode::Switch2OneToFour) => {
-                // We aren't implementing the cassette on 5150, and we'll never have parity errors
+                // We aren't implementing the cassette on 5150.
                 (self.dip_sw2 & 0x0F) | timer_bit
             }
             (MachineType::Ibm5150v64K | MachineType::Ibm5150v256K, PortCMode::Switch2Five) => {
@@ -535,11 +669,17 @@ impl Ppi {
                 // If Port C is in Switch Block 2 mode, switches 6, 7, 8 and will read high (off)
                 (self.dip_sw2 >> 4 & 0x01) | timer_bit
             }
-            (MachineType::Ibm5160, PortCMode::Switch1OneToFour) => {
+            (
+                MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable,
+                PortCMode::Switch1OneToFour,
+            ) => {
                 // Cassette data line has been replaced with a speaker monitor line.
                 (self.dip_sw1 & 0x0F) | speaker_bit | timer_bit
             }
-            (MachineType::Ibm5160, PortCMode::Switch1FiveToEight) => {
+            (
+                MachineType::Ibm5160 | MachineType::Ibm5155 | MachineType::CompaqPortable,
+                PortCMode::Switch1FiveToEight,
+            ) => {
                 // Cassette data line has been replaced with a speaker monitor line.
                 // On 5160, all four switches 5-8 are readable
                 (self.dip_sw1 >> 4 & 0x0F) | speaker_bit | timer_bit
@@ -547,7 +687,8 @@ impl Ppi {
             _ => {
                 panic!("Invalid PPI state");
             }
-        }
+        };
+        port_c | parity_bit
     }
 
     pub fn get_string_state(&self) -> PpiStringState {
@@ -568,6 +709,8 @@ impl Ppi {
             kb_resets_counter: format!("{}", self.kb_resets_counter),
             port_c_mode: format!("{:?}", self.port_c_mode),
             port_c_value: format!("{:08b}", port_c_value),
+            dip_sw1: format!("{:08b}", !self.dip_sw1),
+            dip_sw2: format!("{:08b}", !self.dip_sw2),
         }
     }
 
@@ -583,12 +726,15 @@ impl Ppi {
         self.pb_byte & PORTB_TIMER2_GATE != 0
     }
 
+    /// Set the state of the PIT channel 2 output line, read back on PC5. The speaker monitor
+    /// line follows the output of the AND gate combining the timer output with PB1.
     pub fn set_pit_output_bit(&mut self, state: bool) {
         self.timer_in = state;
+        self.update_speaker_bit();
     }
 
-    pub fn set_speaker_bit(&mut self, state: bool) {
-        self.speaker_in = state;
+    fn update_speaker_bit(&mut self) {
+        self.speaker_in = self.timer_in && self.get_pb1_state();
     }
 
     /// Return whether NMI generation is enabled
@@ -596,6 +742,19 @@ impl Ppi {
         self.pb_byte & PORTB_PARITY_MB_EN == 0 || self.pb_byte & PORTB_PARITY_EX_EN == 0
     }
 
+    /// Latch a RAM parity error, if the RAM parity check is enabled. The latch is read on PC7
+    /// and holds until the parity check is disabled.
+    pub fn set_parity_error(&mut self) {
+        if self.pb_byte & PORTB_PARITY_MB_EN == 0 {
+            self.parity_check = true;
+        }
+    }
+
+    /// Return whether a RAM parity error is latched.
+    pub fn parity_check(&self) -> bool {
+        self.parity_check
+    }
+
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
         // Our keyboard byte was read, so clear the interrupt request line and reset the byte
         // read at the keyboard IO port to 0
@@ -635,3 +794,48 @@ impl Ppi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dip_switch_override() {
+        let defaults = Ppi::default_dip_switches(MachineType::Ibm5160, 0xA0000, false, &[VideoType::CGA], 1);
+        let mut ppi = Ppi::new(MachineType::Ibm5160, defaults);
+        assert_eq!(ppi.dip_switches(), defaults);
+
+        // Switches are active-low when read through port C.
+        let switches = DipSwitches {
+            sw1: 0b0011_0101,
+            sw2: 0,
+        };
+        ppi.set_dip_switches(switches);
+        assert_eq!(ppi.dip_switches(), switches);
+        ppi.port_c_mode = PortCMode::Switch1OneToFour;
+        assert_eq!(ppi.calc_port_c_value() & 0x0F, 0b1010);
+        ppi.port_c_mode = PortCMode::Switch1FiveToEight;
+        assert_eq!(ppi.calc_port_c_value() & 0x0F, 0b1100);
+    }
+
+    #[test]
+    fn test_parity_check_latch() {
+        let defaults = Ppi::default_dip_switches(MachineType::Ibm5160, 0xA0000, false, &[VideoType::CGA], 1);
+        let mut ppi = Ppi::new(MachineType::Ibm5160, defaults);
+
+        // A parity error is ignored while the parity check is disabled.
+        ppi.handle_portb_write(PORTB_PARITY_MB_EN);
+        ppi.set_parity_error();
+        assert!(!ppi.parity_check());
+
+        ppi.handle_portb_write(0);
+        ppi.set_parity_error();
+        assert!(ppi.parity_check());
+        assert_eq!(ppi.calc_port_c_value() & PORTC_PARITY_CHECK, PORTC_PARITY_CHECK);
+
+        // Disabling the parity check clears the latch.
+        ppi.handle_portb_write(PORTB_PARITY_MB_EN);
+        assert!(!ppi.parity_check());
+        assert_eq!(ppi.calc_port_c_value() & PORTC_PARITY_CHECK, 0);
+    }
+}
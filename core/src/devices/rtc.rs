@@ -0,0 +1,399 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    devices::rtc.rs
+
+    Implements a National Semiconductor MM58167A real time clock, as found on
+    many multifunction cards for the PC and XT. A clock utility run at boot
+    (such as ASTCLOCK for the AST SixPakPlus) reads the chip to set the DOS
+    date and time.
+
+    The chip's 32 registers are decoded at consecutive IO ports from the
+    card's base address. Counter registers hold BCD values. The chip has no
+    year counter, so clock utilities keep the year in one of its RAM latches,
+    and it can't account for leap days. The interrupt outputs are not
+    connected.
+
+    The clock advances with emulated time, so it remains deterministic unless
+    it is set from the host clock.
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_traits::{devicestate::DeviceState, nonvolatile::NonVolatile},
+    host_clock::ClockTime,
+};
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+pub const RTC_DEFAULT_IO_BASE: u16 = 0x2C0;
+const RTC_REGISTER_COUNT: u16 = 32;
+
+const REG_MILLISECONDS: u16 = 0x00;
+const REG_HUNDREDTHS: u16 = 0x01;
+const REG_SECONDS: u16 = 0x02;
+const REG_MINUTES: u16 = 0x03;
+const REG_HOURS: u16 = 0x04;
+const REG_WEEKDAY: u16 = 0x05;
+const REG_DAY: u16 = 0x06;
+const REG_MONTH: u16 = 0x07;
+const REG_RAM_START: u16 = 0x08;
+const REG_RAM_END: u16 = 0x0F;
+const REG_INT_STATUS: u16 = 0x10;
+const REG_INT_CONTROL: u16 = 0x11;
+const REG_COUNTER_RESET: u16 = 0x12;
+const REG_RAM_RESET: u16 = 0x13;
+const REG_STATUS: u16 = 0x14;
+const REG_GO: u16 = 0x15;
+
+const NVRAM_SIZE: usize = REG_RAM_END as usize + 1;
+
+const DAYS_IN_MONTH: [u8; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum RtcType {
+    #[default]
+    MM58167,
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+pub struct Rtc {
+    io_base: u16,
+    us_accum: f64,
+    millisecond: u16,
+    second: u8,
+    minute: u8,
+    hour: u8,
+    weekday: u8,
+    day: u8,
+    month: u8,
+    ram: [u8; 8],
+    int_control: u8,
+}
+
+/// The serializable state of the RTC counters, RAM latches and interrupt control register.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RtcState {
+    pub us_accum: f64,
+    pub millisecond: u16,
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub weekday: u8,
+    pub day: u8,
+    pub month: u8,
+    pub ram: [u8; 8],
+    pub int_control: u8,
+}
+
+impl DeviceState for Rtc {
+    type State = RtcState;
+
+    fn save_state(&self) -> RtcState {
+        RtcState {
+            us_accum: self.us_accum,
+            millisecond: self.millisecond,
+            second: self.second,
+            minute: self.minute,
+            hour: self.hour,
+            weekday: self.weekday,
+            day: self.day,
+            month: self.month,
+            ram: self.ram,
+            int_control: self.int_control,
+        }
+    }
+
+    fn load_state(&mut self, state: RtcState) -> Result<(), Error> {
+        if !(1..=12).contains(&state.month) || !(1..=31).contains(&state.day) {
+            bail!("RTC state has invalid date: {}/{}", state.month, state.day);
+        }
+        self.us_accum = state.us_accum;
+        self.millisecond = state.millisecond;
+        self.second = state.second;
+        self.minute = state.minute;
+        self.hour = state.hour;
+        self.weekday = state.weekday;
+        self.day = state.day;
+        self.month = state.month;
+        self.ram = state.ram;
+        self.int_control = state.int_control;
+        Ok(())
+    }
+}
+
+/// The NVRAM image is the chip's first 16 registers as read from the bus: the clock counters in
+/// BCD followed by the RAM latches. Persisting the counters lets a clock set by the guest resume
+/// from where it stood when the machine was shut down.
+impl NonVolatile for Rtc {
+    fn nvram_name(&self) -> String {
+        String::from("rtc")
+    }
+
+    fn nvram(&self) -> Vec<u8> {
+        (0..NVRAM_SIZE as u16).map(|reg| self.read_register(reg)).collect()
+    }
+
+    fn load_nvram(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() != NVRAM_SIZE {
+            bail!("RTC NVRAM image is {} bytes, expected {}", data.len(), NVRAM_SIZE);
+        }
+        for (reg, byte) in data.iter().enumerate() {
+            self.write_u8(
+                self.io_base + reg as u16,
+                *byte,
+                None,
+                DeviceRunTimeUnit::Microseconds(0.0),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Rtc {
+    pub fn new(io_base: Option<u16>) -> Self {
+        Self {
+            io_base: io_base.unwrap_or(RTC_DEFAULT_IO_BASE),
+            us_accum: 0.0,
+            millisecond: 0,
+            second: 0,
+            minute: 0,
+            hour: 0,
+            weekday: 1,
+            day: 1,
+            month: 1,
+            ram: [0; 8],
+            int_control: 0,
+        }
+    }
+
+    /// Set the clock counters. The year is not stored by the chip.
+    pub fn set_time(&mut self, time: &ClockTime) {
+        self.us_accum = (time.micros % 1000) as f64;
+        self.millisecond = (time.micros / 1000) as u16;
+        self.second = time.second;
+        self.minute = time.minute;
+        self.hour = time.hour;
+        self.weekday = time.weekday;
+        self.day = time.day;
+        self.month = time.month;
+    }
+
+    /// Return the current clock counters. The year is reported as 0.
+    pub fn time(&self) -> ClockTime {
+        ClockTime {
+            year: 0,
+            month: self.month,
+            day: self.day,
+            weekday: self.weekday,
+            hour: self.hour,
+            minute: self.minute,
+            second: self.second,
+            micros: self.millisecond as u32 * 1000 + self.us_accum as u32,
+        }
+    }
+
+    /// Read a register without side effects. Reads on the bus never have any.
+    fn read_register(&self, reg: u16) -> u8 {
+        match reg {
+            REG_MILLISECONDS => to_bcd((self.millisecond % 10) as u8) << 4,
+            REG_HUNDREDTHS => to_bcd(((self.millisecond / 10) % 100) as u8),
+            REG_SECONDS => to_bcd(self.second),
+            REG_MINUTES => to_bcd(self.minute),
+            REG_HOURS => to_bcd(self.hour),
+            REG_WEEKDAY => to_bcd(self.weekday),
+            REG_DAY => to_bcd(self.day),
+            REG_MONTH => to_bcd(self.month),
+            reg @ REG_RAM_START..=REG_RAM_END => self.ram[(reg - REG_RAM_START) as usize],
+            REG_INT_CONTROL => self.int_control,
+            // Counters never roll over in the middle of a read, so the status bit is always clear.
+            REG_INT_STATUS | REG_STATUS => 0,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn run(&mut self, us: f64) {
+        self.us_accum += us;
+        if self.us_accum >= 1000.0 {
+            let ms = (self.us_accum / 1000.0) as u32;
+            self.us_accum -= ms as f64 * 1000.0;
+
+            let total_ms = self.millisecond as u32 + ms;
+            self.millisecond = (total_ms % 1000) as u16;
+            for _ in 0..total_ms / 1000 {
+                self.tick_second();
+            }
+        }
+    }
+
+    fn tick_second(&mut self) {
+        self.second += 1;
+        if self.second < 60 {
+            return;
+        }
+        self.second = 0;
+        self.minute += 1;
+        if self.minute < 60 {
+            return;
+        }
+        self.minute = 0;
+        self.hour += 1;
+        if self.hour < 24 {
+            return;
+        }
+        self.hour = 0;
+        self.weekday = self.weekday % 7 + 1;
+        self.day += 1;
+        if self.day <= DAYS_IN_MONTH[(self.month.clamp(1, 12) - 1) as usize] {
+            return;
+        }
+        self.day = 1;
+        self.month = self.month % 12 + 1;
+    }
+
+    fn reset_counters(&mut self, mask: u8) {
+        if mask & 0x01 != 0 {
+            self.millisecond -= self.millisecond % 10;
+            self.us_accum = 0.0;
+        }
+        if mask & 0x02 != 0 {
+            self.millisecond %= 10;
+        }
+        if mask & 0x04 != 0 {
+            self.second = 0;
+        }
+        if mask & 0x08 != 0 {
+            self.minute = 0;
+        }
+        if mask & 0x10 != 0 {
+            self.hour = 0;
+        }
+        if mask & 0x20 != 0 {
+            self.weekday = 1;
+        }
+        if mask & 0x40 != 0 {
+            self.day = 1;
+        }
+        if mask & 0x80 != 0 {
+            self.month = 1;
+        }
+    }
+}
+
+impl IoDevice for Rtc {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        self.read_register(port.wrapping_sub(self.io_base))
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            REG_MILLISECONDS => {
+                self.millisecond = self.millisecond - self.millisecond % 10 + (data >> 4).min(9) as u16;
+            }
+            REG_HUNDREDTHS => {
+                self.millisecond = from_bcd(data).min(99) as u16 * 10 + self.millisecond % 10;
+            }
+            REG_SECONDS => self.second = from_bcd(data).min(59),
+            REG_MINUTES => self.minute = from_bcd(data).min(59),
+            REG_HOURS => self.hour = from_bcd(data).min(23),
+            REG_WEEKDAY => self.weekday = from_bcd(data).clamp(1, 7),
+            REG_DAY => self.day = from_bcd(data).clamp(1, 31),
+            REG_MONTH => self.month = from_bcd(data).clamp(1, 12),
+            reg @ REG_RAM_START..=REG_RAM_END => self.ram[(reg - REG_RAM_START) as usize] = data,
+            REG_INT_CONTROL => self.int_control = data,
+            REG_COUNTER_RESET => self.reset_counters(data),
+            REG_RAM_RESET => {
+                for (i, byte) in self.ram.iter_mut().enumerate() {
+                    if data & (1 << i) != 0 {
+                        *byte = 0;
+                    }
+                }
+            }
+            REG_GO => {
+                // GO restarts the clock at the top of the current minute.
+                self.reset_counters(0x07);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (self.io_base..self.io_base + RTC_REGISTER_COUNT).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtc_rollover() {
+        let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
+        let mut rtc = Rtc::new(None);
+        rtc.set_time(&ClockTime {
+            year: 1987,
+            month: 12,
+            day: 31,
+            weekday: 5,
+            hour: 23,
+            minute: 59,
+            second: 59,
+            micros: 999_500,
+        });
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_HOURS, nul_delta), 0x23);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_HUNDREDTHS, nul_delta), 0x99);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_MILLISECONDS, nul_delta), 0x90);
+
+        rtc.run(1000.0);
+        let time = rtc.time();
+        assert_eq!((time.month, time.day, time.weekday), (1, 1, 6));
+        assert_eq!((time.hour, time.minute, time.second), (0, 0, 0));
+        assert_eq!(time.micros, 500);
+
+        // Writing the counters takes BCD values.
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_MONTH, 0x02, None, nul_delta);
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_DAY, 0x28, None, nul_delta);
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_HOURS, 0x23, None, nul_delta);
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_MINUTES, 0x59, None, nul_delta);
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_SECONDS, 0x59, None, nul_delta);
+        rtc.run(1_000_000.0);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_MONTH, nul_delta), 0x03);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_DAY, nul_delta), 0x01);
+
+        // RAM latches hold their values until reset.
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_RAM_START + 1, 0x87, None, nul_delta);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_RAM_START + 1, nul_delta), 0x87);
+        rtc.write_u8(RTC_DEFAULT_IO_BASE + REG_RAM_RESET, 0xFF, None, nul_delta);
+        assert_eq!(rtc.read_u8(RTC_DEFAULT_IO_BASE + REG_RAM_START + 1, nul_delta), 0x00);
+    }
+}
@@ -36,10 +36,22 @@
     "IBM Asynchronous Communications Adapter"
 */
 
-use std::{collections::VecDeque, io::Read};
+use std::{
+    collections::VecDeque,
+    io::Write,
+    str::FromStr,
+    sync::mpsc::{Receiver, TryRecvError},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{mpsc::Sender, Mutex};
+
+use serde_derive::{Deserialize, Serialize};
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    device_scheduler::earliest_deadline,
+    device_traits::devicestate::DeviceState,
     devices::pic,
 };
 
@@ -54,6 +66,9 @@ const SERIAL_CLOCK: f64 = 1.8432;
 pub const SERIAL1_IRQ: u8 = 4;
 pub const SERIAL2_IRQ: u8 = 3;
 
+// Number of IO ports decoded by each serial port.
+pub const SERIAL_PORT_SPAN: u16 = 7;
+
 /* - Ports -
 
     Ports 0x3F8 & 0x3F9 (And their corresponding secondary ports) are multiplexed via
@@ -95,7 +110,7 @@ const STATUS_DATA_READY: u8 = 0b0000_0001;
 const STATUS_TRANSMIT_EMPTY: u8 = 0b0010_0000;
 //const STATUS_TX_SHIFT_EMPTY: u8 = 0b0100_0000;
 
-const INTERRUPT_ID_MASK: u8 = 0b0000_0011;
+//const INTERRUPT_ID_MASK: u8 = 0b0000_0011;
 
 const INTERRUPT_DATA_AVAIL: u8 = 0b0000_0001;
 const INTERRUPT_TX_EMPTY: u8 = 0b0000_0010;
@@ -117,7 +132,7 @@ const MODEM_CONTROL_LOOP: u8 = 0b0001_0000;
 
 const MODEM_STATUS_DCTS: u8 = 0b0000_0001;
 const MODEM_STATUS_DDSR: u8 = 0b0000_0010;
-//const MODEM_STATUS_TERI: u8 = 0b0000_0100;
+const MODEM_STATUS_TERI: u8 = 0b0000_0100;
 //const MODEM_STATUS_DRLSD: u8 = 0b0000_1000;
 const MODEM_STATUS_CTS: u8 = 0b0001_0000;
 const MODEM_STATUS_DSR: u8 = 0b0010_0000;
@@ -126,7 +141,7 @@ const MODEM_STATUS_RLSD: u8 = 0b1000_0000;
 
 impl IoDevice for SerialPortController {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
-        match port {
+        match self.normalize_port(port) {
             SERIAL1_RX_TX_BUFFER => self.port[0].rx_buffer_read(),
             SERIAL2_RX_TX_BUFFER => self.port[1].rx_buffer_read(),
             SERIAL1_INTERRUPT_ENABLE => self.port[0].interrupt_enable_read(),
@@ -146,7 +161,7 @@ impl IoDevice for SerialPortController {
     }
 
     fn write_u8(&mut self, port: u16, byte: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
-        match port {
+        match self.normalize_port(port) {
             SERIAL1_RX_TX_BUFFER => self.port[0].tx_buffer_write(byte),
             SERIAL2_RX_TX_BUFFER => self.port[1].tx_buffer_write(byte),
             SERIAL1_INTERRUPT_ENABLE => self.port[0].interrupt_enable_write(byte),
@@ -166,33 +181,270 @@ impl IoDevice for SerialPortController {
     }
 
     fn port_list(&self) -> Vec<u16> {
-        vec![
-            SERIAL1_RX_TX_BUFFER,
-            SERIAL1_INTERRUPT_ENABLE,
-            SERIAL1_INTERRUPT_ID,
-            SERIAL1_LINE_CONTROL,
-            SERIAL1_MODEM_CONTROL,
-            SERIAL1_LINE_STATUS,
-            SERIAL1_MODEM_STATUS,
-            SERIAL2_RX_TX_BUFFER,
-            SERIAL2_INTERRUPT_ENABLE,
-            SERIAL2_INTERRUPT_ID,
-            SERIAL2_LINE_CONTROL,
-            SERIAL2_MODEM_CONTROL,
-            SERIAL2_LINE_STATUS,
-            SERIAL2_MODEM_STATUS,
-        ]
+        self.port
+            .iter()
+            .flat_map(|port| port.io_base..port.io_base + SERIAL_PORT_SPAN)
+            .collect()
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum StopBits {
     One,
     OneAndAHalf,
     Two,
 }
 
-#[derive(Debug)]
+/// Line ending handling for a serial port bridged to the host's stdin/stdout.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum StdioLineMode {
+    /// Pass bytes through unmodified.
+    #[default]
+    Raw,
+    /// Drop CR from guest output, and convert LF in host input to CR.
+    Crlf,
+}
+
+impl StdioLineMode {
+    /// Translate a byte sent by the guest for output to stdout. Returns None if the byte should
+    /// be dropped.
+    #[inline]
+    fn to_host(self, byte: u8) -> Option<u8> {
+        match (self, byte) {
+            (StdioLineMode::Crlf, b'\r') => None,
+            _ => Some(byte),
+        }
+    }
+
+    /// Translate a byte read from stdin for the guest.
+    #[inline]
+    fn to_guest(self, byte: u8) -> u8 {
+        match (self, byte) {
+            (StdioLineMode::Crlf, b'\n') => b'\r',
+            _ => byte,
+        }
+    }
+}
+
+impl FromStr for StdioLineMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s {
+            "Raw" => Ok(StdioLineMode::Raw),
+            "Crlf" => Ok(StdioLineMode::Crlf),
+            _ => Err("Bad value for StdioLineMode".to_string()),
+        }
+    }
+}
+
+/// Stdin is read by a single background thread for the life of the process. A blocking read can't
+/// be cancelled, so a thread per bridge would be leaked each time a port was re-bridged. Bytes
+/// read are forwarded to the most recently attached stdio bridge, and discarded while no bridge
+/// is attached.
+#[cfg(not(target_arch = "wasm32"))]
+struct StdinShare {
+    reader_started: bool,
+    bridge_id: u64,
+    sink: Option<Sender<u8>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+static STDIN_SHARE: Mutex<StdinShare> = Mutex::new(StdinShare {
+    reader_started: false,
+    bridge_id: 0,
+    sink: None,
+});
+
+/// Body of the shared stdin reader thread.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin() {
+    use std::io::Read;
+
+    let mut buf = [0u8; 256];
+    loop {
+        // Stdin is only locked for the duration of each read.
+        match std::io::stdin().read(&mut buf) {
+            Ok(0) => break,
+            Ok(ct) => forward_stdin(&buf[0..ct]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    // Let the attached bridge know that stdin was closed.
+    STDIN_SHARE.lock().unwrap().sink = None;
+}
+
+/// Send bytes read from stdin to the attached stdio bridge, if any.
+#[cfg(not(target_arch = "wasm32"))]
+fn forward_stdin(bytes: &[u8]) {
+    let mut share = STDIN_SHARE.lock().unwrap();
+    if let Some(tx) = &share.sink {
+        if bytes.iter().any(|byte| tx.send(*byte).is_err()) {
+            // Bridge was closed.
+            share.sink = None;
+        }
+    }
+}
+
+/// A serial port bridged to the host's stdin and stdout. Stdin reads block, so they can't be done
+/// from update(); bytes are received from the shared stdin reader thread instead.
+struct StdioBridge {
+    #[cfg(not(target_arch = "wasm32"))]
+    id: u64,
+    rx: Receiver<u8>,
+    line_mode: StdioLineMode,
+}
+
+impl StdioBridge {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new(line_mode: StdioLineMode) -> anyhow::Result<Self> {
+        let mut share = STDIN_SHARE.lock().unwrap();
+        if !share.reader_started {
+            std::thread::Builder::new()
+                .name("serial_stdin".to_string())
+                .spawn(read_stdin)?;
+            share.reader_started = true;
+        }
+        Ok(Self::attach(&mut share, line_mode))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new(_line_mode: StdioLineMode) -> anyhow::Result<Self> {
+        anyhow::bail!("Stdio bridge is not supported on this platform")
+    }
+
+    /// Attach a new bridge to stdin. Any previously attached bridge stops receiving input.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn attach(share: &mut StdinShare, line_mode: StdioLineMode) -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        share.bridge_id += 1;
+        share.sink = Some(tx);
+        Self {
+            id: share.bridge_id,
+            rx,
+            line_mode,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for StdioBridge {
+    fn drop(&mut self) {
+        if let Ok(mut share) = STDIN_SHARE.lock() {
+            if share.bridge_id == self.id {
+                share.sink = None;
+            }
+        }
+    }
+}
+
+/// Flow control used on a host serial port bridge.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum SerialFlowControl {
+    #[default]
+    None,
+    /// XON/XOFF
+    Software,
+    /// RTS/CTS
+    Hardware,
+}
+
+#[cfg(feature = "serial")]
+impl From<SerialFlowControl> for serialport::FlowControl {
+    fn from(flow_control: SerialFlowControl) -> Self {
+        match flow_control {
+            SerialFlowControl::None => serialport::FlowControl::None,
+            SerialFlowControl::Software => serialport::FlowControl::Software,
+            SerialFlowControl::Hardware => serialport::FlowControl::Hardware,
+        }
+    }
+}
+
+/// Settings for a serial port bridged to a host serial port.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SerialBridgeConfig {
+    /// Host baud rate. If not set, the host port follows the baud rate programmed by the guest.
+    pub baud_rate: Option<u32>,
+    pub flow_control: SerialFlowControl,
+    /// Maximum number of bytes read from the host port per update.
+    pub rx_buffer_size: usize,
+    /// Maximum number of bytes written to the host port per update. Any remaining bytes are
+    /// held until the next update.
+    pub tx_buffer_size: usize,
+    /// Host port read and write timeout, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for SerialBridgeConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: None,
+            flow_control: SerialFlowControl::None,
+            rx_buffer_size: 1000,
+            tx_buffer_size: 1000,
+            timeout_ms: 5,
+        }
+    }
+}
+
+/// A serial port present on the host, for presenting a bridge device picker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostSerialPortInfo {
+    pub name: String,
+    pub description: String,
+}
+
+/// Enumerate the serial ports present on the host.
+#[cfg(feature = "serial")]
+pub fn enumerate_host_ports() -> Vec<HostSerialPortInfo> {
+    let ports = serialport::available_ports().unwrap_or_else(|e| {
+        log::warn!("Failed to enumerate host serial ports: {}", e);
+        Vec::new()
+    });
+
+    ports
+        .into_iter()
+        .map(|port| {
+            let description = match port.port_type {
+                serialport::SerialPortType::UsbPort(usb) => {
+                    let mut description = String::from("USB");
+                    for part in [usb.manufacturer, usb.product].into_iter().flatten() {
+                        description.push(' ');
+                        description.push_str(&part);
+                    }
+                    description
+                }
+                serialport::SerialPortType::PciPort => "PCI".to_string(),
+                serialport::SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+                serialport::SerialPortType::Unknown => String::new(),
+            };
+            HostSerialPortInfo {
+                name: port.port_name,
+                description,
+            }
+        })
+        .collect()
+}
+
+/// Host serial ports are not available without the `serial` feature.
+#[cfg(not(feature = "serial"))]
+pub fn enumerate_host_ports() -> Vec<HostSerialPortInfo> {
+    Vec::new()
+}
+
+enum BridgeTarget {
+    #[cfg(feature = "serial")]
+    Host(Box<dyn serialport::SerialPort>),
+    Stdio(StdioBridge),
+    /// Transmitted bytes are held for the owner to collect with take_tx_bytes().
+    Buffer,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum IntrAction {
     None,
     Raise,
@@ -215,6 +467,7 @@ pub struct SerialPortDebuggerState {
 
 pub struct SerialPort {
     name: String,
+    io_base: u16,
     irq: u8,
     line_control_reg: u8,
     word_length: u8,
@@ -242,14 +495,16 @@ pub struct SerialPort {
     us_per_byte: f64,
 
     // Serial port bridge
-    bridge_port: Option<Box<dyn serialport::SerialPort>>,
-    bridge_buf:  Vec<u8>,
+    bridge_port:   Option<BridgeTarget>,
+    bridge_buf:    Vec<u8>,
+    bridge_config: SerialBridgeConfig,
 }
 
 impl Default for SerialPort {
     fn default() -> Self {
         Self {
             name: String::new(),
+            io_base: SERIAL1_RX_TX_BUFFER,
             irq: 4,
             line_control_reg: 0,
             word_length: 8,
@@ -276,16 +531,18 @@ impl Default for SerialPort {
             tx_timer: 0.0,
             us_per_byte: 833.333, // 9600 baud
 
-            bridge_port: None,
-            bridge_buf:  vec![0; 1000],
+            bridge_port:   None,
+            bridge_buf:    vec![0; SerialBridgeConfig::default().rx_buffer_size],
+            bridge_config: SerialBridgeConfig::default(),
         }
     }
 }
 
 impl SerialPort {
-    pub fn new(name: String, irq: u8) -> Self {
+    pub fn new(name: String, io_base: u16, irq: u8) -> Self {
         Self {
             name,
+            io_base,
             irq,
             ..Default::default()
         }
@@ -294,6 +551,7 @@ impl SerialPort {
     pub fn reset(&mut self) {
         *self = Self {
             name: self.name.clone(),
+            io_base: self.io_base,
             irq: self.irq,
             ..Default::default()
         }
@@ -314,6 +572,45 @@ impl SerialPort {
         }
         let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u16;
         self.us_per_byte = 1.0 / bytes_per_second as f64 * 1_000_000.0;
+        self.update_bridge_baud();
+    }
+
+    /// Return the baud rate the host port should use: the configured override, or the guest's
+    /// programmed baud rate.
+    #[cfg(feature = "serial")]
+    fn bridge_baud(&self) -> u32 {
+        self.bridge_config
+            .baud_rate
+            .unwrap_or(SerialPort::divisor_to_baud(self.divisor) as u32)
+    }
+
+    /// Update the baud rate of a bridged host port to follow the guest.
+    fn update_bridge_baud(&mut self) {
+        #[cfg(feature = "serial")]
+        let baud = self.bridge_baud();
+        #[cfg(feature = "serial")]
+        if let Some(BridgeTarget::Host(host_port)) = &mut self.bridge_port {
+            if let Err(e) = host_port.set_baud_rate(baud) {
+                log::warn!("{}: Failed to set host port baud rate to {}: {}", self.name, baud, e);
+            }
+        }
+    }
+
+    /// Apply new bridge settings, reconfiguring the host port if one is open.
+    fn set_bridge_config(&mut self, config: SerialBridgeConfig) -> anyhow::Result<()> {
+        self.bridge_buf.resize(config.rx_buffer_size.max(1), 0);
+        self.bridge_config = config;
+
+        #[cfg(feature = "serial")]
+        {
+            let baud = self.bridge_baud();
+            if let Some(BridgeTarget::Host(host_port)) = &mut self.bridge_port {
+                host_port.set_baud_rate(baud)?;
+                host_port.set_flow_control(self.bridge_config.flow_control.into())?;
+                host_port.set_timeout(std::time::Duration::from_millis(self.bridge_config.timeout_ms))?;
+            }
+        }
+        Ok(())
     }
 
     fn line_control_read(&self) -> u8 {
@@ -480,8 +777,9 @@ impl SerialPort {
     fn calc_irr(&self) -> u8 {
         let mut byte = 0;
 
-        // Set bit 0 to 1 if interrupt is NOT pending
-        if self.interrupts_active & INTERRUPT_ID_MASK == 0 {
+        // Set bit 0 to 1 if interrupt is NOT pending. A pending Modem Status interrupt has an ID
+        // of 0, so this must check every interrupt type, not just the ID bits.
+        if self.interrupts_active == 0 {
             byte |= 1;
         }
 
@@ -542,15 +840,21 @@ impl SerialPort {
         else {
             let byte = self.modem_status_reg;
 
-            // Clear DCTS and DDSR flags
+            // Clear DCTS, DDSR and TERI flags
             self.modem_status_reg &= !MODEM_STATUS_DCTS;
             self.modem_status_reg &= !MODEM_STATUS_DDSR;
+            self.modem_status_reg &= !MODEM_STATUS_TERI;
+
+            // Reading the Modem Status register resets the Modem Status interrupt
+            self.lower_interrupt_type(INTERRUPT_MODEM_STATUS);
 
             byte
         }
     }
 
     fn set_modem_status_connected(&mut self) {
+        let old_status = self.modem_status_reg;
+
         if self.modem_status_reg & MODEM_STATUS_CTS == 0 {
             self.modem_status_reg |= MODEM_STATUS_CTS;
             self.modem_status_reg |= MODEM_STATUS_DCTS;
@@ -560,6 +864,34 @@ impl SerialPort {
             self.modem_status_reg |= MODEM_STATUS_DSR;
             self.modem_status_reg |= MODEM_STATUS_DDSR;
         }
+
+        if self.modem_status_reg != old_status {
+            self.raise_interrupt_type(INTERRUPT_MODEM_STATUS);
+        }
+    }
+
+    fn set_modem_status_disconnected(&mut self) {
+        let old_status = self.modem_status_reg;
+
+        if self.modem_status_reg & MODEM_STATUS_CTS != 0 {
+            self.modem_status_reg &= !MODEM_STATUS_CTS;
+            self.modem_status_reg |= MODEM_STATUS_DCTS;
+        }
+
+        if self.modem_status_reg & MODEM_STATUS_DSR != 0 {
+            self.modem_status_reg &= !MODEM_STATUS_DSR;
+            self.modem_status_reg |= MODEM_STATUS_DDSR;
+        }
+
+        // The trailing edge of Ring Indicator is latched in TERI.
+        if self.modem_status_reg & MODEM_STATUS_RI != 0 {
+            self.modem_status_reg &= !MODEM_STATUS_RI;
+            self.modem_status_reg |= MODEM_STATUS_TERI;
+        }
+
+        if self.modem_status_reg != old_status {
+            self.raise_interrupt_type(INTERRUPT_MODEM_STATUS);
+        }
     }
 
     fn raise_interrupt_type(&mut self, interrupt_flag: u8) {
@@ -586,17 +918,23 @@ impl SerialPort {
         }
     }
 
+    #[cfg(feature = "serial")]
     fn bridge_port(&mut self, port_name: String) -> anyhow::Result<bool> {
-        let port_result = serialport::new(port_name.clone(), 9600)
-            .timeout(std::time::Duration::from_millis(5))
+        let port_result = serialport::new(port_name.clone(), self.bridge_baud())
+            .timeout(std::time::Duration::from_millis(self.bridge_config.timeout_ms))
+            .flow_control(self.bridge_config.flow_control.into())
             .stop_bits(serialport::StopBits::One)
             .parity(serialport::Parity::None)
             .open();
 
         match port_result {
             Ok(bridge_port) => {
-                log::trace!("Successfully opened host port {}", port_name);
-                self.bridge_port = Some(bridge_port);
+                log::trace!(
+                    "Successfully opened host port {} at {} baud",
+                    port_name,
+                    self.bridge_baud()
+                );
+                self.bridge_port = Some(BridgeTarget::Host(bridge_port));
                 self.set_modem_status_connected();
                 Ok(true)
             }
@@ -606,22 +944,74 @@ impl SerialPort {
             }
         }
     }
+
+    #[cfg(not(feature = "serial"))]
+    fn bridge_port(&mut self, _port_name: String) -> anyhow::Result<bool> {
+        anyhow::bail!("Host serial port support was not enabled in this build")
+    }
+
+    fn bridge_stdio(&mut self, line_mode: StdioLineMode) -> anyhow::Result<bool> {
+        self.bridge_port = Some(BridgeTarget::Stdio(StdioBridge::new(line_mode)?));
+        log::trace!("{}: Bridged to stdio", self.name);
+        self.set_modem_status_connected();
+        Ok(true)
+    }
+
+    fn bridge_buffer(&mut self) {
+        self.bridge_port = Some(BridgeTarget::Buffer);
+        log::trace!("{}: Bridged to buffer", self.name);
+        self.set_modem_status_connected();
+    }
+
+    fn unbridge_port(&mut self) -> bool {
+        if self.bridge_port.take().is_some() {
+            log::trace!("{}: Closed host port", self.name);
+            self.tx_queue.clear();
+            self.rx_queue.clear();
+            self.set_modem_status_disconnected();
+            true
+        }
+        else {
+            false
+        }
+    }
 }
 
+pub const SERIAL_PORT_COUNT: usize = 2;
+
 pub struct SerialPortController {
-    port: [SerialPort; 2],
+    port: [SerialPort; SERIAL_PORT_COUNT],
 }
 
 impl SerialPortController {
     pub fn new() -> Self {
         Self {
             port: [
-                SerialPort::new("COM1".to_string(), SERIAL1_IRQ),
-                SerialPort::new("COM2".to_string(), SERIAL2_IRQ),
+                SerialPort::new("COM1".to_string(), SERIAL1_RX_TX_BUFFER, SERIAL1_IRQ),
+                SerialPort::new("COM2".to_string(), SERIAL2_RX_TX_BUFFER, SERIAL2_IRQ),
             ],
         }
     }
 
+    /// Relocate the specified serial port to a new IO base address and IRQ, as if the card's
+    /// jumpers had been changed. This should be done before the controller's ports are mapped.
+    pub fn set_port_resources(&mut self, port: usize, io_base: u16, irq: u8) {
+        self.port[port].io_base = io_base;
+        self.port[port].irq = irq;
+    }
+
+    /// Translate an IO address into the corresponding address at the port's default IO base, so
+    /// that relocated ports can share the same register decoding.
+    fn normalize_port(&self, port: u16) -> u16 {
+        const DEFAULT_BASES: [u16; SERIAL_PORT_COUNT] = [SERIAL1_RX_TX_BUFFER, SERIAL2_RX_TX_BUFFER];
+        for (i, serial_port) in self.port.iter().enumerate() {
+            if port >= serial_port.io_base && port < serial_port.io_base + SERIAL_PORT_SPAN {
+                return DEFAULT_BASES[i] + (port - serial_port.io_base);
+            }
+        }
+        0
+    }
+
     pub fn get_debug_state(&self) -> Vec<SerialPortDebuggerState> {
         let mut state = Vec::new();
 
@@ -665,6 +1055,80 @@ impl SerialPortController {
         self.port[port].bridge_port(port_name)
     }
 
+    /// Set the host bridge settings for the specified serial port. Settings apply to any host
+    /// port currently bridged and to subsequent bridges.
+    pub fn set_bridge_config(&mut self, port: usize, config: SerialBridgeConfig) -> anyhow::Result<()> {
+        self.port[port].set_bridge_config(config)
+    }
+
+    /// Return the host bridge settings for the specified serial port.
+    pub fn bridge_config(&self, port: usize) -> &SerialBridgeConfig {
+        &self.port[port].bridge_config
+    }
+
+    /// Bridge the specified serial port to the emulator process's stdin and stdout.
+    pub fn bridge_stdio(&mut self, port: usize, line_mode: StdioLineMode) -> anyhow::Result<bool> {
+        self.port[port].bridge_stdio(line_mode)
+    }
+
+    /// Bridge the specified serial port to an internal buffer. Transmitted bytes are collected with
+    /// take_tx_bytes() and received bytes supplied with queue_byte().
+    pub fn bridge_buffer(&mut self, port: usize) {
+        self.port[port].bridge_buffer()
+    }
+
+    /// Remove and return all bytes transmitted by the specified serial port since the last call.
+    /// Only ports bridged to a buffer accumulate bytes; other bridges consume them in update().
+    pub fn take_tx_bytes(&mut self, port: usize) -> Vec<u8> {
+        match self.port[port].bridge_port {
+            Some(BridgeTarget::Buffer) => self.port[port].tx_queue.drain(..).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Close the host port bridged to the specified serial port, if any. Returns true if a
+    /// bridge was removed.
+    pub fn unbridge_port(&mut self, port: usize) -> bool {
+        self.port[port].unbridge_port()
+    }
+
+    /// Returns true if the specified serial port is bridged to a host port.
+    pub fn is_bridged(&self, port: usize) -> bool {
+        self.port[port].bridge_port.is_some()
+    }
+
+    /// Signal the attachment or removal of a device on the specified serial port by driving
+    /// the CTS and DSR lines. Removal also discards any bytes the device had queued.
+    pub fn set_device_connected(&mut self, port: usize, connected: bool) {
+        if connected {
+            self.port[port].set_modem_status_connected();
+        }
+        else {
+            self.port[port].rx_queue.clear();
+            self.port[port].set_modem_status_disconnected();
+        }
+    }
+
+    /// Return the time in microseconds until the serial ports next need to be run, or None if
+    /// all ports are idle. A port is busy while it has an interrupt to deliver, bytes waiting to be
+    /// received, or a byte waiting to be transmitted.
+    pub fn next_deadline(&self) -> Option<f64> {
+        let mut deadline = None;
+
+        for port in &self.port {
+            if !matches!(port.intr_action, IntrAction::None) {
+                return Some(0.0);
+            }
+            if !port.rx_queue.is_empty() {
+                deadline = earliest_deadline(deadline, Some(port.us_per_byte - port.rx_timer));
+            }
+            if !port.tx_holding_empty {
+                deadline = earliest_deadline(deadline, Some(port.us_per_byte - port.tx_timer));
+            }
+        }
+        deadline
+    }
+
     /// Run the serial ports for the specified number of microseconds
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
         for port in self.port.iter_mut() {
@@ -739,21 +1203,28 @@ impl SerialPortController {
     pub fn update(&mut self) {
         for port in &mut self.port {
             match &mut port.bridge_port {
-                Some(bridge_port) => {
-                    // Write any pending bytes
+                #[cfg(feature = "serial")]
+                Some(BridgeTarget::Host(bridge_port)) => {
+                    use std::io::Read;
+
+                    // Write pending bytes, up to the configured buffer size. Bytes the host
+                    // port did not accept are retried on the next update.
                     if port.tx_queue.len() > 0 {
                         port.tx_queue.make_contiguous();
                         let (tx1, _) = port.tx_queue.as_slices();
+                        let tx_len = tx1.len().min(port.bridge_config.tx_buffer_size.max(1));
 
-                        match bridge_port.write(tx1) {
-                            Ok(_) => {
+                        match bridge_port.write(&tx1[..tx_len]) {
+                            Ok(ct) => {
                                 //log::trace!("Wrote bytes: {:?}", tx1);
+                                port.tx_queue.drain(..ct);
                             }
                             Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-                            Err(e) => log::error!("Error writing byte: {:?}", e),
+                            Err(e) => {
+                                log::error!("Error writing byte: {:?}", e);
+                                port.tx_queue.clear();
+                            }
                         }
-
-                        port.tx_queue.clear();
                     }
 
                     // Read any pending bytes
@@ -774,8 +1245,251 @@ impl SerialPortController {
                         }
                     }
                 }
-                None => {}
+                Some(BridgeTarget::Stdio(stdio)) => {
+                    if !port.tx_queue.is_empty() {
+                        let mut stdout = std::io::stdout().lock();
+                        for byte in port.tx_queue.drain(..).filter_map(|byte| stdio.line_mode.to_host(byte)) {
+                            if let Err(e) = stdout.write_all(&[byte]) {
+                                log::error!("Error writing to stdout: {:?}", e);
+                                break;
+                            }
+                        }
+                        _ = stdout.flush();
+                    }
+
+                    loop {
+                        match stdio.rx.try_recv() {
+                            Ok(byte) => port.rx_queue.push_back(stdio.line_mode.to_guest(byte)),
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Disconnected) => {
+                                log::debug!("{}: stdin closed", port.name);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Some(BridgeTarget::Buffer) | None => {}
             }
         }
     }
 }
+
+/// The serializable state of a single UART. The port's IO base, IRQ and host bridge are
+/// configuration or host resources, and are not included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SerialPortState {
+    pub line_control_reg: u8,
+    pub word_length: u8,
+    pub stop_bits: StopBits,
+    pub parity_enable: bool,
+    pub divisor_latch_access: bool,
+    pub divisor: u16,
+    pub line_status_reg: u8,
+    pub interrupts_active: u8,
+    pub interrupt_enable_reg: u8,
+    pub intr_action: IntrAction,
+    pub modem_control_reg: u8,
+    pub loopback: bool,
+    pub modem_status_reg: u8,
+    pub rx_byte: u8,
+    pub rx_count: usize,
+    pub rx_was_read: bool,
+    pub tx_holding_reg: u8,
+    pub tx_holding_empty: bool,
+    pub rx_queue: Vec<u8>,
+    pub rx_timer: f64,
+    pub tx_count: usize,
+    pub tx_queue: Vec<u8>,
+    pub tx_timer: f64,
+    pub us_per_byte: f64,
+}
+
+/// The serializable state of each port of the serial controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SerialPortControllerState {
+    pub ports: Vec<SerialPortState>,
+}
+
+impl DeviceState for SerialPortController {
+    type State = SerialPortControllerState;
+
+    fn save_state(&self) -> SerialPortControllerState {
+        SerialPortControllerState {
+            ports: self
+                .port
+                .iter()
+                .map(|p| SerialPortState {
+                    line_control_reg: p.line_control_reg,
+                    word_length: p.word_length,
+                    stop_bits: p.stop_bits,
+                    parity_enable: p.parity_enable,
+                    divisor_latch_access: p.divisor_latch_access,
+                    divisor: p.divisor,
+                    line_status_reg: p.line_status_reg,
+                    interrupts_active: p.interrupts_active,
+                    interrupt_enable_reg: p.interrupt_enable_reg,
+                    intr_action: p.intr_action,
+                    modem_control_reg: p.modem_control_reg,
+                    loopback: p.loopback,
+                    modem_status_reg: p.modem_status_reg,
+                    rx_byte: p.rx_byte,
+                    rx_count: p.rx_count,
+                    rx_was_read: p.rx_was_read,
+                    tx_holding_reg: p.tx_holding_reg,
+                    tx_holding_empty: p.tx_holding_empty,
+                    rx_queue: p.rx_queue.iter().copied().collect(),
+                    rx_timer: p.rx_timer,
+                    tx_count: p.tx_count,
+                    tx_queue: p.tx_queue.iter().copied().collect(),
+                    tx_timer: p.tx_timer,
+                    us_per_byte: p.us_per_byte,
+                })
+                .collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: SerialPortControllerState) -> anyhow::Result<()> {
+        if state.ports.len() != SERIAL_PORT_COUNT {
+            anyhow::bail!(
+                "Serial state has {} ports, expected {}",
+                state.ports.len(),
+                SERIAL_PORT_COUNT
+            );
+        }
+        for (p, s) in self.port.iter_mut().zip(state.ports) {
+            p.line_control_reg = s.line_control_reg;
+            p.word_length = s.word_length;
+            p.stop_bits = s.stop_bits;
+            p.parity_enable = s.parity_enable;
+            p.divisor_latch_access = s.divisor_latch_access;
+            p.divisor = s.divisor;
+            p.line_status_reg = s.line_status_reg;
+            p.interrupts_active = s.interrupts_active;
+            p.interrupt_enable_reg = s.interrupt_enable_reg;
+            p.intr_action = s.intr_action;
+            p.modem_control_reg = s.modem_control_reg;
+            p.loopback = s.loopback;
+            p.modem_status_reg = s.modem_status_reg;
+            p.rx_byte = s.rx_byte;
+            p.rx_count = s.rx_count;
+            p.rx_was_read = s.rx_was_read;
+            p.tx_holding_reg = s.tx_holding_reg;
+            p.tx_holding_empty = s.tx_holding_empty;
+            p.rx_queue = s.rx_queue.into();
+            p.rx_timer = s.rx_timer;
+            p.tx_count = s.tx_count;
+            p.tx_queue = s.tx_queue.into();
+            p.tx_timer = s.tx_timer;
+            p.us_per_byte = s.us_per_byte;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_state_roundtrip() {
+        let mut serial = SerialPortController::new();
+        // 1200 baud, 7 data bits, and a byte waiting to be received on COM2.
+        serial.write_u8(
+            SERIAL1_LINE_CONTROL,
+            0x80 | 0x02,
+            None,
+            DeviceRunTimeUnit::Microseconds(0.0),
+        );
+        serial.write_u8(SERIAL1_RX_TX_BUFFER, 0x60, None, DeviceRunTimeUnit::Microseconds(0.0));
+        serial.write_u8(SERIAL1_LINE_CONTROL, 0x02, None, DeviceRunTimeUnit::Microseconds(0.0));
+        serial.queue_byte(1, 0x4D);
+
+        let json = serial.serialize_state().unwrap();
+
+        let mut restored = SerialPortController::new();
+        restored.deserialize_state(&json).unwrap();
+        assert_eq!(restored.dump_state(), serial.dump_state());
+        assert_eq!(restored.port[0].divisor, 0x60);
+        assert_eq!(restored.port[0].word_length, 7);
+        assert_eq!(restored.port[1].rx_queue, [0x4D]);
+
+        let mut state = serial.save_state();
+        state.ports.pop();
+        assert!(restored.load_state(state).is_err());
+    }
+
+    #[test]
+    fn test_modem_status_interrupt() {
+        let mut serial = SerialPortController::new();
+        let delta = DeviceRunTimeUnit::Microseconds(0.0);
+        serial.write_u8(SERIAL1_MODEM_CONTROL, MODEM_CONTROL_OUT2, None, delta);
+        serial.write_u8(SERIAL1_INTERRUPT_ENABLE, INTERRUPT_MODEM_STATUS, None, delta);
+
+        // Attaching a device raises CTS and DSR, and a Modem Status interrupt.
+        serial.set_device_connected(0, true);
+        assert!(matches!(serial.port[0].intr_action, IntrAction::Raise));
+        assert_eq!(serial.read_u8(SERIAL1_INTERRUPT_ID, delta), 0b000);
+
+        // Reading the Modem Status register clears the deltas and the interrupt.
+        let status = MODEM_STATUS_CTS | MODEM_STATUS_DSR;
+        assert_eq!(
+            serial.read_u8(SERIAL1_MODEM_STATUS, delta),
+            status | MODEM_STATUS_DCTS | MODEM_STATUS_DDSR
+        );
+        assert!(matches!(serial.port[0].intr_action, IntrAction::Lower));
+        assert_eq!(serial.read_u8(SERIAL1_INTERRUPT_ID, delta), 0b001);
+        assert_eq!(serial.read_u8(SERIAL1_MODEM_STATUS, delta), status);
+
+        // Removing the device drops the lines and interrupts again.
+        serial.set_device_connected(0, false);
+        assert_eq!(serial.read_u8(SERIAL1_INTERRUPT_ID, delta), 0b000);
+        assert_eq!(
+            serial.read_u8(SERIAL1_MODEM_STATUS, delta),
+            MODEM_STATUS_DCTS | MODEM_STATUS_DDSR
+        );
+        assert_eq!(serial.read_u8(SERIAL1_INTERRUPT_ID, delta), 0b001);
+    }
+
+    #[test]
+    fn test_stdio_line_mode() {
+        assert_eq!(StdioLineMode::from_str("Crlf"), Ok(StdioLineMode::Crlf));
+        assert!(StdioLineMode::from_str("crlf").is_err());
+
+        let output = |mode: StdioLineMode| b"a\r\n".iter().filter_map(|b| mode.to_host(*b)).collect::<Vec<_>>();
+        assert_eq!(output(StdioLineMode::Raw), b"a\r\n");
+        assert_eq!(output(StdioLineMode::Crlf), b"a\n");
+        assert_eq!(StdioLineMode::Raw.to_guest(b'\n'), b'\n');
+        assert_eq!(StdioLineMode::Crlf.to_guest(b'\n'), b'\r');
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_stdio_bridge_attach() {
+        let attach = |mode| BridgeTarget::Stdio(StdioBridge::attach(&mut STDIN_SHARE.lock().unwrap(), mode));
+        let mut serial = SerialPortController::new();
+
+        // Input is delivered to the bridged port with the port's line mode.
+        serial.port[0].bridge_port = Some(attach(StdioLineMode::Crlf));
+        forward_stdin(b"a\n");
+        serial.update();
+        assert_eq!(serial.port[0].rx_queue, [b'a', b'\r']);
+
+        // Bridging another port takes over stdin.
+        serial.port[1].bridge_port = Some(attach(StdioLineMode::Raw));
+        forward_stdin(b"b\n");
+        serial.update();
+        assert_eq!(serial.port[0].rx_queue, [b'a', b'\r']);
+        assert_eq!(serial.port[1].rx_queue, [b'b', b'\n']);
+
+        // Dropping a replaced bridge leaves the current one attached.
+        assert!(serial.port[0].unbridge_port());
+        forward_stdin(b"c");
+        serial.update();
+        assert_eq!(serial.port[1].rx_queue, [b'b', b'\n', b'c']);
+
+        // With no bridge attached, input is discarded.
+        assert!(serial.port[1].unbridge_port());
+        forward_stdin(b"d");
+        assert!(STDIN_SHARE.lock().unwrap().sink.is_none());
+    }
+}
@@ -100,6 +100,7 @@ pub enum PaletteSize {
 }
 
 #[bitfield]
+#[derive(Copy, Clone)]
 pub struct AttributeAddress {
     pub address: B5,
     #[bits = 1]
@@ -257,4 +258,17 @@ impl VGACard {
         }
         self.recalculate_mode();
     }
+
+    /// Return the value of the Attribute register at the specified index.
+    pub fn attribute_register(&self, idx: u8) -> u8 {
+        match idx {
+            0x00..=0x0F => self.attribute_palette_registers[idx as usize],
+            0x10 => self.attribute_mode_control.bytes[0],
+            0x11 => self.attribute_overscan_color.into_bytes()[0],
+            0x12 => self.attribute_color_plane_enable.bytes[0],
+            0x13 => self.attribute_pel_panning,
+            0x14 => self.attribute_color_select.bytes[0],
+            _ => 0,
+        }
+    }
 }
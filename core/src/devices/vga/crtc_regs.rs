@@ -138,14 +138,14 @@ pub struct CMaximumScanline {
     pub maximum_scanline: B5,
     pub vbs_bit_9: B1,
     pub lc_bit_9: B1,
-    pub two_to_four: bool,
+    pub scan_doubling: bool, // Double each scanline (200-line modes on a 400-line raster)
 }
 
 #[bitfield]
 #[derive(Copy, Clone)]
 pub struct CCursorStart {
     pub cursor_start: B5,
-    pub cursor_enable: bool,
+    pub cursor_disable: bool,
     #[skip]
     unused: B2,
 }
@@ -533,32 +533,38 @@ impl VGACard {
     ///
     /// Unlike the EGA, most of the VGA CRTC registers are readable.
     pub fn read_crtc_register(&mut self) -> u8 {
-        match self.crtc_register_selected {
-            CRTCRegister::HorizontalTotal => self.crtc_horizontal_total,
-            CRTCRegister::HorizontalDisplayEnd => self.crtc_horizontal_display_end,
-            CRTCRegister::StartHorizontalBlank => self.crtc_start_horizontal_blank,
-            CRTCRegister::EndHorizontalBlank => self.crtc_end_horizontal_blank.into_bytes()[0],
-            CRTCRegister::StartHorizontalRetrace => self.crtc_start_horizontal_retrace,
-            CRTCRegister::EndHorizontalRetrace => self.crtc_end_horizontal_retrace.into_bytes()[0],
-            CRTCRegister::VerticalTotal => (self.crtc_vertical_total & 0xFF) as u8,
-            CRTCRegister::Overflow => self.crtc_overflow,
-            CRTCRegister::PresetRowScan => self.crtc_preset_row_scan.into_bytes()[0],
-            CRTCRegister::MaximumScanLine => self.crtc_maximum_scanline.into_bytes()[0],
-            CRTCRegister::CursorStartLine => self.crtc_cursor_start.into_bytes()[0],
-            CRTCRegister::CursorEndLine => self.crtc_cursor_end.into_bytes()[0],
-            CRTCRegister::StartAddressH => self.crtc_start_address_ho,
-            CRTCRegister::StartAddressL => self.crtc_start_address_lo,
-            CRTCRegister::CursorAddressH => self.crtc_cursor_address_ho,
-            CRTCRegister::CursorAddressL => self.crtc_cursor_address_lo,
-            CRTCRegister::VerticalRetraceStart => (self.crtc_vertical_retrace_start & 0xFF) as u8,
-            CRTCRegister::VerticalRetraceEnd => self.crtc_vertical_retrace_end.into_bytes()[0],
-            CRTCRegister::VerticalDisplayEnd => (self.crtc_vertical_display_end & 0xFF) as u8,
-            CRTCRegister::Offset => self.crtc_offset,
-            CRTCRegister::UnderlineLocation => self.crtc_underline_location.into_bytes()[0],
-            CRTCRegister::StartVerticalBlank => (self.crtc_start_vertical_blank & 0xFF) as u8,
-            CRTCRegister::EndVerticalBlank => self.crtc_end_vertical_blank,
-            CRTCRegister::ModeControl => self.crtc_mode_control.into_bytes()[0],
-            CRTCRegister::LineCompare => (self.crtc_line_compare & 0xFF) as u8,
+        self.crtc_register(self.crtc_register_select_byte)
+    }
+
+    /// Return the value of the CRTC register at the specified index.
+    pub fn crtc_register(&self, idx: u8) -> u8 {
+        match idx {
+            0x00 => self.crtc_horizontal_total,
+            0x01 => self.crtc_horizontal_display_end,
+            0x02 => self.crtc_start_horizontal_blank,
+            0x03 => self.crtc_end_horizontal_blank.into_bytes()[0],
+            0x04 => self.crtc_start_horizontal_retrace,
+            0x05 => self.crtc_end_horizontal_retrace.into_bytes()[0],
+            0x06 => (self.crtc_vertical_total & 0xFF) as u8,
+            0x07 => self.crtc_overflow,
+            0x08 => self.crtc_preset_row_scan.into_bytes()[0],
+            0x09 => self.crtc_maximum_scanline.into_bytes()[0],
+            0x0A => self.crtc_cursor_start.into_bytes()[0],
+            0x0B => self.crtc_cursor_end.into_bytes()[0],
+            0x0C => self.crtc_start_address_ho,
+            0x0D => self.crtc_start_address_lo,
+            0x0E => self.crtc_cursor_address_ho,
+            0x0F => self.crtc_cursor_address_lo,
+            0x10 => (self.crtc_vertical_retrace_start & 0xFF) as u8,
+            0x11 => self.crtc_vertical_retrace_end.into_bytes()[0],
+            0x12 => (self.crtc_vertical_display_end & 0xFF) as u8,
+            0x13 => self.crtc_offset,
+            0x14 => self.crtc_underline_location.into_bytes()[0],
+            0x15 => (self.crtc_start_vertical_blank & 0xFF) as u8,
+            0x16 => self.crtc_end_vertical_blank,
+            0x17 => self.crtc_mode_control.into_bytes()[0],
+            0x18 => (self.crtc_line_compare & 0xFF) as u8,
+            _ => 0,
         }
     }
 }
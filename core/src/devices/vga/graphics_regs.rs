@@ -270,16 +270,22 @@ impl VGACard {
     }
 
     pub fn read_graphics_data(&self) -> u8 {
-        match self.graphics_register_selected {
-            GraphicsRegister::SetReset => self.graphics_set_reset,
-            GraphicsRegister::EnableSetReset => self.graphics_enable_set_reset,
-            GraphicsRegister::ColorCompare => self.graphics_color_compare,
-            GraphicsRegister::DataRotate => self.graphics_data_rotate.bytes[0],
-            GraphicsRegister::ReadMapSelect => self.graphics_read_map_select,
-            GraphicsRegister::Mode => self.graphics_mode.bytes[0],
-            GraphicsRegister::Miscellaneous => self.graphics_micellaneous.bytes[0],
-            GraphicsRegister::ColorDontCare => self.graphics_color_dont_care,
-            GraphicsRegister::BitMask => self.graphics_bitmask,
+        self.graphics_register(self.graphics_register_selected as u8)
+    }
+
+    /// Return the value of the Graphics register at the specified index.
+    pub fn graphics_register(&self, idx: u8) -> u8 {
+        match idx {
+            0x00 => self.graphics_set_reset,
+            0x01 => self.graphics_enable_set_reset,
+            0x02 => self.graphics_color_compare,
+            0x03 => self.graphics_data_rotate.bytes[0],
+            0x04 => self.graphics_read_map_select,
+            0x05 => self.graphics_mode.bytes[0],
+            0x06 => self.graphics_micellaneous.bytes[0],
+            0x07 => self.graphics_color_dont_care,
+            0x08 => self.graphics_bitmask,
+            _ => 0,
         }
     }
 }
@@ -45,16 +45,20 @@ use modular_bitfield::prelude::*;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemoryMappedDevice},
+    devices::pic::Pic,
     tracelogger::TraceLogger,
 };
 
-use crate::videocard::*;
+use crate::device_traits::videocard::*;
 
 mod attribute_regs;
 mod color_regs;
 mod crtc_regs;
 mod graphics_regs;
 mod sequencer_regs;
+mod state;
+
+pub use state::VgaState;
 
 use attribute_regs::*;
 #[allow(unused_imports)]
@@ -79,6 +83,21 @@ pub const MEM_SIZE_32K: usize = 32768;
 pub const VGA_TEXT_PLANE_SIZE: usize = 16384;
 pub const VGA_GFX_PLANE_SIZE: usize = 65536;
 
+// The VGA renders only the active display area into its frame buffers. The largest active area
+// is 720 pixels (80 columns of 9-dot characters) by 480 lines (mode 12h).
+const VGA_MAX_RASTER_X: u32 = 720;
+const VGA_MAX_RASTER_Y: u32 = 480;
+const VGA_MAX_CLOCK: usize = (VGA_MAX_RASTER_X * VGA_MAX_RASTER_Y) as usize;
+
+// The VGA derives its blink rates from vertical sync. The cursor toggles every 8 frames and
+// blinking character attributes toggle every 16 frames.
+const VGA_CURSOR_BLINK_MASK: u64 = 0x08;
+const VGA_ATTRIBUTE_BLINK_MASK: u64 = 0x10;
+
+// Line graphics characters that replicate their 8th column into the 9th in 9-dot modes.
+const VGA_LINE_GFX_FIRST: u8 = 0xC0;
+const VGA_LINE_GFX_LAST: u8 = 0xDF;
+
 // For an EGA card connected to an EGA monitor
 // See http://www.minuszerodegrees.net/ibm_ega/ibm_ega_switch_settings.htm
 // This is inverted (Checkit will report 0110)
@@ -242,6 +261,25 @@ pub struct EGAFont {
     data: &'static [u8],
 }
 
+const VGA_APERTURE_DESCS: [DisplayApertureDesc; 4] = [
+    DisplayApertureDesc {
+        name: "Cropped",
+        aper_enum: DisplayApertureType::Cropped,
+    },
+    DisplayApertureDesc {
+        name: "Accurate",
+        aper_enum: DisplayApertureType::Accurate,
+    },
+    DisplayApertureDesc {
+        name: "Full",
+        aper_enum: DisplayApertureType::Full,
+    },
+    DisplayApertureDesc {
+        name: "Debug",
+        aper_enum: DisplayApertureType::Debug,
+    },
+];
+
 static EGA_FONTS: [EGAFont; 2] = [
     EGAFont {
         w:    8,
@@ -297,12 +335,17 @@ pub struct VGACard {
     scanline: u32,
     scanline_cycles: u32,
     frame_cycles: u32,
+    frame_count: u64,
     vga_cycle_accumulator: f64,
     cursor_frames: u32,
     in_hblank: bool,
     in_vblank: bool,
 
+    display_w: u32, // Width of the active display area in pixels
+    display_h: u32, // Height of the active display area in scanlines
+
     cursor_status: bool,
+    blink_state: bool, // Whether blinking characters are currently shown
     cursor_slowblink: bool,
     cursor_blink_rate: f64,
 
@@ -399,6 +442,10 @@ pub struct VGACard {
     pipeline_buf: [u8; 4],
     write_buf: [u8; 4],
 
+    back_buf: usize,
+    front_buf: usize,
+    buf: [Box<[u8; VGA_MAX_CLOCK]>; 2],
+
     trace_logger: TraceLogger,
 }
 
@@ -595,7 +642,7 @@ impl VGACard {
                 },
             ],
             u_timings: Default::default(),
-            extents: Default::default(),
+            extents: VGACard::get_default_extents(),
             mode_byte: 0,
             display_mode: DisplayMode::Mode3TextCo80,
             mode_enable: true,
@@ -606,6 +653,7 @@ impl VGACard {
             mode_hires_txt: true,
             mode_blinking: true,
             frame_cycles: 0,
+            frame_count: 0,
             vga_cycle_accumulator: 0.0,
             cursor_frames: 0,
             scanline: 0,
@@ -613,7 +661,11 @@ impl VGACard {
             in_hblank: false,
             in_vblank: false,
 
-            cursor_status: false,
+            display_w: 0,
+            display_h: 0,
+
+            cursor_status: true,
+            blink_state: true,
             cursor_slowblink: false,
             cursor_blink_rate: CGA_DEFAULT_CURSOR_BLINK_RATE,
 
@@ -712,10 +764,46 @@ impl VGACard {
             pipeline_buf: [0; 4],
             write_buf: [0; 4],
 
+            back_buf: 1,
+            front_buf: 0,
+            buf: [Box::new([0; VGA_MAX_CLOCK]), Box::new([0; VGA_MAX_CLOCK])],
+
             trace_logger,
         }
     }
 
+    fn get_default_extents() -> DisplayExtents {
+        DisplayExtents {
+            apertures: VGACard::get_apertures(VGA_MAX_RASTER_X, VGA_MAX_RASTER_Y),
+            field_w: VGA_MAX_RASTER_X,
+            field_h: VGA_MAX_RASTER_Y,
+            row_stride: VGA_MAX_RASTER_X as usize,
+            double_scan: false,
+            mode_byte: 0,
+        }
+    }
+
+    /// Build the aperture list for an active display area of the given size. The VGA only
+    /// renders the active display area, so every aperture covers the same rect.
+    fn get_apertures(w: u32, h: u32) -> Vec<DisplayAperture> {
+        let aperture = DisplayAperture {
+            w,
+            h,
+            x: 0,
+            y: 0,
+            debug: false,
+        };
+        vec![
+            aperture,
+            aperture,
+            aperture,
+            DisplayAperture {
+                debug: true,
+                ..aperture
+            },
+        ]
+    }
+
     fn reset_private(&mut self) {
         self.mode_byte = 0;
         self.display_mode = DisplayMode::Mode3TextCo80;
@@ -727,13 +815,15 @@ impl VGACard {
         self.mode_hires_txt = true;
         self.mode_blinking = true;
         self.frame_cycles = 0;
+        self.frame_count = 0;
         self.cursor_frames = 0;
         self.scanline = 0;
         self.scanline_cycles = 0;
         self.in_hblank = false;
         self.in_vblank = false;
 
-        self.cursor_status = false;
+        self.cursor_status = true;
+        self.blink_state = true;
         self.cursor_slowblink = false;
         self.cursor_blink_rate = CGA_DEFAULT_CURSOR_BLINK_RATE;
 
@@ -768,7 +858,28 @@ impl VGACard {
     }
 
     fn get_cursor_status(&self) -> bool {
-        self.cursor_status
+        self.cursor_status && !self.crtc_cursor_start.cursor_disable()
+    }
+
+    /// Return the offset into plane 2 of the font selected by a Character Map Select value.
+    fn get_font_offset(map: u8) -> usize {
+        match map {
+            0 => 0x0000,
+            1 => 0x4000,
+            2 => 0x8000,
+            3 => 0xC000,
+            4 => 0x2000,
+            5 => 0x6000,
+            6 => 0xA000,
+            _ => 0xE000,
+        }
+    }
+
+    /// Return whether host accesses are in odd/even mode, where address line 0 selects between
+    /// planes 0 and 2 (even) or 1 and 3 (odd). The sequencer Memory Mode bit we call
+    /// odd_even_enable actually disables odd/even addressing when set.
+    fn host_odd_even(&self) -> bool {
+        !self.sequencer_memory_mode.odd_even_enable() && !self.sequencer_memory_mode.chain4_enable()
     }
 
     /// Handle a write to the External Miscellaneous Output Register, 0x3C2
@@ -1130,10 +1241,7 @@ impl VGACard {
     */
 
     pub fn recalculate_timings(&mut self) {
-        let char_clock = match self.sequencer_clocking_mode.character_clock() {
-            CharacterClock::EightDots => 8u32,
-            CharacterClock::NineDots => 9u32,
-        };
+        let char_clock = self.get_character_width();
 
         self.u_timings.character_clock = char_clock;
 
@@ -1146,6 +1254,174 @@ impl VGACard {
 
         self.u_timings.vblank_start = self.crtc_start_vertical_blank as u32;
         self.u_timings.vblank_end = self.crtc_end_vertical_blank_norm as u32;
+
+        // Each character clock is stretched to two pixels when the dot clock is halved.
+        let display_w = (self.crtc_horizontal_display_end as u32 + 1) * char_clock * self.get_pixel_repeat();
+        let display_h = self.crtc_vertical_display_end as u32 + 1;
+        self.display_w = std::cmp::min(display_w, VGA_MAX_RASTER_X);
+        self.display_h = std::cmp::min(display_h, VGA_MAX_RASTER_Y);
+        self.extents.apertures = VGACard::get_apertures(self.display_w, self.display_h);
+    }
+
+    /// Return the width in dots of a character clock.
+    fn get_character_width(&self) -> u32 {
+        match self.sequencer_clocking_mode.character_clock() {
+            CharacterClock::EightDots => 8,
+            CharacterClock::NineDots => 9,
+        }
+    }
+
+    /// Return the number of pixels each dot is stretched to by the half-rate dot clock.
+    fn get_pixel_repeat(&self) -> u32 {
+        match self.sequencer_clocking_mode.dot_clock() {
+            DotClock::Native => 1,
+            DotClock::HalfClock => 2,
+        }
+    }
+
+    /// Return the frequency in Hz of the master clock selected by the Miscellaneous Output
+    /// Register.
+    fn get_master_clock_hz(&self) -> f64 {
+        match self.misc_output_register.clock_select() {
+            ClockSelect::Clock28 => VGA_CLOCK_2 * 1_000_000.0,
+            _ => VGA_CLOCK_1 * 1_000_000.0,
+        }
+    }
+
+    /// Map a displayed scanline to its character row scan line, accounting for scan doubling
+    /// and the Preset Row Scan register.
+    fn get_row_line(&self, line: u32) -> u32 {
+        let line = match self.crtc_maximum_scanline.scan_doubling() {
+            true => line >> 1,
+            false => line,
+        };
+        line + self.crtc_preset_row_scan.preset_row_scan() as u32
+    }
+
+    /// Called at the end of each frame. Advance the blink counters and present the frame.
+    fn end_frame(&mut self) {
+        self.frame_count += 1;
+        self.cursor_status = self.frame_count & VGA_CURSOR_BLINK_MASK == 0;
+        self.blink_state = self.frame_count & VGA_ATTRIBUTE_BLINK_MASK == 0;
+
+        std::mem::swap(&mut self.back_buf, &mut self.front_buf);
+        self.buf[self.back_buf].fill(0);
+    }
+
+    /// Render the specified line of the active display area into the back buffer.
+    fn draw_scanline(&mut self, line: u32) {
+        if line >= self.display_h || self.sequencer_clocking_mode.screen_off() {
+            return;
+        }
+
+        match self.attribute_mode_control.mode() {
+            AttributeMode::Text => self.draw_text_scanline(line),
+            AttributeMode::Graphics => self.draw_gfx_scanline(line),
+        }
+    }
+
+    /// Render a line of text mode. Characters are fetched as words: the character code from
+    /// plane 0 and the attribute from plane 1, with glyphs read from plane 2. In 9-dot modes the
+    /// 9th column is background, except for the line graphics characters when enabled, which
+    /// replicate their 8th column.
+    fn draw_text_scanline(&mut self, line: u32) {
+        let char_width = self.get_character_width();
+        let pixel_repeat = self.get_pixel_repeat();
+        let char_height = self.crtc_maximum_scanline.maximum_scanline() as u32 + 1;
+
+        let row_line = self.get_row_line(line);
+        let glyph_line = (row_line % char_height) as usize;
+        let mut ma = self.crtc_start_address as u32 + (row_line / char_height) * self.crtc_offset as u32 * 2;
+
+        let (cursor_start, cursor_end) = self.get_cursor_span();
+        let cursor_addr = self.get_cursor_address();
+        let cursor_line =
+            self.get_cursor_status() && glyph_line >= cursor_start as usize && glyph_line <= cursor_end as usize;
+
+        let font_a = VGACard::get_font_offset(self.sequencer_character_map_a);
+        let font_b = VGACard::get_font_offset(self.sequencer_character_map_b);
+        let blink_enabled = matches!(
+            self.attribute_mode_control.enable_blink_or_intensity(),
+            AttributeBlinkOrIntensity::Blink
+        );
+        let line_gfx = self.attribute_mode_control.enable_line_character_codes();
+        let underline_line = matches!(
+            self.attribute_mode_control.display_type(),
+            AttributeDisplayType::Monochrome
+        ) && glyph_line == self.crtc_underline_location.underline_location() as usize;
+        let plane_enable = self.attribute_color_plane_enable.enable_plane();
+
+        let row_start = line as usize * VGA_MAX_RASTER_X as usize;
+        let row_end = row_start + self.display_w as usize;
+        let mut x = row_start;
+
+        for _ in 0..=self.crtc_horizontal_display_end {
+            let addr = ((ma as usize) << 1) & (VGA_GFX_PLANE_SIZE - 1);
+            let ch = self.planes[0].buf[addr];
+            let attr = self.planes[1].buf[addr];
+
+            // Attribute bit 3 selects character map A when the two maps differ.
+            let font = match attr & 0x08 != 0 {
+                true => font_a,
+                false => font_b,
+            };
+            let glyph = self.planes[2].buf[(font + ch as usize * 32 + glyph_line) & (VGA_GFX_PLANE_SIZE - 1)];
+
+            let mut fg = attr & 0x0F;
+            let mut bg = attr >> 4;
+            if blink_enabled {
+                bg &= 0x07;
+                if attr & 0x80 != 0 && !self.blink_state {
+                    fg = bg;
+                }
+            }
+
+            let mut pattern = match char_width {
+                9 if line_gfx && (VGA_LINE_GFX_FIRST..=VGA_LINE_GFX_LAST).contains(&ch) => {
+                    (glyph as u16) << 1 | (glyph as u16 & 0x01)
+                }
+                9 => (glyph as u16) << 1,
+                _ => glyph as u16,
+            };
+
+            if (underline_line && attr & 0x07 == 0x01) || (cursor_line && ma == cursor_addr) {
+                pattern = 0x1FF;
+            }
+
+            let fg_color = self.attribute_palette_registers[(fg & plane_enable) as usize] & 0x3F;
+            let bg_color = self.attribute_palette_registers[(bg & plane_enable) as usize] & 0x3F;
+
+            for dot in (0..char_width).rev() {
+                let color = match pattern & (0x01 << dot) != 0 {
+                    true => fg_color,
+                    false => bg_color,
+                };
+                for _ in 0..pixel_repeat {
+                    if x < row_end {
+                        self.buf[self.back_buf][x] = color;
+                        x += 1;
+                    }
+                }
+            }
+            ma = (ma + 1) & 0xFFFF;
+        }
+    }
+
+    /// Render a line of graphics mode from the pixel values produced by get_pixel_raw().
+    fn draw_gfx_scanline(&mut self, line: u32) {
+        let y = match self.crtc_maximum_scanline.scan_doubling() {
+            true => line >> 1,
+            false => line,
+        };
+        let mut pixel_repeat = self.get_pixel_repeat();
+        if let PixelClock::EveryOtherCycle = self.attribute_mode_control.pixel_clock_select() {
+            pixel_repeat *= 2;
+        }
+
+        let row_start = line as usize * VGA_MAX_RASTER_X as usize;
+        for x in 0..self.display_w {
+            self.buf[self.back_buf][row_start + x as usize] = self.get_pixel_raw(x / pixel_repeat, y) & 0x3F;
+        }
     }
 
     fn tick(&mut self) {
@@ -1162,10 +1438,16 @@ impl VGACard {
         if self.scanline_cycles >= self.u_timings.scanline_end {
             self.scanline_cycles = 0;
 
-            if self.scanline == (self.crtc_vertical_total + 2) as u32 {
+            if self.scanline <= self.crtc_vertical_display_end as u32 {
+                self.draw_scanline(self.scanline);
+            }
+
+            // The Vertical Total register holds the total number of scanlines minus 2.
+            if self.scanline >= (self.crtc_vertical_total + 1) as u32 {
                 //log::trace!("last scanline hit: {}", self.scanline);
                 self.scanline = 0;
                 self.frame_cycles = 0;
+                self.end_frame();
             }
             else {
                 self.scanline += 1;
@@ -1242,7 +1524,11 @@ impl VideoCard for VGACard {
     }
 
     fn get_render_mode(&self) -> RenderMode {
-        RenderMode::Indirect
+        RenderMode::Direct
+    }
+
+    fn get_render_depth(&self) -> RenderBpp {
+        RenderBpp::Six
     }
 
     fn get_display_mode(&self) -> DisplayMode {
@@ -1253,43 +1539,31 @@ impl VideoCard for VGACard {
         // not implemented
     }
 
+    /// Return the size of the active display area. 80 column text modes are 720 pixels wide
+    /// with the 9-dot character clock.
     fn get_display_size(&self) -> (u32, u32) {
-        // VGA supports multiple fonts.
-
-        let font_w = EGA_FONTS[self.current_font].w;
-        let _font_h = EGA_FONTS[self.current_font].h;
-
-        // Clock divisor effectively doubles the CRTC register values
-        let _clock_divisor = match self.sequencer_clocking_mode.dot_clock() {
-            DotClock::Native => 1,
-            DotClock::HalfClock => 2,
-        };
-
-        //let width = (self.crtc_horizontal_display_end as u32 + 1) * clock_divisor * font_w as u32;
-        let width = (self.crtc_horizontal_display_end as u32 + 1) * font_w as u32;
-        let height = self.crtc_vertical_display_end as u32 + 1;
-        (width, height)
+        (self.display_w, self.display_h)
     }
 
-    /// Unimplemented for indirect rendering.
     fn get_display_extents(&self) -> &DisplayExtents {
         &self.extents
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_display_aperture(&self) -> (u32, u32) {
-        (0, 0)
+    fn list_display_apertures(&self) -> Vec<DisplayApertureDesc> {
+        VGA_APERTURE_DESCS.to_vec()
     }
 
-    fn list_display_apertures(&self) -> (Vec<DisplayApertureDesc>, usize) {
-        (Vec::new(), 0)
+    fn get_display_apertures(&self) -> Vec<DisplayAperture> {
+        self.extents.apertures.clone()
     }
 
-    fn set_aperture(&mut self, aperture: u32) {}
-
-    /// Unimplemented for indirect rendering.
+    /// Return the beam position within the active display area, if it is within it.
     fn get_beam_pos(&self) -> Option<(u32, u32)> {
-        None
+        let x = self.scanline_cycles * self.get_pixel_repeat();
+        match x < self.display_w && self.scanline < self.display_h {
+            true => Some((x, self.scanline)),
+            false => None,
+        }
     }
 
     fn debug_tick(&mut self, _ticks: u32) {
@@ -1302,29 +1576,38 @@ impl VideoCard for VGACard {
 
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32 {
-        0
+        self.scanline
     }
 
     /// Return whether to double scanlines produced by this adapter.
-    /// For VGA, this is false.
+    /// For VGA, this is false. 200-line modes are scan doubled by the CRTC itself.
     fn get_scanline_double(&self) -> bool {
         false
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_display_buf(&self) -> &[u8] {
-        &[0]
+    /// Return the u8 slice representing the requested buffer type.
+    fn get_buf(&self, buf_select: BufferSelect) -> &[u8] {
+        match buf_select {
+            BufferSelect::Back => &self.buf[self.back_buf][..],
+            BufferSelect::Front => &self.buf[self.front_buf][..],
+        }
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_back_buf(&self) -> &[u8] {
-        &[0]
+    fn get_display_buf(&self) -> &[u8] {
+        &self.buf[self.front_buf][..]
     }
 
-    /// Return the current refresh rate.
-    /// TODO: Handle VGA 70Hz modes.
+    /// Return the current refresh rate, calculated from the master clock and the CRTC totals.
+    /// This is 70Hz for the 400-line modes and 60Hz for the 480-line modes.
     fn get_refresh_rate(&self) -> u32 {
-        60
+        let frame_dots = self.u_timings.scanline_end as f64
+            * (self.crtc_vertical_total as f64 + 2.0)
+            * self.get_pixel_repeat() as f64;
+
+        if frame_dots == 0.0 {
+            return 60;
+        }
+        (self.get_master_clock_hz() / frame_dots).round() as u32
     }
 
     fn get_clock_divisor(&self) -> u32 {
@@ -1395,10 +1678,31 @@ impl VideoCard for VGACard {
         FontInfo { w, h, font_data: data }
     }
 
+    fn get_glyphs(&self) -> GlyphSet {
+        // Fonts are loaded into plane 2 as 32-byte glyphs. Character map B is used for
+        // attributes with bit 3 clear, which is the common case.
+        let offset = VGACard::get_font_offset(self.sequencer_character_map_b);
+        let height = self.get_character_height() as u32;
+        let mut data = Vec::with_capacity(GlyphSet::GLYPH_COUNT * height as usize);
+        for glyph in 0..GlyphSet::GLYPH_COUNT {
+            for row in 0..height as usize {
+                data.push(self.planes[2].buf[(offset + glyph * 32 + row) % VGA_GFX_PLANE_SIZE]);
+            }
+        }
+        GlyphSet {
+            width: self.get_character_width(),
+            height,
+            data,
+        }
+    }
+
     fn get_character_height(&self) -> u8 {
-        //self.crtc_maximum_scanline.maximum_scanline() + 1
+        self.crtc_maximum_scanline.maximum_scanline() + 1
+    }
 
-        14
+    fn get_text_mode_rows(&self) -> Option<u32> {
+        // The VGA has no character row count; divide the displayed scanlines by the character height.
+        (!self.mode_graphics).then(|| (self.crtc_vertical_display_end as u32 + 1) / self.get_character_height() as u32)
     }
 
     /// Return the current palette number, intensity attribute bit, and alt color
@@ -1524,8 +1828,8 @@ impl VideoCard for VGACard {
         push_reg_str!(
             crtc_vec,
             CRTCRegister::MaximumScanLine,
-            "[2T4]",
-            self.crtc_maximum_scanline.two_to_four()
+            "[SD]",
+            self.crtc_maximum_scanline.scan_doubling()
         );
 
         push_reg_str!(
@@ -1791,7 +2095,7 @@ impl VideoCard for VGACard {
         map
     }
 
-    fn run(&mut self, time: DeviceRunTimeUnit) {
+    fn run(&mut self, time: DeviceRunTimeUnit, _pic: &mut Option<Pic>) {
         let elapsed_us = if let DeviceRunTimeUnit::Microseconds(us) = time {
             us
         }
@@ -1879,7 +2183,7 @@ impl VideoCard for VGACard {
             let read_offset = (y_offset + byte_select) as usize;
             // LO 2 bits selects plane
 
-            let byte = self.planes[plane_select].buf[read_offset];
+            let byte = self.planes[plane_select].buf[read_offset & (VGA_GFX_PLANE_SIZE - 1)];
             return byte;
         }
         else {
@@ -1931,6 +2235,14 @@ impl VideoCard for VGACard {
         &self.planes[plane].buf
     }
 
+    fn get_vram_plane_count(&self) -> usize {
+        self.planes.len()
+    }
+
+    fn get_vram(&self, plane: usize) -> Option<&[u8]> {
+        self.planes.get(plane).map(|p| &p.buf[..])
+    }
+
     fn dump_mem(&self, path: &Path) {
         for i in 0..4 {
             let mut filename = path.to_path_buf();
@@ -1948,7 +2260,7 @@ impl VideoCard for VGACard {
     }
 
     fn get_frame_count(&self) -> u64 {
-        0
+        self.frame_count
     }
 
     fn write_trace_log(&mut self, msg: String) {
@@ -1958,6 +2270,10 @@ impl VideoCard for VGACard {
     fn trace_flush(&mut self) {
         self.trace_logger.flush();
     }
+
+    fn get_text_mode_strings(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl MemoryMappedDevice for VGACard {
@@ -1976,7 +2292,7 @@ impl MemoryMappedDevice for VGACard {
         }
 
         // Validate address is within current memory map and get the offset into VRAM
-        let offset = match self.plane_bounds_check(address) {
+        let mut offset = match self.plane_bounds_check(address) {
             Some(offset) => offset,
             None => {
                 trace!(
@@ -1993,6 +2309,14 @@ impl MemoryMappedDevice for VGACard {
             }
         };
 
+        // In odd/even read mode, address line 0 selects the odd or even plane of the pair
+        // selected by the Read Map Select register.
+        let mut read_plane = (self.graphics_read_map_select & 0x03) as usize;
+        if self.graphics_mode.odd_even() {
+            read_plane = (read_plane & 0x02) | (offset & 0x01);
+            offset &= !0x01;
+        }
+
         // Load all the latches regardless of selected plane or read mode
         self.latch_addr = address as u32;
         for i in 0..4 {
@@ -2004,8 +2328,7 @@ impl MemoryMappedDevice for VGACard {
             ReadMode::ReadSelectedPlane => {
                 // In Read Mode 0, the processor reads data from the memory plane selected
                 // by the read map select register.
-                let plane = (self.graphics_read_map_select & 0x03) as usize;
-                let byte = self.planes[plane].buf[offset];
+                let byte = self.planes[read_plane].buf[offset];
 
                 trace!(
                     self,
@@ -2064,6 +2387,9 @@ impl MemoryMappedDevice for VGACard {
             None => return 0,
         };
 
+        if self.host_odd_even() {
+            return self.planes[offset & 0x01].buf[offset & !0x01];
+        }
         self.planes[0].buf[offset]
     }
 
@@ -2103,6 +2429,17 @@ impl MemoryMappedDevice for VGACard {
             offset >>= 2;
         }
 
+        // In odd/even mode, address line 0 selects the odd or even planes for writing. This is
+        // how text mode stores characters in plane 0 and attributes in plane 1.
+        let mut map_mask = self.sequencer_map_mask;
+        if self.host_odd_even() {
+            map_mask &= match offset & 0x01 {
+                0 => 0b0101,
+                _ => 0b1010,
+            };
+            offset &= !0x01;
+        }
+
         match self.graphics_mode.write_mode() {
             WriteMode::Mode0 => {
                 // Write mode 0 performs a pipeline of operations:
@@ -2195,7 +2532,7 @@ impl MemoryMappedDevice for VGACard {
                     // Finally, write data to the planes enabled in the Memory Plane Write Enable field of
                     // the Sequencer Map Mask register.
                     for i in 0..4 {
-                        if self.sequencer_map_mask & (0x01 << i) != 0 {
+                        if map_mask & (0x01 << i) != 0 {
                             self.planes[i].buf[offset] = self.pipeline_buf[i];
                         }
                     }
@@ -2219,7 +2556,7 @@ impl MemoryMappedDevice for VGACard {
 
                 for i in 0..4 {
                     // Only write to planes enabled in the Sequencer Map Mask.
-                    if (self.sequencer_map_mask & (0x01 << i)) != 0 {
+                    if (map_mask & (0x01 << i)) != 0 {
                         self.planes[i].buf[offset] = self.planes[i].latch;
                     }
                 }
@@ -2227,7 +2564,7 @@ impl MemoryMappedDevice for VGACard {
             WriteMode::Mode2 => {
                 for i in 0..4 {
                     // Only write to planes enabled in the Sequencer Map Mask.
-                    if self.sequencer_map_mask & (0x01 << i) != 0 {
+                    if map_mask & (0x01 << i) != 0 {
                         // Extend the bit for this plane to 8 bits.
                         let bit_span: u8 = match (byte & (0x01 << i)) != 0 {
                             true => 0xFF,
@@ -2331,6 +2668,99 @@ mod tests {
         assert_eq!(data_rot, 0x80);
     }
 
+    /// Program the standard 80x25 color text mode (mode 3) register values.
+    fn set_mode3(vga: &mut VGACard) {
+        const CRTC: [u8; 25] = [
+            0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x9C, 0x8E,
+            0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+        ];
+        const ATTRIBUTE: [u8; 21] = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x0C, 0x00,
+            0x0F, 0x08, 0x00,
+        ];
+        const SEQUENCER: [u8; 5] = [0x03, 0x00, 0x03, 0x00, 0x02];
+        const GRAPHICS: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF];
+
+        let t = DeviceRunTimeUnit::Microseconds(0.0);
+        vga.write_u8(MISC_OUTPUT_REGISTER_WRITE, 0x67, None, t);
+        for (i, byte) in SEQUENCER.iter().enumerate() {
+            vga.write_u8(SEQUENCER_ADDRESS_REGISTER, i as u8, None, t);
+            vga.write_u8(SEQUENCER_DATA_REGISTER, *byte, None, t);
+        }
+        for (i, byte) in CRTC.iter().enumerate() {
+            vga.write_u8(CRTC_REGISTER_ADDRESS, i as u8, None, t);
+            vga.write_u8(CRTC_REGISTER, *byte, None, t);
+        }
+        for (i, byte) in GRAPHICS.iter().enumerate() {
+            vga.write_u8(GRAPHICS_ADDRESS, i as u8, None, t);
+            vga.write_u8(GRAPHICS_DATA, *byte, None, t);
+        }
+        vga.read_u8(INPUT_STATUS_REGISTER_1, t);
+        for (i, byte) in ATTRIBUTE.iter().enumerate() {
+            vga.write_u8(ATTRIBUTE_REGISTER, i as u8, None, t);
+            vga.write_u8(ATTRIBUTE_REGISTER, *byte, None, t);
+        }
+    }
+
+    #[test]
+    fn test_text_mode_9dot() {
+        let mut vga = VGACard::new(TraceLogger::None);
+        set_mode3(&mut vga);
+
+        assert_eq!(vga.get_display_mode(), DisplayMode::Mode3TextCo80);
+        assert_eq!(vga.get_display_size(), (720, 400));
+        assert_eq!(vga.get_character_height(), 16);
+        assert_eq!(vga.get_refresh_rate(), 70);
+
+        // Glyphs with the outer columns set, for a normal and a line graphics character.
+        vga.planes[2].buf[0x41 * 32] = 0x81;
+        vga.planes[2].buf[0xC4 * 32] = 0x81;
+
+        // Odd/even addressing puts characters in plane 0 and attributes in plane 1.
+        for (i, byte) in [0x41, 0x1F, 0xC4, 0x1F].iter().enumerate() {
+            MemoryMappedDevice::mmio_write_u8(&mut vga, CGA_ADDRESS + i, *byte, 0);
+        }
+        assert_eq!(vga.planes[0].buf[0..4], [0x41, 0x00, 0xC4, 0x00]);
+        assert_eq!(vga.planes[1].buf[0..4], [0x1F, 0x00, 0x1F, 0x00]);
+        assert_eq!(MemoryMappedDevice::mmio_peek_u8(&vga, CGA_ADDRESS + 3), 0x1F);
+
+        vga.draw_scanline(0);
+        let row = &vga.get_buf(BufferSelect::Back)[0..18];
+        // The 9th column is background for normal characters and replicates the 8th column for
+        // line graphics characters.
+        assert_eq!(row[0..9], [0x3F, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x3F, 0x01]);
+        assert_eq!(row[9..18], [0x3F, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x3F, 0x3F]);
+
+        // Every character row of the 400-line mode is 16 scanlines tall.
+        vga.draw_scanline(16);
+        assert_eq!(vga.get_buf(BufferSelect::Back)[16 * VGA_MAX_RASTER_X as usize], 0x00);
+    }
+
+    #[test]
+    fn test_blink_rates() {
+        let mut vga = VGACard::new(TraceLogger::None);
+        set_mode3(&mut vga);
+
+        let mut cursor_toggles = 0;
+        let mut blink_toggles = 0;
+        let mut cursor = vga.cursor_status;
+        let mut blink = vga.blink_state;
+        for _ in 0..64 {
+            vga.end_frame();
+            if vga.cursor_status != cursor {
+                cursor_toggles += 1;
+                cursor = vga.cursor_status;
+            }
+            if vga.blink_state != blink {
+                blink_toggles += 1;
+                blink = vga.blink_state;
+            }
+        }
+        // The cursor toggles every 8 frames and character blink every 16 frames.
+        assert_eq!(cursor_toggles, 64 / 8);
+        assert_eq!(blink_toggles, 64 / 16);
+    }
+
     #[test]
     fn test_color_compare() {
         /*
@@ -2369,4 +2799,41 @@ mod tests {
         assert_eq!(result, 0b00100111);
         */
     }
+
+    #[test]
+    fn test_vga_state_roundtrip() {
+        use crate::device_traits::devicestate::DeviceState;
+
+        let mut vga = VGACard::new(TraceLogger::None);
+        set_mode3(&mut vga);
+        let t = DeviceRunTimeUnit::Microseconds(0.0);
+        vga.write_u8(PEL_ADDRESS_WRITE_MODE, 0, None, t);
+        for i in 0..(16 * 3) {
+            vga.write_u8(PEL_DATA, (i * 5) as u8 & 0x3F, None, t);
+        }
+        for (p, plane) in vga.planes.iter_mut().enumerate() {
+            for (i, byte) in plane.buf.iter_mut().enumerate() {
+                *byte = (i * (p + 3)) as u8;
+            }
+        }
+
+        // Save in the middle of a frame.
+        vga.run(DeviceRunTimeUnit::Microseconds(7_000.0), &mut None);
+        vga.write_u8(CRTC_REGISTER_ADDRESS, 0x0E, None, t);
+        vga.write_u8(CRTC_REGISTER, 0x01, None, t);
+
+        let mut restored = VGACard::new(TraceLogger::None);
+        restored.load_state(vga.save_state()).unwrap();
+        assert_eq!(restored.dump_state(), vga.dump_state());
+
+        // The first frame completed after the restore was begun before it, so compare the display
+        // once a second one has completed.
+        let frame_count = vga.get_frame_count();
+        while vga.get_frame_count() < frame_count + 2 {
+            vga.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            restored.run(DeviceRunTimeUnit::Microseconds(5_000.0), &mut None);
+            assert_eq!(restored.dump_state(), vga.dump_state());
+        }
+        assert!(restored.get_display_buf() == vga.get_display_buf());
+    }
 }
@@ -85,10 +85,11 @@ pub enum ShiftFour {
     EightDots,
 }
 
+// As on the EGA, 0 == 9 Dots.
 #[derive(Copy, Clone, Debug, BitfieldSpecifier)]
 pub enum CharacterClock {
-    EightDots,
     NineDots,
+    EightDots,
 }
 
 #[derive(Copy, Clone, Debug, BitfieldSpecifier)]
@@ -174,13 +175,18 @@ impl VGACard {
     ///
     /// The Sequencer Data registers are only readable on the VGA.
     pub fn read_sequencer_data(&mut self) -> u8 {
-        let byte = match self.sequencer_register_selected {
-            SequencerRegister::Reset => self.sequencer_reset,
-            SequencerRegister::ClockingMode => self.sequencer_clocking_mode.into_bytes()[0],
-            SequencerRegister::MapMask => self.sequencer_map_mask,
-            SequencerRegister::CharacterMapSelect => self.sequencer_character_map_select.into_bytes()[0],
-            SequencerRegister::MemoryMode => self.sequencer_memory_mode.into_bytes()[0],
-        };
-        byte
+        self.sequencer_register(self.sequencer_register_selected as u8)
+    }
+
+    /// Return the value of the Sequencer register at the specified index.
+    pub fn sequencer_register(&self, idx: u8) -> u8 {
+        match idx {
+            0x00 => self.sequencer_reset,
+            0x01 => self.sequencer_clocking_mode.into_bytes()[0],
+            0x02 => self.sequencer_map_mask,
+            0x03 => self.sequencer_character_map_select.into_bytes()[0],
+            0x04 => self.sequencer_memory_mode.into_bytes()[0],
+            _ => 0,
+        }
     }
 }
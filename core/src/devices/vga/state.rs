@@ -0,0 +1,258 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::vga::state.rs
+
+    Implementation of the DeviceState interface for the IBM VGA.
+
+*/
+
+use super::*;
+use crate::device_traits::devicestate::DeviceState;
+use anyhow::{bail, Error};
+use serde::{Deserialize, Serialize};
+
+/// The serializable state of the VGA: its registers, the color registers of the DAC, the raster
+/// counters, and the contents and latches of the four display planes. The render buffers are not
+/// included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VgaState {
+    // Registers
+    pub misc_output_register: u8,
+    pub crtc_register_select_byte: u8,
+    pub crtc_registers: Vec<u8>,
+    pub sequencer_address_byte: u8,
+    pub sequencer_register_selected: u8,
+    pub sequencer_registers: Vec<u8>,
+    pub graphics_register_address: u8,
+    pub graphics_register_selected: u8,
+    pub graphics_registers: Vec<u8>,
+    pub attribute_data_flipflop: bool,
+    pub attribute_address: u8,
+    pub attribute_selected: u8,
+    pub attribute_registers: Vec<u8>,
+
+    // Values derived from more than one CRTC register, which depend on the order the registers
+    // were written in.
+    pub crtc_vertical_total: u16,
+    pub crtc_vertical_display_end: u16,
+    pub crtc_vertical_retrace_start: u16,
+    pub crtc_start_vertical_blank: u16,
+    pub crtc_line_compare: u16,
+    pub crtc_end_horizontal_blank_norm: u8,
+    pub crtc_end_horizontal_retrace_norm: u8,
+    pub crtc_vertical_retrace_end_norm: u16,
+    pub crtc_end_vertical_blank_norm: u16,
+
+    // DAC
+    pub color_pel_write_address: u8,
+    pub color_pel_write_address_color: u8,
+    pub color_pel_read_address: u8,
+    pub color_pel_read_address_color: u8,
+    pub color_dac_state: u8,
+    pub color_pel_mask: u8,
+    pub color_registers: Vec<[u8; 3]>,
+
+    // Raster
+    pub scanline: u32,
+    pub scanline_cycles: u32,
+    pub frame_cycles: u32,
+    pub frame_count: u64,
+    pub vga_cycle_accumulator: f64,
+    pub in_hblank: bool,
+    pub in_vblank: bool,
+    pub cursor_status: bool,
+    pub blink_state: bool,
+
+    pub latch_addr: u32,
+    pub latches: [u8; 4],
+    pub planes: Vec<Vec<u8>>,
+}
+
+impl DeviceState for VGACard {
+    type State = VgaState;
+
+    fn save_state(&self) -> VgaState {
+        VgaState {
+            misc_output_register: self.misc_output_register.into_bytes()[0],
+            crtc_register_select_byte: self.crtc_register_select_byte,
+            crtc_registers: (0..=0x18).map(|idx| self.crtc_register(idx)).collect(),
+            sequencer_address_byte: self.sequencer_address_byte,
+            sequencer_register_selected: self.sequencer_register_selected as u8,
+            sequencer_registers: (0..=0x04).map(|idx| self.sequencer_register(idx)).collect(),
+            graphics_register_address: self.graphics_register_address,
+            graphics_register_selected: self.graphics_register_selected as u8,
+            graphics_registers: (0..=0x08).map(|idx| self.graphics_register(idx)).collect(),
+            attribute_data_flipflop: matches!(self.attribute_flipflop, AttributeRegisterFlipFlop::Data),
+            attribute_address: self.attribute_address.into_bytes()[0],
+            attribute_selected: self.attribute_selected as u8,
+            attribute_registers: (0..=0x14).map(|idx| self.attribute_register(idx)).collect(),
+
+            crtc_vertical_total: self.crtc_vertical_total,
+            crtc_vertical_display_end: self.crtc_vertical_display_end,
+            crtc_vertical_retrace_start: self.crtc_vertical_retrace_start,
+            crtc_start_vertical_blank: self.crtc_start_vertical_blank,
+            crtc_line_compare: self.crtc_line_compare,
+            crtc_end_horizontal_blank_norm: self.crtc_end_horizontal_blank_norm,
+            crtc_end_horizontal_retrace_norm: self.crtc_end_horizontal_retrace_norm,
+            crtc_vertical_retrace_end_norm: self.crtc_vertical_retrace_end_norm,
+            crtc_end_vertical_blank_norm: self.crtc_end_vertical_blank_norm,
+
+            color_pel_write_address: self.color_pel_write_address,
+            color_pel_write_address_color: self.color_pel_write_address_color,
+            color_pel_read_address: self.color_pel_read_address,
+            color_pel_read_address_color: self.color_pel_read_address_color,
+            color_dac_state: self.color_dac_state,
+            color_pel_mask: self.color_pel_mask,
+            color_registers: self.color_registers.to_vec(),
+
+            scanline: self.scanline,
+            scanline_cycles: self.scanline_cycles,
+            frame_cycles: self.frame_cycles,
+            frame_count: self.frame_count,
+            vga_cycle_accumulator: self.vga_cycle_accumulator,
+            in_hblank: self.in_hblank,
+            in_vblank: self.in_vblank,
+            cursor_status: self.cursor_status,
+            blink_state: self.blink_state,
+
+            latch_addr: self.latch_addr,
+            latches: [
+                self.planes[0].latch,
+                self.planes[1].latch,
+                self.planes[2].latch,
+                self.planes[3].latch,
+            ],
+            planes: self.planes.iter().map(|plane| plane.buf.to_vec()).collect(),
+        }
+    }
+
+    fn load_state(&mut self, state: VgaState) -> Result<(), Error> {
+        if state.crtc_registers.len() != 0x19
+            || state.sequencer_registers.len() != 0x05
+            || state.graphics_registers.len() != 0x09
+            || state.attribute_registers.len() != 0x15
+            || state.color_registers.len() != 256
+        {
+            bail!("VGA state has an unexpected number of registers");
+        }
+        if state.planes.len() != 4 || state.planes.iter().any(|plane| plane.len() != VGA_GFX_PLANE_SIZE) {
+            bail!("VGA state does not have four planes of {} bytes", VGA_GFX_PLANE_SIZE);
+        }
+
+        // Registers are replayed through their write handlers so that the values derived from
+        // them are recalculated.
+        self.write_external_misc_output_register(state.misc_output_register);
+        for (idx, byte) in state.sequencer_registers.iter().enumerate() {
+            self.write_sequencer_address(idx as u8);
+            self.write_sequencer_data(*byte);
+        }
+        // An invalid address keeps the previous selection.
+        self.write_sequencer_address(state.sequencer_register_selected);
+        self.write_sequencer_address(state.sequencer_address_byte);
+
+        // R0-R7 are write protected by R11, and R10-R11 are only writable with the compatible
+        // read bit of R3 set. Open both for the replay and set the saved values afterwards.
+        self.protect_crtc_registers = false;
+        for (idx, byte) in state.crtc_registers.iter().enumerate() {
+            let byte = match idx {
+                0x03 => *byte | 0x80,
+                0x11 => *byte & 0x7F,
+                _ => *byte,
+            };
+            self.write_crtc_register_address(idx as u8);
+            self.write_crtc_register_data(byte);
+        }
+        self.crtc_end_horizontal_blank = CEndHorizontalBlank::from_bytes([state.crtc_registers[0x03]]);
+        self.crtc_vertical_retrace_end = CVerticalRetraceEnd::from_bytes([state.crtc_registers[0x11]]);
+        self.protect_crtc_registers = self.crtc_vertical_retrace_end.protect_regs();
+        self.crtc_vertical_total = state.crtc_vertical_total;
+        self.crtc_vertical_display_end = state.crtc_vertical_display_end;
+        self.crtc_vertical_retrace_start = state.crtc_vertical_retrace_start;
+        self.crtc_start_vertical_blank = state.crtc_start_vertical_blank;
+        self.crtc_line_compare = state.crtc_line_compare;
+        self.crtc_end_horizontal_blank_norm = state.crtc_end_horizontal_blank_norm;
+        self.crtc_end_horizontal_retrace_norm = state.crtc_end_horizontal_retrace_norm;
+        self.crtc_vertical_retrace_end_norm = state.crtc_vertical_retrace_end_norm;
+        self.crtc_end_vertical_blank_norm = state.crtc_end_vertical_blank_norm;
+        self.write_crtc_register_address(state.crtc_register_select_byte);
+
+        for (idx, byte) in state.graphics_registers.iter().enumerate() {
+            self.write_graphics_address(idx as u8);
+            self.write_graphics_data(*byte);
+        }
+        self.write_graphics_address(state.graphics_register_selected);
+        self.write_graphics_address(state.graphics_register_address);
+
+        // Replaying the attribute registers leaves the flipflop in address mode. Selecting the
+        // saved register toggles it to data mode.
+        self.attribute_flipflop = AttributeRegisterFlipFlop::Address;
+        for (idx, byte) in state.attribute_registers.iter().enumerate() {
+            self.write_attribute_register(idx as u8);
+            self.write_attribute_register(*byte);
+        }
+        self.write_attribute_register(state.attribute_selected);
+        self.attribute_flipflop = AttributeRegisterFlipFlop::Address;
+        self.write_attribute_register(state.attribute_address);
+        if !state.attribute_data_flipflop {
+            self.attribute_flipflop = AttributeRegisterFlipFlop::Address;
+        }
+
+        self.color_pel_write_address = 0;
+        self.color_pel_write_address_color = 0;
+        for color in state.color_registers.iter() {
+            for byte in color.iter() {
+                self.write_pel_data(*byte);
+            }
+        }
+        self.color_pel_write_address = state.color_pel_write_address;
+        self.color_pel_write_address_color = state.color_pel_write_address_color;
+        self.color_pel_read_address = state.color_pel_read_address;
+        self.color_pel_read_address_color = state.color_pel_read_address_color;
+        self.color_dac_state = state.color_dac_state;
+        self.color_pel_mask = state.color_pel_mask;
+
+        self.recalculate_mode();
+        self.recalculate_timings();
+
+        self.scanline = state.scanline;
+        self.scanline_cycles = state.scanline_cycles;
+        self.frame_cycles = state.frame_cycles;
+        self.frame_count = state.frame_count;
+        self.vga_cycle_accumulator = state.vga_cycle_accumulator;
+        self.in_hblank = state.in_hblank;
+        self.in_vblank = state.in_vblank;
+        self.cursor_status = state.cursor_status;
+        self.blink_state = state.blink_state;
+
+        self.latch_addr = state.latch_addr;
+        for (i, plane) in self.planes.iter_mut().enumerate() {
+            plane.latch = state.latches[i];
+            plane.buf.copy_from_slice(&state.planes[i]);
+        }
+        Ok(())
+    }
+}
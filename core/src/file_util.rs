@@ -42,3 +42,17 @@ pub fn find_unique_filename(path: &Path, base: &str, ext: &str) -> PathBuf {
 
     test_path
 }
+
+/// Write a memory dump to the specified file, logging the result.
+pub fn write_dump(path: &Path, data: &[u8]) {
+    log::debug!("Dumping {} bytes to {}", data.len(), path.display());
+
+    match std::fs::write(path, data) {
+        Ok(_) => {
+            log::debug!("Wrote memory dump: {}", path.display())
+        }
+        Err(e) => {
+            log::error!("Failed to write memory dump '{}': {}", path.display(), e)
+        }
+    }
+}
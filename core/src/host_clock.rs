@@ -0,0 +1,126 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    host_clock.rs
+
+    Synchronization of guest time sources with the host clock.
+
+    When enabled, the BIOS time-of-day tick count is loaded from the host's
+    local time as the BIOS bootstraps the operating system (INT 19h), and any
+    installed real time clock is set when the machine is created. Both can
+    optionally be corrected at a fixed interval of emulated time.
+
+    Reading the host clock makes a run non-reproducible, so this is off by
+    default and should stay off for movies, lockstep and determinism audits.
+*/
+
+use chrono::{Datelike, Local, Timelike};
+use serde_derive::Deserialize;
+
+/// Address of the BIOS time-of-day tick count at 0040:006C.
+pub const BIOS_TIMER_COUNT_ADDRESS: usize = 0x46C;
+/// Address of the BIOS midnight rollover flag at 0040:0070.
+pub const BIOS_TIMER_OVERFLOW_ADDRESS: usize = 0x470;
+/// Number of timer ticks the BIOS counts in a day before rolling over.
+pub const BIOS_TICKS_PER_DAY: u32 = 0x1800B0;
+/// The BIOS bootstrap interrupt. The BIOS data area is initialized by the time this is called.
+pub const BIOS_BOOTSTRAP_INTERRUPT: u8 = 0x19;
+
+const MICROSECONDS_PER_DAY: u64 = 86_400_000_000;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HostClockConfig {
+    pub enabled: bool,
+    // Interval in seconds of emulated time at which to correct guest time again.
+    // If not set, guest time is only set at startup and on reboot.
+    pub correction_interval: Option<f64>,
+}
+
+/// A source of the host's local time. Machine uses ClockTime::host_now() unless a source is
+/// installed, such as when the host has no system clock of its own.
+pub type HostTimeSource = Box<dyn FnMut() -> ClockTime>;
+
+/// A calendar date and time of day, as loaded into a guest time source.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ClockTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub weekday: u8, // 1-7, Sunday is 1.
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub micros: u32,
+}
+
+impl ClockTime {
+    /// Read the current local time of the host.
+    pub fn host_now() -> Self {
+        let now = Local::now();
+        Self {
+            year: now.year() as u16,
+            month: now.month() as u8,
+            day: now.day() as u8,
+            weekday: now.weekday().number_from_sunday() as u8,
+            hour: now.hour() as u8,
+            minute: now.minute() as u8,
+            second: now.second() as u8,
+            // Nanoseconds may exceed one second during a leap second.
+            micros: (now.nanosecond() / 1000).min(999_999),
+        }
+    }
+
+    /// Return the BIOS time-of-day tick count for this time.
+    pub fn bios_ticks(&self) -> u32 {
+        let us =
+            (self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64) * 1_000_000 + self.micros as u64;
+        ((us * BIOS_TICKS_PER_DAY as u64) / MICROSECONDS_PER_DAY) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bios_ticks() {
+        let mut time = ClockTime::default();
+        assert_eq!(time.bios_ticks(), 0);
+
+        // The BIOS counts 18.2 ticks per second.
+        time.hour = 12;
+        assert_eq!(time.bios_ticks(), BIOS_TICKS_PER_DAY / 2);
+        time.second = 1;
+        assert_eq!(time.bios_ticks(), BIOS_TICKS_PER_DAY / 2 + 18);
+
+        time.hour = 23;
+        time.minute = 59;
+        time.second = 59;
+        time.micros = 999_999;
+        assert_eq!(time.bios_ticks(), BIOS_TICKS_PER_DAY - 1);
+    }
+}
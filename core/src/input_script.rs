@@ -0,0 +1,236 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    input_script.rs
+
+    Timed keyboard input scripts, for automating boot-and-type sequences
+    such as answering the DOS date prompt or launching a program.
+
+    A script is a list of events, each delayed by a number of milliseconds
+    of emulated time after the previous one. Since delays are measured in
+    emulated time, a script produces the same input at the same point in
+    execution on every run regardless of host speed.
+
+    Scripts are stored as TOML:
+
+        [[event]]
+        delay_ms = 4000
+        text = "\r\r"
+
+        [[event]]
+        delay_ms = 500
+        tap = "F1"
+
+    Each event specifies exactly one of `press`, `release`, `tap` or `text`.
+*/
+
+use std::{path::Path, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use serde_derive::Deserialize;
+
+use crate::keys::MartyKey;
+
+/// An action performed by a script event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptAction {
+    /// Press and hold a key.
+    Press(MartyKey),
+    /// Release a held key.
+    Release(MartyKey),
+    /// Press and release a key.
+    Tap(MartyKey),
+    /// Type a string, as with Machine::type_text().
+    Type(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptEvent {
+    /// Delay after the previous event, in milliseconds of emulated time.
+    pub delay_ms: f64,
+    pub action:   ScriptAction,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ScriptEventEntry {
+    #[serde(default)]
+    delay_ms: f64,
+    press: Option<String>,
+    release: Option<String>,
+    tap: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ScriptFile {
+    #[serde(default)]
+    event: Vec<ScriptEventEntry>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    events: Vec<ScriptEvent>,
+}
+
+impl InputScript {
+    pub fn new(events: Vec<ScriptEvent>) -> Self {
+        Self { events }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let script_str = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&script_str)
+    }
+
+    pub fn from_toml_str(script_str: &str) -> Result<Self, Error> {
+        let file: ScriptFile = toml::from_str(script_str)?;
+
+        let mut events = Vec::with_capacity(file.event.len());
+        for (i, entry) in file.event.into_iter().enumerate() {
+            if entry.delay_ms.is_nan() || entry.delay_ms < 0.0 {
+                return Err(anyhow!("Event {}: invalid delay {}", i, entry.delay_ms));
+            }
+
+            let key = |key_str: String| {
+                MartyKey::from_str(&key_str).map_err(|_| anyhow!("Event {}: invalid key '{}'", i, key_str))
+            };
+
+            let mut actions = Vec::new();
+            if let Some(key_str) = entry.press {
+                actions.push(ScriptAction::Press(key(key_str)?));
+            }
+            if let Some(key_str) = entry.release {
+                actions.push(ScriptAction::Release(key(key_str)?));
+            }
+            if let Some(key_str) = entry.tap {
+                actions.push(ScriptAction::Tap(key(key_str)?));
+            }
+            if let Some(text) = entry.text {
+                actions.push(ScriptAction::Type(text));
+            }
+
+            if actions.len() != 1 {
+                return Err(anyhow!(
+                    "Event {}: expected exactly one of press, release, tap or text",
+                    i
+                ));
+            }
+
+            events.push(ScriptEvent {
+                delay_ms: entry.delay_ms,
+                action:   actions.remove(0),
+            });
+        }
+
+        Ok(Self { events })
+    }
+
+    pub fn events(&self) -> &[ScriptEvent] {
+        &self.events
+    }
+}
+
+/// Plays an input script against a running machine. The machine advances the player by the
+/// emulated time elapsed each time devices are run, and applies the actions that become due.
+pub struct InputScriptPlayer {
+    script: InputScript,
+    next: usize,
+    elapsed_us: f64,
+}
+
+impl InputScriptPlayer {
+    pub fn new(script: InputScript) -> Self {
+        Self {
+            script,
+            next: 0,
+            elapsed_us: 0.0,
+        }
+    }
+
+    /// Advance the script by `us` microseconds of emulated time, returning any actions that
+    /// became due, in order.
+    pub fn advance(&mut self, us: f64) -> Vec<ScriptAction> {
+        let mut actions = Vec::new();
+        self.elapsed_us += us;
+
+        while let Some(event) = self.script.events.get(self.next) {
+            let delay_us = event.delay_ms * 1000.0;
+            if self.elapsed_us < delay_us {
+                break;
+            }
+            self.elapsed_us -= delay_us;
+            actions.push(event.action.clone());
+            self.next += 1;
+        }
+        actions
+    }
+
+    /// Returns true once every event in the script has been played.
+    pub fn finished(&self) -> bool {
+        self.next >= self.script.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_script() {
+        let script = InputScript::from_toml_str(
+            r#"
+            [[event]]
+            delay_ms = 10
+            text = "dir\r"
+
+            [[event]]
+            delay_ms = 5
+            tap = "F1"
+
+            [[event]]
+            press = "ShiftLeft"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(script.events().len(), 3);
+
+        let mut player = InputScriptPlayer::new(script);
+        assert!(player.advance(9_999.0).is_empty());
+        assert_eq!(player.advance(1.0), vec![ScriptAction::Type("dir\r".to_string())]);
+        assert_eq!(
+            player.advance(5_000.0),
+            vec![
+                ScriptAction::Tap(MartyKey::F1),
+                ScriptAction::Press(MartyKey::ShiftLeft)
+            ]
+        );
+        assert!(player.finished());
+
+        assert!(InputScript::from_toml_str("[[event]]\ntap = \"NotAKey\"").is_err());
+        assert!(InputScript::from_toml_str("[[event]]\ntap = \"F1\"\ntext = \"a\"").is_err());
+    }
+}
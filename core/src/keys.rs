@@ -39,9 +39,10 @@
     }
 */
 
+use serde::{Deserialize, Serialize};
 use strum_macros::{EnumIter, EnumString};
 
-#[derive(Copy, Clone, Debug, EnumIter, EnumString, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, EnumIter, EnumString, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MartyKey {
     None,
     Backquote,
@@ -239,3 +240,92 @@ pub enum MartyKey {
     F34,
     F35,
 }
+
+impl MartyKey {
+    /// Convert an ASCII character into the key that produces it on a US keyboard layout, and
+    /// whether shift must be held. Returns None for characters that can't be typed directly.
+    pub fn from_char(c: char) -> Option<(MartyKey, bool)> {
+        let key = match c {
+            'a'..='z' | 'A'..='Z' => {
+                let key = match c.to_ascii_lowercase() {
+                    'a' => MartyKey::KeyA,
+                    'b' => MartyKey::KeyB,
+                    'c' => MartyKey::KeyC,
+                    'd' => MartyKey::KeyD,
+                    'e' => MartyKey::KeyE,
+                    'f' => MartyKey::KeyF,
+                    'g' => MartyKey::KeyG,
+                    'h' => MartyKey::KeyH,
+                    'i' => MartyKey::KeyI,
+                    'j' => MartyKey::KeyJ,
+                    'k' => MartyKey::KeyK,
+                    'l' => MartyKey::KeyL,
+                    'm' => MartyKey::KeyM,
+                    'n' => MartyKey::KeyN,
+                    'o' => MartyKey::KeyO,
+                    'p' => MartyKey::KeyP,
+                    'q' => MartyKey::KeyQ,
+                    'r' => MartyKey::KeyR,
+                    's' => MartyKey::KeyS,
+                    't' => MartyKey::KeyT,
+                    'u' => MartyKey::KeyU,
+                    'v' => MartyKey::KeyV,
+                    'w' => MartyKey::KeyW,
+                    'x' => MartyKey::KeyX,
+                    'y' => MartyKey::KeyY,
+                    _ => MartyKey::KeyZ,
+                };
+                return Some((key, c.is_ascii_uppercase()));
+            }
+            '1' => (MartyKey::Digit1, false),
+            '2' => (MartyKey::Digit2, false),
+            '3' => (MartyKey::Digit3, false),
+            '4' => (MartyKey::Digit4, false),
+            '5' => (MartyKey::Digit5, false),
+            '6' => (MartyKey::Digit6, false),
+            '7' => (MartyKey::Digit7, false),
+            '8' => (MartyKey::Digit8, false),
+            '9' => (MartyKey::Digit9, false),
+            '0' => (MartyKey::Digit0, false),
+            '!' => (MartyKey::Digit1, true),
+            '@' => (MartyKey::Digit2, true),
+            '#' => (MartyKey::Digit3, true),
+            '$' => (MartyKey::Digit4, true),
+            '%' => (MartyKey::Digit5, true),
+            '^' => (MartyKey::Digit6, true),
+            '&' => (MartyKey::Digit7, true),
+            '*' => (MartyKey::Digit8, true),
+            '(' => (MartyKey::Digit9, true),
+            ')' => (MartyKey::Digit0, true),
+            '-' => (MartyKey::Minus, false),
+            '_' => (MartyKey::Minus, true),
+            '=' => (MartyKey::Equal, false),
+            '+' => (MartyKey::Equal, true),
+            '[' => (MartyKey::BracketLeft, false),
+            '{' => (MartyKey::BracketLeft, true),
+            ']' => (MartyKey::BracketRight, false),
+            '}' => (MartyKey::BracketRight, true),
+            '\\' => (MartyKey::Backslash, false),
+            '|' => (MartyKey::Backslash, true),
+            ';' => (MartyKey::Semicolon, false),
+            ':' => (MartyKey::Semicolon, true),
+            '\'' => (MartyKey::Quote, false),
+            '"' => (MartyKey::Quote, true),
+            '`' => (MartyKey::Backquote, false),
+            '~' => (MartyKey::Backquote, true),
+            ',' => (MartyKey::Comma, false),
+            '<' => (MartyKey::Comma, true),
+            '.' => (MartyKey::Period, false),
+            '>' => (MartyKey::Period, true),
+            '/' => (MartyKey::Slash, false),
+            '?' => (MartyKey::Slash, true),
+            ' ' => (MartyKey::Space, false),
+            '\t' => (MartyKey::Tab, false),
+            '\n' | '\r' => (MartyKey::Enter, false),
+            '\x08' => (MartyKey::Backspace, false),
+            '\x1b' => (MartyKey::Escape, false),
+            _ => return None,
+        };
+        Some(key)
+    }
+}
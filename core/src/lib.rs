@@ -37,26 +37,44 @@ pub mod bus;
 pub mod bytebuf;
 pub mod bytequeue;
 pub mod coreconfig;
+pub mod cpu_286;
 pub mod cpu_808x;
 pub mod cpu_common;
+pub mod determinism;
+pub mod device_scheduler;
 pub mod device_traits;
 pub mod device_types;
+pub mod device_vectors;
 pub mod devices;
 pub mod file_util;
+pub mod host_clock;
+pub mod input_script;
 pub mod interrupt;
 pub mod keys;
+pub mod lockstep;
 pub mod machine;
 pub mod machine_config;
+pub mod machine_snapshot;
 pub mod memerror;
+pub mod memory_snapshot;
+pub mod movie;
+pub mod ntsc;
+pub mod nvram;
+pub mod profiler;
+pub mod rewind;
 pub mod rom_manager;
 pub mod sound;
 pub mod syntax_token;
+pub mod timeline;
 pub mod tracelogger;
 pub mod updatable;
 pub mod util;
 pub mod vhd;
+pub mod video_trace;
 
 pub mod cpu_validator; // CpuValidator trait
+#[cfg(feature = "cpu_validator")]
+pub mod remote_validator;
 
 #[cfg(feature = "arduino_validator")]
 #[macro_use]
@@ -0,0 +1,341 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    lockstep.rs
+
+    Implements a harness that steps the emulated machine in lockstep with an
+    external reference - another emulator, or a hardware validator - and
+    compares CPU registers and memory after each instruction.
+
+    The reference is reached over TCP using a simple line-based text protocol.
+    Register sets are sent as 14 hex words in the order
+    AX BX CX DX SP BP SI DI CS DS ES SS IP FLAGS.
+
+      -> HELLO MARTYPC 1
+      <- HELLO <name> 1
+      -> SETREGS <registers>         Sent once at start to align the reference.
+      <- OK
+      -> STEP                        Execute one instruction.
+      <- W <address> <byte>          Zero or more memory writes made by the
+                                     instruction, in hex.
+      <- REGS <registers>            Registers after the instruction.
+      -> PEEK <address> <length>     Read memory, in hex.
+      <- DATA <hex bytes>
+      -> BYE
+
+    Any line from the reference beginning with ERR aborts the run.
+*/
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::{
+    cpu_common::CpuOption,
+    cpu_validator::VRegisters,
+    machine::{ExecutionControl, ExecutionState, Machine},
+};
+
+pub const LOCKSTEP_PROTOCOL_VERSION: u32 = 1;
+/// Default mask for FLAGS comparison: OF DF IF TF SF ZF AF PF CF.
+pub const LOCKSTEP_DEFAULT_FLAG_MASK: u16 = 0x0FD5;
+
+const PEEK_CHUNK_SIZE: usize = 0x1000;
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct LockstepParams {
+    /// Address of the reference, as host:port.
+    pub address: String,
+    /// Number of instructions to execute. 0 runs until a mismatch or error occurs.
+    pub instructions: u64,
+    /// Compare all of memory below 'memory_check_size' every this many instructions. 0 disables.
+    pub memory_check_interval: u64,
+    pub memory_check_size: usize,
+    /// Bits of FLAGS to compare.
+    pub flag_mask: u16,
+}
+
+/// A mismatch between MartyPC and the reference, with enough context to investigate it.
+#[derive(Clone, Debug)]
+pub struct LockstepMismatch {
+    pub instruction: u64,
+    pub cycle: u64,
+    pub regs_before: VRegisters,
+    pub regs: VRegisters,
+    pub reference_regs: VRegisters,
+    pub register_diffs: Vec<(&'static str, u16, u16)>,
+    /// Address, our value, and the reference's value for each differing byte of memory.
+    pub memory_diffs: Vec<(u32, u8, u8)>,
+    pub history: String,
+}
+
+impl fmt::Display for LockstepMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Mismatch after instruction {} (cycle {}) at [{:04X}:{:04X}]",
+            self.instruction, self.cycle, self.regs_before.cs, self.regs_before.ip
+        )?;
+        for (name, ours, theirs) in &self.register_diffs {
+            writeln!(f, "  {:>5}: ours {:04X} reference {:04X}", name, ours, theirs)?;
+        }
+        for (addr, ours, theirs) in self.memory_diffs.iter().take(16) {
+            writeln!(f, "  [{:05X}]: ours {:02X} reference {:02X}", addr, ours, theirs)?;
+        }
+        if self.memory_diffs.len() > 16 {
+            writeln!(f, "  ...and {} more memory differences", self.memory_diffs.len() - 16)?;
+        }
+        writeln!(f, "Registers before:\n{}", self.regs_before)?;
+        writeln!(f, "Registers after:\n{}", self.regs)?;
+        writeln!(f, "Reference registers after:\n{}", self.reference_regs)?;
+        write!(f, "Instruction history:\n{}", self.history)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LockstepResult {
+    /// The requested number of instructions ran without a mismatch.
+    Completed(u64),
+    /// The emulated machine stopped before the requested number of instructions.
+    Stopped(u64),
+    Mismatch(Box<LockstepMismatch>),
+}
+
+pub struct LockstepHarness {
+    params: LockstepParams,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    reference_name: String,
+}
+
+impl LockstepHarness {
+    /// Connect to the reference and perform the protocol handshake.
+    pub fn connect(params: LockstepParams) -> Result<Self, Error> {
+        let stream = TcpStream::connect(&params.address)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+        let writer = stream.try_clone()?;
+
+        let mut harness = Self {
+            params,
+            reader: BufReader::new(stream),
+            writer,
+            reference_name: String::new(),
+        };
+
+        harness.send(&format!("HELLO MARTYPC {}", LOCKSTEP_PROTOCOL_VERSION))?;
+        let reply = harness.recv()?;
+        let fields: Vec<&str> = reply.split_whitespace().collect();
+        match fields.as_slice() {
+            ["HELLO", name, version] if *version == LOCKSTEP_PROTOCOL_VERSION.to_string() => {
+                harness.reference_name = name.to_string();
+            }
+            _ => bail!("Bad handshake from reference: {}", reply),
+        }
+        log::debug!("Connected to lockstep reference: {}", harness.reference_name);
+        Ok(harness)
+    }
+
+    pub fn reference_name(&self) -> &str {
+        &self.reference_name
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), Error> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("Reference closed the connection");
+        }
+        let line = line.trim().to_string();
+        if line.starts_with("ERR") {
+            bail!("Reference reported an error: {}", line);
+        }
+        Ok(line)
+    }
+
+    /// Step the reference by one instruction, returning the memory writes it made and its
+    /// registers after the instruction.
+    fn step_reference(&mut self) -> Result<(Vec<(u32, u8)>, VRegisters), Error> {
+        self.send("STEP")?;
+        let mut writes = Vec::new();
+        loop {
+            let line = self.recv()?;
+            let mut fields = line.splitn(2, ' ');
+            match (fields.next(), fields.next()) {
+                (Some("W"), Some(write)) => {
+                    let mut parts = write.split_whitespace();
+                    let addr = parts.next().and_then(|a| u32::from_str_radix(a, 16).ok());
+                    let data = parts.next().and_then(|d| u8::from_str_radix(d, 16).ok());
+                    match (addr, data) {
+                        (Some(addr), Some(data)) => writes.push((addr & 0xFFFFF, data)),
+                        _ => bail!("Bad write from reference: {}", line),
+                    }
+                }
+                (Some("REGS"), Some(regs)) => {
+                    let regs = VRegisters::from_str(regs).map_err(|e| anyhow!(e))?;
+                    return Ok((writes, regs));
+                }
+                _ => bail!("Unexpected reply from reference: {}", line),
+            }
+        }
+    }
+
+    fn peek_reference(&mut self, address: usize, len: usize) -> Result<Vec<u8>, Error> {
+        self.send(&format!("PEEK {:05X} {:X}", address, len))?;
+        let line = self.recv()?;
+        let hex = match line.strip_prefix("DATA") {
+            Some(hex) => hex.trim(),
+            None => bail!("Unexpected reply from reference: {}", line),
+        };
+        if hex.len() != len * 2 {
+            bail!("Reference returned {} bytes, expected {}", hex.len() / 2, len);
+        }
+        (0..len)
+            .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|e| anyhow!(e)))
+            .collect()
+    }
+
+    /// Compare memory below memory_check_size with the reference.
+    fn compare_memory(&mut self, machine: &Machine) -> Result<Vec<(u32, u8, u8)>, Error> {
+        let size = self.params.memory_check_size.min(machine.bus().size());
+        let mut diffs = Vec::new();
+        let mut address = 0;
+        while address < size {
+            let len = PEEK_CHUNK_SIZE.min(size - address);
+            let theirs = self.peek_reference(address, len)?;
+            let ours = machine.bus().get_slice_at(address, len);
+            for (i, (a, b)) in ours.iter().zip(theirs.iter()).enumerate() {
+                if a != b {
+                    diffs.push(((address + i) as u32, *a, *b));
+                }
+            }
+            address += len;
+        }
+        Ok(diffs)
+    }
+
+    /// Run the machine in lockstep with the reference until the instruction limit is reached, a
+    /// mismatch is found, or the machine stops.
+    pub fn run(&mut self, machine: &mut Machine) -> Result<LockstepResult, Error> {
+        machine.set_cpu_option(CpuOption::InstructionHistory(true));
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+
+        let regs = VRegisters::from(&machine.cpu().get_state());
+        self.send(&format!("SETREGS {}", regs.to_hex_string()))?;
+        let reply = self.recv()?;
+        if reply != "OK" {
+            bail!("Unexpected reply from reference: {}", reply);
+        }
+
+        let mut instructions = 0;
+        while self.params.instructions == 0 || instructions < self.params.instructions {
+            let regs_before = VRegisters::from(&machine.cpu().get_state());
+
+            if machine.run(1, &mut exec_control) == 0
+                || machine.get_error_str().is_some()
+                || !matches!(exec_control.get_state(), ExecutionState::Running)
+            {
+                _ = self.send("BYE");
+                return Ok(LockstepResult::Stopped(instructions));
+            }
+            instructions += 1;
+
+            let (writes, reference_regs) = self.step_reference()?;
+            let regs = VRegisters::from(&machine.cpu().get_state());
+            let register_diffs = regs.diff(&reference_regs, self.params.flag_mask);
+
+            // Check the reference's writes against our memory.
+            let mut memory_diffs: Vec<(u32, u8, u8)> = writes
+                .iter()
+                .filter_map(|(addr, data)| match machine.bus().peek_u8(*addr as usize) {
+                    Ok(ours) if ours != *data => Some((*addr, ours, *data)),
+                    _ => None,
+                })
+                .collect();
+
+            if self.params.memory_check_interval > 0 && instructions % self.params.memory_check_interval == 0 {
+                memory_diffs.extend(self.compare_memory(machine)?);
+            }
+
+            if !register_diffs.is_empty() || !memory_diffs.is_empty() {
+                _ = self.send("BYE");
+                return Ok(LockstepResult::Mismatch(Box::new(LockstepMismatch {
+                    instruction: instructions,
+                    cycle: machine.cpu_cycles(),
+                    regs_before,
+                    regs,
+                    reference_regs,
+                    register_diffs,
+                    memory_diffs,
+                    history: machine.cpu().dump_instruction_history_string(),
+                })));
+            }
+        }
+
+        _ = self.send("BYE");
+        Ok(LockstepResult::Completed(instructions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_encoding() {
+        let regs = VRegisters {
+            ax: 0x1234,
+            ip: 0xFFF0,
+            cs: 0xF000,
+            flags: 0xF002,
+            ..Default::default()
+        };
+        let encoded = regs.to_hex_string();
+        assert_eq!(
+            encoded,
+            "1234 0000 0000 0000 0000 0000 0000 0000 F000 0000 0000 0000 FFF0 F002"
+        );
+        assert_eq!(VRegisters::from_str(&encoded).unwrap(), regs);
+
+        // Reserved flag bits are masked off by default.
+        let mut other = regs;
+        other.flags = 0x0002;
+        assert!(regs.diff(&other, LOCKSTEP_DEFAULT_FLAG_MASK).is_empty());
+        other.ax = 0;
+        assert_eq!(regs.diff(&other, LOCKSTEP_DEFAULT_FLAG_MASK), vec![("AX", 0x1234, 0)]);
+    }
+}
@@ -36,45 +36,102 @@
 use log;
 
 use anyhow::{anyhow, Error};
+use chrono::Local;
 use std::{
     cell::Cell,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     fs::File,
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::{
-    breakpoints::BreakPointType,
-    bus::{BusInterface, ClockFactor, DeviceEvent, MEM_CP_BIT},
+    breakpoints::{BreakPointType, Breakpoint, BreakpointId, BreakpointSet, VectorChange},
+    bus::{BusInterface, ClockFactor, DeviceEvent, IoDeviceType, MEM_CP_BIT, MEM_ROM_BIT},
     coreconfig::CoreConfig,
-    cpu_808x::{Cpu, CpuAddress, CpuError, ServiceEvent, StepResult},
-    cpu_common::{CpuOption, CpuType, TraceMode},
-    device_traits::videocard::{VideoCard, VideoCardId, VideoCardInterface, VideoCardState, VideoOption},
+    cpu_808x::{
+        Cpu,
+        CpuAddress,
+        CpuError,
+        InstructionHook,
+        InstructionRecord,
+        Register16,
+        ServiceEvent,
+        SingleStepHook,
+        StepResult,
+        DEFAULT_HALT_CYCLES,
+    },
+    cpu_common::{CpuOption, HistoryExportFormat, TraceMode},
+    device_traits::videocard::{VideoCard, VideoCardId, VideoCardInterface, VideoCardState, VideoOption, VideoType},
     devices::{
         dma::DMAControllerStringState,
         fdc::FloppyController,
-        hdc::HardDiskController,
-        keyboard::KeyboardModifiers,
+        game_port::{GamePort, GamepadAxis, GamepadButton},
+        hdc::{HardDiskController, HdcAccessEntry},
+        keyboard::{KeyboardLeds, KeyboardModifiers, KeyboardState, KeyboardType},
         mouse::Mouse,
-        pic::PicStringState,
+        pic::{IrqTraceEntry, PicStringState},
         pit::{self, PitDisplayState},
-        ppi::PpiStringState,
+        ppi::{DipSwitches, PpiStringState},
+        serial::{SerialBridgeConfig, StdioLineMode, SERIAL_PORT_COUNT},
+    },
+    file_util,
+    host_clock::{
+        ClockTime,
+        HostClockConfig,
+        HostTimeSource,
+        BIOS_BOOTSTRAP_INTERRUPT,
+        BIOS_TICKS_PER_DAY,
+        BIOS_TIMER_COUNT_ADDRESS,
+        BIOS_TIMER_OVERFLOW_ADDRESS,
     },
+    input_script::{InputScript, InputScriptPlayer, ScriptAction},
     keys::MartyKey,
-    machine_config::{get_machine_descriptor, MachineConfiguration, MachineDescriptor},
-    machine_types::MachineType,
-    sound::{SoundPlayer, BUFFER_MS, VOLUME_ADJUST},
-    tracelogger::TraceLogger,
+    machine_config::{
+        get_machine_descriptor,
+        MachineConfiguration,
+        MachineDescriptor,
+        SerialMouseConfig,
+        VideoCardConfig,
+    },
+    machine_snapshot::{MachineSnapshot, QuickSave, QuickSaveInfo, SnapshotHistory, QUICK_SAVE_SLOTS},
+    machine_types::{CpuClockPreset, MachineType},
+    memory_snapshot::{MemoryDiff, MemorySnapshot},
+    movie::{InputMovie, MovieMode, MoviePlayer},
+    nvram::NvramStore,
+    profiler::{CycleProfile, ProfileGranularity, RomProfile},
+    rewind::{RewindBuffer, RewindFrame},
+    sound::{AudioSource, SoundMixer, SoundPlayer, SpeakerProfile, SpeakerStage, BUFFER_MS, VOLUME_ADJUST},
+    timeline::Timeline,
+    tracelogger::{self, TraceLogger},
+    video_trace::VideoTraceFilter,
 };
 
 use ringbuf::{Consumer, Producer, RingBuffer};
 
 pub const STEP_OVER_TIMEOUT: u32 = 320000;
+// Cycle budget of each run() call made while running for a count of instructions or frames.
+pub const RUN_COUNT_SLICE_CYCLES: u32 = 10000;
+/// Interval between keyboard events injected by movie playback, in microseconds.
+pub const MOVIE_KB_INTERVAL_US: f64 = 1000.0;
+/// Default interval between keyboard events produced by type_text(), in microseconds. This is
+/// about one 60Hz frame, the rate at which interactive keyboard events are delivered.
+pub const DEFAULT_TYPE_TEXT_INTERVAL_US: f64 = 16_000.0;
 
 //pub const NUM_HDDS: u32 = 2;
 
 pub const MAX_MEMORY_ADDRESS: usize = 0xFFFFF;
+/// Maximum number of cycles a halted CPU will execute in a single step when halt idling is enabled.
+pub const HALT_IDLE_MAX_CYCLES: u32 = 1000;
+/// Address of the BIOS reset flag word at 0040:0072. A value of 1234h indicates a warm boot.
+pub const BIOS_WARM_BOOT_FLAG_ADDRESS: usize = 0x472;
+pub const BIOS_WARM_BOOT_FLAG: u16 = 0x1234;
+/// Number of interrupt vector changes to keep while the vector table is watched.
+pub const VECTOR_LOG_LEN: usize = 256;
+/// Number of delivered interrupts to keep while the interrupt trace is enabled.
+pub const IRQ_TRACE_LEN: usize = 256;
+/// Breakpoint group of the temporary breakpoint set by run_back_to().
+pub const RUN_TO_BREAKPOINT_GROUP: &str = "run_to";
 
 #[derive(Copy, Clone, Debug)]
 pub struct KeybufferEntry {
@@ -125,6 +182,18 @@ pub enum ExecutionOperation {
     StepOver,
     Run,
     Reset,
+    /// Reset the machine without clearing RAM, as if Ctrl-Alt-Del were pressed.
+    WarmReset,
+    /// Run until the next video frame begins, then pause.
+    FrameAdvance,
+    /// Execute the specified number of instructions, then pause.
+    RunInstructions(u64),
+    /// Run until the specified number of video frames have begun, then pause.
+    RunFrames(u64),
+    /// Undo the last instruction executed, if step-back history is being recorded.
+    StepBack,
+    /// Run until the current procedure or interrupt handler returns, then pause.
+    StepOut,
 }
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -136,16 +205,29 @@ pub struct DelayParams {
 pub struct ExecutionControl {
     pub state: ExecutionState,
     op: Cell<ExecutionOperation>,
+    frame_advance_target: Option<u64>,
+    instructions_remaining: Option<u64>,
+    step_out_frame: Option<(u16, u16)>,
 }
 
 impl ExecutionControl {
     pub fn new() -> Self {
         Self {
             state: ExecutionState::Paused,
-            op:    Cell::new(ExecutionOperation::None),
+            op: Cell::new(ExecutionOperation::None),
+            frame_advance_target: None,
+            instructions_remaining: None,
+            step_out_frame: None,
         }
     }
 
+    /// Cancel any frame or instruction count that running is limited to.
+    fn clear_run_targets(&mut self) {
+        self.frame_advance_target = None;
+        self.instructions_remaining = None;
+        self.step_out_frame = None;
+    }
+
     pub fn set_state(&mut self, state: ExecutionState) {
         self.state = state
     }
@@ -161,9 +243,24 @@ impl ExecutionControl {
                 // Can only pause if Running
                 if let ExecutionState::Running = self.state {
                     self.state = ExecutionState::Paused;
+                    self.clear_run_targets();
+                    self.op.set(op);
+                }
+            }
+            ExecutionOperation::FrameAdvance => {
+                // Can only frame advance if paused / breakpointhit
+                if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
                     self.op.set(op);
                 }
             }
+            ExecutionOperation::RunInstructions(count) | ExecutionOperation::RunFrames(count) => {
+                // Can only run for a count if paused / breakpointhit
+                if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
+                    if count > 0 {
+                        self.op.set(op);
+                    }
+                }
+            }
             ExecutionOperation::Step => {
                 // Can only Step if paused / breakpointhit
                 if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
@@ -176,13 +273,25 @@ impl ExecutionControl {
                     self.op.set(op);
                 }
             }
+            ExecutionOperation::StepOut => {
+                // Can only Step Out if paused / breakpointhit
+                if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
+                    self.op.set(op);
+                }
+            }
+            ExecutionOperation::StepBack => {
+                // Can only Step Back if paused / breakpointhit
+                if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
+                    self.op.set(op);
+                }
+            }
             ExecutionOperation::Run => {
                 // Can only Run if paused / breakpointhit
                 if let ExecutionState::Paused | ExecutionState::BreakpointHit = self.state {
                     self.op.set(op);
                 }
             }
-            ExecutionOperation::Reset => {
+            ExecutionOperation::Reset | ExecutionOperation::WarmReset => {
                 // Can reset anytime.
                 self.op.set(op);
             }
@@ -215,9 +324,10 @@ pub struct PitData {
 
 #[derive(Clone, Default, Debug)]
 pub struct MachineRomEntry {
-    pub md5:  String,
+    pub md5: String,
     pub addr: u32,
     pub data: Vec<u8>,
+    pub wait_states: Option<u32>, // Overrides the machine's ROM wait states for this ROM.
 }
 
 #[derive(Clone, Default, Debug)]
@@ -234,7 +344,7 @@ pub struct MachinePatch {
     pub data:    Vec<u8>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct MachineRomManifest {
     pub checkpoints: Vec<MachineCheckpoint>,
     pub patches: Vec<MachinePatch>,
@@ -327,15 +437,22 @@ impl<'a> MachineBuilder<'a> {
 
     pub fn build(self) -> Result<Machine, Error> {
         let core_config = self.core_config.ok_or(anyhow!("No core configuration specified"))?;
-        let machine_config = self
+        let mut machine_config = self
             .machine_config
             .ok_or(anyhow!("No machine configuration specified"))?;
         let machine_type = self.mtype.ok_or(anyhow!("No machine type specified"))?;
-        let machine_desc = self.descriptor.ok_or(anyhow!("Failed to get machine description"))?;
+        let mut machine_desc = self.descriptor.ok_or(anyhow!("Failed to get machine description"))?;
+        if let Some(desc_config) = machine_config.descriptor.clone() {
+            machine_desc = machine_desc.with_overrides(&desc_config)?;
+            log::debug!("Using custom machine descriptor: {:?}", machine_desc);
+            if let Some(devices) = &desc_config.devices {
+                devices.apply(&mut machine_config);
+            }
+        }
         let rom_manifest = self.rom_manifest.ok_or(anyhow!("No ROM manifest specified!"))?;
         let trace_logger = self.trace_logger;
 
-        Ok(Machine::new(
+        Machine::new(
             *core_config,
             machine_config,
             machine_type,
@@ -344,7 +461,7 @@ impl<'a> MachineBuilder<'a> {
             trace_logger,
             self.sound_player,
             rom_manifest,
-        ))
+        )
     }
 }
 
@@ -360,14 +477,26 @@ pub struct Machine {
     cpu: Cpu,
     speaker_buf_producer: Producer<u8>,
     pit_data: PitData,
+    speaker_stage: SpeakerStage,
+    mixer: SoundMixer,
     debug_snd_file: Option<File>,
     kb_buf: VecDeque<KeybufferEntry>,
+    movie: Option<MoviePlayer>,
+    movie_kb_buf: VecDeque<KeybufferEntry>,
+    movie_kb_timer: f64,
+    typed_kb_buf: VecDeque<KeybufferEntry>,
+    typed_kb_timer: f64,
+    type_text_interval: f64,
+    input_script: Option<InputScriptPlayer>,
+    last_video_frame: Option<u64>,
     error: bool,
     error_str: Option<String>,
     turbo_bit: bool,
     turbo_button: bool,
     cpu_factor: ClockFactor,
     next_cpu_factor: ClockFactor,
+    turbo_factor: ClockFactor,
+    dram_refresh_period: Option<u16>,
     cpu_cycles: u64,
     cpu_instructions: u64,
     system_ticks: u64,
@@ -375,6 +504,31 @@ pub struct Machine {
     patch_map: HashMap<u32, usize>,
     events: Vec<MachineEvent>,
     reload_pending: bool,
+    kb_state: Option<KeyboardState>,
+    memory_snapshots: Vec<MemorySnapshot>,
+    quick_saves: Vec<Option<QuickSave>>,
+    halt_idle: bool,
+    nmi: bool,
+    nmi_pulse: bool,
+    halted_cycles: u64,
+    host_clock: HostClockConfig,
+    host_clock_timer: f64,
+    host_time_source: Option<HostTimeSource>,
+    bios_clock_valid: bool,
+    vector_watch: bool,
+    vector_log: VecDeque<VectorChange>,
+    irq_trace_enabled: bool,
+    irq_trace: VecDeque<IrqTraceEntry>,
+    irq_trace_logger: TraceLogger,
+    rom_profile: Option<RomProfile>,
+    cycle_profile: Option<CycleProfile>,
+    breakpoints: BreakpointSet,
+    last_breakpoint: Option<BreakpointId>,
+    run_to_breakpoint: Option<BreakpointId>,
+    nvram: Option<NvramStore>,
+    timeline: Option<Timeline>,
+    rewind: Option<RewindBuffer>,
+    snapshot_history: Option<SnapshotHistory>,
 }
 
 impl Machine {
@@ -388,7 +542,7 @@ impl Machine {
         sound_player: Option<SoundPlayer>,
         rom_manifest: MachineRomManifest,
         //rom_manager: RomManager,
-    ) -> Machine {
+    ) -> Result<Machine, Error> {
         // Create PIT output log file if specified
         let pit_output_file_option = None;
         /*
@@ -418,7 +572,7 @@ impl Machine {
         use crate::cpu_validator::ValidatorMode;
 
         let mut cpu = Cpu::new(
-            CpuType::Intel8088,
+            machine_desc.cpu_type,
             trace_mode,
             trace_logger,
             #[cfg(feature = "cpu_validator")]
@@ -429,6 +583,8 @@ impl Machine {
             ValidatorMode::Cycle,
             #[cfg(feature = "cpu_validator")]
             core_config.get_validator_baud().unwrap_or(1_000_000),
+            #[cfg(feature = "cpu_validator")]
+            core_config.get_validator_address(),
         );
 
         cpu.set_option(CpuOption::TraceLoggingEnabled(core_config.get_cpu_trace_on()));
@@ -444,6 +600,8 @@ impl Machine {
         }
         let pit_ticks_per_sample = (pit::PIT_MHZ * 1_000_000.0) / sample_rate as f64;
 
+        let speaker_stage = SpeakerStage::new(machine_config.speaker_profile.unwrap_or_default(), sample_rate);
+
         let pit_data = PitData {
             buffer_consumer: speaker_buf_consumer,
             ticks_per_sample: pit_ticks_per_sample,
@@ -471,10 +629,28 @@ impl Machine {
         }
         */
 
-        // Install devices
-        if let Err(err) = cpu.bus_mut().install_devices(&machine_desc, &machine_config) {
-            log::error!("Failed to install devices: {}", err);
+        // Validate the configuration so that all problems can be reported at once, instead of
+        // failing on the first device that can't be installed.
+        let diagnostics = BusInterface::validate_config(&machine_desc, &machine_config);
+        for diagnostic in diagnostics.iter() {
+            if diagnostic.is_error() {
+                log::error!("{}", diagnostic);
+            }
+            else {
+                log::warn!("{}", diagnostic);
+            }
         }
+        let errors: Vec<String> = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.problem.to_string())
+            .collect();
+        if !errors.is_empty() {
+            return Err(anyhow!("Invalid machine configuration: {}", errors.join(" ")));
+        }
+
+        // Install devices
+        cpu.bus_mut().install_devices(&machine_desc, &machine_config)?;
 
         // Load keyboard translation file if specified.
         if let Some(kb_string) = &core_config.get_keyboard_layout() {
@@ -516,14 +692,27 @@ impl Machine {
             //cpu.set_reset_vector(CpuAddress::Segmented(rom_entry_point.0, rom_entry_point.1));
         }
 
-        // Set CPU clock divisor/multiplier
+        // The machine configuration may override the reset vector, for example to start a diagnostic
+        // ROM that uses the wrong jump at the reset vector.
+        if let Some([segment, offset]) = machine_config.reset_vector {
+            log::debug!("Setting reset vector to {:04X}:{:04X}", segment, offset);
+            cpu.set_reset_vector(CpuAddress::Segmented(segment, offset));
+            cpu.reset();
+        }
+
+        // Set CPU clock divisor/multiplier. The machine configuration may override the turbo clock.
+        let turbo_factor = machine_config
+            .turbo_clock
+            .map(|preset| preset.clock_factor())
+            .unwrap_or(machine_desc.cpu_turbo_factor);
         let cpu_factor;
         if core_config.get_machine_turbo() {
-            cpu_factor = machine_desc.cpu_turbo_factor;
+            cpu_factor = turbo_factor;
         }
         else {
             cpu_factor = machine_desc.cpu_factor;
         }
+        cpu.bus_mut().set_cpu_factor(cpu_factor);
 
         cpu.emit_header();
         cpu.reset();
@@ -531,7 +720,13 @@ impl Machine {
         let checkpoint_map = rom_manifest.checkpoint_map();
         let patch_map = rom_manifest.patch_map();
 
-        Machine {
+        let type_text_interval = machine_config
+            .keyboard
+            .as_ref()
+            .and_then(|kb| kb.type_text_delay)
+            .map_or(DEFAULT_TYPE_TEXT_INTERVAL_US, |ms| ms * 1000.0);
+
+        let mut machine = Machine {
             machine_type,
             machine_desc,
             machine_config,
@@ -542,14 +737,26 @@ impl Machine {
             cpu,
             speaker_buf_producer,
             pit_data,
+            speaker_stage,
+            mixer: SoundMixer::new(sample_rate),
             debug_snd_file: None,
             kb_buf: VecDeque::new(),
+            movie: None,
+            movie_kb_buf: VecDeque::new(),
+            movie_kb_timer: 0.0,
+            typed_kb_buf: VecDeque::new(),
+            typed_kb_timer: 0.0,
+            type_text_interval,
+            input_script: None,
+            last_video_frame: None,
             error: false,
             error_str: None,
             turbo_bit: false,
             turbo_button: false,
             cpu_factor,
             next_cpu_factor: cpu_factor,
+            turbo_factor,
+            dram_refresh_period: None,
             cpu_cycles: 0,
             cpu_instructions: 0,
             system_ticks: 0,
@@ -557,12 +764,42 @@ impl Machine {
             patch_map,
             events: Vec::new(),
             reload_pending: false,
-        }
+            kb_state: None,
+            memory_snapshots: Vec::new(),
+            quick_saves: vec![None; QUICK_SAVE_SLOTS],
+            halt_idle: false,
+            nmi: false,
+            nmi_pulse: false,
+            halted_cycles: 0,
+            host_clock: HostClockConfig::default(),
+            host_clock_timer: 0.0,
+            host_time_source: None,
+            bios_clock_valid: false,
+            vector_watch: false,
+            vector_log: VecDeque::new(),
+            irq_trace_enabled: false,
+            irq_trace: VecDeque::new(),
+            irq_trace_logger: TraceLogger::None,
+            rom_profile: None,
+            cycle_profile: None,
+            breakpoints: BreakpointSet::default(),
+            last_breakpoint: None,
+            run_to_breakpoint: None,
+            nvram: None,
+            timeline: None,
+            rewind: None,
+            snapshot_history: None,
+        };
+
+        machine.apply_dram_refresh_config();
+        Ok(machine)
     }
 
     pub fn install_roms(bus: &mut BusInterface, rom_manifest: &MachineRomManifest) {
+        let rom_wait_states = bus.rom_wait_states();
         for rom in rom_manifest.roms.iter() {
-            match bus.copy_from(&rom.data, rom.addr as usize, 0, true) {
+            let wait_states = rom.wait_states.unwrap_or(rom_wait_states);
+            match bus.copy_from(&rom.data, rom.addr as usize, wait_states, true) {
                 Ok(_) => {
                     log::debug!("Mounted rom at location {:06X}", rom.addr);
                 }
@@ -574,8 +811,14 @@ impl Machine {
     }
 
     pub fn reinstall_roms(&mut self, rom_manifest: MachineRomManifest) -> Result<(), Error> {
+        let rom_wait_states = self.cpu.bus().rom_wait_states();
         for rom in rom_manifest.roms.iter() {
-            match self.cpu.bus_mut().copy_from(&rom.data, rom.addr as usize, 0, true) {
+            let wait_states = rom.wait_states.unwrap_or(rom_wait_states);
+            match self
+                .cpu
+                .bus_mut()
+                .copy_from(&rom.data, rom.addr as usize, wait_states, true)
+            {
                 Ok(_) => {
                     log::debug!("Mounted rom at location {:06X}", rom.addr);
                 }
@@ -651,6 +894,137 @@ impl Machine {
         self.cpu.bus_mut()
     }
 
+    /// Capture a labeled snapshot of the address space. A snapshot with the same label is replaced.
+    pub fn take_memory_snapshot(&mut self, label: &str) {
+        // The most recent snapshot is used as the base, so only pages written since are copied.
+        let snapshot =
+            MemorySnapshot::capture(self.cpu.bus_mut(), label, self.cpu_cycles, self.memory_snapshots.last());
+        self.memory_snapshots.retain(|s| s.label != label);
+        self.memory_snapshots.push(snapshot);
+    }
+
+    pub fn memory_snapshots(&self) -> &[MemorySnapshot] {
+        &self.memory_snapshots
+    }
+
+    pub fn remove_memory_snapshot(&mut self, label: &str) -> bool {
+        let len = self.memory_snapshots.len();
+        self.memory_snapshots.retain(|s| s.label != label);
+        self.memory_snapshots.len() != len
+    }
+
+    pub fn clear_memory_snapshots(&mut self) {
+        self.memory_snapshots.clear();
+    }
+
+    /// Diff the snapshot `label` against the snapshot `against`, or against current memory if
+    /// `against` is None.
+    pub fn diff_memory_snapshot(
+        &self,
+        label: &str,
+        against: Option<&str>,
+        merge_gap: usize,
+    ) -> Result<MemoryDiff, Error> {
+        let old = self
+            .memory_snapshots
+            .iter()
+            .find(|s| s.label == label)
+            .ok_or_else(|| anyhow!("No memory snapshot with label '{}'", label))?;
+
+        match against {
+            Some(new_label) => {
+                let new = self
+                    .memory_snapshots
+                    .iter()
+                    .find(|s| s.label == new_label)
+                    .ok_or_else(|| anyhow!("No memory snapshot with label '{}'", new_label))?;
+                Ok(old.diff(new, merge_gap))
+            }
+            None => {
+                let current = MemorySnapshot::capture_current(self.cpu.bus(), "current", self.cpu_cycles, Some(old));
+                Ok(old.diff(&current, merge_gap))
+            }
+        }
+    }
+
+    /// Capture the state of the whole machine.
+    fn capture_snapshot(&mut self) -> MachineSnapshot {
+        MachineSnapshot::capture(
+            &mut self.cpu,
+            self.cpu_cycles,
+            self.cpu_instructions,
+            self.system_ticks,
+            None,
+        )
+    }
+
+    /// Restore the machine to the state captured in a snapshot. The step-back history is
+    /// discarded, as it no longer leads to the restored state.
+    fn restore_snapshot(&mut self, snapshot: &MachineSnapshot) -> Result<(), Error> {
+        if snapshot.memory.len() != self.cpu.bus().size() {
+            return Err(anyhow!(
+                "Snapshot has {} bytes of memory, expected {}",
+                snapshot.memory.len(),
+                self.cpu.bus().size()
+            ));
+        }
+        let mut devices = Vec::with_capacity(snapshot.devices.len());
+        for (device, state) in &snapshot.devices {
+            devices.push((*device, serde_json::from_str::<serde_json::Value>(state)?));
+        }
+
+        self.cpu.restore_state(&snapshot.regs, snapshot.halted);
+        snapshot.memory.restore(self.cpu.bus_mut());
+        for (device, state) in devices {
+            self.cpu.bus_mut().load_device_state(device, state)?;
+        }
+        self.cpu_cycles = snapshot.cycle;
+        self.cpu_instructions = snapshot.instructions;
+        self.system_ticks = snapshot.system_ticks;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    /// Save the state of the machine to the specified quick save slot, replacing any save already
+    /// in it. `thumbnail` is an image of the display supplied by the frontend, kept with the save.
+    pub fn quick_save(&mut self, slot: usize, thumbnail: Vec<u8>) -> Result<(), Error> {
+        if slot >= QUICK_SAVE_SLOTS {
+            return Err(anyhow!("Invalid quick save slot: {}", slot));
+        }
+        let info = QuickSaveInfo {
+            timestamp: Local::now(),
+            frame_count: self.video_frame_count(),
+            thumbnail,
+        };
+        let snapshot = self.capture_snapshot();
+        self.quick_saves[slot] = Some(QuickSave { info, snapshot });
+        Ok(())
+    }
+
+    /// Restore the machine from the specified quick save slot. The save is kept, so it can be
+    /// loaded again. If the machine's devices don't match those the save was taken with, the
+    /// machine may be left partially restored.
+    pub fn quick_load(&mut self, slot: usize) -> Result<(), Error> {
+        let save = match self.quick_saves.get_mut(slot).and_then(|save| save.take()) {
+            Some(save) => save,
+            None => return Err(anyhow!("Quick save slot {} is empty", slot)),
+        };
+        let result = self.restore_snapshot(&save.snapshot);
+        self.quick_saves[slot] = Some(save);
+        self.clear_snapshot_history();
+        result
+    }
+
+    /// Return the information for the save in the specified quick save slot, if there is one.
+    pub fn quick_save_info(&self, slot: usize) -> Option<&QuickSaveInfo> {
+        self.quick_saves.get(slot)?.as_ref().map(|save| &save.info)
+    }
+
+    /// Empty the specified quick save slot. Returns false if the slot was already empty.
+    pub fn clear_quick_save(&mut self, slot: usize) -> bool {
+        self.quick_saves.get_mut(slot).and_then(|save| save.take()).is_some()
+    }
+
     pub fn video_buffer_mut(&mut self, _vid: VideoCardId) -> Option<&mut u8> {
         None
     }
@@ -659,6 +1033,26 @@ impl Machine {
         self.cpu.bus_mut().primary_video_mut()
     }
 
+    /// Install a new video card on the running machine. The frontend is responsible for creating
+    /// a display target for the returned card id.
+    pub fn add_videocard(&mut self, card: &VideoCardConfig) -> Result<VideoCardId, Error> {
+        let vid = self.cpu.bus_mut().add_videocard(card)?;
+        self.machine_config.video.push(card.clone());
+        Ok(vid)
+    }
+
+    /// Remove a video card from the running machine.
+    pub fn remove_videocard(&mut self, vid: VideoCardId) -> Result<(), Error> {
+        // Cards are kept in the order they were installed, which is the order of their entries
+        // in the machine configuration.
+        let pos = self.cpu.bus().enumerate_videocards().iter().position(|id| *id == vid);
+        self.cpu.bus_mut().remove_videocard(vid)?;
+        if let Some(pos) = pos {
+            self.machine_config.video.remove(pos);
+        }
+        Ok(())
+    }
+
     /*
     pub fn enumerate_video_cards(&mut self) -> Vec<VideoCardInterface> {
         let mut vcivec = Vec::new();
@@ -702,6 +1096,49 @@ impl Machine {
         self.cpu.get_option(opt)
     }
 
+    /// Install a callback to run on every INT 01 single-step trap, or remove it with None. This is
+    /// intended for driving a DEBUG-style stepping session from the host.
+    pub fn set_single_step_hook(&mut self, hook: Option<SingleStepHook>) {
+        self.cpu.set_single_step_hook(hook);
+    }
+
+    /// Install a callback to run before each instruction, or remove it with None. The hook may
+    /// modify registers or skip the instruction, ie, to service a BIOS call from the host.
+    pub fn set_pre_instruction_hook(&mut self, hook: Option<InstructionHook>) {
+        self.cpu.set_pre_instruction_hook(hook);
+    }
+
+    /// Install a callback to run after each instruction, or remove it with None.
+    pub fn set_post_instruction_hook(&mut self, hook: Option<InstructionHook>) {
+        self.cpu.set_post_instruction_hook(hook);
+    }
+
+    /// Return the most recent `count` entries of the CPU instruction history as structured records,
+    /// oldest first. Instruction history must be enabled with CpuOption::InstructionHistory.
+    pub fn instruction_history(&self, count: usize) -> Vec<InstructionRecord> {
+        self.cpu.instruction_history(count)
+    }
+
+    /// Export the CPU instruction history to a file at the specified path.
+    pub fn export_instruction_history(&self, path: &Path, format: HistoryExportFormat) -> Result<(), Error> {
+        let file = File::create(path).map_err(|e| anyhow!("Couldn't create {}: {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        self.cpu.export_instruction_history(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Dump the 64K segment addressed by CS to a file at the specified path.
+    pub fn dump_cs(&self, path: &Path) {
+        let address = (self.cpu.get_register16(Register16::CS) as usize) << 4;
+        file_util::write_dump(path, self.cpu.bus().get_slice_at(address, 0x10000));
+    }
+
+    /// Dump the entire address space to a file at the specified path.
+    pub fn dump_mem(&self, path: &Path) {
+        file_util::write_dump(path, self.cpu.bus().get_slice_at(0, 0x10_0000));
+    }
+
     /// Send the specified video option to the active videocard device
     pub fn set_video_option(&mut self, opt: VideoOption) {
         if let Some(video) = self.cpu.bus_mut().primary_video_mut() {
@@ -715,6 +1152,7 @@ impl Machine {
         if let Some(video) = self.cpu.bus_mut().primary_video_mut() {
             video.trace_flush();
         }
+        tracelogger::flush_event_sink();
     }
 
     /// Return the current CPU clock frequency in MHz.
@@ -722,10 +1160,98 @@ impl Machine {
     /// CPU speed is always some factor of the main system crystal frequency.
     /// The CPU itself has no concept of its operational frequency.
     pub fn get_cpu_mhz(&self) -> f64 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => self.machine_desc.system_crystal / (n as f64),
-            ClockFactor::Multiplier(n) => self.machine_desc.system_crystal * (n as f64),
+        self.cpu_factor.cpu_mhz(self.machine_desc.system_crystal)
+    }
+
+    /// Return the active CPU clock factor.
+    pub fn get_cpu_factor(&self) -> ClockFactor {
+        self.cpu_factor
+    }
+
+    /// Request a new CPU clock factor. The bus timing tables, the cycle conversion table and the
+    /// DRAM refresh schedule are all updated together between instructions.
+    ///
+    /// If `apply_now` is true, the change is applied before returning. This must not be done while
+    /// the machine is running, ie, from a device, only between calls to run(). Otherwise, the
+    /// change is applied at the start of the next call to run().
+    ///
+    /// Note that the turbo button and the PPI turbo bit will override this setting when toggled.
+    pub fn set_cpu_factor(&mut self, factor: ClockFactor, apply_now: bool) {
+        self.next_cpu_factor = factor;
+        log::debug!("Set cpu factor to: {:?}", factor);
+        if apply_now {
+            self.update_cpu_factor();
+        }
+    }
+
+    /// Apply the DRAM refresh parameters from the machine configuration, if present.
+    /// A fixed refresh period takes precedence over the period programmed into PIT channel 1.
+    fn apply_dram_refresh_config(&mut self) {
+        let refresh = match self.machine_config.dram_refresh.clone() {
+            Some(refresh) => refresh,
+            None => return,
+        };
+        if let Some(burst_length) = refresh.burst_length {
+            self.cpu.set_option(CpuOption::DramRefreshWaitStates(burst_length));
+        }
+
+        if !refresh.enabled {
+            log::debug!("DRAM refresh simulation disabled by machine configuration.");
+            self.dram_refresh_period = None;
+            self.cpu.set_option(CpuOption::SimulateDramRefresh(false, 0, 0));
+        }
+        else if let Some(period) = refresh.period {
+            log::debug!("Using fixed DRAM refresh period of {} timer ticks.", period);
+            let cycles = self.timer_ticks_to_cpu_cycles(period);
+            self.dram_refresh_period = Some(period);
+            self.cpu
+                .set_option(CpuOption::SimulateDramRefresh(true, cycles, cycles));
+        }
+    }
+
+    /// Returns true if DRAM refresh scheduling should follow the programming of PIT channel 1.
+    fn dram_refresh_from_pit(&self) -> bool {
+        match &self.machine_config.dram_refresh {
+            Some(refresh) => refresh.enabled && refresh.period.is_none(),
+            None => true,
+        }
+    }
+
+    /// Switch the CPU clock to the next faster clock preset, wrapping around to the slowest.
+    /// This emulates the clock selection hotkeys of turbo XT clones. Returns the new CPU clock
+    /// in MHz.
+    pub fn cycle_cpu_clock(&mut self) -> f64 {
+        let crystal = self.machine_desc.system_crystal;
+        let mhz = self.get_cpu_mhz();
+        let factor = CpuClockPreset::ALL
+            .iter()
+            .map(|preset| preset.clock_factor())
+            .find(|factor| factor.cpu_mhz(crystal) > mhz + 0.01)
+            .unwrap_or(CpuClockPreset::ALL[0].clock_factor());
+        self.set_cpu_factor(factor, true);
+        self.get_cpu_mhz()
+    }
+
+    /// Apply a pending CPU clock factor change.
+    fn update_cpu_factor(&mut self) {
+        if self.next_cpu_factor == self.cpu_factor {
+            return;
         }
+        self.cpu_factor = self.next_cpu_factor;
+        self.cpu.bus_mut().set_cpu_factor(self.cpu_factor);
+
+        // The CPU schedules DRAM refresh in CPU cycles, so the refresh period must be recalculated.
+        if let Some(period) = self.dram_refresh_period {
+            let cycles = self.timer_ticks_to_cpu_cycles(period);
+            self.cpu
+                .set_option(CpuOption::SimulateDramRefresh(true, cycles, cycles));
+        }
+
+        log::debug!(
+            "CPU clock changed to {:.2}MHz ({:?})",
+            self.get_cpu_mhz(),
+            self.cpu_factor
+        );
     }
 
     /// Set the specified state of the turbo button. True will enable turbo mode
@@ -736,7 +1262,7 @@ impl Machine {
     pub fn set_turbo_mode(&mut self, state: bool) {
         self.turbo_button = state;
         if state {
-            self.next_cpu_factor = self.machine_desc.cpu_turbo_factor;
+            self.next_cpu_factor = self.turbo_factor;
         }
         else {
             self.next_cpu_factor = self.machine_desc.cpu_factor;
@@ -811,10 +1337,47 @@ impl Machine {
         }
     }
 
+    /// Return the current motherboard DIP switch settings, if the machine has a PPI.
+    pub fn dip_switches(&mut self) -> Option<DipSwitches> {
+        self.cpu.bus_mut().ppi_mut().as_ref().map(|ppi| ppi.dip_switches())
+    }
+
+    /// Set the motherboard DIP switches. The BIOS reads the switches during POST, so the new
+    /// settings take effect when the machine is next reset.
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) -> Result<(), Error> {
+        match self.cpu.bus_mut().ppi_mut() {
+            Some(ppi) => {
+                ppi.set_dip_switches(dip_switches);
+                Ok(())
+            }
+            None => Err(anyhow!("Machine has no PPI")),
+        }
+    }
+
+    /// Set the state of the NMI line, as an expansion card asserting an IO channel check would.
     pub fn set_nmi(&mut self, state: bool) {
+        self.nmi = state;
         self.cpu.set_nmi(state);
     }
 
+    /// Pulse the NMI line for one instruction. As on real hardware, the NMI is lost if it is
+    /// masked at the time.
+    pub fn raise_nmi(&mut self) {
+        self.nmi_pulse = true;
+        self.cpu.set_nmi(true);
+    }
+
+    /// Add or remove a simulated RAM parity error at the specified address. On the 5150 and
+    /// 5160, reading the address raises NMI if the parity check and NMI are enabled.
+    pub fn set_parity_error(&mut self, address: usize, state: bool) {
+        self.cpu.bus_mut().set_parity_error(address, state);
+    }
+
+    /// Remove all simulated RAM parity errors.
+    pub fn clear_parity_errors(&mut self) {
+        self.cpu.bus_mut().clear_parity_errors();
+    }
+
     pub fn dma_state(&mut self) -> DMAControllerStringState {
         // There will always be a primary DMA, so safe to unwrap.
         // TODO: Handle secondary DMA if present.
@@ -838,6 +1401,11 @@ impl Machine {
 
     /// Enter a keypress keycode into the emulator keyboard buffer.
     pub fn key_press(&mut self, keycode: MartyKey, modifiers: KeyboardModifiers) {
+        if let Some(movie) = self.movie.as_mut().filter(|m| !m.passthrough()) {
+            // Input is sampled by the movie at the next frame boundary.
+            movie.live_key(keycode, true);
+            return;
+        }
         self.kb_buf.push_back(KeybufferEntry {
             keycode,
             pressed: true,
@@ -848,6 +1416,10 @@ impl Machine {
 
     /// Enter a key release keycode into the emulator keyboard buffer.
     pub fn key_release(&mut self, keycode: MartyKey) {
+        if let Some(movie) = self.movie.as_mut().filter(|m| !m.passthrough()) {
+            movie.live_key(keycode, false);
+            return;
+        }
         // HO Bit set converts a scancode into its 'release' code
         self.kb_buf.push_back(KeybufferEntry {
             keycode,
@@ -857,6 +1429,217 @@ impl Machine {
         });
     }
 
+    /// Type a string into the emulated keyboard, as if the user had entered it by hand.
+    /// Characters are converted to key presses with the keyboard layout's character table, falling
+    /// back to the US layout. The keys are then translated like any other key press. Keyboard
+    /// events are delivered one at a time at the interval set by set_type_text_interval(), which
+    /// gives the guest time to service each key. A CR/LF pair produces a single Enter. Returns the
+    /// number of characters queued.
+    pub fn type_text(&mut self, text: &str) -> usize {
+        let mut queued = 0;
+        let mut last_char = None;
+
+        for c in text.chars() {
+            if c == '\n' && last_char == Some('\r') {
+                last_char = Some(c);
+                continue;
+            }
+            last_char = Some(c);
+
+            let keys = match self.cpu.bus_mut().keyboard_mut() {
+                Some(keyboard) => keyboard.char_to_keys(c),
+                None => MartyKey::from_char(c).map(|(key, shift)| match shift {
+                    true => vec![MartyKey::ShiftLeft, key],
+                    false => vec![key],
+                }),
+            };
+            let keys = match keys {
+                Some(keys) => keys,
+                None => {
+                    log::warn!("type_text(): No key for character {:?}, skipping", c);
+                    continue;
+                }
+            };
+
+            // Modifier keys are pressed before, and released after, the key they modify.
+            let modifiers = KeyboardModifiers::from_keys(&keys);
+            for keycode in keys.iter() {
+                self.typed_kb_buf.push_back(KeybufferEntry {
+                    keycode: *keycode,
+                    pressed: true,
+                    modifiers,
+                    translate: true,
+                });
+            }
+            for keycode in keys.iter().rev() {
+                self.typed_kb_buf.push_back(KeybufferEntry {
+                    keycode: *keycode,
+                    pressed: false,
+                    modifiers,
+                    translate: true,
+                });
+            }
+            queued += 1;
+        }
+
+        log::debug!("type_text(): queued {} characters", queued);
+        queued
+    }
+
+    /// Set the interval between keyboard events produced by type_text(), in microseconds.
+    /// Slow guest software may need a longer interval to avoid losing keys.
+    pub fn set_type_text_interval(&mut self, us: f64) {
+        self.type_text_interval = us.max(0.0);
+    }
+
+    /// Return the interval between keyboard events produced by type_text(), in microseconds.
+    pub fn type_text_interval(&self) -> f64 {
+        self.type_text_interval
+    }
+
+    /// Returns true if keyboard events are still waiting to be delivered to the guest.
+    pub fn keyboard_events_pending(&self) -> bool {
+        !self.kb_buf.is_empty() || !self.typed_kb_buf.is_empty()
+    }
+
+    /// Discard any keyboard events not yet delivered to the guest, such as the remainder
+    /// of text queued by type_text(). Keys that have already been pressed, such as a shift
+    /// key held for a typed capital, are still released.
+    pub fn clear_keyboard_events(&mut self) {
+        Machine::retain_pending_releases(&mut self.kb_buf);
+        Machine::retain_pending_releases(&mut self.typed_kb_buf);
+    }
+
+    /// Remove all events from a keyboard event queue except releases of keys pressed by events
+    /// that have already been delivered.
+    fn retain_pending_releases(buf: &mut VecDeque<KeybufferEntry>) {
+        let mut queued_presses = Vec::new();
+        buf.retain(|entry| {
+            if entry.pressed {
+                queued_presses.push(entry.keycode);
+                false
+            }
+            else if let Some(pos) = queued_presses.iter().position(|k| *k == entry.keycode) {
+                queued_presses.remove(pos);
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+
+    /// Start tracing video register writes to the specified logger. Only writes passing `filter`
+    /// are logged.
+    pub fn start_video_register_trace(&mut self, logger: TraceLogger, filter: VideoTraceFilter) {
+        self.cpu.bus_mut().start_video_trace(logger, filter);
+    }
+
+    /// Change the filter of an active video register trace.
+    pub fn set_video_register_trace_filter(&mut self, filter: VideoTraceFilter) {
+        if let Some(trace) = self.cpu.bus_mut().video_trace_mut() {
+            trace.set_filter(filter);
+        }
+    }
+
+    /// Stop tracing video register writes.
+    pub fn stop_video_register_trace(&mut self) {
+        self.cpu.bus_mut().stop_video_trace();
+    }
+
+    /// Start recording hard disk controller accesses. Completed accesses are also written to
+    /// `logger`. Does nothing if the machine has no hard disk controller.
+    pub fn start_hdc_access_log(&mut self, logger: TraceLogger) {
+        if let Some(hdc) = self.cpu.bus_mut().hdc_mut() {
+            hdc.set_access_log(true, logger);
+        }
+    }
+
+    /// Stop recording hard disk controller accesses.
+    pub fn stop_hdc_access_log(&mut self) {
+        if let Some(hdc) = self.cpu.bus_mut().hdc_mut() {
+            hdc.set_access_log(false, TraceLogger::None);
+        }
+    }
+
+    /// Remove and return the hard disk controller accesses completed since the last call.
+    pub fn drain_hdc_access_log(&mut self) -> Vec<HdcAccessEntry> {
+        match self.cpu.bus_mut().hdc_mut() {
+            Some(hdc) => hdc.drain_access_log(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Start playing a timed input script. Event delays are measured in emulated time from this
+    /// point. Any script already playing is replaced.
+    pub fn input_script_play(&mut self, script: InputScript) {
+        log::debug!("Starting input script with {} events", script.events().len());
+        self.input_script = Some(InputScriptPlayer::new(script));
+    }
+
+    /// Stop any input script being played. Keyboard events already queued are still delivered.
+    pub fn input_script_stop(&mut self) {
+        self.input_script = None;
+    }
+
+    /// Returns true if an input script has events left to play.
+    pub fn input_script_active(&self) -> bool {
+        self.input_script.is_some()
+    }
+
+    /// Advance the input script by the specified number of microseconds and queue any events
+    /// that became due.
+    fn input_script_advance(&mut self, us: f64) {
+        let actions = match &mut self.input_script {
+            Some(player) => player.advance(us),
+            None => return,
+        };
+
+        for action in actions {
+            match action {
+                ScriptAction::Press(keycode) | ScriptAction::Release(keycode) => {
+                    self.kb_buf.push_back(KeybufferEntry {
+                        keycode,
+                        pressed: matches!(action, ScriptAction::Press(_)),
+                        modifiers: KeyboardModifiers::default(),
+                        translate: true,
+                    });
+                }
+                ScriptAction::Tap(keycode) => {
+                    for pressed in [true, false] {
+                        self.kb_buf.push_back(KeybufferEntry {
+                            keycode,
+                            pressed,
+                            modifiers: KeyboardModifiers::default(),
+                            translate: true,
+                        });
+                    }
+                }
+                ScriptAction::Type(text) => {
+                    self.type_text(&text);
+                }
+            }
+        }
+
+        if self.input_script.as_ref().is_some_and(|player| player.finished()) {
+            log::debug!("Input script finished");
+            self.input_script = None;
+        }
+    }
+
+    /// Return the guest-controlled state of the emulated keyboard, or None if no keyboard is
+    /// present. The Model F has no lock indicators of its own, so its lock state is read from
+    /// the shift flags the BIOS maintains at 0040:0017.
+    pub fn keyboard_state(&mut self) -> Option<KeyboardState> {
+        let bios_flags = self.cpu.bus().peek_u8(0x417).unwrap_or(0);
+        let keyboard = self.cpu.bus_mut().keyboard_mut()?;
+        let mut state = keyboard.get_state();
+        if keyboard.get_type() == KeyboardType::ModelF {
+            state.leds = KeyboardLeds::from_bios_flags(bios_flags);
+        }
+        Some(state)
+    }
+
     /// Simulate the user pressing control-alt-delete.
     pub fn ctrl_alt_del(&mut self) {
         /*
@@ -877,102 +1660,824 @@ impl Machine {
         self.cpu.bus_mut().mouse_mut()
     }
 
-    pub fn bridge_serial_port(&mut self, port_num: usize, port_name: String) {
-        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
-            if let Err(e) = spc.bridge_port(port_num, port_name) {
-                log::error!("Failed to bridge serial port: {}", e);
-            }
+    /// Send a mouse update to the serial mouse, if present. If a movie is recording or playing,
+    /// the update is routed through the movie instead.
+    pub fn mouse_update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) {
+        if let Some(movie) = self.movie.as_mut().filter(|m| !m.passthrough()) {
+            movie.live_mouse(l_button_pressed, r_button_pressed, delta_x as i32, delta_y as i32);
+            return;
         }
-        else {
-            log::error!("No serial port controller present!");
+        if let Some(mouse) = self.cpu.bus_mut().mouse_mut() {
+            mouse.update(l_button_pressed, r_button_pressed, delta_x, delta_y);
         }
     }
 
-    pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
-        self.cpu.set_breakpoints(bp_list)
+    pub fn game_port_mut(&mut self) -> &mut Option<GamePort> {
+        self.cpu.bus_mut().game_port_mut()
     }
 
-    pub fn reset(&mut self) {
-        // TODO: Reload any program specified here?
-
-        // Clear any error state.
-        self.error = false;
-        self.error_str = None;
-
-        // Reset CPU.
-        self.cpu.reset();
-
-        // Clear RAM
-        self.cpu.bus_mut().clear();
+    /// Update a host gamepad axis. The value is mapped onto the emulated joystick axes through
+    /// the game port's configured mapping and calibration.
+    pub fn gamepad_axis(&mut self, axis: GamepadAxis, value: f64) {
+        if let Some(game_port) = self.cpu.bus_mut().game_port_mut() {
+            game_port.set_host_axis(axis, value);
+        }
+    }
 
-        // Reload BIOS ROM images
-        if self.load_bios {
-            Machine::install_roms(self.cpu.bus_mut(), &self.rom_manifest);
-            //self.rom_manager.copy_into_memory(self.cpu.bus_mut());
-            // Clear patch installation status
-            //self.rom_manager.reset_patches();
+    /// Update a host gamepad button.
+    pub fn gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        if let Some(game_port) = self.cpu.bus_mut().game_port_mut() {
+            game_port.set_host_button(button, pressed);
         }
+    }
 
-        // Reset all installed devices.
-        self.cpu.bus_mut().reset_devices();
-        self.events.push(MachineEvent::Reset);
+    /// Reset the machine and start playing back an input movie. If `record_from` is specified,
+    /// recording takes over from that frame, discarding the rest of the movie.
+    pub fn movie_play(&mut self, movie: InputMovie, record_from: Option<u64>) {
+        self.movie_start(MoviePlayer::playback(movie, record_from));
     }
 
-    pub fn set_reload_pending(&mut self, state: bool) {
-        self.reload_pending = state;
+    /// Reset the machine and start recording an input movie.
+    pub fn movie_record(&mut self, movie: InputMovie) {
+        self.movie_start(MoviePlayer::record(movie));
     }
 
-    #[inline]
-    /// Convert a count of CPU cycles to microseconds based on the current CPU clock
-    /// divisor and system crystal speed.
-    fn cpu_cycles_to_us(&self, cycles: u32) -> f64 {
-        let mhz = match self.cpu_factor {
-            ClockFactor::Divisor(n) => self.machine_desc.system_crystal / (n as f64),
-            ClockFactor::Multiplier(n) => self.machine_desc.system_crystal * (n as f64),
-        };
+    fn movie_start(&mut self, player: MoviePlayer) {
+        self.reset();
+        self.kb_buf.clear();
+        self.typed_kb_buf.clear();
+        self.movie_kb_buf.clear();
+        self.movie_kb_timer = 0.0;
+        self.last_video_frame = None;
+        self.movie = Some(player);
+    }
 
-        1.0 / mhz * cycles as f64
+    /// Stop any movie being recorded or played, returning it.
+    pub fn movie_stop(&mut self) -> Option<InputMovie> {
+        self.movie_kb_buf.clear();
+        self.movie.take().map(|m| m.into_movie())
     }
 
-    #[inline]
-    /// Convert a count of CPU cycles to system clock ticks based on the current CPU
-    /// clock divisor.
-    fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32),
-        }
+    /// Return the active movie's mode and the number of the next frame to be started.
+    pub fn movie_status(&self) -> Option<(MovieMode, u64)> {
+        self.movie.as_ref().map(|m| (m.mode(), m.frame()))
     }
 
-    #[allow(dead_code)]
-    #[inline]
-    /// Convert a count of system clock ticks to CPU cycles based on the current CPU
-    /// clock divisor.
-    fn system_ticks_to_cpu_cycles(&self, ticks: u32) -> u32 {
-        match self.cpu_factor {
-            ClockFactor::Divisor(n) => (ticks + (n as u32) - 1) / (n as u32),
-            ClockFactor::Multiplier(n) => ticks * (n as u32),
-        }
+    /// Return the active movie, if any.
+    pub fn movie(&self) -> Option<&InputMovie> {
+        self.movie.as_ref().map(|m| m.movie())
     }
 
-    pub fn get_checkpoint_string(&self, idx: usize) -> Option<String> {
-        if idx < self.rom_manifest.checkpoints.len() {
-            Some(self.rom_manifest.checkpoints[idx].desc.clone())
-        }
-        else {
-            None
-        }
+    /// Return the number of frames completed by the primary video card.
+    fn video_frame_count(&self) -> Option<u64> {
+        self.cpu.bus().primary_video().map(|video| video.get_frame_count())
     }
 
-    pub fn run(&mut self, cycle_target: u32, exec_control: &mut ExecutionControl) -> u64 {
-        let mut kb_event_processed = false;
+    /// Called when the primary video card starts a new frame while a movie is active.
+    fn movie_frame(&mut self) {
+        let changes = match &mut self.movie {
+            Some(movie) => movie.frame_input(),
+            None => return,
+        };
+        for keycode in changes.released {
+            self.movie_kb_buf.push_back(KeybufferEntry {
+                keycode,
+                pressed: false,
+                modifiers: KeyboardModifiers::default(),
+                translate: true,
+            });
+        }
+        for keycode in changes.pressed {
+            self.movie_kb_buf.push_back(KeybufferEntry {
+                keycode,
+                pressed: true,
+                modifiers: KeyboardModifiers::default(),
+                translate: true,
+            });
+        }
+        if let Some(m) = changes.mouse {
+            if let Some(mouse) = self.cpu.bus_mut().mouse_mut() {
+                mouse.update(m.left, m.right, m.delta_x as f64, m.delta_y as f64);
+            }
+        }
+    }
+
+    pub fn bridge_serial_port(&mut self, port_num: usize, port_name: String) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if let Err(e) = spc.bridge_port(port_num, port_name) {
+                log::error!("Failed to bridge serial port: {}", e);
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    /// Set the host bridge settings (baud override, flow control, buffer sizes) for the specified
+    /// serial port. Settings apply to any currently bridged host port and to later bridges.
+    pub fn set_serial_bridge_config(&mut self, port_num: usize, config: SerialBridgeConfig) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if port_num >= SERIAL_PORT_COUNT {
+                log::error!("Invalid serial port: {}", port_num);
+            }
+            else if let Err(e) = spc.set_bridge_config(port_num, config) {
+                log::error!("Failed to configure serial port bridge: {}", e);
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    /// Bridge the specified serial port to the emulator process's stdin and stdout, so a guest
+    /// serial console can be driven from a script or terminal.
+    pub fn bridge_serial_stdio(&mut self, port_num: usize, line_mode: StdioLineMode) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if port_num >= SERIAL_PORT_COUNT {
+                log::error!("Invalid serial port: {}", port_num);
+                return;
+            }
+            if let Err(e) = spc.bridge_stdio(port_num, line_mode) {
+                log::error!("Failed to bridge serial port to stdio: {}", e);
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    /// Bridge the specified serial port to an internal buffer, so that the frontend can exchange
+    /// bytes with the guest using serial_queue_bytes() and serial_take_tx_bytes().
+    pub fn bridge_serial_buffer(&mut self, port_num: usize) -> Result<(), Error> {
+        match self.cpu.bus_mut().serial_mut() {
+            Some(spc) if port_num < SERIAL_PORT_COUNT => {
+                spc.bridge_buffer(port_num);
+                Ok(())
+            }
+            Some(_) => Err(anyhow!("Invalid serial port: {}", port_num)),
+            None => Err(anyhow!("No serial port controller present")),
+        }
+    }
+
+    /// Queue bytes to be received by the specified serial port.
+    pub fn serial_queue_bytes(&mut self, port_num: usize, bytes: &[u8]) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if port_num < SERIAL_PORT_COUNT {
+                for byte in bytes {
+                    spc.queue_byte(port_num, *byte);
+                }
+            }
+        }
+    }
+
+    /// Return the bytes transmitted by the specified buffer-bridged serial port since the last call.
+    pub fn serial_take_tx_bytes(&mut self, port_num: usize) -> Vec<u8> {
+        match self.cpu.bus_mut().serial_mut() {
+            Some(spc) if port_num < SERIAL_PORT_COUNT => spc.take_tx_bytes(port_num),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Close the host port bridged to the specified serial port. The guest sees CTS and DSR
+    /// drop as if the remote device was unplugged.
+    pub fn unbridge_serial_port(&mut self, port_num: usize) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if port_num < SERIAL_PORT_COUNT && spc.unbridge_port(port_num) {
+                log::debug!("Unbridged serial port {}", port_num);
+            }
+            else {
+                log::warn!("Serial port {} is not bridged", port_num);
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    /// Attach a serial mouse to the running machine.
+    pub fn attach_serial_mouse(&mut self, serial_mouse_config: &SerialMouseConfig) -> Result<(), Error> {
+        self.cpu.bus_mut().attach_serial_mouse(serial_mouse_config)
+    }
+
+    /// Detach the serial mouse from the running machine. Returns true if a mouse was removed.
+    pub fn detach_serial_mouse(&mut self) -> bool {
+        self.cpu.bus_mut().detach_serial_mouse()
+    }
+
+    /// Replace all ungrouped breakpoints with the provided list. Breakpoints in named groups are
+    /// not affected.
+    pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
+        self.breakpoints.remove_group(None);
+        for bp in bp_list {
+            self.breakpoints.add(bp, None);
+        }
+        self.apply_breakpoints();
+    }
+
+    /// Add a breakpoint, optionally as a member of a named group, and return its id.
+    pub fn add_breakpoint(&mut self, bp: BreakPointType, group: Option<&str>) -> BreakpointId {
+        let id = self.breakpoints.add(bp, group);
+        self.apply_breakpoints();
+        id
+    }
+
+    /// Remove the breakpoint with the specified id. Returns false if there is no such breakpoint.
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let removed = self.breakpoints.remove(id);
+        self.apply_breakpoints();
+        removed
+    }
+
+    /// Remove all breakpoints in the named group. Returns the number of breakpoints removed.
+    pub fn remove_breakpoint_group(&mut self, group: &str) -> usize {
+        let removed = self.breakpoints.remove_group(Some(group));
+        self.apply_breakpoints();
+        removed
+    }
+
+    /// Enable or disable a breakpoint without deleting it. Returns false if there is no such
+    /// breakpoint.
+    pub fn enable_breakpoint(&mut self, id: BreakpointId, state: bool) -> bool {
+        let found = self.breakpoints.set_enabled(id, state);
+        self.apply_breakpoints();
+        found
+    }
+
+    /// Enable or disable all breakpoints in the named group. Returns the number of breakpoints in
+    /// the group.
+    pub fn enable_breakpoint_group(&mut self, group: &str, state: bool) -> usize {
+        let count = self.breakpoints.set_group_enabled(group, state);
+        self.apply_breakpoints();
+        count
+    }
+
+    /// Return all breakpoints with their group, enabled state and hit count.
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.breakpoints.list()
+    }
+
+    /// Return the names of all breakpoint groups.
+    pub fn breakpoint_groups(&self) -> Vec<String> {
+        self.breakpoints.groups()
+    }
+
+    /// Return the id of the breakpoint that was most recently hit, if it could be identified.
+    pub fn last_breakpoint(&self) -> Option<BreakpointId> {
+        self.last_breakpoint
+    }
+
+    pub fn reset_breakpoint_hits(&mut self) {
+        self.breakpoints.reset_hits();
+    }
+
+    fn apply_breakpoints(&mut self) {
+        self.cpu.set_breakpoints(self.breakpoints.active());
+    }
+
+    fn record_breakpoint_hit(&mut self) {
+        self.last_breakpoint = self
+            .cpu
+            .take_breakpoint_trigger()
+            .and_then(|trigger| self.breakpoints.record_hit(&trigger));
+        // A run to a breakpoint ends at the first breakpoint hit, whichever breakpoint it was.
+        if let Some(id) = self.run_to_breakpoint.take() {
+            self.remove_breakpoint(id);
+        }
+    }
+
+    /// Return the address of the character byte of the specified text cell on the primary video
+    /// card, taking the current start address into account. Returns None if no card is present or
+    /// the card is not in a text mode.
+    pub fn text_cell_address(&self, row: u32, col: u32) -> Option<u32> {
+        let card = self.cpu.bus().primary_video()?;
+        if card.is_graphics_mode() {
+            return None;
+        }
+        let (base, mask) = match card.get_video_type() {
+            VideoType::MDA => (0xB0000, 0x0FFF),
+            VideoType::CGA | VideoType::CompaqDual => (0xB8000, 0x3FFF),
+            #[cfg(feature = "ega")]
+            VideoType::EGA => (0xB8000, 0x7FFF),
+            #[cfg(feature = "vga")]
+            VideoType::VGA => (0xB8000, 0x7FFF),
+        };
+        let columns = if card.is_40_columns() { 40 } else { 80 };
+        let offset = (card.get_start_address() as u32 + row * columns + col) * 2;
+        Some(base + (offset & mask))
+    }
+
+    pub fn reset(&mut self) {
+        // TODO: Reload any program specified here?
+
+        // Non-volatile memory survives a reset, so this is a good time to persist it.
+        self.flush_nvram_logged();
+
+        // Clear any error state.
+        self.error = false;
+        self.error_str = None;
+
+        // Reset CPU.
+        self.cpu.reset();
+
+        // Clear RAM
+        self.cpu.bus_mut().clear();
+
+        // Reload BIOS ROM images
+        if self.load_bios {
+            Machine::install_roms(self.cpu.bus_mut(), &self.rom_manifest);
+            //self.rom_manager.copy_into_memory(self.cpu.bus_mut());
+            // Clear patch installation status
+            //self.rom_manager.reset_patches();
+        }
+
+        // Reset all installed devices.
+        self.cpu.bus_mut().reset_devices();
+        self.bios_clock_valid = false;
+        self.clear_rewind();
+        self.clear_snapshot_history();
+        self.events.push(MachineEvent::Reset);
+    }
+
+    /// Reset the machine without clearing RAM. The BIOS warm boot flag at 0040:0072 is set so
+    /// that the BIOS skips its memory test, as it would after Ctrl-Alt-Del. Memory-resident
+    /// programs that survive a warm boot on real hardware will survive this reset.
+    pub fn warm_reset(&mut self) {
+        self.flush_nvram_logged();
+
+        self.error = false;
+        self.error_str = None;
+
+        self.cpu.reset();
+
+        // Reload BIOS ROM images.
+        if self.load_bios {
+            Machine::install_roms(self.cpu.bus_mut(), &self.rom_manifest);
+        }
+
+        self.cpu.bus_mut().reset_devices();
+        self.bios_clock_valid = false;
+
+        _ = self
+            .cpu
+            .bus_mut()
+            .write_u16(BIOS_WARM_BOOT_FLAG_ADDRESS, BIOS_WARM_BOOT_FLAG, 0);
+        self.clear_rewind();
+        self.clear_snapshot_history();
+        self.events.push(MachineEvent::Reset);
+    }
+
+    pub fn set_reload_pending(&mut self, state: bool) {
+        self.reload_pending = state;
+    }
+
+    #[inline]
+    /// Convert a count of CPU cycles to microseconds based on the current CPU clock
+    /// divisor and system crystal speed.
+    fn cpu_cycles_to_us(&self, cycles: u32) -> f64 {
+        1.0 / self.get_cpu_mhz() * cycles as f64
+    }
+
+    #[inline]
+    /// Convert a count of CPU cycles to system clock ticks based on the current CPU
+    /// clock divisor. If the CPU clock is not a multiple of the system clock, fractional ticks
+    /// are carried over to the next call by the bus.
+    fn cpu_cycles_to_system_ticks(&mut self, cycles: u32) -> u32 {
+        self.cpu.bus_mut().advance_system_ticks(cycles)
+    }
+
+    /// Return the time in microseconds until the next scheduled event that could wake a halted
+    /// CPU: a timer channel 0 interrupt or a device deadline.
+    fn next_wake_event_us(&self) -> Option<f64> {
+        self.cpu.bus().next_device_deadline()
+    }
+
+    /// Return the number of cycles a halted CPU can execute before the next event that could
+    /// wake it. Devices without a schedule, such as the keyboard, are serviced at least every
+    /// HALT_IDLE_MAX_CYCLES.
+    fn halt_idle_cycles(&self) -> u32 {
+        match self.next_wake_event_us() {
+            Some(us) => ((us * self.get_cpu_mhz()) as u32).clamp(DEFAULT_HALT_CYCLES, HALT_IDLE_MAX_CYCLES),
+            None => DEFAULT_HALT_CYCLES,
+        }
+    }
+
+    /// If the CPU is halted waiting for an interrupt, return the time in microseconds until the
+    /// next scheduled event that could wake it. A frontend may sleep the host thread this long
+    /// instead of polling an idle machine.
+    /// Returns None if the CPU is running, cannot be woken by an interrupt, or halt idling is off.
+    pub fn halt_idle_time(&self) -> Option<f64> {
+        if !self.halt_idle || !self.cpu.is_halted() || !self.cpu.interrupts_enabled() {
+            return None;
+        }
+        self.next_wake_event_us()
+    }
+
+    /// Enable or disable halt idling. When enabled, a halted CPU is run to the next timer interrupt
+    /// or scheduled device event in large steps, which greatly reduces host CPU usage while the
+    /// guest is idle (for example, sitting at the DOS prompt with an idle driver loaded).
+    pub fn set_halt_idle(&mut self, state: bool) {
+        self.halt_idle = state;
+        if !state {
+            self.cpu.set_halt_cycles(DEFAULT_HALT_CYCLES);
+        }
+    }
+
+    /// Configure synchronization of guest time with the host clock. When enabled, any real time
+    /// clock is set immediately, and the BIOS tick count is set when the BIOS next bootstraps
+    /// the operating system.
+    pub fn set_host_clock(&mut self, config: HostClockConfig) {
+        self.cpu.set_interrupt_hook(BIOS_BOOTSTRAP_INTERRUPT, config.enabled);
+        self.host_clock = config;
+        self.host_clock_timer = 0.0;
+        if self.host_clock.enabled {
+            self.sync_host_clock();
+        }
+    }
+
+    /// Install a source of host time to use in place of the system clock, or restore the system
+    /// clock with None.
+    pub fn set_host_time_source(&mut self, source: Option<HostTimeSource>) {
+        self.host_time_source = source;
+    }
+
+    /// Set guest time sources from the host's local time. The BIOS tick count is only written
+    /// once the BIOS has initialized its data area.
+    pub fn sync_host_clock(&mut self) {
+        let time = match &mut self.host_time_source {
+            Some(source) => source(),
+            None => ClockTime::host_now(),
+        };
+        log::debug!("Setting guest time from host clock: {:?}", time);
+
+        let bios_clock_valid = self.bios_clock_valid;
+        let bus = self.cpu.bus_mut();
+        if let Some(rtc) = bus.rtc_mut() {
+            rtc.set_time(&time);
+        }
+
+        if bios_clock_valid {
+            let ticks = time.bios_ticks();
+            let old_lo = bus.read_u16(BIOS_TIMER_COUNT_ADDRESS, 0).map_or(0, |(w, _)| w);
+            let old_hi = bus.read_u16(BIOS_TIMER_COUNT_ADDRESS + 2, 0).map_or(0, |(w, _)| w);
+            let old_ticks = (old_hi as u32) << 16 | old_lo as u32;
+
+            // If the host has passed midnight but the guest hasn't, set the rollover flag so that
+            // DOS advances its date.
+            if old_ticks > ticks && old_ticks - ticks > BIOS_TICKS_PER_DAY / 2 {
+                _ = bus.write_u8(BIOS_TIMER_OVERFLOW_ADDRESS, 1, 0);
+            }
+            _ = bus.write_u16(BIOS_TIMER_COUNT_ADDRESS, ticks as u16, 0);
+            _ = bus.write_u16(BIOS_TIMER_COUNT_ADDRESS + 2, (ticks >> 16) as u16, 0);
+        }
+    }
+
+    /// Persist device non-volatile memory to image files in the specified directory, which should
+    /// be unique to the machine configuration. Any existing images are loaded immediately. If the
+    /// host clock is enabled, it takes precedence over clock counters loaded from NVRAM.
+    pub fn set_nvram_dir(&mut self, dir: PathBuf) {
+        let mut store = NvramStore::new(dir);
+        let loaded = store.load(self.cpu.bus_mut().nvram_devices_mut());
+        log::debug!("Loaded {} NVRAM image(s) from {:?}", loaded, store.dir());
+        self.nvram = Some(store);
+        if self.host_clock.enabled {
+            self.sync_host_clock();
+        }
+    }
+
+    /// Return the directory NVRAM images are persisted to, if set.
+    pub fn nvram_dir(&self) -> Option<&Path> {
+        self.nvram.as_ref().map(|store| store.dir())
+    }
+
+    /// Write any device non-volatile memory that has changed to its image file. The frontend
+    /// should call this before exiting. Does nothing if no NVRAM directory has been set.
+    pub fn flush_nvram(&mut self) -> Result<(), Error> {
+        if let Some(store) = &mut self.nvram {
+            let written = store.save(self.cpu.bus().nvram_devices())?;
+            if written > 0 {
+                log::debug!("Wrote {} NVRAM image(s) to {:?}", written, store.dir());
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_nvram_logged(&mut self) {
+        if let Err(e) = self.flush_nvram() {
+            log::error!("Failed to save NVRAM: {}", e);
+        }
+    }
+
+    /// Enable or disable watching the interrupt vector table. While enabled, each change to a
+    /// vector is logged along with the CS:IP of the instruction that made it, and the most recent
+    /// changes are available from vector_changes().
+    pub fn set_vector_watch(&mut self, state: bool) {
+        self.vector_watch = state;
+        self.cpu.bus_mut().set_ivt_watch(state);
+        if !state {
+            self.vector_log.clear();
+        }
+    }
+
+    /// Return the most recent interrupt vector changes, oldest first.
+    pub fn vector_changes(&self) -> &VecDeque<VectorChange> {
+        &self.vector_log
+    }
+
+    /// Start tracing delivered interrupts. For each interrupt, the time the device raised its IR
+    /// line, the PIC raised INTR and the CPU acknowledged it are recorded in system ticks. The most
+    /// recent interrupts are available from irq_trace(), and each is also written to `logger`.
+    pub fn start_irq_trace(&mut self, logger: TraceLogger) {
+        self.irq_trace_logger.flush();
+        self.irq_trace_logger = logger;
+        self.irq_trace_enabled = true;
+        self.irq_trace.clear();
+        self.cpu.bus_mut().set_irq_trace(true);
+    }
+
+    /// Stop tracing delivered interrupts.
+    pub fn stop_irq_trace(&mut self) {
+        self.irq_trace_logger.flush();
+        self.irq_trace_logger = TraceLogger::None;
+        self.irq_trace_enabled = false;
+        self.cpu.bus_mut().set_irq_trace(false);
+    }
+
+    /// Return the most recently delivered interrupts, oldest first.
+    pub fn irq_trace(&self) -> &VecDeque<IrqTraceEntry> {
+        &self.irq_trace
+    }
+
+    /// Collect interrupts delivered since the last call into the interrupt trace.
+    fn update_irq_trace(&mut self) {
+        for entry in self.cpu.bus_mut().take_irq_trace() {
+            self.irq_trace_logger.println(format!(
+                "[{:12}] IRQ{:<2} INT {:02X} intr:+{} inta:+{} total:{}",
+                entry.request_ticks,
+                entry.irq,
+                entry.vector,
+                entry.intr_latency(),
+                entry.inta_latency(),
+                entry.latency()
+            ));
+            if self.irq_trace.len() == IRQ_TRACE_LEN {
+                self.irq_trace.pop_front();
+            }
+            self.irq_trace.push_back(entry);
+        }
+    }
+
+    /// Enable or disable profiling of cycles spent executing from ROM versus RAM. Enabling
+    /// profiling starts a new profile.
+    pub fn set_rom_profiling(&mut self, state: bool) {
+        self.rom_profile = state.then(RomProfile::default);
+    }
+
+    /// Return the current ROM profile, if profiling is enabled.
+    pub fn rom_profile(&self) -> Option<&RomProfile> {
+        self.rom_profile.as_ref()
+    }
+
+    /// Clear the counters of the current ROM profile.
+    pub fn reset_rom_profile(&mut self) {
+        if let Some(profile) = &mut self.rom_profile {
+            profile.reset();
+        }
+    }
+
+    /// Enable or disable profiling of the cycles executed per instruction address or memory
+    /// block. Enabling profiling starts a new profile.
+    pub fn set_cycle_profiling(&mut self, state: bool, granularity: ProfileGranularity) {
+        self.cycle_profile = state.then(|| CycleProfile::new(granularity));
+    }
+
+    /// Return the current cycle profile, if profiling is enabled.
+    pub fn cycle_profile(&self) -> Option<&CycleProfile> {
+        self.cycle_profile.as_ref()
+    }
+
+    /// Clear the counters of the current cycle profile.
+    pub fn reset_cycle_profile(&mut self) {
+        if let Some(profile) = &mut self.cycle_profile {
+            profile.reset();
+        }
+    }
+
+    /// Enable or disable recording of the event timeline. Enabling recording starts a new
+    /// timeline that retains up to `capacity` events.
+    pub fn set_timeline(&mut self, state: bool, capacity: usize) {
+        self.timeline = state.then(|| Timeline::new(capacity));
+    }
+
+    /// Return the event timeline, if recording is enabled. Event ticks are comparable with
+    /// system_ticks().
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    /// Discard the events recorded in the timeline, if recording is enabled.
+    pub fn clear_timeline(&mut self) {
+        if let Some(timeline) = &mut self.timeline {
+            timeline.clear();
+        }
+    }
+
+    /// Enable or disable recording of step-back history. Enabling recording starts a new history
+    /// that retains up to `capacity` instructions.
+    pub fn set_rewind(&mut self, state: bool, capacity: usize) {
+        self.rewind = state.then(|| RewindBuffer::new(capacity));
+        self.cpu.bus_mut().set_write_journal(state);
+    }
+
+    /// Return the step-back history, if recording is enabled.
+    pub fn rewind(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Discard the step-back history, if recording is enabled.
+    pub fn clear_rewind(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+            _ = self.cpu.bus_mut().take_write_journal();
+        }
+    }
+
+    /// Enable or disable taking periodic snapshots for run_back_to(). Enabling starts a new history
+    /// that takes a snapshot every `interval` seconds of emulated time, and retains up to
+    /// `capacity` of the most recent snapshots.
+    pub fn set_snapshot_history(&mut self, state: bool, interval: f64, capacity: usize) {
+        let ticks = (interval * self.machine_desc.system_crystal * 1_000_000.0) as u64;
+        self.snapshot_history = state.then(|| SnapshotHistory::new(ticks, capacity));
+    }
+
+    /// Return the snapshot history, if periodic snapshots are enabled.
+    pub fn snapshot_history(&self) -> Option<&SnapshotHistory> {
+        self.snapshot_history.as_ref()
+    }
+
+    /// Discard the snapshot history, if periodic snapshots are enabled.
+    pub fn clear_snapshot_history(&mut self) {
+        if let Some(history) = &mut self.snapshot_history {
+            history.clear();
+        }
+    }
+
+    /// Take a snapshot for the snapshot history, if one is due. Memory pages are shared with the
+    /// previous snapshot where they have not been written since.
+    fn update_snapshot_history(&mut self) {
+        let history = match &mut self.snapshot_history {
+            Some(history) if history.due(self.system_ticks) => history,
+            _ => return,
+        };
+        let snapshot = MachineSnapshot::capture(
+            &mut self.cpu,
+            self.cpu_cycles,
+            self.cpu_instructions,
+            self.system_ticks,
+            history.last(),
+        );
+        history.push(snapshot);
+    }
+
+    /// Go back `seconds` of emulated time and run until the specified breakpoint is hit. The
+    /// machine is restored from the nearest snapshot taken at or before that time and execution
+    /// is resumed with a temporary breakpoint, which is removed when execution next stops at a
+    /// breakpoint. Snapshots taken after the restored one are discarded, as the re-run replaces
+    /// them. Returns the id of the temporary breakpoint.
+    pub fn run_back_to(
+        &mut self,
+        seconds: f64,
+        bp: BreakPointType,
+        exec_control: &mut ExecutionControl,
+    ) -> Result<BreakpointId, Error> {
+        let ticks = (seconds * self.machine_desc.system_crystal * 1_000_000.0) as u64;
+        let target = self.system_ticks.saturating_sub(ticks);
+
+        let mut history = match self.snapshot_history.take() {
+            Some(history) => history,
+            None => return Err(anyhow!("Snapshot history is not enabled")),
+        };
+        let result = match history.rewind_to(target) {
+            Some(snapshot) => {
+                if snapshot.system_ticks > target {
+                    log::debug!(
+                        "Snapshot history does not go back {} seconds, using oldest snapshot.",
+                        seconds
+                    );
+                }
+                self.restore_snapshot(snapshot)
+            }
+            None => Err(anyhow!("No snapshots have been taken")),
+        };
+        self.snapshot_history = Some(history);
+        result?;
+
+        if let Some(id) = self.run_to_breakpoint.take() {
+            self.breakpoints.remove(id);
+        }
+        let id = self.add_breakpoint(bp, Some(RUN_TO_BREAKPOINT_GROUP));
+        self.run_to_breakpoint = Some(id);
+
+        self.cpu.clear_breakpoint_flag();
+        exec_control.clear_run_targets();
+        exec_control.state = ExecutionState::Paused;
+        exec_control.set_op(ExecutionOperation::Run);
+        Ok(id)
+    }
+
+    /// Step execution back by one instruction, restoring the CPU registers and undoing the memory
+    /// writes made by the instruction. Device state is not rewound. Returns false if there is no
+    /// history to step back through.
+    pub fn step_back(&mut self) -> bool {
+        let frame = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(frame) => frame,
+            None => {
+                log::debug!("Step back requested with no step-back history.");
+                return false;
+            }
+        };
+
+        if !frame.journal.io.is_empty() {
+            log::debug!(
+                "Stepping back over {} IO writes. Device state is not rewound.",
+                frame.journal.io.len()
+            );
+        }
+        self.cpu.bus_mut().undo_writes(&frame.journal);
+        self.cpu.restore_state(&frame.regs, frame.halted);
+        true
+    }
+
+    /// Return the state of the specified device as structured JSON, or None if the device is not
+    /// installed or does not support state capture.
+    pub fn device_state(&self, device: IoDeviceType) -> Option<serde_json::Value> {
+        self.bus().device_state(device)
+    }
+
+    /// Return the state of every device that supports state capture, keyed by device type.
+    pub fn device_states(&self) -> BTreeMap<String, serde_json::Value> {
+        self.bus().device_states()
+    }
+
+    /// Restore the state of the specified device from structured JSON.
+    pub fn load_device_state(&mut self, device: IoDeviceType, state: serde_json::Value) -> Result<(), Error> {
+        self.bus_mut().load_device_state(device, state)
+    }
+
+    /// Return the total number of CPU cycles spent halted.
+    pub fn halted_cycles(&self) -> u64 {
+        self.halted_cycles
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    /// Convert a count of system clock ticks to CPU cycles based on the current CPU
+    /// clock divisor.
+    fn system_ticks_to_cpu_cycles(&self, ticks: u32) -> u32 {
+        self.cpu_factor.ticks_to_cycles(ticks, self.machine_desc.system_crystal)
+    }
+
+    pub fn get_checkpoint_string(&self, idx: usize) -> Option<String> {
+        if idx < self.rom_manifest.checkpoints.len() {
+            Some(self.rom_manifest.checkpoints[idx].desc.clone())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Execute the specified number of instructions and pause, without returning in between.
+    /// Time spent halted does not count towards the instructions executed. Returns the number of
+    /// CPU steps taken, as run() does, including steps spent halted.
+    pub fn run_instructions(&mut self, count: u64, exec_control: &mut ExecutionControl) -> u64 {
+        exec_control.set_op(ExecutionOperation::RunInstructions(count));
+        self.run_until_paused(exec_control)
+    }
+
+    /// Run until the specified number of video frames have begun and pause, without returning in
+    /// between. Returns the number of instructions executed.
+    pub fn run_frames(&mut self, count: u64, exec_control: &mut ExecutionControl) -> u64 {
+        exec_control.set_op(ExecutionOperation::RunFrames(count));
+        self.run_until_paused(exec_control)
+    }
+
+    fn run_until_paused(&mut self, exec_control: &mut ExecutionControl) -> u64 {
+        let mut instructions = self.run(RUN_COUNT_SLICE_CYCLES, exec_control);
+        while let ExecutionState::Running = exec_control.state {
+            if !matches!(self.state, MachineState::On) || self.error {
+                break;
+            }
+            instructions += self.run(RUN_COUNT_SLICE_CYCLES, exec_control);
+        }
+        instructions
+    }
+
+    pub fn run(&mut self, cycle_target: u32, exec_control: &mut ExecutionControl) -> u64 {
+        let mut kb_event_processed = false;
         let mut skip_breakpoint = false;
         let mut instr_count = 0;
 
         // Update cpu factor.
-        let new_factor = self.next_cpu_factor;
-        self.cpu_factor = new_factor;
-        self.bus_mut().set_cpu_factor(new_factor);
+        self.update_cpu_factor();
 
         // Don't run this iteration if we're pending a ROM reload
         if self.reload_pending {
@@ -980,11 +2485,20 @@ impl Machine {
         }
 
         // Was reset requested?
-        if let ExecutionOperation::Reset = exec_control.peek_op() {
-            _ = exec_control.get_op(); // Clear the reset operation
-            self.reset();
-            exec_control.state = ExecutionState::Paused;
-            return 0;
+        match exec_control.peek_op() {
+            ExecutionOperation::Reset => {
+                _ = exec_control.get_op(); // Clear the reset operation
+                self.reset();
+                exec_control.state = ExecutionState::Paused;
+                return 0;
+            }
+            ExecutionOperation::WarmReset => {
+                _ = exec_control.get_op(); // Clear the reset operation
+                self.warm_reset();
+                exec_control.state = ExecutionState::Paused;
+                return 0;
+            }
+            _ => {}
         }
 
         let mut step_over = false;
@@ -1010,6 +2524,36 @@ impl Machine {
                         exec_control.state = ExecutionState::Running;
                         cycle_target
                     }
+                    ExecutionOperation::FrameAdvance => {
+                        if !self.begin_frame_advance(exec_control, 1) {
+                            return 0;
+                        }
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::RunFrames(count) => {
+                        if !self.begin_frame_advance(exec_control, count) {
+                            return 0;
+                        }
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::RunInstructions(count) => {
+                        exec_control.instructions_remaining = Some(count);
+                        exec_control.state = ExecutionState::Running;
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::StepOut => {
+                        exec_control.step_out_frame = Some(self.cpu.stack_frame());
+                        exec_control.state = ExecutionState::Running;
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::StepBack => {
+                        self.step_back();
+                        return 0;
+                    }
                     _ => return 0,
                 }
             }
@@ -1054,6 +2598,47 @@ impl Machine {
                         exec_control.state = ExecutionState::Running;
                         cycle_target
                     }
+                    ExecutionOperation::FrameAdvance => {
+                        if !self.begin_frame_advance(exec_control, 1) {
+                            return 0;
+                        }
+                        // Clear CPU's breakpoint flag
+                        self.cpu.clear_breakpoint_flag();
+                        // Skip current breakpoint, if any
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::RunFrames(count) => {
+                        if !self.begin_frame_advance(exec_control, count) {
+                            return 0;
+                        }
+                        self.cpu.clear_breakpoint_flag();
+                        skip_breakpoint = true;
+                        cycle_target
+                    }
+                    ExecutionOperation::RunInstructions(count) => {
+                        self.cpu.clear_breakpoint_flag();
+                        skip_breakpoint = true;
+                        exec_control.instructions_remaining = Some(count);
+                        exec_control.state = ExecutionState::Running;
+                        cycle_target
+                    }
+                    ExecutionOperation::StepOut => {
+                        self.cpu.clear_breakpoint_flag();
+                        skip_breakpoint = true;
+                        exec_control.step_out_frame = Some(self.cpu.stack_frame());
+                        exec_control.state = ExecutionState::Running;
+                        cycle_target
+                    }
+                    ExecutionOperation::StepBack => {
+                        if self.step_back() {
+                            // Clear CPU's breakpoint flag
+                            self.cpu.clear_breakpoint_flag();
+                            // Transition to ExecutionState::Paused
+                            exec_control.state = ExecutionState::Paused;
+                        }
+                        return 0;
+                    }
                     _ => return 0,
                 }
             }
@@ -1088,7 +2673,10 @@ impl Machine {
                 break;
             }
 
+            self.update_snapshot_history();
+
             let flat_address = self.cpu.flat_ip();
+            let (instruction_cs, instruction_ip) = self.cpu.instruction_csip();
 
             // Match checkpoints
             if self.cpu.bus().get_flags(flat_address as usize) & MEM_CP_BIT != 0 {
@@ -1119,6 +2707,27 @@ impl Machine {
 
             let mut step_over_target = None;
 
+            // If the CPU is halted, we can skip ahead to the next timer interrupt instead of stepping
+            // through the idle period a few cycles at a time.
+            let halted = self.cpu.is_halted();
+            if halted && self.halt_idle {
+                let budget = self
+                    .halt_idle_cycles()
+                    .min(cycle_target_adj - cycles_elapsed)
+                    .max(DEFAULT_HALT_CYCLES);
+                self.cpu.set_halt_cycles(budget);
+            }
+
+            // Capture the state before this instruction executes for step-back. Writes journaled
+            // outside of the run loop, such as memory edits from the debugger, are discarded.
+            let rewind_start = if self.rewind.is_some() {
+                _ = self.cpu.bus_mut().take_write_journal();
+                Some((self.cpu.rewind_state(), self.cpu_cycles))
+            }
+            else {
+                None
+            };
+
             match self.cpu.step(skip_breakpoint) {
                 Ok((step_result, step_cycles)) => match step_result {
                     StepResult::Normal => {
@@ -1129,6 +2738,8 @@ impl Machine {
                         step_over_target = Some(target);
                     }
                     StepResult::BreakpointHit => {
+                        self.record_breakpoint_hit();
+                        exec_control.clear_run_targets();
                         exec_control.state = ExecutionState::BreakpointHit;
                         return 1;
                     }
@@ -1153,7 +2764,18 @@ impl Machine {
 
             skip_breakpoint = false;
 
-            if cpu_cycles > 200 {
+            if let Some(profile) = &mut self.rom_profile {
+                let rom = self.cpu.bus().get_flags(flat_address as usize) & MEM_ROM_BIT != 0;
+                profile.add(rom, self.cpu.current_interrupt(), cpu_cycles);
+            }
+            if let Some(profile) = &mut self.cycle_profile {
+                profile.add(instruction_cs, instruction_ip, cpu_cycles);
+            }
+
+            if halted {
+                self.halted_cycles += cpu_cycles as u64;
+            }
+            else if cpu_cycles > 200 {
                 log::warn!("CPU instruction took too long! Cycles: {}", cpu_cycles);
             }
 
@@ -1171,6 +2793,8 @@ impl Machine {
             // devices for 3 cycles on NOP, for example?
             let (intr, _) = self.run_devices(cpu_cycles, &mut kb_event_processed);
             self.cpu.set_intr(intr);
+            let nmi = self.nmi || std::mem::take(&mut self.nmi_pulse) || self.cpu.bus().parity_nmi();
+            self.cpu.set_nmi(nmi);
 
             // Finish instruction after running devices (RNI)
             if let Err(err) = self.cpu.step_finish() {
@@ -1200,6 +2824,7 @@ impl Machine {
                                     StepResult::BreakpointHit => {
                                         // We can hit an 'inner' breakpoint while stepping over. This is fine, and ends the step
                                         // over operation at the breakpoint.
+                                        self.record_breakpoint_hit();
                                         exec_control.state = ExecutionState::BreakpointHit;
                                         return instr_count;
                                     }
@@ -1247,13 +2872,81 @@ impl Machine {
                 }
             }
 
+            if let (Some(rewind), Some((regs, cycle))) = (&mut self.rewind, rewind_start) {
+                let journal = self.cpu.bus_mut().take_write_journal();
+                // Idle cycles spent halted change nothing that can be stepped back through.
+                if !(halted && self.cpu.is_halted() && journal.is_empty()) {
+                    rewind.push(RewindFrame {
+                        regs,
+                        halted,
+                        cycle,
+                        journal,
+                    });
+                }
+            }
+
+            if self.vector_watch {
+                for change in self.cpu.bus_mut().take_vector_changes() {
+                    if self.vector_log.len() == VECTOR_LOG_LEN {
+                        self.vector_log.pop_front();
+                    }
+                    self.vector_log.push_back(change);
+                }
+            }
+
+            if self.irq_trace_enabled {
+                self.update_irq_trace();
+            }
+
             if let Some(event) = self.cpu.get_service_event() {
                 match event {
                     ServiceEvent::TriggerPITLogging => {
                         log::debug!("PIT logging has been triggered.");
                         self.pit_data.logging_triggered = true;
                     }
+                    ServiceEvent::InterruptHook(BIOS_BOOTSTRAP_INTERRUPT) if self.host_clock.enabled => {
+                        // The BIOS has finished POST, so its data area is valid.
+                        self.bios_clock_valid = true;
+                        self.sync_host_clock();
+                    }
+                    ServiceEvent::InterruptHook(_) => {}
+                }
+            }
+
+            // Watch for frame boundaries if a movie or frame advance is active.
+            if self.movie.is_some() || exec_control.frame_advance_target.is_some() {
+                let frame = self.video_frame_count();
+                if frame != self.last_video_frame {
+                    self.last_video_frame = frame;
+                    self.movie_frame();
+                }
+                if let (Some(frame), Some(target)) = (frame, exec_control.frame_advance_target) {
+                    if frame >= target {
+                        exec_control.frame_advance_target = None;
+                        exec_control.state = ExecutionState::Paused;
+                        break;
+                    }
+                }
+            }
+
+            // Pause once the procedure or interrupt handler being stepped out of has returned.
+            if let Some((ss, sp)) = exec_control.step_out_frame {
+                if self.cpu.returned_from(ss, sp) {
+                    exec_control.step_out_frame = None;
+                    exec_control.state = ExecutionState::Paused;
+                    break;
+                }
+            }
+
+            // Pause once the requested number of instructions have executed. A halted CPU
+            // retires no instructions, so steps spent halted are not counted.
+            if let (Some(remaining), false) = (exec_control.instructions_remaining, halted) {
+                if remaining <= 1 {
+                    exec_control.instructions_remaining = None;
+                    exec_control.state = ExecutionState::Paused;
+                    break;
                 }
+                exec_control.instructions_remaining = Some(remaining - 1);
             }
         }
 
@@ -1263,6 +2956,23 @@ impl Machine {
         instr_count
     }
 
+    /// Set up a frame advance operation, running until the primary video card completes the
+    /// specified number of frames. Returns false if there is no video card to count frames with.
+    fn begin_frame_advance(&mut self, exec_control: &mut ExecutionControl, frames: u64) -> bool {
+        match self.video_frame_count() {
+            Some(frame) => {
+                self.last_video_frame = Some(frame);
+                exec_control.frame_advance_target = Some(frame + frames);
+                exec_control.state = ExecutionState::Running;
+                true
+            }
+            None => {
+                log::warn!("Frame advance requested, but no video card is present.");
+                false
+            }
+        }
+    }
+
     /// Run the other devices in the machine for the specified number of cpu cycles.
     /// CPU cycles drive the timing of the rest of the system; they will be converted into the
     /// appropriate timing units for other devices as needed.
@@ -1276,6 +2986,18 @@ impl Machine {
         // Convert cycles into system clock ticks
         let sys_ticks = self.cpu_cycles_to_system_ticks(cpu_cycles);
 
+        if self.input_script.is_some() {
+            self.input_script_advance(us);
+        }
+
+        if let (true, Some(interval)) = (self.host_clock.enabled, self.host_clock.correction_interval) {
+            self.host_clock_timer += us;
+            if self.host_clock_timer >= interval * 1_000_000.0 {
+                self.host_clock_timer = 0.0;
+                self.sync_host_clock();
+            }
+        }
+
         // Process a keyboard event once per frame.
         // A reasonably fast typist can generate two events in a single 16ms frame, and to the virtual cpu
         // they then appear to happen instantaneously. The PPI has no buffer, so one scancode gets lost.
@@ -1290,6 +3012,25 @@ impl Machine {
             }
         }
 
+        // Typed text is paced in emulated time at the configured interval.
+        if !self.typed_kb_buf.is_empty() {
+            self.typed_kb_timer += us;
+            if kb_event_opt.is_none() && self.typed_kb_timer >= self.type_text_interval {
+                self.typed_kb_timer = 0.0;
+                kb_event_opt = self.typed_kb_buf.pop_front();
+            }
+        }
+
+        // Movie input is paced in emulated time rather than per frontend update, so that playback
+        // does not depend on host timing.
+        if !self.movie_kb_buf.is_empty() {
+            self.movie_kb_timer += us;
+            if kb_event_opt.is_none() && self.movie_kb_timer >= MOVIE_KB_INTERVAL_US {
+                self.movie_kb_timer = 0.0;
+                kb_event_opt = self.movie_kb_buf.pop_front();
+            }
+        }
+
         // Run devices.
         // We send the IO bus the elapsed time in us, and a mutable reference to the PIT channel #2 ring buffer
         // so that we can collect output from the timer.
@@ -1303,15 +3044,19 @@ impl Machine {
 
         if let Some(event) = device_event {
             match event {
-                DeviceEvent::DramRefreshUpdate(dma_counter, dma_counter_val, _dma_tick_adjust) => {
+                DeviceEvent::DramRefreshUpdate(dma_counter, dma_counter_val, _dma_tick_adjust)
+                    if self.dram_refresh_from_pit() =>
+                {
+                    self.dram_refresh_period = Some(dma_counter);
                     self.cpu.set_option(CpuOption::SimulateDramRefresh(
                         true,
                         self.timer_ticks_to_cpu_cycles(dma_counter),
                         self.timer_ticks_to_cpu_cycles(dma_counter_val), //self.timer_ticks_to_cpu_cycles(0)
                     ))
                 }
-                DeviceEvent::DramRefreshEnable(state) if state == false => {
+                DeviceEvent::DramRefreshEnable(state) if !state && self.dram_refresh_from_pit() => {
                     // Stop refresh
+                    self.dram_refresh_period = None;
                     self.cpu.set_option(CpuOption::SimulateDramRefresh(false, 0, 0));
                 }
                 _ => {}
@@ -1327,26 +3072,22 @@ impl Machine {
         let intr = self.cpu.bus_mut().pic_mut().as_ref().unwrap().query_interrupt_line();
 
         self.system_ticks += sys_ticks as u64;
+
+        if let Some(timeline) = &mut self.timeline {
+            let address = self.cpu.get_csip();
+            timeline.sample(self.cpu.bus_mut(), self.system_ticks, address);
+        }
         (intr, sys_ticks)
     }
 
     fn timer_ticks_to_cpu_cycles(&self, timer_ticks: u16) -> u32 {
-        let timer_multiplier = if let Some(_timer_crystal) = self.machine_desc.timer_crystal {
+        if let Some(_timer_crystal) = self.machine_desc.timer_crystal {
             // We have an alternate
             todo!("Unimplemented conversion for AT timer");
-            //1
         }
-        else {
-            match self.machine_desc.cpu_factor {
-                ClockFactor::Divisor(n) => self.machine_desc.timer_divisor / (n as u32),
-                ClockFactor::Multiplier(_n) => {
-                    todo!("unimplemented conversion for CPU multiplier");
-                    //1
-                }
-            }
-        };
 
-        timer_ticks as u32 * timer_multiplier
+        // The PIT is clocked at a fixed divisor of the system crystal.
+        self.system_ticks_to_cpu_cycles(timer_ticks as u32 * self.machine_desc.timer_divisor)
     }
 
     /// Called to update machine once per frame. This can be used to update the state of devices that don't require
@@ -1371,21 +3112,14 @@ impl Machine {
 
                             if turbo_bit != self.turbo_bit {
                                 // Turbo bit has changed.
-                                match turbo_bit {
-                                    true => {
-                                        self.next_cpu_factor = self.machine_desc.cpu_turbo_factor;
-                                        device_events.push(DeviceEvent::TurboToggled(true));
-                                    }
-                                    false => {
-                                        self.next_cpu_factor = self.machine_desc.cpu_factor;
-                                        device_events.push(DeviceEvent::TurboToggled(false));
-                                    }
-                                }
-                                log::debug!(
-                                    "Set turbo state to: {} New cpu factor is {:?}",
-                                    turbo_bit,
-                                    self.next_cpu_factor
-                                );
+                                let factor = match turbo_bit {
+                                    true => self.turbo_factor,
+                                    false => self.machine_desc.cpu_factor,
+                                };
+                                log::debug!("Set turbo state to: {} New cpu factor is {:?}", turbo_bit, factor);
+                                // We are between calls to run(), so the new clock can be applied now.
+                                self.set_cpu_factor(factor, true);
+                                device_events.push(DeviceEvent::TurboToggled(turbo_bit));
                             }
                             self.turbo_bit = turbo_bit;
                         }
@@ -1395,6 +3129,16 @@ impl Machine {
             _ => {}
         }
 
+        // Report changes to keyboard state so the frontend can mirror lock indicators.
+        let kb_state = self.keyboard_state();
+        if kb_state != self.kb_state {
+            if let Some(state) = kb_state {
+                log::debug!("Keyboard state changed: {:?}", state);
+                device_events.push(DeviceEvent::KeyboardStateChanged(state));
+            }
+            self.kb_state = kb_state;
+        }
+
         device_events
     }
 
@@ -1404,6 +3148,44 @@ impl Machine {
         }
     }
 
+    /// Select the model used for the speaker's output stage.
+    pub fn set_speaker_profile(&mut self, profile: SpeakerProfile) {
+        let sample_rate = self.sound_player.as_ref().map_or(44000, |player| player.sample_rate());
+        self.speaker_stage = SpeakerStage::new(profile, sample_rate);
+    }
+
+    pub fn speaker_profile(&self) -> SpeakerProfile {
+        self.speaker_stage.profile()
+    }
+
+    /// Return the audio sources installed in this machine.
+    pub fn audio_sources(&self) -> Vec<AudioSource> {
+        let mut sources = Vec::new();
+        if self.machine_desc.have_ppi {
+            sources.push(AudioSource::PcSpeaker);
+        }
+        sources
+    }
+
+    /// Start recording the sound output to a WAV file at the specified path. Each audio source
+    /// is also recorded to its own stem beside it. Returns the paths of the files being written.
+    pub fn start_audio_recording(&mut self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        if self.mixer.is_recording() {
+            return Err(anyhow!("Audio recording already in progress"));
+        }
+        let sources = self.audio_sources();
+        self.mixer.start_recording(path, &sources)
+    }
+
+    /// Stop recording the sound output. Returns the paths of the completed files.
+    pub fn stop_audio_recording(&mut self) -> Result<Vec<PathBuf>, Error> {
+        self.mixer.stop_recording()
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.mixer.is_recording()
+    }
+
     pub fn pit_buf_to_sound_buf(&mut self) {
         let nsamples = self.pit_data.next_sample_size;
         if self.pit_data.buffer_consumer.len() < self.pit_data.next_sample_size {
@@ -1450,8 +3232,10 @@ impl Machine {
         //log::trace!("Sample: sum: {}, ticks: {}, avg: {}", sum, pit_ticks, average);
         self.pit_data.samples_produced += 1;
         //log::trace!("producer: {}", self.pit_samples_produced);
+        let speaker_output = self.speaker_stage.process(average);
+        let output = self.mixer.mix(&[(AudioSource::PcSpeaker, speaker_output)]);
         if let Some(sound_player) = &mut self.sound_player {
-            sound_player.queue_sample(average * VOLUME_ADJUST);
+            sound_player.queue_sample(output * VOLUME_ADJUST);
         }
 
         // Calculate size of next audio sample in pit samples by carrying over fractional part
@@ -1468,3 +3252,648 @@ impl Machine {
         self.bus_mut().for_each_videocard(|video| f(video))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu_common::CpuType,
+        cpu_validator::ValidatorType,
+        devices::pit::PitType,
+        machine_config::{
+            BusType,
+            ConventionalMemoryConfig,
+            KeyboardConfig,
+            MachineDescriptorConfig,
+            MemoryConfig,
+            PicType,
+        },
+    };
+
+    struct TestCoreConfig;
+
+    impl CoreConfig for TestCoreConfig {
+        fn get_base_dir(&self) -> PathBuf {
+            PathBuf::new()
+        }
+        fn get_machine_type(&self) -> MachineType {
+            MachineType::Ibm5160
+        }
+        fn get_machine_noroms(&self) -> bool {
+            true
+        }
+        fn get_machine_turbo(&self) -> bool {
+            false
+        }
+        fn get_keyboard_layout(&self) -> Option<String> {
+            None
+        }
+        fn get_keyboard_debug(&self) -> bool {
+            false
+        }
+        fn get_validator_type(&self) -> Option<ValidatorType> {
+            None
+        }
+        fn get_validator_trace_file(&self) -> Option<PathBuf> {
+            None
+        }
+        fn get_validator_baud(&self) -> Option<u32> {
+            None
+        }
+        fn get_validator_address(&self) -> Option<String> {
+            None
+        }
+        fn get_cpu_trace_mode(&self) -> Option<TraceMode> {
+            None
+        }
+        fn get_cpu_trace_on(&self) -> bool {
+            false
+        }
+        fn get_cpu_trace_file(&self) -> Option<PathBuf> {
+            None
+        }
+    }
+
+    fn test_config() -> MachineConfiguration {
+        MachineConfiguration {
+            speaker: false,
+            speaker_profile: None,
+            ppi_turbo: None,
+            turbo_clock: None,
+            reset_vector: None,
+            dram_refresh: None,
+            wait_states: None,
+            open_bus: None,
+            descriptor: None,
+            dip_switches: None,
+            machine_type: MachineType::Ibm5160,
+            memory: MemoryConfig {
+                conventional: ConventionalMemoryConfig {
+                    size: 0x10000,
+                    wait_states: 0,
+                },
+                address_wrap: true,
+            },
+            keyboard: None,
+            serial_mouse: None,
+            game_port: None,
+            rtc: None,
+            video: Vec::new(),
+            serial: Vec::new(),
+            fdc: None,
+            hdc: None,
+            media: None,
+        }
+    }
+
+    /// Build a ROM-less machine running `program` from 0000:1000.
+    fn test_machine(config: &MachineConfiguration, program: &[u8]) -> Machine {
+        let core_config = TestCoreConfig;
+        let mut machine = MachineBuilder::new()
+            .with_core_config(Box::new(&core_config))
+            .with_machine_config(config)
+            .with_roms(MachineRomManifest::new())
+            .with_sound_player(None)
+            .build()
+            .expect("Failed to build machine");
+        machine
+            .load_program(program, 0x0000, 0x1000)
+            .expect("Failed to load program");
+        machine
+    }
+
+    fn run_machine(machine: &mut Machine, cycles: u32) {
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+        machine.run(cycles, &mut exec_control);
+    }
+
+    #[test]
+    fn test_halt_idle_time() {
+        #[rustfmt::skip]
+        let program = [
+            0xB0, 0x36,       // MOV AL, 36h  ; Channel 0, lobyte/hibyte, mode 3
+            0xE6, 0x43,       // OUT 43h, AL
+            0xB0, 0x00,       // MOV AL, 00h
+            0xE6, 0x40,       // OUT 40h, AL
+            0xB0, 0x10,       // MOV AL, 10h  ; Reload value 1000h
+            0xE6, 0x40,       // OUT 40h, AL
+            0xFB,             // STI
+            0xF4,             // HLT
+        ];
+        let config = test_config();
+
+        // Channel 0 counts down 1000h ticks by two in mode 3, so the next interrupt is at most
+        // 800h timer ticks away.
+        let max_us = 0x800 as f64 * 12.0 / 14.318180;
+
+        let mut machine = test_machine(&config, &program);
+        machine.set_halt_idle(true);
+        assert_eq!(machine.halt_idle_time(), None, "CPU is not halted yet");
+        run_machine(&mut machine, 200);
+        assert!(machine.cpu.is_halted());
+        let idle_us = machine
+            .halt_idle_time()
+            .expect("Halted machine should report an idle time");
+        assert!(idle_us > 0.0 && idle_us <= max_us, "Idle time {} out of range", idle_us);
+
+        // With halt idling off, the frontend must keep polling the machine.
+        let mut machine = test_machine(&config, &program);
+        run_machine(&mut machine, 200);
+        assert!(machine.cpu.is_halted());
+        assert_eq!(machine.halt_idle_time(), None);
+    }
+
+    #[test]
+    fn test_halt_idle_cycles() {
+        #[rustfmt::skip]
+        let program = [
+            0xB0, 0x36,       // MOV AL, 36h  ; Channel 0, lobyte/hibyte, mode 3
+            0xE6, 0x43,       // OUT 43h, AL
+            0xB0, 0x00,       // MOV AL, 00h
+            0xE6, 0x40,       // OUT 40h, AL
+            0xB0, 0x01,       // MOV AL, 01h  ; Reload value 100h
+            0xE6, 0x40,       // OUT 40h, AL
+            0xFB,             // STI
+            0xF4,             // HLT
+        ];
+        let config = test_config();
+        let mut machine = test_machine(&config, &program);
+        machine.set_halt_idle(true);
+        run_machine(&mut machine, 200);
+        assert!(machine.cpu.is_halted());
+
+        // The next interrupt is at most 80h timer ticks away. The 5160 runs 4 CPU cycles per
+        // timer tick, so the halted CPU must not skip more than 200h cycles.
+        let cycles = machine.halt_idle_cycles();
+        assert!(
+            cycles > DEFAULT_HALT_CYCLES && cycles <= 0x200,
+            "Halt idle cycles {} out of range",
+            cycles
+        );
+    }
+
+    /// Build a machine with a keyboard, running a program that stores each scancode received
+    /// by its IRQ1 handler at 0000:2000.
+    fn keyboard_test_machine(config: &mut MachineConfiguration) -> Machine {
+        #[rustfmt::skip]
+        let program = [
+            0xFA,                         // CLI
+            0x31, 0xC0,                   // XOR AX, AX
+            0x8E, 0xD8,                   // MOV DS, AX
+            0x8E, 0xC0,                   // MOV ES, AX
+            0x8E, 0xD0,                   // MOV SS, AX
+            0xBC, 0x00, 0x0F,             // MOV SP, 0F00h
+            0xB0, 0x13, 0xE6, 0x20,       // MOV AL, 13h; OUT 20h, AL  ; ICW1
+            0xB0, 0x08, 0xE6, 0x21,       // MOV AL, 08h; OUT 21h, AL  ; ICW2: IRQ0 at INT 8
+            0xB0, 0x09, 0xE6, 0x21,       // MOV AL, 09h; OUT 21h, AL  ; ICW4
+            0xB0, 0xFD, 0xE6, 0x21,       // MOV AL, FDh; OUT 21h, AL  ; Unmask IRQ1 only
+            0xB0, 0x99, 0xE6, 0x63,       // MOV AL, 99h; OUT 63h, AL  ; PPI mode
+            0xB0, 0x40, 0xE6, 0x61,       // MOV AL, 40h; OUT 61h, AL  ; Enable keyboard
+            0xC7, 0x06, 0x24, 0x00, 0x42, 0x10, // MOV WORD [0024h], 1042h
+            0xC7, 0x06, 0x26, 0x00, 0x00, 0x00, // MOV WORD [0026h], 0000h
+            0xBF, 0x00, 0x20,             // MOV DI, 2000h
+            0xB9, 0x40, 0x00,             // MOV CX, 40h
+            0x30, 0xC0,                   // XOR AL, AL
+            0xFC,                         // CLD
+            0xF3, 0xAA,                   // REP STOSB ; Clear the scancode buffer
+            0xBF, 0x00, 0x20,             // MOV DI, 2000h
+            0xFB,                         // STI
+            0xF4,                         // HLT
+            0xEB, 0xFD,                   // JMP -3
+            // IRQ1 handler at 0000:1042
+            0xE4, 0x60,                   // IN AL, 60h
+            0xAA,                         // STOSB
+            0xE4, 0x61,                   // IN AL, 61h
+            0x0C, 0x80,                   // OR AL, 80h
+            0xE6, 0x61,                   // OUT 61h, AL ; Acknowledge the scancode
+            0x24, 0x7F,                   // AND AL, 7Fh
+            0xE6, 0x61,                   // OUT 61h, AL
+            0xB0, 0x20, 0xE6, 0x20,       // MOV AL, 20h; OUT 20h, AL  ; EOI
+            0xCF,                         // IRET
+        ];
+        config.keyboard = Some(KeyboardConfig {
+            kb_type: KeyboardType::ModelF,
+            layout: "US".to_string(),
+            typematic: false,
+            typematic_delay: None,
+            typematic_rate: None,
+            type_text_delay: Some(1.0),
+        });
+        let mut machine = test_machine(config, &program);
+        // Let the program install its handler.
+        run_machine(&mut machine, 500);
+        machine
+    }
+
+    fn received_scancodes(machine: &Machine) -> Vec<u8> {
+        (0x2000..0x2040)
+            .map(|addr| machine.cpu.bus().peek_u8(addr).unwrap())
+            .take_while(|byte| *byte != 0)
+            .collect()
+    }
+
+    #[test]
+    fn test_type_text() {
+        let mut config = test_config();
+        let mut machine = keyboard_test_machine(&mut config);
+        assert_eq!(machine.type_text_interval(), 1000.0);
+
+        assert_eq!(machine.type_text("aB\r\n"), 3);
+        assert!(machine.keyboard_events_pending());
+        run_machine(&mut machine, 100_000);
+        assert!(!machine.keyboard_events_pending());
+        assert_eq!(
+            received_scancodes(&machine),
+            vec![0x1E, 0x9E, 0x2A, 0x30, 0xB0, 0xAA, 0x1C, 0x9C]
+        );
+
+        // A layout's character table replaces the US layout.
+        let mut machine = keyboard_test_machine(&mut config);
+        machine
+            .cpu
+            .bus_mut()
+            .keyboard_mut()
+            .unwrap()
+            .load_mapping_str("[keyboard.modelf]\nchar_table = { \"z\" = [\"KeyY\"] }")
+            .unwrap();
+        machine.type_text("zZ");
+        run_machine(&mut machine, 100_000);
+        assert_eq!(received_scancodes(&machine), vec![0x15, 0x95, 0x2A, 0x2C, 0xAC, 0xAA]);
+    }
+
+    #[test]
+    fn test_clear_keyboard_events_releases_keys() {
+        let mut config = test_config();
+        let mut machine = keyboard_test_machine(&mut config);
+
+        // Clear the text once the shift key has been pressed. The shift key is still released.
+        machine.type_text("AB");
+        while received_scancodes(&machine).is_empty() {
+            run_machine(&mut machine, 500);
+        }
+        machine.clear_keyboard_events();
+        run_machine(&mut machine, 100_000);
+        assert!(!machine.keyboard_events_pending());
+        assert_eq!(received_scancodes(&machine), vec![0x2A, 0xAA]);
+    }
+
+    #[test]
+    fn test_run_instructions() {
+        #[rustfmt::skip]
+        let program = [
+            0x31, 0xC0,                   // XOR AX, AX
+            0x8E, 0xD8,                   // MOV DS, AX
+            0x8E, 0xD0,                   // MOV SS, AX
+            0xBC, 0x00, 0x0F,             // MOV SP, 0F00h
+            0xB0, 0x13, 0xE6, 0x20,       // MOV AL, 13h; OUT 20h, AL  ; ICW1
+            0xB0, 0x08, 0xE6, 0x21,       // MOV AL, 08h; OUT 21h, AL  ; ICW2: IRQ0 at INT 8
+            0xB0, 0x09, 0xE6, 0x21,       // MOV AL, 09h; OUT 21h, AL  ; ICW4
+            0xB0, 0xFE, 0xE6, 0x21,       // MOV AL, FEh; OUT 21h, AL  ; Unmask IRQ0 only
+            0xC7, 0x06, 0x20, 0x00, 0x36, 0x10, // MOV WORD [0020h], 1036h
+            0xC7, 0x06, 0x22, 0x00, 0x00, 0x00, // MOV WORD [0022h], 0000h
+            0xB0, 0x36, 0xE6, 0x43,       // MOV AL, 36h; OUT 43h, AL  ; Channel 0, mode 3
+            0xB0, 0x00, 0xE6, 0x40,       // MOV AL, 00h; OUT 40h, AL
+            0xB0, 0x01, 0xE6, 0x40,       // MOV AL, 01h; OUT 40h, AL  ; Reload value 100h
+            0xFB,                         // STI
+            0xF4,                         // HLT
+            0x42,                         // INC DX
+            0xEB, 0xFC,                   // JMP -4 ; Back to HLT
+            // IRQ0 handler at 0000:1036
+            0x43,                         // INC BX
+            0xB0, 0x20, 0xE6, 0x20,       // MOV AL, 20h; OUT 20h, AL  ; EOI
+            0xCF,                         // IRET
+        ];
+        const SETUP_INSTRUCTIONS: u64 = 22;
+        // Each timer interrupt runs the four instructions of the handler, then INC DX, JMP and
+        // HLT.
+        const LOOP_INSTRUCTIONS: u64 = 7;
+
+        let config = test_config();
+        let mut machine = test_machine(&config, &program);
+        let mut exec_control = ExecutionControl::new();
+
+        // Programming the timer raises IRQ0 at once, so the handler is entered after the HLT.
+        assert_eq!(
+            machine.run_instructions(SETUP_INSTRUCTIONS, &mut exec_control),
+            SETUP_INSTRUCTIONS
+        );
+        assert!(matches!(exec_control.get_state(), ExecutionState::Paused));
+        assert_eq!(machine.cpu.ip(), 0x1036);
+
+        // The time spent halted between interrupts must not count towards the instructions run.
+        machine.run_instructions(LOOP_INSTRUCTIONS * 3, &mut exec_control);
+        assert!(matches!(exec_control.get_state(), ExecutionState::Paused));
+        assert!(machine.cpu.is_halted());
+        assert_eq!(machine.cpu.get_register16(Register16::BX), 3);
+        assert_eq!(machine.cpu.get_register16(Register16::DX), 3);
+
+        machine.run_instructions(2, &mut exec_control);
+        assert_eq!(machine.cpu.get_register16(Register16::BX), 4);
+        assert_eq!(machine.cpu.get_register16(Register16::DX), 3);
+    }
+
+    #[test]
+    fn test_cpu_clock_presets() {
+        let mut machine = test_machine(&test_config(), &[0xEB, 0xFE]);
+        let crystal = machine.machine_desc.system_crystal;
+        assert_eq!(machine.get_cpu_factor(), CpuClockPreset::Mhz4_77.clock_factor());
+
+        // Each press of the clock hotkey selects the next faster preset, then wraps around.
+        for preset in CpuClockPreset::ALL[1..].iter().chain(&CpuClockPreset::ALL[..1]) {
+            let mhz = machine.cycle_cpu_clock();
+            assert_eq!(machine.get_cpu_factor(), preset.clock_factor());
+            assert_eq!(machine.cpu.bus().get_cpu_factor(), preset.clock_factor());
+            assert!((mhz - preset.clock_factor().cpu_mhz(crystal)).abs() < 0.001);
+        }
+
+        // A deferred change waits for the next call to run().
+        machine.set_cpu_factor(ClockFactor::Fixed(8.0), false);
+        assert_eq!(machine.get_cpu_factor(), ClockFactor::Divisor(3));
+        run_machine(&mut machine, 100);
+        assert_eq!(machine.get_cpu_factor(), ClockFactor::Fixed(8.0));
+        assert_eq!(machine.cpu.bus().get_cpu_factor(), ClockFactor::Fixed(8.0));
+
+        machine.set_cpu_factor(ClockFactor::Divisor(2), true);
+        assert_eq!(machine.cpu.bus().get_cpu_factor(), ClockFactor::Divisor(2));
+    }
+
+    #[test]
+    fn test_run_frames() {
+        #[rustfmt::skip]
+        let program = [
+            0xFB,                         // STI
+            0xF4,                         // HLT
+            0xEB, 0xFD,                   // JMP -3
+        ];
+        let mut config = test_config();
+        config.video.push(VideoCardConfig {
+            video_type: VideoType::CGA,
+            monitor: None,
+            memory: None,
+            display: None,
+            scaler_preset: None,
+            lpt_mode: None,
+            wait_states: None,
+        });
+        let mut machine = test_machine(&config, &program);
+        let mut exec_control = ExecutionControl::new();
+
+        let start = machine.video_frame_count().unwrap();
+        machine.run_frames(2, &mut exec_control);
+        assert!(matches!(exec_control.get_state(), ExecutionState::Paused));
+        assert_eq!(machine.video_frame_count(), Some(start + 2));
+
+        machine.run_frames(1, &mut exec_control);
+        assert_eq!(machine.video_frame_count(), Some(start + 3));
+
+        // Without a video card there are no frames to count, so nothing runs.
+        let mut machine = test_machine(&test_config(), &program);
+        assert_eq!(machine.run_frames(1, &mut exec_control), 0);
+        assert!(matches!(exec_control.get_state(), ExecutionState::Paused));
+    }
+
+    #[test]
+    fn test_quick_save() {
+        #[rustfmt::skip]
+        let program = [
+            0xB0, 0x36,             // MOV AL, 36h  ; Channel 0, lobyte/hibyte, mode 3
+            0xE6, 0x43,             // OUT 43h, AL
+            0xB0, 0x00,             // MOV AL, 00h
+            0xE6, 0x40,             // OUT 40h, AL
+            0xE6, 0x40,             // OUT 40h, AL
+            0xFF, 0x06, 0x00, 0x20, // INC WORD [2000h]
+            0x40,                   // INC AX
+            0xEB, 0xF9,             // JMP -7
+        ];
+        let config = test_config();
+        let mut machine = test_machine(&config, &program);
+        run_machine(&mut machine, 1000);
+
+        assert!(machine.quick_save_info(0).is_none());
+        assert!(machine.quick_load(0).is_err());
+        assert!(machine.quick_save(QUICK_SAVE_SLOTS, Vec::new()).is_err());
+        machine.quick_save(0, vec![1, 2, 3]).unwrap();
+        assert_eq!(machine.quick_save_info(0).unwrap().thumbnail, vec![1, 2, 3]);
+
+        // The instruction queue is not saved, so fetching resumes from IP.
+        let mut regs = machine.cpu.rewind_state();
+        regs.pc = regs.ip;
+        let counter = machine.cpu.bus().peek_u8(0x2000).unwrap();
+        let devices = machine.device_states();
+        let cycles = machine.cpu_cycles;
+
+        run_machine(&mut machine, 5000);
+        assert_ne!(machine.cpu.bus().peek_u8(0x2000).unwrap(), counter);
+
+        machine.quick_load(0).unwrap();
+        assert_eq!(machine.cpu.rewind_state(), regs);
+        assert_eq!(machine.cpu.bus().peek_u8(0x2000).unwrap(), counter);
+        assert_eq!(machine.device_states(), devices);
+        assert_eq!(machine.cpu_cycles, cycles);
+
+        // Execution from a quick save is repeatable, and the save can be loaded again.
+        run_machine(&mut machine, 3000);
+        let regs = machine.cpu.rewind_state();
+        let devices = machine.device_states();
+        machine.quick_load(0).unwrap();
+        run_machine(&mut machine, 3000);
+        assert_eq!(machine.cpu.rewind_state(), regs);
+        assert_eq!(machine.device_states(), devices);
+
+        assert!(machine.clear_quick_save(0));
+        assert!(!machine.clear_quick_save(0));
+        assert!(machine.quick_save_info(0).is_none());
+    }
+
+    #[test]
+    fn test_run_back_to() {
+        #[rustfmt::skip]
+        let program = [
+            0xB0, 0x36,             // MOV AL, 36h  ; Channel 0, lobyte/hibyte, mode 3
+            0xE6, 0x43,             // OUT 43h, AL
+            0xB0, 0x00,             // MOV AL, 00h
+            0xE6, 0x40,             // OUT 40h, AL
+            0xE6, 0x40,             // OUT 40h, AL
+            0xFF, 0x06, 0x00, 0x20, // INC WORD [2000h]
+            0x40,                   // INC AX
+            0xEB, 0xF9,             // JMP -7
+        ];
+        let config = test_config();
+        let mut machine = test_machine(&config, &program);
+        let mut exec_control = ExecutionControl::new();
+        let bp = BreakPointType::ExecuteFlat(0x100E);
+        assert!(machine.run_back_to(0.001, bp, &mut exec_control).is_err());
+
+        // Take a snapshot every 100us of emulated time.
+        machine.set_snapshot_history(true, 0.0001, 100);
+        run_machine(&mut machine, 20_000);
+        let taken = machine.snapshot_history().unwrap().len();
+        assert!(taken > 10, "Only {} snapshots taken", taken);
+
+        let ticks = machine.system_ticks();
+        let counter = machine.cpu.bus().peek_u8(0x2000).unwrap();
+        let id = machine.run_back_to(0.001, bp, &mut exec_control).unwrap();
+        let back = (0.001 * machine.machine_desc.system_crystal * 1_000_000.0) as u64;
+        assert!(machine.system_ticks() <= ticks - back);
+        assert!(machine.snapshot_history().unwrap().len() < taken);
+        assert_ne!(machine.cpu.bus().peek_u8(0x2000).unwrap(), counter);
+
+        machine.run(1000, &mut exec_control);
+        assert!(matches!(exec_control.get_state(), ExecutionState::BreakpointHit));
+        assert_eq!(machine.last_breakpoint(), Some(id));
+        assert_eq!(machine.cpu.instruction_csip(), (0x0000, 0x100E));
+        // The temporary breakpoint is gone, so running continues past it.
+        assert!(machine.breakpoints().is_empty());
+        exec_control.set_op(ExecutionOperation::Run);
+        machine.run(1000, &mut exec_control);
+        assert!(matches!(exec_control.get_state(), ExecutionState::Running));
+    }
+
+    #[test]
+    fn test_add_remove_videocard_config() {
+        let card = |video_type| VideoCardConfig {
+            video_type,
+            monitor: None,
+            memory: None,
+            display: None,
+            scaler_preset: None,
+            lpt_mode: None,
+            wait_states: None,
+        };
+        let video_types = |machine: &Machine| {
+            machine
+                .machine_config
+                .video
+                .iter()
+                .map(|c| c.video_type)
+                .collect::<Vec<_>>()
+        };
+        let mut config = test_config();
+        config.video.push(card(VideoType::CGA));
+        let mut machine = test_machine(&config, &[0xF4]);
+        let cga = machine.cpu.bus().enumerate_videocards()[0];
+
+        let mda = machine.add_videocard(&card(VideoType::MDA)).unwrap();
+        assert_eq!(video_types(&machine), vec![VideoType::CGA, VideoType::MDA]);
+        assert!(machine.add_videocard(&card(VideoType::MDA)).is_err());
+        assert_eq!(video_types(&machine), vec![VideoType::CGA, VideoType::MDA]);
+
+        // The configuration entry removed is the one for the card, not the first of its type.
+        machine.remove_videocard(cga).unwrap();
+        assert_eq!(video_types(&machine), vec![VideoType::MDA]);
+        let cga = machine.add_videocard(&card(VideoType::CGA)).unwrap();
+        assert_eq!(video_types(&machine), vec![VideoType::MDA, VideoType::CGA]);
+        machine.remove_videocard(mda).unwrap();
+        assert_eq!(video_types(&machine), vec![VideoType::CGA]);
+        assert!(machine.remove_videocard(mda).is_err());
+        assert_eq!(machine.cpu.bus().enumerate_videocards(), vec![cga]);
+    }
+
+    #[test]
+    fn test_descriptor_overrides() {
+        let xt = get_machine_descriptor(MachineType::Ibm5160).unwrap();
+
+        // Values not specified are kept from the built-in descriptor.
+        let overrides: MachineDescriptorConfig = toml::from_str("cpu_mhz = 10.0\ntimer_crystal = 14.0").unwrap();
+        let desc = xt.with_overrides(&overrides).unwrap();
+        assert_eq!(desc.cpu_factor, ClockFactor::Fixed(10.0));
+        assert_eq!(desc.cpu_turbo_factor, xt.cpu_turbo_factor);
+        assert_eq!(desc.timer_crystal, Some(14.0));
+        assert_eq!(desc.pic_type, PicType::Single);
+
+        // A timer crystal of 0 clocks the PIT from the system crystal again.
+        let overrides: MachineDescriptorConfig = toml::from_str("timer_crystal = 0.0").unwrap();
+        assert_eq!(desc.with_overrides(&overrides).unwrap().timer_crystal, None);
+
+        // A 16-bit bus is rejected for an 8088, unless the CPU is replaced as well.
+        let overrides: MachineDescriptorConfig = toml::from_str("bus_type = \"Isa16\"").unwrap();
+        assert!(xt.with_overrides(&overrides).is_err());
+        let overrides: MachineDescriptorConfig =
+            toml::from_str("bus_type = \"Isa16\"\ncpu_type = \"Intel80286\"").unwrap();
+        assert_eq!(xt.with_overrides(&overrides).unwrap().bus_type, BusType::Isa16);
+
+        for invalid in [
+            "cpu_divisor = 0",
+            "cpu_mhz = 0.0",
+            "system_crystal = -1.0",
+            "timer_divisor = 0",
+        ] {
+            let overrides: MachineDescriptorConfig = toml::from_str(invalid).unwrap();
+            assert!(xt.with_overrides(&overrides).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_80286_descriptor() {
+        #[rustfmt::skip]
+        let program = [
+            0x6A, 0xFB, // PUSH -5     ; 80186+ only
+            0x58,       // POP AX
+            0xF4,       // HLT
+        ];
+        let mut config = test_config();
+        config.descriptor = Some(toml::from_str("cpu_type = \"Intel80286\"\nbus_type = \"Isa16\"").unwrap());
+        let mut machine = test_machine(&config, &program);
+        assert_eq!(machine.machine_desc.cpu_type, CpuType::Intel80286);
+        run_machine(&mut machine, 200);
+        assert_eq!(machine.cpu.get_register16(Register16::AX), 0xFFFB);
+    }
+
+    #[test]
+    fn test_full_descriptor() {
+        let desc_config: MachineDescriptorConfig = toml::from_str(
+            r#"
+            cpu_type = "Intel8086"
+            system_crystal = 24.0
+            timer_crystal = 14.31818
+            cpu_mhz = 8.0
+            turbo_divisor = 2
+            timer_divisor = 12
+            pit_type = "Model8254"
+            have_ppi = true
+            kb_controller = "Ppi"
+            bus_type = "Isa8"
+            pic_type = "Single"
+            dma_type = "Single"
+
+            [devices.keyboard]
+            type = "ModelF"
+            layout = "US"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = test_config();
+        config.descriptor = Some(desc_config);
+        let machine = test_machine(&config, &[0xF4]);
+        let desc = machine.machine_desc;
+        assert_eq!(desc.cpu_type, CpuType::Intel8086);
+        assert_eq!(desc.system_crystal, 24.0);
+        assert_eq!(desc.bus_crystal, 24.0);
+        assert_eq!(desc.timer_crystal, Some(14.31818));
+        assert_eq!(desc.cpu_factor, ClockFactor::Fixed(8.0));
+        assert_eq!(desc.cpu_turbo_factor, ClockFactor::Divisor(2));
+        assert_eq!(desc.pit_type, PitType::Model8254);
+        assert_eq!(machine.get_cpu_factor(), ClockFactor::Fixed(8.0));
+
+        // The descriptor's default keyboard is installed, as the configuration specifies none.
+        let keyboard = machine.config().keyboard.as_ref().expect("No default keyboard");
+        assert_eq!(keyboard.kb_type, KeyboardType::ModelF);
+
+        // A keyboard specified by the configuration replaces the default.
+        config.keyboard = Some(KeyboardConfig {
+            kb_type: KeyboardType::ModelM,
+            layout: String::from("US"),
+            typematic: false,
+            typematic_delay: None,
+            typematic_rate: None,
+            type_text_delay: None,
+        });
+        let machine = test_machine(&config, &[0xF4]);
+        assert_eq!(
+            machine.config().keyboard.as_ref().unwrap().kb_type,
+            KeyboardType::ModelM
+        );
+    }
+}
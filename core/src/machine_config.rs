@@ -31,11 +31,16 @@
 */
 
 use crate::machine_types::{
+    CompaqDisplay,
+    CpuClockPreset,
+    EgaMemorySize,
+    EgaMonitorType,
     FdcType,
     FloppyDriveType,
     HardDiskControllerType,
     HardDriveFormat,
     MachineType,
+    OpenBusType,
     SerialControllerType,
     SerialMouseType,
 };
@@ -47,7 +52,15 @@ use crate::{
     bus::ClockFactor,
     cpu_common::CpuType,
     device_traits::videocard::VideoType,
-    devices::{keyboard::KeyboardType, pit::PitType},
+    devices::{
+        game_port::{GamepadAxis, GamepadButton},
+        keyboard::KeyboardType,
+        lpt_port::ParallelPortMode,
+        pit::PitType,
+        ppi::DipSwitches,
+        rtc::RtcType,
+    },
+    sound::SpeakerProfile,
     tracelogger::TraceLogger,
 };
 
@@ -83,25 +96,25 @@ pub struct DeviceSpec {
     hotplug: bool,          // Whether device can be added/removed while machine is running.
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum KbControllerType {
     Ppi,
     At,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum PicType {
     Single,
     Chained,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum DmaType {
     Single,
     Chained,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub enum BusType {
     Isa8,
     Isa16,
@@ -116,6 +129,9 @@ lazy_static! {
         m.insert(MachineType::Ibm5150v64K, vec!["ibm5150v64k"]);
         m.insert(MachineType::Ibm5150v256K, vec!["ibm5150v256k"]);
         m.insert(MachineType::Ibm5160, vec!["ibm5160"]);
+        // The 5155 shipped with the XT motherboard and BIOS.
+        m.insert(MachineType::Ibm5155, vec!["ibm5160"]);
+        m.insert(MachineType::CompaqPortable, vec!["compaq_portable"]);
         m
     };
 
@@ -127,6 +143,8 @@ lazy_static! {
         m.insert(MachineType::Ibm5150v64K, vec!["ibm_basic"]);
         m.insert(MachineType::Ibm5150v256K, vec!["ibm_basic"]);
         m.insert(MachineType::Ibm5160, vec!["ibm_basic"]);
+        m.insert(MachineType::Ibm5155, vec!["ibm_basic"]);
+        m.insert(MachineType::CompaqPortable, vec![]);
         m
     };
 }
@@ -226,6 +244,46 @@ lazy_static! {
                     dma_type: DmaType::Single,
                 },
             ),
+            (
+                MachineType::Ibm5155,
+                MachineDescriptor {
+                    machine_type: MachineType::Ibm5155,
+                    system_crystal: IBM_PC_SYSTEM_CLOCK,
+                    timer_crystal: None,
+                    bus_crystal: IBM_PC_SYSTEM_CLOCK,
+                    cpu_type: CpuType::Intel8088,
+                    cpu_factor: ClockFactor::Divisor(3),
+                    cpu_turbo_factor: ClockFactor::Divisor(2),
+                    bus_type: BusType::Isa8,
+                    bus_factor: ClockFactor::Divisor(1),
+                    timer_divisor: PIT_DIVISOR,
+                    have_ppi: true,
+                    kb_controller: KbControllerType::Ppi,
+                    pit_type: PitType::Model8253,
+                    pic_type: PicType::Single,
+                    dma_type: DmaType::Single,
+                },
+            ),
+            (
+                MachineType::CompaqPortable,
+                MachineDescriptor {
+                    machine_type: MachineType::CompaqPortable,
+                    system_crystal: IBM_PC_SYSTEM_CLOCK,
+                    timer_crystal: None,
+                    bus_crystal: IBM_PC_SYSTEM_CLOCK,
+                    cpu_type: CpuType::Intel8088,
+                    cpu_factor: ClockFactor::Divisor(3),
+                    cpu_turbo_factor: ClockFactor::Divisor(2),
+                    bus_type: BusType::Isa8,
+                    bus_factor: ClockFactor::Divisor(1),
+                    timer_divisor: PIT_DIVISOR,
+                    have_ppi: true,
+                    kb_controller: KbControllerType::Ppi,
+                    pit_type: PitType::Model8253,
+                    pic_type: PicType::Single,
+                    dma_type: DmaType::Single,
+                },
+            ),
         ]);
         map
     };
@@ -235,9 +293,150 @@ pub fn get_machine_descriptor(machine_type: MachineType) -> Option<&'static Mach
     MACHINE_DESCS.get(&machine_type)
 }
 
+/// A user-supplied machine descriptor. A machine type selects a built-in MachineDescriptor, and
+/// every value specified here replaces the corresponding value of that description. Specifying
+/// all values describes a clone motherboard outright, with the machine type only selecting its
+/// ROMs and DIP switch layout.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MachineDescriptorConfig {
+    pub cpu_type: Option<CpuType>,
+    pub system_crystal: Option<f64>, // The main system crystal speed in MHz.
+    pub timer_crystal: Option<f64>,  // A separate PIT crystal speed in MHz. 0 clocks the PIT from the system crystal.
+    pub cpu_divisor: Option<u8>,     // The CPU clock as a divisor of the system crystal.
+    pub cpu_mhz: Option<f64>,        // The CPU clock from a separate oscillator, in MHz. Overrides cpu_divisor.
+    pub turbo_divisor: Option<u8>,   // Same as above, but when turbo is active.
+    pub turbo_mhz: Option<f64>,
+    pub timer_divisor: Option<u32>, // The PIT clock as a divisor of the timer crystal.
+    pub pit_type: Option<PitType>,
+    pub have_ppi: Option<bool>,
+    pub kb_controller: Option<KbControllerType>,
+    pub bus_type: Option<BusType>,
+    pub pic_type: Option<PicType>,
+    pub dma_type: Option<DmaType>,
+    pub devices: Option<DefaultDeviceConfig>, // The motherboard's default device complement.
+}
+
+/// The devices a motherboard provides by default. Each device is used only when the machine
+/// configuration does not specify that kind of device itself.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DefaultDeviceConfig {
+    pub keyboard: Option<KeyboardConfig>,
+    pub fdc: Option<FloppyControllerConfig>,
+    pub hdc: Option<HardDriveControllerConfig>,
+    pub serial: Option<Vec<SerialControllerConfig>>,
+    pub video: Option<Vec<VideoCardConfig>>,
+    pub game_port: Option<GamePortConfig>,
+    pub rtc: Option<RtcConfig>,
+}
+
+impl DefaultDeviceConfig {
+    /// Install each default device the specified configuration does not already specify.
+    pub fn apply(&self, config: &mut MachineConfiguration) {
+        if config.keyboard.is_none() {
+            config.keyboard = self.keyboard.clone();
+        }
+        if config.fdc.is_none() {
+            config.fdc = self.fdc.clone();
+        }
+        if config.hdc.is_none() {
+            config.hdc = self.hdc.clone();
+        }
+        if config.serial.is_empty() {
+            config.serial = self.serial.clone().unwrap_or_default();
+        }
+        if config.video.is_empty() {
+            config.video = self.video.clone().unwrap_or_default();
+        }
+        if config.game_port.is_none() {
+            config.game_port = self.game_port.clone();
+        }
+        if config.rtc.is_none() {
+            config.rtc = self.rtc.clone();
+        }
+    }
+}
+
+impl MachineDescriptor {
+    /// Return a copy of this descriptor with the values of the specified descriptor configuration applied.
+    pub fn with_overrides(&self, overrides: &MachineDescriptorConfig) -> Result<MachineDescriptor, Error> {
+        let mut desc = *self;
+
+        if let Some(cpu_type) = overrides.cpu_type {
+            desc.cpu_type = cpu_type;
+        }
+        if let Some(crystal) = overrides.system_crystal {
+            if crystal <= 0.0 {
+                return Err(anyhow!("Invalid system crystal frequency: {}", crystal));
+            }
+            desc.system_crystal = crystal;
+            desc.bus_crystal = crystal;
+        }
+        if let Some(crystal) = overrides.timer_crystal {
+            if crystal < 0.0 {
+                return Err(anyhow!("Invalid timer crystal frequency: {}", crystal));
+            }
+            desc.timer_crystal = if crystal > 0.0 { Some(crystal) } else { None };
+        }
+        desc.cpu_factor = Self::clock_override(desc.cpu_factor, overrides.cpu_divisor, overrides.cpu_mhz)?;
+        desc.cpu_turbo_factor =
+            Self::clock_override(desc.cpu_turbo_factor, overrides.turbo_divisor, overrides.turbo_mhz)?;
+
+        if let Some(divisor) = overrides.timer_divisor {
+            if divisor == 0 {
+                return Err(anyhow!("Timer divisor cannot be 0"));
+            }
+            desc.timer_divisor = divisor;
+        }
+        if let Some(pit_type) = overrides.pit_type {
+            desc.pit_type = pit_type;
+        }
+        if let Some(have_ppi) = overrides.have_ppi {
+            desc.have_ppi = have_ppi;
+        }
+        if let Some(kb_controller) = overrides.kb_controller {
+            desc.kb_controller = kb_controller;
+        }
+        if let Some(bus_type) = overrides.bus_type {
+            desc.bus_type = bus_type;
+        }
+        if let Some(pic_type) = overrides.pic_type {
+            desc.pic_type = pic_type;
+        }
+        if let Some(dma_type) = overrides.dma_type {
+            desc.dma_type = dma_type;
+        }
+
+        // A 16-bit bus requires a CPU with a 16-bit data bus. This is checked after all values are
+        // applied, as the configuration may change the CPU as well.
+        if desc.bus_type == BusType::Isa16 && !desc.cpu_type.has_16bit_bus() {
+            return Err(anyhow!(
+                "Bus type {:?} is not supported by {:?}",
+                desc.bus_type,
+                desc.cpu_type
+            ));
+        }
+
+        Ok(desc)
+    }
+
+    fn clock_override(factor: ClockFactor, divisor: Option<u8>, mhz: Option<f64>) -> Result<ClockFactor, Error> {
+        match (divisor, mhz) {
+            (_, Some(mhz)) if mhz > 0.0 => Ok(ClockFactor::Fixed(mhz)),
+            (_, Some(mhz)) => Err(anyhow!("Invalid CPU clock frequency: {}", mhz)),
+            (Some(0), None) => Err(anyhow!("CPU clock divisor cannot be 0")),
+            (Some(divisor), None) => Ok(ClockFactor::Divisor(divisor)),
+            (None, None) => Ok(factor),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct MemoryConfig {
     pub conventional: ConventionalMemoryConfig,
+    /// Wrap addresses at 1MB. Disabling this models an AT with the A20 gate open, where real mode
+    /// addresses above FFFF:000F reach the high memory area. Only 80286 machines should disable it.
+    #[serde(default = "_default_true")]
+    pub address_wrap: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -246,6 +445,76 @@ pub struct ConventionalMemoryConfig {
     pub wait_states: u32,
 }
 
+fn _default_true() -> bool {
+    true
+}
+
+/// DRAM refresh parameters. By default, MartyPC infers the refresh period from the BIOS's
+/// programming of PIT channel 1. Specifying a period here fixes the refresh rate regardless
+/// of how the PIT is programmed, while `enabled = false` disables refresh simulation entirely.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DramRefreshConfig {
+    #[serde(default = "_default_true")]
+    pub enabled: bool,
+    pub period: Option<u16>,       // Refresh period in PIT timer ticks. The IBM BIOS uses 18.
+    pub burst_length: Option<u32>, // Wait states inserted per refresh DMA cycle. Default is 6.
+}
+
+/// Additional wait states for devices on the 8-bit expansion bus.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WaitStateConfig {
+    #[serde(default)]
+    pub rom: u32, // Wait states applied to all installed ROMs.
+    #[serde(default)]
+    pub memory: Vec<MemoryWaitConfig>,
+    #[serde(default)]
+    pub io: Vec<IoWaitConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MemoryWaitConfig {
+    pub address: u32,
+    pub size: u32,
+    pub wait_states: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IoWaitConfig {
+    pub port: u16,
+    pub size: Option<u16>, // Number of consecutive ports to apply wait states to. Default is 1.
+    pub wait_states: u32,
+}
+
+/// Determines what is read from unconnected IO ports, and optionally from unpopulated memory.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OpenBusConfig {
+    #[serde(rename = "type", default)]
+    pub ob_type: OpenBusType,
+    pub value:   Option<u8>,      // Value read for a Fixed open bus. Default is 0xFF.
+    pub pattern: Option<Vec<u8>>, // Values read in sequence for a Pattern open bus.
+    #[serde(default)]
+    pub memory:  bool, // Apply to reads of unpopulated memory above conventional memory.
+}
+
+/// Overrides for the motherboard DIP switches. Each value replaces a whole switch block. Bit 0 is
+/// switch 1, and a set bit means the switch is ON. Blocks left unspecified keep the settings
+/// derived from the installed hardware.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DipSwitchConfig {
+    pub sw1: Option<u8>,
+    pub sw2: Option<u8>,
+}
+
+impl DipSwitchConfig {
+    /// Apply these overrides to the specified switch settings.
+    pub fn apply(&self, dip_switches: DipSwitches) -> DipSwitches {
+        DipSwitches {
+            sw1: self.sw1.unwrap_or(dip_switches.sw1),
+            sw2: self.sw2.unwrap_or(dip_switches.sw2),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct KeyboardConfig {
     #[serde(rename = "type")]
@@ -255,6 +524,8 @@ pub struct KeyboardConfig {
     pub typematic: bool,
     pub typematic_delay: Option<f64>,
     pub typematic_rate: Option<f64>,
+    /// Delay in milliseconds between keyboard events when typing text into the guest.
+    pub type_text_delay: Option<f64>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -262,12 +533,85 @@ pub struct SerialMouseConfig {
     #[serde(rename = "type")]
     pub mouse_type: SerialMouseType,
     pub port: u32,
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub invert_x: bool,
+    #[serde(default)]
+    pub invert_y: bool,
+    pub report_interval: Option<f64>,
+}
+
+/// Calibration points for a game port axis, as fractions of the joystick's full resistance.
+/// These should match the positions a guest calibration routine expects to see at the
+/// stick's minimum, center and maximum.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AxisCalibration {
+    pub min:    f64,
+    pub center: f64,
+    pub max:    f64,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            min:    0.0,
+            center: 0.5,
+            max:    1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadAxisConfig {
+    pub host: GamepadAxis,
+    pub axis: usize,
+    #[serde(default)]
+    pub invert: bool,
+    #[serde(default)]
+    pub deadzone: f64,
+    pub curve: Option<f64>, // Response curve exponent applied to the host value. Default is linear.
+    pub calibration: Option<AxisCalibration>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadButtonConfig {
+    pub host:   GamepadButton,
+    pub button: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamePortConfig {
+    pub io_base: Option<u16>, // Overrides the default IO base address of 0x201.
+    #[serde(default)]
+    pub axis:    Vec<GamepadAxisConfig>,
+    #[serde(default)]
+    pub button:  Vec<GamepadButtonConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RtcConfig {
+    #[serde(rename = "type")]
+    pub rtc_type: RtcType,
+    pub io_base:  Option<u16>, // Overrides the default IO base address of 0x2C0.
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct VideoCardConfig {
     #[serde(rename = "type")]
     pub video_type: VideoType,
+    // Only used by the EGA.
+    pub monitor: Option<EgaMonitorType>,
+    // Only used by the EGA.
+    pub memory: Option<EgaMemorySize>,
+    // Only used by the Compaq dual-mode board.
+    pub display: Option<CompaqDisplay>,
+    // Scaler preset used by windows showing this card that don't name their own,
+    // for machines with a built-in monitor.
+    pub scaler_preset: Option<String>,
+    // Only used by the MDA and Compaq video, for the printer port.
+    pub lpt_mode: Option<ParallelPortMode>,
+    // Wait states added to accesses to the card's memory apertures.
+    pub wait_states: Option<u32>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -301,7 +645,10 @@ pub struct FloppyDriveConfig {
 pub struct HardDriveControllerConfig {
     #[serde(rename = "type")]
     pub hdc_type: HardDiskControllerType,
-    pub drive:    Option<Vec<HardDriveConfig>>,
+    pub io_base: Option<u16>, // Overrides the controller's default IO base address.
+    pub irq: Option<u8>,      // Overrides the controller's default IRQ.
+    pub dma: Option<usize>,   // Overrides the controller's default DMA channel.
+    pub drive: Option<Vec<HardDriveConfig>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -328,21 +675,55 @@ pub struct MediaConfig {
     pub hdd:    Option<Vec<HardDriveImage>>,
 }
 
-#[derive(Clone, Debug)]
+/// The complete configuration of a machine instance. Frontends that read configurations from
+/// a single TOML table can deserialize this directly.
+#[derive(Clone, Debug, Deserialize)]
 pub struct MachineConfiguration {
+    #[serde(default)]
     pub speaker: bool,
+    pub speaker_profile: Option<SpeakerProfile>,
     pub ppi_turbo: Option<bool>,
+    pub turbo_clock: Option<CpuClockPreset>,
+    pub reset_vector: Option<[u16; 2]>, // Segment and offset the CPU begins execution at after reset.
+    #[serde(rename = "type")]
     pub machine_type: MachineType,
     pub memory: MemoryConfig,
+    pub dram_refresh: Option<DramRefreshConfig>,
+    pub wait_states: Option<WaitStateConfig>,
+    pub open_bus: Option<OpenBusConfig>,
+    pub descriptor: Option<MachineDescriptorConfig>,
+    pub dip_switches: Option<DipSwitchConfig>,
     pub keyboard: Option<KeyboardConfig>,
     pub serial_mouse: Option<SerialMouseConfig>,
+    pub game_port: Option<GamePortConfig>,
+    pub rtc: Option<RtcConfig>,
+    #[serde(default)]
     pub video: Vec<VideoCardConfig>,
+    #[serde(default)]
     pub serial: Vec<SerialControllerConfig>,
     pub fdc: Option<FloppyControllerConfig>,
     pub hdc: Option<HardDriveControllerConfig>,
     pub media: Option<MediaConfig>,
 }
 
+/// Return the maximum amount of conventional memory that can be installed without overlapping
+/// the memory aperture of an installed video card or the adapter ROM area at C0000.
+pub fn conventional_memory_limit(config: &MachineConfiguration) -> u32 {
+    config
+        .video
+        .iter()
+        .map(|card| match card.video_type {
+            VideoType::MDA => 0xB0000,
+            VideoType::CGA => 0xB8000,
+            VideoType::CompaqDual => 0xB0000,
+            #[cfg(feature = "ega")]
+            VideoType::EGA => 0xA0000,
+            #[cfg(feature = "vga")]
+            VideoType::VGA => 0xA0000,
+        })
+        .fold(0xC0000, u32::min)
+}
+
 pub fn normalize_conventional_memory(config: &MachineConfiguration) -> Result<u32, Error> {
     let mut conventional_memory = config.memory.conventional.size;
     conventional_memory = conventional_memory & 0xfffff000; // Normalize to 4K boundary
@@ -396,12 +777,23 @@ pub fn normalize_conventional_memory(config: &MachineConfiguration) -> Result<u3
         _ => conventional_memory,
     };
 
+    // Memory beyond 640K is possible on machines without an EGA or VGA, as RAM can fill the
+    // unused video memory space up to the first installed video card's aperture.
+    let memory_limit = conventional_memory_limit(config);
+
     if new_conventional_memory == 0 {
         Err(anyhow!(
             "Invalid conventional memory size specified: {}",
             conventional_memory
         ))
     }
+    else if new_conventional_memory > memory_limit {
+        Err(anyhow!(
+            "Conventional memory size {:05X} overlaps video memory. The maximum for this video configuration is {}K.",
+            new_conventional_memory,
+            memory_limit / 1024
+        ))
+    }
     else {
         Ok(new_conventional_memory)
     }
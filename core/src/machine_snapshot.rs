@@ -0,0 +1,180 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    machine_snapshot.rs
+
+    Implements snapshots of the whole machine that can be restored to resume
+    execution from the point they were taken, and the quick save slots and
+    periodic snapshot history built on them.
+
+    A snapshot holds the CPU registers, the address space as captured by a
+    MemorySnapshot, and the state of each device that supports DeviceState.
+    The CPU's instruction queue is not captured, so it is refilled when a
+    snapshot is restored. Attached media such as disk images are not part of
+    a snapshot.
+*/
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    bus::IoDeviceType,
+    cpu_808x::{Cpu, CpuRegisterState},
+    memory_snapshot::MemorySnapshot,
+};
+
+pub const QUICK_SAVE_SLOTS: usize = 10;
+pub const DEFAULT_SNAPSHOT_HISTORY_LEN: usize = 30;
+
+#[derive(Clone, Debug)]
+pub struct MachineSnapshot {
+    /// The CPU register state at the instruction boundary the snapshot was taken at.
+    pub regs: CpuRegisterState,
+    pub halted: bool,
+    pub cycle: u64,
+    pub instructions: u64,
+    pub system_ticks: u64,
+    pub memory: MemorySnapshot,
+    /// The state of each device, as compact JSON.
+    pub devices: Vec<(IoDeviceType, String)>,
+}
+
+impl MachineSnapshot {
+    /// Capture the state of the machine at the current instruction boundary. Memory pages that
+    /// have not been written since `base` was captured are shared with it.
+    pub fn capture(
+        cpu: &mut Cpu,
+        cycle: u64,
+        instructions: u64,
+        system_ticks: u64,
+        base: Option<&MachineSnapshot>,
+    ) -> Self {
+        let devices = cpu
+            .bus()
+            .capture_device_states()
+            .into_iter()
+            .map(|(device, state)| (device, state.to_string()))
+            .collect();
+
+        Self {
+            regs: cpu.rewind_state(),
+            halted: cpu.is_halted(),
+            cycle,
+            instructions,
+            system_ticks,
+            memory: MemorySnapshot::capture(cpu.bus_mut(), "snapshot", cycle, base.map(|base| &base.memory)),
+            devices,
+        }
+    }
+}
+
+/// The information shown for a quick save slot.
+#[derive(Clone, Debug)]
+pub struct QuickSaveInfo {
+    pub timestamp:   DateTime<Local>,
+    /// The frame count of the primary video card, if there is one.
+    pub frame_count: Option<u64>,
+    /// An image of the display, supplied by the frontend.
+    pub thumbnail:   Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct QuickSave {
+    pub info: QuickSaveInfo,
+    pub snapshot: MachineSnapshot,
+}
+
+/// Snapshots taken at a regular interval while the machine runs, so that execution can be resumed
+/// from an earlier point in time.
+pub struct SnapshotHistory {
+    snapshots: VecDeque<MachineSnapshot>,
+    interval:  u64,
+    capacity:  usize,
+}
+
+impl SnapshotHistory {
+    /// Create a snapshot history that takes a snapshot every `interval` system ticks and retains up
+    /// to `capacity` of the most recent snapshots.
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            interval:  interval.max(1),
+            capacity:  capacity.max(1),
+        }
+    }
+
+    /// Return true if a snapshot should be taken at the specified system tick.
+    pub fn due(&self, system_ticks: u64) -> bool {
+        match self.snapshots.back() {
+            Some(last) => system_ticks >= last.system_ticks + self.interval,
+            None => true,
+        }
+    }
+
+    /// Record a snapshot, discarding the oldest snapshot if the history is full.
+    pub fn push(&mut self, snapshot: MachineSnapshot) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Return the most recent snapshot.
+    pub fn last(&self) -> Option<&MachineSnapshot> {
+        self.snapshots.back()
+    }
+
+    /// Discard the snapshots taken after the specified system tick, and return the most recent
+    /// snapshot that remains. The oldest snapshot is kept even if it was taken after the tick, as
+    /// it is the closest the history can get.
+    pub fn rewind_to(&mut self, system_ticks: u64) -> Option<&MachineSnapshot> {
+        while self.snapshots.len() > 1 && self.snapshots.back().is_some_and(|s| s.system_ticks > system_ticks) {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
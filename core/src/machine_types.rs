@@ -35,12 +35,16 @@ use serde::{self, Deserializer};
 use serde_derive::Deserialize;
 use std::str::FromStr;
 
+use crate::bus::ClockFactor;
+
 #[derive(Copy, Clone, Debug, Deserialize, Hash, Eq, PartialEq)]
 pub enum MachineType {
     Fuzzer8088,
     Ibm5150v64K,
     Ibm5150v256K,
     Ibm5160,
+    Ibm5155,
+    CompaqPortable,
 }
 
 impl FromStr for MachineType {
@@ -54,6 +58,8 @@ impl FromStr for MachineType {
             "ibm5150v64k" => Ok(MachineType::Ibm5150v64K),
             "ibm5150v256k" => Ok(MachineType::Ibm5150v64K),
             "ibm5160" => Ok(MachineType::Ibm5160),
+            "ibm5155" => Ok(MachineType::Ibm5155),
+            "compaqportable" => Ok(MachineType::CompaqPortable),
             _ => Err("Bad value for model".to_string()),
         }
     }
@@ -65,6 +71,7 @@ pub enum FloppyDriveType {
     Floppy720K,
     Floppy12M,
     Floppy144M,
+    Floppy8Inch,
 }
 
 impl FromStr for FloppyDriveType {
@@ -78,6 +85,7 @@ impl FromStr for FloppyDriveType {
             "floppy720k" => Ok(FloppyDriveType::Floppy720K),
             "floppy12m" => Ok(FloppyDriveType::Floppy12M),
             "floppy144m" => Ok(FloppyDriveType::Floppy144M),
+            "floppy8inch" => Ok(FloppyDriveType::Floppy8Inch),
             _ => Err("Bad value for floppy drive type".to_string()),
         }
     }
@@ -95,7 +103,7 @@ impl<'de> serde::Deserialize<'de> for FloppyDriveType {
             type Value = FloppyDriveType;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("`360k`, `720k`, `1.2m` or `1.44m`")
+                formatter.write_str("`360k`, `720k`, `1.2m`, `1.44m` or `8in`")
             }
 
             fn visit_str<E>(self, value: &str) -> Result<FloppyDriveType, E>
@@ -107,6 +115,7 @@ impl<'de> serde::Deserialize<'de> for FloppyDriveType {
                     "720k" => Ok(FloppyDriveType::Floppy720K),
                     "1.2m" => Ok(FloppyDriveType::Floppy12M),
                     "1.44m" => Ok(FloppyDriveType::Floppy144M),
+                    "8in" => Ok(FloppyDriveType::Floppy8Inch),
                     _ => Err(E::custom(format!("invalid floppy type: {}", value))),
                 }
             }
@@ -124,6 +133,8 @@ pub enum FdcType {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum HardDiskControllerType {
     IbmXebec,
+    /// The Western Digital WD1002-WX1 and the compatible DTC 5150 controllers.
+    Wd1002,
 }
 
 impl FromStr for HardDiskControllerType {
@@ -134,6 +145,7 @@ impl FromStr for HardDiskControllerType {
     {
         match s.to_lowercase().as_str() {
             "ibmxebec" => Ok(HardDiskControllerType::IbmXebec),
+            "wd1002" | "dtc5150" => Ok(HardDiskControllerType::Wd1002),
             _ => Err("Bad value for HardDiskControllerType".to_string()),
         }
     }
@@ -154,3 +166,98 @@ pub enum SerialControllerType {
 pub enum SerialMouseType {
     Microsoft,
 }
+
+/// The type of monitor attached to an EGA card. This determines the card's configuration
+/// switch settings, which the EGA BIOS reads to select the available video modes.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum EgaMonitorType {
+    /// IBM 5154 Enhanced Color Display. Provides 350 line modes.
+    #[default]
+    EnhancedColor,
+    /// IBM 5154 Enhanced Color Display configured for 200 line 'normal color' modes.
+    NormalColor,
+    /// IBM 5153 Color Display or other CGA-frequency monitor.
+    Color,
+    /// IBM 5151 Monochrome Display.
+    Monochrome,
+}
+
+/// The amount of video memory installed on an EGA card. A base EGA has 64K, and the Graphics
+/// Memory Expansion Card brings it to 128K or, with the Graphics Memory Module Kit, 256K.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum EgaMemorySize {
+    #[serde(rename = "64K")]
+    Ega64K,
+    #[serde(rename = "128K")]
+    Ega128K,
+    #[default]
+    #[serde(rename = "256K")]
+    Ega256K,
+}
+
+impl EgaMemorySize {
+    /// Return the size of each of the four memory planes, in bytes.
+    pub fn plane_size(&self) -> usize {
+        match self {
+            EgaMemorySize::Ega64K => 0x4000,
+            EgaMemorySize::Ega128K => 0x8000,
+            EgaMemorySize::Ega256K => 0x10000,
+        }
+    }
+}
+
+/// The monitor driven by a Compaq Portable dual-mode video board.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum CompaqDisplay {
+    /// The built-in dual-frequency monitor. Displays 9x14 text when the monochrome personality
+    /// is enabled, and CGA output otherwise.
+    #[default]
+    Internal,
+    /// An external CGA monitor. Only the CGA personality can be displayed.
+    External,
+}
+
+/// The behavior of reads from unconnected IO ports and unpopulated memory.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum OpenBusType {
+    /// Always return a fixed value, usually 0xFF.
+    #[default]
+    Fixed,
+    /// Return the last value transferred on the data bus, as floating data lines tend to hold it.
+    LastValue,
+    /// Return successive values from a repeating pattern.
+    Pattern,
+}
+
+/// CPU clock presets for turbo boards. The 4.77 and 7.16MHz presets are derived from the system
+/// crystal, while the 8 and 10MHz presets model boards with a separate CPU oscillator.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+pub enum CpuClockPreset {
+    #[serde(rename = "4.77MHz")]
+    Mhz4_77,
+    #[serde(rename = "7.16MHz")]
+    Mhz7_16,
+    #[serde(rename = "8MHz")]
+    Mhz8,
+    #[serde(rename = "10MHz")]
+    Mhz10,
+}
+
+impl CpuClockPreset {
+    /// All presets, in order of increasing clock speed.
+    pub const ALL: [CpuClockPreset; 4] = [
+        CpuClockPreset::Mhz4_77,
+        CpuClockPreset::Mhz7_16,
+        CpuClockPreset::Mhz8,
+        CpuClockPreset::Mhz10,
+    ];
+
+    pub fn clock_factor(&self) -> ClockFactor {
+        match self {
+            CpuClockPreset::Mhz4_77 => ClockFactor::Divisor(3),
+            CpuClockPreset::Mhz7_16 => ClockFactor::Divisor(2),
+            CpuClockPreset::Mhz8 => ClockFactor::Fixed(8.0),
+            CpuClockPreset::Mhz10 => ClockFactor::Fixed(10.0),
+        }
+    }
+}
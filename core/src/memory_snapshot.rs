@@ -0,0 +1,344 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    memory_snapshot.rs
+
+    Implements labeled snapshots of the address space and a structured diff
+    between two snapshots, or between a snapshot and current memory. This
+    allows answering questions like 'what changed when I pressed this key'
+    from the debugger.
+
+    Snapshots are stored as pages shared by reference count. When a snapshot
+    is captured with a previous snapshot as its base, only pages that were
+    written since the base was taken are copied; the rest are shared. Diffs
+    skip shared pages entirely.
+*/
+
+use std::{fmt, sync::Arc};
+
+use crate::bus::{BusInterface, MEMORY_PAGE_SHIFT, MEMORY_PAGE_SIZE, OPEN_BUS_BYTE};
+
+#[derive(Clone, Debug)]
+pub struct MemorySnapshot {
+    pub label: String,
+    pub cycle: u64,
+    len: usize,
+    // Memory epoch the snapshot was taken at, if it can serve as the base for another capture.
+    epoch: Option<u32>,
+    pages: Vec<Arc<[u8]>>,
+    volatile: Vec<bool>,
+}
+
+/// A contiguous range of bytes that differ between two snapshots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryDiffRange {
+    pub start: usize,
+    pub old:   Vec<u8>,
+    pub new:   Vec<u8>,
+}
+
+impl MemoryDiffRange {
+    pub fn len(&self) -> usize {
+        self.new.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty()
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.new.len()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryDiff {
+    pub old_label: String,
+    pub new_label: String,
+    pub ranges:    Vec<MemoryDiffRange>,
+}
+
+impl MemoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Return the total number of bytes that changed.
+    pub fn changed_bytes(&self) -> usize {
+        self.ranges.iter().map(|r| r.len()).sum()
+    }
+}
+
+impl fmt::Display for MemoryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} -> {}: {} range(s), {} byte(s) changed",
+            self.old_label,
+            self.new_label,
+            self.ranges.len(),
+            self.changed_bytes()
+        )?;
+        for range in &self.ranges {
+            write!(f, "[{:05X}-{:05X}]", range.start, range.end() - 1)?;
+            for (old, new) in range.old.iter().zip(range.new.iter()) {
+                write!(f, " {:02X}->{:02X}", old, new)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemorySnapshot {
+    pub fn from_bytes(label: &str, cycle: u64, data: Vec<u8>) -> Self {
+        let pages: Vec<Arc<[u8]>> = data.chunks(MEMORY_PAGE_SIZE).map(Arc::from).collect();
+        Self {
+            label: label.to_string(),
+            cycle,
+            len: data.len(),
+            epoch: None,
+            volatile: vec![false; pages.len()],
+            pages,
+        }
+    }
+
+    /// Capture the full address space as seen by the CPU. Memory-mapped devices are peeked so
+    /// that video memory is included without side effects.
+    ///
+    /// If `base` is provided, pages that have not been written since it was captured are shared
+    /// with it instead of copied.
+    pub fn capture(bus: &mut BusInterface, label: &str, cycle: u64, base: Option<&MemorySnapshot>) -> Self {
+        let epoch = bus.advance_memory_epoch();
+        let mut snapshot = Self::capture_current(bus, label, cycle, base);
+        snapshot.epoch = Some(epoch);
+        snapshot
+    }
+
+    /// Capture the address space as with capture(), without starting a new memory epoch. The
+    /// resulting snapshot cannot be used as the base for a later capture.
+    pub fn capture_current(bus: &BusInterface, label: &str, cycle: u64, base: Option<&MemorySnapshot>) -> Self {
+        let len = bus.size();
+        let page_ct = (len + MEMORY_PAGE_SIZE - 1) >> MEMORY_PAGE_SHIFT;
+        let mut pages = Vec::with_capacity(page_ct);
+        let mut volatile = Vec::with_capacity(page_ct);
+
+        for page in 0..page_ct {
+            let page_volatile = bus.page_is_volatile(page);
+
+            if let Some(shared) = base.and_then(|base| base.unchanged_page(bus, page)) {
+                if !page_volatile {
+                    pages.push(shared);
+                    volatile.push(false);
+                    continue;
+                }
+            }
+
+            let start = page << MEMORY_PAGE_SHIFT;
+            let end = (start + MEMORY_PAGE_SIZE).min(len);
+            let data: Arc<[u8]> = if page_volatile {
+                (start..end)
+                    .map(|address| bus.peek_u8(address).unwrap_or(OPEN_BUS_BYTE))
+                    .collect()
+            }
+            else {
+                Arc::from(bus.get_slice_at(start, end - start))
+            };
+            pages.push(data);
+            volatile.push(page_volatile);
+        }
+
+        Self {
+            label: label.to_string(),
+            cycle,
+            len,
+            epoch: None,
+            pages,
+            volatile,
+        }
+    }
+
+    /// Write the snapshot back to memory. Pages that contain memory-mapped devices are skipped, as
+    /// their contents belong to the devices. If the snapshot can serve as a base, pages that have
+    /// not been written since it was captured are skipped as well.
+    pub fn restore(&self, bus: &mut BusInterface) {
+        for (page, data) in self.pages.iter().enumerate() {
+            if self.volatile[page] || self.unchanged_page(bus, page).is_some() {
+                continue;
+            }
+            _ = bus.patch_from(data, page << MEMORY_PAGE_SHIFT);
+        }
+    }
+
+    /// Return this snapshot's copy of the specified page if memory has not changed since it was taken.
+    fn unchanged_page(&self, bus: &BusInterface, page: usize) -> Option<Arc<[u8]>> {
+        let epoch = self.epoch?;
+        if page < self.pages.len() && !self.volatile[page] && !bus.page_changed_since(page, epoch) {
+            Some(self.pages[page].clone())
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn byte(&self, address: usize) -> u8 {
+        self.pages[address >> MEMORY_PAGE_SHIFT][address & (MEMORY_PAGE_SIZE - 1)]
+    }
+
+    /// Return a copy of the bytes in the specified range.
+    pub fn bytes(&self, start: usize, end: usize) -> Vec<u8> {
+        (start..end).map(|address| self.byte(address)).collect()
+    }
+
+    /// Return the number of pages this snapshot shares with `other`.
+    pub fn shared_pages(&self, other: &MemorySnapshot) -> usize {
+        (0..self.pages.len().min(other.pages.len()))
+            .filter(|page| self.shares_page(other, *page))
+            .count()
+    }
+
+    #[inline]
+    fn shares_page(&self, other: &MemorySnapshot, page: usize) -> bool {
+        page < self.pages.len() && page < other.pages.len() && Arc::ptr_eq(&self.pages[page], &other.pages[page])
+    }
+
+    /// Produce a diff from this snapshot to `newer`. Adjacent changed bytes are coalesced into a
+    /// single range; ranges separated by at most `merge_gap` unchanged bytes are merged, with
+    /// the unchanged bytes included.
+    pub fn diff(&self, newer: &MemorySnapshot, merge_gap: usize) -> MemoryDiff {
+        let mut ranges: Vec<MemoryDiffRange> = Vec::new();
+        let len = self.len.min(newer.len);
+
+        let mut address = 0;
+        while address < len {
+            if address & (MEMORY_PAGE_SIZE - 1) == 0 && self.shares_page(newer, address >> MEMORY_PAGE_SHIFT) {
+                address += MEMORY_PAGE_SIZE;
+                continue;
+            }
+            if self.byte(address) == newer.byte(address) {
+                address += 1;
+                continue;
+            }
+
+            let start = address;
+            let mut end = address + 1;
+            let mut scan = end;
+            while scan < len {
+                if self.byte(scan) != newer.byte(scan) {
+                    end = scan + 1;
+                }
+                else if scan - end >= merge_gap {
+                    break;
+                }
+                scan += 1;
+            }
+
+            ranges.push(MemoryDiffRange {
+                start,
+                old: self.bytes(start, end),
+                new: newer.bytes(start, end),
+            });
+            address = end;
+        }
+
+        MemoryDiff {
+            old_label: self.label.clone(),
+            new_label: newer.label.clone(),
+            ranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_diff() {
+        let a = MemorySnapshot::from_bytes("a", 0, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        let b = MemorySnapshot::from_bytes("b", 100, vec![0, 9, 9, 3, 4, 9, 6, 7]);
+
+        let diff = a.diff(&b, 0);
+        assert_eq!(diff.ranges.len(), 2);
+        assert_eq!(diff.ranges[0].start, 1);
+        assert_eq!(diff.ranges[0].old, vec![1, 2]);
+        assert_eq!(diff.ranges[0].new, vec![9, 9]);
+        assert_eq!(diff.ranges[1].start, 5);
+        assert_eq!(diff.changed_bytes(), 3);
+
+        let merged = a.diff(&b, 2);
+        assert_eq!(merged.ranges.len(), 1);
+        assert_eq!(merged.ranges[0].old, vec![1, 2, 3, 4, 5]);
+
+        assert!(a.diff(&a, 0).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_shared_pages() {
+        let mut bus = BusInterface::default();
+        bus.write_u8(0x1000, 0x55, 0).unwrap();
+
+        let first = MemorySnapshot::capture(&mut bus, "first", 0, None);
+        bus.write_u8(0x1001, 0xAA, 0).unwrap();
+        let second = MemorySnapshot::capture(&mut bus, "second", 0, Some(&first));
+
+        // Only the written page should have been copied.
+        assert_eq!(second.shared_pages(&first), (bus.size() >> MEMORY_PAGE_SHIFT) - 1);
+        assert_eq!(second.byte(0x1000), 0x55);
+
+        let diff = first.diff(&second, 0);
+        assert_eq!(diff.ranges.len(), 1);
+        assert_eq!(diff.ranges[0].start, 0x1001);
+        assert_eq!(diff.ranges[0].new, vec![0xAA]);
+
+        // A capture based on the second snapshot shares all pages if nothing was written.
+        let third = MemorySnapshot::capture(&mut bus, "third", 0, Some(&second));
+        assert_eq!(third.shared_pages(&second), bus.size() >> MEMORY_PAGE_SHIFT);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut bus = BusInterface::default();
+        bus.write_u8(0x1000, 0x55, 0).unwrap();
+        let snapshot = MemorySnapshot::capture(&mut bus, "snapshot", 0, None);
+
+        bus.write_u8(0x1000, 0xAA, 0).unwrap();
+        bus.write_u8(0x8000, 0xAA, 0).unwrap();
+        snapshot.restore(&mut bus);
+        assert_eq!(bus.peek_u8(0x1000).unwrap(), 0x55);
+        assert_eq!(bus.peek_u8(0x8000).unwrap(), snapshot.byte(0x8000));
+
+        let current = MemorySnapshot::capture_current(&bus, "current", 0, None);
+        assert!(snapshot.diff(&current, 0).is_empty());
+    }
+}
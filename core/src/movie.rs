@@ -0,0 +1,430 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    movie.rs
+
+    Input movies for tool-assisted input crafting and demo verification.
+
+    A movie records the keyboard and mouse input state for each video frame
+    since power-on. Input is only ever applied to the machine at frame
+    boundaries, both while recording and during playback, so a movie replays
+    with the same emulated timing it was recorded with.
+
+    Movies are stored as TOML. Only frames where the input state changes are
+    stored; a frame's state persists until the next stored frame.
+*/
+
+use std::{collections::HashSet, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::keys::MartyKey;
+
+pub const MOVIE_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MovieMouse {
+    pub left:    bool,
+    pub right:   bool,
+    pub delta_x: i32,
+    pub delta_y: i32,
+}
+
+impl MovieMouse {
+    fn is_idle(&self) -> bool {
+        self.delta_x == 0 && self.delta_y == 0
+    }
+}
+
+/// The input state for a single frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MovieInput {
+    pub keys:  Vec<MartyKey>,
+    pub mouse: MovieMouse,
+}
+
+impl MovieInput {
+    fn normalize(&mut self) {
+        self.keys.sort_by_key(|k| format!("{:?}", k));
+        self.keys.dedup();
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MovieFrameEntry {
+    frame: u64,
+    #[serde(default)]
+    keys:  Vec<String>,
+    mouse: Option<MovieMouse>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MovieFile {
+    version: u32,
+    machine: String,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    rerecord_count: u32,
+    frame_count: u64,
+    #[serde(default)]
+    frame: Vec<MovieFrameEntry>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputMovie {
+    pub machine: String,
+    pub author: String,
+    pub description: String,
+    pub rerecord_count: u32,
+    frame_count: u64,
+    // Sorted by frame number.
+    frames: Vec<(u64, MovieInput)>,
+}
+
+impl InputMovie {
+    /// Create an empty movie for the named machine type.
+    pub fn new(machine: &str) -> Self {
+        Self {
+            machine: machine.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let movie_str = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&movie_str)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_toml_string()?)?;
+        Ok(())
+    }
+
+    pub fn from_toml_str(movie_str: &str) -> Result<Self, Error> {
+        let file: MovieFile = toml::from_str(movie_str)?;
+        if file.version != MOVIE_VERSION {
+            return Err(anyhow!("Unsupported movie version: {}", file.version));
+        }
+
+        let mut frames: Vec<(u64, MovieInput)> = Vec::with_capacity(file.frame.len());
+        for entry in file.frame {
+            let mut keys = Vec::with_capacity(entry.keys.len());
+            for key_str in entry.keys.iter() {
+                let key = MartyKey::from_str(key_str)
+                    .map_err(|_| anyhow!("Frame {}: invalid key '{}'", entry.frame, key_str))?;
+                keys.push(key);
+            }
+            if let Some((last, _)) = frames.last() {
+                if entry.frame <= *last {
+                    return Err(anyhow!("Frame {} is out of order", entry.frame));
+                }
+            }
+            let mut input = MovieInput {
+                keys,
+                mouse: entry.mouse.unwrap_or_default(),
+            };
+            input.normalize();
+            frames.push((entry.frame, input));
+        }
+
+        Ok(Self {
+            machine: file.machine,
+            author: file.author,
+            description: file.description,
+            rerecord_count: file.rerecord_count,
+            frame_count: file.frame_count,
+            frames,
+        })
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, Error> {
+        let file = MovieFile {
+            version: MOVIE_VERSION,
+            machine: self.machine.clone(),
+            author: self.author.clone(),
+            description: self.description.clone(),
+            rerecord_count: self.rerecord_count,
+            frame_count: self.frame_count,
+            frame: self
+                .frames
+                .iter()
+                .map(|(frame, input)| MovieFrameEntry {
+                    frame: *frame,
+                    keys:  input.keys.iter().map(|k| format!("{:?}", k)).collect(),
+                    mouse: if input.mouse == MovieMouse::default() {
+                        None
+                    }
+                    else {
+                        Some(input.mouse)
+                    },
+                })
+                .collect(),
+        };
+        Ok(toml::to_string(&file)?)
+    }
+
+    /// Return the number of frames in the movie.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Return the input state in effect at the specified frame. Mouse motion is only reported on
+    /// the frame it was stored for.
+    pub fn input_at(&self, frame: u64) -> MovieInput {
+        let idx = self.frames.partition_point(|(f, _)| *f <= frame);
+        if idx == 0 {
+            return MovieInput::default();
+        }
+        let (stored_frame, input) = &self.frames[idx - 1];
+        let mut input = input.clone();
+        if *stored_frame != frame {
+            input.mouse.delta_x = 0;
+            input.mouse.delta_y = 0;
+        }
+        input
+    }
+
+    /// Set the input state for the specified frame, which must be at or after the end of the movie.
+    pub fn push_input(&mut self, frame: u64, mut input: MovieInput) {
+        input.normalize();
+        if self.input_at(frame) != input {
+            self.frames.retain(|(f, _)| *f < frame);
+            self.frames.push((frame, input));
+        }
+        self.frame_count = self.frame_count.max(frame + 1);
+    }
+
+    /// Discard all input from the specified frame onwards, to re-record from that point.
+    /// Increments the rerecord count.
+    pub fn truncate(&mut self, frame: u64) {
+        self.frames.retain(|(f, _)| *f < frame);
+        self.frame_count = self.frame_count.min(frame);
+        self.rerecord_count += 1;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MovieMode {
+    /// Input is read from the movie. Live input is ignored.
+    Playback,
+    /// Live input is sampled at each frame boundary and appended to the movie.
+    Recording,
+    /// Playback has passed the end of the movie. Live input is applied as normal.
+    Finished,
+}
+
+/// Drives a movie against a running machine. The machine calls frame_input() at each video frame
+/// boundary and applies the returned changes.
+pub struct MoviePlayer {
+    movie: InputMovie,
+    mode: MovieMode,
+    frame: u64,
+    record_from: Option<u64>,
+    live: MovieInput,
+    applied: MovieInput,
+}
+
+/// Input changes to apply at a frame boundary.
+#[derive(Clone, Debug, Default)]
+pub struct MovieFrameChanges {
+    pub pressed:  Vec<MartyKey>,
+    pub released: Vec<MartyKey>,
+    pub mouse:    Option<MovieMouse>,
+}
+
+impl MoviePlayer {
+    /// Play back `movie` from power-on. If `record_from` is specified, playback stops at that
+    /// frame, the remainder of the movie is discarded and recording begins.
+    pub fn playback(movie: InputMovie, record_from: Option<u64>) -> Self {
+        let mut player = Self::new(movie, MovieMode::Playback);
+        player.record_from = record_from;
+        if record_from == Some(0) {
+            player.begin_recording();
+        }
+        player
+    }
+
+    /// Record a new movie from power-on.
+    pub fn record(movie: InputMovie) -> Self {
+        Self::new(movie, MovieMode::Recording)
+    }
+
+    fn new(movie: InputMovie, mode: MovieMode) -> Self {
+        Self {
+            movie,
+            mode,
+            frame: 0,
+            record_from: None,
+            live: MovieInput::default(),
+            applied: MovieInput::default(),
+        }
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    /// Return the number of the next frame to be started.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn movie(&self) -> &InputMovie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> InputMovie {
+        self.movie
+    }
+
+    /// Returns true if live input should be passed to the machine directly rather than through
+    /// the movie.
+    pub fn passthrough(&self) -> bool {
+        self.mode == MovieMode::Finished
+    }
+
+    /// Record a live key press or release, to be sampled at the next frame boundary.
+    pub fn live_key(&mut self, key: MartyKey, pressed: bool) {
+        if pressed {
+            if !self.live.keys.contains(&key) {
+                self.live.keys.push(key);
+            }
+        }
+        else {
+            self.live.keys.retain(|k| *k != key);
+        }
+    }
+
+    /// Record live mouse input, to be sampled at the next frame boundary. Motion accumulates
+    /// until then.
+    pub fn live_mouse(&mut self, left: bool, right: bool, delta_x: i32, delta_y: i32) {
+        self.live.mouse.left = left;
+        self.live.mouse.right = right;
+        self.live.mouse.delta_x += delta_x;
+        self.live.mouse.delta_y += delta_y;
+    }
+
+    fn begin_recording(&mut self) {
+        log::debug!("Movie: recording from frame {}", self.frame);
+        self.movie.truncate(self.frame);
+        self.mode = MovieMode::Recording;
+        self.record_from = None;
+        // Continue holding whatever the movie held at this point.
+        self.live = self.applied.clone();
+        self.live.mouse.delta_x = 0;
+        self.live.mouse.delta_y = 0;
+    }
+
+    /// Start the next frame and return the input changes to apply for it.
+    pub fn frame_input(&mut self) -> MovieFrameChanges {
+        if self.mode == MovieMode::Playback && self.record_from == Some(self.frame) {
+            self.begin_recording();
+        }
+
+        let input = match self.mode {
+            MovieMode::Playback => {
+                if self.frame >= self.movie.frame_count() {
+                    log::debug!("Movie: playback finished at frame {}", self.frame);
+                    self.mode = MovieMode::Finished;
+                    return MovieFrameChanges::default();
+                }
+                self.movie.input_at(self.frame)
+            }
+            MovieMode::Recording => {
+                let input = self.live.clone();
+                self.movie.push_input(self.frame, input.clone());
+                self.live.mouse.delta_x = 0;
+                self.live.mouse.delta_y = 0;
+                input
+            }
+            MovieMode::Finished => return MovieFrameChanges::default(),
+        };
+
+        let old_keys: HashSet<MartyKey> = self.applied.keys.iter().copied().collect();
+        let new_keys: HashSet<MartyKey> = input.keys.iter().copied().collect();
+        let changes = MovieFrameChanges {
+            pressed:  input.keys.iter().filter(|k| !old_keys.contains(k)).copied().collect(),
+            released: self
+                .applied
+                .keys
+                .iter()
+                .filter(|k| !new_keys.contains(k))
+                .copied()
+                .collect(),
+            mouse:    if !input.mouse.is_idle()
+                || input.mouse.left != self.applied.mouse.left
+                || input.mouse.right != self.applied.mouse.right
+            {
+                Some(input.mouse)
+            }
+            else {
+                None
+            },
+        };
+
+        self.applied = input;
+        self.frame += 1;
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_roundtrip_and_rerecord() {
+        let mut recorder = MoviePlayer::record(InputMovie::new("Ibm5160"));
+        recorder.frame_input();
+        recorder.live_key(MartyKey::KeyA, true);
+        let changes = recorder.frame_input();
+        assert_eq!(changes.pressed, vec![MartyKey::KeyA]);
+        recorder.live_mouse(true, false, 5, -3);
+        recorder.frame_input();
+        recorder.live_key(MartyKey::KeyA, false);
+        recorder.frame_input();
+
+        let movie_str = recorder.into_movie().to_toml_string().unwrap();
+        let movie = InputMovie::from_toml_str(&movie_str).unwrap();
+        assert_eq!(movie.frame_count(), 4);
+        assert_eq!(movie.input_at(1).keys, vec![MartyKey::KeyA]);
+        assert_eq!(movie.input_at(2).mouse.delta_x, 5);
+        assert!(movie.input_at(3).keys.is_empty());
+
+        // Play back two frames, then re-record from frame 2.
+        let mut player = MoviePlayer::playback(movie, Some(2));
+        player.frame_input();
+        assert_eq!(player.frame_input().pressed, vec![MartyKey::KeyA]);
+        assert_eq!(player.frame_input().released.len(), 0);
+        assert_eq!(player.mode(), MovieMode::Recording);
+        assert_eq!(player.movie().rerecord_count, 1);
+        assert_eq!(player.movie().frame_count(), 3);
+    }
+}
@@ -0,0 +1,578 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    ntsc.rs
+
+    Core-side NTSC composite encoder and decoder.
+
+    The encoder converts lines of 4-bit RGBI pixels, one per 14.318Mhz hdot,
+    into a composite signal sampled at four times the color subcarrier
+    frequency. The decoder demodulates that signal back into RGBA pixels.
+
+    The encoding model is Andrew Jenner (reenigne)'s sampled chroma
+    multiplexer, as also used by DosBox and 86Box. The multiplexer table and
+    intensity levels are sampled from real hardware and remain under their
+    original UNLICENSE terms.
+
+    Since the signal is produced here rather than in a shader, every frontend
+    gets identical artifact colors. Any adapter that generates its composite
+    signal from RGBI values through the same chroma multiplexer circuit (CGA,
+    PCjr, Tandy) can share this pipeline.
+
+*/
+
+use std::f64::consts::TAU;
+
+/// Composite levels sampled from the CGA's chroma multiplexer, indexed by
+/// (left_color << 5) | (right_color << 2) | phase, where colors are the 3-bit RGB values on
+/// either side of a transition.
+#[rustfmt::skip]
+pub const CHROMA_MULTIPLEXER: [u8; 256] = [
+      2,   2,   2,   2, 114, 174,   4,   3,   2,   1, 133, 135,   2, 113, 150,   4,
+    133,   2,   1,  99, 151, 152,   2,   1,   3,   2,  96, 136, 151, 152, 151, 152,
+      2,  56,  62,   4, 111, 250, 118,   4,   0,  51, 207, 137,   1, 171, 209,   5,
+    140,  50,  54, 100, 133, 202,  57,   4,   2,  50, 153, 149, 128, 198, 198, 135,
+     32,   1,  36,  81, 147, 158,   1,  42,  33,   1, 210, 254,  34, 109, 169,  77,
+    177,   2,   0, 165, 189, 154,   3,  44,  33,   0,  91, 197, 178, 142, 144, 192,
+      4,   2,  61,  67, 117, 151, 112,  83,   4,   0, 249, 255,   3, 107, 249, 117,
+    147,   1,  50, 162, 143, 141,  52,  54,   3,   0, 145, 206, 124, 123, 192, 193,
+     72,  78,   2,   0, 159, 208,   4,   0,  53,  58, 164, 159,  37, 159, 171,   1,
+    248, 117,   4,  98, 212, 218,   5,   2,  54,  59,  93, 121, 176, 181, 134, 130,
+      1,  61,  31,   0, 160, 255,  34,   1,   1,  58, 197, 166,   0, 177, 194,   2,
+    162, 111,  34,  96, 205, 253,  32,   1,   1,  57, 123, 125, 119, 188, 150, 112,
+     78,   4,   0,  75, 166, 180,  20,  38,  78,   1, 143, 246,  42, 113, 156,  37,
+    252,   4,   1, 188, 175, 129,   1,  37, 118,   4,  88, 249, 202, 150, 145, 200,
+     61,  59,  60,  60, 228, 252, 117,  77,  60,  58, 248, 251,  81, 212, 254, 107,
+    198,  59,  58, 169, 250, 251,  81,  80, 100,  58, 154, 250, 251, 252, 252, 252
+];
+
+/// Luma levels contributed by an intensity (or, on the new CGA, R, G or B) bit, indexed by
+/// the bit's value in the left and right pixels of a transition.
+pub const INTENSITY: [f64; 4] = [77.175381, 88.654656, 166.564623, 174.228438];
+
+/// Number of border samples emitted on either side of an encoded line. The decoder's filters
+/// reach four samples in each direction.
+pub const NTSC_BORDER_SAMPLES: usize = 5;
+
+// YIQ to RGB matrix coefficients.
+const RI: f64 = 0.9563;
+const RQ: f64 = 0.6210;
+const GI: f64 = -0.2721;
+const GQ: f64 = -0.6474;
+const BI: f64 = -1.1069;
+const BQ: f64 = 1.7046;
+
+/// Which revision of the composite output circuit to model.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum NtscModel {
+    /// The original IBM CGA, where luma is only derived from the I bit and chroma.
+    #[default]
+    OldCga,
+    /// The later IBM CGA revision (and PCjr-style adapters) that mix the R, G and B
+    /// signals into luma, producing distinct grey levels.
+    NewCga,
+}
+
+/// User-facing adjustments applied by the decoder. A value of 1.0 is nominal for every
+/// multiplier; hue is an offset in degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NtscParams {
+    pub model: NtscModel,
+    pub hue: f64,
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+    pub sharpness: f64,
+}
+
+impl Default for NtscParams {
+    fn default() -> Self {
+        Self {
+            model: NtscModel::OldCga,
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 1.0,
+            sharpness: 0.0,
+        }
+    }
+}
+
+/// Return the composite level for the new CGA, which mixes the R, G and B signals into luma
+/// alongside the chroma multiplexer level `c` and intensity level `i`.
+#[inline]
+pub fn new_cga_level(c: f64, i: f64, r: f64, g: f64, b: f64) -> f64 {
+    (c / 0.72) * 0.29 + (i / 0.28) * 0.32 + (r / 0.28) * 0.1 + (g / 0.28) * 0.22 + (b / 0.28) * 0.07
+}
+
+/// Converts RGBI pixel data into composite samples.
+pub struct NtscEncoder {
+    model: NtscModel,
+    mono: bool,
+    contrast: f64,
+    brightness: f64,
+    // Indexed by (left_rgbi << 6) | (right_rgbi << 2) | phase
+    table: [i32; 1024],
+}
+
+impl NtscEncoder {
+    pub fn new(model: NtscModel) -> Self {
+        let mut encoder = Self {
+            model,
+            mono: false,
+            contrast: 0.0,
+            brightness: 0.0,
+            table: [0; 1024],
+        };
+        encoder.recalculate(model, false, 1.0, 1.0);
+        encoder
+    }
+
+    /// Rebuild the sample table. `mono` disables the color burst, as when the CGA's BW mode
+    /// bit is set.
+    pub fn recalculate(&mut self, model: NtscModel, mono: bool, contrast: f64, brightness: f64) {
+        self.model = model;
+        self.mono = mono;
+
+        let new_cga = model == NtscModel::NewCga;
+        let (min_v, max_v) = if new_cga {
+            let (i0, i3) = (INTENSITY[0], INTENSITY[3]);
+            (
+                new_cga_level(CHROMA_MULTIPLEXER[0] as f64, i0, i0, i0, i0),
+                new_cga_level(CHROMA_MULTIPLEXER[255] as f64, i3, i3, i3, i3),
+            )
+        }
+        else {
+            (
+                CHROMA_MULTIPLEXER[0] as f64 + INTENSITY[0],
+                CHROMA_MULTIPLEXER[255] as f64 + INTENSITY[3],
+            )
+        };
+
+        // The new CGA has a 120% contrast and -10 brightness offset relative to the old.
+        let brightness = (brightness - 1.0) * 10.0;
+        let base_contrast = 256.0 / (max_v - min_v);
+        self.contrast = base_contrast * contrast * (if new_cga { 1.2 } else { 1.0 });
+        self.brightness = -min_v * base_contrast + (if new_cga { brightness - 10.0 } else { brightness }) * 5.0;
+
+        for x in 0..1024 {
+            let phase = x & 3;
+            let right = (x >> 2) & 15;
+            let left = (x >> 6) & 15;
+            let mut rc = right;
+            let mut lc = left;
+
+            if mono {
+                // No colorburst: any color collapses to the full chroma level.
+                rc = (right & 8) | if (right & 7) != 0 { 7 } else { 0 };
+                lc = (left & 8) | if (left & 7) != 0 { 7 } else { 0 };
+            }
+            let c = CHROMA_MULTIPLEXER[((lc & 7) << 5) | ((rc & 7) << 2) | phase] as f64;
+            let i = INTENSITY[(left >> 3) | ((right >> 2) & 2)];
+            let v = if new_cga {
+                let r = INTENSITY[((left >> 2) & 1) | ((right >> 1) & 2)];
+                let g = INTENSITY[((left >> 1) & 1) | (right & 2)];
+                let b = INTENSITY[(left & 1) | ((right << 1) & 2)];
+                new_cga_level(c, i, r, g, b)
+            }
+            else {
+                c + i
+            };
+            self.table[x] = (v * self.contrast + self.brightness) as i32;
+        }
+    }
+
+    #[inline]
+    pub fn model(&self) -> NtscModel {
+        self.model
+    }
+
+    #[inline]
+    pub fn is_mono(&self) -> bool {
+        self.mono
+    }
+
+    /// Return the composite level produced for the transition between two RGBI values at the
+    /// specified subcarrier phase (0-3).
+    #[inline]
+    pub fn sample(&self, left: u8, right: u8, phase: usize) -> i32 {
+        self.table[(((left & 0x0F) as usize) << 6) | (((right & 0x0F) as usize) << 2) | (phase & 3)]
+    }
+
+    /// Encode a line of RGBI pixels into `out`, framed by `NTSC_BORDER_SAMPLES` samples of the
+    /// border color on either side. `out` is resized to `rgbi.len() + 2 * NTSC_BORDER_SAMPLES`.
+    pub fn encode_line(&self, border: u8, rgbi: &[u8], out: &mut Vec<i32>) {
+        out.clear();
+        if rgbi.is_empty() {
+            return;
+        }
+        let border = border & 0x0F;
+
+        for x in 0..4 {
+            out.push(self.sample(border, border, (x + 3) & 3));
+        }
+        out.push(self.sample(border, rgbi[0], 3));
+
+        for x in 0..rgbi.len() - 1 {
+            out.push(self.sample(rgbi[x], rgbi[x + 1], x));
+        }
+        out.push(self.sample(rgbi[rgbi.len() - 1], border, 3));
+
+        for x in 0..NTSC_BORDER_SAMPLES {
+            out.push(self.sample(border, border, x));
+        }
+    }
+
+    /// The composite samples of the color burst reference (color 6) over one subcarrier cycle.
+    fn burst(&self) -> [i32; 4] {
+        let base = 6 * 68;
+        [
+            self.table[base],
+            self.table[base + 1],
+            self.table[base + 2],
+            self.table[base + 3],
+        ]
+    }
+}
+
+/// Demodulates composite samples into 32-bit RGBA pixels.
+pub struct NtscDecoder {
+    sharpness: i32,
+    ri: i32,
+    rq: i32,
+    gi: i32,
+    gq: i32,
+    bi: i32,
+    bq: i32,
+    mono: bool,
+    temp: Vec<i32>,
+    atemp: Vec<i32>,
+    btemp: Vec<i32>,
+}
+
+impl NtscDecoder {
+    pub fn new() -> Self {
+        Self {
+            sharpness: 0,
+            ri: 0,
+            rq: 0,
+            gi: 0,
+            gq: 0,
+            bi: 0,
+            bq: 0,
+            mono: false,
+            temp: Vec::new(),
+            atemp: Vec::new(),
+            btemp: Vec::new(),
+        }
+    }
+
+    /// Recalculate the IQ demodulation matrix, locking to the encoder's color burst.
+    /// `hires_text` selects the hue correction used for 80 column text mode.
+    pub fn recalculate(&mut self, encoder: &NtscEncoder, params: &NtscParams, hires_text: bool) {
+        let new_cga = encoder.model() == NtscModel::NewCga;
+        let mode_hue = if hires_text { 14.0 } else { 4.0 };
+        let mode_saturation = (if new_cga { 4.35 } else { 2.9 }) * params.saturation;
+
+        let burst = encoder.burst();
+        let i = (burst[0] - burst[2]) as f64;
+        let q = (burst[1] - burst[3]) as f64;
+
+        let a = TAU * (33.0 + 90.0 + params.hue + mode_hue) / 360.0;
+        let (s, c) = a.sin_cos();
+        let r = 256.0 * mode_saturation / (i * i + q * q).sqrt();
+
+        let iq_adjust_i = -(i * c + q * s) * r;
+        let iq_adjust_q = (q * c - i * s) * r;
+
+        self.ri = (RI * iq_adjust_i + RQ * iq_adjust_q) as i32;
+        self.rq = (-RI * iq_adjust_q + RQ * iq_adjust_i) as i32;
+        self.gi = (GI * iq_adjust_i + GQ * iq_adjust_q) as i32;
+        self.gq = (-GI * iq_adjust_q + GQ * iq_adjust_i) as i32;
+        self.bi = (BI * iq_adjust_i + BQ * iq_adjust_q) as i32;
+        self.bq = (-BI * iq_adjust_q + BQ * iq_adjust_i) as i32;
+
+        self.sharpness = (params.sharpness * 256.0) as i32;
+        self.mono = encoder.is_mono();
+    }
+
+    /// Decode a line produced by `NtscEncoder::encode_line` into `out`, one RGBA pixel per
+    /// source pixel. Pixels are packed as 0xAABBGGRR.
+    pub fn decode_line(&mut self, signal: &[i32], out: &mut [u32]) {
+        if signal.len() <= NTSC_BORDER_SAMPLES * 2 {
+            return;
+        }
+        let w = (signal.len() - NTSC_BORDER_SAMPLES * 2).min(out.len());
+
+        self.temp.clear();
+        self.temp.extend_from_slice(signal);
+
+        if self.mono {
+            for (s, out_px) in self.temp[NTSC_BORDER_SAMPLES - 1..].windows(3).zip(&mut out[..w]) {
+                let c = (s[1] + s[1]) << 3;
+                let d = (s[0] + s[2]) << 3;
+                let y = ((c + d) << 8) + self.sharpness * (c - d);
+                *out_px = 0xFF00_0000 | (byte_clamp(y) as u32 * 0x0001_0101);
+            }
+            return;
+        }
+
+        // Separate chroma with a comb over one subcarrier cycle in each direction.
+        self.atemp.clear();
+        self.btemp.clear();
+        for x in 0..(w + 2) {
+            let i = x + 4;
+            self.atemp.push(
+                self.temp[i - 4] - ((self.temp[i - 2] - self.temp[i] + self.temp[i + 2]) << 1) + self.temp[i + 4],
+            );
+            self.btemp
+                .push((self.temp[i - 3] - self.temp[i - 1] + self.temp[i + 1] - self.temp[i + 3]) << 1);
+        }
+
+        const B: usize = NTSC_BORDER_SAMPLES;
+        self.temp[B - 1] = (self.temp[B - 1] << 3) - self.atemp[0];
+        self.temp[B] = (self.temp[B] << 3) - self.atemp[1];
+
+        for (x, out_px) in out[..w].iter_mut().enumerate() {
+            let i = x + B;
+            let p = x + 1;
+            self.temp[i + 1] = (self.temp[i + 1] << 3) - self.atemp[p + 1];
+            let a = self.atemp[p];
+            let b = self.btemp[p];
+
+            // Rotate the demodulated vector by the subcarrier phase of this sample.
+            let (a, b) = match x & 3 {
+                0 => (a, b),
+                1 => (-b, a),
+                2 => (-a, -b),
+                _ => (b, -a),
+            };
+
+            let c = self.temp[i] + self.temp[i];
+            let d = self.temp[i - 1] + self.temp[i + 1];
+            let y = ((c + d) << 8) + self.sharpness * (c - d);
+            let rr = y + self.ri * a + self.rq * b;
+            let gg = y + self.gi * a + self.gq * b;
+            let bb = y + self.bi * a + self.bq * b;
+
+            *out_px =
+                0xFF00_0000 | ((byte_clamp(bb) as u32) << 16) | ((byte_clamp(gg) as u32) << 8) | byte_clamp(rr) as u32;
+        }
+    }
+}
+
+impl Default for NtscDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A complete RGBI to RGBA composite pipeline.
+pub struct NtscPipeline {
+    params:    NtscParams,
+    mode_byte: u8,
+    encoder:   NtscEncoder,
+    decoder:   NtscDecoder,
+    signal:    Vec<i32>,
+}
+
+impl NtscPipeline {
+    pub fn new(params: NtscParams) -> Self {
+        let mut pipeline = Self {
+            params,
+            mode_byte: 0,
+            encoder: NtscEncoder::new(params.model),
+            decoder: NtscDecoder::new(),
+            signal: Vec::new(),
+        };
+        pipeline.recalculate();
+        pipeline
+    }
+
+    pub fn params(&self) -> &NtscParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: NtscParams) {
+        if params != self.params {
+            self.params = params;
+            self.recalculate();
+        }
+    }
+
+    /// Update the pipeline for a CGA mode control register value. Only the BW (bit 2) and
+    /// 80 column text (bits 0-1) state affect decoding.
+    pub fn set_mode_byte(&mut self, mode_byte: u8) {
+        if (mode_byte & 0x07) != (self.mode_byte & 0x07) {
+            self.mode_byte = mode_byte;
+            self.recalculate();
+        }
+    }
+
+    fn recalculate(&mut self) {
+        let mono = self.mode_byte & 0x04 != 0;
+        let hires_text = self.mode_byte & 0x03 == 0x01;
+        self.encoder
+            .recalculate(self.params.model, mono, self.params.contrast, self.params.brightness);
+        self.decoder.recalculate(&self.encoder, &self.params, hires_text);
+    }
+
+    /// Convert a line of RGBI pixels to RGBA pixels.
+    pub fn process_line(&mut self, border: u8, rgbi: &[u8], out: &mut [u32]) {
+        self.encoder.encode_line(border, rgbi, &mut self.signal);
+        self.decoder.decode_line(&self.signal, out);
+    }
+
+    /// Convert a full frame of RGBI pixels of the specified width to RGBA pixels.
+    pub fn process_frame(&mut self, border: u8, w: usize, rgbi: &[u8], out: &mut [u32]) {
+        if w == 0 {
+            return;
+        }
+        for (in_line, out_line) in rgbi.chunks_exact(w).zip(out.chunks_exact_mut(w)) {
+            self.process_line(border, in_line, out_line);
+        }
+    }
+}
+
+#[inline]
+fn byte_clamp(v: i32) -> u8 {
+    (v >> 13).clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(px: u32) -> (u8, u8, u8) {
+        (px as u8, (px >> 8) as u8, (px >> 16) as u8)
+    }
+
+    fn decode_solid(pipeline: &mut NtscPipeline, color: u8) -> u32 {
+        let rgbi = [color; 64];
+        let mut out = [0u32; 64];
+        pipeline.process_line(color, &rgbi, &mut out);
+        // Sample the middle of the line, away from the filter edges.
+        out[32]
+    }
+
+    #[test]
+    fn test_new_cga_level() {
+        // With all inputs at the same level, the new CGA weights sum to unity at each scale.
+        let (c, i) = (CHROMA_MULTIPLEXER[255] as f64, INTENSITY[3]);
+        let expected = (c / 0.72) * 0.29 + (i / 0.28) * 0.71;
+        assert!((new_cga_level(c, i, i, i, i) - expected).abs() < 1e-9);
+
+        // Luma rises monotonically with each of the R, G and B inputs.
+        let base = new_cga_level(c, INTENSITY[0], INTENSITY[0], INTENSITY[0], INTENSITY[0]);
+        assert!(new_cga_level(c, INTENSITY[0], INTENSITY[3], INTENSITY[0], INTENSITY[0]) > base);
+        assert!(new_cga_level(c, INTENSITY[0], INTENSITY[0], INTENSITY[3], INTENSITY[0]) > base);
+        assert!(new_cga_level(c, INTENSITY[0], INTENSITY[0], INTENSITY[0], INTENSITY[3]) > base);
+    }
+
+    #[test]
+    fn test_encode_line_length() {
+        let encoder = NtscEncoder::new(NtscModel::OldCga);
+        let mut signal = Vec::new();
+        encoder.encode_line(0, &[], &mut signal);
+        assert!(signal.is_empty());
+
+        encoder.encode_line(0, &[1, 2, 3, 4, 5, 6, 7, 8], &mut signal);
+        assert_eq!(signal.len(), 8 + 2 * NTSC_BORDER_SAMPLES);
+    }
+
+    #[test]
+    fn test_black_and_white() {
+        for model in [NtscModel::OldCga, NtscModel::NewCga] {
+            let mut pipeline = NtscPipeline::new(NtscParams {
+                model,
+                ..Default::default()
+            });
+            pipeline.set_mode_byte(0x0A);
+
+            let (r, g, b) = rgb(decode_solid(&mut pipeline, 0));
+            assert!(
+                r < 16 && g < 16 && b < 16,
+                "{:?} black decoded as {:?}",
+                model,
+                (r, g, b)
+            );
+
+            let (r, g, b) = rgb(decode_solid(&mut pipeline, 15));
+            assert!(
+                r > 224 && g > 224 && b > 224,
+                "{:?} white decoded as {:?}",
+                model,
+                (r, g, b)
+            );
+        }
+    }
+
+    #[test]
+    fn test_mono_is_grey() {
+        let mut pipeline = NtscPipeline::new(NtscParams::default());
+        // Setting the BW bit disables the color burst.
+        pipeline.set_mode_byte(0x0E);
+
+        let rgbi: Vec<u8> = (0..64).map(|x| (x / 4) as u8).collect();
+        let mut out = vec![0u32; rgbi.len()];
+        pipeline.process_line(0, &rgbi, &mut out);
+        for px in out {
+            let (r, g, b) = rgb(px);
+            assert!(r == g && g == b, "mono pixel {:08X} is not grey", px);
+        }
+    }
+
+    #[test]
+    fn test_artifact_color() {
+        let mut pipeline = NtscPipeline::new(NtscParams::default());
+        pipeline.set_mode_byte(0x1A);
+
+        // Pairs of black and white hdots repeat once per subcarrier cycle, producing a
+        // saturated artifact color rather than a grey.
+        let rgbi: Vec<u8> = (0..64).map(|x| ((x >> 1) & 1) as u8 * 15).collect();
+        let mut out = vec![0u32; rgbi.len()];
+        pipeline.process_line(0, &rgbi, &mut out);
+        let (r, g, b) = rgb(out[32]);
+        let spread = r.max(g).max(b) - r.min(g).min(b);
+        assert!(spread > 64, "expected artifact color, got {:?}", (r, g, b));
+    }
+
+    #[test]
+    fn test_process_frame() {
+        let mut pipeline = NtscPipeline::new(NtscParams::default());
+        let (w, h) = (32, 4);
+        let rgbi: Vec<u8> = (0..w * h).map(|x| (x / w) as u8 * 5).collect();
+        let mut frame = vec![0u32; w * h];
+        pipeline.process_frame(0, w, &rgbi, &mut frame);
+
+        // Each line decodes the same as processing it individually.
+        let mut line = vec![0u32; w];
+        for y in 0..h {
+            pipeline.process_line(0, &rgbi[y * w..(y + 1) * w], &mut line);
+            assert_eq!(&frame[y * w..(y + 1) * w], &line[..]);
+        }
+    }
+}
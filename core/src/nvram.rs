@@ -0,0 +1,165 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    nvram.rs
+
+    Persistence of device non-volatile memory to host files.
+
+    Each device implementing NonVolatile gets its own image file in the
+    machine's NVRAM directory, named after the device. Images are loaded
+    once when the directory is set, and written back whenever the machine
+    flushes its NVRAM - on reset, and when the frontend shuts down.
+
+    Device state restored from a save state replaces NVRAM contents like
+    any other write by the guest would, and is persisted on the next flush.
+*/
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error;
+
+use crate::device_traits::nonvolatile::NonVolatile;
+
+pub const NVRAM_FILE_EXTENSION: &str = "nvr";
+
+pub struct NvramStore {
+    dir: PathBuf,
+    // The image last read from or written to each file, so that unchanged images aren't rewritten.
+    persisted: HashMap<String, Vec<u8>>,
+}
+
+impl NvramStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            persisted: HashMap::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn image_path(&self, name: &str) -> PathBuf {
+        self.dir.join(name).with_extension(NVRAM_FILE_EXTENSION)
+    }
+
+    /// Load each device's image from its file. Devices without an image file keep their current
+    /// contents. An image a device rejects is logged and skipped, and will be overwritten on the
+    /// next save. Returns the number of images loaded.
+    pub fn load(&mut self, devices: Vec<&mut dyn NonVolatile>) -> usize {
+        let mut loaded = 0;
+        for device in devices {
+            let name = device.nvram_name();
+            let path = self.image_path(&name);
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    log::warn!("Failed to read NVRAM image {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            match device.load_nvram(&data) {
+                Ok(()) => {
+                    log::debug!("Loaded NVRAM image {:?}", path);
+                    self.persisted.insert(name, data);
+                    loaded += 1;
+                }
+                Err(e) => log::warn!("Ignoring NVRAM image {:?}: {}", path, e),
+            }
+        }
+        loaded
+    }
+
+    /// Write the image of each device whose contents have changed since they were last loaded
+    /// or saved. Returns the number of images written.
+    pub fn save(&mut self, devices: Vec<&dyn NonVolatile>) -> Result<usize, Error> {
+        let mut written = 0;
+        for device in devices {
+            let name = device.nvram_name();
+            let data = device.nvram();
+            if self.persisted.get(&name) == Some(&data) {
+                continue;
+            }
+            std::fs::create_dir_all(&self.dir)?;
+
+            // Write to a temporary file first, so that an interrupted write can't leave a
+            // truncated image behind.
+            let path = self.image_path(&name);
+            let tmp_path = path.with_extension(format!("{}.tmp", NVRAM_FILE_EXTENSION));
+            std::fs::write(&tmp_path, &data)?;
+            std::fs::rename(&tmp_path, &path)?;
+
+            log::debug!("Saved NVRAM image {:?}", path);
+            self.persisted.insert(name, data);
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bus::{DeviceRunTimeUnit, IoDevice},
+        devices::rtc::Rtc,
+    };
+
+    #[test]
+    fn test_nvram_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("martypc_nvram_test_{}", std::process::id()));
+        let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
+
+        let mut rtc = Rtc::new(None);
+        rtc.write_u8(0x2C9, 0x87, None, nul_delta); // RAM latch 1
+        rtc.write_u8(0x2C3, 0x42, None, nul_delta); // Minutes
+
+        let mut store = NvramStore::new(&dir);
+        assert_eq!(store.save(vec![&rtc]).unwrap(), 1);
+        // Unchanged contents are not rewritten.
+        assert_eq!(store.save(vec![&rtc]).unwrap(), 0);
+
+        let mut restored = Rtc::new(None);
+        let mut store = NvramStore::new(&dir);
+        assert_eq!(store.load(vec![&mut restored]), 1);
+        assert_eq!(restored.read_u8(0x2C9, nul_delta), 0x87);
+        assert_eq!(restored.read_u8(0x2C3, nul_delta), 0x42);
+
+        // A truncated image is rejected, leaving the device untouched.
+        std::fs::write(store.image_path("rtc"), [0x55; 4]).unwrap();
+        let mut fresh = Rtc::new(None);
+        assert_eq!(NvramStore::new(&dir).load(vec![&mut fresh]), 0);
+        assert_eq!(fresh.read_u8(0x2C9, nul_delta), 0);
+
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,309 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    profiler.rs
+
+    Execution profiling. The ROM profile splits executed cycles between code
+    running from ROM (the BIOS and adapter ROMs) and code running from RAM,
+    in total and per interrupt handler, to show how much time software
+    spends waiting in BIOS services.
+
+    Cycles are attributed to the innermost interrupt on the CPU's call stack.
+    As with the debugger's call stack view, a handler that never returns,
+    such as the INT 19h bootstrap, remains on the stack, so code it transfers
+    control to is attributed to it.
+
+    The cycle profile accumulates executed cycles per instruction address, or
+    per 16-byte block of memory, to find the loops that consume the most
+    emulated time. Cycles spent halted are attributed to the address following
+    the HLT instruction.
+*/
+
+use std::collections::HashMap;
+
+use crate::cpu_808x::CpuAddress;
+
+pub const PROFILE_BLOCK_SIZE: u32 = 16;
+
+/// Cycles executed from ROM and from RAM.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RegionCycles {
+    pub rom: u64,
+    pub ram: u64,
+}
+
+impl RegionCycles {
+    #[inline]
+    fn add(&mut self, rom: bool, cycles: u32) {
+        if rom {
+            self.rom += cycles as u64;
+        }
+        else {
+            self.ram += cycles as u64;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.rom + self.ram
+    }
+
+    /// Return the fraction of cycles executed from ROM, or 0.0 if no cycles were executed.
+    pub fn rom_fraction(&self) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => self.rom as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RomProfile {
+    total: RegionCycles,
+    outside_interrupt: RegionCycles,
+    vectors: Vec<RegionCycles>,
+}
+
+impl Default for RomProfile {
+    fn default() -> Self {
+        Self {
+            total: RegionCycles::default(),
+            outside_interrupt: RegionCycles::default(),
+            vectors: vec![RegionCycles::default(); 256],
+        }
+    }
+}
+
+impl RomProfile {
+    /// Account for an instruction that executed from ROM or RAM within the specified interrupt
+    /// handler, if any.
+    #[inline]
+    pub fn add(&mut self, rom: bool, interrupt: Option<u8>, cycles: u32) {
+        self.total.add(rom, cycles);
+        match interrupt {
+            Some(vector) => self.vectors[vector as usize].add(rom, cycles),
+            None => self.outside_interrupt.add(rom, cycles),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Return the cycles executed since profiling began or was last reset.
+    pub fn total(&self) -> RegionCycles {
+        self.total
+    }
+
+    /// Return the cycles executed outside any interrupt handler.
+    pub fn outside_interrupt(&self) -> RegionCycles {
+        self.outside_interrupt
+    }
+
+    /// Return the cycles executed within the handler for the specified interrupt vector,
+    /// including any interrupts nested within it.
+    pub fn vector(&self, vector: u8) -> RegionCycles {
+        self.vectors[vector as usize]
+    }
+
+    /// Return the interrupt vectors that executed any cycles, busiest first.
+    pub fn by_interrupt(&self) -> Vec<(u8, RegionCycles)> {
+        let mut vectors: Vec<(u8, RegionCycles)> = self
+            .vectors
+            .iter()
+            .enumerate()
+            .filter(|(_, cycles)| cycles.total() > 0)
+            .map(|(vector, cycles)| (vector as u8, *cycles))
+            .collect();
+        vectors.sort_by_key(|(_, cycles)| std::cmp::Reverse(cycles.total()));
+        vectors
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ProfileGranularity {
+    /// Count cycles per CS:IP.
+    #[default]
+    Instruction,
+    /// Count cycles per 16-byte block of the address space.
+    Block,
+}
+
+/// The cycles and instructions executed at an instruction address or memory block.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HotSpot {
+    /// A segmented address for instruction granularity, or the flat address of the start of a
+    /// block for block granularity.
+    pub address: CpuAddress,
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CycleProfile {
+    granularity: ProfileGranularity,
+    counters: HashMap<u32, (u64, u64)>,
+    total: u64,
+}
+
+impl CycleProfile {
+    pub fn new(granularity: ProfileGranularity) -> Self {
+        Self {
+            granularity,
+            ..Default::default()
+        }
+    }
+
+    /// Account for an instruction executed at the specified CS:IP.
+    #[inline]
+    pub fn add(&mut self, cs: u16, ip: u16, cycles: u32) {
+        let key = match self.granularity {
+            ProfileGranularity::Instruction => (cs as u32) << 16 | ip as u32,
+            ProfileGranularity::Block => u32::from(CpuAddress::Segmented(cs, ip)) / PROFILE_BLOCK_SIZE,
+        };
+        let counter = self.counters.entry(key).or_default();
+        counter.0 += cycles as u64;
+        counter.1 += 1;
+        self.total += cycles as u64;
+    }
+
+    pub fn reset(&mut self) {
+        self.counters.clear();
+        self.total = 0;
+    }
+
+    pub fn granularity(&self) -> ProfileGranularity {
+        self.granularity
+    }
+
+    /// Return the cycles executed since profiling began or was last reset.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Return up to `count` of the addresses that executed the most cycles, busiest first.
+    pub fn hottest(&self, count: usize) -> Vec<HotSpot> {
+        let mut hot_spots: Vec<HotSpot> = self
+            .counters
+            .iter()
+            .map(|(key, (cycles, instructions))| HotSpot {
+                address: match self.granularity {
+                    ProfileGranularity::Instruction => CpuAddress::Segmented((key >> 16) as u16, *key as u16),
+                    ProfileGranularity::Block => CpuAddress::Flat(key * PROFILE_BLOCK_SIZE),
+                },
+                cycles: *cycles,
+                instructions: *instructions,
+            })
+            .collect();
+        // Break ties by address so that reports are stable.
+        hot_spots.sort_by(|a, b| {
+            b.cycles
+                .cmp(&a.cycles)
+                .then(u32::from(a.address).cmp(&u32::from(b.address)))
+        });
+        hot_spots.truncate(count);
+        hot_spots
+    }
+
+    /// Format up to `count` of the busiest addresses as a table of cycles, share of all cycles
+    /// executed, and instructions executed.
+    pub fn report(&self, count: usize) -> String {
+        let mut report = format!("{:<10} {:>12} {:>7} {:>12}\n", "Address", "Cycles", "%", "Instructions");
+        for hot_spot in self.hottest(count) {
+            report.push_str(&format!(
+                "{:<10} {:>12} {:>6.2}% {:>12}\n",
+                hot_spot.address.to_string(),
+                hot_spot.cycles,
+                hot_spot.cycles as f64 * 100.0 / self.total.max(1) as f64,
+                hot_spot.instructions
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_profile() {
+        let mut profile = RomProfile::default();
+        profile.add(false, None, 10);
+        profile.add(true, Some(0x16), 30);
+        profile.add(false, Some(0x16), 10);
+        profile.add(true, Some(0x10), 20);
+
+        assert_eq!(profile.total(), RegionCycles { rom: 50, ram: 20 });
+        assert_eq!(profile.outside_interrupt(), RegionCycles { rom: 0, ram: 10 });
+        assert_eq!(profile.vector(0x16).rom_fraction(), 0.75);
+        assert_eq!(
+            profile.by_interrupt(),
+            vec![
+                (0x16, RegionCycles { rom: 30, ram: 10 }),
+                (0x10, RegionCycles { rom: 20, ram: 0 })
+            ]
+        );
+
+        profile.reset();
+        assert_eq!(profile.total().total(), 0);
+    }
+
+    #[test]
+    fn test_cycle_profile() {
+        let mut profile = CycleProfile::new(ProfileGranularity::Instruction);
+        profile.add(0x1000, 0x0010, 4);
+        profile.add(0x1000, 0x0012, 9);
+        profile.add(0x1000, 0x0010, 4);
+        profile.add(0x1001, 0x0000, 2);
+
+        let hot_spots = profile.hottest(2);
+        assert_eq!(profile.total(), 19);
+        assert_eq!(hot_spots.len(), 2);
+        assert!(matches!(hot_spots[0].address, CpuAddress::Segmented(0x1000, 0x0012)));
+        assert_eq!((hot_spots[0].cycles, hot_spots[0].instructions), (9, 1));
+        assert!(matches!(hot_spots[1].address, CpuAddress::Segmented(0x1000, 0x0010)));
+        assert_eq!((hot_spots[1].cycles, hot_spots[1].instructions), (8, 2));
+
+        // 1000:0010, 1000:0012 and 1001:0000 all fall within the block at 10010h.
+        let mut profile = CycleProfile::new(ProfileGranularity::Block);
+        profile.add(0x1000, 0x0010, 4);
+        profile.add(0x1000, 0x0012, 9);
+        profile.add(0x1001, 0x0000, 2);
+        profile.add(0x1000, 0x0020, 1);
+
+        let hot_spots = profile.hottest(10);
+        assert_eq!(hot_spots.len(), 2);
+        assert!(matches!(hot_spots[0].address, CpuAddress::Flat(0x10010)));
+        assert_eq!((hot_spots[0].cycles, hot_spots[0].instructions), (15, 3));
+
+        let report = profile.report(1);
+        let row = report.lines().nth(1).unwrap();
+        assert!(row.starts_with("10010 ") && row.contains(" 93.75% "));
+
+        profile.reset();
+        assert!(profile.hottest(10).is_empty());
+    }
+}
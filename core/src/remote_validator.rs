@@ -0,0 +1,638 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    remote_validator.rs
+
+    Implements a CpuValidator that forwards each instruction to a remote
+    validation daemon over TCP. The daemon may drive real hardware attached
+    to another machine, or be a software oracle. The daemon executes the
+    instruction and returns the resulting bus operations, cycle states and
+    registers, which are compared against the emulator here.
+
+    The protocol is line-based text. Register sets are encoded as in
+    VRegisters::to_hex_string().
+
+      -> HELLO MARTYPC-VALIDATOR 1
+      <- HELLO <name> 1
+      -> MODE INSTRUCTION|CYCLE
+      <- OK
+      -> SETREGS <registers>       Reset the CPU and load registers.
+      <- OK
+      -> BEGIN <registers> <instruction end> <program end>
+      -> OP <type> <address> <byte>
+                                   Bus operations performed by the emulator,
+                                   so the daemon can supply the same data.
+      -> EXEC <flags> <peek fetch> <has modrm> <instruction bytes> <name>
+      <- CYCLE <address> <ale> <segment> <bus state> <t-state> <signals>
+               <data bus> <queue op> <queue byte> <queue contents>
+                                   Zero or more, in Cycle mode. Signals are
+                                   six 0/1 characters for the active-low
+                                   MRDC AMWC MWTC IORC AIOWC IOWC lines.
+      <- OP <type> <address> <byte>
+                                   Zero or more bus operations.
+      <- REGS <registers>
+      <- DONE OK|END
+
+    Bus operation types are CODE, MEMR, MEMW, IOR and IOW. Numbers are hex.
+    Any line from the daemon beginning with ERR fails validation.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::{cpu_808x::QueueOp, cpu_validator::*, tracelogger::TraceLogger};
+
+pub const REMOTE_VALIDATOR_PROTOCOL_VERSION: u32 = 1;
+pub const REMOTE_VALIDATOR_DEFAULT_ADDRESS: &str = "127.0.0.1:7851";
+/// FLAGS bits compared when masking undefined flags: OF DF IF TF SF ZF AF PF CF.
+const DEFINED_FLAGS_MASK: u16 = 0x0FD5;
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+macro_rules! trace_error {
+    ($self:ident, $($t:tt)*) => {{
+        log::error!("{}", &format!($($t)*));
+        $self.trace_logger.print(&format!($($t)*));
+        $self.trace_logger.print("\n".to_string());
+    }};
+}
+
+fn bus_op_type_str(op_type: BusOpType) -> &'static str {
+    match op_type {
+        BusOpType::CodeRead => "CODE",
+        BusOpType::MemRead => "MEMR",
+        BusOpType::MemWrite => "MEMW",
+        BusOpType::IoRead => "IOR",
+        BusOpType::IoWrite => "IOW",
+    }
+}
+
+fn parse_hex(s: Option<&str>) -> Result<u32, Error> {
+    let s = s.ok_or_else(|| anyhow!("Missing field"))?;
+    u32::from_str_radix(s, 16).map_err(|_| anyhow!("Bad hex value: {}", s))
+}
+
+fn parse_bus_op(fields: &str) -> Result<BusOp, Error> {
+    let mut parts = fields.split_whitespace();
+    let op_type = match parts.next() {
+        Some("CODE") => BusOpType::CodeRead,
+        Some("MEMR") => BusOpType::MemRead,
+        Some("MEMW") => BusOpType::MemWrite,
+        Some("IOR") => BusOpType::IoRead,
+        Some("IOW") => BusOpType::IoWrite,
+        other => bail!("Bad bus operation type: {:?}", other),
+    };
+    Ok(BusOp {
+        op_type,
+        addr: parse_hex(parts.next())?,
+        data: parse_hex(parts.next())? as u8,
+        flags: 0,
+    })
+}
+
+fn parse_cycle_state(n: u32, fields: &str) -> Result<CycleState, Error> {
+    let mut parts = fields.split_whitespace();
+    let addr = parse_hex(parts.next())?;
+    let ale = parts.next() == Some("1");
+    let a_type = match parts.next() {
+        Some("ES") => AccessType::AlternateData,
+        Some("SS") => AccessType::Stack,
+        Some("DS") => AccessType::Data,
+        _ => AccessType::CodeOrNone,
+    };
+    let b_state = match parts.next() {
+        Some("INTA") => BusState::INTA,
+        Some("IOR") => BusState::IOR,
+        Some("IOW") => BusState::IOW,
+        Some("HALT") => BusState::HALT,
+        Some("CODE") => BusState::CODE,
+        Some("MEMR") => BusState::MEMR,
+        Some("MEMW") => BusState::MEMW,
+        Some("PASV") => BusState::PASV,
+        other => bail!("Bad bus state: {:?}", other),
+    };
+    let t_state = match parts.next() {
+        Some("Ti") => BusCycle::Ti,
+        Some("T1") => BusCycle::T1,
+        Some("T2") => BusCycle::T2,
+        Some("T3") => BusCycle::T3,
+        Some("T4") => BusCycle::T4,
+        Some("Tw") => BusCycle::Tw,
+        other => bail!("Bad T-state: {:?}", other),
+    };
+    let signals: Vec<bool> = parts
+        .next()
+        .ok_or_else(|| anyhow!("Missing bus signals"))?
+        .chars()
+        .map(|c| c == '1')
+        .collect();
+    if signals.len() != 6 {
+        bail!("Expected 6 bus signals");
+    }
+    let data_bus = parse_hex(parts.next())? as u16;
+    let q_op = match parts.next() {
+        Some("F") => QueueOp::First,
+        Some("S") => QueueOp::Subsequent,
+        Some("E") => QueueOp::Flush,
+        _ => QueueOp::Idle,
+    };
+    let q_byte = parse_hex(parts.next())? as u8;
+
+    let mut q = [0; 6];
+    let mut q_len = 0;
+    if let Some(queue) = parts.next().filter(|q| *q != "-") {
+        for (i, byte) in queue.as_bytes().chunks(2).take(q.len()).enumerate() {
+            q[i] = u8::from_str_radix(std::str::from_utf8(byte)?, 16)?;
+            q_len += 1;
+        }
+    }
+
+    Ok(CycleState {
+        n,
+        addr,
+        t_state,
+        a_type,
+        b_state,
+        ale,
+        mrdc: signals[0],
+        amwc: signals[1],
+        mwtc: signals[2],
+        iorc: signals[3],
+        aiowc: signals[4],
+        iowc: signals[5],
+        inta: matches!(b_state, BusState::INTA),
+        q_op,
+        q_byte,
+        q_len,
+        q,
+        data_bus,
+    })
+}
+
+#[derive(Default)]
+struct RemoteInstruction {
+    name: String,
+    instr: Vec<u8>,
+    instr_end: usize,
+    discard: bool,
+    regs: [VRegisters; 2],
+    emu_ops: Vec<BusOp>,
+}
+
+pub struct RemoteValidator {
+    mode: ValidatorMode,
+    mask_flags: bool,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    remote_name: String,
+
+    current_instr: RemoteInstruction,
+    end_addr: usize,
+
+    last_cpu_states: Vec<CycleState>,
+    last_cpu_ops:    Vec<BusOp>,
+    last_cpu_queue:  Vec<u8>,
+    last_cpu_regs:   VRegisters,
+
+    trace_logger: TraceLogger,
+}
+
+impl RemoteValidator {
+    pub fn new(trace_logger: TraceLogger, address: &str) -> Result<Self, Error> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+        let writer = stream.try_clone()?;
+
+        let mut validator = Self {
+            mode: ValidatorMode::Instruction,
+            mask_flags: true,
+            reader: BufReader::new(stream),
+            writer,
+            remote_name: String::new(),
+            current_instr: Default::default(),
+            end_addr: 0,
+            last_cpu_states: Vec::new(),
+            last_cpu_ops: Vec::new(),
+            last_cpu_queue: Vec::new(),
+            last_cpu_regs: Default::default(),
+            trace_logger,
+        };
+
+        validator.send(&format!(
+            "HELLO MARTYPC-VALIDATOR {}",
+            REMOTE_VALIDATOR_PROTOCOL_VERSION
+        ))?;
+        let reply = validator.recv()?;
+        let fields: Vec<&str> = reply.split_whitespace().collect();
+        match fields.as_slice() {
+            ["HELLO", name, version] if *version == REMOTE_VALIDATOR_PROTOCOL_VERSION.to_string() => {
+                validator.remote_name = name.to_string();
+            }
+            _ => bail!("Bad handshake from validation daemon: {}", reply),
+        }
+        log::debug!("Connected to remote validator {} at {}", validator.remote_name, address);
+        Ok(validator)
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), Error> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("Validation daemon closed the connection");
+        }
+        let line = line.trim().to_string();
+        if line.starts_with("ERR") {
+            bail!("Validation daemon reported an error: {}", line);
+        }
+        Ok(line)
+    }
+
+    fn expect_ok(&mut self) -> Result<(), Error> {
+        let reply = self.recv()?;
+        if reply != "OK" {
+            bail!("Unexpected reply from validation daemon: {}", reply);
+        }
+        Ok(())
+    }
+
+    /// Send the current instruction to the daemon and collect its results.
+    /// Returns the cycle states, bus operations, queue contents and registers from the daemon,
+    /// and whether the daemon has reached the end of the program.
+    fn exec_remote(
+        &mut self,
+        flags: u8,
+        peek_fetch: u16,
+        has_modrm: bool,
+    ) -> Result<(Vec<CycleState>, Vec<BusOp>, Vec<u8>, VRegisters, bool), Error> {
+        let begin = format!(
+            "BEGIN {} {:05X} {:05X}",
+            self.current_instr.regs[0].to_hex_string(),
+            self.current_instr.instr_end,
+            self.end_addr
+        );
+        self.send(&begin)?;
+
+        let ops: Vec<String> = self
+            .current_instr
+            .emu_ops
+            .iter()
+            .map(|op| format!("OP {} {:05X} {:02X}", bus_op_type_str(op.op_type), op.addr, op.data))
+            .collect();
+        for op in ops {
+            self.send(&op)?;
+        }
+
+        let instr_hex: String = self.current_instr.instr.iter().map(|b| format!("{:02X}", b)).collect();
+        let exec = format!(
+            "EXEC {:02X} {:04X} {} {} {}",
+            flags, peek_fetch, has_modrm as u8, instr_hex, self.current_instr.name
+        );
+        self.send(&exec)?;
+
+        let mut states = Vec::new();
+        let mut ops = Vec::new();
+        let mut queue = Vec::new();
+        let mut regs = None;
+        loop {
+            let line = self.recv()?;
+            let (tag, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+            match tag {
+                "CYCLE" => states.push(parse_cycle_state(states.len() as u32, rest)?),
+                "OP" => ops.push(parse_bus_op(rest)?),
+                "QUEUE" => {
+                    queue = rest
+                        .as_bytes()
+                        .chunks(2)
+                        .map(|b| u8::from_str_radix(std::str::from_utf8(b)?, 16).map_err(|e| anyhow!(e)))
+                        .collect::<Result<Vec<u8>, Error>>()?;
+                }
+                "REGS" => regs = Some(VRegisters::from_str(rest).map_err(|e| anyhow!(e))?),
+                "DONE" => {
+                    let regs = regs.ok_or_else(|| anyhow!("Validation daemon sent no registers"))?;
+                    return Ok((states, ops, queue, regs, rest.trim() == "END"));
+                }
+                _ => bail!("Unexpected reply from validation daemon: {}", line),
+            }
+        }
+    }
+
+    fn flag_mask(&self) -> u16 {
+        if self.mask_flags {
+            DEFINED_FLAGS_MASK
+        }
+        else {
+            0xFFFF
+        }
+    }
+
+    /// Compare the emulator's bus operations to the daemon's. Code fetches are not compared,
+    /// as prefetch timing is covered by cycle validation.
+    fn validate_ops(&mut self, flags: u8, cpu_ops: &[BusOp]) -> bool {
+        let filter = |ops: &[BusOp], reads: bool| -> Vec<(BusOpType, u32, u8)> {
+            ops.iter()
+                .filter(|op| match op.op_type {
+                    BusOpType::MemRead | BusOpType::IoRead => reads,
+                    BusOpType::MemWrite | BusOpType::IoWrite => !reads,
+                    BusOpType::CodeRead => false,
+                })
+                .map(|op| (op.op_type, op.addr, op.data))
+                .collect()
+        };
+
+        let mut ok = true;
+        for (reads, skip_flag) in [(true, VAL_NO_READS), (false, VAL_NO_WRITES)] {
+            if flags & skip_flag != 0 {
+                continue;
+            }
+            let emu = filter(&self.current_instr.emu_ops, reads);
+            let cpu = filter(cpu_ops, reads);
+            if emu != cpu {
+                trace_error!(self, "Bus operation mismatch:\nEMU: {:X?}\nCPU: {:X?}", emu, cpu);
+                ok = false;
+            }
+        }
+        ok
+    }
+
+    fn validate_cycles(&mut self, flags: u8, cpu_states: &[CycleState], emu_states: &[CycleState]) -> bool {
+        let allowed = if flags & VAL_ALLOW_ONE != 0 { 1 } else { 0 };
+        if difference(cpu_states.len(), emu_states.len()) > allowed {
+            trace_error!(
+                self,
+                "Cycle count mismatch: EMU: {} CPU: {}",
+                emu_states.len(),
+                cpu_states.len()
+            );
+            return false;
+        }
+        if allowed == 0 {
+            for (i, (cpu, emu)) in cpu_states.iter().zip(emu_states.iter()).enumerate() {
+                if cpu != emu {
+                    trace_error!(self, "Cycle state mismatch at cycle {}:\nEMU: {}\nCPU: {}", i, emu, cpu);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn difference(a: usize, b: usize) -> usize {
+    if a > b {
+        a - b
+    }
+    else {
+        b - a
+    }
+}
+
+impl CpuValidator for RemoteValidator {
+    fn init(&mut self, mode: ValidatorMode, mask_flags: bool, _cycle_trace: bool, _visit_once: bool) -> bool {
+        self.mode = mode;
+        self.mask_flags = mask_flags;
+        let mode_str = match mode {
+            ValidatorMode::Instruction => "MODE INSTRUCTION",
+            ValidatorMode::Cycle => "MODE CYCLE",
+        };
+        match self.send(mode_str).and_then(|_| self.expect_ok()) {
+            Ok(_) => true,
+            Err(e) => {
+                log::error!("Failed to initialize remote validator: {}", e);
+                false
+            }
+        }
+    }
+
+    fn reset_instruction(&mut self) {
+        self.current_instr.emu_ops.clear();
+    }
+
+    fn begin_instruction(&mut self, regs: &VRegisters, end_instr: usize, end_program: usize) {
+        self.current_instr.discard = false;
+        self.current_instr.regs[0] = *regs;
+        self.current_instr.instr_end = end_instr;
+        self.end_addr = end_program;
+    }
+
+    fn set_regs(&mut self) {
+        let setregs = format!("SETREGS {}", self.current_instr.regs[0].to_hex_string());
+        if let Err(e) = self.send(&setregs).and_then(|_| self.expect_ok()) {
+            trace_error!(self, "Failed to set registers on remote validator: {}", e);
+        }
+    }
+
+    fn validate_instruction(
+        &mut self,
+        name: String,
+        instr: &[u8],
+        flags: u8,
+        peek_fetch: u16,
+        has_modrm: bool,
+        _cycles: i32,
+        regs: &VRegisters,
+        emu_states: &[CycleState],
+    ) -> Result<ValidatorResult, ValidatorError> {
+        if instr.is_empty() {
+            trace_error!(self, "Instruction length was 0");
+            return Err(ValidatorError::ParameterError);
+        }
+
+        self.current_instr.name = name;
+        self.current_instr.instr = instr.to_vec();
+        self.current_instr.regs[1] = *regs;
+
+        if self.current_instr.discard {
+            self.reset_instruction();
+            return Ok(ValidatorResult::Ok);
+        }
+
+        let (cpu_states, cpu_ops, cpu_queue, cpu_regs, end) = match self.exec_remote(flags, peek_fetch, has_modrm) {
+            Ok(result) => result,
+            Err(e) => {
+                trace_error!(self, "Remote validator error: {}", e);
+                return Err(ValidatorError::CpuError);
+            }
+        };
+
+        if !self.validate_ops(flags, &cpu_ops) {
+            return Err(ValidatorError::MemOpMismatch);
+        }
+
+        if flags & VAL_NO_CYCLES == 0
+            && !emu_states.is_empty()
+            && !cpu_states.is_empty()
+            && !self.validate_cycles(flags, &cpu_states, emu_states)
+        {
+            return Err(ValidatorError::CycleMismatch);
+        }
+
+        self.last_cpu_states = cpu_states;
+        self.last_cpu_ops = cpu_ops;
+        self.last_cpu_queue = cpu_queue;
+        self.last_cpu_regs = cpu_regs;
+
+        if flags & VAL_NO_REGS == 0 {
+            let mask = if flags & VAL_NO_FLAGS != 0 { 0 } else { self.flag_mask() };
+            let diffs = regs.diff(&cpu_regs, mask);
+            if !diffs.is_empty() {
+                trace_error!(
+                    self,
+                    "Register mismatch after {}: {:X?}\nEMU:\n{}\nCPU:\n{}",
+                    self.current_instr.name,
+                    diffs,
+                    regs,
+                    cpu_regs
+                );
+                return Err(ValidatorError::RegisterMismatch);
+            }
+        }
+
+        self.reset_instruction();
+        match end {
+            true => Ok(ValidatorResult::OkEnd),
+            false => Ok(ValidatorResult::Ok),
+        }
+    }
+
+    fn validate_regs(&mut self, regs: &VRegisters) -> Result<(), ValidatorError> {
+        let diffs = regs.diff(&self.last_cpu_regs, self.flag_mask());
+        if !diffs.is_empty() {
+            trace_error!(self, "Register validation failure: {:X?}", diffs);
+            return Err(ValidatorError::RegisterMismatch);
+        }
+        Ok(())
+    }
+
+    fn emu_read_byte(&mut self, addr: u32, data: u8, bus_type: BusType, read_type: ReadType) {
+        if self.current_instr.discard {
+            return;
+        }
+        let op_type = match (bus_type, read_type) {
+            (BusType::Mem, ReadType::Code) => BusOpType::CodeRead,
+            (BusType::Mem, ReadType::Data) => BusOpType::MemRead,
+            (BusType::Io, _) => BusOpType::IoRead,
+        };
+        self.current_instr.emu_ops.push(BusOp {
+            op_type,
+            addr,
+            data,
+            flags: 0,
+        });
+    }
+
+    fn emu_write_byte(&mut self, addr: u32, data: u8, bus_type: BusType) {
+        if self.current_instr.discard {
+            return;
+        }
+        let op_type = match bus_type {
+            BusType::Mem => BusOpType::MemWrite,
+            BusType::Io => BusOpType::IoWrite,
+        };
+        self.current_instr.emu_ops.push(BusOp {
+            op_type,
+            addr,
+            data,
+            flags: 0,
+        });
+    }
+
+    fn discard_op(&mut self) {
+        self.current_instr.discard = true;
+    }
+
+    fn flush(&mut self) {
+        _ = self.writer.flush();
+        self.trace_logger.flush();
+    }
+
+    fn cycle_states(&self) -> &Vec<CycleState> {
+        &self.last_cpu_states
+    }
+
+    fn name(&self) -> String {
+        self.current_instr.name.clone()
+    }
+
+    fn instr_bytes(&self) -> Vec<u8> {
+        self.current_instr.instr.clone()
+    }
+
+    fn initial_regs(&self) -> VRegisters {
+        self.current_instr.regs[0]
+    }
+
+    fn final_regs(&self) -> VRegisters {
+        self.current_instr.regs[1]
+    }
+
+    fn cpu_ops(&self) -> Vec<BusOp> {
+        self.last_cpu_ops.clone()
+    }
+
+    fn cpu_reads(&self) -> Vec<BusOp> {
+        self.last_cpu_ops
+            .iter()
+            .take_while(|op| matches!(op.op_type, BusOpType::CodeRead | BusOpType::MemRead | BusOpType::IoRead))
+            .filter(|op| !matches!(op.op_type, BusOpType::CodeRead))
+            .cloned()
+            .collect()
+    }
+
+    fn cpu_queue(&self) -> Vec<u8> {
+        self.last_cpu_queue.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cycle_state() {
+        let state = parse_cycle_state(0, "FFFF0 1 CS CODE T1 111111 00 - 00 -").unwrap();
+        assert!(state.ale);
+        assert_eq!(state.addr, 0xFFFF0);
+        assert_eq!(state.b_state, BusState::CODE);
+        assert_eq!(state.q_len, 0);
+
+        let state = parse_cycle_state(1, "FFFF0 0 CS PASV T3 011111 EA F EA EA00").unwrap();
+        assert!(!state.mrdc);
+        assert_eq!(state.q_op, QueueOp::First);
+        assert_eq!(state.queue_vec(), vec![0xEA, 0x00]);
+
+        let op = parse_bus_op("MEMW 00400 2A").unwrap();
+        assert_eq!((op.op_type, op.addr, op.data), (BusOpType::MemWrite, 0x400, 0x2A));
+    }
+}
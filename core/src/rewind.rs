@@ -0,0 +1,125 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    rewind.rs
+
+    Implements a ring buffer of per-instruction rewind frames so that the
+    debugger can step execution backwards.
+
+    Each frame holds the CPU register state from before an instruction
+    executed, and a journal of the writes made while it executed. Memory
+    writes are journaled with the value they replaced, so they can be undone
+    by restoring those values in reverse order. IO writes are journaled for
+    inspection only - device state is not rewound, nor is video memory or
+    the passage of time.
+*/
+
+use std::collections::VecDeque;
+
+use crate::cpu_808x::CpuRegisterState;
+
+pub const DEFAULT_REWIND_LEN: usize = 4096;
+
+/// Writes made to the bus while journaling is enabled.
+#[derive(Clone, Debug, Default)]
+pub struct WriteJournal {
+    /// The address and previous value of each byte of conventional memory written.
+    pub memory: Vec<(usize, u8)>,
+    /// The port and value of each IO write.
+    pub io: Vec<(u16, u8)>,
+}
+
+impl WriteJournal {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.io.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RewindFrame {
+    /// The CPU register state before the instruction executed.
+    pub regs:    CpuRegisterState,
+    /// Whether the CPU was halted before the instruction executed.
+    pub halted:  bool,
+    /// The CPU cycle count before the instruction executed.
+    pub cycle:   u64,
+    pub journal: WriteJournal,
+}
+
+pub struct RewindBuffer {
+    frames:   VecDeque<RewindFrame>,
+    capacity: usize,
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_LEN)
+    }
+}
+
+impl RewindBuffer {
+    /// Create a rewind buffer that retains up to `capacity` of the most recent frames.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames:   VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Record a frame, discarding the oldest frame if the buffer is full.
+    pub fn push(&mut self, frame: RewindFrame) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Remove and return the most recent frame.
+    pub fn pop(&mut self) -> Option<RewindFrame> {
+        self.frames.pop_back()
+    }
+
+    /// Return the most recent frame without removing it.
+    pub fn last(&self) -> Option<&RewindFrame> {
+        self.frames.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
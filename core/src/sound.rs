@@ -32,14 +32,22 @@
 
 #![allow(dead_code)]
 
+use anyhow::Error;
+#[cfg(feature = "sound")]
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "sound")]
 use ringbuf::{
     Producer,
     //Consumer,
     RingBuffer,
 };
-//use std::fs::File;
-//use std::io::Write;
+use serde_derive::Deserialize;
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
 pub const VOLUME_ADJUST: f32 = 0.10;
 
@@ -49,6 +57,7 @@ pub const BUFFER_MS: f32 = 100.0;
 #[cfg(not(target_arch = "wasm32"))]
 pub const BUFFER_MS: f32 = 30.0;
 
+#[cfg(feature = "sound")]
 pub struct SoundPlayer {
     audio_device: cpal::Device,
     //audio_config_s: cpal::SupportedStreamConfig,
@@ -64,6 +73,7 @@ pub struct SoundPlayer {
     output_stream: cpal::Stream,
 }
 
+#[cfg(feature = "sound")]
 impl SoundPlayer {
     pub fn get_device() -> (cpal::Device, cpal::SampleFormat) {
         let audio_device = cpal::default_host()
@@ -178,6 +188,31 @@ impl SoundPlayer {
     }
 }
 
+/// Without the `sound` feature there is no host audio output. This type has no values, so a
+/// machine built without the feature always runs without a sound player.
+#[cfg(not(feature = "sound"))]
+pub enum SoundPlayer {}
+
+#[cfg(not(feature = "sound"))]
+impl SoundPlayer {
+    pub fn play(&self) {
+        match *self {}
+    }
+
+    pub fn queue_sample(&mut self, _data: f32) {
+        match *self {}
+    }
+
+    pub fn queue_sample_slice(&mut self, _data: &[f32]) {
+        match *self {}
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "sound")]
 fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
 where
     T: cpal::Sample,
@@ -190,3 +225,348 @@ where
         }
     }
 }
+
+/// Models the output stage of the PC speaker. The sampled speaker signal is a square wave, but
+/// the speaker itself shapes what is heard: the small speaker of the IBM 5150 has almost no bass
+/// and a strong resonance, while the larger cone speakers of many clones sound duller and fuller.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum SpeakerProfile {
+    /// Output the sampled signal unfiltered.
+    #[default]
+    Ideal,
+    /// The small, piezo-like speaker of the IBM 5150.
+    Piezo,
+    /// A typical cone speaker as found in clone machines.
+    Cone,
+}
+
+/// A second order IIR filter, with coefficients from the RBJ Audio EQ Cookbook.
+#[derive(Copy, Clone, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn from_coefficients(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Return (cos(w0), alpha) for the given sample rate, center frequency and Q. The frequency
+    /// is kept below the Nyquist limit so that low sample rates produce a stable filter.
+    fn params(sample_rate: u32, freq: f32, q: f32) -> (f32, f32) {
+        let freq = freq.min(sample_rate as f32 * 0.45);
+        let w0 = 2.0 * PI * freq / sample_rate as f32;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn low_pass(sample_rate: u32, freq: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, freq, q);
+        Self::from_coefficients(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn high_pass(sample_rate: u32, freq: f32, q: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, freq, q);
+        Self::from_coefficients(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn peaking(sample_rate: u32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, alpha) = Self::params(sample_rate, freq, q);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        Self::from_coefficients(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Applies the transfer function of a SpeakerProfile to speaker samples.
+pub struct SpeakerStage {
+    profile: SpeakerProfile,
+    filters: Vec<Biquad>,
+}
+
+impl SpeakerStage {
+    pub fn new(profile: SpeakerProfile, sample_rate: u32) -> Self {
+        let filters = match profile {
+            SpeakerProfile::Ideal => Vec::new(),
+            SpeakerProfile::Piezo => vec![
+                Biquad::high_pass(sample_rate, 500.0, 0.707),
+                Biquad::peaking(sample_rate, 3000.0, 1.5, 8.0),
+                Biquad::low_pass(sample_rate, 7000.0, 0.707),
+            ],
+            SpeakerProfile::Cone => vec![
+                Biquad::high_pass(sample_rate, 120.0, 0.707),
+                Biquad::peaking(sample_rate, 1200.0, 0.8, 2.0),
+                Biquad::low_pass(sample_rate, 4500.0, 0.707),
+            ],
+        };
+        Self { profile, filters }
+    }
+
+    pub fn profile(&self) -> SpeakerProfile {
+        self.profile
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filters.iter_mut().fold(sample, |x, filter| filter.process(x))
+    }
+}
+
+/// Identifies a device that contributes audio to the sound output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AudioSource {
+    PcSpeaker,
+}
+
+impl AudioSource {
+    /// The name appended to a recording's filename to form the filename of this source's stem.
+    pub fn stem_name(&self) -> &'static str {
+        match self {
+            AudioSource::PcSpeaker => "pc_speaker",
+        }
+    }
+}
+
+/// Writes mono, 16-bit PCM samples to a WAV file. The chunk sizes in the header are written when
+/// the file is finalized, or when the writer is dropped.
+pub struct WavWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    data_len: u32,
+    finalized: bool,
+}
+
+impl WavWriter {
+    const HEADER_LEN: u32 = 44;
+
+    pub fn create(path: &Path, sample_rate: u32) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(Self::HEADER_LEN - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // Channels
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * 2).to_le_bytes())?; // Byte rate
+        writer.write_all(&2u16.to_le_bytes())?; // Block align
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer,
+            data_len: 0,
+            finalized: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a sample in the range -1.0 to 1.0. Samples outside this range are clipped.
+    pub fn write_sample(&mut self, sample: f32) -> Result<(), Error> {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.writer.write_all(&sample.to_le_bytes())?;
+        self.data_len += 2;
+        Ok(())
+    }
+
+    pub fn finalize(&mut self) -> Result<(), Error> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.finalized = true;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(Self::HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finalize() {
+            log::error!("Error finalizing WAV file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+struct AudioRecording {
+    mix:   WavWriter,
+    stems: Vec<(AudioSource, WavWriter)>,
+}
+
+impl AudioRecording {
+    fn write(&mut self, samples: &[(AudioSource, f32)], mixed: f32) -> Result<(), Error> {
+        self.mix.write_sample(mixed)?;
+        for (source, stem) in self.stems.iter_mut() {
+            // A source that produced no sample this period contributes silence to its stem.
+            let sample = samples
+                .iter()
+                .find(|(s, _)| s == source)
+                .map_or(0.0, |(_, sample)| *sample);
+            stem.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+        self.mix.finalize()?;
+        paths.push(self.mix.path().to_path_buf());
+        for (_, stem) in self.stems.iter_mut() {
+            stem.finalize()?;
+            paths.push(stem.path().to_path_buf());
+        }
+        Ok(paths)
+    }
+}
+
+/// Mixes the output of each audio source into a single sound output. While recording, the mixed
+/// output is written to a WAV file, and each source is written to its own stem alongside it.
+pub struct SoundMixer {
+    sample_rate: u32,
+    recording:   Option<AudioRecording>,
+}
+
+impl SoundMixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            recording: None,
+        }
+    }
+
+    /// Mix one sample from each audio source, returning the mixed sample.
+    pub fn mix(&mut self, samples: &[(AudioSource, f32)]) -> f32 {
+        let mixed = samples.iter().map(|(_, sample)| *sample).sum();
+
+        if let Some(recording) = &mut self.recording {
+            if let Err(e) = recording.write(samples, mixed) {
+                log::error!("Error writing audio recording, recording stopped: {}", e);
+                self.recording = None;
+            }
+        }
+        mixed
+    }
+
+    /// Start recording the mixed output to the specified path. A stem is recorded for each of
+    /// the specified sources, named after the recording with the source's stem name appended.
+    /// Returns the paths of all files being written.
+    pub fn start_recording(&mut self, path: &Path, sources: &[AudioSource]) -> Result<Vec<PathBuf>, Error> {
+        let mix = WavWriter::create(path, self.sample_rate)?;
+        let base_name = path.file_stem().unwrap_or_default().to_string_lossy();
+
+        let mut paths = vec![path.to_path_buf()];
+        let mut stems = Vec::new();
+        for source in sources {
+            let stem_path = path.with_file_name(format!("{}_{}.wav", base_name, source.stem_name()));
+            stems.push((*source, WavWriter::create(&stem_path, self.sample_rate)?));
+            paths.push(stem_path);
+        }
+
+        self.recording = Some(AudioRecording { mix, stems });
+        Ok(paths)
+    }
+
+    /// Stop recording, returning the paths of the completed files.
+    pub fn stop_recording(&mut self) -> Result<Vec<PathBuf>, Error> {
+        match self.recording.take() {
+            Some(recording) => recording.finalize(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_stage() {
+        // The ideal profile passes samples through unchanged.
+        let mut stage = SpeakerStage::new(SpeakerProfile::Ideal, 44100);
+        assert_eq!(stage.process(1.0), 1.0);
+        assert_eq!(stage.process(0.0), 0.0);
+
+        // Real speakers can't reproduce DC, so a held speaker line decays to silence.
+        for profile in [SpeakerProfile::Piezo, SpeakerProfile::Cone] {
+            let mut stage = SpeakerStage::new(profile, 44100);
+            let mut out = 0.0;
+            for _ in 0..44100 {
+                out = stage.process(1.0);
+                assert!(out.is_finite());
+            }
+            assert!(out.abs() < 0.001, "{:?} settled at {}", profile, out);
+        }
+    }
+
+    #[test]
+    fn test_stem_recording() {
+        let path = std::env::temp_dir().join(format!("martypc_stem_test_{}.wav", std::process::id()));
+        let mut mixer = SoundMixer::new(44100);
+
+        let paths = mixer.start_recording(&path, &[AudioSource::PcSpeaker]).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(mixer.is_recording());
+        for _ in 0..100 {
+            assert_eq!(mixer.mix(&[(AudioSource::PcSpeaker, 0.5)]), 0.5);
+        }
+        assert_eq!(mixer.stop_recording().unwrap(), paths);
+        assert!(!mixer.is_recording());
+
+        for path in paths {
+            let wav = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(wav.len(), 44 + 200);
+            assert_eq!(&wav[0..4], b"RIFF");
+            assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 200);
+            assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 200);
+            assert_eq!(i16::from_le_bytes(wav[44..46].try_into().unwrap()), i16::MAX / 2);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    -------------------------------------------------------------------------
+
+    timeline.rs
+
+    A timeline of significant machine events - interrupt requests and
+    acknowledgements, DMA transfers, vertical retrace, video mode changes and
+    disk controller commands - stamped with the system tick and CPU address
+    at which they were observed, for display alongside CPU execution.
+
+    Events are found by sampling device state after each device update and
+    comparing it with the previous sample, so devices need no knowledge of
+    the timeline. The cost is resolution: events are placed at the end of
+    the update in which they occurred, and repeated events of the same kind
+    within one update are merged. DMA channel 0 is not recorded, as DRAM
+    refresh would otherwise flood the timeline.
+*/
+
+use std::{collections::VecDeque, ops::Range};
+
+use crate::{
+    bus::BusInterface,
+    cpu_808x::CpuAddress,
+    device_traits::videocard::{DisplayMode, VideoCardId},
+    devices::{dma::DMA_CHANNEL_COUNT, fdc, hdc},
+};
+
+pub const DEFAULT_TIMELINE_LEN: usize = 100_000;
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TimelineCategory {
+    Interrupt,
+    Dma,
+    Video,
+    Disk,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum TimelineEventKind {
+    IrqRaised(u8),
+    IrqAcknowledged(u8),
+    DmaTransfer { channel: usize, bytes: u64 },
+    Vsync(VideoCardId),
+    VideoModeChange { card: VideoCardId, mode: DisplayMode },
+    FdcCommand(fdc::Command),
+    HdcCommand(hdc::Command),
+}
+
+impl TimelineEventKind {
+    pub fn category(&self) -> TimelineCategory {
+        match self {
+            TimelineEventKind::IrqRaised(_) | TimelineEventKind::IrqAcknowledged(_) => TimelineCategory::Interrupt,
+            TimelineEventKind::DmaTransfer { .. } => TimelineCategory::Dma,
+            TimelineEventKind::Vsync(_) | TimelineEventKind::VideoModeChange { .. } => TimelineCategory::Video,
+            TimelineEventKind::FdcCommand(_) | TimelineEventKind::HdcCommand(_) => TimelineCategory::Disk,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TimelineEvent {
+    /// System ticks elapsed since the machine was created.
+    pub tick:    u64,
+    /// The CPU's CS:IP when the event was observed.
+    pub address: CpuAddress,
+    pub kind:    TimelineEventKind,
+}
+
+/// Device state observed at the previous sample.
+#[derive(Default)]
+struct DeviceSample {
+    irr: u8,
+    dma_transfers: [u64; DMA_CHANNEL_COUNT],
+    video: Vec<(VideoCardId, u64, DisplayMode)>,
+    fdc_commands: u64,
+    hdc_commands: u64,
+}
+
+pub struct Timeline {
+    events: VecDeque<TimelineEvent>,
+    capacity: usize,
+    dropped: u64,
+    last: Option<DeviceSample>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMELINE_LEN)
+    }
+}
+
+impl Timeline {
+    /// Create a timeline that retains up to `capacity` of the most recent events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity: capacity.max(1),
+            dropped: 0,
+            last: None,
+        }
+    }
+
+    /// Compare device state with the previous sample and record any events found. The first
+    /// sample only establishes a baseline.
+    pub fn sample(&mut self, bus: &mut BusInterface, tick: u64, address: CpuAddress) {
+        let mut sample = DeviceSample::default();
+        if let Some(pic) = bus.pic_mut().as_ref() {
+            sample.irr = pic.irr();
+        }
+        if let Some(dma) = bus.dma_mut().as_ref() {
+            for (channel, count) in sample.dma_transfers.iter_mut().enumerate() {
+                *count = dma.transfer_count(channel);
+            }
+        }
+        for card in bus.enumerate_videocards() {
+            if let Some(video) = bus.video(&card) {
+                sample
+                    .video
+                    .push((card, video.get_frame_count(), video.get_display_mode()));
+            }
+        }
+        let mut fdc_command = None;
+        if let Some(fdc) = bus.fdc_mut().as_ref() {
+            let (count, command) = fdc.dispatched_commands();
+            sample.fdc_commands = count;
+            fdc_command = Some(command);
+        }
+        let mut hdc_command = None;
+        if let Some(hdc) = bus.hdc_mut().as_ref() {
+            let (count, command) = hdc.dispatched_commands();
+            sample.hdc_commands = count;
+            hdc_command = Some(command);
+        }
+
+        if let Some(last) = self.last.take() {
+            let mut push = |kind| self.push(TimelineEvent { tick, address, kind });
+
+            // An IRR bit is only cleared by the CPU acknowledging the request.
+            for irq in 0..8 {
+                let bit = 1 << irq;
+                if last.irr & bit != 0 && sample.irr & bit == 0 {
+                    push(TimelineEventKind::IrqAcknowledged(irq));
+                }
+                else if last.irr & bit == 0 && sample.irr & bit != 0 {
+                    push(TimelineEventKind::IrqRaised(irq));
+                }
+            }
+            for channel in 1..DMA_CHANNEL_COUNT {
+                let bytes = sample.dma_transfers[channel].wrapping_sub(last.dma_transfers[channel]);
+                if bytes > 0 {
+                    push(TimelineEventKind::DmaTransfer { channel, bytes });
+                }
+            }
+            for (card, frames, mode) in sample.video.iter() {
+                if let Some((_, last_frames, last_mode)) = last.video.iter().find(|(id, _, _)| id == card) {
+                    if frames != last_frames {
+                        push(TimelineEventKind::Vsync(*card));
+                    }
+                    if mode != last_mode {
+                        push(TimelineEventKind::VideoModeChange {
+                            card: *card,
+                            mode: *mode,
+                        });
+                    }
+                }
+            }
+            if let (Some(command), true) = (fdc_command, sample.fdc_commands != last.fdc_commands) {
+                push(TimelineEventKind::FdcCommand(command));
+            }
+            if let (Some(command), true) = (hdc_command, sample.hdc_commands != last.hdc_commands) {
+                push(TimelineEventKind::HdcCommand(command));
+            }
+        }
+        self.last = Some(sample);
+    }
+
+    fn push(&mut self, event: TimelineEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Return all retained events, oldest first.
+    pub fn events(&self) -> &VecDeque<TimelineEvent> {
+        &self.events
+    }
+
+    /// Return the retained events within the specified range of system ticks, optionally limited
+    /// to a single category, oldest first.
+    pub fn query(&self, ticks: Range<u64>, category: Option<TimelineCategory>) -> Vec<&TimelineEvent> {
+        let start = self.events.partition_point(|e| e.tick < ticks.start);
+        self.events
+            .range(start..)
+            .take_while(|e| e.tick < ticks.end)
+            .filter(|e| category.is_none_or(|c| e.kind.category() == c))
+            .collect()
+    }
+
+    /// Return the number of events discarded because the timeline was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Discard all events. The next sample is compared against the current device state.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.dropped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline_query() {
+        let mut timeline = Timeline::new(3);
+        let address = CpuAddress::Segmented(0xF000, 0xE05B);
+        for (tick, kind) in [
+            (10, TimelineEventKind::IrqRaised(0)),
+            (
+                20,
+                TimelineEventKind::DmaTransfer {
+                    channel: 2,
+                    bytes:   512,
+                },
+            ),
+            (30, TimelineEventKind::IrqAcknowledged(0)),
+            (40, TimelineEventKind::IrqRaised(6)),
+        ] {
+            timeline.push(TimelineEvent { tick, address, kind });
+        }
+
+        // The oldest event is discarded once the timeline is full.
+        assert_eq!(timeline.events().len(), 3);
+        assert_eq!(timeline.dropped(), 1);
+        assert_eq!(timeline.events()[0].tick, 20);
+
+        let events = timeline.query(20..40, None);
+        assert_eq!(events.len(), 2);
+        let events = timeline.query(0..100, Some(TimelineCategory::Interrupt));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1].kind, TimelineEventKind::IrqRaised(6)));
+    }
+}
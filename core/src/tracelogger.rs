@@ -30,21 +30,42 @@
     that may wish to implement logging.
 
     Thanks to Bigbass for the suggestion that avoids references.
+
+    It also implements structured event logging. Events belong to a category
+    with its own level that can be changed at runtime, and can be written to
+    a JSON Lines sink in addition to the regular log.
 */
 
+use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     fs::File,
     io::{BufWriter, Write},
     path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex,
+    },
 };
 
-#[derive(Debug)]
+/// A destination for trace output. Traces are written to any Write implementation, so a host
+/// without a filesystem can supply its own sink with from_writer().
 pub enum TraceLogger {
-    FileWriter(BufWriter<File>),
+    Writer(BufWriter<Box<dyn Write + Send>>),
     Console,
     None,
 }
 
+impl fmt::Debug for TraceLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceLogger::Writer(_) => write!(f, "Writer"),
+            TraceLogger::Console => write!(f, "Console"),
+            TraceLogger::None => write!(f, "None"),
+        }
+    }
+}
+
 impl Default for TraceLogger {
     fn default() -> TraceLogger {
         TraceLogger::None
@@ -54,7 +75,7 @@ impl Default for TraceLogger {
 impl TraceLogger {
     pub fn from_filename<S: AsRef<Path>>(filename: S) -> Self {
         match File::create(filename) {
-            Ok(file) => TraceLogger::FileWriter(BufWriter::new(file)),
+            Ok(file) => TraceLogger::from_writer(file),
             Err(e) => {
                 eprintln!("Couldn't create specified video tracelog file: {}", e);
                 TraceLogger::None
@@ -62,10 +83,14 @@ impl TraceLogger {
         }
     }
 
+    pub fn from_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        TraceLogger::Writer(BufWriter::new(Box::new(writer)))
+    }
+
     #[inline(always)]
     pub fn print<S: AsRef<str> + std::fmt::Display>(&mut self, msg: S) {
         match self {
-            TraceLogger::FileWriter(buf) => {
+            TraceLogger::Writer(buf) => {
                 _ = buf.write_all(msg.as_ref().as_bytes());
             }
             TraceLogger::Console => println!("{}", msg),
@@ -76,7 +101,7 @@ impl TraceLogger {
     #[inline(always)]
     pub fn println<S: AsRef<str> + std::fmt::Display>(&mut self, msg: S) {
         match self {
-            TraceLogger::FileWriter(buf) => {
+            TraceLogger::Writer(buf) => {
                 _ = buf.write_all(msg.as_ref().as_bytes());
                 _ = buf.write_all("\n".as_bytes());
             }
@@ -86,7 +111,7 @@ impl TraceLogger {
     }
 
     pub fn flush(&mut self) {
-        if let TraceLogger::FileWriter(file) = self {
+        if let TraceLogger::Writer(file) = self {
             if let Err(e) = file.flush() {
                 log::error!("Failed to flush trace log: {}", e);
             }
@@ -95,6 +120,212 @@ impl TraceLogger {
 
     #[inline(always)]
     pub fn is_some(&self) -> bool {
-        matches!(*self, TraceLogger::FileWriter(_) | TraceLogger::Console)
+        matches!(*self, TraceLogger::Writer(_) | TraceLogger::Console)
+    }
+}
+
+/// Allows binary and formatted trace output to be written directly to the logger.
+impl Write for TraceLogger {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            TraceLogger::Writer(writer) => writer.write(buf),
+            TraceLogger::Console => std::io::stdout().write(buf),
+            TraceLogger::None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            TraceLogger::Writer(writer) => writer.flush(),
+            TraceLogger::Console => std::io::stdout().flush(),
+            TraceLogger::None => Ok(()),
+        }
+    }
+}
+
+/// The subsystem an event belongs to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LogCategory {
+    Cpu,
+    Pit,
+    Fdc,
+    Video,
+    Io,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 5] = [
+        LogCategory::Cpu,
+        LogCategory::Pit,
+        LogCategory::Fdc,
+        LogCategory::Video,
+        LogCategory::Io,
+    ];
+}
+
+impl fmt::Display for LogCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The verbosity of an event. A category logs events at its level and below.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 6] = [
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    fn from_u8(level: u8) -> LogLevel {
+        LogLevel::ALL.get(level as usize).copied().unwrap_or(LogLevel::Trace)
+    }
+
+    fn to_log_level(self) -> Option<log::Level> {
+        match self {
+            LogLevel::Off => None,
+            LogLevel::Error => Some(log::Level::Error),
+            LogLevel::Warn => Some(log::Level::Warn),
+            LogLevel::Info => Some(log::Level::Info),
+            LogLevel::Debug => Some(log::Level::Debug),
+            LogLevel::Trace => Some(log::Level::Trace),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// Categories default to Trace so that events are filtered only by the regular log filter
+// until a category level is lowered.
+static CATEGORY_LEVELS: [AtomicU8; 5] = [
+    AtomicU8::new(LogLevel::Trace as u8),
+    AtomicU8::new(LogLevel::Trace as u8),
+    AtomicU8::new(LogLevel::Trace as u8),
+    AtomicU8::new(LogLevel::Trace as u8),
+    AtomicU8::new(LogLevel::Trace as u8),
+];
+static EVENT_SINK_ACTIVE: AtomicBool = AtomicBool::new(false);
+static EVENT_SINK: Mutex<TraceLogger> = Mutex::new(TraceLogger::None);
+
+/// Log a structured event in the specified category, if the category's level allows it.
+/// Arguments are only formatted when the event will be logged.
+///
+/// `log_event!(LogCategory::Pit, LogLevel::Debug, "Channel {} reloaded", c);`
+#[macro_export]
+macro_rules! log_event {
+    ($category:expr, $level:expr, $($arg:tt)+) => {
+        if $crate::tracelogger::event_enabled($category, $level) {
+            $crate::tracelogger::write_event($category, $level, module_path!(), format_args!($($arg)+));
+        }
+    };
+}
+
+pub fn set_category_level(category: LogCategory, level: LogLevel) {
+    CATEGORY_LEVELS[category as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub fn category_level(category: LogCategory) -> LogLevel {
+    LogLevel::from_u8(CATEGORY_LEVELS[category as usize].load(Ordering::Relaxed))
+}
+
+#[inline(always)]
+pub fn event_enabled(category: LogCategory, level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= CATEGORY_LEVELS[category as usize].load(Ordering::Relaxed)
+}
+
+/// Set the sink that events are written to as JSON Lines. Pass TraceLogger::None to stop writing
+/// events. Any previous sink is flushed.
+pub fn set_event_sink(sink: TraceLogger) {
+    let mut event_sink = EVENT_SINK.lock().unwrap_or_else(|e| e.into_inner());
+    event_sink.flush();
+    EVENT_SINK_ACTIVE.store(sink.is_some(), Ordering::Relaxed);
+    *event_sink = sink;
+}
+
+pub fn flush_event_sink() {
+    EVENT_SINK.lock().unwrap_or_else(|e| e.into_inner()).flush();
+}
+
+/// Write an event to the regular log and the event sink, if one is set. Called by log_event!.
+pub fn write_event(category: LogCategory, level: LogLevel, target: &str, args: fmt::Arguments) {
+    if let Some(log_level) = level.to_log_level() {
+        log::log!(target: target, log_level, "[{}] {}", category, args);
+    }
+
+    if EVENT_SINK_ACTIVE.load(Ordering::Relaxed) {
+        let event = serde_json::json!({
+            "time": chrono::Local::now().to_rfc3339(),
+            "category": category,
+            "level": level,
+            "target": target,
+            "message": args.to_string(),
+        });
+        EVENT_SINK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .println(event.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_levels() {
+        set_category_level(LogCategory::Fdc, LogLevel::Info);
+        assert_eq!(category_level(LogCategory::Fdc), LogLevel::Info);
+        assert!(event_enabled(LogCategory::Fdc, LogLevel::Warn));
+        assert!(event_enabled(LogCategory::Fdc, LogLevel::Info));
+        assert!(!event_enabled(LogCategory::Fdc, LogLevel::Debug));
+        assert!(!event_enabled(LogCategory::Fdc, LogLevel::Off));
+        // Other categories are unaffected.
+        assert!(event_enabled(LogCategory::Io, LogLevel::Trace));
+
+        set_category_level(LogCategory::Fdc, LogLevel::Off);
+        assert!(!event_enabled(LogCategory::Fdc, LogLevel::Error));
+        set_category_level(LogCategory::Fdc, LogLevel::Trace);
+    }
+
+    #[test]
+    fn test_writer_sink() {
+        #[derive(Clone, Default)]
+        struct SharedBuf(std::sync::Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buf = SharedBuf::default();
+        let mut logger = TraceLogger::from_writer(buf.clone());
+        assert!(logger.is_some());
+        logger.print("trace ");
+        logger.println("line");
+        logger.flush();
+        assert_eq!(buf.0.lock().unwrap().as_slice(), b"trace line\n");
     }
 }
@@ -0,0 +1,135 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    video_trace.rs
+
+    Video register write tracing. When enabled, every IO write that modifies
+    a video card register is logged with the register's old and new values,
+    the raster position of the card at the time of the write and the CS:IP
+    of the instruction that performed it.
+
+    Old values are reported by the card where it can do so cheaply; otherwise
+    the last value written while tracing was active is shown, or '??' if the
+    register has not been written yet.
+*/
+
+use std::collections::HashMap;
+
+use crate::{
+    device_traits::videocard::{VideoCardId, VideoRegister, VideoRegisterGroup},
+    tracelogger::TraceLogger,
+};
+
+/// Selects which register writes are traced.
+#[derive(Clone, Debug, Default)]
+pub struct VideoTraceFilter {
+    /// Register groups to trace. An empty list traces all groups.
+    pub groups: Vec<VideoRegisterGroup>,
+    /// Only trace writes to the register with this index, if specified.
+    pub index: Option<u8>,
+    /// Skip writes that do not change the register's value.
+    pub changes_only: bool,
+}
+
+impl VideoTraceFilter {
+    fn matches(&self, reg: &VideoRegister) -> bool {
+        (self.groups.is_empty() || self.groups.contains(&reg.group))
+            && self.index.is_none_or(|index| index == reg.index)
+    }
+}
+
+pub struct VideoRegisterTrace {
+    filter: VideoTraceFilter,
+    logger: TraceLogger,
+    shadow: HashMap<(VideoCardId, VideoRegisterGroup, u8), u8>,
+    cs: u16,
+    ip: u16,
+}
+
+impl VideoRegisterTrace {
+    pub fn new(logger: TraceLogger, filter: VideoTraceFilter) -> Self {
+        Self {
+            filter,
+            logger,
+            shadow: HashMap::new(),
+            cs: 0,
+            ip: 0,
+        }
+    }
+
+    pub fn filter(&self) -> &VideoTraceFilter {
+        &self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: VideoTraceFilter) {
+        self.filter = filter;
+    }
+
+    /// Set the address of the instruction performing the next IO write.
+    #[inline]
+    pub fn set_source(&mut self, cs: u16, ip: u16) {
+        self.cs = cs;
+        self.ip = ip;
+    }
+
+    /// Log a write of `data` to the specified register, if it passes the filter.
+    pub fn log_write(
+        &mut self,
+        vid: VideoCardId,
+        reg: VideoRegister,
+        data: u8,
+        beam: Option<(u32, u32)>,
+        scanline: u32,
+    ) {
+        let old = reg
+            .value
+            .or_else(|| self.shadow.get(&(vid, reg.group, reg.index)).copied());
+        self.shadow.insert((vid, reg.group, reg.index), data);
+
+        if !self.filter.matches(&reg) || (self.filter.changes_only && old == Some(data)) {
+            return;
+        }
+
+        let old_str = match old {
+            Some(old) => format!("{:02X}", old),
+            None => "??".to_string(),
+        };
+        let pos_str = match beam {
+            Some((x, y)) => format!("X:{:04} Y:{:04}", x, y),
+            None => format!("SL:{:04}", scanline),
+        };
+
+        self.logger.println(format!(
+            "[{:?}:{}] [{}] {:?}[{:02X}]: {} -> {:02X} @ {:04X}:{:04X}",
+            vid.vtype, vid.idx, pos_str, reg.group, reg.index, old_str, data, self.cs, self.ip
+        ));
+    }
+
+    pub fn flush(&mut self) {
+        self.logger.flush();
+    }
+}
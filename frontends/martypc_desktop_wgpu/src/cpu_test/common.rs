@@ -83,6 +83,7 @@ pub struct TestFailItem {
     pub reason: FailType,
 }
 
+#[derive(Default)]
 pub struct TestResult {
     pub pass: bool,
     pub duration: Duration,
@@ -593,18 +594,37 @@ pub fn print_cycle_diff(log: &mut BufWriter<File>, test_states: &Vec<CycleState>
     }
 }
 
+/// Return the percentage of tests that passed, counting warnings as passes.
+pub fn pass_rate(result: &TestResult) -> f64 {
+    let total = result.passed + result.warning + result.failed;
+    if total == 0 {
+        return 100.0;
+    }
+    (result.passed + result.warning) as f64 * 100.0 / total as f64
+}
+
 pub fn print_summary(summary: &TestResultSummary) {
     // Collect and sort keys
     let mut keys: Vec<_> = summary.results.keys().collect();
     keys.sort();
 
+    let mut total_passed = 0;
+    let mut total_warning = 0;
+    let mut total_failed = 0;
+
     // Iterate using sorted keys
     for key in keys {
         if let Some(result) = summary.results.get(key) {
+            total_passed += result.passed;
+            total_warning += result.warning;
+            total_failed += result.failed;
+
             let filename = format!("{:?}", key);
+            let rate = format!("{:6.2}%", pass_rate(result));
             println!(
-                "File: {:15} Passed: {:6} Warning: {:6} Failed: {:6} Reg: {:6} Cycle: {:6} Mem: {:6}",
+                "File: {:15} Rate: {} Passed: {:6} Warning: {:6} Failed: {:6} Reg: {:6} Cycle: {:6} Mem: {:6}",
                 filename.bright_blue(),
+                if result.failed > 0 { rate.red() } else { rate.green() },
                 result.passed,
                 if result.warning > 0 {
                     format!("{:6}", result.warning.to_string().yellow())
@@ -639,4 +659,45 @@ pub fn print_summary(summary: &TestResultSummary) {
             );
         }
     }
+
+    let total = TestResult {
+        passed: total_passed,
+        warning: total_warning,
+        failed: total_failed,
+        ..Default::default()
+    };
+    println!(
+        "Total: {} tests, {} passed, {} warnings, {} failed. Pass rate: {:.2}%",
+        total_passed + total_warning + total_failed,
+        total_passed,
+        total_warning,
+        total_failed,
+        pass_rate(&total)
+    );
+}
+
+/// Write a per-opcode summary of test results as CSV, so runs can be compared between builds.
+pub fn write_summary_csv(summary: &TestResultSummary, path: &PathBuf) -> std::io::Result<()> {
+    let mut keys: Vec<_> = summary.results.keys().collect();
+    keys.sort();
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "file,passed,warning,failed,reg_mismatch,cycle_mismatch,mem_mismatch,pass_rate")?;
+    for key in keys {
+        if let Some(result) = summary.results.get(key) {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{:.2}",
+                key.to_string_lossy(),
+                result.passed,
+                result.warning,
+                result.failed,
+                result.reg_mismatch,
+                result.cycle_mismatch,
+                result.mem_mismatch,
+                pass_rate(result)
+            )?;
+        }
+    }
+    writer.flush()
 }
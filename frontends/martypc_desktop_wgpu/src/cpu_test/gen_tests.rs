@@ -86,6 +86,8 @@ pub fn run_gentests(config: &ConfigFileParams) {
         ValidatorMode::Instruction,
         #[cfg(feature = "cpu_validator")]
         config.validator.baud_rate.unwrap_or(1_000_000),
+        #[cfg(feature = "cpu_validator")]
+        config.validator.address.clone(),
     );
 
     if let Some(seed) = config.tests.test_seed {
@@ -38,5 +38,6 @@ pub mod common;
 #[cfg(feature = "arduino_validator")]
 pub mod gen_tests;
 pub mod process_tests;
-#[cfg(feature = "arduino_validator")]
+// The test runner doesn't require validator hardware, only the validator cycle state hooks.
+#[cfg(feature = "cpu_validator")]
 pub mod run_tests;
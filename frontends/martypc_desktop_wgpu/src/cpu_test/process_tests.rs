@@ -234,6 +234,8 @@ fn process_tests(
         ValidatorMode::Instruction,
         #[cfg(feature = "cpu_validator")]
         config.validator.baud_rate.unwrap_or(1_000_000),
+        #[cfg(feature = "cpu_validator")]
+        config.validator.address.clone(),
     );
 
     // We should have a vector of tests now.
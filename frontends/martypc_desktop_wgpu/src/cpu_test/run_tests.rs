@@ -39,6 +39,7 @@ use crate::cpu_test::common::{
     validate_cycles,
     validate_memory,
     validate_registers,
+    write_summary_csv,
 };
 use std::{
     collections::{HashMap, LinkedList},
@@ -138,6 +139,8 @@ pub fn run_runtests(config: ConfigFileParams) {
         log_path = PathBuf::from(test_output_path.clone());
     }
     log_path.push("validation.log");
+    let mut summary_path = log_path.clone();
+    summary_path.set_file_name("summary.csv");
 
     let mut summary = TestResultSummary {
         results: HashMap::new(),
@@ -268,11 +271,25 @@ pub fn run_runtests(config: ConfigFileParams) {
         println!("Completed in: {} seconds", test_suite_start.elapsed().as_secs());
     }
 
+    if let Err(e) = write_summary_csv(&summary, &summary_path) {
+        eprintln!("Failed to write test summary to {:?}: {}", summary_path, e);
+    }
+    else {
+        println!("Wrote test summary to {:?}", summary_path);
+    }
+
     //let mut writer_lock = writer_arc.lock().unwrap();
     //_ = writeln!(&mut writer_lock, "All tests validated!");
     //_ = writer_lock.flush();
 
     // writer & file dropped here
+
+    _ = log_writer.flush();
+
+    // Exit with an error status if any test failed, so that the runner can drive 'git bisect run'.
+    if summary.results.values().any(|result| result.failed > 0) {
+        std::process::exit(1);
+    }
 }
 
 fn run_tests(
@@ -317,6 +334,8 @@ fn run_tests(
         ValidatorMode::Instruction,
         #[cfg(feature = "cpu_validator")]
         config.validator.baud_rate.unwrap_or(1_000_000),
+        #[cfg(feature = "cpu_validator")]
+        config.validator.address.clone(),
     );
 
     if config.machine.cpu.trace_on {
@@ -39,17 +39,24 @@ use display_manager_wgpu::WgpuDisplayManager;
 use frontend_common::{
     display_scaler::SCALER_MODES,
     floppy_manager::FloppyManager,
+    netplay::NetplaySession,
     resource_manager::ResourceManager,
     rom_manager::RomManager,
+    terminal_renderer::TerminalRenderer,
     timestep_manager::PerfSnapshot,
     vhd_manager::VhdManager,
 };
 use marty_core::{
     cpu_common::CpuOption,
+    input_script::InputScript,
     machine::{ExecutionControl, Machine, MachineEvent, MachineState},
+    tracelogger::{self, TraceLogger},
     vhd::VirtualHardDisk,
+    video_trace::VideoTraceFilter,
 };
 use marty_egui::{state::GuiState, GuiBoolean, GuiWindow};
+#[cfg(feature = "vnc")]
+use marty_vnc::VncServer;
 use videocard_renderer::AspectCorrectionMode;
 
 /// Define flags to be used by emulator.
@@ -79,6 +86,10 @@ pub struct Emulator {
     pub vhd_manager: VhdManager,
     pub flags: EmuFlags,
     pub perf: PerfSnapshot,
+    #[cfg(feature = "vnc")]
+    pub vnc: Option<VncServer>,
+    pub netplay: Option<NetplaySession>,
+    pub terminal: Option<TerminalRenderer>,
 }
 
 impl Emulator {
@@ -115,6 +126,19 @@ impl Emulator {
         self.machine.set_cpu_option(CpuOption::EnableServiceInterrupt(
             self.config.machine.cpu.service_interrupt.unwrap_or(false),
         ));
+        self.machine
+            .set_halt_idle(self.config.machine.cpu.halt_idle.unwrap_or(false));
+        self.machine.set_host_clock(self.config.machine.host_clock.clone());
+        self.machine.set_vector_watch(self.config.emulator.debugger.vector_watch);
+        self.machine.set_cpu_option(CpuOption::DecodeCache(
+            self.config.machine.cpu.decode_cache.unwrap_or(false),
+        ));
+        self.machine.set_cpu_option(CpuOption::FastCore(
+            self.config.machine.cpu.fast_core.unwrap_or(false),
+        ));
+        self.machine.set_cpu_option(CpuOption::TraceMemoryOperands(
+            self.config.machine.cpu.trace_mem_operands.unwrap_or(false),
+        ));
 
         // TODO: Re-enable these
         //gui.set_option(GuiBoolean::EnableSnow, config.machine.cga_snow.unwrap_or(false));
@@ -156,6 +180,65 @@ impl Emulator {
             }
         }
 
+        // Link a guest serial port to the netplay peer.
+        if let Some(netplay) = &mut self.netplay {
+            netplay.attach(&mut self.machine)?;
+        }
+
+        // Trace video register writes if requested.
+        if let Some(trace) = &self.config.emulator.video_register_trace {
+            self.machine.start_video_register_trace(
+                TraceLogger::from_filename(&trace.file),
+                VideoTraceFilter {
+                    groups: trace.groups.clone(),
+                    index: trace.index,
+                    changes_only: trace.changes_only,
+                },
+            );
+        }
+
+        // Log hard disk controller accesses if requested.
+        if let Some(hdc_log) = &self.config.emulator.hdc_access_log {
+            self.machine.start_hdc_access_log(TraceLogger::from_filename(&hdc_log.file));
+        }
+
+        // Trace interrupt latency if requested.
+        if let Some(irq_trace) = &self.config.emulator.irq_trace {
+            self.machine.start_irq_trace(TraceLogger::from_filename(&irq_trace.file));
+        }
+
+        // Set event log category levels and open the JSON event log if requested.
+        if let Some(event_log) = &self.config.emulator.event_log {
+            for (category, level) in event_log.levels.iter() {
+                tracelogger::set_category_level(*category, *level);
+            }
+            if let Some(file) = &event_log.file {
+                tracelogger::set_event_sink(TraceLogger::from_filename(file));
+            }
+        }
+
+        // Start a timed input script if one was specified.
+        if let Some(script_path) = &self.config.machine.input.input_script {
+            match InputScript::load(script_path) {
+                Ok(script) => self.machine.input_script_play(script),
+                Err(e) => log::error!("Failed to load input script {}: {}", script_path.display(), e),
+            }
+        }
+
+        // Bridge a guest serial port to stdio if requested.
+        if let Some(serial_stdio) = &self.config.emulator.serial_stdio {
+            self.machine
+                .bridge_serial_stdio(serial_stdio.port, serial_stdio.line_mode);
+        }
+
+        if let Some(serial_bridge) = &self.config.emulator.serial_bridge {
+            self.machine
+                .set_serial_bridge_config(serial_bridge.port, serial_bridge.settings.clone());
+            if let Some(host_port) = &serial_bridge.host_port {
+                self.machine.bridge_serial_port(serial_bridge.port, host_port.clone());
+            }
+        }
+
         self.gui.set_option(
             GuiBoolean::CpuEnableWaitStates,
             self.config.machine.cpu.wait_states.unwrap_or(true),
@@ -173,6 +256,10 @@ impl Emulator {
             self.config.machine.cpu.instruction_history.unwrap_or(false),
         ));
 
+        if let Some(depth) = self.config.machine.cpu.instruction_history_depth {
+            self.machine.set_cpu_option(CpuOption::InstructionHistoryDepth(depth));
+        }
+
         self.gui
             .set_option(GuiBoolean::CpuTraceLoggingEnabled, self.config.machine.cpu.trace_on);
         self.machine
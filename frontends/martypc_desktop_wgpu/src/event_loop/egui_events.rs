@@ -32,10 +32,12 @@
 use crate::Emulator;
 use display_manager_wgpu::DisplayManager;
 use marty_core::{
-    breakpoints::BreakPointType,
+    breakpoints::{BreakPointType, VideoWriteBreakpoint},
     cpu_common::CpuOption,
     device_traits::videocard::ClockingMode,
     machine::MachineState,
+    rewind::DEFAULT_REWIND_LEN,
+    tracelogger,
     vhd,
 };
 use marty_egui::{
@@ -59,6 +61,13 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             // User chose exit option from menu. Shut down.
             // TODO: Add a timeout from last VHD write for safety?
             println!("Thank you for using MartyPC!");
+            if let Err(e) = emu.machine.flush_nvram() {
+                log::error!("Failed to save NVRAM: {}", e);
+            }
+            tracelogger::flush_event_sink();
+            if let Some(terminal) = &mut emu.terminal {
+                _ = terminal.restore(&mut std::io::stdout());
+            }
             elwt.exit();
         }
         GuiEvent::SetNMI(state) => {
@@ -79,6 +88,9 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 (GuiBoolean::CpuTraceLoggingEnabled, state) => {
                     emu.machine.set_cpu_option(CpuOption::TraceLoggingEnabled(state));
                 }
+                (GuiBoolean::CpuStepBackHistory, state) => {
+                    emu.machine.set_rewind(state, DEFAULT_REWIND_LEN);
+                }
                 (GuiBoolean::TurboButton, state) => {
                     emu.machine.set_turbo_mode(state);
                 }
@@ -225,25 +237,25 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
         GuiEvent::LoadFloppy(drive_select, item_idx) => {
             log::debug!("Load floppy image: {:?} into drive: {}", item_idx, drive_select);
 
+            // Images whose files are read-only are always loaded write-protected.
+            let write_protect = emu.config.emulator.media.write_protect_default
+                || emu
+                    .floppy_manager
+                    .catalog_entry(*item_idx)
+                    .map_or(false, |entry| entry.write_protected);
+
             if let Some(fdc) = emu.machine.fdc() {
                 emu.floppy_manager.get_floppy_name(*item_idx).map(|name| {
                     log::info!("Loading floppy image: {:?} into drive: {}", name, drive_select);
 
                     match emu.floppy_manager.load_floppy_data(*item_idx, &emu.rm) {
-                        Ok(floppy_image) => match fdc.load_image_from(
-                            *drive_select,
-                            floppy_image,
-                            emu.config.emulator.media.write_protect_default,
-                        ) {
+                        Ok(floppy_image) => match fdc.load_image_from(*drive_select, floppy_image, write_protect) {
                             Ok(()) => {
                                 log::info!("Floppy image successfully loaded into virtual drive.");
                                 emu.gui
                                     .set_floppy_selection(*drive_select, Some(*item_idx), Some(name.clone().into()));
 
-                                emu.gui.set_floppy_write_protected(
-                                    *drive_select,
-                                    emu.config.emulator.media.write_protect_default,
-                                );
+                                emu.gui.set_floppy_write_protected(*drive_select, write_protect);
 
                                 emu.gui
                                     .toasts()
@@ -339,7 +351,14 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
         }
         GuiEvent::BridgeSerialPort(port_name) => {
             log::info!("Bridging serial port: {}", port_name);
-            emu.machine.bridge_serial_port(1, port_name.clone());
+            let guest_port = emu
+                .config
+                .emulator
+                .serial_bridge
+                .as_ref()
+                .map(|bridge| bridge.port)
+                .unwrap_or(1);
+            emu.machine.bridge_serial_port(guest_port, port_name.clone());
         }
         GuiEvent::DumpVRAM => {
             if let Some(video_card) = emu.machine.primary_videocard() {
@@ -366,7 +385,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             emu.rm
                 .get_available_filename("dump", "cs_dump", Some("bin"))
                 .ok()
-                .map(|path| emu.machine.cpu().dump_cs(&path))
+                .map(|path| emu.machine.dump_cs(&path))
                 .or_else(|| {
                     log::error!("Failed to get available filename for memory dump!");
                     None
@@ -376,15 +395,39 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             emu.rm
                 .get_available_filename("dump", "memdump", Some("bin"))
                 .ok()
-                .map(|path| emu.machine.bus().dump_mem(&path))
+                .map(|path| emu.machine.dump_mem(&path))
                 .or_else(|| {
                     log::error!("Failed to get available filename for memory dump!");
                     None
                 });
         }
+        GuiEvent::DumpFont => {
+            // Glyphs are written as 256 consecutive glyphs, one byte per row.
+            if let Some(video_card) = emu.machine.primary_videocard() {
+                let glyphs = video_card.get_glyphs();
+                let base_name = format!(
+                    "{:?}_font_{}x{}",
+                    video_card.get_video_type(),
+                    glyphs.width,
+                    glyphs.height
+                );
+
+                emu.rm
+                    .get_available_filename("dump", &base_name, Some("bin"))
+                    .ok()
+                    .map(|path| match std::fs::write(&path, &glyphs.data) {
+                        Ok(_) => log::info!("Wrote font dump: {}", path.display()),
+                        Err(e) => log::error!("Failed to write font dump '{}': {}", path.display(), e),
+                    })
+                    .or_else(|| {
+                        log::error!("Failed to get available filename for font dump!");
+                        None
+                    });
+            }
+        }
         GuiEvent::EditBreakpoint => {
             // Get breakpoints from GUI
-            let (bp_str, bp_mem_str, bp_int_str) = emu.gui.get_breakpoints();
+            let (bp_str, bp_mem_str, bp_int_str, bp_vram_str) = emu.gui.get_breakpoints();
 
             let mut breakpoints = Vec::new();
 
@@ -411,6 +454,31 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 }
             }
 
+            // Push video memory write breakpoint to list. This is either an address expression or a
+            // text cell given as row,col, optionally followed by =value.
+            let (bp_vram_target, bp_vram_value) = match bp_vram_str.split_once('=') {
+                Some((target, value)) => (target.trim(), u8::from_str_radix(value.trim(), 16).ok()),
+                None => (bp_vram_str.trim(), None),
+            };
+            let vram_range = match bp_vram_target.split_once(',') {
+                Some((row, col)) => match (row.trim().parse::<u32>(), col.trim().parse::<u32>()) {
+                    (Ok(row), Ok(col)) => emu.machine.text_cell_address(row, col).map(|addr| (addr, 2)),
+                    _ => None,
+                },
+                None => emu
+                    .machine
+                    .cpu()
+                    .eval_address(bp_vram_target)
+                    .map(|addr| (u32::from(addr), 1)),
+            };
+            if let Some((start, size)) = vram_range {
+                breakpoints.push(BreakPointType::VideoWrite(VideoWriteBreakpoint {
+                    start,
+                    size,
+                    value: bp_vram_value,
+                }));
+            }
+
             emu.machine.set_breakpoints(breakpoints);
         }
         GuiEvent::MemoryUpdate => {
@@ -443,6 +511,10 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             // Request to flush trace logs.
             emu.machine.flush_trace_logs();
         }
+        GuiEvent::SetLogLevel(category, level) => {
+            log::debug!("Setting {} event log level to {}", category, level);
+            tracelogger::set_category_level(*category, *level);
+        }
         GuiEvent::DelayAdjust => {
             let delay_params = emu.gui.delay_adjust.get_params();
 
@@ -495,6 +567,57 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
         }
+        GuiEvent::ToggleAudioRecording => {
+            let result = if emu.machine.is_recording_audio() {
+                emu.machine.stop_audio_recording()
+            }
+            else {
+                emu.rm
+                    .get_available_filename("recording", "recording", Some("wav"))
+                    .and_then(|path| emu.machine.start_audio_recording(&path))
+            };
+
+            match result {
+                Ok(paths) => {
+                    for path in paths {
+                        log::info!("Audio recording file: {}", path.display());
+                    }
+                }
+                Err(err) => {
+                    log::error!("Audio recording error: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::ExportInstructionHistory(format) => {
+            let result = emu
+                .rm
+                .get_available_filename("trace", "instruction_history", Some(format.extension()))
+                .and_then(|path| {
+                    emu.machine.export_instruction_history(&path, *format)?;
+                    Ok(path)
+                });
+
+            match result {
+                Ok(path) => {
+                    log::info!("Exported instruction history to: {}", path.display());
+                    emu.gui
+                        .toasts()
+                        .info(format!("Exported instruction history to: {}", path.display()))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to export instruction history: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
         GuiEvent::CtrlAltDel => {
             emu.machine.ctrl_alt_del();
         }
@@ -33,7 +33,7 @@ use crate::{event_loop::egui_events::handle_egui_event, Emulator};
 use display_manager_wgpu::DisplayManager;
 use marty_core::{
     bytequeue::ByteQueue,
-    cpu_808x::{Cpu, CpuAddress},
+    cpu_808x::{Cpu, CpuAddress, CPU_HISTORY_LEN},
     cpu_common::CpuOption,
     machine,
     syntax_token::SyntaxToken,
@@ -68,6 +68,7 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
 
     // -- Update machine state
     emu.gui.set_machine_state(emu.machine.get_state());
+    emu.gui.set_audio_recording(emu.machine.is_recording_audio());
 
     // -- Update VHD Creator window
     if emu.gui.is_window_open(GuiWindow::VHDCreator) {
@@ -172,7 +173,7 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
 
     // -- Update Instruction Trace window
     if emu.gui.is_window_open(GuiWindow::InstructionHistoryViewer) {
-        let trace = emu.machine.cpu().dump_instruction_history_tokens();
+        let trace = emu.machine.cpu().dump_instruction_history_tokens(CPU_HISTORY_LEN);
         emu.gui.trace_viewer.set_content(trace);
     }
 
@@ -213,6 +214,7 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
             None => 0,
         };
 
+        let cpu_type = emu.machine.cpu().cpu_type();
         let bus = emu.machine.bus_mut();
 
         let mut listview_vec = Vec::new();
@@ -227,7 +229,7 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
 
                 let mut decode_vec = Vec::new();
 
-                match Cpu::decode(bus) {
+                match Cpu::decode_cpu(bus, cpu_type) {
                     Ok(i) => {
                         let instr_slice = bus.get_slice_at(disassembly_addr_flat, i.size as usize);
                         let instr_bytes_str = util::fmt_byte_array(instr_slice);
@@ -37,6 +37,7 @@ use winit::{
 };
 
 use display_manager_wgpu::DisplayManager;
+use frontend_common::constants::SHORT_NOTIFICATION_TIME;
 
 use crate::{input::TranslateKey, Emulator};
 
@@ -137,6 +138,18 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
                         }
                     }
                 }
+                (winit::event::ElementState::Pressed, KeyCode::F12) => {
+                    if emu.kb_data.ctrl_pressed && !repeat {
+                        // Ctrl-F12 pressed. Switch to the next CPU clock preset, like the clock selection
+                        // hotkeys of turbo XT clones.
+                        let mhz = emu.machine.cycle_cpu_clock();
+                        log::info!("Control F12 pressed. CPU clock set to {:.2}MHz.", mhz);
+                        emu.gui
+                            .toasts()
+                            .info(format!("CPU clock: {:.2}MHz", mhz))
+                            .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                    }
+                }
                 (winit::event::ElementState::Pressed, KeyCode::Enter) => {
                     if emu.kb_data.ctrl_pressed && emu.kb_data.modifiers.alt {
                         // Ctrl-Alt Enter pressed. Toggle fullscreen.
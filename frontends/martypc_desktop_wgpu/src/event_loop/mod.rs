@@ -131,6 +131,13 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                     }
                 }
                 WindowEvent::CloseRequested => {
+                    if let Err(e) = emu.machine.flush_nvram() {
+                        log::error!("Failed to save NVRAM: {}", e);
+                    }
+                    marty_core::tracelogger::flush_event_sink();
+                    if let Some(terminal) = &mut emu.terminal {
+                        _ = terminal.restore(&mut std::io::stdout());
+                    }
                     elwt.exit();
                     return;
                 }
@@ -37,6 +37,8 @@ use marty_core::{device_traits::videocard::BufferSelect, machine::ExecutionState
 pub fn render_frame(emu: &mut Emulator) {
     // First, run each renderer to resolve all videocard views.
     // Every renderer will have an associated card and backend.
+    #[cfg(feature = "vnc")]
+    let mut vnc_frame_sent = false;
     emu.dm.for_each_renderer(|renderer, vid, backend_buf| {
         if let Some(videocard) = emu.machine.bus_mut().video_mut(&vid) {
             // Check if the emulator is paused - if paused, optionally select the back buffer
@@ -61,6 +63,12 @@ pub fn render_frame(emu: &mut Emulator) {
                 }
             }
 
+            // Dual-mode cards may change which personality they display at any time.
+            let video_type = videocard.get_video_type();
+            if renderer.get_video_type() != video_type {
+                renderer.set_video_type(video_type);
+            }
+
             let extents = videocard.get_display_extents();
 
             // Update mode byte.
@@ -76,10 +84,27 @@ pub fn render_frame(emu: &mut Emulator) {
                 backend_buf,
                 extents,
                 beam_pos,
-            )
+            );
+
+            // Send the first card's frame to any VNC clients.
+            #[cfg(feature = "vnc")]
+            if let Some(vnc) = &mut emu.vnc {
+                if !vnc_frame_sent {
+                    let dims = renderer.get_params().backend;
+                    vnc.update_frame(backend_buf, dims.w, dims.h);
+                    vnc_frame_sent = true;
+                }
+            }
         }
     });
 
+    // Mirror the text screen to the terminal.
+    if let Some(terminal) = &mut emu.terminal {
+        if let Err(e) = terminal.render(emu.machine.bus(), &mut std::io::stdout().lock()) {
+            log::error!("Failed to render to terminal: {}", e);
+        }
+    }
+
     // Prepare guis for rendering.
     emu.dm.for_each_gui(|gui, window| gui.prepare(window, &mut emu.gui));
 
@@ -35,9 +35,12 @@ use winit::event_loop::EventLoopWindowTarget;
 use display_manager_wgpu::DisplayManager;
 use frontend_common::{
     constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME},
+    netplay::NetplayStatus,
     timestep_manager::{MachinePerfStats, TimestepManager},
 };
-use marty_core::{bus::DeviceEvent, machine::MachineEvent};
+use marty_core::{bus::DeviceEvent, devices::keyboard::KeyboardModifiers, machine::MachineEvent};
+#[cfg(feature = "vnc")]
+use marty_vnc::VncEvent;
 use videocard_renderer::RendererEvent;
 
 use crate::{
@@ -52,6 +55,9 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
         emu,
         |emuc| {
             // Per second freq
+            if emuc.config.emulator.media.watch_for_changes {
+                poll_media_changes(emuc);
+            }
             MachinePerfStats {
                 cpu_mhz: emuc.machine.get_cpu_mhz(),
                 cpu_cycles: emuc.machine.cpu_cycles(),
@@ -63,16 +69,39 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
         |emuc, cycles| {
             // Per emu update freq
 
+            // In a netplay session the machine is advanced in lockstep frames below instead.
+            if emuc.netplay.is_some() {
+                return None;
+            }
             emuc.machine.run(cycles, &mut emuc.exec_control.borrow_mut());
+
+            // If the machine is halted waiting for an interrupt, let the timestep manager sleep
+            // until the next scheduled device event instead of spinning.
+            emuc.machine.halt_idle_time().map(|us| Duration::from_secs_f64(us / 1_000_000.0))
         },
         |emuc, tmc, &perf| {
             emuc.perf = perf;
 
+            // Run one lockstep frame, if the peer has caught up.
+            if let Some(netplay) = &mut emuc.netplay {
+                match netplay.run_frame(&mut emuc.machine, &mut emuc.exec_control.borrow_mut()) {
+                    Ok(NetplayStatus::Disconnected) => {
+                        log::warn!("Netplay peer disconnected. Continuing offline.");
+                        emuc.netplay = None;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("Netplay error: {}. Continuing offline.", e);
+                        emuc.netplay = None;
+                    }
+                }
+            }
+
             // Per frame freq
-            if let Some(mouse) = emuc.machine.mouse_mut() {
+            if emuc.machine.mouse_mut().is_some() {
                 // Send any pending mouse update to machine if mouse is captured
                 if emuc.mouse_data.is_captured && emuc.mouse_data.have_update {
-                    mouse.update(
+                    emuc.machine.mouse_update(
                         emuc.mouse_data.l_button_was_pressed,
                         emuc.mouse_data.r_button_was_pressed,
                         emuc.mouse_data.frame_delta_x,
@@ -96,7 +125,7 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
 
                     if emuc.mouse_data.l_button_was_released || emuc.mouse_data.r_button_was_released {
                         // Send release event
-                        mouse.update(l_release_state, r_release_state, 0.0, 0.0);
+                        emuc.machine.mouse_update(l_release_state, r_release_state, 0.0, 0.0);
                     }
 
                     // Reset mouse for next frame
@@ -104,6 +133,36 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                 }
             }
 
+            // Apply input from VNC clients
+            #[cfg(feature = "vnc")]
+            if let Some(vnc) = &emuc.vnc {
+                while let Some(event) = vnc.get_event() {
+                    match event {
+                        VncEvent::Key { key, pressed: true } => {
+                            emuc.machine.key_press(key, KeyboardModifiers::default());
+                        }
+                        VncEvent::Key { key, pressed: false } => {
+                            emuc.machine.key_release(key);
+                        }
+                        VncEvent::Pointer {
+                            left,
+                            right,
+                            delta_x,
+                            delta_y,
+                            ..
+                        } => {
+                            emuc.machine.mouse_update(left, right, delta_x as f64, delta_y as f64);
+                        }
+                        VncEvent::ClientConnected(addr) => {
+                            log::info!("VNC client connected from {}", addr);
+                        }
+                        VncEvent::ClientDisconnected(addr) => {
+                            log::info!("VNC client {} disconnected", addr);
+                        }
+                    }
+                }
+            }
+
             // Drain machine events
             while let Some(event) = emuc.machine.get_event() {
                 match event {
@@ -174,17 +233,18 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
             for event in events {
                 match event {
                     DeviceEvent::TurboToggled(state) => {
-                        // Send notification
+                        // Send notification. The new clock has already been applied.
+                        let mhz = emuc.machine.get_cpu_mhz();
                         if state {
                             emuc.gui
                                 .toasts()
-                                .info("Turbo mode enabled!".to_string())
+                                .info(format!("Turbo mode enabled! ({:.2}MHz)", mhz))
                                 .set_duration(Some(SHORT_NOTIFICATION_TIME));
                         }
                         else {
                             emuc.gui
                                 .toasts()
-                                .info("Turbo mode disabled!".to_string())
+                                .info(format!("Turbo mode disabled! ({:.2}MHz)", mhz))
                                 .set_duration(Some(SHORT_NOTIFICATION_TIME));
                         }
                     }
@@ -231,3 +291,19 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
         },
     );
 }
+
+/// Re-scan the floppy directories for images that were added, removed or modified while running,
+/// and refresh the floppy image tree if anything changed.
+fn poll_media_changes(emu: &mut Emulator) {
+    match emu.floppy_manager.rescan_if_changed(&emu.rm) {
+        Ok(true) => {
+            if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
+                emu.gui.set_floppy_tree(floppy_tree);
+            }
+        }
+        Ok(false) => {}
+        Err(e) => {
+            log::error!("Error scanning floppy directory: {}", e);
+        }
+    }
+}
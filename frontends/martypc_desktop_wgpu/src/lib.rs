@@ -42,7 +42,7 @@ mod event_loop;
 mod input;
 mod run_headless;
 
-#[cfg(feature = "arduino_validator")]
+#[cfg(feature = "cpu_validator")]
 mod run_fuzzer;
 
 use std::{
@@ -52,21 +52,30 @@ use std::{
 };
 
 #[cfg(feature = "arduino_validator")]
-use crate::{cpu_test::gen_tests::run_gentests, cpu_test::run_tests, run_fuzzer::run_fuzzer};
+use crate::cpu_test::gen_tests::run_gentests;
+#[cfg(feature = "cpu_validator")]
+use crate::{cpu_test::run_tests, run_fuzzer::run_fuzzer};
 
 use config_toml_bpaf::TestMode;
 
 use marty_core::{
     cpu_validator::ValidatorType,
-    devices::keyboard::KeyboardModifiers,
-    machine::{ExecutionControl, ExecutionState, MachineBuilder},
+    determinism::{DeterminismAudit, DeterminismAuditParams, DeterminismAuditResult},
+    devices::{keyboard::KeyboardModifiers, serial::enumerate_host_ports},
+    lockstep::{LockstepHarness, LockstepParams, LockstepResult},
+    machine::{ExecutionControl, ExecutionState, MachineBuilder, MachineState},
     sound::SoundPlayer,
 };
 
+#[cfg(feature = "vnc")]
+use marty_vnc::VncServer;
+
 use display_manager_wgpu::{DisplayBackend, DisplayManager, DisplayManagerGuiOptions, WgpuDisplayManagerBuilder};
 use frontend_common::{
     floppy_manager::FloppyManager,
+    netplay::NetplaySession,
     resource_manager::ResourceManager,
+    terminal_renderer::TerminalRenderer,
     timestep_manager::TimestepManager,
     vhd_manager::VhdManager,
 };
@@ -282,9 +291,15 @@ pub fn run() {
         resource_manager.set_ignore_dirs(ignore_dirs.clone());
     }
 
+    // Running JSON tests doesn't need a validator.
     #[cfg(feature = "cpu_validator")]
     match config.validator.vtype {
-        Some(ValidatorType::None) | None => {
+        Some(ValidatorType::None) | None
+            if !matches!(
+                config.tests.test_mode,
+                Some(TestMode::Run) | Some(TestMode::Validate) | Some(TestMode::Process)
+            ) =>
+        {
             eprintln!("Compiled with validator but no validator specified");
             std::process::exit(1);
         }
@@ -455,10 +470,7 @@ pub fn run() {
     }
 
     // Enumerate host serial ports
-    let serial_ports = serialport::available_ports().unwrap_or_else(|e| {
-        log::warn!("Didn't find any serial ports: {:?}", e);
-        Vec::new()
-    });
+    let serial_ports = enumerate_host_ports();
 
     for port in &serial_ports {
         log::debug!("Found serial port: {:?}", port);
@@ -493,6 +505,89 @@ pub fn run() {
         //return run_headless::run_headless(&config, rom_manager, floppy_manager);
     }
 
+    // If a determinism audit was requested, run two machines side by side, report and exit.
+    if let Some(audit_config) = &config.emulator.determinism_audit {
+        let audit_machine_config = machine_config_file.to_machine_config();
+        let params = DeterminismAuditParams {
+            cycles: audit_config.cycles,
+            segment_cycles: audit_config.segment_cycles,
+        };
+
+        println!("Running determinism audit for {} cycles...", params.cycles);
+        let mut audit = DeterminismAudit::new(params, || {
+            MachineBuilder::new()
+                .with_core_config(Box::new(&config))
+                .with_machine_config(&audit_machine_config)
+                .with_roms(rom_manifest.clone())
+                .build()
+        });
+
+        match audit.run() {
+            Ok(DeterminismAuditResult::Deterministic(cycles, rolling_hash)) => {
+                println!(
+                    "Determinism audit passed: {} cycles, rolling hash: {:016X}",
+                    cycles, rolling_hash
+                );
+                std::process::exit(0);
+            }
+            Ok(DeterminismAuditResult::Diverged(report)) => {
+                eprintln!("Determinism audit failed: {}", report);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Determinism audit could not run: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // If lockstep comparison was requested, run against the reference, report and exit.
+    if let Some(lockstep_config) = &config.emulator.lockstep {
+        let params = LockstepParams {
+            address: lockstep_config.address.clone(),
+            instructions: lockstep_config.instructions,
+            memory_check_interval: lockstep_config.memory_check_interval,
+            memory_check_size: lockstep_config.memory_check_size,
+            flag_mask: lockstep_config.flag_mask,
+        };
+
+        let mut lockstep_machine = MachineBuilder::new()
+            .with_core_config(Box::new(&config))
+            .with_machine_config(&machine_config_file.to_machine_config())
+            .with_roms(rom_manifest.clone())
+            .build()
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to build machine: {}", e);
+                std::process::exit(1);
+            });
+        lockstep_machine.change_state(MachineState::On);
+
+        let mut harness = LockstepHarness::connect(params).unwrap_or_else(|e| {
+            eprintln!("Failed to connect to lockstep reference: {}", e);
+            std::process::exit(1);
+        });
+        println!("Running in lockstep with reference: {}", harness.reference_name());
+
+        match harness.run(&mut lockstep_machine) {
+            Ok(LockstepResult::Completed(instructions)) => {
+                println!("Lockstep run completed: {} instructions matched.", instructions);
+                std::process::exit(0);
+            }
+            Ok(LockstepResult::Stopped(instructions)) => {
+                println!("Machine stopped after {} matching instructions.", instructions);
+                std::process::exit(0);
+            }
+            Ok(LockstepResult::Mismatch(mismatch)) => {
+                eprintln!("{}", mismatch);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Lockstep run failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // ExecutionControl is shared via RefCell with GUI so that state can be updated by control widget
     let exec_control = Rc::new(RefCell::new(ExecutionControl::new()));
 
@@ -551,11 +646,17 @@ pub fn run() {
         .with_trace_log(trace_file_path)
         .with_sound_player(sound_player_opt);
 
-    let machine = machine_builder.build().unwrap_or_else(|e| {
+    let mut machine = machine_builder.build().unwrap_or_else(|e| {
         log::error!("Failed to build machine: {:?}", e);
         std::process::exit(1);
     });
 
+    // Keep non-volatile device memory, such as a clock card's RAM, in a directory per machine
+    // configuration.
+    if let Some(nvram_path) = resource_manager.get_resource_path("nvram") {
+        machine.set_nvram_dir(nvram_path.join(&config.machine.config_name));
+    }
+
     // Get a list of video devices from machine.
     let cardlist = machine.bus().enumerate_videocards();
 
@@ -582,10 +683,17 @@ pub fn run() {
         debug_drawing: false,
     };
 
+    // Machines with a built-in monitor specify a default scaler preset for their video card.
+    let card_presets = cardlist
+        .iter()
+        .map(|card| machine_config.video.get(card.idx).and_then(|v| v.scaler_preset.clone()))
+        .collect();
+
     // Create displays.
     let mut display_manager = WgpuDisplayManagerBuilder::build(
         &config,
         cardlist,
+        card_presets,
         &config.emulator.scaler_preset,
         None,
         Some(MARTY_ICON),
@@ -607,6 +715,39 @@ pub fn run() {
 
     let machine_events = Vec::new();
 
+    // Start the VNC server, if configured.
+    #[cfg(feature = "vnc")]
+    let vnc = match &config.emulator.vnc {
+        Some(vnc_config) if vnc_config.enabled => match VncServer::new(&vnc_config.address, "MartyPC") {
+            Ok(server) => Some(server),
+            Err(e) => {
+                log::error!("Failed to start VNC server on {}: {}", vnc_config.address, e);
+                None
+            }
+        },
+        _ => None,
+    };
+    #[cfg(not(feature = "vnc"))]
+    if config.emulator.vnc.as_ref().is_some_and(|vnc_config| vnc_config.enabled) {
+        log::warn!("VNC server requested, but MartyPC was built without the vnc feature");
+    }
+
+    // Connect to the netplay peer, if configured. A host listens for the peer in the background,
+    // and the machine is not run until it connects.
+    let netplay = match &config.emulator.netplay {
+        Some(netplay_config) => match NetplaySession::new(netplay_config, machine.get_cpu_mhz()) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                eprintln!("Failed to establish netplay session: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Mirror the text mode screen to the terminal, if configured.
+    let terminal = config.emulator.terminal_output.then(TerminalRenderer::new);
+
     // Put everything we want to handle in event loop into an Emulator struct
     let mut emu = Emulator {
         rm: resource_manager,
@@ -628,6 +769,10 @@ pub fn run() {
             render_gui: render_egui,
             debug_keyboard: false,
         },
+        #[cfg(feature = "vnc")]
+        vnc,
+        netplay,
+        terminal,
     };
 
     // Resize video cards
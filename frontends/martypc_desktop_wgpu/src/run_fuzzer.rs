@@ -26,47 +26,86 @@
 
     run_fuzzer.rs - Implement the main procedure for fuzzer mode.
                     Requires CPU validator feature.
+
+    The fuzzer generates random instructions, constrained by the opcode
+    range and exclude list in the [tests] config section, over randomized
+    register and memory state. Each case is executed once on a CPU without
+    a validator and once on a CPU with the configured validator, if any.
+    Failing cases are minimized and saved as JSON so that they can be
+    replayed with the fuzzer_replay option.
 */
 
 use std::{
-    cell::RefCell,
+    fmt::{self, Display},
     fs::File,
-    io::{BufWriter, Write},
-    rc::Rc,
+    io::{BufReader, BufWriter},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
 use config_toml_bpaf::ConfigFileParams;
-use frontend_common::{floppy_manager::FloppyManager, rom_manager::RomManager};
+use serde::{Deserialize, Serialize};
 
 use marty_core::{
     bytequeue::ByteQueue,
     cpu_808x::{mnemonic::Mnemonic, Cpu, *},
-    cpu_common::{CpuOption, CpuType, TraceMode},
-    devices::pic::Pic,
+    cpu_common::{CpuOption, CpuType},
+    cpu_validator::{VRegisters, ValidatorMode, ValidatorType},
     tracelogger::TraceLogger,
 };
 
-pub fn run_fuzzer(config: &ConfigFileParams) {
-    /*
-    let mut trace_file_option: Box<dyn Write + 'a> = Box::new(std::io::stdout());
-    if config.emulator.trace_mode != TraceMode::None {
-        // Open the trace file if specified
-        if let Some(filename) = &config.emulator.trace_file {
-            match File::create(filename) {
-                Ok(file) => {
-                    trace_file_option = Box::new(BufWriter::new(file));
-                },
-                Err(e) => {
-                    eprintln!("Couldn't create specified tracelog file: {}", e);
-                }
-            }
+/// Number of bytes captured at CS:IP for each case. This covers the longest generated
+/// instruction plus the trailing bytes that end up in the prefetch queue.
+const FUZZ_CASE_BYTES: usize = 16;
+const FUZZ_DEFAULT_SEED: u64 = 1234;
+const FUZZ_DEFAULT_OUTPUT_DIR: &str = "fuzz";
+
+/// A single fuzzer case. Memory is recreated from `mem_seed`, then the registers and
+/// instruction bytes are loaded on top of it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FuzzCase {
+    pub name: String,
+    pub mem_seed: u64,
+    pub bytes: Vec<u8>,
+    pub regs: VRegisters,
+    #[serde(default)]
+    pub failure: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum FuzzFailure {
+    /// The CPU returned an error or panicked while running without the validator.
+    Emulator(String),
+    /// The validator rejected the instruction.
+    Validator(String),
+    /// The CPU finished in a different state with and without the validator.
+    Divergence(String),
+}
+
+impl FuzzFailure {
+    fn same_kind(&self, other: &FuzzFailure) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FuzzFailure::Emulator(msg) => write!(f, "Emulator error: {}", msg),
+            FuzzFailure::Validator(msg) => write!(f, "Validator error: {}", msg),
+            FuzzFailure::Divergence(msg) => write!(f, "Divergence: {}", msg),
         }
     }
-    */
+}
 
-    //let mut io_bus = IoBusInterface::new();
-    let pic = Rc::new(RefCell::new(Pic::new()));
+struct Fuzzer {
+    cpu: Cpu,
+    validated_cpu: Option<Cpu>,
+    trace_on: bool,
+}
 
+pub fn run_fuzzer(config: &ConfigFileParams) {
     // Create the cpu trace file, if specified
     let mut cpu_trace = TraceLogger::None;
     if let Some(trace_filename) = &config.machine.cpu.trace_file {
@@ -80,262 +119,313 @@ pub fn run_fuzzer(config: &ConfigFileParams) {
     }
 
     let trace_mode = config.machine.cpu.trace_mode.unwrap_or_default();
+    let vtype = config.validator.vtype.unwrap_or_default();
 
-    #[cfg(feature = "cpu_validator")]
-    use marty_core::cpu_validator::ValidatorMode;
-
-    let mut cpu = Cpu::new(
+    let cpu = Cpu::new(
         CpuType::Intel8088,
         trace_mode,
         cpu_trace,
-        #[cfg(feature = "cpu_validator")]
-        config.validator.vtype.unwrap(),
-        #[cfg(feature = "cpu_validator")]
-        validator_trace,
-        #[cfg(feature = "cpu_validator")]
+        ValidatorType::None,
+        TraceLogger::None,
         ValidatorMode::Instruction,
-        #[cfg(feature = "cpu_validator")]
-        config.validator.baud_rate.unwrap_or(1_000_000),
+        0,
+        None,
     );
 
-    cpu.randomize_seed(1234);
-    cpu.randomize_mem();
+    let validated_cpu = match vtype {
+        ValidatorType::None => {
+            println!("No validator configured. Fuzzing without validation.");
+            None
+        }
+        _ => Some(Cpu::new(
+            CpuType::Intel8088,
+            trace_mode,
+            TraceLogger::None,
+            vtype,
+            validator_trace,
+            ValidatorMode::Instruction,
+            config.validator.baud_rate.unwrap_or(1_000_000),
+            config.validator.address.clone(),
+        )),
+    };
+
+    let mut fuzzer = Fuzzer {
+        cpu,
+        validated_cpu,
+        trace_on: config.machine.cpu.trace_on,
+    };
+
+    if let Some(replay_path) = &config.tests.fuzzer_replay {
+        fuzzer.replay(Path::new(replay_path));
+        return;
+    }
 
-    let mut test_num = 0;
+    let output_dir = match &config.tests.test_output_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let mut dir = PathBuf::from(config.emulator.basedir.clone());
+            dir.push(FUZZ_DEFAULT_OUTPUT_DIR);
+            dir
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        eprintln!("Couldn't create fuzzer output directory {:?}: {}", output_dir, e);
+        return;
+    }
 
-    'testloop: loop {
-        cpu.reset();
+    let (opcodes, extensions) = match opcode_list(config) {
+        Some(lists) => lists,
+        None => return,
+    };
+
+    let seed = config.tests.test_seed.unwrap_or(FUZZ_DEFAULT_SEED);
+    let iterations = config.tests.fuzzer_iterations;
+    println!(
+        "Fuzzing {} opcodes with seed {}, {} iterations.",
+        opcodes.len(),
+        seed,
+        iterations.map_or("unlimited".to_string(), |n| n.to_string())
+    );
 
-        test_num += 1;
-        cpu.randomize_regs();
+    let fuzz_start = Instant::now();
+    let mut case_num: u32 = 0;
+    let mut ran = 0;
+    let mut failed = 0;
+
+    while iterations.map_or(true, |n| case_num < n) {
+        case_num += 1;
+
+        let opcode = opcodes[case_num as usize % opcodes.len()];
+        let case = match fuzzer.generate(seed.wrapping_add(case_num as u64), opcode, &extensions) {
+            Some(case) => case,
+            None => continue,
+        };
+        ran += 1;
+
+        log::trace!("Case {}: {} {:02X?}", case_num, case.name, case.bytes);
 
-        if cpu.ip() > 0xFFF0 {
-            // Avoid IP wrapping issues for now
-            continue;
+        if let Some(failure) = fuzzer.check(&case) {
+            failed += 1;
+            println!("Case {}: {} failed. {}", case_num, case.name, failure);
+
+            let minimized = fuzzer.minimize(&case, &failure);
+
+            let mut case_path = output_dir.clone();
+            case_path.push(format!("fuzz_{:06}.json", case_num));
+            match write_case(&case_path, &minimized) {
+                Ok(_) => println!("Wrote minimized case '{}' to {:?}", minimized.name, case_path),
+                Err(e) => eprintln!("Failed to write fuzzer case {:?}: {}", case_path, e),
+            }
         }
 
-        if Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip()) > 0xFFFF0 {
-            // Avoid address space wrapping
-            continue;
+        if case_num % 1000 == 0 {
+            println!("Ran {} cases, {} failed.", ran, failed);
         }
+    }
 
-        // Generate specific opcodes (optional)
-
-        // ALU ops
-
-        /*
-        cpu.random_inst_from_opcodes(
-            &[
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, // ADD
-                0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, // OR
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, // ADC
-                0x18, 0x19, 0x1A, 0x1B, 0x1C, 0x1D, // SBB
-                0x20, 0x21, 0x22, 0x23, 0x24, 0x25, // AND
-                0x28, 0x29, 0x2A, 0x2B, 0x2C, 0x2D, // SUB
-                0x30, 0x31, 0x32, 0x33, 0x34, 0x35, // XOR
-                0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, // CMP
-            ]
-        );
-        */
-
-        // Completed 5000 tests
-
-        //cpu.random_inst_from_opcodes(&[0x06, 0x07, 0x0E, 0x0F, 0x16, 0x17, 0x1E, 0x1F]); // PUSH/POP - completed 5000 tests
-        //cpu.random_inst_from_opcodes(&[0x27, 0x2F, 0x37, 0x3F]); // DAA, DAS, AAA, AAS
-        //cpu.random_inst_from_opcodes(&[0x37]);
-
-        //cpu.random_inst_from_opcodes(&[0x37]);
-
-        //cpu.random_inst_from_opcodes(&[0x90]);
-
-        /*
-        // INC & DEC
-        cpu.random_inst_from_opcodes(
-            &[
-                0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
-                0x48, 0x49, 0x4A, 0x4B, 0x4C, 0x4D, 0x4E, 0x4F,
-            ]
-        );
-        */
-
-        /*
-        // PUSH & POP
-        cpu.random_inst_from_opcodes(
-            &[
-                0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57,
-                0x58, 0x59, 0x5A, 0x5B, 0x5C, 0x5D, 0x5E, 0x5F,
-            ]
-        );
-        */
-
-        /*
-        // Relative jumps
-        cpu.random_inst_from_opcodes(
-            &[
-                0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76, 0x77,
-                0x78, 0x79, 0x7A, 0x7B, 0x7C, 0x7D, 0x7E, 0x7F,
-            ]
-        );
-        */
-
-        //cpu.random_inst_from_opcodes(&[0x80, 0x81, 82, 83]); // ALU imm8, imm16, and imm8s
-        //cpu.random_inst_from_opcodes(&[0x84, 0x85]); // TEST 8 & 16 bit
-        //cpu.random_inst_from_opcodes(&[0x86, 0x87]); // XCHG 8 & 16 bit
-        //cpu.random_inst_from_opcodes(&[0x88, 0x89, 0x8A, 0x8B]); // MOV various
-        //cpu.random_inst_from_opcodes(&[0x8D]); // LEA
-        //cpu.random_inst_from_opcodes(&[0x8C, 0x8E]); // MOV Sreg
-
-        //cpu.random_inst_from_opcodes(&[0x8F]); // POP  (Weird behavior when REG != 0)
-
-        //cpu.random_inst_from_opcodes(&[0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97]); // XCHG reg, ax
-        //cpu.random_inst_from_opcodes(&[0x98, 0x99]); // CBW, CWD
-        //cpu.random_inst_from_opcodes(&[0x9A]); // CALLF
-        //cpu.random_inst_from_opcodes(&[0x9C, 0x9D]); // PUSHF, POPF
-        //cpu.random_inst_from_opcodes(&[0x9E, 0x9F]); // SAHF, LAHF
-        //cpu.random_inst_from_opcodes(&[0xA0, 0xA1, 0xA2, 0xA3]); // MOV offset
-
-        //cpu.random_inst_from_opcodes(&[0xA4, 0xA5]); // MOVS
-        //cpu.random_inst_from_opcodes(&[0xAC, 0xAD]); // LODS
-
-        //cpu.random_inst_from_opcodes(&[0xA6, 0xA7]); // CMPS
-        //cpu.random_inst_from_opcodes(&[0xAE, 0xAF]); // SCAS
-
-        //cpu.random_inst_from_opcodes(&[0xA8, 0xA9]); // TEST
-
-        //cpu.random_inst_from_opcodes(&[0xAA, 0xAB]); // STOS
-
-        // MOV imm
-        /*
-        cpu.random_inst_from_opcodes(
-            &[
-                0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7,
-                0xB8, 0xB9, 0xBA, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF
-            ]
-        );
-        */
-
-        //cpu.random_inst_from_opcodes(&[0xC0, 0xC1, 0xC2, 0xC3]); // RETN
-        //cpu.random_inst_from_opcodes(&[0xC4]); // LES
-        //cpu.random_inst_from_opcodes(&[0xC5]); // LDS
-        //cpu.random_inst_from_opcodes(&[0xC6, 0xC7]); // MOV r/m, imm
-        //cpu.random_inst_from_opcodes(&[0xC8, 0xC9, 0xCA, 0xCB]); // RETF
-        //cpu.random_inst_from_opcodes(&[0xCC]); // INT3
-        //cpu.random_inst_from_opcodes(&[0xCD]); // INT
-        //cpu.random_inst_from_opcodes(&[0xCE]); // INT0
-        //cpu.random_inst_from_opcodes(&[0xCF]); // IRET  ** unaccounted for cycle after FLUSH
-
-        //cpu.random_inst_from_opcodes(&[0xD0, 0xD1]); // Misc bitshift ops, 1
-        //cpu.random_inst_from_opcodes(&[0xD2]); // Misc bitshift ops, cl
-
-        //cpu.random_inst_from_opcodes(&[0xD4]); // AAM
-        //cpu.random_inst_from_opcodes(&[0xD5]); // AAD
-        //cpu.random_inst_from_opcodes(&[0xD6]); // SALC
-        //cpu.random_inst_from_opcodes(&[0xD7]); // XLAT
-        //cpu.random_inst_from_opcodes(&[0xD8, 0xD9, 0xDA, 0xDB, 0xDC, 0xDD, 0xDE, 0xDF]); // ESC
-
-        //cpu.random_inst_from_opcodes(&[0xE0, 0xE1, 0xE2, 0xE3]); // LOOP & JCXZ
-        //cpu.random_inst_from_opcodes(&[0xE8, 0xE9, 0xEA, 0xEB]); // CALL & JMP
-
-        //cpu.random_inst_from_opcodes(&[0xF5]); // CMC
-
-        //cpu.random_grp_instruction(0xF6, &[0, 1, 2, 3]); // 8 bit TEST, NOT & NEG
-        //cpu.random_grp_instruction(0xF7, &[0, 1, 2, 3]); // 16 bit TEST, NOT & NEG
-        //cpu.random_grp_instruction(0xF6, &[4, 5]); // 8 bit MUL & IMUL
-        //cpu.random_grp_instruction(0xF7, &[4, 5]); // 16 bit MUL & IMUL
-
-        cpu.random_grp_instruction(0xF6, &[6, 7]); // 8 bit DIV & IDIV
-                                                   //cpu.random_grp_instruction(0xF7, &[6, 7]); // 16 bit DIV & IDIV
-
-        //cpu.random_inst_from_opcodes(&[0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD]); // CLC, STC, CLI, STI, CLD, STD
-
-        //cpu.random_grp_instruction(0xFE, &[0, 1]); // 8 bit INC & DEC
-        //cpu.random_grp_instruction(0xFF, &[0, 1]); // 16 bit INC & DEC
-
-        //cpu.random_grp_instruction(0xFE, &[2, 3]); // CALL & CALLF
-        //cpu.random_grp_instruction(0xFF, &[2, 3]); // CALL & CALLF
-        //cpu.random_grp_instruction(0xFE, &[4, 5]); // JMP & JMPF
-        //cpu.random_grp_instruction(0xFF, &[4, 5]); // JMP & JMPF
-        //cpu.random_grp_instruction(0xFE, &[6, 7]); // 8-bit broken PUSH & POP
-        //cpu.random_grp_instruction(0xFF, &[6, 7]); // PUSH & POP
-
-        // Decode this instruction
-        let instruction_address = Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip());
+    println!(
+        "Fuzzing complete. Ran {} cases in {:.2} seconds, {} failed.",
+        ran,
+        fuzz_start.elapsed().as_secs_f32(),
+        failed
+    );
 
-        cpu.bus_mut().seek(instruction_address as usize);
-        let (opcode, _cost) = cpu.bus_mut().read_u8(instruction_address as usize, 0).expect("mem err");
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
 
-        let mut i = match Cpu::decode(cpu.bus_mut()) {
-            Ok(i) => i,
-            Err(_) => {
-                log::error!("Instruction decode error, skipping...");
-                continue;
-            }
-        };
+/// Build the list of opcodes to fuzz from the test configuration. Returns the opcode list
+/// and the list of group opcode extensions to use.
+fn opcode_list(config: &ConfigFileParams) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut opcode_range_start = 0;
+    let mut opcode_range_end = 0xFF;
+
+    if let Some(test_opcode_range) = &config.tests.test_opcode_range {
+        if test_opcode_range.len() > 1 {
+            opcode_range_start = test_opcode_range[0];
+            opcode_range_end = test_opcode_range[1];
+        }
+        else {
+            log::error!("Invalid opcode range specified.");
+            return None;
+        }
+    }
+
+    let mut opcode_range_exclude = config.tests.test_opcode_exclude_list.clone().unwrap_or_default();
+    opcode_range_exclude.extend_from_slice(&[
+        0x26, 0x2E, 0x36, 0x3E, // Segment override prefixes
+        0x9B, // WAIT instruction
+        0xF0, 0xF1, 0xF2, 0xF3, // Prefixes
+        0xF4, // HLT
+    ]);
 
-        // Skip N successful instructions
+    let mut opcodes = Vec::from_iter(opcode_range_start..=opcode_range_end);
+    opcodes.retain(|x| !opcode_range_exclude.contains(x));
 
-        // was at 13546
-        if test_num < 0 {
-            continue;
+    if opcodes.is_empty() {
+        log::error!("No opcodes left to fuzz after exclusions.");
+        return None;
+    }
+
+    let extensions = match &config.tests.test_extension_range {
+        Some(range) if range.len() > 1 => Vec::from_iter(range[0]..=range[1]),
+        Some(_) => {
+            log::error!("Invalid opcode extension range specified.");
+            return None;
         }
+        None => Vec::from_iter(0..=7),
+    };
 
-        cpu.set_option(CpuOption::EnableWaitStates(false));
-        cpu.set_option(CpuOption::TraceLoggingEnabled(config.machine.cpu.trace_on));
+    Some((opcodes, extensions))
+}
+
+fn is_group_opcode(opcode: u8) -> bool {
+    matches!(opcode, 0x80..=0x83 | 0xD0..=0xD3 | 0xF6 | 0xF7 | 0xFE | 0xFF)
+}
+
+fn is_string_op(mnemonic: Mnemonic) -> bool {
+    matches!(
+        mnemonic,
+        Mnemonic::MOVSB
+            | Mnemonic::MOVSW
+            | Mnemonic::CMPSB
+            | Mnemonic::CMPSW
+            | Mnemonic::STOSB
+            | Mnemonic::STOSW
+            | Mnemonic::LODSB
+            | Mnemonic::LODSW
+            | Mnemonic::SCASB
+            | Mnemonic::SCASW
+    )
+}
+
+fn write_case(path: &Path, case: &FuzzCase) -> anyhow::Result<()> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), case)?;
+    Ok(())
+}
+
+fn read_case(path: &Path) -> anyhow::Result<FuzzCase> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+/// Set up the CPU for a case. Returns the decoded instruction and whether it is a string
+/// instruction, or None if the bytes at CS:IP don't decode.
+fn load_case(cpu: &mut Cpu, case: &FuzzCase, trace_on: bool) -> Option<(Instruction, bool)> {
+    cpu.randomize_seed(case.mem_seed);
+    cpu.randomize_mem();
+    cpu.set_fuzz_regs(&case.regs);
+    cpu.write_fuzz_bytes(&case.bytes);
+
+    let instruction_address = Cpu::calc_linear_address(case.regs.cs, case.regs.ip);
+    cpu.bus_mut().seek(instruction_address as usize);
+    let mut i = Cpu::decode(cpu.bus_mut()).ok()?;
+    i.address = instruction_address;
+
+    cpu.set_option(CpuOption::EnableWaitStates(false));
+    cpu.set_option(CpuOption::TraceLoggingEnabled(trace_on));
+
+    // Set terminating address for CPU validator.
+    let end_address = Cpu::calc_linear_address(case.regs.cs, case.regs.ip.wrapping_add(i.size as u16));
+    cpu.set_end_address(end_address as usize);
+
+    let rep = is_string_op(i.mnemonic);
+    Some((i, rep))
+}
 
-        match i.opcode {
-            0xFE | 0xD2 | 0xD3 | 0x8F => {
-                continue;
+/// Run a case to completion. Returns None if the case couldn't be loaded, otherwise the final
+/// register state or the error raised by the CPU.
+fn execute(cpu: &mut Cpu, case: &FuzzCase, trace_on: bool) -> Option<Result<VRegisters, String>> {
+    let (_, rep) = load_case(cpu, case, trace_on)?;
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        // We loop here to handle REP string instructions, which are broken up into 1 effective
+        // instruction execution per iteration. The 8088 makes no such distinction.
+        loop {
+            if let Err(err) = cpu.step(false) {
+                cpu.trace_flush();
+                return Err(err.to_string());
             }
-            _ => {}
+            if let Err(err) = cpu.step_finish() {
+                cpu.trace_flush();
+                return Err(err.to_string());
+            }
+            if !(rep && cpu.in_rep()) {
+                return Ok(VRegisters::from(&cpu.get_state()));
+            }
+        }
+    }));
+
+    Some(match result {
+        Ok(result) => result,
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(msg) => msg.clone(),
+                    None => "panic".to_string(),
+                },
+            };
+            Err(format!("CPU panicked: {}", msg))
+        }
+    })
+}
+
+impl Fuzzer {
+    /// Generate a new case from `seed`. Returns None if the generated instruction is one
+    /// we can't reasonably validate.
+    fn generate(&mut self, seed: u64, opcode: u8, extensions: &[u8]) -> Option<FuzzCase> {
+        let cpu = &mut self.cpu;
+
+        cpu.randomize_seed(seed);
+        cpu.randomize_mem();
+        cpu.randomize_regs();
+
+        let mut instruction_address = Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip());
+        while (cpu.ip() > 0xFFF0) || ((instruction_address & 0xFFFFF) > 0xFFFF0) {
+            // Avoid IP and address space wrapping issues for now
+            cpu.randomize_regs();
+            instruction_address = Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip());
         }
 
-        let mut rep = false;
+        if is_group_opcode(opcode) {
+            cpu.random_grp_instruction(opcode, extensions);
+        }
+        else {
+            cpu.random_inst_from_opcodes(&[opcode]);
+        }
+
+        let mut regs = VRegisters::from(&cpu.get_state());
+        let bytes = (0..FUZZ_CASE_BYTES)
+            .map(|o| cpu.bus().peek_u8(instruction_address as usize + o).unwrap_or(0))
+            .collect::<Vec<u8>>();
+
+        cpu.bus_mut().seek(instruction_address as usize);
+        let i = match Cpu::decode(cpu.bus_mut()) {
+            Ok(i) => i,
+            Err(_) => {
+                log::error!("Instruction decode error, skipping...");
+                return None;
+            }
+        };
+
         match i.mnemonic {
-            /*
-            Mnemonic::INT | Mnemonic::INT3 | Mnemonic::INTO | Mnemonic::IRET => {
-                continue;
-            },
-            */
-            Mnemonic::FWAIT => {
-                continue;
+            Mnemonic::FWAIT | Mnemonic::HLT => {
+                return None;
             }
             Mnemonic::POPF => {
                 // POPF can set trap flag which messes up the validator
-                continue;
+                return None;
             }
             Mnemonic::LDS | Mnemonic::LES | Mnemonic::LEA => {
                 if let OperandType::Register16(_) = i.operand2_type {
                     // Invalid forms end up using the last calculated EA. However this will differ between
                     // the validator and CPU due to the validator setup routine.
-                    continue;
+                    return None;
                 }
             }
-            Mnemonic::HLT => {
-                // For obvious reasons
-                continue;
-            }
-            /*
-            Mnemonic::AAM | Mnemonic::DIV | Mnemonic::IDIV => {
-                // Timings on these will take some work
-                continue;
-            }
-            */
-            Mnemonic::MOVSB
-            | Mnemonic::MOVSW
-            | Mnemonic::CMPSB
-            | Mnemonic::CMPSW
-            | Mnemonic::STOSB
-            | Mnemonic::STOSW
-            | Mnemonic::LODSB
-            | Mnemonic::LODSW
-            | Mnemonic::SCASB
-            | Mnemonic::SCASW => {
-                // limit cx to 31.
-                cpu.set_register16(Register16::CX, cpu.get_register16(Register16::CX) % 32);
-
-                rep = true;
-            }
-
             Mnemonic::SETMO
             | Mnemonic::SETMOC
             | Mnemonic::ROL
@@ -346,63 +436,145 @@ pub fn run_fuzzer(config: &ConfigFileParams) {
             | Mnemonic::SHR
             | Mnemonic::SAR => {
                 // Limit cl to 0-31.
-                cpu.set_register8(Register8::CL, cpu.get_register8(Register8::CL) % 32);
+                regs.cx = (regs.cx & 0xFF00) | (regs.cx & 0x1F);
+            }
+            m if is_string_op(m) => {
+                // Limit cx to 31.
+                regs.cx %= 32;
             }
             _ => {}
         }
 
-        i.address = instruction_address;
+        Some(FuzzCase {
+            name: i.to_string(),
+            mem_seed: seed,
+            bytes,
+            regs,
+            failure: String::new(),
+        })
+    }
 
-        log::trace!(
-            "Test {}: Validating instruction: {} op:{:02X} @ [{:05X}]",
-            test_num,
-            i,
-            opcode,
-            i.address
-        );
+    /// Run a case without and then with the validator. Returns None if the case passed or
+    /// couldn't be loaded.
+    fn check(&mut self, case: &FuzzCase) -> Option<FuzzFailure> {
+        let plain_regs = match execute(&mut self.cpu, case, self.trace_on)? {
+            Ok(regs) => regs,
+            Err(e) => return Some(FuzzFailure::Emulator(e)),
+        };
 
-        // Set terminating address for CPU validator.
-        let end_address =
-            Cpu::calc_linear_address(cpu.get_register16(Register16::CS), cpu.ip().wrapping_add(i.size as u16));
+        if let Some(cpu) = &mut self.validated_cpu {
+            match execute(cpu, case, self.trace_on)? {
+                Ok(regs) => {
+                    let diffs = plain_regs.diff(&regs, 0xFFFF);
+                    if !diffs.is_empty() {
+                        let desc = diffs
+                            .iter()
+                            .map(|(name, a, b)| format!("{}: {:04X} != {:04X}", name, a, b))
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                        return Some(FuzzFailure::Divergence(desc));
+                    }
+                }
+                Err(e) => return Some(FuzzFailure::Validator(e)),
+            }
+        }
 
-        cpu.set_end_address(end_address as usize);
-        log::trace!("Setting end address: {:05X}", end_address);
+        None
+    }
 
-        // We loop here to handle REP string instructions, which are broken up into 1 effective instruction
-        // execution per iteration. The 8088 makes no such distinction.
-        loop {
-            match cpu.step(false) {
-                Ok((_, cycles)) => {
-                    log::trace!("Instruction reported {} cycles", cycles);
+    /// Keep `candidate` if it still fails the same way as the original case.
+    fn try_reduce(&mut self, best: &mut FuzzCase, failure: &mut FuzzFailure, candidate: FuzzCase) -> bool {
+        match self.check(&candidate) {
+            Some(new_failure) if new_failure.same_kind(failure) => {
+                *best = candidate;
+                *failure = new_failure;
+                true
+            }
+            _ => false,
+        }
+    }
 
-                    if rep & cpu.in_rep() {
-                        continue;
-                    }
-                }
-                Err(err) => {
-                    log::error!("CPU Error: {}\n", err);
-                    cpu.trace_flush();
-                    break 'testloop;
-                }
+    /// Reduce a failing case to the simplest form that still fails the same way: strip
+    /// prefixes, replace trailing bytes with NOPs, and zero registers and flags one at a time.
+    fn minimize(&mut self, case: &FuzzCase, failure: &FuzzFailure) -> FuzzCase {
+        let mut best = case.clone();
+        let mut failure = failure.clone();
+
+        // Strip prefixes.
+        while matches!(best.bytes[0], 0x26 | 0x2E | 0x36 | 0x3E | 0xF0 | 0xF2 | 0xF3) {
+            let mut candidate = best.clone();
+            candidate.bytes.remove(0);
+            candidate.bytes.push(0x90);
+            if !self.try_reduce(&mut best, &mut failure, candidate) {
+                break;
             }
+        }
 
-            match cpu.step_finish() {
-                Ok(_) => {
-                    //log::trace!("Instruction reported {} cycles", cycles);
+        // Replace bytes following the instruction with NOPs.
+        if let Some((i, _)) = load_case(&mut self.cpu, &best, false) {
+            let size = (i.size as usize).min(best.bytes.len());
+            if best.bytes[size..].iter().any(|&b| b != 0x90) {
+                let mut candidate = best.clone();
+                candidate.bytes[size..].fill(0x90);
+                self.try_reduce(&mut best, &mut failure, candidate);
+            }
+        }
 
-                    if rep & cpu.in_rep() {
-                        continue;
-                    }
-                    break;
-                }
-                Err(err) => {
-                    log::error!("CPU Error: {}\n", err);
-                    cpu.trace_flush();
-                    break 'testloop;
-                }
+        // Zero registers. CS and IP are left alone so the instruction stays where it is.
+        let reg_fields: [fn(&mut VRegisters) -> &mut u16; 11] = [
+            |r| &mut r.ax,
+            |r| &mut r.bx,
+            |r| &mut r.cx,
+            |r| &mut r.dx,
+            |r| &mut r.sp,
+            |r| &mut r.bp,
+            |r| &mut r.si,
+            |r| &mut r.di,
+            |r| &mut r.ds,
+            |r| &mut r.es,
+            |r| &mut r.ss,
+        ];
+        for field in reg_fields {
+            if *field(&mut best.regs) != 0 {
+                let mut candidate = best.clone();
+                *field(&mut candidate.regs) = 0;
+                self.try_reduce(&mut best, &mut failure, candidate);
             }
         }
+
+        if best.regs.flags != 0 {
+            let mut candidate = best.clone();
+            candidate.regs.flags = 0;
+            self.try_reduce(&mut best, &mut failure, candidate);
+        }
+
+        if let Some((i, _)) = load_case(&mut self.cpu, &best, false) {
+            best.name = i.to_string();
+        }
+        best.failure = failure.to_string();
+        best
     }
 
-    //std::process::exit(0);
+    fn replay(&mut self, path: &Path) {
+        let case = match read_case(path) {
+            Ok(case) => case,
+            Err(e) => {
+                eprintln!("Failed to read fuzzer case {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+
+        println!("Replaying case '{}' from {:?}", case.name, path);
+        if !case.failure.is_empty() {
+            println!("Recorded failure: {}", case.failure);
+        }
+
+        match self.check(&case) {
+            Some(failure) => {
+                println!("Case failed. {}", failure);
+                std::process::exit(1);
+            }
+            None => println!("Case passed."),
+        }
+    }
 }
@@ -91,6 +91,9 @@ impl CoreConfig for ConfigFileParams {
     fn get_validator_baud(&self) -> Option<u32> {
         self.validator.baud_rate
     }
+    fn get_validator_address(&self) -> Option<String> {
+        self.validator.address.clone()
+    }
     fn get_cpu_trace_mode(&self) -> Option<TraceMode> {
         self.machine.cpu.trace_mode
     }
@@ -33,6 +33,7 @@
 */
 
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -41,11 +42,22 @@ use marty_core::{
     coreconfig::VideoCardDefinition,
     cpu_common::TraceMode,
     cpu_validator::ValidatorType,
-    devices::keyboard::KeyboardType,
+    device_traits::videocard::VideoRegisterGroup,
+    devices::{
+        keyboard::KeyboardType,
+        serial::{SerialBridgeConfig, StdioLineMode},
+    },
+    host_clock::HostClockConfig,
     machine_types::HardDiskControllerType,
+    tracelogger::{LogCategory, LogLevel},
 };
 
-use frontend_common::{display_scaler::ScalerPreset, resource_manager::PathConfigItem, MartyGuiTheme};
+use frontend_common::{
+    display_scaler::ScalerPreset,
+    netplay::NetplayConfig,
+    resource_manager::PathConfigItem,
+    MartyGuiTheme,
+};
 use marty_common::VideoDimensions;
 
 use bpaf::Bpaf;
@@ -58,6 +70,21 @@ const fn _default_true() -> bool {
 const fn _default_false() -> bool {
     true
 }
+const fn _default_audit_cycles() -> u64 {
+    50_000_000
+}
+const fn _default_audit_segment_cycles() -> u32 {
+    100_000
+}
+const fn _default_lockstep_memory_check_size() -> usize {
+    0xA0000
+}
+const fn _default_lockstep_flag_mask() -> u16 {
+    0x0FD5
+}
+const fn _default_serial_bridge_port() -> usize {
+    1
+}
 
 mod coreconfig;
 
@@ -103,6 +130,8 @@ pub struct Media {
     pub raw_sector_image_extensions: Option<Vec<String>>,
     #[serde(default)]
     pub write_protect_default: bool,
+    #[serde(default)]
+    pub watch_for_changes: bool,
     pub vhd: Option<Vec<VhdConfigEntry>>,
 }
 
@@ -117,6 +146,8 @@ pub struct Debugger {
     pub checkpoint_notify_level: Option<u32>,
     #[serde(default)]
     pub breakpoint_notify: bool,
+    #[serde(default)]
+    pub vector_watch: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -131,6 +162,8 @@ pub struct Emulator {
     #[serde(default)]
     pub headless: bool,
     #[serde(default)]
+    pub terminal_output: bool,
+    #[serde(default)]
     pub romscan: bool,
     #[serde(default)]
     pub machinescan: bool,
@@ -155,6 +188,14 @@ pub struct Emulator {
     pub video_trace_file: Option<PathBuf>,
     //pub video_frame_debug: bool,
     #[serde(default)]
+    pub video_register_trace: Option<VideoRegisterTraceConfig>,
+    #[serde(default)]
+    pub hdc_access_log: Option<HdcAccessLogConfig>,
+    #[serde(default)]
+    pub irq_trace: Option<IrqTraceConfig>,
+    #[serde(default)]
+    pub event_log: Option<EventLogConfig>,
+    #[serde(default)]
     pub pit_output_file: Option<PathBuf>,
     #[serde(default)]
     pub pit_output_int_trigger: bool,
@@ -162,6 +203,18 @@ pub struct Emulator {
     pub window: Vec<WindowDefinition>,
     pub scaler_preset: Vec<ScalerPreset>,
     pub input: EmulatorInput,
+    #[serde(default)]
+    pub vnc: Option<VncServerConfig>,
+    #[serde(default)]
+    pub serial_stdio: Option<SerialStdioConfig>,
+    #[serde(default)]
+    pub serial_bridge: Option<SerialBridgeOptions>,
+    #[serde(default)]
+    pub netplay: Option<NetplayConfig>,
+    #[serde(default)]
+    pub determinism_audit: Option<DeterminismAuditConfig>,
+    #[serde(default)]
+    pub lockstep: Option<LockstepConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -180,6 +233,7 @@ pub struct Validator {
     pub trigger_address: Option<u32>,
     pub trace_file: Option<PathBuf>,
     pub baud_rate: Option<u32>,
+    pub address: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -193,6 +247,8 @@ pub struct Tests {
     pub test_opcode_exclude_list: Option<Vec<u8>>,
     pub test_opcode_gen_count: Option<u32>,
     pub test_opcode_gen_append: Option<bool>,
+    pub fuzzer_iterations: Option<u32>,
+    pub fuzzer_replay: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -200,17 +256,23 @@ pub struct Cpu {
     pub wait_states: Option<bool>,
     pub off_rails_detection: Option<bool>,
     pub on_halt: Option<HaltMode>,
+    pub halt_idle: Option<bool>,
+    pub decode_cache: Option<bool>,
+    pub fast_core: Option<bool>,
     pub instruction_history: Option<bool>,
+    pub instruction_history_depth: Option<usize>,
     pub service_interrupt: Option<bool>,
     #[serde(default)]
     pub trace_on: bool,
     pub trace_mode: Option<TraceMode>,
     pub trace_file: Option<PathBuf>,
+    pub trace_mem_operands: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MachineInput {
     pub keyboard_layout: Option<String>,
+    pub input_script: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -230,9 +292,82 @@ pub struct Machine {
     pub turbo: bool,
     pub cpu: Cpu,
     pub pit_phase: Option<u32>,
+    #[serde(default)]
+    pub host_clock: HostClockConfig,
     pub input: MachineInput,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct VncServerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SerialStdioConfig {
+    pub port: usize,
+    #[serde(default)]
+    pub line_mode: StdioLineMode,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SerialBridgeOptions {
+    #[serde(default = "_default_serial_bridge_port")]
+    pub port: usize,
+    pub host_port: Option<String>,
+    #[serde(flatten)]
+    pub settings: SerialBridgeConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeterminismAuditConfig {
+    #[serde(default = "_default_audit_cycles")]
+    pub cycles: u64,
+    #[serde(default = "_default_audit_segment_cycles")]
+    pub segment_cycles: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LockstepConfig {
+    pub address: String,
+    #[serde(default)]
+    pub instructions: u64,
+    #[serde(default)]
+    pub memory_check_interval: u64,
+    #[serde(default = "_default_lockstep_memory_check_size")]
+    pub memory_check_size: usize,
+    #[serde(default = "_default_lockstep_flag_mask")]
+    pub flag_mask: u16,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VideoRegisterTraceConfig {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub groups: Vec<VideoRegisterGroup>,
+    pub index: Option<u8>,
+    #[serde(default)]
+    pub changes_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HdcAccessLogConfig {
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IrqTraceConfig {
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventLogConfig {
+    pub file:   Option<PathBuf>,
+    #[serde(default)]
+    pub levels: HashMap<LogCategory, LogLevel>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct EmulatorInput {
     #[serde(default)]
@@ -279,6 +414,9 @@ pub struct CmdLineArgs {
     #[bpaf(long, switch)]
     pub headless: bool,
 
+    #[bpaf(long, switch)]
+    pub terminal_output: bool,
+
     #[bpaf(long, switch)]
     pub fuzzer: bool,
 
@@ -328,6 +466,17 @@ pub struct CmdLineArgs {
 
     //#[bpaf(long, switch)]
     //pub video_frame_debug: bool,
+    #[bpaf(long)]
+    pub serial_stdio: Option<usize>,
+    #[bpaf(long)]
+    pub serial_stdio_line_mode: Option<StdioLineMode>,
+
+    #[bpaf(long)]
+    pub determinism_audit: Option<u64>,
+
+    #[bpaf(long)]
+    pub input_script: Option<PathBuf>,
+
     #[bpaf(long)]
     pub run_bin: Option<String>,
     #[bpaf(long)]
@@ -356,6 +505,7 @@ impl ConfigFileParams {
         }
 
         self.emulator.headless |= shell_args.headless;
+        self.emulator.terminal_output |= shell_args.terminal_output;
         self.emulator.fuzzer |= shell_args.fuzzer;
         self.emulator.auto_poweron |= shell_args.auto_poweron;
         self.emulator.warpspeed |= shell_args.warpspeed;
@@ -367,6 +517,39 @@ impl ConfigFileParams {
         self.emulator.debug_keyboard |= shell_args.debug_keyboard;
         self.machine.no_roms |= shell_args.no_roms;
 
+        if let Some(port) = shell_args.serial_stdio {
+            match &mut self.emulator.serial_stdio {
+                Some(serial_stdio) => serial_stdio.port = port,
+                None => {
+                    self.emulator.serial_stdio = Some(SerialStdioConfig {
+                        port,
+                        line_mode: StdioLineMode::Raw,
+                    })
+                }
+            }
+        }
+        if let Some(line_mode) = shell_args.serial_stdio_line_mode {
+            if let Some(serial_stdio) = &mut self.emulator.serial_stdio {
+                serial_stdio.line_mode = line_mode;
+            }
+        }
+
+        if let Some(input_script) = shell_args.input_script {
+            self.machine.input.input_script = Some(input_script);
+        }
+
+        if let Some(cycles) = shell_args.determinism_audit {
+            match &mut self.emulator.determinism_audit {
+                Some(audit) => audit.cycles = cycles,
+                None => {
+                    self.emulator.determinism_audit = Some(DeterminismAuditConfig {
+                        cycles,
+                        segment_cycles: _default_audit_segment_cycles(),
+                    })
+                }
+            }
+        }
+
         /*
         if let Some(video) = shell_args.video_type {
             self.machine.primary_video = Some(video);
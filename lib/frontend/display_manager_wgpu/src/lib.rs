@@ -258,6 +258,7 @@ impl DefaultResolver for WindowDefinition {
 /// our display targets using:
 /// - the user configuration file
 /// - a list of video cards from the emulator core
+/// - the default scaler preset for each video card, if its machine configuration specifies one
 /// - a list of scaler preset definitions
 /// - a path to an icon (TODO: support different icons per window?)
 /// - a struct of GUI options for the immediate-mode gui a window may contain
@@ -265,6 +266,7 @@ impl WgpuDisplayManagerBuilder {
     pub fn build(
         config: &ConfigFileParams,
         cards: Vec<VideoCardId>,
+        card_presets: Vec<Option<String>>,
         scaler_presets: &Vec<ScalerPreset>,
         icon_path: Option<PathBuf>,
         icon_buf: Option<&[u8]>,
@@ -326,6 +328,7 @@ impl WgpuDisplayManagerBuilder {
                 true,
                 &config.emulator.window[0],
                 &cards,
+                &card_presets,
                 gui_options,
                 icon.clone(),
             )
@@ -334,8 +337,16 @@ impl WgpuDisplayManagerBuilder {
             // Create the rest of the windows
             for window_def in config.emulator.window.iter().skip(1) {
                 if window_def.enabled {
-                    Self::create_target_from_window_def(&mut dm, false, &window_def, &cards, gui_options, icon.clone())
-                        .expect("FATAL: Failed to create a window target");
+                    Self::create_target_from_window_def(
+                        &mut dm,
+                        false,
+                        &window_def,
+                        &cards,
+                        &card_presets,
+                        gui_options,
+                        icon.clone(),
+                    )
+                    .expect("FATAL: Failed to create a window target");
                 }
             }
         }
@@ -348,6 +359,7 @@ impl WgpuDisplayManagerBuilder {
         main_window: bool,
         window_def: &WindowDefinition,
         cards: &Vec<VideoCardId>,
+        card_presets: &Vec<Option<String>>,
         gui_options: &DisplayManagerGuiOptions,
         icon: Option<Icon>,
     ) -> Result<(), Error> {
@@ -355,11 +367,13 @@ impl WgpuDisplayManagerBuilder {
         log::debug!("{:?}", window_def);
 
         let mut card_id_opt = None;
+        let mut card_preset_opt = None;
         let mut card_string = String::new();
 
         if let Some(w_card_id) = resolved_def.card_id {
             if w_card_id < cards.len() {
                 card_id_opt = Some(cards[w_card_id]);
+                card_preset_opt = card_presets.get(w_card_id).cloned().flatten();
                 card_string.push_str(&format!("{:?}", cards[w_card_id].vtype))
             }
             card_string.push_str(&format!("({})", w_card_id));
@@ -396,7 +410,21 @@ impl WgpuDisplayManagerBuilder {
         // If this is Some, it locks the window resolution to some scale factor of card resolution
         window_opts.card_scale = window_def.card_scale;
 
-        let preset_name = window_def.scaler_preset.clone().unwrap_or("default".to_string());
+        // A preset named by the window takes priority over the card's default preset, which
+        // represents a machine's built-in monitor.
+        let mut preset_name = window_def
+            .scaler_preset
+            .clone()
+            .or(card_preset_opt)
+            .unwrap_or("default".to_string());
+
+        if dm.get_scaler_preset(preset_name.clone()).is_none() {
+            log::warn!(
+                "Scaler preset '{}' not found, using default preset instead.",
+                preset_name
+            );
+            preset_name = "default".to_string();
+        }
 
         // Construct window title.
         let window_title = format!("{}: {}", &window_def.name, card_string).to_string();
@@ -27,17 +27,19 @@
     frontend_common::floppy_manager.rs
 
     Discover floppy images in the 'floppy' resource and provide an interface
-    for enumerating and loading them.
+    for enumerating, cataloging and loading them.
 
 */
 
 use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
+use marty_core::device_types::{chs::DiskChs, fdc::DISK_FORMATS};
 use std::{
     collections::HashMap,
     ffi::OsString,
     fmt::Display,
     fs,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use anyhow::Error;
@@ -63,10 +65,72 @@ impl Display for FloppyError {
 
 #[allow(dead_code)]
 pub struct FloppyImage {
-    idx:  usize,
+    idx: usize,
     name: OsString,
     path: PathBuf,
     size: u64,
+    modified: Option<SystemTime>,
+    read_only: bool,
+}
+
+impl FloppyImage {
+    fn from_path(idx: usize, path: &Path) -> Self {
+        let metadata = path.metadata().ok();
+        Self {
+            idx,
+            name: path.file_name().unwrap_or_default().to_os_string(),
+            path: path.to_path_buf(),
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            read_only: metadata.as_ref().map_or(false, |m| m.permissions().readonly()),
+        }
+    }
+}
+
+/// The disk geometry of a raw sector image, as detected from its size.
+#[derive(Copy, Clone, Debug)]
+pub struct FloppyImageFormat {
+    pub chs: DiskChs,
+    pub sector_size: usize,
+    pub eight_inch: bool,
+}
+
+impl FloppyImageFormat {
+    pub fn from_size(size: u64) -> Option<Self> {
+        DISK_FORMATS.get(&(size as usize)).map(|fmt| Self {
+            chs: fmt.chs,
+            sector_size: fmt.sector_size,
+            eight_inch: fmt.eight_inch,
+        })
+    }
+}
+
+impl Display for FloppyImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let size = self.chs.c() as usize * self.chs.h() as usize * self.chs.s() as usize * self.sector_size;
+        write!(
+            f,
+            "{}K {}{}",
+            size / 1024,
+            self.chs,
+            if self.eight_inch { " 8\"" } else { "" }
+        )
+    }
+}
+
+/// A structured description of a floppy image known to the floppy manager.
+#[derive(Clone, Debug)]
+pub struct FloppyCatalogEntry {
+    /// Index of the image, as used by load_floppy_data() and save_floppy_data().
+    pub idx: usize,
+    pub name: OsString,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Detected format of the image, or None if the image size does not match a known format.
+    pub format: Option<FloppyImageFormat>,
+    /// Whether the image file itself is read-only. A read-only image should be mounted
+    /// write-protected.
+    pub write_protected: bool,
 }
 
 pub struct FloppyManager {
@@ -96,30 +160,84 @@ impl FloppyManager {
     }
 
     pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        // Retrieve all items from the floppy resource paths.
+        let floppy_items = rm.enumerate_items("floppy", true, true, Some(self.extensions.clone()))?;
+        self.set_items(floppy_items);
+        Ok(true)
+    }
+
+    /// Re-scan the floppy resource paths and rebuild the image lists only if images were added,
+    /// removed or modified since the last scan. Returns true if the image lists changed.
+    /// This is cheap enough to be polled periodically to pick up images dropped into the
+    /// floppy directories while the emulator is running.
+    pub fn rescan_if_changed(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        let floppy_items = rm.enumerate_items("floppy", true, true, Some(self.extensions.clone()))?;
+
+        let images: Vec<FloppyImage> = floppy_items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| FloppyImage::from_path(idx, &item.full_path))
+            .collect();
+
+        let unchanged = images.len() == self.image_vec.len()
+            && images.iter().zip(self.image_vec.iter()).all(|(new, old)| {
+                new.path == old.path
+                    && new.size == old.size
+                    && new.modified == old.modified
+                    && new.read_only == old.read_only
+            });
+
+        if unchanged {
+            return Ok(false);
+        }
+
+        log::debug!(
+            "Floppy images changed on disk, rescanned {} images (was {})",
+            images.len(),
+            self.image_vec.len()
+        );
+        self.set_items(floppy_items);
+        Ok(true)
+    }
+
+    fn set_items(&mut self, floppy_items: Vec<ResourceItem>) {
         // Clear and rebuild image lists.
         self.image_vec.clear();
         self.image_map.clear();
 
-        // Retrieve all items from the floppy resource paths.
-        let floppy_items = rm.enumerate_items("floppy", true, true, Some(self.extensions.clone()))?;
-
         // Index mapping between 'files' vec and 'image_vec' should be maintained.
         for item in floppy_items.iter() {
             let idx = self.image_vec.len();
-            self.image_vec.push(FloppyImage {
-                idx,
-                name: item.full_path.file_name().unwrap().to_os_string(),
-                path: item.full_path.clone(),
-                size: item.full_path.metadata().unwrap().len(),
-            });
-
-            self.image_map
-                .insert(item.full_path.file_name().unwrap().to_os_string(), idx);
+            let image = FloppyImage::from_path(idx, &item.full_path);
+            self.image_map.insert(image.name.clone(), idx);
+            self.image_vec.push(image);
         }
 
         self.files = floppy_items;
+    }
 
-        Ok(true)
+    /// Return a catalog of all known floppy images, in index order.
+    pub fn catalog(&self) -> Vec<FloppyCatalogEntry> {
+        self.image_vec
+            .iter()
+            .map(|image| self.make_catalog_entry(image))
+            .collect()
+    }
+
+    /// Return the catalog entry for the image at the specified index.
+    pub fn catalog_entry(&self, idx: usize) -> Option<FloppyCatalogEntry> {
+        self.image_vec.get(idx).map(|image| self.make_catalog_entry(image))
+    }
+
+    fn make_catalog_entry(&self, image: &FloppyImage) -> FloppyCatalogEntry {
+        FloppyCatalogEntry {
+            idx: image.idx,
+            name: image.name.clone(),
+            path: image.path.clone(),
+            size: image.size,
+            format: FloppyImageFormat::from_size(image.size),
+            write_protected: image.read_only,
+        }
     }
 
     pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
@@ -144,12 +262,7 @@ impl FloppyManager {
                         );
 
                         let idx = self.image_vec.len();
-                        self.image_vec.push(FloppyImage {
-                            idx,
-                            name: path.file_name().unwrap().to_os_string(),
-                            path: path.clone(),
-                            size: path.metadata().unwrap().len(),
-                        });
+                        self.image_vec.push(FloppyImage::from_path(idx, &path));
 
                         self.image_map.insert(path.file_name().unwrap().to_os_string(), idx);
                     }
@@ -183,12 +296,7 @@ impl FloppyManager {
                             );
 
                             let idx = self.image_vec.len();
-                            self.image_vec.push(FloppyImage {
-                                idx,
-                                name: entry.file_name(),
-                                path: entry.path(),
-                                size: entry.metadata().unwrap().len(),
-                            });
+                            self.image_vec.push(FloppyImage::from_path(idx, &entry.path()));
 
                             self.image_map.insert(entry.file_name(), idx);
                         }
@@ -38,8 +38,10 @@ pub mod display_manager;
 pub mod display_scaler;
 pub mod floppy_manager;
 pub mod machine_manager;
+pub mod netplay;
 pub mod resource_manager;
 pub mod rom_manager;
+pub mod terminal_renderer;
 pub mod timestep_manager;
 pub mod types;
 pub mod vhd_manager;
@@ -34,17 +34,25 @@ use anyhow::Error;
 use marty_core::{
     device_traits::videocard::VideoType,
     machine_config::{
+        DipSwitchConfig,
+        DramRefreshConfig,
         FloppyControllerConfig,
+        GamePortConfig,
         HardDriveControllerConfig,
         KeyboardConfig,
         MachineConfiguration,
+        MachineDescriptorConfig,
         MediaConfig,
         MemoryConfig,
+        OpenBusConfig,
+        RtcConfig,
         SerialControllerConfig,
         SerialMouseConfig,
         VideoCardConfig,
+        WaitStateConfig,
     },
-    machine_types::{HardDiskControllerType, MachineType},
+    machine_types::{CpuClockPreset, HardDiskControllerType, MachineType},
+    sound::SpeakerProfile,
 };
 
 use serde_derive::Deserialize;
@@ -73,15 +81,25 @@ pub struct MachineConfigFileEntry {
     rom_set: String,
     overlays: Option<Vec<String>>,
     memory: MemoryConfig,
+    dram_refresh: Option<DramRefreshConfig>,
+    wait_states: Option<WaitStateConfig>,
+    open_bus: Option<OpenBusConfig>,
+    descriptor: Option<MachineDescriptorConfig>, // Overrides the machine type's built-in hardware description.
+    dip_switches: Option<DipSwitchConfig>,       // Overrides the DIP switches derived from the installed hardware.
     #[serde(default)]
     speaker: bool,
+    speaker_profile: Option<SpeakerProfile>, // Models the speaker's output stage. Defaults to unfiltered.
     ppi_turbo: Option<bool>, // This bool is an option so that it is three state - missing means no turbo feature, true means ppi high = turbo, false means ppi low = turbo.
+    turbo_clock: Option<CpuClockPreset>, // Overrides the machine's default turbo clock speed.
+    reset_vector: Option<[u16; 2]>,      // Overrides the CPU reset vector, as [segment, offset].
     fdc: Option<FloppyControllerConfig>,
     hdc: Option<HardDriveControllerConfig>,
     serial: Option<Vec<SerialControllerConfig>>,
     video: Option<Vec<VideoCardConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    game_port: Option<GamePortConfig>,
+    rtc: Option<RtcConfig>,
     media: Option<MediaConfig>,
 }
 
@@ -89,12 +107,15 @@ pub struct MachineConfigFileEntry {
 pub struct MachineConfigFileOverlayEntry {
     name: String,
     memory: Option<MemoryConfig>,
+    dip_switches: Option<DipSwitchConfig>,
     fdc: Option<FloppyControllerConfig>,
     hdc: Option<HardDriveControllerConfig>,
     serial: Option<Vec<SerialControllerConfig>>,
     video: Option<Vec<VideoCardConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    game_port: Option<GamePortConfig>,
+    rtc: Option<RtcConfig>,
     media: Option<MediaConfig>,
 }
 
@@ -168,10 +189,11 @@ impl MachineManager {
         }
 
         // Check for duplicate names
-        for config in machine_configs {
+        for mut config in machine_configs {
             if self.configs.contains_key(&config.name) {
                 return Err(anyhow::anyhow!("Duplicate machine name: {}", config.name));
             }
+            config.apply_default_devices();
             self.configs.insert(config.name.clone(), config);
         }
         for overlay in overlay_configs {
@@ -307,6 +329,14 @@ impl MachineConfigFileEntry {
                         req_vec.push(String::from("ibm_xebec"));
                     }
                 }
+                HardDiskControllerType::Wd1002 => {
+                    if req_set.insert(String::from("expansion")) {
+                        req_vec.push(String::from("expansion"));
+                    }
+                    if req_set.insert(String::from("wd1002")) {
+                        req_vec.push(String::from("wd1002"));
+                    }
+                }
             }
         }
 
@@ -351,6 +381,10 @@ impl MachineConfigFileEntry {
             log::debug!("Applying memory overlay: {:?}", memory);
             self.memory = memory;
         }
+        if let Some(dip_switches) = overlay.dip_switches {
+            log::debug!("Applying DIP switch overlay: {:?}", dip_switches);
+            self.dip_switches = Some(dip_switches);
+        }
         if let Some(fdc) = overlay.fdc {
             log::debug!("Applying FDC overlay: {:?}", fdc);
             self.fdc = Some(fdc);
@@ -375,20 +409,67 @@ impl MachineConfigFileEntry {
             log::debug!("Applying serial mouse overlay: {:?}", serial_mouse);
             self.serial_mouse = Some(serial_mouse);
         }
+        if let Some(game_port) = overlay.game_port {
+            log::debug!("Applying game port overlay: {:?}", game_port);
+            self.game_port = Some(game_port);
+        }
+        if let Some(rtc) = overlay.rtc {
+            log::debug!("Applying RTC overlay: {:?}", rtc);
+            self.rtc = Some(rtc);
+        }
+    }
+
+    /// Install the default devices of this configuration's machine descriptor, for each kind of device the
+    /// configuration does not specify. This is done before any overlays are applied, so that overlays replace
+    /// default devices like any other.
+    pub fn apply_default_devices(&mut self) {
+        if let Some(devices) = self.descriptor.as_ref().and_then(|d| d.devices.clone()) {
+            if self.keyboard.is_none() {
+                self.keyboard = devices.keyboard;
+            }
+            if self.fdc.is_none() {
+                self.fdc = devices.fdc;
+            }
+            if self.hdc.is_none() {
+                self.hdc = devices.hdc;
+            }
+            if self.serial.is_none() {
+                self.serial = devices.serial;
+            }
+            if self.video.is_none() {
+                self.video = devices.video;
+            }
+            if self.game_port.is_none() {
+                self.game_port = devices.game_port;
+            }
+            if self.rtc.is_none() {
+                self.rtc = devices.rtc;
+            }
+        }
     }
 
     pub fn to_machine_config(&self) -> MachineConfiguration {
         MachineConfiguration {
             speaker: self.speaker,
+            speaker_profile: self.speaker_profile,
             ppi_turbo: self.ppi_turbo,
+            turbo_clock: self.turbo_clock,
+            reset_vector: self.reset_vector,
             machine_type: self.machine_type,
             memory: self.memory.clone(),
+            dram_refresh: self.dram_refresh.clone(),
+            wait_states: self.wait_states.clone(),
+            open_bus: self.open_bus.clone(),
+            descriptor: self.descriptor.clone(),
+            dip_switches: self.dip_switches.clone(),
             fdc: self.fdc.clone(),
             hdc: self.hdc.clone(),
             serial: self.serial.clone().unwrap_or_default(),
             video: self.video.clone().unwrap_or_default(),
             keyboard: self.keyboard.clone(),
             serial_mouse: self.serial_mouse.clone(),
+            game_port: self.game_port.clone(),
+            rtc: self.rtc.clone(),
             media: self.media.clone(),
         }
     }
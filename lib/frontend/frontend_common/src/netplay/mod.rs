@@ -0,0 +1,578 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    frontend_common::netplay::mod.rs
+
+    Lockstep netplay over a virtual null-modem cable.
+
+    Each side runs its own machine. The two machines are advanced in fixed
+    frames of CPU cycles, and the bytes each guest transmits on the linked
+    serial port during frame N are received by the other guest at the start
+    of frame N + input_delay. Neither side may run frame N until it holds the
+    peer's bytes for frame N - input_delay, so the guests always see serial
+    data arrive at the same emulated time regardless of network jitter, and
+    serial multiplayer games stay in sync across the internet.
+*/
+
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Error};
+use marty_core::machine::{ExecutionControl, Machine};
+use serde_derive::Deserialize;
+
+const NETPLAY_MAGIC: &[u8; 4] = b"MNP1";
+const NETPLAY_VERSION: u16 = 1;
+const HELLO_LEN: usize = 14;
+/// How long to wait for the peer's hello once connected.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MSG_FRAME: u8 = 1;
+const MSG_GOODBYE: u8 = 2;
+
+/// Lockstep frames per second, used to derive the default frame length from the CPU clock.
+pub const NETPLAY_FRAME_RATE: f64 = 60.0;
+pub const DEFAULT_INPUT_DELAY: u32 = 4;
+
+/// Return the number of CPU cycles in one lockstep frame at the given CPU clock.
+pub fn default_cycles_per_frame(cpu_mhz: f64) -> u32 {
+    (cpu_mhz * 1_000_000.0 / NETPLAY_FRAME_RATE).round() as u32
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+pub enum NetplayRole {
+    Host,
+    Client,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NetplayConfig {
+    pub role: NetplayRole,
+    /// Address to listen on (Host) or connect to (Client).
+    pub address: String,
+    /// Guest serial port linked to the peer. 0 = COM1, 1 = COM2.
+    #[serde(default)]
+    pub serial_port: usize,
+    /// Frames of latency to hide. The host's value is used by both sides.
+    pub input_delay: Option<u32>,
+    /// CPU cycles per lockstep frame. The host's value is used by both sides.
+    pub cycles_per_frame: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NetplayStatus {
+    /// The given frame was run.
+    Advanced(u64),
+    /// The host is still waiting for the peer to connect; the machine was not run.
+    Listening,
+    /// The peer's data for the next frame has not arrived yet; the machine was not run.
+    Waiting,
+    /// The peer has left the session.
+    Disconnected,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NetMessage {
+    Frame(u64, Vec<u8>),
+    Goodbye,
+}
+
+/// The session parameters exchanged by both sides when connecting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Hello {
+    version: u16,
+    input_delay: u32,
+    cycles_per_frame: u32,
+}
+
+impl Hello {
+    fn encode(&self) -> [u8; HELLO_LEN] {
+        let mut buf = [0u8; HELLO_LEN];
+        buf[0..4].copy_from_slice(NETPLAY_MAGIC);
+        buf[4..6].copy_from_slice(&self.version.to_be_bytes());
+        buf[6..10].copy_from_slice(&self.input_delay.to_be_bytes());
+        buf[10..14].copy_from_slice(&self.cycles_per_frame.to_be_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; HELLO_LEN]) -> Result<Self, Error> {
+        if &buf[0..4] != NETPLAY_MAGIC {
+            bail!("Peer is not a MartyPC netplay session");
+        }
+        Ok(Self {
+            version: u16::from_be_bytes([buf[4], buf[5]]),
+            input_delay: u32::from_be_bytes(buf[6..10].try_into().unwrap()),
+            cycles_per_frame: u32::from_be_bytes(buf[10..14].try_into().unwrap()),
+        })
+    }
+}
+
+enum Link {
+    /// A host waiting for the peer to connect. The listener is non-blocking.
+    Listening(TcpListener),
+    Connected {
+        writer: BufWriter<TcpStream>,
+        rx: Receiver<NetMessage>,
+    },
+}
+
+pub struct NetplaySession {
+    link: Link,
+    remote_frames: BTreeMap<u64, Vec<u8>>,
+    remote_closed: bool,
+    serial_port: usize,
+    input_delay: u32,
+    cycles_per_frame: u32,
+    /// Cycles run past the end of previous frames, as run() finishes the instruction that
+    /// reaches its target. These are taken off the next frame so frames stay aligned.
+    cycle_overshoot: u32,
+    frame: u64,
+    bytes_sent: usize,
+    bytes_received: usize,
+}
+
+impl NetplaySession {
+    /// Begin a session as described by `config`. `cpu_mhz` is the machine's CPU clock, used for
+    /// the default frame length.
+    ///
+    /// A host returns immediately, listening for the peer in the background; run_frame() reports
+    /// NetplayStatus::Listening until it connects. A client connects before returning.
+    pub fn new(config: &NetplayConfig, cpu_mhz: f64) -> Result<Self, Error> {
+        let input_delay = config.input_delay.unwrap_or(DEFAULT_INPUT_DELAY);
+        let cycles_per_frame = config
+            .cycles_per_frame
+            .unwrap_or_else(|| default_cycles_per_frame(cpu_mhz));
+
+        let (link, input_delay, cycles_per_frame) = match config.role {
+            NetplayRole::Host => {
+                let listener = TcpListener::bind(&config.address)?;
+                listener.set_nonblocking(true)?;
+                log::info!("Netplay: waiting for peer on {}", listener.local_addr()?);
+                (Link::Listening(listener), input_delay, cycles_per_frame)
+            }
+            NetplayRole::Client => {
+                log::info!("Netplay: connecting to {}", config.address);
+                let stream = TcpStream::connect(&config.address)?;
+                Self::connect(stream, false, input_delay, cycles_per_frame)?
+            }
+        };
+
+        Ok(Self {
+            link,
+            remote_frames: BTreeMap::new(),
+            remote_closed: false,
+            serial_port: config.serial_port,
+            input_delay,
+            cycles_per_frame,
+            cycle_overshoot: 0,
+            frame: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+        })
+    }
+
+    /// Return the address a host is listening on, until the peer connects.
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        match &self.link {
+            Link::Listening(listener) => listener.local_addr().ok(),
+            Link::Connected { .. } => None,
+        }
+    }
+
+    /// Accept a waiting peer, if any, without blocking. Returns true once connected.
+    pub fn poll_connection(&mut self) -> Result<bool, Error> {
+        let listener = match &self.link {
+            Link::Listening(listener) => listener,
+            Link::Connected { .. } => return Ok(true),
+        };
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                log::info!("Netplay: peer connected from {}", peer);
+                // Some platforms pass the listener's non-blocking mode on to accepted sockets.
+                stream.set_nonblocking(false)?;
+                let (link, _, _) = Self::connect(stream, true, self.input_delay, self.cycles_per_frame)?;
+                self.link = link;
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Exchange hellos with the peer and start receiving its messages. Returns the connected
+    /// link, and the input delay and cycles per frame to use, which are the host's.
+    fn connect(
+        stream: TcpStream,
+        host: bool,
+        mut input_delay: u32,
+        mut cycles_per_frame: u32,
+    ) -> Result<(Link, u32, u32), Error> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        // Both sides send a hello; the client adopts the host's timing parameters.
+        let hello = Hello {
+            version: NETPLAY_VERSION,
+            input_delay,
+            cycles_per_frame,
+        };
+        writer.write_all(&hello.encode())?;
+        writer.flush()?;
+
+        writer.get_ref().set_read_timeout(Some(HELLO_TIMEOUT))?;
+        let mut buf = [0u8; HELLO_LEN];
+        reader.read_exact(&mut buf)?;
+        writer.get_ref().set_read_timeout(None)?;
+
+        let peer = Hello::decode(&buf)?;
+        if peer.version != NETPLAY_VERSION {
+            bail!("Netplay version mismatch: local {}, peer {}", NETPLAY_VERSION, peer.version);
+        }
+        if !host {
+            input_delay = peer.input_delay;
+            cycles_per_frame = peer.cycles_per_frame;
+        }
+        if cycles_per_frame == 0 {
+            bail!("Invalid cycles per frame: 0");
+        }
+        log::info!(
+            "Netplay: session established. Input delay: {} frames, {} cycles per frame",
+            input_delay,
+            cycles_per_frame
+        );
+
+        let (tx, rx) = channel();
+        thread::Builder::new()
+            .name("netplay_rx".to_string())
+            .spawn(move || loop {
+                match read_message(&mut reader) {
+                    Ok(msg) => {
+                        let goodbye = matches!(msg, NetMessage::Goodbye);
+                        if tx.send(msg).is_err() || goodbye {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Netplay: connection lost: {}", e);
+                        _ = tx.send(NetMessage::Goodbye);
+                        break;
+                    }
+                }
+            })?;
+
+        Ok((Link::Connected { writer, rx }, input_delay, cycles_per_frame))
+    }
+
+    /// Link the machine's serial port to the session. Call once before the first run_frame().
+    pub fn attach(&mut self, machine: &mut Machine) -> Result<(), Error> {
+        machine.bridge_serial_buffer(self.serial_port)
+    }
+
+    /// Return the number of the next frame to be run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn input_delay(&self) -> u32 {
+        self.input_delay
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    /// Return the total number of serial bytes sent to and received from the peer.
+    pub fn byte_counts(&self) -> (usize, usize) {
+        (self.bytes_sent, self.bytes_received)
+    }
+
+    /// Return how many frames the peer is ahead of us. Negative values mean we are ahead.
+    pub fn frame_lead(&self) -> i64 {
+        match self.remote_frames.keys().next_back() {
+            Some(last) => *last as i64 + 1 + self.input_delay as i64 - self.frame as i64,
+            None => self.input_delay as i64 - self.frame as i64,
+        }
+    }
+
+    /// Run the next lockstep frame if the peer's data for it has arrived.
+    pub fn run_frame(
+        &mut self,
+        machine: &mut Machine,
+        exec_control: &mut ExecutionControl,
+    ) -> Result<NetplayStatus, Error> {
+        if !self.poll_connection()? {
+            return Ok(NetplayStatus::Listening);
+        }
+        self.pump_messages();
+
+        let delay = self.input_delay as u64;
+        if self.frame >= delay {
+            let remote_frame = self.frame - delay;
+            match self.remote_frames.remove(&remote_frame) {
+                Some(bytes) => {
+                    self.bytes_received += bytes.len();
+                    machine.serial_queue_bytes(self.serial_port, &bytes);
+                }
+                None if self.remote_closed => return Ok(NetplayStatus::Disconnected),
+                None => return Ok(NetplayStatus::Waiting),
+            }
+        }
+
+        // A previous frame may have overrun this one entirely, in which case nothing is run.
+        let target = self.frame_cycle_target();
+        let start = machine.cpu_cycles();
+        if target > 0 {
+            machine.run(target, exec_control);
+        }
+        self.end_frame_cycles(machine.cpu_cycles() - start);
+
+        let tx_bytes = machine.serial_take_tx_bytes(self.serial_port);
+        self.bytes_sent += tx_bytes.len();
+        self.send_frame(self.frame, tx_bytes)?;
+
+        let frame = self.frame;
+        self.frame += 1;
+        Ok(NetplayStatus::Advanced(frame))
+    }
+
+    /// Return the number of cycles to run for the current frame, less any overshoot carried
+    /// from previous frames.
+    fn frame_cycle_target(&self) -> u32 {
+        self.cycles_per_frame.saturating_sub(self.cycle_overshoot)
+    }
+
+    /// Record the number of cycles run for the current frame, carrying any overshoot.
+    fn end_frame_cycles(&mut self, ran: u64) {
+        self.cycle_overshoot = (self.cycle_overshoot as u64 + ran).saturating_sub(self.cycles_per_frame as u64) as u32;
+    }
+
+    /// Notify the peer that we are leaving the session.
+    pub fn close(&mut self) {
+        if let Link::Connected { writer, .. } = &mut self.link {
+            _ = write_message(writer, &NetMessage::Goodbye);
+        }
+    }
+
+    fn pump_messages(&mut self) {
+        let rx = match &self.link {
+            Link::Connected { rx, .. } => rx,
+            Link::Listening(_) => return,
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(NetMessage::Frame(frame, bytes)) => {
+                    self.remote_frames.insert(frame, bytes);
+                }
+                Ok(NetMessage::Goodbye) | Err(TryRecvError::Disconnected) => {
+                    if !self.remote_closed {
+                        log::info!("Netplay: peer left the session");
+                    }
+                    self.remote_closed = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+    }
+
+    fn send_frame(&mut self, frame: u64, bytes: Vec<u8>) -> Result<(), Error> {
+        match &mut self.link {
+            Link::Connected { writer, .. } => write_message(writer, &NetMessage::Frame(frame, bytes)),
+            Link::Listening(_) => Err(anyhow!("Netplay peer is not connected")),
+        }
+    }
+}
+
+impl Drop for NetplaySession {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Write a message to the peer and flush it. A frame is its type byte, its number as a
+/// big-endian u64, then its length as a big-endian u16 followed by that many serial bytes.
+fn write_message<W: Write>(writer: &mut W, msg: &NetMessage) -> Result<(), Error> {
+    match msg {
+        NetMessage::Frame(frame, bytes) => {
+            if bytes.len() > u16::MAX as usize {
+                return Err(anyhow!("Too many serial bytes in one frame: {}", bytes.len()));
+            }
+            writer.write_all(&[MSG_FRAME])?;
+            writer.write_all(&frame.to_be_bytes())?;
+            writer.write_all(&(bytes.len() as u16).to_be_bytes())?;
+            writer.write_all(bytes)?;
+        }
+        NetMessage::Goodbye => writer.write_all(&[MSG_GOODBYE])?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_message<R: Read>(reader: &mut R) -> Result<NetMessage, Error> {
+    let mut msg_type = [0u8; 1];
+    reader.read_exact(&mut msg_type)?;
+    match msg_type[0] {
+        MSG_FRAME => {
+            let mut header = [0u8; 10];
+            reader.read_exact(&mut header)?;
+            let frame = u64::from_be_bytes(header[0..8].try_into().unwrap());
+            let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+            let mut bytes = vec![0u8; len];
+            reader.read_exact(&mut bytes)?;
+            Ok(NetMessage::Frame(frame, bytes))
+        }
+        MSG_GOODBYE => Ok(NetMessage::Goodbye),
+        t => Err(anyhow!("Unknown netplay message type: {}", t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_config() -> NetplayConfig {
+        NetplayConfig {
+            role: NetplayRole::Host,
+            address: "127.0.0.1:0".to_string(),
+            serial_port: 0,
+            input_delay: Some(2),
+            cycles_per_frame: None,
+        }
+    }
+
+    #[test]
+    fn test_default_cycles_per_frame() {
+        assert_eq!(default_cycles_per_frame(14.31818 / 3.0), 79_545);
+        assert_eq!(default_cycles_per_frame(8.0), 133_333);
+    }
+
+    #[test]
+    fn test_hello_roundtrip() {
+        let hello = Hello {
+            version: NETPLAY_VERSION,
+            input_delay: 3,
+            cycles_per_frame: 133_333,
+        };
+        assert_eq!(Hello::decode(&hello.encode()).unwrap(), hello);
+
+        let mut bad = hello.encode();
+        bad[0] = b'X';
+        assert!(Hello::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let messages = [
+            NetMessage::Frame(0, Vec::new()),
+            NetMessage::Frame(0x0102_0304_0506, vec![0x41, 0x0D, 0x0A]),
+            NetMessage::Goodbye,
+        ];
+        let mut buf = Vec::new();
+        for msg in &messages {
+            write_message(&mut buf, msg).unwrap();
+        }
+        assert_eq!(&buf[0..11], &[MSG_FRAME, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut reader = buf.as_slice();
+        for msg in &messages {
+            assert_eq!(&read_message(&mut reader).unwrap(), msg);
+        }
+        assert!(reader.is_empty());
+
+        // Unknown, truncated and oversized messages are errors.
+        assert!(read_message(&mut [0xFFu8].as_slice()).is_err());
+        assert!(read_message(&mut &buf[11..15]).is_err());
+        let oversized = NetMessage::Frame(0, vec![0; u16::MAX as usize + 1]);
+        assert!(write_message(&mut Vec::new(), &oversized).is_err());
+    }
+
+    #[test]
+    fn test_frame_cycle_overshoot() {
+        let mut session = NetplaySession::new(&host_config(), 8.0).unwrap();
+        assert_eq!(session.cycles_per_frame(), 133_333);
+
+        // Cycles run past the end of a frame are taken off the next one.
+        assert_eq!(session.frame_cycle_target(), 133_333);
+        session.end_frame_cycles(133_340);
+        assert_eq!(session.frame_cycle_target(), 133_326);
+        session.end_frame_cycles(133_330);
+        assert_eq!(session.frame_cycle_target(), 133_329);
+
+        // A frame that stops short, such as at a breakpoint, doesn't carry a deficit.
+        session.end_frame_cycles(100);
+        assert_eq!(session.frame_cycle_target(), 133_333);
+    }
+
+    #[test]
+    fn test_session_connect() {
+        let mut host = NetplaySession::new(&host_config(), 8.0).unwrap();
+        let address = host.listen_addr().unwrap().to_string();
+
+        // Nobody has connected yet, so the host doesn't block.
+        assert!(!host.poll_connection().unwrap());
+
+        let client = thread::spawn(move || {
+            let config = NetplayConfig {
+                role: NetplayRole::Client,
+                address,
+                serial_port: 1,
+                input_delay: None,
+                cycles_per_frame: Some(1000),
+            };
+            NetplaySession::new(&config, 4.77).unwrap()
+        });
+        let mut tries = 0;
+        while !host.poll_connection().unwrap() {
+            tries += 1;
+            assert!(tries < 500, "Client did not connect");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut client = client.join().unwrap();
+        assert!(host.listen_addr().is_none());
+
+        // The client adopts the host's parameters.
+        assert_eq!(client.input_delay(), 2);
+        assert_eq!(client.cycles_per_frame(), host.cycles_per_frame());
+
+        host.send_frame(0, vec![0x55]).unwrap();
+        host.close();
+        let mut tries = 0;
+        while !client.remote_closed {
+            client.pump_messages();
+            tries += 1;
+            assert!(tries < 500, "Client did not receive goodbye");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(client.remote_frames.get(&0), Some(&vec![0x55]));
+    }
+}
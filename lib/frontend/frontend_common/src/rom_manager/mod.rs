@@ -91,6 +91,7 @@ pub struct RomDescriptor {
     offset: Option<u32>,
     chip: Option<String>,
     org: Option<RomOrganization>,
+    wait_states: Option<u32>,
     #[serde(skip)]
     present: bool,
 }
@@ -822,9 +823,10 @@ impl RomManager {
                         }
 
                         new_manifest.roms.push(MachineRomEntry {
-                            md5:  rom_desc.md5.clone().unwrap(),
+                            md5: rom_desc.md5.clone().unwrap(),
                             addr: rom_desc.addr,
                             data: rom_vec,
+                            wait_states: rom_desc.wait_states,
                         });
                         new_manifest.rom_paths.push(rom_file.path.clone());
                     }
@@ -844,9 +846,10 @@ impl RomManager {
                         }
 
                         new_manifest.roms.push(MachineRomEntry {
-                            md5:  rom_desc.md5.clone().unwrap(),
+                            md5: rom_desc.md5.clone().unwrap(),
                             addr: rom_desc.addr,
                             data: rom_vec,
+                            wait_states: rom_desc.wait_states,
                         });
                         new_manifest.rom_paths.push(rom_file.path.clone());
                     }
@@ -0,0 +1,389 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    frontend_common::terminal_renderer::mod.rs
+
+    Render text-mode video memory to a host terminal using ANSI escape
+    sequences. The active mode, page and cursor position are taken from the
+    BIOS data area, so this works with any video card whose BIOS maintains it.
+    The number of rows is taken from the video card's CRTC, so programs that
+    reprogram it for 43 or 50 row modes are displayed in full.
+    Only cells that changed since the last frame are redrawn, which keeps the
+    output small enough to use over a slow SSH connection.
+*/
+
+use std::io::{self, Write};
+
+use marty_core::bus::BusInterface;
+
+const BDA_VIDEO_MODE: usize = 0x449;
+const BDA_COLUMNS: usize = 0x44A;
+const BDA_PAGE_OFFSET: usize = 0x44E;
+const BDA_CURSOR_POS: usize = 0x450;
+const BDA_ACTIVE_PAGE: usize = 0x462;
+
+const MDA_TEXT_BASE: usize = 0xB0000;
+const CGA_TEXT_BASE: usize = 0xB8000;
+const DEFAULT_ROWS: usize = 25;
+const MAX_ROWS: usize = 60;
+
+/// Map from IBM PC color index order to ANSI color order.
+const ANSI_COLOR: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// Code page 437 to Unicode.
+#[rustfmt::skip]
+const CP437: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TerminalRendererStatus {
+    /// A text mode frame was rendered.
+    Text { cols: usize, rows: usize },
+    /// The adapter is in a graphics mode; nothing was rendered.
+    Graphics(u8),
+}
+
+/// A snapshot of the active text page.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextScreen {
+    pub cols: usize,
+    pub rows: usize,
+    pub mono: bool,
+    pub cursor: Option<(usize, usize)>,
+    /// Character and attribute words, row by row.
+    pub cells: Vec<u16>,
+}
+
+impl TextScreen {
+    /// Read the active text page from the bus. Returns the video mode as an error if the
+    /// adapter is in a graphics mode.
+    pub fn read(bus: &BusInterface) -> Result<Self, u8> {
+        let peek = |addr: usize| bus.peek_u8(addr).unwrap_or(0);
+        let peek_u16 = |addr: usize| peek(addr) as u16 | (peek(addr + 1) as u16) << 8;
+
+        let mode = peek(BDA_VIDEO_MODE) & 0x7F;
+        let mono = match mode {
+            0..=3 => false,
+            7 => true,
+            _ => return Err(mode),
+        };
+        let cols = match peek_u16(BDA_COLUMNS) {
+            40 => 40,
+            _ => 80,
+        };
+        // Only EGA and later BIOSes record the number of rows, so ask the CRTC. Without a
+        // video card, assume the standard text modes.
+        let rows = match bus.primary_video() {
+            Some(video) => match video.get_text_mode_rows() {
+                Some(0) => DEFAULT_ROWS,
+                Some(rows) => (rows as usize).min(MAX_ROWS),
+                None => return Err(mode),
+            },
+            None => DEFAULT_ROWS,
+        };
+        let base = if mono { MDA_TEXT_BASE } else { CGA_TEXT_BASE } + peek_u16(BDA_PAGE_OFFSET) as usize;
+
+        let page = (peek(BDA_ACTIVE_PAGE) & 0x07) as usize;
+        let cursor_word = peek_u16(BDA_CURSOR_POS + page * 2);
+        let (cursor_col, cursor_row) = ((cursor_word & 0xFF) as usize, (cursor_word >> 8) as usize);
+        let cursor = (cursor_col < cols && cursor_row < rows).then_some((cursor_col, cursor_row));
+
+        let cells = (0..cols * rows).map(|i| peek_u16(base + i * 2)).collect();
+
+        Ok(Self {
+            cols,
+            rows,
+            mono,
+            cursor,
+            cells,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct TerminalRenderer {
+    cols: usize,
+    rows: usize,
+    mono: bool,
+    cells: Vec<u16>,
+    blink_as_bright: bool,
+    out_buf: Vec<u8>,
+}
+
+impl TerminalRenderer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Interpret attribute bit 7 as a bright background rather than blink.
+    pub fn set_blink_as_bright(&mut self, state: bool) {
+        self.blink_as_bright = state;
+        self.invalidate();
+    }
+
+    /// Force a full redraw on the next call to render().
+    pub fn invalidate(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Restore the terminal's default attributes and cursor. Call when done rendering.
+    pub fn restore<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        self.invalidate();
+        out.write_all(b"\x1b[0m\x1b[?25h\r\n")?;
+        out.flush()
+    }
+
+    /// Render the active text page to `out`, drawing only the cells that changed since the
+    /// last call.
+    pub fn render<W: Write>(&mut self, bus: &BusInterface, out: &mut W) -> io::Result<TerminalRendererStatus> {
+        match TextScreen::read(bus) {
+            Ok(screen) => self.draw(&screen, out),
+            Err(mode) => Ok(TerminalRendererStatus::Graphics(mode)),
+        }
+    }
+
+    /// Draw a text screen to `out`, drawing only the cells that changed since the last call.
+    pub fn draw<W: Write>(&mut self, screen: &TextScreen, out: &mut W) -> io::Result<TerminalRendererStatus> {
+        let TextScreen {
+            cols,
+            rows,
+            mono,
+            cursor,
+            ..
+        } = *screen;
+
+        self.out_buf.clear();
+        let full_redraw =
+            cols != self.cols || rows != self.rows || mono != self.mono || self.cells.len() != cols * rows;
+        if full_redraw {
+            self.cols = cols;
+            self.rows = rows;
+            self.mono = mono;
+            self.cells = vec![0; cols * rows];
+            self.out_buf.extend_from_slice(b"\x1b[0m\x1b[2J");
+        }
+
+        // Hide the cursor while drawing to avoid flicker.
+        self.out_buf.extend_from_slice(b"\x1b[?25l");
+
+        let mut last_attr: Option<u8> = None;
+        let mut next_pos: Option<(usize, usize)> = None;
+        let mut char_buf = [0u8; 4];
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen.cells.get(row * cols + col).copied().unwrap_or(0);
+                if !full_redraw && self.cells[row * cols + col] == cell {
+                    continue;
+                }
+                self.cells[row * cols + col] = cell;
+
+                if next_pos != Some((col, row)) {
+                    _ = write!(self.out_buf, "\x1b[{};{}H", row + 1, col + 1);
+                }
+                let attr = (cell >> 8) as u8;
+                if last_attr != Some(attr) {
+                    self.push_attr(attr);
+                    last_attr = Some(attr);
+                }
+                let ch = CP437[(cell & 0xFF) as usize];
+                self.out_buf.extend_from_slice(ch.encode_utf8(&mut char_buf).as_bytes());
+                next_pos = Some((col + 1, row));
+            }
+        }
+
+        if let Some((col, row)) = cursor {
+            _ = write!(self.out_buf, "\x1b[{};{}H\x1b[?25h", row + 1, col + 1);
+        }
+
+        out.write_all(&self.out_buf)?;
+        out.flush()?;
+        Ok(TerminalRendererStatus::Text { cols, rows })
+    }
+
+    fn push_attr(&mut self, attr: u8) {
+        let blink = attr & 0x80 != 0 && !self.blink_as_bright;
+        if self.mono {
+            // MDA attributes: 0x00, 0x08, 0x80 and 0x88 are invisible, 0x70 is reverse video,
+            // a foreground of 1 is underlined and bit 3 is high intensity.
+            let mut sgr = String::from("\x1b[0");
+            match attr & 0x77 {
+                0x00 => sgr.push_str(";8"),
+                0x70 => sgr.push_str(";7"),
+                a if a & 0x07 == 0x01 => sgr.push_str(";4"),
+                _ => {}
+            }
+            if attr & 0x08 != 0 {
+                sgr.push_str(";1");
+            }
+            if blink {
+                sgr.push_str(";5");
+            }
+            sgr.push('m');
+            self.out_buf.extend_from_slice(sgr.as_bytes());
+        }
+        else {
+            let fg = attr & 0x0F;
+            let bg = (attr >> 4) & if self.blink_as_bright { 0x0F } else { 0x07 };
+            let fg_code = if fg & 0x08 != 0 { 90 } else { 30 } + ANSI_COLOR[(fg & 0x07) as usize];
+            let bg_code = if bg & 0x08 != 0 { 100 } else { 40 } + ANSI_COLOR[(bg & 0x07) as usize];
+            _ = write!(
+                self.out_buf,
+                "\x1b[0;{};{}{}m",
+                fg_code,
+                bg_code,
+                if blink { ";5" } else { "" }
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_bus(mode: u8, text: &[u8], attr: u8) -> BusInterface {
+        let mut bus = BusInterface::default();
+        bus.copy_from(&[mode], BDA_VIDEO_MODE, 0, false).unwrap();
+        bus.copy_from(&[80, 0], BDA_COLUMNS, 0, false).unwrap();
+        bus.copy_from(&[0, 0], BDA_PAGE_OFFSET, 0, false).unwrap();
+        bus.copy_from(&[0], BDA_ACTIVE_PAGE, 0, false).unwrap();
+        // Cursor at column 2, row 1
+        bus.copy_from(&[2, 1], BDA_CURSOR_POS, 0, false).unwrap();
+
+        let base = if mode == 7 { MDA_TEXT_BASE } else { CGA_TEXT_BASE };
+        let cells: Vec<u8> = (0..80 * DEFAULT_ROWS)
+            .flat_map(|i| [text.get(i).copied().unwrap_or(b' '), attr])
+            .collect();
+        bus.copy_from(&cells, base, 0, false).unwrap();
+        bus
+    }
+
+    fn screen(cells: &[u16]) -> TextScreen {
+        TextScreen {
+            cols: 4,
+            rows: 2,
+            mono: false,
+            cursor: None,
+            cells: cells.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_read_text_screen() {
+        let bus = text_bus(3, b"HI", 0x1F);
+        let screen = TextScreen::read(&bus).unwrap();
+        assert_eq!((screen.cols, screen.rows, screen.mono), (80, DEFAULT_ROWS, false));
+        assert_eq!(screen.cursor, Some((2, 1)));
+        assert_eq!(screen.cells.len(), 80 * DEFAULT_ROWS);
+        assert_eq!(&screen.cells[..3], &[0x1F48, 0x1F49, 0x1F20]);
+
+        let bus = text_bus(7, b"A", 0x07);
+        let screen = TextScreen::read(&bus).unwrap();
+        assert!(screen.mono);
+        assert_eq!(screen.cells[0], 0x0741);
+
+        // Graphics modes are reported rather than rendered.
+        let bus = text_bus(4, b"", 0);
+        assert_eq!(TextScreen::read(&bus), Err(4));
+        let mut out = Vec::new();
+        assert_eq!(
+            TerminalRenderer::new().render(&bus, &mut out).unwrap(),
+            TerminalRendererStatus::Graphics(4)
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_draw_changed_cells() {
+        let mut renderer = TerminalRenderer::new();
+        let mut out = Vec::new();
+        let mut cells = vec![0x0741; 8];
+        assert_eq!(
+            renderer.draw(&screen(&cells), &mut out).unwrap(),
+            TerminalRendererStatus::Text { cols: 4, rows: 2 }
+        );
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("\x1b[0m\x1b[2J"));
+        assert_eq!(text.matches('A').count(), 8);
+
+        // Only the changed cell is redrawn, preceded by a cursor move to its position.
+        let mut out = Vec::new();
+        cells[6] = 0x0742;
+        renderer.draw(&screen(&cells), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(!text.contains("\x1b[2J"));
+        assert!(!text.contains('A'));
+        assert!(text.contains("\x1b[2;3H"));
+        assert!(text.contains('B'));
+
+        // Nothing changed: only the cursor is hidden.
+        let mut out = Vec::new();
+        renderer.draw(&screen(&cells), &mut out).unwrap();
+        assert_eq!(out, b"\x1b[?25l");
+
+        // A change in dimensions forces a full redraw.
+        let mut out = Vec::new();
+        let mut resized = screen(&cells);
+        resized.rows = 1;
+        renderer.draw(&resized, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("\x1b[0m\x1b[2J"));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut renderer = TerminalRenderer::new();
+        let mut out = Vec::new();
+        // Bright white on blue, followed by a code page 437 box drawing character.
+        renderer.draw(&screen(&[0x1F41, 0x1FC4, 0, 0, 0, 0, 0, 0]), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\x1b[0;97;44mA─"));
+
+        // On the MDA, attribute 0x70 is reverse video and 0x01 is underlined.
+        let mut out = Vec::new();
+        let mut mono = screen(&[0x7041, 0x0142, 0, 0, 0, 0, 0, 0]);
+        mono.mono = true;
+        renderer.draw(&mono, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\x1b[0;7mA"));
+        assert!(text.contains("\x1b[0;4mB"));
+    }
+}
@@ -38,6 +38,7 @@ const UPS_CAP: u64 = 1000; // Maximum number of window manager updates per secon
 const UPS_MIN_DURATION: Duration = Duration::from_millis(1000 / UPS_CAP as u64); // Minimum duration between window manager updates
 const DEFAULT_EMU_FPS_TARGET: u32 = 60; // Default rendering FPS for the emulator
 const FRAME_HISTORY_LEN: usize = 60; // Number of frames of history to keep
+const IDLE_WAIT_MIN: Duration = Duration::from_millis(1); // Shortest idle wait worth handing to the OS scheduler
 
 #[derive(Copy, Clone, Default)]
 pub struct FrameEntry {
@@ -118,6 +119,11 @@ impl HertzEvent {
             false
         }
     }
+    /// Return the time remaining until this event next fires.
+    #[inline]
+    pub fn until_next(&self) -> Duration {
+        self.target.saturating_sub(self.accum)
+    }
 }
 
 #[derive(Copy, Clone, Default)]
@@ -200,6 +206,7 @@ pub struct TimestepManager {
     perf_stats: PerfStats,
     total_running_time: Duration,
     frame_due: bool,
+    idle_time: Option<Duration>, // Host time the emulated machine reported it can idle for after its last update
 }
 
 impl Default for TimestepManager {
@@ -226,6 +233,7 @@ impl Default for TimestepManager {
             perf_stats: PerfStats::default(),
 
             frame_due: false,
+            idle_time: None,
         }
     }
 }
@@ -247,6 +255,9 @@ impl TimestepManager {
     /// When a second has elapsed, the 'machine_callback' is called to retrieve the current
     /// CPU cycle count, system tick count, instruction count, and optionally the number of
     /// rendered frames from the primary video card (if present).
+    /// The 'emu_update_callback' may return the amount of time the emulated machine can idle
+    /// for (such as while halted waiting for an interrupt). If so, the host thread will sleep
+    /// until that time has passed or the next scheduled update or render is due.
     pub fn wm_update<E, F, G, H>(
         &mut self,
         emu: &mut E,
@@ -255,7 +266,7 @@ impl TimestepManager {
         mut emu_render_callback: H,
     ) where
         F: FnOnce(&mut E) -> MachinePerfStats,
-        G: FnMut(&mut E, u32),
+        G: FnMut(&mut E, u32) -> Option<Duration>,
         H: FnMut(&mut E, &TimestepManager, &PerfSnapshot),
     {
         if !self.init {
@@ -277,7 +288,7 @@ impl TimestepManager {
         if self.emu_update_rate.tick(elapsed) {
             self.last_frame_instant = Instant::now();
             let emu_start = Instant::now();
-            emu_update_callback(emu, self.cpu_cycle_update_target);
+            self.idle_time = emu_update_callback(emu, self.cpu_cycle_update_target);
             self.perf_stats.emu_ups.tick();
             self.perf_stats.emu_time = emu_start.elapsed();
         }
@@ -296,9 +307,26 @@ impl TimestepManager {
         }
 
         self.last_instant = self.current_instant;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(wait) = self.idle_wait() {
+            thread::sleep(wait);
+            return;
+        }
         thread::yield_now();
     }
 
+    /// Return the amount of time the host can sleep for, if the emulated machine is idle.
+    /// The wait is bounded by the next emulator update and render, so input and video are
+    /// still serviced on schedule. Waits too short for the OS scheduler to honor return None.
+    pub fn idle_wait(&self) -> Option<Duration> {
+        let wait = self
+            .idle_time?
+            .min(self.emu_update_rate.until_next())
+            .min(self.emu_render_rate.until_next());
+        (wait >= IDLE_WAIT_MIN).then_some(wait)
+    }
+
     pub fn handle_second<E, F>(&mut self, emu: &mut E, second_callback: F)
     where
         F: FnOnce(&mut E) -> MachinePerfStats,
@@ -366,3 +394,37 @@ impl TimestepManager {
         (&self.perf_stats, self.frame_history.as_vec())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hertz_event_until_next() {
+        let mut event = HertzEvent::new(100);
+        assert_eq!(event.until_next(), Duration::from_millis(10));
+        assert!(!event.tick(Duration::from_millis(4)));
+        assert_eq!(event.until_next(), Duration::from_millis(6));
+        assert!(event.tick(Duration::from_millis(7)));
+        assert_eq!(event.until_next(), Duration::from_millis(9));
+    }
+
+    #[test]
+    fn test_idle_wait() {
+        let mut tm = TimestepManager::new();
+        // Not idle: never wait.
+        assert_eq!(tm.idle_wait(), None);
+
+        // Idle wait is capped by the next emulator update (60Hz).
+        tm.idle_time = Some(Duration::from_secs(1));
+        assert_eq!(tm.idle_wait(), Some(Duration::from_micros(1_000_000 / 60)));
+
+        // The machine's next event comes first.
+        tm.idle_time = Some(Duration::from_millis(5));
+        assert_eq!(tm.idle_wait(), Some(Duration::from_millis(5)));
+
+        // Too short to be worth sleeping for.
+        tm.idle_time = Some(Duration::from_micros(200));
+        assert_eq!(tm.idle_wait(), None);
+    }
+}
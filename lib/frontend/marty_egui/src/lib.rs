@@ -50,8 +50,6 @@ use frontend_common::{
     display_scaler::{ScalerMode, ScalerParams, ScalerPreset},
 };
 
-use serialport::SerialPortInfo;
-
 mod color;
 mod constants;
 mod image;
@@ -68,10 +66,12 @@ mod windows;
 mod workspace;
 
 use marty_core::{
+    cpu_common::HistoryExportFormat,
     device_traits::videocard::{DisplayApertureDesc, DisplayApertureType, VideoCardState, VideoCardStateEntry},
     device_types::hdc::HardDiskFormat,
     devices::{pic::PicStringState, pit::PitDisplayState, ppi::PpiStringState},
     machine::{ExecutionControl, MachineState},
+    tracelogger::{LogCategory, LogLevel},
 };
 
 use serde::{Deserialize, Serialize};
@@ -122,6 +122,7 @@ pub enum GuiBoolean {
     CpuEnableWaitStates,
     CpuInstructionHistory,
     CpuTraceLoggingEnabled,
+    CpuStepBackHistory,
     TurboButton,
 }
 
@@ -174,6 +175,7 @@ pub enum GuiEvent {
     DumpVRAM,
     DumpCS,
     DumpAllMem,
+    DumpFont,
     EditBreakpoint,
     MemoryUpdate,
     TokenHover(usize),
@@ -181,10 +183,13 @@ pub enum GuiEvent {
     CompositeAdjust(usize, CompositeParams),
     ScalerAdjust(usize, ScalerParams),
     FlushLogs,
+    SetLogLevel(LogCategory, LogLevel),
     DelayAdjust,
     TickDevice(DeviceSelection, u32),
     MachineStateChange(MachineState),
     TakeScreenshot(usize),
+    ToggleAudioRecording,
+    ExportInstructionHistory(HistoryExportFormat),
     Exit,
     SetNMI(bool),
     TriggerParity,
@@ -35,7 +35,10 @@ use crate::{state::GuiState, GuiBoolean, GuiEnum, GuiEvent, GuiVariable, GuiVari
 use marty_core::device_traits::videocard::VideoType;
 use std::time::Duration;
 
-use marty_core::machine::MachineState;
+use marty_core::{
+    machine::MachineState,
+    tracelogger::{self, LogCategory, LogLevel},
+};
 
 impl GuiState {
     pub fn draw_menu(&mut self, ui: &mut egui::Ui) {
@@ -121,6 +124,21 @@ impl GuiState {
                         ui.close_menu();
                     }
                 });
+
+                ui.separator();
+
+                let record_label = match self.audio_recording {
+                    true => "⏹ Stop Audio Recording",
+                    false => "⏺ Start Audio Recording",
+                };
+                if ui
+                    .button(record_label)
+                    .on_hover_text("Record the sound output and each audio device to WAV files")
+                    .clicked()
+                {
+                    self.event_queue.send(GuiEvent::ToggleAudioRecording);
+                    ui.close_menu();
+                }
             });
 
             let media_response = ui.menu_button("Media", |ui| {
@@ -254,6 +272,10 @@ impl GuiState {
                             self.event_queue.send(GuiEvent::DumpAllMem);
                             ui.close_menu();
                         }
+                        if ui.button("Character Glyphs").clicked() {
+                            self.event_queue.send(GuiEvent::DumpFont);
+                            ui.close_menu();
+                        }
                     });
                 });
 
@@ -288,6 +310,20 @@ impl GuiState {
                      */
                 });
 
+                ui.menu_button("Log Levels", |ui| {
+                    for category in LogCategory::ALL {
+                        let current_level = tracelogger::category_level(category);
+                        ui.menu_button(format!("{}: {}", category, current_level), |ui| {
+                            for level in LogLevel::ALL {
+                                if ui.radio(current_level == level, format!("{}", level)).clicked() {
+                                    self.event_queue.send(GuiEvent::SetLogLevel(category, level));
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    }
+                });
+
                 if ui.button("Flush Trace Logs").clicked() {
                     self.event_queue.send(GuiEvent::FlushLogs);
                     ui.close_menu();
@@ -296,12 +332,14 @@ impl GuiState {
             ui.menu_button("Options", |ui| {
                 ui.menu_button("Attach COM2: ...", |ui| {
                     for port in &self.serial_ports {
+                        let label = if port.description.is_empty() {
+                            port.name.clone()
+                        }
+                        else {
+                            format!("{} ({})", port.name, port.description)
+                        };
                         if ui
-                            .radio_value(
-                                &mut self.serial_port_name,
-                                port.port_name.clone(),
-                                port.port_name.clone(),
-                            )
+                            .radio_value(&mut self.serial_port_name, port.name.clone(), label)
                             .clicked()
                         {
                             self.event_queue
@@ -49,11 +49,10 @@ use frontend_common::{
 };
 use marty_core::{
     device_traits::videocard::{DisplayApertureDesc, VideoCardState, VideoCardStateEntry},
-    devices::{pit::PitDisplayState, ppi::PpiStringState},
+    devices::{pit::PitDisplayState, ppi::PpiStringState, serial::HostSerialPortInfo},
     machine::{ExecutionControl, MachineState},
 };
 use serde::{Deserialize, Serialize};
-use serialport::SerialPortInfo;
 use std::{
     cell::RefCell,
     collections::{BTreeMap, HashMap},
@@ -160,7 +159,8 @@ pub struct GuiState {
     pub(crate) option_flags: HashMap<GuiBoolean, bool>,
     pub(crate) option_enums: GuiEnumMap,
 
-    pub(crate) machine_state: MachineState,
+    pub(crate) machine_state:   MachineState,
+    pub(crate) audio_recording: bool,
 
     video_mem: ColorImage,
     pub(crate) perf_stats: PerformanceStats,
@@ -185,7 +185,7 @@ pub struct GuiState {
     pub(crate) vhd_name1: OsString,
 
     // Serial ports
-    pub(crate) serial_ports: Vec<SerialPortInfo>,
+    pub(crate) serial_ports: Vec<HostSerialPortInfo>,
     pub(crate) serial_port_name: String,
 
     pub(crate) exec_control: Rc<RefCell<ExecutionControl>>,
@@ -273,6 +273,7 @@ impl GuiState {
             (GuiBoolean::CpuEnableWaitStates, true),
             (GuiBoolean::CpuInstructionHistory, false),
             (GuiBoolean::CpuTraceLoggingEnabled, false),
+            (GuiBoolean::CpuStepBackHistory, false),
             (GuiBoolean::TurboButton, false),
             //(GuiBoolean::ShowBackBuffer, true),
             //(GuiBoolean::EnableSnow, true),
@@ -295,6 +296,7 @@ impl GuiState {
             option_enums,
 
             machine_state: MachineState::Off,
+            audio_recording: false,
             video_mem: ColorImage::new([320, 200], egui::Color32::BLACK),
 
             perf_stats: Default::default(),
@@ -432,6 +434,10 @@ impl GuiState {
         self.machine_state = state;
     }
 
+    pub fn set_audio_recording(&mut self, state: bool) {
+        self.audio_recording = state;
+    }
+
     pub fn set_floppy_drives(&mut self, drive_ct: usize) {
         self.floppy_drives.clear();
         for idx in 0..drive_ct {
@@ -524,7 +530,7 @@ impl GuiState {
         *self.window_open_flags.get_mut(&window).unwrap() = true;
     }
 
-    pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
+    pub fn get_breakpoints(&mut self) -> (&str, &str, &str, &str) {
         self.cpu_control.get_breakpoints()
     }
 
@@ -532,7 +538,7 @@ impl GuiState {
         self.pit_viewer.update_state(state);
     }
 
-    pub fn update_serial_ports(&mut self, ports: Vec<SerialPortInfo>) {
+    pub fn update_serial_ports(&mut self, ports: Vec<HostSerialPortInfo>) {
         self.serial_ports = ports;
     }
 
@@ -39,6 +39,8 @@ pub struct CpuControl {
     breakpoint: String,
     mem_breakpoint: String,
     int_breakpoint: String,
+    vram_breakpoint: String,
+    run_count: String,
 }
 
 impl CpuControl {
@@ -48,6 +50,8 @@ impl CpuControl {
             breakpoint: String::new(),
             mem_breakpoint: String::new(),
             int_breakpoint: String::new(),
+            vram_breakpoint: String::new(),
+            run_count: String::from("1"),
         }
     }
 
@@ -92,10 +96,45 @@ impl CpuControl {
                 };
 
                 if ui.input(|i| i.key_pressed(egui::Key::F11)) {
-                    exec_control.set_op(ExecutionOperation::Step);
+                    if ui.input(|i| i.modifiers.shift) {
+                        exec_control.set_op(ExecutionOperation::StepOut);
+                    }
+                    else {
+                        exec_control.set_op(ExecutionOperation::Step);
+                    }
                 }
             });
 
+            ui.add_enabled_ui(step_enabled, |ui| {
+                if ui
+                    .button(egui::RichText::new("⤴").font(egui::FontId::proportional(20.0)))
+                    .on_hover_text("Step Out")
+                    .clicked()
+                {
+                    exec_control.set_op(ExecutionOperation::StepOut);
+                };
+            });
+
+            ui.add_enabled_ui(step_enabled, |ui| {
+                if ui
+                    .button(egui::RichText::new("⬅").font(egui::FontId::proportional(20.0)))
+                    .on_hover_text("Step Back (requires Step-back History)")
+                    .clicked()
+                {
+                    exec_control.set_op(ExecutionOperation::StepBack);
+                };
+            });
+
+            ui.add_enabled_ui(step_enabled, |ui| {
+                if ui
+                    .button(egui::RichText::new("⏭").font(egui::FontId::proportional(20.0)))
+                    .on_hover_text("Frame Advance")
+                    .clicked()
+                {
+                    exec_control.set_op(ExecutionOperation::FrameAdvance);
+                };
+            });
+
             ui.add_enabled_ui(run_enabled, |ui| {
                 if ui
                     .button(egui::RichText::new("▶").font(egui::FontId::proportional(20.0)))
@@ -116,6 +155,14 @@ impl CpuControl {
                 exec_control.set_op(ExecutionOperation::Reset);
             };
 
+            if ui
+                .button(egui::RichText::new("⟳").font(egui::FontId::proportional(20.0)))
+                .on_hover_text("Warm Reset")
+                .clicked()
+            {
+                exec_control.set_op(ExecutionOperation::WarmReset);
+            };
+
             ui.menu_button(egui::RichText::new("⏷").font(egui::FontId::proportional(20.0)), |ui| {
                 if ui
                     .checkbox(
@@ -162,6 +209,21 @@ impl CpuControl {
                     ));
                     ui.close_menu();
                 }
+                if ui
+                    .checkbox(
+                        &mut gui_options.get_mut(&GuiBoolean::CpuStepBackHistory).unwrap(),
+                        "Step-back History",
+                    )
+                    .clicked()
+                {
+                    let new_opt = gui_options.get(&GuiBoolean::CpuStepBackHistory).unwrap();
+
+                    events.send(GuiEvent::VariableChanged(
+                        GuiVariableContext::Global,
+                        GuiVariable::Bool(GuiBoolean::CpuStepBackHistory, *new_opt),
+                    ));
+                    ui.close_menu();
+                }
             });
         });
 
@@ -172,6 +234,20 @@ impl CpuControl {
             ui.label(&state_str);
         });
         ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Run for: ");
+            ui.add(egui::TextEdit::singleline(&mut self.run_count).desired_width(60.0));
+            let count = self.run_count.trim().parse::<u64>().ok().filter(|count| *count > 0);
+            ui.add_enabled_ui(step_enabled && count.is_some(), |ui| {
+                if ui.button("Instructions").clicked() {
+                    exec_control.set_op(ExecutionOperation::RunInstructions(count.unwrap()));
+                }
+                if ui.button("Frames").clicked() {
+                    exec_control.set_op(ExecutionOperation::RunFrames(count.unwrap()));
+                }
+            });
+        });
+        ui.separator();
         ui.horizontal(|ui| {
             ui.label("Exec Breakpoint: ");
             if ui.text_edit_singleline(&mut self.breakpoint).changed() {
@@ -192,9 +268,25 @@ impl CpuControl {
                 events.send(GuiEvent::EditBreakpoint);
             }
         });
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("VRAM Breakpoint: ");
+            if ui
+                .text_edit_singleline(&mut self.vram_breakpoint)
+                .on_hover_text("Address or text cell (row,col), optionally followed by =value in hex")
+                .changed()
+            {
+                events.send(GuiEvent::EditBreakpoint);
+            }
+        });
     }
 
-    pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
-        (&self.breakpoint, &self.mem_breakpoint, &self.int_breakpoint)
+    pub fn get_breakpoints(&mut self) -> (&str, &str, &str, &str) {
+        (
+            &self.breakpoint,
+            &self.mem_breakpoint,
+            &self.int_breakpoint,
+            &self.vram_breakpoint,
+        )
     }
 }
@@ -218,6 +218,10 @@ impl CpuViewerControl {
             MartyLayout::kv_row(ui, "Cycle #", None, |ui| {
                 ui.add(egui::TextEdit::singleline(&mut self.cpu_state.cycle_count).font(egui::TextStyle::Monospace));
             });
+            MartyLayout::kv_row(ui, "Wait States", None, |ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.cpu_state.wait_states).font(egui::TextStyle::Monospace))
+                    .on_hover_text("Bus wait states incurred by the last instruction");
+            });
         });
     }
 
@@ -166,6 +166,9 @@ impl CycleTraceViewerControl {
             TraceMode::CycleSigrok => {
                 ui.label("Cycle tracing in sigrok mode. No display available.");
             }
+            TraceMode::CycleJson | TraceMode::CycleBinary => {
+                ui.label("Cycle tracing to file only. No display available.");
+            }
             TraceMode::Instruction => {
                 ui.label("CPU tracing in instruction mode. No cycle tracing available.");
             }
@@ -32,7 +32,7 @@
 
 */
 use crate::{token_listview::*, *};
-use marty_core::syntax_token::*;
+use marty_core::{cpu_common::HistoryExportFormat, syntax_token::*};
 
 pub struct InstructionHistoryControl {
     pub address: String,
@@ -55,6 +55,31 @@ impl InstructionHistoryControl {
         self.tlv.set_capacity(32);
         self.tlv.set_visible(32);
 
+        ui.horizontal(|ui| {
+            if ui
+                .button("Export Text")
+                .on_hover_text("Export the full instruction history as text")
+                .clicked()
+            {
+                events.send(GuiEvent::ExportInstructionHistory(HistoryExportFormat::Text));
+            }
+            if ui
+                .button("Export CSV")
+                .on_hover_text("Export the full instruction history as CSV")
+                .clicked()
+            {
+                events.send(GuiEvent::ExportInstructionHistory(HistoryExportFormat::Csv));
+            }
+            if ui
+                .button("Export JSON")
+                .on_hover_text("Export the full instruction history as JSON")
+                .clicked()
+            {
+                events.send(GuiEvent::ExportInstructionHistory(HistoryExportFormat::Json));
+            }
+        });
+        ui.separator();
+
         let mut new_row = self.row;
         ui.horizontal(|ui| {
             self.tlv.draw(ui, events, &mut new_row, &mut |scrolled_to, sevents| {});
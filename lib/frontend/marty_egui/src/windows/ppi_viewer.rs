@@ -96,6 +96,14 @@ impl PpiViewerControl {
                 ui.label(egui::RichText::new("Port C Value: ").text_style(egui::TextStyle::Monospace));
                 ui.add(egui::TextEdit::singleline(&mut self.ppi_state.port_c_value).font(egui::TextStyle::Monospace));
                 ui.end_row();
+
+                ui.label(egui::RichText::new("DIP SW1 (ON=1):").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut self.ppi_state.dip_sw1).font(egui::TextStyle::Monospace));
+                ui.end_row();
+
+                ui.label(egui::RichText::new("DIP SW2 (ON=1):").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut self.ppi_state.dip_sw2).font(egui::TextStyle::Monospace));
+                ui.end_row();
             });
     }
 
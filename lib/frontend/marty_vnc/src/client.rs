@@ -0,0 +1,493 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    marty_vnc::client.rs
+
+    Per-client RFB protocol handling. Each client gets a reader thread that
+    parses client messages and a writer loop that answers framebuffer update
+    requests as new frames arrive.
+*/
+
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::{keysym::keysym_to_martykey, SharedFrame, VncEvent};
+
+const RFB_VERSION: &[u8; 12] = b"RFB 003.008\n";
+const SECURITY_NONE: u8 = 1;
+
+const ENCODING_RAW: i32 = 0;
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 480;
+// How long to wait for the frontend to submit a first frame before sending ServerInit.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Copy, Clone, Debug)]
+struct PixelFormat {
+    bpp: u8,
+    depth: u8,
+    big_endian: bool,
+    true_color: bool,
+    r_max: u16,
+    g_max: u16,
+    b_max: u16,
+    r_shift: u8,
+    g_shift: u8,
+    b_shift: u8,
+}
+
+impl Default for PixelFormat {
+    fn default() -> Self {
+        Self {
+            bpp: 32,
+            depth: 24,
+            big_endian: false,
+            true_color: true,
+            r_max: 255,
+            g_max: 255,
+            b_max: 255,
+            r_shift: 16,
+            g_shift: 8,
+            b_shift: 0,
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Parse a pixel format sent by the client. Formats that can't be encoded are rejected, so
+    /// the sizes and shifts of an accepted format are always in range.
+    fn from_bytes(b: &[u8; 16]) -> Result<Self, Error> {
+        let format = Self {
+            bpp: b[0],
+            depth: b[1],
+            big_endian: b[2] != 0,
+            true_color: b[3] != 0,
+            r_max: u16::from_be_bytes([b[4], b[5]]),
+            g_max: u16::from_be_bytes([b[6], b[7]]),
+            b_max: u16::from_be_bytes([b[8], b[9]]),
+            r_shift: b[10],
+            g_shift: b[11],
+            b_shift: b[12],
+        };
+
+        if !matches!(format.bpp, 8 | 16 | 32) {
+            bail!("unsupported bits per pixel {}", format.bpp);
+        }
+        if format.true_color {
+            let shift = format.r_shift.max(format.g_shift).max(format.b_shift);
+            if shift >= format.bpp {
+                bail!("colour shift {} out of range for {} bits per pixel", shift, format.bpp);
+            }
+        }
+        else if format.bpp != 8 {
+            bail!("colour-mapped formats must be 8 bits per pixel");
+        }
+        Ok(format)
+    }
+
+    fn to_bytes(self) -> [u8; 16] {
+        let mut b = [0u8; 16];
+        b[0] = self.bpp;
+        b[1] = self.depth;
+        b[2] = self.big_endian as u8;
+        b[3] = self.true_color as u8;
+        b[4..6].copy_from_slice(&self.r_max.to_be_bytes());
+        b[6..8].copy_from_slice(&self.g_max.to_be_bytes());
+        b[8..10].copy_from_slice(&self.b_max.to_be_bytes());
+        b[10] = self.r_shift;
+        b[11] = self.g_shift;
+        b[12] = self.b_shift;
+        b
+    }
+
+    /// Convert an RGB triple to a pixel value in this format. Colour-mapped formats use the
+    /// 3-3-2 palette sent by send_color_map().
+    fn pixel(&self, r: u8, g: u8, b: u8) -> u32 {
+        if self.true_color {
+            let r = (r as u32 * self.r_max as u32 / 255) << self.r_shift;
+            let g = (g as u32 * self.g_max as u32 / 255) << self.g_shift;
+            let b = (b as u32 * self.b_max as u32 / 255) << self.b_shift;
+            r | g | b
+        }
+        else {
+            (r as u32 >> 5) | ((g as u32 >> 5) << 3) | ((b as u32 >> 6) << 6)
+        }
+    }
+
+    fn push_pixel(&self, out: &mut Vec<u8>, pixel: u32) {
+        match (self.bpp, self.big_endian) {
+            (8, _) => out.push(pixel as u8),
+            (16, false) => out.extend_from_slice(&(pixel as u16).to_le_bytes()),
+            (16, true) => out.extend_from_slice(&(pixel as u16).to_be_bytes()),
+            (_, false) => out.extend_from_slice(&pixel.to_le_bytes()),
+            (_, true) => out.extend_from_slice(&pixel.to_be_bytes()),
+        }
+    }
+}
+
+/// Messages passed from the reader thread to the writer loop.
+enum ClientMessage {
+    SetPixelFormat(PixelFormat),
+    SetEncodings(Vec<i32>),
+    UpdateRequest { incremental: bool },
+}
+
+pub(crate) fn run_client(stream: TcpStream, name: &str, frame: Arc<Mutex<SharedFrame>>, event_tx: Sender<VncEvent>) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            log::warn!("VNC client has no peer address: {}", e);
+            return;
+        }
+    };
+    log::info!("VNC client connected: {}", peer);
+    _ = event_tx.send(VncEvent::ClientConnected(peer));
+
+    if let Err(e) = serve(stream, peer, name, frame, &event_tx) {
+        log::info!("VNC client {} disconnected: {}", peer, e);
+    }
+    _ = event_tx.send(VncEvent::ClientDisconnected(peer));
+}
+
+fn serve(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    name: &str,
+    frame: Arc<Mutex<SharedFrame>>,
+    event_tx: &Sender<VncEvent>,
+) -> Result<(), Error> {
+    stream.set_nodelay(true)?;
+    handshake(&mut stream)?;
+
+    // ClientInit: a single shared-desktop flag, which we ignore.
+    let mut shared = [0u8; 1];
+    stream.read_exact(&mut shared)?;
+
+    // Give the frontend a moment to submit a frame so we can report the right size.
+    let start = Instant::now();
+    let (mut client_w, mut client_h) = loop {
+        let (w, h) = {
+            let frame = frame.lock().map_err(|_| anyhow!("frame lock poisoned"))?;
+            (frame.w, frame.h)
+        };
+        if w > 0 && h > 0 {
+            break (w, h);
+        }
+        if start.elapsed() > FIRST_FRAME_TIMEOUT {
+            break (DEFAULT_WIDTH, DEFAULT_HEIGHT);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let mut format = PixelFormat::default();
+    let mut init = Vec::new();
+    init.extend_from_slice(&(client_w as u16).to_be_bytes());
+    init.extend_from_slice(&(client_h as u16).to_be_bytes());
+    init.extend_from_slice(&format.to_bytes());
+    init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    init.extend_from_slice(name.as_bytes());
+    stream.write_all(&init)?;
+
+    // Parse client messages on a separate thread so we can answer update requests as frames arrive.
+    let (msg_tx, msg_rx) = channel();
+    let reader_stream = stream.try_clone()?;
+    let reader_events = event_tx.clone();
+    thread::Builder::new().name("vnc_reader".to_string()).spawn(move || {
+        if let Err(e) = read_messages(reader_stream, msg_tx, reader_events) {
+            log::debug!("VNC reader for {} exiting: {}", peer, e);
+        }
+    })?;
+
+    let mut desktop_size = false;
+    // The frame is copied out of the shared buffer, so the frontend isn't blocked while it is encoded.
+    let mut snapshot = SharedFrame::default();
+    let mut pending = false;
+    let mut force = false;
+    let mut last_generation = 0;
+
+    let result = loop {
+        match msg_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(ClientMessage::SetPixelFormat(new_format)) => {
+                format = new_format;
+                if !format.true_color {
+                    send_color_map(&mut stream)?;
+                }
+                force = true;
+            }
+            Ok(ClientMessage::SetEncodings(encodings)) => {
+                desktop_size = encodings.contains(&ENCODING_DESKTOP_SIZE);
+            }
+            Ok(ClientMessage::UpdateRequest { incremental }) => {
+                pending = true;
+                force |= !incremental;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break Ok(()),
+        }
+
+        if !pending {
+            continue;
+        }
+
+        {
+            let current = frame.lock().map_err(|_| anyhow!("frame lock poisoned"))?;
+            if !force && current.generation == last_generation {
+                continue;
+            }
+            snapshot.copy_from(&current);
+        }
+
+        let mut update = Vec::new();
+        let mut rects = 1u16;
+        if desktop_size && snapshot.w > 0 && (snapshot.w != client_w || snapshot.h != client_h) {
+            client_w = snapshot.w;
+            client_h = snapshot.h;
+            rects += 1;
+            push_rect_header(&mut update, client_w, client_h, ENCODING_DESKTOP_SIZE);
+        }
+        push_rect_header(&mut update, client_w, client_h, ENCODING_RAW);
+        encode_raw(&mut update, &snapshot, client_w, client_h, &format);
+        last_generation = snapshot.generation;
+
+        let mut header = vec![0u8, 0u8];
+        header.extend_from_slice(&rects.to_be_bytes());
+        if let Err(e) = stream.write_all(&header).and_then(|_| stream.write_all(&update)) {
+            break Err(e.into());
+        }
+        pending = false;
+        force = false;
+    };
+
+    _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn handshake(stream: &mut TcpStream) -> Result<(), Error> {
+    stream.write_all(RFB_VERSION)?;
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let version_str = String::from_utf8_lossy(&version);
+    log::debug!("VNC client version: {}", version_str.trim_end());
+
+    // RFB 3.3 clients are told the security type; 3.7 and later choose from a list.
+    let minor = match version_str.get(8..11).and_then(|s| s.parse::<u32>().ok()) {
+        Some(minor) => minor,
+        None => return Err(anyhow!("invalid protocol version {:?}", version_str)),
+    };
+    if minor < 7 {
+        stream.write_all(&(SECURITY_NONE as u32).to_be_bytes())?;
+        return Ok(());
+    }
+
+    stream.write_all(&[1, SECURITY_NONE])?;
+    let mut choice = [0u8; 1];
+    stream.read_exact(&mut choice)?;
+    if choice[0] != SECURITY_NONE {
+        return Err(anyhow!("unsupported security type {}", choice[0]));
+    }
+    if minor >= 8 {
+        // SecurityResult: OK
+        stream.write_all(&0u32.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_messages(
+    mut stream: TcpStream,
+    msg_tx: Sender<ClientMessage>,
+    event_tx: Sender<VncEvent>,
+) -> Result<(), Error> {
+    let mut last_pos: Option<(u16, u16)> = None;
+    loop {
+        let mut msg_type = [0u8; 1];
+        stream.read_exact(&mut msg_type)?;
+        match msg_type[0] {
+            0 => {
+                let mut buf = [0u8; 19];
+                stream.read_exact(&mut buf)?;
+                let mut pf = [0u8; 16];
+                pf.copy_from_slice(&buf[3..19]);
+                msg_tx.send(ClientMessage::SetPixelFormat(PixelFormat::from_bytes(&pf)?))?;
+            }
+            2 => {
+                let mut buf = [0u8; 3];
+                stream.read_exact(&mut buf)?;
+                let count = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+                let mut encodings = vec![0u8; count * 4];
+                stream.read_exact(&mut encodings)?;
+                let encodings = encodings
+                    .chunks_exact(4)
+                    .map(|e| i32::from_be_bytes([e[0], e[1], e[2], e[3]]))
+                    .collect();
+                msg_tx.send(ClientMessage::SetEncodings(encodings))?;
+            }
+            3 => {
+                // We always send the full frame, so the requested rectangle is ignored.
+                let mut buf = [0u8; 9];
+                stream.read_exact(&mut buf)?;
+                msg_tx.send(ClientMessage::UpdateRequest {
+                    incremental: buf[0] != 0,
+                })?;
+            }
+            4 => {
+                let mut buf = [0u8; 7];
+                stream.read_exact(&mut buf)?;
+                let keysym = u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]);
+                match keysym_to_martykey(keysym) {
+                    Some(key) => event_tx.send(VncEvent::Key {
+                        key,
+                        pressed: buf[0] != 0,
+                    })?,
+                    None => log::debug!("Unhandled VNC keysym: {:04X}", keysym),
+                }
+            }
+            5 => {
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf)?;
+                let x = u16::from_be_bytes([buf[1], buf[2]]);
+                let y = u16::from_be_bytes([buf[3], buf[4]]);
+                let (delta_x, delta_y) = match last_pos {
+                    Some((lx, ly)) => (x as i32 - lx as i32, y as i32 - ly as i32),
+                    None => (0, 0),
+                };
+                last_pos = Some((x, y));
+                event_tx.send(VncEvent::Pointer {
+                    left: buf[0] & 0x01 != 0,
+                    right: buf[0] & 0x04 != 0,
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                })?;
+            }
+            6 => {
+                // ClientCutText: not supported, discard the text.
+                let mut buf = [0u8; 7];
+                stream.read_exact(&mut buf)?;
+                let len = u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]) as u64;
+                std::io::copy(&mut (&mut stream).take(len), &mut std::io::sink())?;
+            }
+            t => return Err(anyhow!("unsupported client message type {}", t)),
+        }
+    }
+}
+
+fn push_rect_header(out: &mut Vec<u8>, w: u32, h: u32, encoding: i32) {
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(w as u16).to_be_bytes());
+    out.extend_from_slice(&(h as u16).to_be_bytes());
+    out.extend_from_slice(&encoding.to_be_bytes());
+}
+
+/// Encode the frame as a Raw rectangle of the client's size, cropping or padding with black
+/// if the frame size differs.
+fn encode_raw(out: &mut Vec<u8>, frame: &SharedFrame, w: u32, h: u32, format: &PixelFormat) {
+    let black = format.pixel(0, 0, 0);
+    out.reserve(w as usize * h as usize * (format.bpp as usize / 8));
+    for y in 0..h {
+        for x in 0..w {
+            if x < frame.w && y < frame.h {
+                let o = (y as usize * frame.w as usize + x as usize) * 4;
+                let p = format.pixel(frame.data[o], frame.data[o + 1], frame.data[o + 2]);
+                format.push_pixel(out, p);
+            }
+            else {
+                format.push_pixel(out, black);
+            }
+        }
+    }
+}
+
+/// Send a 3-3-2 RGB palette for clients that request a colour-mapped pixel format.
+fn send_color_map(stream: &mut TcpStream) -> Result<(), Error> {
+    let mut msg = vec![1u8, 0];
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&256u16.to_be_bytes());
+    for i in 0..256u32 {
+        let r = (i & 0x07) * 0xFFFF / 7;
+        let g = ((i >> 3) & 0x07) * 0xFFFF / 7;
+        let b = ((i >> 6) & 0x03) * 0xFFFF / 3;
+        msg.extend_from_slice(&(r as u16).to_be_bytes());
+        msg.extend_from_slice(&(g as u16).to_be_bytes());
+        msg.extend_from_slice(&(b as u16).to_be_bytes());
+    }
+    stream.write_all(&msg)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_bytes(bpp: u8, true_color: bool, shifts: [u8; 3]) -> [u8; 16] {
+        let mut b = PixelFormat::default().to_bytes();
+        b[0] = bpp;
+        b[3] = true_color as u8;
+        b[10..13].copy_from_slice(&shifts);
+        b
+    }
+
+    #[test]
+    fn test_pixel_format_validation() {
+        assert!(PixelFormat::from_bytes(&PixelFormat::default().to_bytes()).is_ok());
+        assert!(PixelFormat::from_bytes(&format_bytes(16, true, [11, 5, 0])).is_ok());
+        assert!(PixelFormat::from_bytes(&format_bytes(8, false, [0, 0, 0])).is_ok());
+
+        // Shifts past the pixel size would overflow when encoding.
+        assert!(PixelFormat::from_bytes(&format_bytes(32, true, [40, 8, 0])).is_err());
+        assert!(PixelFormat::from_bytes(&format_bytes(16, true, [11, 5, 16])).is_err());
+        assert!(PixelFormat::from_bytes(&format_bytes(24, true, [16, 8, 0])).is_err());
+        assert!(PixelFormat::from_bytes(&format_bytes(16, false, [0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn test_pixel() {
+        let mut b = format_bytes(16, true, [11, 5, 0]);
+        b[4..10].copy_from_slice(&[0, 31, 0, 63, 0, 31]);
+        let rgb565 = PixelFormat::from_bytes(&b).unwrap();
+        assert_eq!(rgb565.pixel(255, 0, 0), 0xF800);
+        assert_eq!(rgb565.pixel(0, 255, 0), 0x07E0);
+        assert_eq!(rgb565.pixel(0, 0, 255), 0x001F);
+
+        let mapped = PixelFormat::from_bytes(&format_bytes(8, false, [0, 0, 0])).unwrap();
+        assert_eq!(mapped.pixel(255, 255, 255), 0xFF);
+    }
+}
@@ -0,0 +1,109 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    marty_vnc::keysym.rs
+
+    Translation of X11 keysyms, as sent in RFB KeyEvent messages, to MartyKey.
+*/
+
+use marty_core::keys::MartyKey;
+
+/// Translate an X11 keysym to the corresponding key on a US keyboard. Shifted symbols map to
+/// their unshifted key, since the client sends the Shift keysym separately.
+pub fn keysym_to_martykey(keysym: u32) -> Option<MartyKey> {
+    let key = match keysym {
+        0x20..=0x7E => return MartyKey::from_char(char::from(keysym as u8)).map(|(key, _shift)| key),
+        0xFF08 => MartyKey::Backspace,
+        0xFF09 => MartyKey::Tab,
+        0xFF0D => MartyKey::Enter,
+        0xFF13 => MartyKey::Pause,
+        0xFF14 => MartyKey::ScrollLock,
+        0xFF1B => MartyKey::Escape,
+        0xFF50 => MartyKey::Home,
+        0xFF51 => MartyKey::ArrowLeft,
+        0xFF52 => MartyKey::ArrowUp,
+        0xFF53 => MartyKey::ArrowRight,
+        0xFF54 => MartyKey::ArrowDown,
+        0xFF55 => MartyKey::PageUp,
+        0xFF56 => MartyKey::PageDown,
+        0xFF57 => MartyKey::End,
+        0xFF61 => MartyKey::PrintScreen,
+        0xFF63 => MartyKey::Insert,
+        0xFF7F => MartyKey::NumLock,
+        0xFF8D => MartyKey::NumpadEnter,
+        // Keypad keys with NumLock off
+        0xFF95 => MartyKey::Numpad7,
+        0xFF96 => MartyKey::Numpad4,
+        0xFF97 => MartyKey::Numpad8,
+        0xFF98 => MartyKey::Numpad6,
+        0xFF99 => MartyKey::Numpad2,
+        0xFF9A => MartyKey::Numpad9,
+        0xFF9B => MartyKey::Numpad3,
+        0xFF9C => MartyKey::Numpad1,
+        0xFF9D => MartyKey::Numpad5,
+        0xFF9E => MartyKey::Numpad0,
+        0xFF9F => MartyKey::NumpadDecimal,
+        0xFFAA => MartyKey::NumpadMultiply,
+        0xFFAB => MartyKey::NumpadAdd,
+        0xFFAD => MartyKey::NumpadSubtract,
+        0xFFAE => MartyKey::NumpadDecimal,
+        0xFFAF => MartyKey::NumpadDivide,
+        0xFFB0 => MartyKey::Numpad0,
+        0xFFB1 => MartyKey::Numpad1,
+        0xFFB2 => MartyKey::Numpad2,
+        0xFFB3 => MartyKey::Numpad3,
+        0xFFB4 => MartyKey::Numpad4,
+        0xFFB5 => MartyKey::Numpad5,
+        0xFFB6 => MartyKey::Numpad6,
+        0xFFB7 => MartyKey::Numpad7,
+        0xFFB8 => MartyKey::Numpad8,
+        0xFFB9 => MartyKey::Numpad9,
+        0xFFBE => MartyKey::F1,
+        0xFFBF => MartyKey::F2,
+        0xFFC0 => MartyKey::F3,
+        0xFFC1 => MartyKey::F4,
+        0xFFC2 => MartyKey::F5,
+        0xFFC3 => MartyKey::F6,
+        0xFFC4 => MartyKey::F7,
+        0xFFC5 => MartyKey::F8,
+        0xFFC6 => MartyKey::F9,
+        0xFFC7 => MartyKey::F10,
+        0xFFC8 => MartyKey::F11,
+        0xFFC9 => MartyKey::F12,
+        0xFFE1 => MartyKey::ShiftLeft,
+        0xFFE2 => MartyKey::ShiftRight,
+        0xFFE3 => MartyKey::ControlLeft,
+        0xFFE4 => MartyKey::ControlRight,
+        0xFFE5 => MartyKey::CapsLock,
+        0xFFE7 | 0xFFEB => MartyKey::MetaLeft,
+        0xFFE8 | 0xFFEC => MartyKey::MetaRight,
+        0xFFE9 => MartyKey::AltLeft,
+        0xFFEA => MartyKey::AltRight,
+        0xFFFF => MartyKey::Delete,
+        _ => return None,
+    };
+    Some(key)
+}
@@ -0,0 +1,309 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    marty_vnc::lib.rs
+
+    A minimal RFB (VNC) server. The frontend hands the server each rendered
+    RGBA frame and drains keyboard and pointer input from it, so a headless
+    MartyPC instance can be viewed and controlled remotely.
+
+    Only the Raw encoding and the None security type are implemented, so the
+    server should be bound to a trusted interface.
+*/
+
+mod client;
+pub mod keysym;
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+        Mutex,
+    },
+    thread,
+};
+
+use anyhow::Error;
+use marty_core::keys::MartyKey;
+
+pub const DEFAULT_VNC_PORT: u16 = 5900;
+
+/// Input and connection events produced by VNC clients.
+#[derive(Copy, Clone, Debug)]
+pub enum VncEvent {
+    ClientConnected(SocketAddr),
+    ClientDisconnected(SocketAddr),
+    Key {
+        key: MartyKey,
+        pressed: bool,
+    },
+    /// A pointer update. VNC reports absolute positions; delta_x and delta_y are the motion
+    /// since the client's previous pointer event, for use with relative devices like the
+    /// serial mouse.
+    Pointer {
+        left: bool,
+        right: bool,
+        x: u16,
+        y: u16,
+        delta_x: i32,
+        delta_y: i32,
+    },
+}
+
+/// The most recent frame submitted by the frontend, in RGBA8 format.
+#[derive(Default)]
+pub(crate) struct SharedFrame {
+    pub w: u32,
+    pub h: u32,
+    pub data: Vec<u8>,
+    pub generation: u64,
+}
+
+impl SharedFrame {
+    /// Copy another frame into this one, reusing this frame's buffer.
+    pub(crate) fn copy_from(&mut self, other: &SharedFrame) {
+        self.w = other.w;
+        self.h = other.h;
+        self.data.clear();
+        self.data.extend_from_slice(&other.data);
+        self.generation = other.generation;
+    }
+}
+
+pub struct VncServer {
+    addr: SocketAddr,
+    frame: Arc<Mutex<SharedFrame>>,
+    clients: Arc<AtomicUsize>,
+    event_rx: Receiver<VncEvent>,
+}
+
+impl VncServer {
+    /// Bind a listening socket on `addr` and start accepting clients in the background.
+    /// `name` is the desktop name reported to clients.
+    pub fn new(addr: &str, name: &str) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let frame = Arc::new(Mutex::new(SharedFrame::default()));
+        let clients = Arc::new(AtomicUsize::new(0));
+        let (event_tx, event_rx) = channel();
+
+        let accept_frame = frame.clone();
+        let accept_clients = clients.clone();
+        let name = name.to_string();
+        thread::Builder::new()
+            .name("vnc_accept".to_string())
+            .spawn(move || Self::accept_loop(listener, name, accept_frame, accept_clients, event_tx))?;
+
+        log::info!("VNC server listening on {}", addr);
+        Ok(Self {
+            addr,
+            frame,
+            clients,
+            event_rx,
+        })
+    }
+
+    fn accept_loop(
+        listener: TcpListener,
+        name: String,
+        frame: Arc<Mutex<SharedFrame>>,
+        clients: Arc<AtomicUsize>,
+        event_tx: Sender<VncEvent>,
+    ) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let name = name.clone();
+                    let frame = frame.clone();
+                    let clients = clients.clone();
+                    let event_tx = event_tx.clone();
+                    let spawn_result = thread::Builder::new().name("vnc_client".to_string()).spawn(move || {
+                        clients.fetch_add(1, Ordering::SeqCst);
+                        client::run_client(stream, &name, frame, event_tx);
+                        clients.fetch_sub(1, Ordering::SeqCst);
+                    });
+                    if let Err(e) = spawn_result {
+                        log::error!("Failed to spawn VNC client thread: {}", e);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("VNC accept failed: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Return the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Return the number of currently connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.load(Ordering::SeqCst)
+    }
+
+    /// Submit a new frame. `rgba` holds `w` * `h` pixels of 4 bytes each, in R, G, B, A order.
+    /// Frames are only copied while at least one client is connected.
+    pub fn update_frame(&mut self, rgba: &[u8], w: u32, h: u32) {
+        if self.client_count() == 0 {
+            return;
+        }
+        let len = w as usize * h as usize * 4;
+        if rgba.len() < len {
+            log::warn!("VNC frame buffer too small: {} < {}", rgba.len(), len);
+            return;
+        }
+        if let Ok(mut frame) = self.frame.lock() {
+            frame.w = w;
+            frame.h = h;
+            frame.data.clear();
+            frame.data.extend_from_slice(&rgba[0..len]);
+            frame.generation += 1;
+        }
+    }
+
+    /// Return the next pending client event, if any.
+    pub fn get_event(&self) -> Option<VncEvent> {
+        self.event_rx.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Duration,
+    };
+
+    const FRAME_W: u32 = 4;
+    const FRAME_H: u32 = 2;
+
+    fn read_n(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// A 4x2 frame with red, green and blue pixels followed by black.
+    fn test_frame() -> Vec<u8> {
+        let mut frame = vec![0u8; (FRAME_W * FRAME_H * 4) as usize];
+        frame[0..4].copy_from_slice(&[255, 0, 0, 255]);
+        frame[4..8].copy_from_slice(&[0, 255, 0, 255]);
+        frame[8..12].copy_from_slice(&[0, 0, 255, 255]);
+        frame
+    }
+
+    /// Connect to the server and complete the RFB 3.8 handshake and initialization, returning
+    /// the stream and the ServerInit message.
+    fn connect(server: &mut VncServer) -> (TcpStream, Vec<u8>) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        assert_eq!(read_n(&mut stream, 12), b"RFB 003.008\n");
+        stream.write_all(b"RFB 003.008\n").unwrap();
+        // One security type is offered: None.
+        assert_eq!(read_n(&mut stream, 2), [1, 1]);
+        stream.write_all(&[1]).unwrap();
+        // SecurityResult: OK
+        assert_eq!(read_n(&mut stream, 4), [0, 0, 0, 0]);
+
+        // The client is counted before the handshake starts, so the frame is accepted now and
+        // ServerInit reports its size.
+        assert_eq!(server.client_count(), 1);
+        server.update_frame(&test_frame(), FRAME_W, FRAME_H);
+
+        // ClientInit: shared desktop.
+        stream.write_all(&[1]).unwrap();
+        let mut init = read_n(&mut stream, 24);
+        let name_len = u32::from_be_bytes([init[20], init[21], init[22], init[23]]) as usize;
+        init.extend(read_n(&mut stream, name_len));
+        (stream, init)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut server = VncServer::new("127.0.0.1:0", "test").unwrap();
+        let (_stream, init) = connect(&mut server);
+
+        assert_eq!(&init[0..4], [0, FRAME_W as u8, 0, FRAME_H as u8]);
+        // 32bpp, depth 24, little endian true colour, 8 bits per channel at shifts 16, 8, 0.
+        assert_eq!(&init[4..17], [32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 16, 8, 0]);
+        assert_eq!(&init[24..], b"test");
+
+        let mut event = None;
+        for _ in 0..100 {
+            event = server.get_event();
+            if event.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(event, Some(VncEvent::ClientConnected(_))));
+    }
+
+    #[test]
+    fn test_framebuffer_update() {
+        let mut server = VncServer::new("127.0.0.1:0", "test").unwrap();
+        let (mut stream, _) = connect(&mut server);
+
+        // SetPixelFormat: 16bpp big endian RGB565.
+        let mut msg = vec![0u8, 0, 0, 0];
+        msg.extend_from_slice(&[16, 16, 1, 1, 0, 31, 0, 63, 0, 31, 11, 5, 0, 0, 0, 0]);
+        // FramebufferUpdateRequest: non-incremental, full screen.
+        msg.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, FRAME_W as u8, 0, FRAME_H as u8]);
+        stream.write_all(&msg).unwrap();
+
+        // FramebufferUpdate with a single Raw rectangle.
+        assert_eq!(read_n(&mut stream, 4), [0, 0, 0, 1]);
+        assert_eq!(
+            read_n(&mut stream, 12),
+            [0, 0, 0, 0, 0, FRAME_W as u8, 0, FRAME_H as u8, 0, 0, 0, 0]
+        );
+        let pixels = read_n(&mut stream, (FRAME_W * FRAME_H * 2) as usize);
+        assert_eq!(&pixels[0..6], [0xF8, 0x00, 0x07, 0xE0, 0x00, 0x1F]);
+        assert!(pixels[6..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_invalid_pixel_format_disconnects() {
+        let mut server = VncServer::new("127.0.0.1:0", "test").unwrap();
+        let (mut stream, _) = connect(&mut server);
+
+        // SetPixelFormat with a red shift past the end of a 32-bit pixel.
+        let mut msg = vec![0u8, 0, 0, 0];
+        msg.extend_from_slice(&[32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 40, 8, 0, 0, 0, 0]);
+        stream.write_all(&msg).unwrap();
+
+        // The server closes the connection rather than waiting for an update request.
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -86,7 +86,6 @@ impl VideoRenderer {
                         self.params.render.w,
                         self.params.render.h,
                         input_buf,
-                        &mut self.composite_bufs,
                         &mut self.composite_ctx,
                         &self.composite_params,
                         self.params.aperture,
@@ -114,6 +113,16 @@ impl VideoRenderer {
                 extents,
                 RenderBpp::Six,
             ),
+            #[cfg(feature = "vga")]
+            VideoType::VGA => VideoRenderer::draw_ega_direct_u32(
+                first_pass_buf,
+                self.params.render.w,
+                self.params.render.h,
+                input_buf,
+                self.params.aperture,
+                extents,
+                RenderBpp::Six,
+            ),
             _ => {
                 // unimplemented
             }
@@ -458,16 +467,15 @@ impl VideoRenderer {
 
     /// Render the CGA Direct framebuffer as a composite artifact color simulation.
     ///
-    /// This version uses reenigne's composite color multiplexer algorithm.
-    /// It is 3x faster than my sampling algorithm and produces more accurate colors;
-    /// I know when I'm beat.
+    /// This version uses reenigne's composite color multiplexer algorithm, as implemented by
+    /// the core's NTSC pipeline. It is 3x faster than my sampling algorithm and produces more
+    /// accurate colors; I know when I'm beat.
     pub fn draw_cga_direct_composite_reenigne(
         frame: &mut [u8],
         w: u32,
         h: u32,
         dbuf: &[u8],
-        bufs: &mut ReCompositeBuffers,
-        ctx: &mut ReCompositeContext,
+        ctx: &mut NtscPipeline,
         params: &CompositeParams,
         aperture: DisplayApertureType,
         extents: &DisplayExtents,
@@ -498,7 +506,7 @@ impl VideoRenderer {
             let out_slice = &mut frame[d_o..d_end];
             let out_slice32: &mut [u32] = bytemuck::cast_slice_mut(out_slice);
 
-            ctx.composite_process(0, w as usize, bufs, in_slice, out_slice32);
+            ctx.process_line(0, in_slice, &mut out_slice32[..(w as usize)]);
 
             out_slice32.copy_within(0..(w as usize), w as usize);
         }
@@ -512,7 +520,7 @@ impl VideoRenderer {
         if (mode & cga::CGA_MODE_ENABLE_MASK) != (self.last_cga_mode & cga::CGA_MODE_ENABLE_MASK) {
             // Mode has changed; recalculate composite parameters.
             //log::debug!("mode changed: new:{:02X} old:{:02X} recalculating composite parameters...", mode, self.last_cga_mode);
-            self.composite_ctx.recalculate(mode);
+            self.composite_ctx.set_mode_byte(mode);
             self.last_cga_mode = mode;
         }
     }
@@ -521,8 +529,7 @@ impl VideoRenderer {
     /// reenigne's composite conversion algorithm will recalculate composite parameters
     /// when adjustments are changed.
     pub fn cga_direct_param_update(&mut self, composite_params: &CompositeParams) {
-        self.composite_ctx.adjust(composite_params);
-        self.composite_ctx.recalculate(self.last_cga_mode);
+        self.composite_ctx.set_params(composite_params.ntsc_params());
 
         self.composite_params = *composite_params;
     }
@@ -47,18 +47,20 @@ use web_time::Duration;
 use image;
 use log;
 
-use composite_new::{ReCompositeBuffers, ReCompositeContext};
 pub use display_backend_trait::DisplayBackend;
 use marty_common::VideoDimensions;
-use marty_core::device_traits::videocard::{
-    BufferSelect,
-    CGAColor,
-    CGAPalette,
-    DisplayApertureType,
-    DisplayExtents,
-    DisplayMode,
-    RenderBpp,
-    VideoType,
+use marty_core::{
+    device_traits::videocard::{
+        BufferSelect,
+        CGAColor,
+        CGAPalette,
+        DisplayApertureType,
+        DisplayExtents,
+        DisplayMode,
+        RenderBpp,
+        VideoType,
+    },
+    ntsc::{NtscModel, NtscParams, NtscPipeline},
 };
 use serde::Deserialize;
 
@@ -70,8 +72,6 @@ pub mod composite;
 pub mod consts;
 pub mod draw;
 pub mod resize;
-// Reenigne composite
-pub mod composite_new;
 
 /// Events that the renderer can return. These must be read and handled every frame to avoid
 /// memory leaks.
@@ -157,6 +157,25 @@ pub struct CompositeParams {
     pub new_cga: bool,
 }
 
+impl CompositeParams {
+    /// Convert to the decoder adjustments used by the core's NTSC pipeline.
+    pub fn ntsc_params(&self) -> NtscParams {
+        NtscParams {
+            model: if self.new_cga {
+                NtscModel::NewCga
+            }
+            else {
+                NtscModel::OldCga
+            },
+            hue: self.hue,
+            saturation: self.sat,
+            contrast: self.contrast,
+            brightness: self.luma,
+            ..Default::default()
+        }
+    }
+}
+
 impl Default for CompositeParams {
     fn default() -> Self {
         Self {
@@ -209,9 +228,8 @@ pub struct VideoRenderer {
     sync_table:    Vec<(f32, f32, f32)>,
 
     // Reenigne composite stuff
-    composite_ctx:  ReCompositeContext,
-    composite_bufs: ReCompositeBuffers,
-    last_cga_mode:  u8,
+    composite_ctx: NtscPipeline,
+    last_cga_mode: u8,
 
     // Composite adjustments
     composite_enabled: bool,
@@ -259,8 +277,7 @@ impl VideoRenderer {
             sync_table: Vec::new(),
 
             // Reenigne composite stuff
-            composite_ctx: ReCompositeContext::new(),
-            composite_bufs: ReCompositeBuffers::new(),
+            composite_ctx: NtscPipeline::new(NtscParams::default()),
             last_cga_mode: 0,
 
             composite_enabled: false,
@@ -315,6 +332,17 @@ impl VideoRenderer {
         &self.params
     }
 
+    pub fn get_video_type(&self) -> VideoType {
+        self.video_type
+    }
+
+    /// Change the type of card being rendered. Used for cards such as the Compaq dual-mode board
+    /// that present a different personality depending on their current mode.
+    pub fn set_video_type(&mut self, video_type: VideoType) {
+        log::debug!("Setting renderer video type to {:?}", video_type);
+        self.video_type = video_type;
+    }
+
     pub fn select_buffer(&mut self, selection: BufferSelect) {
         self.buffer_select = selection;
     }
@@ -0,0 +1,76 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   marty.h
+
+   C declarations for the marty_ffi library. See lib/marty_ffi/src/lib.rs
+   for full documentation of each function.
+*/
+
+#ifndef MARTY_H
+#define MARTY_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MARTY_FFI_VERSION 1
+
+#define MARTY_OK 0
+#define MARTY_ERR (-1)
+
+typedef struct MartyMachine MartyMachine;
+
+uint32_t marty_ffi_version(void);
+const char *marty_last_error(void);
+
+MartyMachine *marty_machine_new(const char *config);
+void marty_machine_free(MartyMachine *m);
+int32_t marty_machine_reset(MartyMachine *m);
+int32_t marty_machine_warm_reset(MartyMachine *m);
+
+uint64_t marty_run_cycles(MartyMachine *m, uint32_t cycles);
+int32_t marty_frame_update(MartyMachine *m);
+uint64_t marty_cpu_cycles(MartyMachine *m);
+
+const uint8_t *marty_get_framebuffer(MartyMachine *m, uint32_t *width, uint32_t *height, uint32_t *stride);
+
+int32_t marty_key_event(MartyMachine *m, const char *key, bool pressed);
+int32_t marty_type_text(MartyMachine *m, const char *text);
+int32_t marty_mouse_event(MartyMachine *m, bool left_pressed, bool right_pressed, double delta_x, double delta_y);
+
+size_t marty_read_memory(MartyMachine *m, uint32_t address, uint8_t *buf, size_t len);
+size_t marty_write_memory(MartyMachine *m, uint32_t address, const uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MARTY_H */
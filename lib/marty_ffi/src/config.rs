@@ -0,0 +1,201 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   marty_ffi::config.rs
+
+   Configuration blob accepted by marty_machine_new().
+
+   The blob is a TOML document describing the machine to build and the ROM
+   images to load into it. A minimal example:
+
+       [machine]
+       type = "Ibm5160"
+       memory.conventional = { size = 0xA0000, wait_states = 0 }
+       video = [ { type = "CGA" } ]
+
+       [[rom]]
+       path = "roms/BIOS_5160_09MAY86_U18_59X7268_62X0890_27256_F800.BIN"
+       address = 0xF8000
+*/
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+use serde_derive::Deserialize;
+
+use marty_core::{
+    coreconfig::CoreConfig,
+    cpu_common::TraceMode,
+    cpu_validator::ValidatorType,
+    machine::{MachineRomEntry, MachineRomManifest},
+    machine_config::MachineConfiguration,
+    machine_types::MachineType,
+};
+
+/// The [machine] table: a core MachineConfiguration, plus the options the core reads through
+/// CoreConfig.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FfiMachineConfig {
+    #[serde(flatten)]
+    pub config: MachineConfiguration,
+    #[serde(default)]
+    pub turbo:  bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FfiRomConfig {
+    pub path:    PathBuf,
+    pub address: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FfiConfig {
+    #[serde(default)]
+    pub base_dir: Option<PathBuf>,
+    pub machine: FfiMachineConfig,
+    #[serde(default)]
+    pub rom: Vec<FfiRomConfig>,
+}
+
+impl FfiConfig {
+    pub fn from_toml_str(toml_str: &str) -> Result<Self, Error> {
+        let config: FfiConfig = toml::from_str(toml_str)?;
+        Ok(config)
+    }
+
+    pub fn to_machine_config(&self) -> MachineConfiguration {
+        self.machine.config.clone()
+    }
+
+    /// Read each ROM image listed in the configuration, relative to base_dir, and build a ROM
+    /// manifest from them.
+    pub fn load_roms(&self) -> Result<MachineRomManifest, Error> {
+        let mut manifest = MachineRomManifest::new();
+        for rom in self.rom.iter() {
+            let path = self.get_base_dir().join(&rom.path);
+            let data =
+                std::fs::read(&path).map_err(|e| anyhow::anyhow!("Failed to read ROM {}: {}", path.display(), e))?;
+            log::debug!(
+                "Loaded ROM {} ({} bytes) at {:05X}",
+                path.display(),
+                data.len(),
+                rom.address
+            );
+            manifest.roms.push(MachineRomEntry {
+                md5: String::new(),
+                addr: rom.address,
+                data,
+                wait_states: None,
+            });
+            manifest.rom_paths.push(path);
+        }
+        Ok(manifest)
+    }
+}
+
+impl CoreConfig for FfiConfig {
+    fn get_base_dir(&self) -> PathBuf {
+        self.base_dir.clone().unwrap_or_else(|| PathBuf::from("."))
+    }
+    fn get_machine_type(&self) -> MachineType {
+        self.machine.config.machine_type
+    }
+    fn get_machine_noroms(&self) -> bool {
+        self.rom.is_empty()
+    }
+    fn get_machine_turbo(&self) -> bool {
+        self.machine.turbo
+    }
+    fn get_keyboard_layout(&self) -> Option<String> {
+        self.machine.config.keyboard.as_ref().map(|kb| kb.layout.clone())
+    }
+    fn get_keyboard_debug(&self) -> bool {
+        false
+    }
+    fn get_validator_type(&self) -> Option<ValidatorType> {
+        None
+    }
+    fn get_validator_trace_file(&self) -> Option<PathBuf> {
+        None
+    }
+    fn get_validator_baud(&self) -> Option<u32> {
+        None
+    }
+    fn get_validator_address(&self) -> Option<String> {
+        None
+    }
+    fn get_cpu_trace_mode(&self) -> Option<TraceMode> {
+        None
+    }
+    fn get_cpu_trace_on(&self) -> bool {
+        false
+    }
+    fn get_cpu_trace_file(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use marty_core::device_traits::videocard::VideoType;
+
+    #[test]
+    fn test_machine_config() {
+        let config = FfiConfig::from_toml_str(
+            r#"
+            [machine]
+            type = "Ibm5160"
+            turbo = true
+            turbo_clock = "8MHz"
+            memory.conventional = { size = 0xA0000, wait_states = 0 }
+            video = [ { type = "CGA" } ]
+            keyboard = { type = "ModelF", layout = "US" }
+
+            [[rom]]
+            path = "bios.bin"
+            address = 0xFE000
+            "#,
+        )
+        .unwrap();
+
+        let machine_config = config.to_machine_config();
+        assert_eq!(machine_config.machine_type, MachineType::Ibm5160);
+        assert_eq!(machine_config.memory.conventional.size, 0xA0000);
+        assert!(machine_config.memory.address_wrap);
+        assert_eq!(machine_config.video[0].video_type, VideoType::CGA);
+        assert!(machine_config.serial.is_empty());
+        assert!(!machine_config.speaker);
+
+        // Options read through CoreConfig are taken from the same table.
+        assert!(config.get_machine_turbo());
+        assert!(!config.get_machine_noroms());
+        assert_eq!(config.get_keyboard_layout(), Some(String::from("US")));
+        assert_eq!(config.rom[0].address, 0xFE000);
+
+        assert!(FfiConfig::from_toml_str("[machine]\ntype = \"Ibm5160\"").is_err());
+    }
+}
@@ -0,0 +1,466 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   marty_ffi::lib.rs
+
+   A C ABI for the MartyPC core, so that non-Rust frontends and research
+   tools can embed the emulator. See include/marty.h for the matching C
+   declarations.
+
+   All functions taking a MartyMachine pointer expect a handle returned by
+   marty_machine_new() that has not yet been passed to marty_machine_free().
+   Functions that can fail return MARTY_ERR (or a null pointer) and record a
+   description of the failure retrievable via marty_last_error().
+*/
+
+pub mod config;
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+    str::FromStr,
+};
+
+use marty_core::{
+    devices::keyboard::KeyboardModifiers,
+    keys::MartyKey,
+    machine::{ExecutionControl, ExecutionState, Machine, MachineBuilder},
+};
+
+use crate::config::FfiConfig;
+
+/// Version of the C interface. Incremented whenever a function signature changes.
+pub const MARTY_FFI_VERSION: u32 = 1;
+
+pub const MARTY_OK: i32 = 0;
+pub const MARTY_ERR: i32 = -1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: impl ToString) {
+    let msg = msg.to_string().replace('\0', " ");
+    log::error!("marty_ffi: {}", msg);
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(msg).ok());
+}
+
+/// Opaque handle to an emulated machine.
+pub struct MartyMachine {
+    machine: Machine,
+    exec_control: ExecutionControl,
+}
+
+impl MartyMachine {
+    pub fn from_config_str(config_str: &str) -> Result<Self, anyhow::Error> {
+        let config = FfiConfig::from_toml_str(config_str)?;
+        let machine_config = config.to_machine_config();
+        let rom_manifest = config.load_roms()?;
+
+        let machine = MachineBuilder::new()
+            .with_core_config(Box::new(&config))
+            .with_machine_config(&machine_config)
+            .with_roms(rom_manifest)
+            .with_sound_player(None)
+            .build()?;
+
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+
+        Ok(Self { machine, exec_control })
+    }
+
+    pub fn machine(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+}
+
+/// Convert a possibly null C string into a &str, recording an error on failure.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error(format!("{} is null", name));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(e) => {
+            set_last_error(format!("{} is not valid UTF-8: {}", name, e));
+            None
+        }
+    }
+}
+
+unsafe fn machine_arg<'a>(m: *mut MartyMachine) -> Option<&'a mut MartyMachine> {
+    if m.is_null() {
+        set_last_error("machine handle is null");
+    }
+    m.as_mut()
+}
+
+/// Return the version of the C interface.
+#[no_mangle]
+pub extern "C" fn marty_ffi_version() -> u32 {
+    MARTY_FFI_VERSION
+}
+
+/// Return a description of the last error that occurred on this thread, or null if none has.
+/// The returned string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn marty_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Create a machine from a null-terminated TOML configuration blob. Returns null on failure.
+///
+/// # Safety
+/// `config` must be null or point to a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marty_machine_new(config: *const c_char) -> *mut MartyMachine {
+    let config_str = match str_arg(config, "config") {
+        Some(s) => s,
+        None => return ptr::null_mut(),
+    };
+    match MartyMachine::from_config_str(config_str) {
+        Ok(m) => Box::into_raw(Box::new(m)),
+        Err(e) => {
+            set_last_error(format!("Failed to create machine: {}", e));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Destroy a machine created by marty_machine_new(). Passing null is a no-op.
+///
+/// # Safety
+/// `m` must be null or a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_machine_free(m: *mut MartyMachine) {
+    if !m.is_null() {
+        drop(Box::from_raw(m));
+    }
+}
+
+/// Reset the machine as if the reset button were pressed.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_machine_reset(m: *mut MartyMachine) -> i32 {
+    match machine_arg(m) {
+        Some(m) => {
+            m.machine.reset();
+            m.exec_control.set_state(ExecutionState::Running);
+            MARTY_OK
+        }
+        None => MARTY_ERR,
+    }
+}
+
+/// Reset the machine without clearing RAM, as if Ctrl-Alt-Del were pressed.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_machine_warm_reset(m: *mut MartyMachine) -> i32 {
+    match machine_arg(m) {
+        Some(m) => {
+            m.machine.warm_reset();
+            m.exec_control.set_state(ExecutionState::Running);
+            MARTY_OK
+        }
+        None => MARTY_ERR,
+    }
+}
+
+/// Run the machine for at least the specified number of CPU cycles. Returns the number of
+/// instructions executed.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_run_cycles(m: *mut MartyMachine, cycles: u32) -> u64 {
+    match machine_arg(m) {
+        Some(m) => m.machine.run(cycles, &mut m.exec_control),
+        None => 0,
+    }
+}
+
+/// Perform once-per-frame device updates, such as servicing serial port bridges. Hosts should call
+/// this after running each frame's worth of cycles.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_frame_update(m: *mut MartyMachine) -> i32 {
+    match machine_arg(m) {
+        Some(m) => {
+            _ = m.machine.frame_update();
+            MARTY_OK
+        }
+        None => MARTY_ERR,
+    }
+}
+
+/// Return the total number of CPU cycles executed since the machine was created.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_cpu_cycles(m: *mut MartyMachine) -> u64 {
+    match machine_arg(m) {
+        Some(m) => m.machine.cpu_cycles(),
+        None => 0,
+    }
+}
+
+/// Return a pointer to the primary video card's last completed frame, or null if the machine
+/// has no video card. The buffer holds one palette index per pixel; its dimensions are written
+/// to `width`, `height` and `stride` (bytes per row) when those pointers are non-null.
+/// The pointer is valid until the next call to marty_run_cycles() or marty_machine_free().
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new(). Non-null output pointers must be valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn marty_get_framebuffer(
+    m: *mut MartyMachine,
+    width: *mut u32,
+    height: *mut u32,
+    stride: *mut u32,
+) -> *const u8 {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return ptr::null(),
+    };
+    match m.machine.primary_videocard() {
+        Some(card) => {
+            let extents = card.get_display_extents();
+            if !width.is_null() {
+                *width = extents.field_w;
+            }
+            if !height.is_null() {
+                *height = extents.field_h;
+            }
+            if !stride.is_null() {
+                *stride = extents.row_stride as u32;
+            }
+            card.get_display_buf().as_ptr()
+        }
+        None => {
+            set_last_error("machine has no video card");
+            ptr::null()
+        }
+    }
+}
+
+/// Press or release a key. `key` is the name of a MartyKey variant, such as "KeyA" or "Enter".
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new(). `key` must be null or point to a valid
+/// null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marty_key_event(m: *mut MartyMachine, key: *const c_char, pressed: bool) -> i32 {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return MARTY_ERR,
+    };
+    let key_str = match str_arg(key, "key") {
+        Some(s) => s,
+        None => return MARTY_ERR,
+    };
+    let keycode = match MartyKey::from_str(key_str) {
+        Ok(k) => k,
+        Err(_) => {
+            set_last_error(format!("Unknown key name: {}", key_str));
+            return MARTY_ERR;
+        }
+    };
+
+    if pressed {
+        m.machine.key_press(keycode, KeyboardModifiers::default());
+    }
+    else {
+        m.machine.key_release(keycode);
+    }
+    MARTY_OK
+}
+
+/// Queue a string to be typed into the machine. Returns the number of characters queued.
+/// Queued key events are delivered one per call to marty_run_cycles().
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new(). `text` must be null or point to a valid
+/// null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn marty_type_text(m: *mut MartyMachine, text: *const c_char) -> i32 {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return MARTY_ERR,
+    };
+    match str_arg(text, "text") {
+        Some(s) => m.machine.type_text(s) as i32,
+        None => MARTY_ERR,
+    }
+}
+
+/// Send a mouse update to the serial mouse, if one is attached.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new().
+#[no_mangle]
+pub unsafe extern "C" fn marty_mouse_event(
+    m: *mut MartyMachine,
+    left_pressed: bool,
+    right_pressed: bool,
+    delta_x: f64,
+    delta_y: f64,
+) -> i32 {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return MARTY_ERR,
+    };
+    match m.machine.mouse_mut() {
+        Some(mouse) => {
+            mouse.update(left_pressed, right_pressed, delta_x, delta_y);
+            MARTY_OK
+        }
+        None => {
+            set_last_error("machine has no serial mouse");
+            MARTY_ERR
+        }
+    }
+}
+
+/// Copy up to `len` bytes of memory starting at `address` into `buf`, without side effects on
+/// memory-mapped devices. Returns the number of bytes copied.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new(). `buf` must be valid for `len` bytes of
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn marty_read_memory(m: *mut MartyMachine, address: u32, buf: *mut u8, len: usize) -> usize {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return 0,
+    };
+    if buf.is_null() {
+        set_last_error("buf is null");
+        return 0;
+    }
+    let out = std::slice::from_raw_parts_mut(buf, len);
+    let bus = m.machine.bus();
+    for (i, byte) in out.iter_mut().enumerate() {
+        match bus.peek_u8(address as usize + i) {
+            Ok(data) => *byte = data,
+            Err(e) => {
+                set_last_error(format!("Read failed at {:05X}: {}", address as usize + i, e));
+                return i;
+            }
+        }
+    }
+    len
+}
+
+/// Write `len` bytes from `buf` into memory starting at `address`. Writes go through the bus,
+/// so memory-mapped devices see them. Returns the number of bytes written.
+///
+/// # Safety
+/// `m` must be a live handle from marty_machine_new(). `buf` must be valid for `len` bytes of
+/// reads.
+#[no_mangle]
+pub unsafe extern "C" fn marty_write_memory(m: *mut MartyMachine, address: u32, buf: *const u8, len: usize) -> usize {
+    let m = match machine_arg(m) {
+        Some(m) => m,
+        None => return 0,
+    };
+    if buf.is_null() {
+        set_last_error("buf is null");
+        return 0;
+    }
+    let data = std::slice::from_raw_parts(buf, len);
+    let bus = m.machine.bus_mut();
+    for (i, byte) in data.iter().enumerate() {
+        if let Err(e) = bus.write_u8(address as usize + i, *byte, 0) {
+            set_last_error(format!("Write failed at {:05X}: {}", address as usize + i, e));
+            return i;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CONFIG: &str = r#"
+        [machine]
+        type = "Ibm5160"
+        reset_vector = [0x0000, 0x1000]
+        memory.conventional = { size = 0x10000, wait_states = 0 }
+    "#;
+
+    #[test]
+    fn test_machine_lifecycle() {
+        let config = CString::new(TEST_CONFIG).unwrap();
+        unsafe {
+            let m = marty_machine_new(config.as_ptr());
+            assert!(!m.is_null());
+
+            // Memory written through the interface is read back unchanged.
+            let program = [0xEB, 0xFE]; // JMP $
+            assert_eq!(marty_write_memory(m, 0x1000, program.as_ptr(), program.len()), 2);
+            let mut buf = [0u8; 2];
+            assert_eq!(marty_read_memory(m, 0x1000, buf.as_mut_ptr(), buf.len()), 2);
+            assert_eq!(buf, program);
+
+            assert!(marty_run_cycles(m, 10_000) > 0);
+            assert!(marty_cpu_cycles(m) >= 10_000);
+            assert_eq!(marty_frame_update(m), MARTY_OK);
+            assert_eq!(marty_machine_reset(m), MARTY_OK);
+            marty_machine_free(m);
+        }
+    }
+
+    #[test]
+    fn test_machine_errors() {
+        let config = CString::new("[machine]").unwrap();
+        unsafe {
+            assert!(marty_machine_new(config.as_ptr()).is_null());
+            assert!(!marty_last_error().is_null());
+            let msg = CStr::from_ptr(marty_last_error()).to_str().unwrap();
+            assert!(msg.starts_with("Failed to create machine"));
+
+            assert!(marty_machine_new(ptr::null()).is_null());
+            assert_eq!(marty_run_cycles(ptr::null_mut(), 100), 0);
+            assert_eq!(marty_machine_reset(ptr::null_mut()), MARTY_ERR);
+            marty_machine_free(ptr::null_mut());
+        }
+    }
+}